@@ -1,18 +1,20 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use argon2::{
     password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
     Argon2,
 };
 use chrono::{DateTime, Utc};
 use ring::{
-    digest, pbkdf2,
+    aead::{self, Aad, LessSafeKey, Nonce, UnboundKey},
+    digest, hmac, pbkdf2,
     rand::{self, SecureRandom},
 };
 use serde::{Deserialize, Serialize};
-use std::{borrow::Cow, net::IpAddr, num::NonZeroU32};
+use std::{borrow::Cow, net::IpAddr, num::NonZeroU32, time::Duration};
 use strum_macros::{Display, EnumString};
+use subtle::ConstantTimeEq;
 
-use crate::{tables::Value, UniqueKey};
+use crate::{types::HostNetworkGroup, UniqueKey};
 
 /// Possible role types of `Account`.
 #[derive(Clone, Copy, Debug, Display, Eq, PartialEq, Deserialize, Serialize, EnumString)]
@@ -38,13 +40,306 @@ pub struct Account {
     last_signin_time: Option<DateTime<Utc>>,
     pub allow_access_from: Option<Vec<IpAddr>>,
     pub max_parallel_sessions: Option<u32>,
+    /// The networks sign-ins are allowed from, in addition to the hosts in
+    /// `allow_access_from`.
+    pub allowed_networks: Option<HostNetworkGroup>,
+    password_hash_algorithm: PasswordHashAlgorithm,
+    last_signin_address: Option<IpAddr>,
+    last_failed_signin_time: Option<DateTime<Utc>>,
+    last_failed_signin_address: Option<IpAddr>,
+    failed_logins: u32,
+    locked_until: Option<DateTime<Utc>>,
+    password_changed_time: DateTime<Utc>,
+    /// The previous passwords, from the most recent one.
+    password_history: Vec<SaltedPassword>,
+    totp: Option<Totp>,
+    /// The strength of the password set since the account was read, which is
+    /// checked against the password policy when the account is stored.
+    #[serde(skip)]
+    new_password: Option<PasswordStrength>,
+    /// The position of the password set since the account was read among
+    /// the passwords the account had before, from the most recent one, if it
+    /// is one of them.
+    #[serde(skip)]
+    reused_password: Option<usize>,
+}
+
+/// An account without its secrets, for moving accounts between deployments.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ExportedAccount {
+    pub username: String,
+    pub role: Role,
+    pub name: String,
+    pub department: String,
+    pub creation_time: DateTime<Utc>,
+    pub allow_access_from: Option<Vec<IpAddr>>,
+    pub allowed_networks: Option<HostNetworkGroup>,
+    pub max_parallel_sessions: Option<u32>,
+}
+
+impl ExportedAccount {
+    /// Creates an account with `password` from the exported one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the salt for password cannot be generated.
+    pub fn into_account(self, password: &str) -> Result<Account> {
+        let mut account = Account::new(
+            &self.username,
+            password,
+            self.role,
+            self.name,
+            self.department,
+            self.allow_access_from,
+            self.max_parallel_sessions,
+        )?;
+        account.creation_time = self.creation_time;
+        account.allowed_networks = self.allowed_networks;
+        Ok(account)
+    }
+}
+
+impl From<&Account> for ExportedAccount {
+    fn from(account: &Account) -> Self {
+        Self {
+            username: account.username.clone(),
+            role: account.role,
+            name: account.name.clone(),
+            department: account.department.clone(),
+            creation_time: account.creation_time,
+            allow_access_from: account.allow_access_from.clone(),
+            allowed_networks: account.allowed_networks.clone(),
+            max_parallel_sessions: account.max_parallel_sessions,
+        }
+    }
+}
+
+/// The key encrypting TOTP secrets in the database, a 256-bit AES-GCM key.
+pub struct TotpKey(LessSafeKey);
+
+impl TotpKey {
+    /// The length of a key in bytes.
+    pub const LEN: usize = 32;
+
+    /// Creates a key from `key` of `TotpKey::LEN` bytes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `key` is not `TotpKey::LEN` bytes long.
+    pub fn new(key: &[u8]) -> Result<Self> {
+        let Ok(key) = UnboundKey::new(&aead::AES_256_GCM, key) else {
+            bail!("TOTP key must be {} bytes long", Self::LEN);
+        };
+        Ok(Self(LessSafeKey::new(key)))
+    }
+
+    /// Encrypts `secret`, and returns the nonce followed by the ciphertext.
+    fn seal(&self, secret: &[u8]) -> Result<Vec<u8>> {
+        let mut nonce = [0_u8; aead::NONCE_LEN];
+        if rand::SystemRandom::new().fill(&mut nonce).is_err() {
+            bail!("failed to generate a nonce");
+        }
+        let mut in_out = secret.to_vec();
+        if self
+            .0
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::empty(),
+                &mut in_out,
+            )
+            .is_err()
+        {
+            bail!("failed to encrypt TOTP secret");
+        }
+        let mut sealed = nonce.to_vec();
+        sealed.extend(in_out);
+        Ok(sealed)
+    }
+
+    /// Decrypts what `TotpKey::seal` returned.
+    fn open(&self, sealed: &[u8]) -> Result<Vec<u8>> {
+        if sealed.len() < aead::NONCE_LEN {
+            bail!("invalid TOTP secret");
+        }
+        let (nonce, ciphertext) = sealed.split_at(aead::NONCE_LEN);
+        let Ok(nonce) = Nonce::try_assume_unique_for_key(nonce) else {
+            bail!("invalid TOTP secret");
+        };
+        let mut in_out = ciphertext.to_vec();
+        let Ok(secret) = self.0.open_in_place(nonce, Aad::empty(), &mut in_out) else {
+            bail!("failed to decrypt TOTP secret");
+        };
+        Ok(secret.to_vec())
+    }
+}
+
+/// The TOTP secret of an account.
+#[derive(Deserialize, Serialize)]
+struct Totp {
+    /// The secret encrypted with a `TotpKey`.
+    sealed_secret: Vec<u8>,
+    /// The time step of the last accepted code, which cannot be used again.
+    last_step: Option<i64>,
+}
+
+impl Totp {
+    /// The duration of a time step in seconds.
+    const STEP: i64 = 30;
+    const DIGITS: u32 = 6;
+    /// The number of time steps before and after the current one whose codes
+    /// are accepted, to allow for clock drift.
+    const SKEW: i64 = 1;
+
+    /// Returns the HOTP code of `secret` for `counter`, as in RFC 4226.
+    fn code(secret: &[u8], counter: i64) -> String {
+        let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, secret);
+        let tag = hmac::sign(&key, &counter.to_be_bytes());
+        let digest = tag.as_ref();
+        let offset = usize::from(digest[digest.len() - 1] & 0x0f);
+        let value = u32::from_be_bytes([
+            digest[offset] & 0x7f,
+            digest[offset + 1],
+            digest[offset + 2],
+            digest[offset + 3],
+        ]);
+        format!(
+            "{:0width$}",
+            value % 10_u32.pow(Self::DIGITS),
+            width = Self::DIGITS as usize
+        )
+    }
+}
+
+/// The requirements for a new password, and how long a password stays
+/// valid.
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub require_uppercase: bool,
+    pub require_lowercase: bool,
+    pub require_digit: bool,
+    /// Requires a character that is neither a letter nor a digit.
+    pub require_symbol: bool,
+    /// The number of previous passwords that cannot be reused, up to
+    /// `Account::MAX_PASSWORD_HISTORY`.
+    pub history: usize,
+    /// How long a password stays valid. If `None`, it never expires.
+    pub max_age: Option<Duration>,
+}
+
+impl PasswordPolicy {
+    /// Checks if `password` meets the length and character class
+    /// requirements.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error describing the first requirement not met.
+    pub fn check(&self, password: &str) -> Result<()> {
+        self.check_strength(&PasswordStrength::new(password))
+    }
+
+    fn check_strength(&self, strength: &PasswordStrength) -> Result<()> {
+        if strength.length < self.min_length {
+            bail!(
+                "password must be at least {} characters long",
+                self.min_length
+            );
+        }
+        let classes = [
+            (
+                self.require_uppercase,
+                strength.uppercase,
+                "an uppercase letter",
+            ),
+            (
+                self.require_lowercase,
+                strength.lowercase,
+                "a lowercase letter",
+            ),
+            (self.require_digit, strength.digit, "a digit"),
+            (self.require_symbol, strength.symbol, "a symbol"),
+        ];
+        for (required, contained, description) in classes {
+            if required && !contained {
+                bail!("password must contain {description}");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The length and character classes of a password, which is all a password
+/// policy checks.
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+struct PasswordStrength {
+    length: usize,
+    uppercase: bool,
+    lowercase: bool,
+    digit: bool,
+    symbol: bool,
+}
+
+impl PasswordStrength {
+    fn new(password: &str) -> Self {
+        Self {
+            length: password.chars().count(),
+            uppercase: password.chars().any(char::is_uppercase),
+            lowercase: password.chars().any(char::is_lowercase),
+            digit: password.chars().any(|c| c.is_ascii_digit()),
+            symbol: password.chars().any(|c| !c.is_alphanumeric()),
+        }
+    }
+}
+
+/// The number of consecutive failed password validations that locks an
+/// account, and how long the account stays locked.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct LockoutPolicy {
+    pub max_failures: u32,
+    pub duration: Duration,
+}
+
+/// The result of validating a password or a TOTP code.
+#[derive(Debug, Eq, PartialEq)]
+pub enum PasswordValidation {
+    Valid,
+    Invalid,
+    /// The password is valid, but the account requires a TOTP code, which
+    /// was not given.
+    TotpRequired,
+    /// The sign-in is from an address not allowed by the allowed hosts and
+    /// networks of the account. The password is not checked.
+    AddressNotAllowed,
+    /// The account is locked until the given time. The password is not
+    /// checked.
+    Locked(DateTime<Utc>),
+}
+
+/// An account in the format used until 0.26.
+#[derive(Deserialize, Serialize)]
+struct AccountV0_26 {
+    username: String,
+    password: SaltedPassword,
+    role: Role,
+    name: String,
+    department: String,
+    creation_time: DateTime<Utc>,
+    last_signin_time: Option<DateTime<Utc>>,
+    allow_access_from: Option<Vec<IpAddr>>,
+    max_parallel_sessions: Option<u32>,
     password_hash_algorithm: PasswordHashAlgorithm,
 }
 
 impl Account {
     const DEFAULT_HASH_ALGORITHM: PasswordHashAlgorithm = PasswordHashAlgorithm::Argon2id;
 
-    /// Creates a new `Account` with the given information
+    /// The maximum number of previous passwords kept.
+    pub const MAX_PASSWORD_HISTORY: usize = 24;
+
+    /// Creates a new `Account` with the given information. The password is
+    /// checked against the password policy when the account is stored.
     ///
     /// # Errors
     ///
@@ -58,6 +353,7 @@ impl Account {
         allow_access_from: Option<Vec<IpAddr>>,
         max_parallel_sessions: Option<u32>,
     ) -> Result<Self> {
+        let new_password = Some(PasswordStrength::new(password));
         let password =
             SaltedPassword::new_with_hash_algorithm(password, &Self::DEFAULT_HASH_ALGORITHM)?;
         Ok(Self {
@@ -70,25 +366,151 @@ impl Account {
             last_signin_time: None,
             allow_access_from,
             max_parallel_sessions,
+            allowed_networks: None,
             password_hash_algorithm: Self::DEFAULT_HASH_ALGORITHM,
+            last_signin_address: None,
+            last_failed_signin_time: None,
+            last_failed_signin_address: None,
+            failed_logins: 0,
+            locked_until: None,
+            password_changed_time: Utc::now(),
+            password_history: Vec::new(),
+            totp: None,
+            new_password,
+            reused_password: None,
+        })
+    }
+
+    /// Deserializes an account stored in the format used until 0.26.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `value` is not an account in the old format.
+    pub(crate) fn from_v0_26(value: &[u8]) -> Result<Self> {
+        use bincode::Options;
+
+        let old: AccountV0_26 = bincode::DefaultOptions::new().deserialize(value)?;
+        Ok(Self {
+            username: old.username,
+            password: old.password,
+            role: old.role,
+            name: old.name,
+            department: old.department,
+            creation_time: old.creation_time,
+            last_signin_time: old.last_signin_time,
+            allow_access_from: old.allow_access_from,
+            max_parallel_sessions: old.max_parallel_sessions,
+            allowed_networks: None,
+            password_hash_algorithm: old.password_hash_algorithm,
+            last_signin_address: None,
+            last_failed_signin_time: None,
+            last_failed_signin_address: None,
+            failed_logins: 0,
+            locked_until: None,
+            password_changed_time: old.creation_time,
+            password_history: Vec::new(),
+            totp: None,
+            new_password: None,
+            reused_password: None,
         })
     }
 
     /// Update `Account::password` with the given password using
-    /// `Account::DEFAULT_HASH_ALGORITHM`.
+    /// `Account::DEFAULT_HASH_ALGORITHM`. The password is checked against the
+    /// password policy, including its reuse of the previous passwords, when
+    /// the account is stored.
     ///
     /// # Errors
     ///
     /// Returns an error if the salt for password cannot be generated.
     pub fn update_password(&mut self, password: &str) -> Result<()> {
-        self.password =
-            SaltedPassword::new_with_hash_algorithm(password, &Self::DEFAULT_HASH_ALGORITHM)?;
+        let reused = self.reused_position(password, Self::MAX_PASSWORD_HISTORY);
+        self.set_password(password, reused)
+    }
+
+    /// Returns the position of `password` among the current password and
+    /// up to `history` previous passwords, from the current one, if it is
+    /// one of them.
+    fn reused_position(&self, password: &str, history: usize) -> Option<usize> {
+        std::iter::once(&self.password)
+            .chain(self.password_history.iter().take(history))
+            .position(|old| old.is_match(password))
+    }
+
+    fn set_password(&mut self, password: &str, reused: Option<usize>) -> Result<()> {
+        let new = SaltedPassword::new_with_hash_algorithm(password, &Self::DEFAULT_HASH_ALGORITHM)?;
+        let old = std::mem::replace(&mut self.password, new);
+        self.password_history.insert(0, old);
+        self.password_history.truncate(Self::MAX_PASSWORD_HISTORY);
         self.password_hash_algorithm = Self::DEFAULT_HASH_ALGORITHM;
+        self.password_changed_time = Utc::now();
+        self.new_password = Some(PasswordStrength::new(password));
+        self.reused_password = reused;
         Ok(())
     }
 
+    /// Checks the password set since the account was created or read, if
+    /// any, against `policy`. The password cannot be the one it replaced or
+    /// one of the previous passwords the policy keeps.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error describing the first requirement of `policy` the
+    /// password doesn't meet.
+    pub(crate) fn check_new_password(&self, policy: &PasswordPolicy) -> Result<()> {
+        let Some(strength) = &self.new_password else {
+            return Ok(());
+        };
+        policy.check_strength(strength)?;
+        // Position 0 is the password replaced, and position `i` the `i`-th
+        // previous password before that.
+        if self
+            .reused_password
+            .is_some_and(|position| position <= policy.history)
+        {
+            bail!("password was used recently");
+        }
+        Ok(())
+    }
+
+    /// Updates the password after checking it against `policy`. The new
+    /// password cannot be the current one or one of the previous passwords
+    /// the policy keeps.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the password doesn't meet the requirements of
+    /// `policy`, or the salt for password cannot be generated.
+    pub fn change_password(&mut self, password: &str, policy: &PasswordPolicy) -> Result<()> {
+        policy.check(password)?;
+        let reused = self.reused_position(password, policy.history);
+        if reused.is_some_and(|position| position <= policy.history) {
+            bail!("password was used recently");
+        }
+        self.set_password(password, reused)
+    }
+
+    #[must_use]
+    pub fn password_changed_time(&self) -> DateTime<Utc> {
+        self.password_changed_time
+    }
+
+    /// Returns `true` if the password is older than the maximum age in
+    /// `policy` at `now`.
+    #[must_use]
+    pub fn password_expired(&self, policy: &PasswordPolicy, now: DateTime<Utc>) -> bool {
+        policy.max_age.map_or(false, |max_age| {
+            chrono::Duration::from_std(max_age)
+                .ok()
+                .and_then(|d| self.password_changed_time.checked_add_signed(d))
+                .map_or(false, |expiry| expiry <= now)
+        })
+    }
+
+    /// Returns `true` if `provided` is the password. This doesn't check or
+    /// count toward a lockout; `Store::validate_password` does.
     #[must_use]
-    pub fn verify_password(&self, provided: &str) -> bool {
+    pub(crate) fn verify_password(&self, provided: &str) -> bool {
         self.password.is_match(provided)
     }
 
@@ -97,14 +519,216 @@ impl Account {
         self.creation_time
     }
 
-    pub fn update_last_signin_time(&mut self) {
-        self.last_signin_time = Some(Utc::now());
+    /// Records a successful sign-in from `address` now, updating the time and
+    /// the address of the last sign-in together.
+    pub fn update_last_signin_time(&mut self, address: Option<IpAddr>) {
+        self.record_signin(true, address, Utc::now());
     }
 
     #[must_use]
     pub fn last_signin_time(&self) -> Option<DateTime<Utc>> {
         self.last_signin_time
     }
+
+    /// Returns the source address of the last successful sign-in.
+    #[must_use]
+    pub fn last_signin_address(&self) -> Option<IpAddr> {
+        self.last_signin_address
+    }
+
+    #[must_use]
+    pub fn last_failed_signin_time(&self) -> Option<DateTime<Utc>> {
+        self.last_failed_signin_time
+    }
+
+    /// Returns the source address of the last failed sign-in.
+    #[must_use]
+    pub fn last_failed_signin_address(&self) -> Option<IpAddr> {
+        self.last_failed_signin_address
+    }
+
+    /// Returns `true` if sign-ins from `addr` are allowed. If neither
+    /// `allow_access_from` nor `allowed_networks` is set, sign-ins are allowed
+    /// from anywhere; otherwise, `addr` must be one of the hosts or in one of
+    /// the networks.
+    #[must_use]
+    pub fn is_signin_allowed(&self, addr: IpAddr) -> bool {
+        if self.allow_access_from.is_none() && self.allowed_networks.is_none() {
+            return true;
+        }
+        self.allow_access_from
+            .as_ref()
+            .map_or(false, |hosts| hosts.contains(&addr))
+            || self
+                .allowed_networks
+                .as_ref()
+                .map_or(false, |group| group.contains(addr))
+    }
+
+    /// Records a sign-in attempt from `address` at `now`.
+    pub fn record_signin(&mut self, success: bool, address: Option<IpAddr>, now: DateTime<Utc>) {
+        if success {
+            self.last_signin_time = Some(now);
+            self.last_signin_address = address;
+        } else {
+            self.last_failed_signin_time = Some(now);
+            self.last_failed_signin_address = address;
+        }
+    }
+
+    /// Returns the number of consecutive failed password validations since
+    /// the last successful one or the last lockout.
+    #[must_use]
+    pub fn failed_logins(&self) -> u32 {
+        self.failed_logins
+    }
+
+    /// Returns the time until which the account is locked, or `None` if it is
+    /// not locked at `now`.
+    #[must_use]
+    pub fn locked_until(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.locked_until.filter(|until| *until > now)
+    }
+
+    /// Validates `password` at `now`, along with the TOTP code in `totp`,
+    /// decrypting the secret with its key, if TOTP is enabled for the account.
+    /// Consecutive failures of either are counted together, and the account
+    /// gets locked when the count reaches the maximum in `policy`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the TOTP secret cannot be decrypted with the key.
+    pub fn validate_password(
+        &mut self,
+        password: &str,
+        totp: Option<(&str, &TotpKey)>,
+        policy: Option<&LockoutPolicy>,
+        now: DateTime<Utc>,
+    ) -> Result<PasswordValidation> {
+        if let Some(until) = self.locked_until(now) {
+            return Ok(PasswordValidation::Locked(until));
+        }
+        if self.verify_password(password) {
+            if self.totp.is_none() {
+                self.clear_lockout();
+                return Ok(PasswordValidation::Valid);
+            }
+            let Some((code, key)) = totp else {
+                return Ok(PasswordValidation::TotpRequired);
+            };
+            if self.accept_totp(code, key, now)? {
+                self.clear_lockout();
+                return Ok(PasswordValidation::Valid);
+            }
+        }
+        Ok(self.record_failure(policy, now))
+    }
+
+    /// Counts a failed validation at `now`, and locks the account when the
+    /// count reaches the maximum in `policy`.
+    fn record_failure(
+        &mut self,
+        policy: Option<&LockoutPolicy>,
+        now: DateTime<Utc>,
+    ) -> PasswordValidation {
+        self.failed_logins = self.failed_logins.saturating_add(1);
+        match policy {
+            Some(policy)
+                if policy.max_failures > 0 && self.failed_logins >= policy.max_failures =>
+            {
+                let until = chrono::Duration::from_std(policy.duration)
+                    .ok()
+                    .and_then(|d| now.checked_add_signed(d))
+                    .unwrap_or(DateTime::<Utc>::MAX_UTC);
+                self.failed_logins = 0;
+                self.locked_until = Some(until);
+                PasswordValidation::Locked(until)
+            }
+            _ => PasswordValidation::Invalid,
+        }
+    }
+
+    /// Unlocks the account and resets the count of failed password
+    /// validations.
+    pub fn clear_lockout(&mut self) {
+        self.failed_logins = 0;
+        self.locked_until = None;
+    }
+
+    /// Stores the TOTP secret, encrypted with `key`, enabling TOTP for the
+    /// account.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the secret cannot be encrypted.
+    pub fn set_totp_secret(&mut self, secret: &[u8], key: &TotpKey) -> Result<()> {
+        self.totp = Some(Totp {
+            sealed_secret: key.seal(secret)?,
+            last_step: None,
+        });
+        Ok(())
+    }
+
+    /// Removes the TOTP secret, disabling TOTP for the account.
+    pub fn clear_totp(&mut self) {
+        self.totp = None;
+    }
+
+    #[must_use]
+    pub fn totp_enabled(&self) -> bool {
+        self.totp.is_some()
+    }
+
+    /// Verifies the TOTP `code` at `now`. A code is accepted only once, and
+    /// no code older than the last accepted one is accepted. Consecutive
+    /// failures are counted along with failed password validations, and the
+    /// account gets locked when the count reaches the maximum in `policy`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if TOTP is not enabled for the account or the secret
+    /// cannot be decrypted with `key`.
+    pub fn verify_totp(
+        &mut self,
+        code: &str,
+        key: &TotpKey,
+        policy: Option<&LockoutPolicy>,
+        now: DateTime<Utc>,
+    ) -> Result<PasswordValidation> {
+        if self.totp.is_none() {
+            bail!("TOTP is not enabled");
+        }
+        if let Some(until) = self.locked_until(now) {
+            return Ok(PasswordValidation::Locked(until));
+        }
+        if self.accept_totp(code, key, now)? {
+            self.clear_lockout();
+            return Ok(PasswordValidation::Valid);
+        }
+        Ok(self.record_failure(policy, now))
+    }
+
+    /// Returns `true` if `code` is valid at `now` and has not been used, and
+    /// marks it as used.
+    fn accept_totp(&mut self, code: &str, key: &TotpKey, now: DateTime<Utc>) -> Result<bool> {
+        let Some(totp) = &mut self.totp else {
+            bail!("TOTP is not enabled");
+        };
+        let secret = key.open(&totp.sealed_secret)?;
+        let current = now.timestamp().div_euclid(Totp::STEP);
+        let accepted = (current - Totp::SKEW..=current + Totp::SKEW)
+            .filter(|step| totp.last_step.map_or(true, |last| *step > last))
+            .find(|step| {
+                Totp::code(&secret, *step)
+                    .as_bytes()
+                    .ct_eq(code.as_bytes())
+                    .into()
+            });
+        if let Some(step) = accepted {
+            totp.last_step = Some(step);
+        }
+        Ok(accepted.is_some())
+    }
 }
 
 impl UniqueKey for Account {
@@ -113,16 +737,6 @@ impl UniqueKey for Account {
     }
 }
 
-impl Value for Account {
-    fn value(&self) -> Cow<[u8]> {
-        use bincode::Options;
-        let Ok(value) = bincode::DefaultOptions::new().serialize(&self) else {
-            unreachable!("serialization into memory should never fail")
-        };
-        Cow::Owned(value)
-    }
-}
-
 #[derive(Default, Debug, Deserialize, Serialize, PartialEq)]
 enum PasswordHashAlgorithm {
     #[default]
@@ -303,7 +917,18 @@ mod tests {
             last_signin_time: None,
             allow_access_from: None,
             max_parallel_sessions: None,
+            allowed_networks: None,
             password_hash_algorithm: PasswordHashAlgorithm::Pbkdf2HmacSha512,
+            last_signin_address: None,
+            last_failed_signin_time: None,
+            last_failed_signin_address: None,
+            failed_logins: 0,
+            locked_until: None,
+            password_changed_time: Utc::now(),
+            password_history: Vec::new(),
+            totp: None,
+            new_password: None,
+            reused_password: None,
         };
         assert!(account.verify_password("password"));
         assert!(!account.verify_password("updated"));
@@ -317,4 +942,301 @@ mod tests {
             Account::DEFAULT_HASH_ALGORITHM
         )
     }
+
+    #[test]
+    fn from_v0_26() {
+        use bincode::Options;
+
+        let old = AccountV0_26 {
+            username: "test".to_string(),
+            password: SaltedPassword::with_argon2id("password").unwrap(),
+            role: Role::SecurityAdministrator,
+            name: String::new(),
+            department: String::new(),
+            creation_time: Utc::now(),
+            last_signin_time: None,
+            allow_access_from: None,
+            max_parallel_sessions: Some(2),
+            password_hash_algorithm: PasswordHashAlgorithm::Argon2id,
+        };
+        let value = bincode::DefaultOptions::new().serialize(&old).unwrap();
+
+        let migrated = Account::from_v0_26(&value).unwrap();
+        assert_eq!(migrated.username, "test");
+        assert_eq!(migrated.max_parallel_sessions, Some(2));
+        assert!(migrated.verify_password("password"));
+        assert_eq!(migrated.failed_logins(), 0);
+        assert_eq!(migrated.password_changed_time(), old.creation_time);
+    }
+
+    #[test]
+    fn password_policy() {
+        let policy = PasswordPolicy {
+            min_length: 8,
+            require_uppercase: true,
+            require_digit: true,
+            history: 1,
+            max_age: Some(Duration::from_secs(3600)),
+            ..PasswordPolicy::default()
+        };
+        assert!(policy.check("Short1").is_err());
+        assert!(policy.check("lowercase1").is_err());
+        assert!(policy.check("NoDigitsHere").is_err());
+        assert!(policy.check("Valid1234").is_ok());
+
+        let mut account = Account::new(
+            "test",
+            "Password1",
+            Role::SecurityAdministrator,
+            String::new(),
+            String::new(),
+            None,
+            None,
+        )
+        .unwrap();
+        assert!(account.change_password("Password1", &policy).is_err());
+        assert!(account.change_password("Password2", &policy).is_ok());
+        assert!(account.change_password("Password1", &policy).is_err());
+        assert!(account.change_password("Password3", &policy).is_ok());
+        assert!(account.change_password("Password1", &policy).is_ok());
+        assert!(account.verify_password("Password1"));
+
+        let changed = account.password_changed_time();
+        assert!(!account.password_expired(&policy, changed));
+        assert!(account.password_expired(&policy, changed + chrono::Duration::hours(1)));
+        assert!(!account.password_expired(
+            &PasswordPolicy::default(),
+            changed + chrono::Duration::days(1000)
+        ));
+    }
+
+    #[test]
+    fn lockout() {
+        let mut account = Account::new(
+            "test",
+            "password",
+            Role::SecurityAdministrator,
+            String::new(),
+            String::new(),
+            None,
+            None,
+        )
+        .unwrap();
+        let policy = LockoutPolicy {
+            max_failures: 2,
+            duration: Duration::from_secs(600),
+        };
+        let now = Utc::now();
+
+        assert_eq!(
+            account
+                .validate_password("wrong", None, Some(&policy), now)
+                .unwrap(),
+            PasswordValidation::Invalid
+        );
+        assert_eq!(account.failed_logins(), 1);
+        assert_eq!(
+            account
+                .validate_password("password", None, Some(&policy), now)
+                .unwrap(),
+            PasswordValidation::Valid
+        );
+        assert_eq!(account.failed_logins(), 0);
+
+        account
+            .validate_password("wrong", None, Some(&policy), now)
+            .unwrap();
+        let until = now + chrono::Duration::seconds(600);
+        assert_eq!(
+            account
+                .validate_password("wrong", None, Some(&policy), now)
+                .unwrap(),
+            PasswordValidation::Locked(until)
+        );
+        assert_eq!(
+            account
+                .validate_password("password", None, Some(&policy), now)
+                .unwrap(),
+            PasswordValidation::Locked(until)
+        );
+        assert_eq!(account.locked_until(now), Some(until));
+        assert_eq!(account.locked_until(until), None);
+        assert_eq!(
+            account
+                .validate_password("password", None, Some(&policy), until)
+                .unwrap(),
+            PasswordValidation::Valid
+        );
+
+        for _ in 0..5 {
+            account.validate_password("wrong", None, None, now).unwrap();
+        }
+        assert_eq!(account.failed_logins(), 5);
+        assert_eq!(account.locked_until(now), None);
+        account.clear_lockout();
+        assert_eq!(account.failed_logins(), 0);
+    }
+
+    #[test]
+    fn totp() {
+        use chrono::TimeZone;
+
+        // The test vectors in RFC 6238, truncated to six digits.
+        let secret = b"12345678901234567890";
+        assert_eq!(Totp::code(secret, 59 / Totp::STEP), "287082");
+        assert_eq!(Totp::code(secret, 1_111_111_109 / Totp::STEP), "081804");
+
+        let key = TotpKey::new(&[7; TotpKey::LEN]).unwrap();
+        assert!(TotpKey::new(&[7; 16]).is_err());
+        let mut account = Account::new(
+            "test",
+            "password",
+            Role::SecurityAdministrator,
+            String::new(),
+            String::new(),
+            None,
+            None,
+        )
+        .unwrap();
+        let now = Utc.timestamp_opt(1_111_111_109, 0).unwrap();
+        assert!(account.verify_totp("081804", &key, None, now).is_err());
+
+        account.set_totp_secret(secret, &key).unwrap();
+        assert!(account.totp_enabled());
+        assert_eq!(
+            account.verify_totp("000000", &key, None, now).unwrap(),
+            PasswordValidation::Invalid
+        );
+        assert_eq!(
+            account.verify_totp("081804", &key, None, now).unwrap(),
+            PasswordValidation::Valid
+        );
+        // Replayed code
+        assert_eq!(
+            account.verify_totp("081804", &key, None, now).unwrap(),
+            PasswordValidation::Invalid
+        );
+        let other = TotpKey::new(&[8; TotpKey::LEN]).unwrap();
+        assert!(account.verify_totp("081804", &other, None, now).is_err());
+
+        // The password alone isn't enough, and a wrong code counts as a
+        // failure.
+        let next = now + chrono::Duration::seconds(Totp::STEP * 3);
+        let code = Totp::code(secret, next.timestamp() / Totp::STEP);
+        assert_eq!(
+            account
+                .validate_password("password", None, None, next)
+                .unwrap(),
+            PasswordValidation::TotpRequired
+        );
+        assert_eq!(
+            account
+                .validate_password("password", Some(("000000", &key)), None, next)
+                .unwrap(),
+            PasswordValidation::Invalid
+        );
+        assert_eq!(account.failed_logins(), 2);
+        assert_eq!(
+            account
+                .validate_password("wrong", Some((&code, &key)), None, next)
+                .unwrap(),
+            PasswordValidation::Invalid
+        );
+        assert_eq!(
+            account
+                .validate_password("password", Some((&code, &key)), None, next)
+                .unwrap(),
+            PasswordValidation::Valid
+        );
+        assert_eq!(account.failed_logins(), 0);
+
+        // Wrong codes lock the account.
+        let policy = LockoutPolicy {
+            max_failures: 2,
+            duration: Duration::from_secs(600),
+        };
+        let later = next + chrono::Duration::seconds(Totp::STEP * 3);
+        let code = Totp::code(secret, later.timestamp() / Totp::STEP);
+        assert_eq!(
+            account
+                .verify_totp("000000", &key, Some(&policy), later)
+                .unwrap(),
+            PasswordValidation::Invalid
+        );
+        let until = later + chrono::Duration::seconds(600);
+        assert_eq!(
+            account
+                .verify_totp("000000", &key, Some(&policy), later)
+                .unwrap(),
+            PasswordValidation::Locked(until)
+        );
+        assert_eq!(
+            account
+                .verify_totp(&code, &key, Some(&policy), later)
+                .unwrap(),
+            PasswordValidation::Locked(until)
+        );
+
+        account.clear_totp();
+        assert!(!account.totp_enabled());
+    }
+
+    #[test]
+    fn record_signin() {
+        let mut account = Account::new(
+            "test",
+            "password",
+            Role::SecurityAdministrator,
+            String::new(),
+            String::new(),
+            None,
+            None,
+        )
+        .unwrap();
+        let now = Utc::now();
+        let address = "192.168.0.1".parse().ok();
+
+        account.record_signin(false, address, now);
+        assert_eq!(account.last_failed_signin_time(), Some(now));
+        assert_eq!(account.last_failed_signin_address(), address);
+        assert_eq!(account.last_signin_time(), None);
+
+        account.record_signin(true, None, now);
+        assert_eq!(account.last_signin_time(), Some(now));
+        assert_eq!(account.last_signin_address(), None);
+        assert_eq!(account.last_failed_signin_address(), address);
+
+        account.update_last_signin_time(address);
+        assert!(account.last_signin_time() >= Some(now));
+        assert_eq!(account.last_signin_address(), address);
+    }
+
+    #[test]
+    fn is_signin_allowed() {
+        let mut account = Account::new(
+            "test",
+            "password",
+            Role::SecurityAdministrator,
+            String::new(),
+            String::new(),
+            None,
+            None,
+        )
+        .unwrap();
+        let host: IpAddr = "192.168.0.1".parse().unwrap();
+        let inside: IpAddr = "10.0.0.5".parse().unwrap();
+        assert!(account.is_signin_allowed(host));
+
+        account.allowed_networks = Some(HostNetworkGroup::new(
+            Vec::new(),
+            vec!["10.0.0.0/24".parse().unwrap()],
+            Vec::new(),
+        ));
+        assert!(account.is_signin_allowed(inside));
+        assert!(!account.is_signin_allowed(host));
+
+        account.allow_access_from = Some(vec![host]);
+        assert!(account.is_signin_allowed(host));
+        assert!(!account.is_signin_allowed("10.0.1.5".parse().unwrap()));
+    }
 }
@@ -0,0 +1,278 @@
+//! Portable archives of the key-value store.
+//!
+//! An archive is a gzip-compressed stream of bincode-encoded records. It starts
+//! with a header identifying the archive format and the version of this crate
+//! that created it, followed by the key-value pairs of each table. Since the
+//! archive does not depend on the on-disk format of RocksDB, it can be used to
+//! move data between systems running different versions of RocksDB.
+
+use anyhow::{anyhow, bail, Context, Result};
+use bincode::Options;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use rocksdb::{IteratorMode, OptimisticTransactionDB, ReadOptions, WriteBatchWithTransaction};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+const MAGIC: &[u8; 8] = b"REVIEWDB";
+const FORMAT_VERSION: u32 = 1;
+
+#[derive(Deserialize, Serialize)]
+struct Header {
+    format: u32,
+    version: String,
+}
+
+#[derive(Deserialize, Serialize)]
+enum Record {
+    Table(String),
+    Entry(
+        #[serde(with = "serde_bytes")] Vec<u8>,
+        #[serde(with = "serde_bytes")] Vec<u8>,
+    ),
+    End,
+}
+
+/// Writes all key-value pairs in the tables named `names` into an archive at
+/// `path`.
+///
+/// # Errors
+///
+/// Returns an error if the archive cannot be written or the database operation
+/// fails.
+pub(crate) fn export(db: &OptimisticTransactionDB, names: &[&str], path: &Path) -> Result<()> {
+    let file = File::create(path).context("cannot create archive")?;
+    let mut writer = GzEncoder::new(BufWriter::new(file), Compression::default());
+    writer.write_all(MAGIC)?;
+    let header = Header {
+        format: FORMAT_VERSION,
+        version: env!("CARGO_PKG_VERSION").to_string(),
+    };
+    write_record(&mut writer, &header)?;
+
+    // All the tables are read from one snapshot, so that the archive doesn't
+    // mix states across tables written during the export.
+    let snapshot = db.snapshot();
+    for name in names {
+        let cf = db
+            .cf_handle(name)
+            .ok_or_else(|| anyhow!("database must have column family \"{name}\""))?;
+        write_record(&mut writer, &Record::Table((*name).to_string()))?;
+        let mut opts = ReadOptions::default();
+        opts.set_snapshot(&snapshot);
+        for item in db.iterator_cf_opt(cf, opts, IteratorMode::Start) {
+            let (key, value) = item.context("cannot read from database")?;
+            write_record(&mut writer, &Record::Entry(key.to_vec(), value.to_vec()))?;
+        }
+    }
+    write_record(&mut writer, &Record::End)?;
+
+    writer.finish()?.flush()?;
+    Ok(())
+}
+
+/// Replaces the contents of the tables named `names` with the key-value pairs
+/// in the archive at `path`.
+///
+/// The whole archive is read and staged in a single write batch, along with
+/// the deletion of the current contents, before anything is written. The
+/// database is left unchanged if the archive is truncated or invalid.
+///
+/// # Errors
+///
+/// Returns an error if the archive cannot be read, was created with an
+/// incompatible version, contains a table not in `names`, or the database
+/// operation fails.
+pub(crate) fn import(db: &OptimisticTransactionDB, names: &[&str], path: &Path) -> Result<()> {
+    let file = File::open(path).context("cannot open archive")?;
+    let mut reader = GzDecoder::new(BufReader::new(file));
+    let mut magic = [0; MAGIC.len()];
+    reader
+        .read_exact(&mut magic)
+        .context("cannot read archive")?;
+    if &magic != MAGIC {
+        bail!("not an archive");
+    }
+    let header: Header = read_record(&mut reader)?;
+    if header.format != FORMAT_VERSION {
+        bail!("unsupported archive format {}", header.format);
+    }
+    let version = Version::parse(&header.version).context("invalid archive version")?;
+    if !crate::migration::is_compatible(&version) {
+        bail!("archive version {version} is incompatible with the current version");
+    }
+
+    let mut batch = WriteBatchWithTransaction::<true>::default();
+    for name in names {
+        clear(db, name, &mut batch)?;
+    }
+
+    let mut cf = None;
+    loop {
+        match read_record(&mut reader)? {
+            Record::Table(name) => {
+                if !names.contains(&name.as_str()) {
+                    bail!("unknown table \"{name}\" in archive");
+                }
+                cf = db.cf_handle(&name);
+            }
+            Record::Entry(key, value) => {
+                let cf = cf.ok_or_else(|| anyhow!("archive entry without a table"))?;
+                batch.put_cf(cf, key, value);
+            }
+            Record::End => break,
+        }
+    }
+    db.write(batch).context("cannot write to database")?;
+    Ok(())
+}
+
+/// Adds the deletion of all key-value pairs in the table named `name` to
+/// `batch`.
+fn clear(
+    db: &OptimisticTransactionDB,
+    name: &str,
+    batch: &mut WriteBatchWithTransaction<true>,
+) -> Result<()> {
+    let cf = db
+        .cf_handle(name)
+        .ok_or_else(|| anyhow!("database must have column family \"{name}\""))?;
+    for item in db.iterator_cf(cf, IteratorMode::Start) {
+        let (key, _) = item.context("cannot read from database")?;
+        batch.delete_cf(cf, key);
+    }
+    Ok(())
+}
+
+fn write_record<W: Write, T: Serialize>(writer: &mut W, record: &T) -> Result<()> {
+    bincode::DefaultOptions::new()
+        .serialize_into(writer, record)
+        .context("cannot write archive")
+}
+
+fn read_record<R: Read, T: for<'de> Deserialize<'de>>(reader: &mut R) -> Result<T> {
+    bincode::DefaultOptions::new()
+        .deserialize_from(reader)
+        .context("cannot read archive")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{event::DnsEventFields, EventKind, EventMessage, Store};
+    use bincode::Options;
+    use chrono::Utc;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    fn example_message() -> EventMessage {
+        let fields = DnsEventFields {
+            source: "collector1".to_string(),
+            session_end_time: Utc::now(),
+            src_addr: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+            src_port: 10000,
+            dst_addr: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)),
+            dst_port: 53,
+            proto: 17,
+            query: "foo.com".to_string(),
+            answer: vec!["1.1.1.1".to_string()],
+            trans_id: 1,
+            rtt: 1,
+            qclass: 0,
+            qtype: 0,
+            rcode: 0,
+            aa_flag: false,
+            tc_flag: false,
+            rd_flag: false,
+            ra_flag: false,
+            ttl: vec![1; 5],
+            confidence: 0.8,
+        };
+        EventMessage {
+            time: Utc::now(),
+            kind: EventKind::DnsCovertChannel,
+            fields: bincode::DefaultOptions::new()
+                .serialize(&fields)
+                .expect("serializable"),
+        }
+    }
+
+    #[test]
+    fn export_import() {
+        use crate::Iterable;
+
+        let archive_dir = tempfile::tempdir().unwrap();
+        let archive = archive_dir.path().join("store.archive");
+
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let src = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+        let key = src.events().put(&example_message()).unwrap();
+        src.category_map().insert("category 3").unwrap();
+        src.export(&archive).unwrap();
+
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let dst = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+        dst.category_map().insert("to be replaced").unwrap();
+        dst.import(&archive).unwrap();
+
+        let mut events = dst.events().iter_forward();
+        assert_eq!(events.next().unwrap().unwrap().0, key);
+        assert!(events.next().is_none());
+
+        let src_categories: Vec<_> = src
+            .category_map()
+            .iter(crate::Direction::Forward, None)
+            .map(Result::unwrap)
+            .collect();
+        let dst_categories: Vec<_> = dst
+            .category_map()
+            .iter(crate::Direction::Forward, None)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(src_categories, dst_categories);
+        assert_eq!(dst.category_map().count().unwrap(), 3);
+    }
+
+    #[test]
+    fn import_invalid_archive() {
+        let archive_dir = tempfile::tempdir().unwrap();
+        let archive = archive_dir.path().join("store.archive");
+        std::fs::write(&archive, b"not an archive").unwrap();
+
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+        assert!(store.import(&archive).is_err());
+    }
+
+    #[test]
+    fn import_truncated_archive() {
+        let archive_dir = tempfile::tempdir().unwrap();
+        let archive = archive_dir.path().join("store.archive");
+
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let src = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+        for i in 0..100 {
+            src.category_map().insert(&format!("category {i}")).unwrap();
+        }
+        src.export(&archive).unwrap();
+        let data = std::fs::read(&archive).unwrap();
+        std::fs::write(&archive, &data[..data.len() / 2]).unwrap();
+
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let dst = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+        dst.category_map().insert("kept").unwrap();
+        let key = dst.events().put(&example_message()).unwrap();
+        assert!(dst.import(&archive).is_err());
+
+        assert_eq!(dst.category_map().count().unwrap(), 3);
+        let mut events = dst.events().iter_forward();
+        assert_eq!(events.next().unwrap().unwrap().0, key);
+    }
+}
@@ -0,0 +1 @@
+DROP INDEX IF EXISTS cluster_event_ids_idx;
@@ -0,0 +1 @@
+CREATE INDEX IF NOT EXISTS cluster_event_ids_idx ON cluster USING GIN (event_ids);
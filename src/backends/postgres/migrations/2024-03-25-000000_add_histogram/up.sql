@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS histogram (
+  id SERIAL PRIMARY KEY,
+  description_id INTEGER NOT NULL,
+  lower DOUBLE PRECISION NOT NULL,
+  upper DOUBLE PRECISION NOT NULL,
+  counts BIGINT[] NOT NULL
+);
+CREATE INDEX IF NOT EXISTS histogram_idx_desc_id ON histogram(description_id);
@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS cluster_history (
+  id SERIAL PRIMARY KEY,
+  cluster_id INTEGER NOT NULL,
+  batch_ts TIMESTAMP NOT NULL,
+  size BIGINT NOT NULL,
+  score DOUBLE PRECISION,
+  UNIQUE (cluster_id, batch_ts)
+);
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS cluster_signature_tsv_idx;
+ALTER TABLE cluster DROP COLUMN IF EXISTS signature_tsv;
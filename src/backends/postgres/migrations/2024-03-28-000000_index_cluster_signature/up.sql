@@ -0,0 +1,3 @@
+ALTER TABLE cluster ADD COLUMN IF NOT EXISTS signature_tsv TSVECTOR
+  GENERATED ALWAYS AS (to_tsvector('simple', signature)) STORED;
+CREATE INDEX IF NOT EXISTS cluster_signature_tsv_idx ON cluster USING GIN (signature_tsv);
@@ -0,0 +1,4 @@
+DROP INDEX IF EXISTS outlier_idx_model_latest_event_id;
+DROP INDEX IF EXISTS outlier_idx_model_size;
+ALTER TABLE outlier DROP COLUMN IF EXISTS latest_event_id;
+DROP FUNCTION IF EXISTS max_event_id;
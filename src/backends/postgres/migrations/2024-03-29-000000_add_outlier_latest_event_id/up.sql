@@ -0,0 +1,11 @@
+CREATE OR REPLACE FUNCTION max_event_id(event_ids BIGINT[])
+RETURNS BIGINT
+LANGUAGE SQL IMMUTABLE
+AS $$
+  SELECT COALESCE(MAX(id), 0) FROM unnest(event_ids) AS id
+$$;
+
+ALTER TABLE outlier ADD COLUMN IF NOT EXISTS latest_event_id BIGINT NOT NULL
+  GENERATED ALWAYS AS (max_event_id(event_ids)) STORED;
+CREATE INDEX IF NOT EXISTS outlier_idx_model_size ON outlier(model_id, size, id);
+CREATE INDEX IF NOT EXISTS outlier_idx_model_latest_event_id ON outlier(model_id, latest_event_id, id);
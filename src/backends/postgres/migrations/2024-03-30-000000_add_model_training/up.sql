@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS model_training (
+  model_id INTEGER PRIMARY KEY,
+  trained_at TIMESTAMP NOT NULL,
+  data_start TIMESTAMP NOT NULL,
+  data_end TIMESTAMP NOT NULL,
+  data_sources TEXT[] NOT NULL,
+  hyperparameters JSONB NOT NULL,
+  feature_schema JSONB NOT NULL
+);
+CREATE INDEX IF NOT EXISTS model_training_data_sources ON model_training USING GIN (data_sources);
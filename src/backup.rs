@@ -2,9 +2,10 @@
 
 use crate::Store;
 use anyhow::Result;
-use chrono::{DateTime, TimeZone, Utc};
+use chrono::{DateTime, Datelike, TimeZone, Utc};
 use rocksdb::backup::BackupEngineInfo;
-use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, sync::Arc};
 use tokio::sync::RwLock;
 use tracing::{info, warn};
 
@@ -25,8 +26,61 @@ impl From<BackupEngineInfo> for BackupInfo {
     }
 }
 
+/// The retention policy applied to backups after each backup.
+///
+/// For each of the most recent `daily` days (and `weekly` ISO weeks) that have
+/// a backup, the latest backup of that day (or week) is retained.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct RetentionPolicy {
+    pub daily: u32,
+    pub weekly: u32,
+}
+
+impl RetentionPolicy {
+    /// Returns the number of the most recent backups to keep so that every
+    /// backup selected by this policy is retained.
+    ///
+    /// The backup engine can only purge the oldest backups, so all backups
+    /// newer than the oldest selected one are kept as well. The latest backup
+    /// is always kept.
+    #[must_use]
+    pub fn backups_to_keep(&self, backups: &[BackupEngineInfo]) -> u32 {
+        // RocksDB records backup timestamps in seconds since the Unix epoch.
+        let mut backups: Vec<_> = backups
+            .iter()
+            .filter_map(|b| {
+                Utc.timestamp_opt(b.timestamp, 0)
+                    .single()
+                    .map(|t| (b.backup_id, t.date_naive()))
+            })
+            .collect();
+        backups.sort_unstable_by(|a, b| b.0.cmp(&a.0));
+
+        let mut days = HashSet::new();
+        let mut weeks = HashSet::new();
+        let mut keep = 1;
+        for (i, (_, date)) in backups.iter().enumerate() {
+            let mut selected = false;
+            if days.len() < self.daily as usize && days.insert(*date) {
+                selected = true;
+            }
+            let week = date.iso_week();
+            if weeks.len() < self.weekly as usize && weeks.insert((week.year(), week.week())) {
+                selected = true;
+            }
+            if selected {
+                keep = i + 1;
+            }
+        }
+        u32::try_from(keep).unwrap_or(u32::MAX)
+    }
+}
+
 /// Creates a new database backup, keeping the specified number of backups.
 ///
+/// If a retention policy is stored in the database, it takes precedence over
+/// `backups_to_keep`.
+///
 /// # Errors
 ///
 /// Returns an error if backup fails.
@@ -35,7 +89,11 @@ pub async fn create(store: &Arc<RwLock<Store>>, flush: bool, backups_to_keep: u3
     info!("backing up database...");
     let res = {
         let mut store = store.write().await;
-        store.backup(flush, backups_to_keep)
+        match store.backup_retention_policy() {
+            Ok(Some(policy)) => store.backup_with_policy(flush, &policy),
+            Ok(None) => store.backup(flush, backups_to_keep),
+            Err(e) => Err(e),
+        }
     };
     match res {
         Ok(()) => {
@@ -93,7 +151,8 @@ pub async fn restore(store: &Arc<RwLock<Store>>, backup_id: Option<u32>) -> Resu
 mod tests {
     use crate::{event::DnsEventFields, EventKind, EventMessage, Store};
     use bincode::Options;
-    use chrono::Utc;
+    use chrono::{TimeZone, Utc};
+    use rocksdb::backup::BackupEngineInfo;
     use std::{
         net::{IpAddr, Ipv4Addr},
         sync::Arc,
@@ -183,4 +242,91 @@ mod tests {
         assert_eq!(backup_list.get(1).unwrap().id, 2);
         assert_eq!(backup_list.get(2).unwrap().id, 3);
     }
+
+    fn backup_info(id: u32, year: i32, month: u32, day: u32) -> BackupEngineInfo {
+        BackupEngineInfo {
+            timestamp: Utc
+                .with_ymd_and_hms(year, month, day, 0, 0, 0)
+                .unwrap()
+                .timestamp(),
+            backup_id: id,
+            size: 0,
+            num_files: 0,
+        }
+    }
+
+    #[test]
+    fn retention_policy() {
+        use crate::backup::RetentionPolicy;
+
+        // 2024-03-04 is a Monday.
+        let backups = vec![
+            backup_info(1, 2024, 2, 19),
+            backup_info(2, 2024, 2, 26),
+            backup_info(3, 2024, 3, 4),
+            backup_info(4, 2024, 3, 9),
+            backup_info(5, 2024, 3, 10),
+            backup_info(6, 2024, 3, 10),
+        ];
+
+        let policy = RetentionPolicy {
+            daily: 2,
+            weekly: 0,
+        };
+        assert_eq!(policy.backups_to_keep(&backups), 3);
+
+        let policy = RetentionPolicy {
+            daily: 1,
+            weekly: 2,
+        };
+        assert_eq!(policy.backups_to_keep(&backups), 5);
+
+        let policy = RetentionPolicy {
+            daily: 1,
+            weekly: 10,
+        };
+        assert_eq!(policy.backups_to_keep(&backups), 6);
+
+        let policy = RetentionPolicy::default();
+        assert_eq!(policy.backups_to_keep(&backups), 1);
+        assert_eq!(policy.backups_to_keep(&[]), 1);
+    }
+
+    #[tokio::test]
+    async fn create_with_retention_policy() {
+        use crate::backup::{create, list, RetentionPolicy};
+        use tokio::sync::RwLock;
+
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+
+        let store = Arc::new(RwLock::new(
+            Store::new(db_dir.path(), backup_dir.path()).unwrap(),
+        ));
+
+        {
+            let store = store.read().await;
+            assert!(store.backup_retention_policy().unwrap().is_none());
+            let policy = RetentionPolicy {
+                daily: 1,
+                weekly: 1,
+            };
+            store.set_backup_retention_policy(&policy).unwrap();
+            assert_eq!(store.backup_retention_policy().unwrap(), Some(policy));
+        }
+
+        let msg = example_message();
+        for _ in 0..3 {
+            {
+                let store = store.read().await;
+                store.events().put(&msg).unwrap();
+            }
+            create(&store, true, 3).await.unwrap();
+        }
+
+        // All backups are taken on the same day, so only the latest is kept.
+        let backup_list = list(&store).await.unwrap();
+        assert_eq!(backup_list.len(), 1);
+        assert_eq!(backup_list[0].id, 3);
+    }
 }
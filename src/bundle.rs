@@ -0,0 +1,272 @@
+//! Export and import of models.
+//!
+//! A bundle holds everything needed to move a trained model between
+//! environments: the model with its classifier, batch info, and scores, in
+//! the format of [`Model::into_serialized`], the scores in each batch, and the
+//! training metadata and the most recent pretrained model, if any.
+//!
+//! A bundle starts with `BUNDLE_MAGIC` and the format version, so that a
+//! bundle from an incompatible version is rejected. They are followed by
+//! everything but the pretrained model, and then the pretrained model as is,
+//! which is copied between the stream and its files without being held in
+//! memory.
+
+use crate::{
+    batch_info::BatchInfo, model::TrainingMetadata, scores::Scores, types::ModelScores,
+    BatchScores, Database, Model, PretrainedModelWriter, Store,
+};
+use anyhow::{anyhow, Context, Result};
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{self, Read, Write},
+    sync::Arc,
+};
+use tokio::sync::RwLock;
+
+const BUNDLE_MAGIC: &[u8] = b"RMB\0";
+const BUNDLE_FORMAT_VERSION: u32 = 1;
+const HEADER_SIZE: usize = 8;
+
+/// Everything in a bundle but the pretrained model, which follows it.
+#[derive(Deserialize, Serialize)]
+struct Body {
+    model: Vec<u8>,
+    /// The training metadata in JSON, which bincode cannot deserialize.
+    training_metadata: Option<String>,
+    /// The scores in each batch, in the order of the batches.
+    batch_scores: Vec<(i64, ModelScores)>,
+    /// The timestamp of the most recent pretrained model with the same name
+    /// as the model, if any.
+    pretrained: Option<i64>,
+}
+
+/// Writes the model with `name`, along with its batch info, scores, batch
+/// scores, training metadata, and pretrained model, to `writer` as a bundle
+/// that [`import_model`] can load, and returns `writer`.
+///
+/// The bundle is written on a thread for blocking work, and the pretrained
+/// model is copied to `writer` without being read into memory.
+///
+/// # Errors
+///
+/// Returns an error if the model does not exist, a database operation fails,
+/// or the bundle cannot be written.
+pub async fn export_model<W>(
+    db: &Database,
+    store: &Arc<RwLock<Store>>,
+    name: &str,
+    writer: W,
+) -> Result<W>
+where
+    W: Write + Send + 'static,
+{
+    let sql = db.load_model_by_name(name).await?;
+    let id = sql.id;
+    let mut model = Model::from_storage(sql);
+    let training_metadata = db
+        .model_training_metadata(id)
+        .await?
+        .map(|m| serde_json::to_string(&m))
+        .transpose()?;
+    let name = name.to_string();
+    crate::run_blocking(store, move |store| {
+        model.batch_info = store
+            .batch_info_map()
+            .get_all_for(id)?
+            .into_iter()
+            .map(BatchInfo::into_inner)
+            .collect();
+        if let Some(scores) = store.scores_map().get(id)? {
+            model.scores = scores.into_inner();
+        }
+        let batch_scores = store
+            .batch_scores_map()
+            .get_all_for(id)?
+            .into_iter()
+            .map(|s| (s.batch, s.scores))
+            .collect();
+        let pretrained = store.latest_pretrained_model(&name)?;
+
+        let mut writer = writer;
+        encode(
+            &mut writer,
+            &Body {
+                model: model.into_serialized()?,
+                training_metadata,
+                batch_scores,
+                pretrained: pretrained.as_ref().map(|(timestamp, _)| *timestamp),
+            },
+        )?;
+        if let Some((_, mut reader)) = pretrained {
+            io::copy(&mut reader, &mut writer).context("cannot write pretrained model")?;
+        }
+        writer.flush()?;
+        Ok(writer)
+    })
+    .await
+}
+
+/// Adds the model in the bundle read from `reader`, created by
+/// [`export_model`], along with its batch info, scores, batch scores,
+/// training metadata, and pretrained model, and returns the ID of the new
+/// model.
+///
+/// Either everything in the bundle is added, or nothing is. If storing any
+/// part fails, what has been added is removed. The bundle is read on a thread
+/// for blocking work, and the pretrained model is copied from `reader`
+/// without being read into memory.
+///
+/// # Errors
+///
+/// Returns an error if the bundle is invalid or of an incompatible version, a
+/// model with the same name, or its pretrained model with the same
+/// timestamp, already exists, or a database operation fails.
+pub async fn import_model<R>(db: &Database, store: &Arc<RwLock<Store>>, reader: R) -> Result<i32>
+where
+    R: Read + Send + 'static,
+{
+    let (body, model, pretrained) = crate::run_blocking(store, move |store| {
+        let mut reader = reader;
+        let body = decode(&mut reader)?;
+        let model = Model::from_serialized(&body.model)?;
+        // The pretrained model is written first, but becomes visible only
+        // when the writer finishes, after everything else has been stored.
+        let pretrained = body
+            .pretrained
+            .map(|timestamp| -> Result<_> {
+                let mut writer = store.pretrained_model_writer(&model.name, timestamp)?;
+                io::copy(&mut reader, &mut writer).context("cannot read pretrained model")?;
+                Ok(writer)
+            })
+            .transpose()?;
+        Ok((body, model, pretrained))
+    })
+    .await?;
+
+    let training_metadata: Option<TrainingMetadata> = body
+        .training_metadata
+        .map(|m| serde_json::from_str(&m))
+        .transpose()?;
+    let mut model = model;
+    let batch_info = std::mem::take(&mut model.batch_info);
+    let scores = std::mem::take(&mut model.scores);
+    let (sql, _, _) = model.into_storage();
+
+    let id = db.add_model(&sql).await?;
+    let records = Records {
+        training_metadata,
+        batch_info: batch_info
+            .into_iter()
+            .map(|inner| BatchInfo::new(id, inner))
+            .collect(),
+        scores: Scores::new(id, scores),
+        batch_scores: body
+            .batch_scores
+            .into_iter()
+            .map(|(batch, scores)| BatchScores::new(id, batch, scores))
+            .collect(),
+    };
+    if let Err(e) = add_records(db, store, id, records, pretrained).await {
+        if let Err(rollback) = db.delete_model(&sql.name).await {
+            return Err(e.context(format!("cannot remove the imported model: {rollback}")));
+        }
+        return Err(e);
+    }
+    Ok(id)
+}
+
+/// What [`import_model`] stores for a model added to PostgreSQL.
+struct Records {
+    training_metadata: Option<TrainingMetadata>,
+    batch_info: Vec<BatchInfo>,
+    scores: Scores,
+    batch_scores: Vec<BatchScores>,
+}
+
+/// Stores `records` of the model with `id`, and then finishes writing its
+/// pretrained model. If the pretrained model cannot be written, the records
+/// stored in RocksDB are removed.
+async fn add_records(
+    db: &Database,
+    store: &Arc<RwLock<Store>>,
+    id: i32,
+    records: Records,
+    pretrained: Option<PretrainedModelWriter>,
+) -> Result<()> {
+    let Records {
+        training_metadata,
+        batch_info,
+        scores,
+        batch_scores,
+    } = records;
+    if let Some(metadata) = training_metadata {
+        db.set_model_training_metadata(id, &metadata).await?;
+    }
+    crate::run_blocking(store, move |store| {
+        store.put_model_results(&batch_info, &scores, &batch_scores)?;
+        let Some(writer) = pretrained else {
+            return Ok(());
+        };
+        if let Err(e) = writer.finish() {
+            store.batch_info_map().delete_all_for(id)?;
+            store.scores_map().delete(id)?;
+            store.batch_scores_map().delete_all_for(id)?;
+            return Err(e);
+        }
+        Ok(())
+    })
+    .await
+}
+
+fn encode<W: Write>(writer: &mut W, body: &Body) -> Result<()> {
+    writer.write_all(BUNDLE_MAGIC)?;
+    writer.write_all(&BUNDLE_FORMAT_VERSION.to_le_bytes())?;
+    bincode::DefaultOptions::new().serialize_into(writer, body)?;
+    Ok(())
+}
+
+fn decode<R: Read>(reader: &mut R) -> Result<Body> {
+    let mut header = [0; HEADER_SIZE];
+    if reader.read_exact(&mut header).is_err() || &header[..4] != BUNDLE_MAGIC {
+        return Err(anyhow!("not a model bundle"));
+    }
+    let version = u32::from_le_bytes(header[4..].try_into()?);
+    if version != BUNDLE_FORMAT_VERSION {
+        return Err(anyhow!(
+            "bundle format mismatch: {version} (expecting: {BUNDLE_FORMAT_VERSION})"
+        ));
+    }
+    Ok(bincode::DefaultOptions::new().deserialize_from(reader)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_decode() {
+        let body = Body {
+            model: b"model".to_vec(),
+            training_metadata: Some("{}".to_string()),
+            batch_scores: vec![(10, [(1, 0.5)].into_iter().collect())],
+            pretrained: Some(20),
+        };
+        let mut bundle = Vec::new();
+        encode(&mut bundle, &body).unwrap();
+        bundle.extend(b"pretrained");
+        let mut reader = bundle.as_slice();
+        let decoded = decode(&mut reader).unwrap();
+        assert_eq!(decoded.model, body.model);
+        assert_eq!(decoded.training_metadata, body.training_metadata);
+        assert_eq!(decoded.batch_scores, body.batch_scores);
+        assert_eq!(decoded.pretrained, body.pretrained);
+        // The pretrained model follows the body.
+        assert_eq!(reader, b"pretrained");
+
+        bundle[4] = 0;
+        assert!(decode(&mut bundle.as_slice()).is_err());
+        assert!(decode(&mut b"RMB".as_slice()).is_err());
+        assert!(decode(&mut b"RCM\0\x01\0\0\0".as_slice()).is_err());
+    }
+}
@@ -2,13 +2,16 @@ use std::{borrow::Cow, cmp::Ordering};
 
 use serde::{Deserialize, Serialize};
 
-use crate::{Indexable, IndexedMapUpdate};
+use crate::{Indexable, IndexedMapUpdate, Named};
 
 /// A category for a cluster.
 #[derive(Debug, Deserialize, Queryable, Serialize, PartialEq, Eq)]
 pub struct Category {
     pub id: u32,
     pub name: String,
+
+    /// The ID of the category this category belongs to, if any.
+    pub parent_id: Option<u32>,
 }
 
 impl PartialOrd for Category {
@@ -53,6 +56,12 @@ impl Indexable for Category {
     }
 }
 
+impl Named for Category {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
 impl IndexedMapUpdate for Category {
     type Entry = Category;
 
@@ -75,3 +84,25 @@ impl IndexedMapUpdate for Category {
         self.name == value.name
     }
 }
+
+/// An update of the parent of a category.
+pub(crate) struct ParentUpdate {
+    pub(crate) parent_id: Option<u32>,
+}
+
+impl IndexedMapUpdate for ParentUpdate {
+    type Entry = Category;
+
+    fn key(&self) -> Option<Cow<[u8]>> {
+        None
+    }
+
+    fn apply(&self, mut value: Self::Entry) -> Result<Self::Entry, anyhow::Error> {
+        value.parent_id = self.parent_id;
+        Ok(value)
+    }
+
+    fn verify(&self, value: &Self::Entry) -> bool {
+        self.parent_id == value.parent_id
+    }
+}
@@ -0,0 +1,50 @@
+//! Removal of the data of deleted models.
+
+use crate::{Database, PrunedStatistics, Store};
+use anyhow::Result;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::info;
+
+/// The numbers of rows and entries deleted by [`remove_orphans`].
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct RemovedOrphans {
+    /// The number of entries deleted from the batch info table.
+    pub batch_info: usize,
+    /// The number of entries deleted from the scores table.
+    pub scores: usize,
+    /// The number of entries deleted from the batch scores table.
+    pub batch_scores: usize,
+    /// The number of rows deleted from `cluster`.
+    pub clusters: usize,
+    /// The number of rows deleted from `cluster_history`.
+    pub cluster_history: usize,
+    /// The number of rows deleted from `time_series`.
+    pub time_series: usize,
+    /// The number of rows deleted from `outlier`.
+    pub outliers: usize,
+    /// The number of rows deleted from `model_training`.
+    pub training_metadata: usize,
+    /// The numbers of rows deleted from the column statistics tables.
+    pub statistics: PrunedStatistics,
+}
+
+/// Deletes the batch info, scores, clusters, outliers, and statistics
+/// referencing models that no longer exist, and returns what was deleted.
+///
+/// Data of a model added while this is running may be deleted, so this
+/// should not run concurrently with adding models.
+///
+/// # Errors
+///
+/// Returns an error if a database operation fails.
+pub async fn remove_orphans(db: &Database, store: &Arc<RwLock<Store>>) -> Result<RemovedOrphans> {
+    let (models, mut removed) = db.delete_orphans().await?;
+
+    let store = store.read().await;
+    removed.batch_info = store.batch_info_map().delete_orphans(&models)?;
+    removed.scores = store.scores_map().delete_orphans(&models)?;
+    removed.batch_scores = store.batch_scores_map().delete_orphans(&models)?;
+    info!("removed data of deleted models: {removed:?}");
+    Ok(removed)
+}
@@ -1,7 +1,12 @@
-use crate::{tokio_postgres::types::ToSql, types::Cluster, Database, Error, Type, Value};
-use chrono::NaiveDateTime;
+use crate::{
+    tokio_postgres::types::ToSql, types::Cluster, Cursor, Database, Direction, Error, Page,
+    PageRequest, Type, Value,
+};
+use chrono::{NaiveDateTime, Utc};
+use diesel_async::AsyncPgConnection;
 use futures::future::join_all;
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use tracing::error;
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -16,6 +21,59 @@ pub struct UpdateClusterRequest {
     pub labels: Option<Vec<String>>,
 }
 
+/// The size and score of a cluster in a batch.
+#[derive(Debug, Deserialize, Queryable, Serialize)]
+pub struct ClusterHistory {
+    pub batch_ts: NaiveDateTime,
+    pub size: i64,
+    pub score: Option<f64>,
+}
+
+/// A cluster found by `Database::search_clusters`.
+pub struct ClusterSearchResult {
+    pub cluster: Cluster,
+    /// How well the signature matches the query, from 0 to 1.
+    pub rank: f32,
+}
+
+#[derive(QueryableByName)]
+struct RankedCluster {
+    #[diesel(sql_type = diesel::sql_types::Integer)]
+    id: i32,
+    #[diesel(sql_type = diesel::sql_types::Float)]
+    rank: f32,
+}
+
+/// The columns of a cluster changed by `Database::merge_clusters`.
+#[derive(Queryable)]
+struct MergeableCluster {
+    id: i32,
+    event_ids: Vec<Option<i64>>,
+    event_sources: Vec<Option<String>>,
+    size: i64,
+    labels: Option<Vec<Option<String>>>,
+    merged_into: Option<i32>,
+}
+
+/// A new cluster split from another by `Database::split_cluster`.
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::cluster)]
+struct SplitClusterInput {
+    category_id: i32,
+    cluster_id: String,
+    detector_id: i32,
+    event_ids: Vec<Option<i64>>,
+    event_sources: Vec<Option<String>>,
+    labels: Option<Vec<Option<String>>>,
+    last_modification_time: Option<NaiveDateTime>,
+    model_id: i32,
+    qualifier_id: i32,
+    score: Option<f64>,
+    signature: String,
+    size: i64,
+    status_id: i32,
+}
+
 #[derive(Queryable)]
 struct ClusterDbSchema {
     id: i32,
@@ -32,6 +90,7 @@ struct ClusterDbSchema {
     score: Option<f64>,
     last_modification_time: Option<NaiveDateTime>,
     model_id: i32,
+    merged_into: Option<i32>,
 }
 
 impl From<ClusterDbSchema> for Cluster {
@@ -56,17 +115,18 @@ impl From<ClusterDbSchema> for Cluster {
             score: c.score,
             last_modification_time: c.last_modification_time,
             model_id: c.model_id,
+            merged_into: c.merged_into,
         }
     }
 }
 
 impl Database {
-    /// Counts the number of clusters matching the given conditions.
+    /// Counts the number of clusters matching the given conditions. The
+    /// clusters merged into others are not counted.
     ///
     /// # Errors
     ///
     /// Returns an error if a database operation fails.
-    #[allow(clippy::vec_init_then_push)] // `vec![..]` requires static lifetime.
     pub async fn count_clusters(
         &self,
         model: i32,
@@ -75,33 +135,60 @@ impl Database {
         qualifiers: Option<&[i32]>,
         statuses: Option<&[i32]>,
     ) -> Result<i64, Error> {
-        let conn = self.pool.get().await?;
-        let mut any_variables = Vec::new();
-        let mut values = Vec::<&Value>::new();
-        values.push(&model);
-        if categories.is_some() {
-            any_variables.push(("category_id", Type::INT4_ARRAY));
-            values.push(&categories);
+        use super::schema::cluster::dsl;
+        use diesel::{ExpressionMethods, QueryDsl};
+        use diesel_async::RunQueryDsl;
+
+        let mut query = dsl::cluster
+            .filter(dsl::model_id.eq(model))
+            .filter(dsl::merged_into.is_null())
+            .into_boxed();
+        if let Some(categories) = categories {
+            query = query.filter(dsl::category_id.eq_any(categories));
         }
-        if detectors.is_some() {
-            any_variables.push(("detector_id", Type::INT4_ARRAY));
-            values.push(&detectors);
+        if let Some(detectors) = detectors {
+            query = query.filter(dsl::detector_id.eq_any(detectors));
         }
-        if qualifiers.is_some() {
-            any_variables.push(("qualifier_id", Type::INT4_ARRAY));
-            values.push(&qualifiers);
+        if let Some(qualifiers) = qualifiers {
+            query = query.filter(dsl::qualifier_id.eq_any(qualifiers));
         }
-        if statuses.is_some() {
-            any_variables.push(("status_id", Type::INT4_ARRAY));
-            values.push(&statuses);
+        if let Some(statuses) = statuses {
+            query = query.filter(dsl::status_id.eq_any(statuses));
         }
-        conn.count(
-            "cluster",
-            &[("model_id", Type::INT4)],
-            &any_variables,
-            &values,
-        )
-        .await
+        let mut conn = self.pool.get_diesel_conn().await?;
+        Ok(query.count().get_result::<i64>(&mut conn).await?)
+    }
+
+    /// Counts the clusters in the given category, across all models.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a database operation fails.
+    pub async fn count_clusters_in_category(&self, category: i32) -> Result<i64, Error> {
+        use super::schema::cluster::dsl;
+        use diesel::{ExpressionMethods, QueryDsl};
+        use diesel_async::RunQueryDsl;
+
+        let query = dsl::cluster.filter(dsl::category_id.eq(category)).count();
+        let mut conn = self.pool.get_diesel_conn().await?;
+        Ok(query.get_result::<i64>(&mut conn).await?)
+    }
+
+    /// Moves the clusters in the category `from` to the category `to`, and
+    /// returns the number of moved clusters.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a database operation fails.
+    pub async fn replace_cluster_category(&self, from: i32, to: i32) -> Result<usize, Error> {
+        use super::schema::cluster::dsl;
+        use diesel::{ExpressionMethods, QueryDsl};
+        use diesel_async::RunQueryDsl;
+
+        let query = diesel::update(dsl::cluster.filter(dsl::category_id.eq(from)))
+            .set(dsl::category_id.eq(to));
+        let mut conn = self.pool.get_diesel_conn().await?;
+        Ok(query.execute(&mut conn).await?)
     }
 
     /// Returns the numerical ID of the cluster with the given cluster ID.
@@ -120,12 +207,14 @@ impl Database {
         .await
     }
 
-    /// Returns the clusters that satisfy the given conditions.
+    /// Returns a page of the clusters that satisfy the given conditions,
+    /// except those merged into others, in descending order of size and then
+    /// of ID.
     ///
     /// # Errors
     ///
-    /// Returns an error if a database operation fails.
-    #[allow(clippy::too_many_arguments)]
+    /// Returns an error if the cursor of `request` is invalid or a database
+    /// operation fails.
     pub async fn load_clusters(
         &self,
         model: i32,
@@ -133,17 +222,128 @@ impl Database {
         detectors: Option<&[i32]>,
         qualifiers: Option<&[i32]>,
         statuses: Option<&[i32]>,
-        after: &Option<(i32, i64)>,
-        before: &Option<(i32, i64)>,
-        is_first: bool,
-        limit: usize,
-    ) -> Result<Vec<Cluster>, Error> {
+        request: &PageRequest,
+    ) -> Result<Page<Cluster>, Error> {
         use super::schema::cluster::dsl;
         use diesel::{BoolExpressionMethods, ExpressionMethods, QueryDsl};
         use diesel_async::RunQueryDsl;
 
-        let limit = i64::try_from(limit).map_err(|_| Error::InvalidInput("limit".into()))? + 1;
-        let mut query = dsl::cluster
+        let cursor = request
+            .cursor
+            .as_ref()
+            .map(Cursor::key::<(i64, i32)>)
+            .transpose()?;
+        let limit =
+            i64::try_from(request.limit).map_err(|_| Error::InvalidInput("limit".into()))? + 1;
+        let query = || {
+            let mut query = dsl::cluster
+                .select((
+                    dsl::id,
+                    dsl::cluster_id,
+                    dsl::category_id,
+                    dsl::detector_id,
+                    dsl::event_ids,
+                    dsl::event_sources,
+                    dsl::labels,
+                    dsl::qualifier_id,
+                    dsl::status_id,
+                    dsl::signature,
+                    dsl::size,
+                    dsl::score,
+                    dsl::last_modification_time,
+                    dsl::model_id,
+                    dsl::merged_into,
+                ))
+                .filter(dsl::model_id.eq(model))
+                .filter(dsl::merged_into.is_null())
+                .into_boxed();
+            if let Some(categories) = categories {
+                query = query.filter(dsl::category_id.eq_any(categories));
+            }
+            if let Some(detectors) = detectors {
+                query = query.filter(dsl::detector_id.eq_any(detectors));
+            }
+            if let Some(qualifiers) = qualifiers {
+                query = query.filter(dsl::qualifier_id.eq_any(qualifiers));
+            }
+            if let Some(statuses) = statuses {
+                query = query.filter(dsl::status_id.eq_any(statuses));
+            }
+            query
+        };
+
+        let mut page = query();
+        let mut beyond = query();
+        if let Some((size, id)) = cursor {
+            match request.direction {
+                Direction::Forward => {
+                    page = page.filter(
+                        dsl::size
+                            .lt(size)
+                            .or(dsl::size.eq(size).and(dsl::id.lt(id))),
+                    );
+                    beyond = beyond.filter(
+                        dsl::size
+                            .gt(size)
+                            .or(dsl::size.eq(size).and(dsl::id.ge(id))),
+                    );
+                }
+                Direction::Reverse => {
+                    page = page.filter(
+                        dsl::size
+                            .gt(size)
+                            .or(dsl::size.eq(size).and(dsl::id.gt(id))),
+                    );
+                    beyond = beyond.filter(
+                        dsl::size
+                            .lt(size)
+                            .or(dsl::size.eq(size).and(dsl::id.le(id))),
+                    );
+                }
+            }
+        }
+        page = match request.direction {
+            Direction::Forward => page
+                .order_by(dsl::size.desc())
+                .then_order_by(dsl::id.desc()),
+            Direction::Reverse => page.order_by(dsl::size.asc()).then_order_by(dsl::id.asc()),
+        };
+
+        let mut conn = self.pool.get_diesel_conn().await?;
+        let rows = page
+            .limit(limit)
+            .get_results::<ClusterDbSchema>(&mut conn)
+            .await?;
+        let has_beyond_cursor = cursor.is_some()
+            && !beyond
+                .limit(1)
+                .get_results::<ClusterDbSchema>(&mut conn)
+                .await?
+                .is_empty();
+        Ok(Page::from_rows(request, rows, has_beyond_cursor, |c| {
+            (Cursor::from_key(&(c.size, c.id)), c.into())
+        }))
+    }
+
+    /// Returns the clusters that include the event with the given timestamp
+    /// and source, in the order of their IDs. Clusters merged into others are
+    /// skipped, since the cluster they were merged into includes their events.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a database operation fails.
+    pub async fn clusters_of_event(
+        &self,
+        timestamp: i64,
+        source: &str,
+    ) -> Result<Vec<Cluster>, Error> {
+        use super::schema::cluster::dsl;
+        use diesel::{ExpressionMethods, PgArrayExpressionMethods, QueryDsl};
+        use diesel_async::RunQueryDsl;
+
+        // The GIN index on `event_ids` narrows down the clusters by timestamp,
+        // and the sources are compared here.
+        let query = dsl::cluster
             .select((
                 dsl::id,
                 dsl::cluster_id,
@@ -159,54 +359,339 @@ impl Database {
                 dsl::score,
                 dsl::last_modification_time,
                 dsl::model_id,
+                dsl::merged_into,
             ))
-            .filter(dsl::model_id.eq(&model))
-            .limit(limit)
-            .into_boxed();
+            .filter(dsl::event_ids.contains(vec![Some(timestamp)]))
+            .filter(dsl::merged_into.is_null())
+            .order_by(dsl::id.asc());
 
-        if let Some(categories) = categories {
-            query = query.filter(dsl::category_id.eq_any(categories));
-        }
-        if let Some(detectors) = detectors {
-            query = query.filter(dsl::detector_id.eq_any(detectors));
+        let mut conn = self.pool.get_diesel_conn().await?;
+        let rows = query.get_results::<ClusterDbSchema>(&mut conn).await?;
+        Ok(clusters_including_event(rows, timestamp, source))
+    }
+
+    /// Returns the clusters in the model with `model_id`, except those merged
+    /// into others, whose signatures contain all the words in `query`, in
+    /// descending order of their rank.
+    /// At most `limit` clusters are returned after skipping the first
+    /// `offset` ones.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `limit` or `offset` is too large or a database
+    /// operation fails.
+    pub async fn search_clusters(
+        &self,
+        model_id: i32,
+        query: &str,
+        limit: usize,
+        offset: usize,
+    ) -> Result<Vec<ClusterSearchResult>, Error> {
+        use super::schema::cluster::dsl;
+        use diesel::{
+            sql_query,
+            sql_types::{BigInt, Integer, Text},
+            ExpressionMethods, QueryDsl,
+        };
+        use diesel_async::RunQueryDsl;
+
+        let limit = i64::try_from(limit).map_err(|_| Error::InvalidInput("limit".into()))?;
+        let offset = i64::try_from(offset).map_err(|_| Error::InvalidInput("offset".into()))?;
+        let mut conn = self.pool.get_diesel_conn().await?;
+        // `@@` is accelerated by the GIN index on `signature_tsv`, and the
+        // normalization 32 scales the rank to the range from 0 to 1.
+        let ranked = sql_query(
+            "SELECT id, ts_rank(signature_tsv, query, 32) AS rank
+                FROM cluster, plainto_tsquery('simple', $2) AS query
+                WHERE model_id = $1 AND merged_into IS NULL AND signature_tsv @@ query
+                ORDER BY rank DESC, id ASC LIMIT $3 OFFSET $4",
+        )
+        .bind::<Integer, _>(model_id)
+        .bind::<Text, _>(query)
+        .bind::<BigInt, _>(limit)
+        .bind::<BigInt, _>(offset)
+        .load::<RankedCluster>(&mut conn)
+        .await?;
+        if ranked.is_empty() {
+            return Ok(Vec::new());
         }
-        if let Some(qualifiers) = qualifiers {
-            query = query.filter(dsl::qualifier_id.eq_any(qualifiers));
+
+        let ids: Vec<_> = ranked.iter().map(|r| r.id).collect();
+        let mut clusters: HashMap<i32, Cluster> = dsl::cluster
+            .select((
+                dsl::id,
+                dsl::cluster_id,
+                dsl::category_id,
+                dsl::detector_id,
+                dsl::event_ids,
+                dsl::event_sources,
+                dsl::labels,
+                dsl::qualifier_id,
+                dsl::status_id,
+                dsl::signature,
+                dsl::size,
+                dsl::score,
+                dsl::last_modification_time,
+                dsl::model_id,
+                dsl::merged_into,
+            ))
+            .filter(dsl::id.eq_any(&ids))
+            .get_results::<ClusterDbSchema>(&mut conn)
+            .await?
+            .into_iter()
+            .map(|c| (c.id, c.into()))
+            .collect();
+        Ok(ranked
+            .into_iter()
+            .filter_map(|r| {
+                Some(ClusterSearchResult {
+                    cluster: clusters.remove(&r.id)?,
+                    rank: r.rank,
+                })
+            })
+            .collect())
+    }
+
+    /// Merges the cluster `from` into the cluster `into`, both in the model
+    /// with `model_id`. The events and labels of `from` that `into` doesn't
+    /// have are added to `into`, the size of `from` less the events both
+    /// have is added to that of `into`, and `from` is marked as merged into
+    /// `into`. The events later found for `from` by `update_clusters` are
+    /// added to `into`.
+    ///
+    /// Returns the ID of `into`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if either cluster does not exist, they are the same,
+    /// either has already been merged, or a database operation fails.
+    pub async fn merge_clusters(
+        &self,
+        model_id: i32,
+        into: &str,
+        from: &str,
+    ) -> Result<i32, Error> {
+        use super::schema::cluster::dsl;
+        use diesel::{ExpressionMethods, QueryDsl};
+        use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection, RunQueryDsl};
+
+        if into == from {
+            return Err(Error::InvalidInput(format!(
+                "cannot merge cluster \"{into}\" into itself"
+            )));
         }
-        if let Some(statuses) = statuses {
-            query = query.filter(dsl::status_id.eq_any(statuses));
+        let mut conn = self.pool.get_diesel_conn().await?;
+        conn.transaction::<_, Error, _>(|conn| {
+            async move {
+                let into = load_mergeable_cluster(conn, model_id, into).await?;
+                let from = load_mergeable_cluster(conn, model_id, from).await?;
+
+                let mut events: Vec<_> =
+                    into.event_ids.into_iter().zip(into.event_sources).collect();
+                let mut seen: HashSet<_> = events.iter().cloned().collect();
+                let mut shared = 0;
+                for event in from.event_ids.into_iter().zip(from.event_sources) {
+                    if seen.insert(event.clone()) {
+                        events.push(event);
+                    } else {
+                        shared += 1;
+                    }
+                }
+                let (event_ids, event_sources): (Vec<_>, Vec<_>) = events.into_iter().unzip();
+                let labels = match (into.labels, from.labels) {
+                    (Some(mut labels), Some(others)) => {
+                        for label in others {
+                            if !labels.contains(&label) {
+                                labels.push(label);
+                            }
+                        }
+                        Some(labels)
+                    }
+                    (labels, None) | (None, labels) => labels,
+                };
+                let now = Utc::now().naive_utc();
+
+                diesel::update(dsl::cluster.filter(dsl::id.eq(into.id)))
+                    .set((
+                        dsl::event_ids.eq(event_ids),
+                        dsl::event_sources.eq(event_sources),
+                        dsl::size.eq(into.size + (from.size - shared).max(0)),
+                        dsl::labels.eq(labels),
+                        dsl::last_modification_time.eq(Some(now)),
+                    ))
+                    .execute(conn)
+                    .await?;
+                diesel::update(dsl::cluster.filter(dsl::id.eq(from.id)))
+                    .set((
+                        dsl::merged_into.eq(Some(into.id)),
+                        dsl::last_modification_time.eq(Some(now)),
+                    ))
+                    .execute(conn)
+                    .await?;
+                Ok(into.id)
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    /// Moves `events`, given as pairs of timestamps and sources, from the
+    /// cluster `from` in the model with `model_id` to a new cluster
+    /// `cluster_id` with `signature` and `qualifier_id`. The new cluster
+    /// takes the other attributes of `from`, and the sizes of both clusters
+    /// are adjusted by the number of events moved.
+    ///
+    /// Returns the ID of the new cluster.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `from` does not exist or has been merged into
+    /// another cluster, `events` is empty, not all of `events` are in `from`,
+    /// all the events of `from` would be moved, a cluster with `cluster_id`
+    /// already exists, or a database operation fails.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn split_cluster(
+        &self,
+        model_id: i32,
+        from: &str,
+        cluster_id: &str,
+        events: &[(i64, String)],
+        signature: &str,
+        qualifier_id: i32,
+    ) -> Result<i32, Error> {
+        use super::schema::cluster::dsl;
+        use diesel::{ExpressionMethods, OptionalExtension, QueryDsl};
+        use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection, RunQueryDsl};
+
+        if events.is_empty() {
+            return Err(Error::InvalidInput("no events to move".into()));
         }
+        let mut conn = self.pool.get_diesel_conn().await?;
+        conn.transaction::<_, Error, _>(|conn| {
+            async move {
+                let source = dsl::cluster
+                    .select((
+                        dsl::id,
+                        dsl::cluster_id,
+                        dsl::category_id,
+                        dsl::detector_id,
+                        dsl::event_ids,
+                        dsl::event_sources,
+                        dsl::labels,
+                        dsl::qualifier_id,
+                        dsl::status_id,
+                        dsl::signature,
+                        dsl::size,
+                        dsl::score,
+                        dsl::last_modification_time,
+                        dsl::model_id,
+                        dsl::merged_into,
+                    ))
+                    .filter(dsl::model_id.eq(model_id))
+                    .filter(dsl::cluster_id.eq(from))
+                    .for_update()
+                    .first::<ClusterDbSchema>(conn)
+                    .await
+                    .optional()?
+                    .ok_or_else(|| Error::InvalidInput(format!("no such cluster: {from}")))?;
+                if source.merged_into.is_some() {
+                    return Err(Error::InvalidInput(format!(
+                        "cluster \"{from}\" has already been merged"
+                    )));
+                }
+
+                let moving: HashSet<_> = events
+                    .iter()
+                    .map(|(ts, src)| (Some(*ts), Some(src.clone())))
+                    .collect();
+                let (moved, kept): (Vec<_>, Vec<_>) = source
+                    .event_ids
+                    .into_iter()
+                    .zip(source.event_sources)
+                    .partition(|event| moving.contains(event));
+                if moved.iter().collect::<HashSet<_>>().len() != moving.len() {
+                    return Err(Error::InvalidInput(format!(
+                        "not all of the events are in cluster \"{from}\""
+                    )));
+                }
+                if kept.is_empty() {
+                    return Err(Error::InvalidInput(
+                        "cannot move all the events of a cluster".into(),
+                    ));
+                }
+                let moved_size = i64::try_from(moved.len()).unwrap_or(i64::MAX);
+                let now = Some(Utc::now().naive_utc());
+
+                let (kept_ids, kept_sources): (Vec<_>, Vec<_>) = kept.into_iter().unzip();
+                diesel::update(dsl::cluster.filter(dsl::id.eq(source.id)))
+                    .set((
+                        dsl::event_ids.eq(kept_ids),
+                        dsl::event_sources.eq(kept_sources),
+                        dsl::size.eq((source.size - moved_size).max(0)),
+                        dsl::last_modification_time.eq(now),
+                    ))
+                    .execute(conn)
+                    .await?;
+
+                let (event_ids, event_sources) = moved.into_iter().unzip();
+                let new = SplitClusterInput {
+                    category_id: source.category_id,
+                    cluster_id: cluster_id.to_string(),
+                    detector_id: source.detector_id,
+                    event_ids,
+                    event_sources,
+                    labels: source.labels,
+                    last_modification_time: now,
+                    model_id,
+                    qualifier_id,
+                    score: source.score,
+                    signature: signature.to_string(),
+                    size: moved_size,
+                    status_id: source.status_id,
+                };
+                let id = diesel::insert_into(dsl::cluster)
+                    .values(&new)
+                    .returning(dsl::id)
+                    .get_result(conn)
+                    .await
+                    .map_err(|e| insert_error(cluster_id, e))?;
+                Ok(id)
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    /// Returns the sizes and scores of the cluster with the given ID in the
+    /// batches between `after` and `before`, inclusive, in chronological
+    /// order.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a database operation fails.
+    pub async fn cluster_history(
+        &self,
+        id: i32,
+        after: Option<NaiveDateTime>,
+        before: Option<NaiveDateTime>,
+    ) -> Result<Vec<ClusterHistory>, Error> {
+        use super::schema::cluster_history::dsl;
+        use diesel::{ExpressionMethods, QueryDsl};
+        use diesel_async::RunQueryDsl;
+
+        let mut query = dsl::cluster_history
+            .select((dsl::batch_ts, dsl::size, dsl::score))
+            .filter(dsl::cluster_id.eq(id))
+            .order_by(dsl::batch_ts.asc())
+            .into_boxed();
         if let Some(after) = after {
-            query = query.filter(
-                dsl::size
-                    .eq(after.1)
-                    .and(dsl::id.lt(after.0))
-                    .or(dsl::size.lt(after.1)),
-            );
+            query = query.filter(dsl::batch_ts.ge(after));
         }
         if let Some(before) = before {
-            query = query.filter(
-                dsl::size
-                    .eq(before.1)
-                    .and(dsl::id.gt(before.0))
-                    .or(dsl::size.gt(before.1)),
-            );
-        }
-        if is_first {
-            query = query
-                .order_by(dsl::size.desc())
-                .then_order_by(dsl::id.desc());
-        } else {
-            query = query.order_by(dsl::size.asc()).then_order_by(dsl::id.asc());
+            query = query.filter(dsl::batch_ts.le(before));
         }
-
         let mut conn = self.pool.get_diesel_conn().await?;
-        let rows = query.get_results::<ClusterDbSchema>(&mut conn).await?;
-        if is_first {
-            Ok(rows.into_iter().map(Into::into).collect())
-        } else {
-            Ok(rows.into_iter().rev().map(Into::into).collect())
-        }
+        Ok(query.load(&mut conn).await?)
     }
 
     /// Updates the cluster with the given ID.
@@ -245,7 +730,46 @@ impl Database {
         }
     }
 
-    /// Updates the clusters with the given cluster IDs.
+    /// Sets the qualifier of the clusters with the given IDs in the model with
+    /// `model_id` to `qualifier_id`, and returns the number of clusters
+    /// updated.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a database operation fails.
+    pub async fn update_clusters_qualifier(
+        &self,
+        model_id: i32,
+        cluster_ids: &[i32],
+        qualifier_id: i32,
+    ) -> Result<usize, Error> {
+        use super::schema::cluster::dsl;
+        use diesel::{ExpressionMethods, QueryDsl};
+        use diesel_async::RunQueryDsl;
+
+        if cluster_ids.is_empty() {
+            return Ok(0);
+        }
+        let query = diesel::update(
+            dsl::cluster
+                .filter(dsl::model_id.eq(model_id))
+                .filter(dsl::id.eq_any(cluster_ids)),
+        )
+        .set((
+            dsl::qualifier_id.eq(qualifier_id),
+            dsl::last_modification_time.eq(Some(Utc::now().naive_utc())),
+        ));
+        let mut conn = self.pool.get_diesel_conn().await?;
+        Ok(query.execute(&mut conn).await?)
+    }
+
+    /// Updates the clusters with the given cluster IDs. The events for a
+    /// cluster merged into another are added to the cluster it was merged
+    /// into, whose other attributes are kept.
+    ///
+    /// If `batch_ts` is given, the sizes and scores of the updated clusters
+    /// are recorded, in the same transaction as the update, as those in the
+    /// batch at `batch_ts`, replacing any recorded earlier for the batch.
     ///
     /// # Errors
     ///
@@ -254,9 +778,24 @@ impl Database {
         &self,
         cluster_update: Vec<UpdateClusterRequest>,
         model_id: i32,
+        batch_ts: Option<NaiveDateTime>,
     ) -> Result<(), Error> {
         let query = "SELECT attempt_cluster_upsert(
             $1::text, $2::int4, $3::int8[], $4::text[], $5::int4, $6::text, $7::int8, $8::int4, $9::text[], $10::float8)";
+        // Follows the whole chain of merges, as `attempt_cluster_upsert` does,
+        // to record the history of the cluster the events were added to.
+        let history_query = "WITH RECURSIVE target (id, merged_into) AS (
+                SELECT id, merged_into FROM cluster
+                WHERE model_id = $1 AND cluster_id = ANY($3)
+                UNION
+                SELECT cluster.id, cluster.merged_into FROM cluster
+                JOIN target ON cluster.id = target.merged_into
+            )
+            INSERT INTO cluster_history (cluster_id, batch_ts, size, score)
+            SELECT id, $2, size, score FROM cluster
+            WHERE id IN (SELECT id FROM target WHERE merged_into IS NULL)
+            ON CONFLICT (cluster_id, batch_ts)
+            DO UPDATE SET size = EXCLUDED.size, score = EXCLUDED.score";
 
         // Split `cluster_update` into Vector of 1,000 each to create database
         // transactions with 1,000 queries
@@ -275,7 +814,7 @@ impl Database {
                     tokio::spawn(async move {
                         let mut conn = pool.get().await?;
                         let txn = conn.build_transaction().await?;
-                        for c in chunk {
+                        for c in &chunk {
                             let (timestamps, sources) = c.event_ids.iter().fold(
                                 (Vec::new(), Vec::new()),
                                 |(mut ts, mut src), id| {
@@ -299,6 +838,12 @@ impl Database {
 
                             txn.execute(query, params.as_slice()).await?;
                         }
+                        if let Some(batch_ts) = batch_ts {
+                            let cluster_ids: Vec<&str> =
+                                chunk.iter().map(|c| c.cluster_id.as_str()).collect();
+                            txn.execute(history_query, &[&model_id, &batch_ts, &cluster_ids])
+                                .await?;
+                        }
                         txn.commit().await?;
                         anyhow::Ok(())
                     })
@@ -318,3 +863,109 @@ impl Database {
         Ok(())
     }
 }
+
+/// Converts an error inserting the cluster `cluster_id` into `Error`, which is
+/// `Error::InvalidInput` if the cluster already exists.
+pub(crate) fn insert_error(cluster_id: &str, e: diesel::result::Error) -> Error {
+    use diesel::result::{DatabaseErrorKind, Error as DieselError};
+
+    match e {
+        DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+            Error::InvalidInput(format!("cluster \"{cluster_id}\" already exists"))
+        }
+        e => Error::Query(e),
+    }
+}
+
+/// Returns the clusters in `rows` that include the event with `timestamp` and
+/// `source`, except those merged into others.
+fn clusters_including_event(
+    rows: Vec<ClusterDbSchema>,
+    timestamp: i64,
+    source: &str,
+) -> Vec<Cluster> {
+    rows.into_iter()
+        .filter(|c| c.merged_into.is_none())
+        .filter(|c| {
+            c.event_ids
+                .iter()
+                .zip(&c.event_sources)
+                .any(|(t, s)| *t == Some(timestamp) && s.as_deref() == Some(source))
+        })
+        .map(Into::into)
+        .collect()
+}
+
+/// Loads the cluster with `cluster_id` in the model with `model_id` and locks
+/// its row until the end of the transaction.
+async fn load_mergeable_cluster(
+    conn: &mut AsyncPgConnection,
+    model_id: i32,
+    cluster_id: &str,
+) -> Result<MergeableCluster, Error> {
+    use super::schema::cluster::dsl;
+    use diesel::{ExpressionMethods, OptionalExtension, QueryDsl};
+    use diesel_async::RunQueryDsl;
+
+    let cluster = dsl::cluster
+        .select((
+            dsl::id,
+            dsl::event_ids,
+            dsl::event_sources,
+            dsl::size,
+            dsl::labels,
+            dsl::merged_into,
+        ))
+        .filter(dsl::model_id.eq(model_id))
+        .filter(dsl::cluster_id.eq(cluster_id))
+        .for_update()
+        .first::<MergeableCluster>(conn)
+        .await
+        .optional()?
+        .ok_or_else(|| Error::InvalidInput(format!("no such cluster: {cluster_id}")))?;
+    if cluster.merged_into.is_some() {
+        return Err(Error::InvalidInput(format!(
+            "cluster \"{cluster_id}\" has already been merged"
+        )));
+    }
+    Ok(cluster)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ClusterDbSchema;
+
+    fn cluster(id: i32, events: &[(i64, &str)], merged_into: Option<i32>) -> ClusterDbSchema {
+        ClusterDbSchema {
+            id,
+            cluster_id: format!("cluster {id}"),
+            category_id: 1,
+            detector_id: 1,
+            event_ids: events.iter().map(|(t, _)| Some(*t)).collect(),
+            event_sources: events.iter().map(|(_, s)| Some((*s).to_string())).collect(),
+            labels: None,
+            qualifier_id: 1,
+            status_id: 1,
+            signature: String::new(),
+            size: i64::try_from(events.len()).unwrap(),
+            score: None,
+            last_modification_time: None,
+            model_id: 1,
+            merged_into,
+        }
+    }
+
+    #[test]
+    fn clusters_including_event_after_merge() {
+        // Cluster 2 merged into cluster 1, which now has the events of both.
+        let rows = vec![
+            cluster(1, &[(10, "a"), (20, "b"), (30, "c")], None),
+            cluster(2, &[(20, "b"), (30, "c")], Some(1)),
+            cluster(3, &[(20, "c")], None),
+        ];
+        let found = super::clusters_including_event(rows, 20, "b");
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id, 1);
+        assert_eq!(found[0].merged_into, None);
+    }
+}
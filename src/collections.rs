@@ -2,7 +2,11 @@ mod indexed_map;
 mod indexed_set;
 mod map;
 
-use crate::EXCLUSIVE;
+use crate::{
+    metrics::{record, Operation},
+    notification::{notify, ChangeKind, Channel},
+    EXCLUSIVE,
+};
 
 pub use self::{
     indexed_map::IndexedMap,
@@ -40,11 +44,38 @@ pub trait IterableMap<'i, I: Iterator + 'i> {
     fn iter_backward(&'i self) -> Result<I>;
 }
 
+/// How the IDs of an indexed map are used, returned by
+/// [`crate::IndexedTable::id_usage`].
+///
+/// The ID of a removed entry becomes available right away. Available IDs are
+/// assigned in the reverse order of removal, i.e., the most recently freed ID
+/// goes to the next inserted entry, and a new ID is taken only when none is
+/// available. This way a map with many insertions and removals doesn't run out
+/// of IDs as long as the number of entries stays within `u32`. The ID of a
+/// hard-deactivated entry stays reserved until it is cleared, and a soft-deactivated entry
+/// keeps its ID.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IdUsage {
+    /// The number of IDs assigned to entries, including soft-deactivated ones.
+    pub used: u32,
+
+    /// The number of IDs below `next_new` that can be assigned again.
+    pub free: u32,
+
+    /// The number of IDs reserved by hard-deactivated entries.
+    pub reserved: u32,
+
+    /// The lowest ID never assigned before. IDs are taken from here only when
+    /// no ID is free.
+    pub next_new: u32,
+}
+
 #[derive(Deserialize, Serialize)]
 enum KeyIndexEntry {
     Key(Vec<u8>),
     Index(u32),
     Inactive(Option<u32>),
+    Deactivated(Vec<u8>),
 }
 
 #[derive(Default, Deserialize, Serialize)]
@@ -62,7 +93,8 @@ impl KeyIndex {
             .context("invalid serialized form")
     }
 
-    /// Returns the number of entries containing `Key`.
+    /// Returns the number of entries containing `Key`, which excludes
+    /// soft-deactivated entries.
     fn count(&self) -> usize {
         self.keys
             .iter()
@@ -70,15 +102,46 @@ impl KeyIndex {
             .count()
     }
 
-    /// Retrieves the key corresponding to the given index.
+    /// Retrieves the key corresponding to the given index, whether or not the
+    /// entry is soft-deactivated.
     fn get(&self, index: u32) -> Result<Option<&[u8]>> {
         let i = usize::try_from(index).context("index out of range")?;
         Ok(match self.keys.get(i) {
             Some(KeyIndexEntry::Inactive(_) | KeyIndexEntry::Index(_)) | None => None,
-            Some(KeyIndexEntry::Key(key)) => Some(key),
+            Some(KeyIndexEntry::Key(key) | KeyIndexEntry::Deactivated(key)) => Some(key),
         })
     }
 
+    /// Returns the indices and keys of the soft-deactivated entries.
+    pub(crate) fn deactivated(&self) -> impl Iterator<Item = (u32, &[u8])> {
+        self.keys
+            .iter()
+            .enumerate()
+            .filter_map(|(i, entry)| match entry {
+                KeyIndexEntry::Deactivated(key) => Some((
+                    u32::try_from(i).expect("not exceeding u32::MAX"),
+                    key.as_slice(),
+                )),
+                _ => None,
+            })
+    }
+
+    /// Returns how the indices are used.
+    fn usage(&self) -> IdUsage {
+        let mut usage = IdUsage {
+            next_new: u32::try_from(self.keys.len()).unwrap_or(u32::MAX),
+            ..IdUsage::default()
+        };
+        for entry in &self.keys {
+            match entry {
+                KeyIndexEntry::Key(_) | KeyIndexEntry::Deactivated(_) => usage.used += 1,
+                KeyIndexEntry::Index(_) => usage.free += 1,
+                KeyIndexEntry::Inactive(_) => usage.reserved += 1,
+            }
+        }
+        usage
+    }
+
     pub fn iter(&self) -> KeyIndexIterator {
         KeyIndexIterator {
             entries: &self.keys,
@@ -90,7 +153,9 @@ impl KeyIndex {
     fn deactivate(&mut self, id: u32) -> Result<Vec<u8>> {
         let i = usize::try_from(id).context("index out of range")?;
         let key = match self.keys.get_mut(i) {
-            Some(KeyIndexEntry::Key(ref mut key)) => mem::take(key),
+            Some(KeyIndexEntry::Key(ref mut key) | KeyIndexEntry::Deactivated(ref mut key)) => {
+                mem::take(key)
+            }
             Some(KeyIndexEntry::Inactive(_) | KeyIndexEntry::Index(_)) => {
                 bail!("no such ID");
             }
@@ -101,6 +166,38 @@ impl KeyIndex {
         Ok(key)
     }
 
+    /// Marks the key at the given index as soft-deactivated, keeping its index,
+    /// and returns the key.
+    fn soft_deactivate(&mut self, id: u32) -> Result<Vec<u8>> {
+        let i = usize::try_from(id).context("index out of range")?;
+        let key = match self.keys.get_mut(i) {
+            Some(KeyIndexEntry::Key(ref mut key)) => mem::take(key),
+            Some(KeyIndexEntry::Deactivated(_)) => bail!("already deactivated"),
+            Some(KeyIndexEntry::Inactive(_) | KeyIndexEntry::Index(_)) => {
+                bail!("no such ID");
+            }
+            None => bail!("index out of range"),
+        };
+        self.keys[i] = KeyIndexEntry::Deactivated(key.clone());
+        Ok(key)
+    }
+
+    /// Clears the soft-deactivated mark of the key at the given index, and
+    /// returns the key.
+    fn reactivate(&mut self, id: u32) -> Result<Vec<u8>> {
+        let i = usize::try_from(id).context("index out of range")?;
+        let key = match self.keys.get_mut(i) {
+            Some(KeyIndexEntry::Deactivated(ref mut key)) => mem::take(key),
+            Some(KeyIndexEntry::Key(_)) => bail!("not deactivated"),
+            Some(KeyIndexEntry::Inactive(_) | KeyIndexEntry::Index(_)) => {
+                bail!("no such ID");
+            }
+            None => bail!("index out of range"),
+        };
+        self.keys[i] = KeyIndexEntry::Key(key.clone());
+        Ok(key)
+    }
+
     /// Makes deactivated indices available.
     fn clear_inactive(&mut self) -> Result<()> {
         while let Some(inactive) = self.inactive {
@@ -132,7 +229,9 @@ impl KeyIndex {
             Ordering::Greater => {
                 let i = usize::try_from(id).context("too many keys")?;
                 self.available = match self.keys.get(i) {
-                    Some(KeyIndexEntry::Key(_)) => bail!("corrupt index"),
+                    Some(KeyIndexEntry::Key(_) | KeyIndexEntry::Deactivated(_)) => {
+                        bail!("corrupt index")
+                    }
                     Some(KeyIndexEntry::Index(i)) => *i,
                     _ => unreachable!(),
                 };
@@ -145,11 +244,51 @@ impl KeyIndex {
         Ok(id)
     }
 
+    /// Inserts a new key at the given index.
+    fn insert_at(&mut self, id: u32, key: &[u8]) -> Result<()> {
+        if id == u32::MAX {
+            bail!("index is full");
+        }
+        let i = usize::try_from(id).context("too many keys")?;
+        // Every slot appended here is available, and the list of available
+        // slots, which used to end at the old length, now runs through them.
+        while self.keys.len() <= i {
+            let next = u32::try_from(self.keys.len() + 1).context("too many keys")?;
+            self.keys.push(KeyIndexEntry::Index(next));
+        }
+        let next = match self.keys[i] {
+            KeyIndexEntry::Index(next) => next,
+            KeyIndexEntry::Key(_) | KeyIndexEntry::Inactive(_) | KeyIndexEntry::Deactivated(_) => {
+                bail!("ID already in use")
+            }
+        };
+        if self.available == id {
+            self.available = next;
+        } else {
+            let mut cur = self.available;
+            loop {
+                let j = usize::try_from(cur).context("corrupt index")?;
+                match self.keys.get_mut(j) {
+                    Some(KeyIndexEntry::Index(n)) if *n == id => {
+                        *n = next;
+                        break;
+                    }
+                    Some(KeyIndexEntry::Index(n)) => cur = *n,
+                    _ => bail!("corrupt index"),
+                }
+            }
+        }
+        self.keys[i] = KeyIndexEntry::Key(key.to_vec());
+        Ok(())
+    }
+
     /// Removes a key at the given index.
     fn remove(&mut self, id: u32) -> Result<Vec<u8>> {
         let i = usize::try_from(id).context("index out of range")?;
         let key = match self.keys.get_mut(i) {
-            Some(KeyIndexEntry::Key(ref mut key)) => mem::take(key),
+            Some(KeyIndexEntry::Key(ref mut key) | KeyIndexEntry::Deactivated(ref mut key)) => {
+                mem::take(key)
+            }
             Some(KeyIndexEntry::Inactive(_) | KeyIndexEntry::Index(_)) => {
                 bail!("no such ID");
             }
@@ -164,7 +303,9 @@ impl KeyIndex {
     fn update(&mut self, id: u32, key: &[u8]) -> Result<Vec<u8>> {
         let i = usize::try_from(id).context("index out of range")?;
         let key = match self.keys.get_mut(i) {
-            Some(KeyIndexEntry::Key(ref mut old_key)) => mem::replace(old_key, key.to_vec()),
+            Some(
+                KeyIndexEntry::Key(ref mut old_key) | KeyIndexEntry::Deactivated(ref mut old_key),
+            ) => mem::replace(old_key, key.to_vec()),
             Some(KeyIndexEntry::Inactive(_) | KeyIndexEntry::Index(_)) => {
                 bail!("no such ID");
             }
@@ -207,12 +348,87 @@ where
     fn make_indexed_key(key: Cow<[u8]>, index: u32) -> Cow<[u8]>;
     fn value(&self) -> Vec<u8>;
     fn set_index(&mut self, index: u32);
+
+    /// Checks if the entry can be stored in its table.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error describing why the entry is invalid.
+    fn validate(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Adds the references to the entry to other tables in `txn`, which
+    /// inserts the entry.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails.
+    fn add_references(
+        &self,
+        _db: &rocksdb::OptimisticTransactionDB,
+        _txn: &rocksdb::Transaction<rocksdb::OptimisticTransactionDB>,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    /// Updates the references to the entry, previously stored under
+    /// `old_key`, in other tables in `txn`, which updates the entry to `self`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails.
+    fn update_references(
+        &self,
+        _db: &rocksdb::OptimisticTransactionDB,
+        _txn: &rocksdb::Transaction<rocksdb::OptimisticTransactionDB>,
+        _old_key: &[u8],
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    /// Removes the references to the entry with `id`, stored under `key`,
+    /// from other tables in `txn`, which removes the entry.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails.
+    fn remove_references(
+        _db: &rocksdb::OptimisticTransactionDB,
+        _txn: &rocksdb::Transaction<rocksdb::OptimisticTransactionDB>,
+        _id: u32,
+        _key: &[u8],
+    ) -> Result<()> {
+        Ok(())
+    }
 }
 
 pub trait Indexed {
     fn db(&self) -> &rocksdb::OptimisticTransactionDB;
     fn cf(&self) -> &rocksdb::ColumnFamily;
 
+    /// Returns the channel to report changes and operations through, if any.
+    fn notifier(&self) -> Option<&Channel> {
+        None
+    }
+
+    /// Replaces the key `old` of an entry with `new` in `txn`, in the indices
+    /// kept alongside the map, if any. `None` means the entry is added or
+    /// removed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `new` conflicts with the key of another entry, or
+    /// the database operation fails.
+    fn replace_key(
+        &self,
+        _txn: &rocksdb::Transaction<rocksdb::OptimisticTransactionDB>,
+        _old: Option<&[u8]>,
+        _new: Option<&[u8]>,
+    ) -> Result<()> {
+        Ok(())
+    }
+
     /// Returns the index.
     ///
     /// # Errors
@@ -225,6 +441,15 @@ pub trait Indexed {
         KeyIndex::from_bytes(value).context("invalid index in database")
     }
 
+    /// Returns how the IDs of the map are used.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the index is not found or the database operation fails.
+    fn id_usage(&self) -> Result<IdUsage> {
+        Ok(self.index()?.usage())
+    }
+
     /// Returns the index in a transaction.
     ///
     /// # Errors
@@ -249,6 +474,7 @@ pub trait Indexed {
     ///
     /// Never fails.
     fn inner_iterator(&self, mode: IteratorMode) -> Result<IndexedMapIterator> {
+        record(self.notifier(), Operation::Scan);
         let iter = self.db().iterator_cf(self.cf(), mode);
 
         Ok(IndexedMapIterator { inner: iter })
@@ -279,6 +505,7 @@ pub trait Indexed {
             if key.is_empty() {
                 bail!("corrupt index");
             }
+            self.replace_key(&txn, Some(&key), None)?;
             txn.put_cf(
                 self.cf(),
                 [],
@@ -298,6 +525,7 @@ pub trait Indexed {
                 }
             }
         }
+        notify(self.notifier(), ChangeKind::Remove, &key);
         Ok(key)
     }
 
@@ -344,6 +572,7 @@ pub trait Indexed {
             return Ok(None);
         };
         let key = T::make_indexed_key(Cow::Borrowed(key), id);
+        record(self.notifier(), Operation::Read);
         self.db()
             .get_cf(self.cf(), &key)
             .context("cannot read entry")?
@@ -351,6 +580,66 @@ pub trait Indexed {
             .transpose()
     }
 
+    /// Gets an entry corresponding to the given index in `txn`, so that the
+    /// transaction fails if the entry changes before it is committed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the index is invalid or cannot be read.
+    fn get_by_id_in_transaction<T: Indexable + FromKeyValue>(
+        &self,
+        txn: &rocksdb::Transaction<rocksdb::OptimisticTransactionDB>,
+        id: u32,
+    ) -> Result<Option<T>> {
+        let index = self.index_in_transaction(txn)?;
+        let Some(key) = index.get(id).context("invalid ID")? else {
+            return Ok(None);
+        };
+        let key = T::make_indexed_key(Cow::Borrowed(key), id);
+        record(self.notifier(), Operation::Read);
+        txn.get_for_update_cf(self.cf(), &key, EXCLUSIVE)
+            .context("cannot read entry")?
+            .map(|value| T::from_key_value(&key, &value))
+            .transpose()
+    }
+
+    /// Gets the entries corresponding to the given indices, in the same order.
+    /// An entry is `None` if its index has no key.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any index is invalid or cannot be read.
+    fn get_many_by_id<T: Indexable + FromKeyValue>(&self, ids: &[u32]) -> Result<Vec<Option<T>>> {
+        let index = self.index()?;
+        let mut keys = Vec::with_capacity(ids.len());
+        for &id in ids {
+            record(self.notifier(), Operation::Read);
+            keys.push(
+                index
+                    .get(id)
+                    .context("invalid ID")?
+                    .map(|key| T::make_indexed_key(Cow::Borrowed(key), id)),
+            );
+        }
+        let mut values = self
+            .db()
+            .multi_get_cf(keys.iter().flatten().map(|key| (self.cf(), key)))
+            .into_iter();
+        keys.iter()
+            .map(|key| {
+                let Some(key) = key else {
+                    return Ok(None);
+                };
+                values
+                    .next()
+                    .expect("one value per key")
+                    .context("cannot read entry")?
+                    .map(|value| T::from_key_value(key, &value))
+                    .transpose()
+            })
+            .collect()
+    }
+
     /// Inserts a new key-value pair.
     ///
     /// # Errors
@@ -366,6 +655,8 @@ pub trait Indexed {
             let mut index = self.index_in_transaction(&txn)?;
             i = index.insert(&entry.key()).context("cannot insert key")?;
             entry.set_index(i);
+            self.replace_key(&txn, None, Some(&entry.key()))?;
+            entry.add_references(self.db(), &txn)?;
             if txn
                 .get_for_update_cf(self.cf(), entry.indexed_key(), super::EXCLUSIVE)
                 .context("cannot read from database")?
@@ -392,9 +683,57 @@ pub trait Indexed {
                 }
             }
         }
+        notify(self.notifier(), ChangeKind::Insert, &entry.indexed_key());
         Ok(i)
     }
 
+    /// Inserts a new key-value pair with the ID of `entry`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the ID or the key already exists.
+    fn insert_at<T: Indexable>(&self, entry: &T) -> Result<()> {
+        if entry.key().is_empty() {
+            bail!("key shouldn't be empty");
+        }
+        loop {
+            let txn = self.db().transaction();
+            let mut index = self.index_in_transaction(&txn)?;
+            index
+                .insert_at(entry.index(), &entry.key())
+                .context("cannot insert key")?;
+            self.replace_key(&txn, None, Some(&entry.key()))?;
+            entry.add_references(self.db(), &txn)?;
+            if txn
+                .get_for_update_cf(self.cf(), entry.indexed_key(), super::EXCLUSIVE)
+                .context("cannot read from database")?
+                .is_some()
+            {
+                bail!("key already exists");
+            }
+            txn.put_cf(
+                self.cf(),
+                [],
+                bincode::DefaultOptions::new()
+                    .serialize(&index)
+                    .expect("serializable"),
+            )
+            .context("failed to update database index")?;
+            txn.put_cf(self.cf(), entry.indexed_key(), entry.value())
+                .context("failed to write new entry")?;
+            match txn.commit() {
+                Ok(()) => break,
+                Err(e) => {
+                    if !e.as_ref().starts_with("Resource busy:") {
+                        return Err(e).context("failed to store new entry");
+                    }
+                }
+            }
+        }
+        notify(self.notifier(), ChangeKind::Insert, &entry.indexed_key());
+        Ok(())
+    }
+
     /// Removes a key-value pair with the given ID.
     ///
     /// # Errors
@@ -402,6 +741,7 @@ pub trait Indexed {
     /// Returns an error if the database operation fails.
     fn remove<T: Indexable>(&self, id: u32) -> Result<Vec<u8>> {
         let mut key;
+        let mut indexed_key;
         loop {
             let txn = self.db().transaction();
             let mut index = self
@@ -411,7 +751,8 @@ pub trait Indexed {
             if key.is_empty() {
                 bail!("corrupt index");
             }
-            let indexed_key = T::make_indexed_key(Cow::Borrowed(&key), id);
+            self.replace_key(&txn, Some(&key), None)?;
+            indexed_key = T::make_indexed_key(Cow::Borrowed(&key), id).into_owned();
             txn.put_cf(
                 self.cf(),
                 [],
@@ -420,8 +761,9 @@ pub trait Indexed {
                     .context("failed to serialize index")?,
             )
             .context("failed to update database index")?;
-            txn.delete_cf(self.cf(), indexed_key)
+            txn.delete_cf(self.cf(), &indexed_key)
                 .context("failed to remove entry")?;
+            T::remove_references(self.db(), &txn, id, &indexed_key)?;
             match txn.commit() {
                 Ok(()) => break,
                 Err(e) => {
@@ -431,6 +773,7 @@ pub trait Indexed {
                 }
             }
         }
+        notify(self.notifier(), ChangeKind::Remove, &indexed_key);
         Ok(key)
     }
 
@@ -463,6 +806,7 @@ pub trait Indexed {
                 }
             }
         }
+        notify(self.notifier(), ChangeKind::Update, &entry.indexed_key());
         Ok(())
     }
 
@@ -478,64 +822,56 @@ pub trait Indexed {
         V: IndexedMapUpdate,
         V::Entry: Indexable + From<O::Entry>,
     {
+        self.update_many(&[(id, old, new)])
+    }
+
+    /// Updates old key-value pairs to new ones in a single transaction.
+    ///
+    /// Either all the updates are applied, or none of them is.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any `id` is invalid, any entry has changed, or the
+    /// database operation fails.
+    fn update_many<O, V>(&self, updates: &[(u32, &O, &V)]) -> Result<()>
+    where
+        O: IndexedMapUpdate,
+        O::Entry: Indexable + FromKeyValue,
+        V: IndexedMapUpdate,
+        V::Entry: Indexable + From<O::Entry>,
+    {
+        self.update_many_with(updates, |_| Ok(()))
+    }
+
+    /// Updates old key-value pairs to new ones, and then calls `f`, in a
+    /// single transaction.
+    ///
+    /// Either all the updates and the writes by `f` are applied, or none of
+    /// them is.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any `id` is invalid, any entry has changed, `f`
+    /// fails, or the database operation fails.
+    fn update_many_with<O, V, F>(&self, updates: &[(u32, &O, &V)], f: F) -> Result<()>
+    where
+        O: IndexedMapUpdate,
+        O::Entry: Indexable + FromKeyValue,
+        V: IndexedMapUpdate,
+        V::Entry: Indexable + From<O::Entry>,
+        F: Fn(&rocksdb::Transaction<rocksdb::OptimisticTransactionDB>) -> Result<()>,
+    {
+        let mut changes;
         loop {
             let txn = self.db().transaction();
             let mut index = self
                 .index_in_transaction(&txn)
                 .context("cannot read index")?;
-            let cur_key = if let Some(key) = new.key() {
-                if key.is_empty() {
-                    bail!("key shouldn't be empty");
-                }
-                index.update(id, &key).context("cannot update index")?
-            } else {
-                Vec::new()
-            };
-            let key = if new.key().is_some() {
-                V::Entry::make_indexed_key(Cow::Owned(cur_key), id)
-            } else if let Some(key) = index.get(id).context("invalid ID")? {
-                V::Entry::make_indexed_key(Cow::Borrowed(key), id)
-            } else {
-                bail!("no such ID");
-            };
-
-            let entry = if let Some(value) = txn
-                .get_for_update_cf(self.cf(), &key, super::EXCLUSIVE)
-                .context("cannot read entry")?
-            {
-                O::Entry::from_key_value(&key, &value).context("invalid entry in database")?
-            } else {
-                bail!("corrupt index");
-            };
-            if !old.verify(&entry) {
-                bail!("entry changed");
-            }
-            let new_key = if let Some(new_key) = new.key() {
-                let new_key = V::Entry::make_indexed_key(new_key, id);
-
-                if new_key != key {
-                    txn.delete_cf(self.cf(), &key)
-                        .context("failed to delete old entry")?;
-                    if txn
-                        .get_pinned_cf(self.cf(), &new_key)
-                        .context("cannot read from database")?
-                        .is_some()
-                    {
-                        bail!("new key already exists");
-                    }
-                }
-                new_key
-            } else {
-                key
-            };
-
-            let new_entry = new.apply(entry.into());
-            txn.put_cf(
-                self.cf(),
-                new_key,
-                new_entry.context("invalid update")?.value(),
-            )
-            .context("failed to write updated entry")?;
+            changes = Vec::with_capacity(updates.len());
+            for (id, old, new) in updates {
+                changes.push(self.update_in_transaction(&txn, &mut index, *id, *old, *new)?);
+            }
+            f(&txn)?;
             txn.put_cf(
                 self.cf(),
                 [],
@@ -553,8 +889,94 @@ pub trait Indexed {
                 }
             }
         }
+        for (removed_key, updated_key) in changes {
+            if let Some(removed_key) = removed_key {
+                notify(self.notifier(), ChangeKind::Remove, &removed_key);
+            }
+            notify(self.notifier(), ChangeKind::Update, &updated_key);
+        }
         Ok(())
     }
+
+    /// Updates an old key-value pair to a new one in `txn`, and returns the
+    /// removed key, if the key has changed, and the updated key.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the `id` is invalid or the database operation fails.
+    fn update_in_transaction<O, V>(
+        &self,
+        txn: &rocksdb::Transaction<rocksdb::OptimisticTransactionDB>,
+        index: &mut KeyIndex,
+        id: u32,
+        old: &O,
+        new: &V,
+    ) -> Result<(Option<Vec<u8>>, Vec<u8>)>
+    where
+        O: IndexedMapUpdate,
+        O::Entry: Indexable + FromKeyValue,
+        V: IndexedMapUpdate,
+        V::Entry: Indexable + From<O::Entry>,
+    {
+        let cur_key = if let Some(key) = new.key() {
+            if key.is_empty() {
+                bail!("key shouldn't be empty");
+            }
+            let cur_key = index.update(id, &key).context("cannot update index")?;
+            if cur_key != key.as_ref() {
+                self.replace_key(txn, Some(&cur_key), Some(&key))?;
+            }
+            cur_key
+        } else {
+            Vec::new()
+        };
+        let key = if new.key().is_some() {
+            V::Entry::make_indexed_key(Cow::Owned(cur_key), id)
+        } else if let Some(key) = index.get(id).context("invalid ID")? {
+            V::Entry::make_indexed_key(Cow::Borrowed(key), id)
+        } else {
+            bail!("no such ID");
+        };
+
+        let entry = if let Some(value) = txn
+            .get_for_update_cf(self.cf(), &key, super::EXCLUSIVE)
+            .context("cannot read entry")?
+        {
+            O::Entry::from_key_value(&key, &value).context("invalid entry in database")?
+        } else {
+            bail!("corrupt index");
+        };
+        if !old.verify(&entry) {
+            bail!("entry changed");
+        }
+        let new_entry = new.apply(entry.into()).context("invalid update")?;
+        new_entry.update_references(self.db(), txn, &key)?;
+        let mut removed_key = None;
+        let new_key = if let Some(new_key) = new.key() {
+            let new_key = V::Entry::make_indexed_key(new_key, id);
+
+            if new_key != key {
+                txn.delete_cf(self.cf(), &key)
+                    .context("failed to delete old entry")?;
+                removed_key = Some(key.into_owned());
+                if txn
+                    .get_pinned_cf(self.cf(), &new_key)
+                    .context("cannot read from database")?
+                    .is_some()
+                {
+                    bail!("new key already exists");
+                }
+            }
+            new_key
+        } else {
+            key
+        };
+
+        let updated_key = new_key.into_owned();
+        txn.put_cf(self.cf(), &updated_key, new_entry.value())
+            .context("failed to write updated entry")?;
+        Ok((removed_key, updated_key))
+    }
 }
 
 #[allow(clippy::module_name_repetitions)]
@@ -629,4 +1051,77 @@ mod tests {
         index.clear_inactive().unwrap();
         assert_eq!(index.count(), 0);
     }
+
+    #[test]
+    fn index_soft_deactivate() {
+        let mut index = super::KeyIndex::default();
+        let id_a = index.insert(b"a").unwrap();
+        let id_b = index.insert(b"b").unwrap();
+
+        assert_eq!(index.soft_deactivate(id_a).unwrap(), b"a");
+        assert!(index.soft_deactivate(id_a).is_err());
+        assert_eq!(index.count(), 1);
+        assert_eq!(index.get(id_a).unwrap(), Some(b"a".as_slice()));
+        assert_eq!(
+            index.deactivated().collect::<Vec<_>>(),
+            [(id_a, b"a".as_slice())]
+        );
+        assert_eq!(index.usage().used, 2);
+
+        assert_eq!(index.reactivate(id_a).unwrap(), b"a");
+        assert!(index.reactivate(id_a).is_err());
+        assert!(index.reactivate(id_b).is_err());
+        assert_eq!(index.count(), 2);
+
+        index.soft_deactivate(id_b).unwrap();
+        assert_eq!(index.remove(id_b).unwrap(), b"b");
+        assert_eq!(index.deactivated().count(), 0);
+        assert_eq!(index.insert(b"c").unwrap(), id_b);
+    }
+
+    #[test]
+    fn index_usage() {
+        let mut index = super::KeyIndex::default();
+        for key in [b"a", b"b", b"c", b"d"] {
+            index.insert(key).unwrap();
+        }
+        index.remove(1).unwrap();
+        index.remove(2).unwrap();
+        index.deactivate(3).unwrap();
+        let usage = index.usage();
+        assert_eq!(
+            usage,
+            super::IdUsage {
+                used: 1,
+                free: 2,
+                reserved: 1,
+                next_new: 4,
+            }
+        );
+
+        // Removed IDs are reused before new ones, the most recent first.
+        assert_eq!(index.insert(b"e").unwrap(), 2);
+        assert_eq!(index.insert(b"f").unwrap(), 1);
+        assert_eq!(index.insert(b"g").unwrap(), 4);
+    }
+
+    #[test]
+    fn index_insert_at() {
+        let mut index = super::KeyIndex::default();
+        index.insert_at(2, b"c").unwrap();
+        assert!(index.insert_at(2, b"x").is_err());
+        assert_eq!(index.get(2).unwrap(), Some(b"c".as_slice()));
+
+        // The slots skipped by `insert_at` are reused first.
+        assert_eq!(index.insert(b"a").unwrap(), 0);
+        index.insert_at(1, b"b").unwrap();
+        assert_eq!(index.insert(b"d").unwrap(), 3);
+
+        index.remove(1).unwrap();
+        index.remove(3).unwrap();
+        index.insert_at(1, b"b").unwrap();
+        assert_eq!(index.insert(b"e").unwrap(), 3);
+        assert_eq!(index.insert(b"f").unwrap(), 4);
+        assert_eq!(index.count(), 5);
+    }
 }
@@ -1,13 +1,25 @@
-use super::Indexed;
+use super::{Indexable, Indexed};
+use crate::{
+    metrics::{record, Operation},
+    notification::{notify, ChangeKind, Channel},
+    tables::replace_lowercase_name,
+};
 use anyhow::{anyhow, bail, Context, Result};
+use bincode::Options;
+use std::{borrow::Cow, collections::HashSet};
 
 /// A map where each key has an associated numerical ID.
 ///
 /// The IDs are stored in the first entry, i.e., under an empty key, as a
 /// serialized `Vec`.
+///
+/// Soft-deactivated entries are kept in the map and marked as such in the
+/// index, which hides them from iteration.
 pub struct IndexedMap<'a> {
     db: &'a rocksdb::OptimisticTransactionDB,
     cf: &'a rocksdb::ColumnFamily,
+    name: &'a str,
+    notifier: Option<&'a Channel>,
 }
 
 impl<'a> Indexed for IndexedMap<'a> {
@@ -18,6 +30,19 @@ impl<'a> Indexed for IndexedMap<'a> {
     fn cf(&self) -> &rocksdb::ColumnFamily {
         self.cf
     }
+
+    fn notifier(&self) -> Option<&Channel> {
+        self.notifier
+    }
+
+    fn replace_key(
+        &self,
+        txn: &rocksdb::Transaction<rocksdb::OptimisticTransactionDB>,
+        old: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<()> {
+        replace_lowercase_name(self.db, txn, self.name, old, new)
+    }
 }
 
 impl<'a> IndexedMap<'a> {
@@ -26,12 +51,29 @@ impl<'a> IndexedMap<'a> {
     /// # Errors
     ///
     /// Returns an error if the column family cannot be found.
-    pub fn new(db: &'a rocksdb::OptimisticTransactionDB, name: &str) -> Result<Self> {
+    pub fn new(db: &'a rocksdb::OptimisticTransactionDB, name: &'a str) -> Result<Self> {
         db.cf_handle(name)
-            .map(|cf| Self { db, cf })
+            .map(|cf| Self {
+                db,
+                cf,
+                name,
+                notifier: None,
+            })
             .ok_or_else(|| anyhow!("database error: cannot find column family \"{}\"", name))
     }
 
+    /// Returns the name of the column family of this map.
+    pub(crate) fn name(&self) -> &str {
+        self.name
+    }
+
+    /// Reports changes and operations made through this map to `notifier`.
+    #[must_use]
+    pub(crate) fn with_notifier(mut self, notifier: Option<&'a Channel>) -> Self {
+        self.notifier = notifier;
+        self
+    }
+
     /// Gets a value corresponding to the given key.
     ///
     /// # Errors
@@ -41,6 +83,86 @@ impl<'a> IndexedMap<'a> {
         if key.is_empty() {
             bail!("key shouldn't be empty");
         }
+        record(self.notifier, Operation::Read);
         self.db.get_cf(self.cf, key).context("cannot read entry")
     }
+
+    /// Returns the IDs of the soft-deactivated entries, in ascending order.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the index cannot be read.
+    pub(crate) fn deactivated_ids(&self) -> Result<Vec<u32>> {
+        Ok(self.index()?.deactivated().map(|(id, _)| id).collect())
+    }
+
+    /// Returns the keys of the soft-deactivated entries.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the index cannot be read.
+    pub(crate) fn deactivated_keys<T: Indexable>(&self) -> Result<HashSet<Vec<u8>>> {
+        Ok(self
+            .index()?
+            .deactivated()
+            .map(|(id, key)| T::make_indexed_key(Cow::Borrowed(key), id).into_owned())
+            .collect())
+    }
+
+    /// Marks the entry with `id` as soft-deactivated, keeping it in the map,
+    /// and returns its key.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `id` is invalid, the entry is already deactivated,
+    /// or the database operation fails.
+    pub(crate) fn soft_deactivate<T: Indexable>(&self, id: u32) -> Result<Vec<u8>> {
+        let key = self.set_deactivated(id, true)?;
+        let indexed_key = T::make_indexed_key(Cow::Borrowed(&key), id);
+        notify(self.notifier, ChangeKind::Remove, &indexed_key);
+        Ok(key)
+    }
+
+    /// Clears the soft-deactivated mark of the entry with `id`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the entry is not deactivated or the database
+    /// operation fails.
+    pub(crate) fn reactivate<T: Indexable>(&self, id: u32) -> Result<()> {
+        let key = self.set_deactivated(id, false)?;
+        let indexed_key = T::make_indexed_key(Cow::Borrowed(&key), id);
+        notify(self.notifier, ChangeKind::Insert, &indexed_key);
+        Ok(())
+    }
+
+    fn set_deactivated(&self, id: u32, deactivated: bool) -> Result<Vec<u8>> {
+        loop {
+            let txn = self.db.transaction();
+            let mut index = self
+                .index_in_transaction(&txn)
+                .context("cannot read index")?;
+            let key = if deactivated {
+                index.soft_deactivate(id)?
+            } else {
+                index.reactivate(id)?
+            };
+            txn.put_cf(
+                self.cf,
+                [],
+                bincode::DefaultOptions::new()
+                    .serialize(&index)
+                    .context("failed to serialize index")?,
+            )
+            .context("failed to update database index")?;
+            match txn.commit() {
+                Ok(()) => break Ok(key),
+                Err(e) => {
+                    if !e.as_ref().starts_with("Resource busy:") {
+                        return Err(e).context("failed to update database index");
+                    }
+                }
+            }
+        }
+    }
 }
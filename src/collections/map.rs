@@ -1,16 +1,32 @@
 use super::IterableMap;
-use crate::EXCLUSIVE;
+use crate::{
+    metrics::{record, Operation},
+    notification::{notify, ChangeKind, Channel},
+    EXCLUSIVE,
+};
 use anyhow::{anyhow, bail, Context, Result};
 use rocksdb::{Direction, IteratorMode};
 
 pub struct Map<'a> {
     pub(crate) db: &'a rocksdb::OptimisticTransactionDB,
     pub(crate) cf: &'a rocksdb::ColumnFamily,
+    pub(crate) notifier: Option<&'a Channel>,
 }
 
 impl<'a> Map<'a> {
     pub(crate) fn open(db: &'a rocksdb::OptimisticTransactionDB, name: &str) -> Option<Self> {
-        db.cf_handle(name).map(|cf| Self { db, cf })
+        db.cf_handle(name).map(|cf| Self {
+            db,
+            cf,
+            notifier: None,
+        })
+    }
+
+    /// Reports changes and operations made through this map to `notifier`.
+    #[must_use]
+    pub(crate) fn with_notifier(mut self, notifier: Option<&'a Channel>) -> Self {
+        self.notifier = notifier;
+        self
     }
 
     /// Deletes a key-value pair with the given key.
@@ -21,7 +37,29 @@ impl<'a> Map<'a> {
     pub fn delete(&self, key: &[u8]) -> Result<(), anyhow::Error> {
         self.db
             .delete_cf(self.cf, key)
-            .map_err(|e| anyhow!("database error: {}", e))
+            .map_err(|e| anyhow!("database error: {}", e))?;
+        notify(self.notifier, ChangeKind::Remove, key);
+        Ok(())
+    }
+
+    /// Deletes the key-value pairs with the given keys in a single write, so
+    /// that either all or none of them are deleted.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails.
+    pub(crate) fn delete_many<K: AsRef<[u8]>>(&self, keys: &[K]) -> Result<()> {
+        let mut batch = rocksdb::WriteBatchWithTransaction::<true>::default();
+        for key in keys {
+            batch.delete_cf(self.cf, key);
+        }
+        self.db
+            .write(batch)
+            .map_err(|e| anyhow!("database error: {}", e))?;
+        for key in keys {
+            notify(self.notifier, ChangeKind::Remove, key.as_ref());
+        }
+        Ok(())
     }
 
     /// Gets a value corresponding to the given key.
@@ -30,6 +68,7 @@ impl<'a> Map<'a> {
     ///
     /// Returns an error if the key does not exist or the database operation fails.
     pub fn get(&self, key: &[u8]) -> Result<Option<impl AsRef<[u8]>>> {
+        record(self.notifier, Operation::Read);
         self.db
             .get_cf(self.cf, key)
             .map_err(|e| anyhow!("database error: {}", e))
@@ -43,7 +82,9 @@ impl<'a> Map<'a> {
     pub fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
         self.db
             .put_cf(self.cf, key, value)
-            .map_err(|e| anyhow!("database error: {}", e))
+            .map_err(|e| anyhow!("database error: {}", e))?;
+        notify(self.notifier, ChangeKind::Update, key);
+        Ok(())
     }
 
     /// Inserts a new key-value pair.
@@ -64,7 +105,10 @@ impl<'a> Map<'a> {
             .context("failed to write new entry")?;
 
         match txn.commit() {
-            Ok(()) => Ok(()),
+            Ok(()) => {
+                notify(self.notifier, ChangeKind::Insert, key);
+                Ok(())
+            }
             Err(e) => {
                 if e.as_ref().starts_with("Resource busy:") {
                     Err(anyhow!("already exists"))
@@ -81,12 +125,15 @@ impl<'a> Map<'a> {
     ///
     /// Returns an error if the database operation fails.
     pub fn replace_all(&self, new: &[(&[u8], &[u8])]) -> Result<()> {
+        let mut old_keys = Vec::new();
         loop {
             let txn = self.db.transaction();
 
+            old_keys.clear();
             for (old_key, _) in self.inner_iterator(IteratorMode::Start) {
-                txn.delete_cf(self.cf, old_key)
+                txn.delete_cf(self.cf, &old_key)
                     .context("failed to delete entries")?;
+                old_keys.push(old_key);
             }
 
             for (key, value) in new {
@@ -104,6 +151,12 @@ impl<'a> Map<'a> {
             }
         }
 
+        for old_key in &old_keys {
+            notify(self.notifier, ChangeKind::Remove, old_key);
+        }
+        for (key, _) in new {
+            notify(self.notifier, ChangeKind::Insert, key);
+        }
         Ok(())
     }
 
@@ -150,6 +203,10 @@ impl<'a> Map<'a> {
                 }
             }
         }
+        if old.0 != new.0 {
+            notify(self.notifier, ChangeKind::Remove, old.0);
+        }
+        notify(self.notifier, ChangeKind::Update, new.0);
         Ok(())
     }
 
@@ -159,12 +216,14 @@ impl<'a> Map<'a> {
     }
 
     fn inner_iterator(&self, mode: IteratorMode) -> MapIterator {
+        record(self.notifier, Operation::Scan);
         let iter = self.db.iterator_cf(self.cf, mode);
 
         MapIterator { inner: iter }
     }
 
     pub(crate) fn inner_prefix_iterator(&self, mode: IteratorMode, prefix: &[u8]) -> MapIterator {
+        record(self.notifier, Operation::Scan);
         let mut readopts = rocksdb::ReadOptions::default();
         readopts.set_iterate_range(rocksdb::PrefixRange(prefix));
         let iter = self.db.iterator_cf_opt(self.cf, readopts, mode);
@@ -1,7 +1,14 @@
+mod diff;
+mod histogram;
 mod load;
+mod prune;
+mod rollup;
 mod round;
 mod save;
 
+pub use diff::{StatisticsDiff, TopNDiff};
+pub use histogram::Histogram;
 pub use load::Statistics;
+pub use prune::PrunedStatistics;
 #[allow(clippy::module_name_repetitions)]
 pub use save::statistics::ColumnStatisticsUpdate;
@@ -0,0 +1,194 @@
+use super::load::ColumnDescriptionLoad;
+use crate::{
+    schema::{cluster::dsl as c_d, column_description::dsl as cd_d},
+    Database, Error,
+};
+use chrono::NaiveDateTime;
+use diesel::{ExpressionMethods, QueryDsl};
+use diesel_async::RunQueryDsl;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use structured::{ColumnStatistics, Description, Element};
+
+/// The change of the statistics of a column in a cluster between two
+/// batches.
+#[derive(Serialize)]
+pub struct StatisticsDiff {
+    pub cluster_id: i32,
+    pub column_index: i32,
+    /// The description in the earlier batch, if any.
+    pub before: Option<Description>,
+    /// The description in the later batch, if any.
+    pub after: Option<Description>,
+    /// The top-N values in either batch.
+    pub top_n: Vec<TopNDiff>,
+}
+
+/// The counts of a top-N value in two batches. A count is zero if the value
+/// is not in the top-N of the batch.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct TopNDiff {
+    pub value: Element,
+    pub before: usize,
+    pub after: usize,
+}
+
+impl TopNDiff {
+    /// Returns the change of the count.
+    #[must_use]
+    pub fn delta(&self) -> i64 {
+        i64::try_from(self.after).unwrap_or(i64::MAX)
+            - i64::try_from(self.before).unwrap_or(i64::MAX)
+    }
+}
+
+impl Database {
+    /// Returns the changes of the statistics of the column at `column_index`
+    /// between the batches at `before` and `after`, for each cluster of the
+    /// model with `model_id`. Clusters with no statistics for the column in
+    /// either batch are left out.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a database operation fails.
+    pub async fn diff_column_statistics(
+        &self,
+        model_id: i32,
+        column_index: i32,
+        before: NaiveDateTime,
+        after: NaiveDateTime,
+    ) -> Result<Vec<StatisticsDiff>, Error> {
+        let rows: Vec<(i32, i32, i32)> = {
+            let mut conn = self.pool.get_diesel_conn().await?;
+            let clusters = c_d::cluster
+                .select(c_d::id)
+                .filter(c_d::model_id.eq(model_id));
+            cd_d::column_description
+                .select((cd_d::id, cd_d::type_id, cd_d::cluster_id))
+                .filter(cd_d::cluster_id.eq_any(clusters))
+                .filter(cd_d::column_index.eq(column_index))
+                .filter(cd_d::batch_ts.eq_any(vec![before, after]))
+                .load(&mut conn)
+                .await?
+        };
+        let mut cluster_of = HashMap::with_capacity(rows.len());
+        let column_info: Vec<_> = rows
+            .into_iter()
+            .map(|(id, type_id, cluster_id)| {
+                cluster_of.insert(id, cluster_id);
+                ColumnDescriptionLoad { id, type_id }
+            })
+            .collect();
+
+        let mut by_cluster: BTreeMap<i32, (Option<ColumnStatistics>, Option<ColumnStatistics>)> =
+            BTreeMap::new();
+        for stats in self.try_load_statistics(&column_info).await? {
+            let Some(&cluster_id) = cluster_of.get(&stats.description_id) else {
+                continue;
+            };
+            let entry = by_cluster.entry(cluster_id).or_default();
+            if stats.batch_ts == before {
+                entry.0 = Some(stats.statistics);
+            } else if stats.batch_ts == after {
+                entry.1 = Some(stats.statistics);
+            }
+        }
+        Ok(by_cluster
+            .into_iter()
+            .map(|(cluster_id, (old, new))| diff(cluster_id, column_index, old, new))
+            .collect())
+    }
+}
+
+fn diff(
+    cluster_id: i32,
+    column_index: i32,
+    before: Option<ColumnStatistics>,
+    after: Option<ColumnStatistics>,
+) -> StatisticsDiff {
+    let mut top_n: Vec<TopNDiff> = before
+        .iter()
+        .flat_map(|s| s.n_largest_count.top_n())
+        .map(|ec| TopNDiff {
+            value: ec.value.clone(),
+            before: ec.count,
+            after: 0,
+        })
+        .collect();
+    for ec in after.iter().flat_map(|s| s.n_largest_count.top_n()) {
+        if let Some(d) = top_n.iter_mut().find(|d| d.value == ec.value) {
+            d.after = ec.count;
+        } else {
+            top_n.push(TopNDiff {
+                value: ec.value.clone(),
+                before: 0,
+                after: ec.count,
+            });
+        }
+    }
+
+    StatisticsDiff {
+        cluster_id,
+        column_index,
+        before: before.map(|s| s.description),
+        after: after.map(|s| s.description),
+        top_n,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use structured::{ElementCount, NLargestCount};
+
+    fn statistics(top_n: &[(&str, usize)]) -> ColumnStatistics {
+        let top_n = top_n
+            .iter()
+            .map(|(value, count)| ElementCount {
+                value: Element::Text((*value).to_string()),
+                count: *count,
+            })
+            .collect();
+        ColumnStatistics {
+            description: Description::new(10, None, None, None, None),
+            n_largest_count: NLargestCount::new(3, top_n, None),
+        }
+    }
+
+    #[test]
+    fn diff_top_n() {
+        let before = statistics(&[("a", 5), ("b", 3)]);
+        let after = statistics(&[("b", 7), ("c", 1)]);
+        let diff = diff(1, 2, Some(before), Some(after));
+        assert_eq!(diff.cluster_id, 1);
+        assert_eq!(diff.column_index, 2);
+        assert!(diff.before.is_some());
+        assert!(diff.after.is_some());
+
+        let text = |v: &str| Element::Text(v.to_string());
+        assert_eq!(
+            diff.top_n,
+            vec![
+                TopNDiff {
+                    value: text("a"),
+                    before: 5,
+                    after: 0
+                },
+                TopNDiff {
+                    value: text("b"),
+                    before: 3,
+                    after: 7
+                },
+                TopNDiff {
+                    value: text("c"),
+                    before: 0,
+                    after: 1
+                },
+            ]
+        );
+        assert_eq!(
+            diff.top_n.iter().map(TopNDiff::delta).collect::<Vec<_>>(),
+            vec![-5, 4, 1]
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use crate::{schema::histogram::dsl as h_d, Error};
+use diesel::{ExpressionMethods, QueryDsl};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// The type IDs of the columns that can have histograms, integer and
+/// floating-point numbers.
+pub(super) const HISTOGRAM_TYPE_IDS: [i32; 2] = [1, 3];
+
+/// A histogram of a numeric column, with buckets of the same width between
+/// `lower` and `upper`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Histogram {
+    pub lower: f64,
+    pub upper: f64,
+    /// The number of values in each bucket, from the lowest one.
+    pub counts: Vec<u64>,
+}
+
+impl Histogram {
+    /// Returns the width of a bucket, or `None` if there are no buckets.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)] // approximation is ok
+    pub fn bucket_width(&self) -> Option<f64> {
+        if self.counts.is_empty() {
+            None
+        } else {
+            Some((self.upper - self.lower) / self.counts.len() as f64)
+        }
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::histogram)]
+pub(super) struct HistogramInput {
+    description_id: i32,
+    lower: f64,
+    upper: f64,
+    counts: Vec<Option<i64>>,
+}
+
+impl HistogramInput {
+    pub(super) fn new(description_id: i32, histogram: &Histogram) -> Self {
+        Self {
+            description_id,
+            lower: histogram.lower,
+            upper: histogram.upper,
+            counts: histogram
+                .counts
+                .iter()
+                .map(|&c| Some(i64::try_from(c).unwrap_or(i64::MAX)))
+                .collect(),
+        }
+    }
+}
+
+/// Inserts the histograms with as few statements as possible.
+pub(super) async fn insert_histograms(
+    conn: &mut AsyncPgConnection,
+    histograms: &[HistogramInput],
+) -> Result<(), Error> {
+    for chunk in histograms.chunks(super::save::ROWS_PER_STATEMENT) {
+        diesel::insert_into(h_d::histogram)
+            .values(chunk)
+            .execute(conn)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Returns the histograms of the column descriptions with `description_ids`,
+/// keyed by the description IDs.
+pub(super) async fn load_histograms(
+    conn: &mut AsyncPgConnection,
+    description_ids: &[i32],
+) -> Result<HashMap<i32, Histogram>, Error> {
+    let rows = h_d::histogram
+        .select((h_d::description_id, h_d::lower, h_d::upper, h_d::counts))
+        .filter(h_d::description_id.eq_any(description_ids))
+        .load::<(i32, f64, f64, Vec<Option<i64>>)>(conn)
+        .await?;
+    Ok(rows
+        .into_iter()
+        .map(|(id, lower, upper, counts)| {
+            let counts = counts
+                .into_iter()
+                .map(|c| c.and_then(|c| u64::try_from(c).ok()).unwrap_or_default())
+                .collect();
+            (
+                id,
+                Histogram {
+                    lower,
+                    upper,
+                    counts,
+                },
+            )
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Histogram;
+
+    #[test]
+    fn bucket_width() {
+        let histogram = Histogram {
+            lower: 0.0,
+            upper: 10.0,
+            counts: vec![1, 2, 3, 4],
+        };
+        assert_eq!(histogram.bucket_width(), Some(2.5));
+        let empty = Histogram {
+            counts: Vec::new(),
+            ..histogram
+        };
+        assert_eq!(empty.bucket_width(), None);
+    }
+}
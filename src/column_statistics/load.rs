@@ -6,6 +6,7 @@ mod int;
 mod ipaddr;
 mod text;
 
+use super::histogram::{load_histograms, Histogram, HISTOGRAM_TYPE_IDS};
 use crate::{
     schema::{self, column_description::dsl as cd_d},
     Database, Error,
@@ -13,23 +14,27 @@ use crate::{
 use chrono::NaiveDateTime;
 use diesel::{ExpressionMethods, QueryDsl};
 use diesel_async::RunQueryDsl;
-use futures::future::join_all;
+use futures::future::{join_all, try_join_all};
 use serde::Serialize;
 use std::{cmp::Reverse, collections::HashMap};
 use structured::{ColumnStatistics, Description, ElementCount, NLargestCount};
 use tracing::error;
 
 #[derive(Debug, Queryable)]
-struct ColumnDescriptionLoad {
-    id: i32,
-    type_id: i32,
+pub(super) struct ColumnDescriptionLoad {
+    pub(super) id: i32,
+    pub(super) type_id: i32,
 }
 
 #[derive(Serialize)]
 pub struct Statistics {
-    batch_ts: NaiveDateTime,
-    column_index: i32,
-    statistics: ColumnStatistics,
+    #[serde(skip)]
+    pub(super) description_id: i32,
+    pub(super) batch_ts: NaiveDateTime,
+    pub(super) column_index: i32,
+    pub(super) statistics: ColumnStatistics,
+    /// The histogram of an integer or floating-point column, if stored.
+    histogram: Option<Histogram>,
 }
 
 trait ColumnIndex {
@@ -79,28 +84,21 @@ impl Database {
         }
 
         let column_info = query.load::<ColumnDescriptionLoad>(&mut conn).await?;
+        Ok(self.load_statistics(&column_info).await)
+    }
 
-        let mut columns: HashMap<i32, Vec<i32>> = HashMap::new();
-        for c in &column_info {
-            columns.entry(c.type_id).or_default().push(c.id);
-        }
-
+    /// Loads the statistics of the column descriptions in `column_info`,
+    /// sorted by their batch timestamps and column indices. The statistics
+    /// of a column type that fail to load are logged and left out.
+    pub(super) async fn load_statistics(
+        &self,
+        column_info: &[ColumnDescriptionLoad],
+    ) -> Vec<Statistics> {
+        let columns = columns_by_type(column_info);
         let mut results = join_all(columns.iter().map(|(type_id, description_ids)| async move {
-            let conn = self.pool.get_diesel_conn().await?;
-            let statistics = match type_id {
-                1 => int::get_int_statistics(conn, description_ids).await,
-                2 => r#enum::get_enum_statistics(conn, description_ids).await,
-                3 => float::get_float_statistics(conn, description_ids).await,
-                4 => text::get_text_statistics(conn, description_ids).await,
-                5 => ipaddr::get_ipaddr_statistics(conn, description_ids).await,
-                6 => datetime::get_datetime_statistics(conn, description_ids).await,
-                7 => binary::get_binary_statistics(conn, description_ids).await,
-                _ => {
-                    return Err(Error::InvalidInput(format!(
-                        "Unexpected column type id: {type_id}"
-                    )));
-                }
-            };
+            let statistics = self
+                .load_statistics_of_type(*type_id, description_ids)
+                .await;
             if let Err(e) = &statistics {
                 error!("An error occurred while loading column statistics: {:?}", e);
             }
@@ -112,9 +110,124 @@ impl Database {
         .flatten()
         .collect::<Vec<_>>();
 
+        if let Err(e) = self.load_histograms_into(column_info, &mut results).await {
+            error!("An error occurred while loading histograms: {:?}", e);
+        }
+
+        results.sort_by_key(|v| (v.batch_ts, v.column_index));
+        results
+    }
+
+    /// Loads the statistics of the column descriptions in `column_info`,
+    /// sorted by their batch timestamps and column indices.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any of the statistics fails to load.
+    pub(super) async fn try_load_statistics(
+        &self,
+        column_info: &[ColumnDescriptionLoad],
+    ) -> Result<Vec<Statistics>, Error> {
+        let columns = columns_by_type(column_info);
+        let mut results = try_join_all(columns.iter().map(|(type_id, description_ids)| {
+            self.load_statistics_of_type(*type_id, description_ids)
+        }))
+        .await?
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+
+        self.load_histograms_into(column_info, &mut results).await?;
+
         results.sort_by_key(|v| (v.batch_ts, v.column_index));
         Ok(results)
     }
+
+    async fn load_statistics_of_type(
+        &self,
+        type_id: i32,
+        description_ids: &[i32],
+    ) -> Result<Vec<Statistics>, Error> {
+        let conn = self.pool.get_diesel_conn().await?;
+        match type_id {
+            1 => int::get_int_statistics(conn, description_ids).await,
+            2 => r#enum::get_enum_statistics(conn, description_ids).await,
+            3 => float::get_float_statistics(conn, description_ids).await,
+            4 => text::get_text_statistics(conn, description_ids).await,
+            5 => ipaddr::get_ipaddr_statistics(conn, description_ids).await,
+            6 => datetime::get_datetime_statistics(conn, description_ids).await,
+            7 => binary::get_binary_statistics(conn, description_ids).await,
+            _ => Err(Error::InvalidInput(format!(
+                "Unexpected column type id: {type_id}"
+            ))),
+        }
+    }
+
+    /// Sets the histograms of the numeric columns in `results`.
+    async fn load_histograms_into(
+        &self,
+        column_info: &[ColumnDescriptionLoad],
+        results: &mut [Statistics],
+    ) -> Result<(), Error> {
+        let histogram_ids: Vec<_> = column_info
+            .iter()
+            .filter(|c| HISTOGRAM_TYPE_IDS.contains(&c.type_id))
+            .map(|c| c.id)
+            .collect();
+        if histogram_ids.is_empty() {
+            return Ok(());
+        }
+        let mut conn = self.pool.get_diesel_conn().await?;
+        let mut histograms = load_histograms(&mut conn, &histogram_ids).await?;
+        for s in results {
+            s.histogram = histograms.remove(&s.description_id);
+        }
+        Ok(())
+    }
+
+    /// Returns at most `limit` top-N values of the column description with
+    /// `description_id`, skipping the first `offset` ones, in descending
+    /// order of their counts.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the column description does not exist, `offset`
+    /// or `limit` is too large, or a database operation fails.
+    pub async fn get_top_n_page(
+        &self,
+        description_id: i32,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<ElementCount>, Error> {
+        let offset = i64::try_from(offset).map_err(|_| Error::InvalidInput("offset".into()))?;
+        let limit = i64::try_from(limit).map_err(|_| Error::InvalidInput("limit".into()))?;
+        let mut conn = self.pool.get_diesel_conn().await?;
+        let type_id: i32 = cd_d::column_description
+            .select(cd_d::type_id)
+            .filter(cd_d::id.eq(description_id))
+            .first(&mut conn)
+            .await?;
+        match type_id {
+            1 => int::get_int_top_n(conn, description_id, offset, limit).await,
+            2 => r#enum::get_enum_top_n(conn, description_id, offset, limit).await,
+            3 => float::get_float_top_n(conn, description_id, offset, limit).await,
+            4 => text::get_text_top_n(conn, description_id, offset, limit).await,
+            5 => ipaddr::get_ipaddr_top_n(conn, description_id, offset, limit).await,
+            6 => datetime::get_datetime_top_n(conn, description_id, offset, limit).await,
+            7 => binary::get_binary_top_n(conn, description_id, offset, limit).await,
+            _ => Err(Error::InvalidInput(format!(
+                "Unexpected column type id: {type_id}"
+            ))),
+        }
+    }
+}
+
+fn columns_by_type(column_info: &[ColumnDescriptionLoad]) -> HashMap<i32, Vec<i32>> {
+    let mut columns: HashMap<i32, Vec<i32>> = HashMap::new();
+    for c in column_info {
+        columns.entry(c.type_id).or_default().push(c.id);
+    }
+    columns
 }
 
 fn build_column_statistics<T, U>(column_descriptions: Vec<T>, top_n: Vec<U>) -> Vec<Statistics>
@@ -137,9 +250,11 @@ where
                     n_largest_count,
                 };
                 Some(Statistics {
+                    description_id: id,
                     batch_ts,
                     column_index,
                     statistics: cs,
+                    histogram: None,
                 })
             } else {
                 None
@@ -118,6 +118,27 @@ pub(super) async fn get_int_statistics(
     Ok(super::build_column_statistics(column_descriptions, top_n))
 }
 
+pub(super) async fn get_int_top_n(
+    mut conn: AsyncPgConnection,
+    description_id: i32,
+    offset: i64,
+    limit: i64,
+) -> Result<Vec<ElementCount>, Error> {
+    let top_n = top_n::top_n_int
+        .select((top_n::description_id, top_n::value, top_n::count))
+        .filter(top_n::description_id.eq(description_id))
+        .order_by((top_n::count.desc(), top_n::id.asc()))
+        .offset(offset)
+        .limit(limit)
+        .load::<TopNInt>(&mut conn)
+        .await?;
+
+    Ok(top_n
+        .into_iter()
+        .map(ToElementCount::to_element_count)
+        .collect())
+}
+
 #[cfg(test)]
 mod tests {
     use super::super::top_n_to_element_counts;
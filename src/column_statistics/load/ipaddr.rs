@@ -8,10 +8,7 @@ use super::{
 use chrono::NaiveDateTime;
 use diesel::{ExpressionMethods, JoinOnDsl, QueryDsl};
 use diesel_async::{pg::AsyncPgConnection, RunQueryDsl};
-use std::{
-    net::{IpAddr, Ipv4Addr},
-    str::FromStr,
-};
+use std::net::{IpAddr, Ipv4Addr};
 use structured::{Description, Element, ElementCount, NLargestCount};
 
 #[derive(Debug, Queryable)]
@@ -56,13 +53,10 @@ impl ToDescription for DescriptionIpAddr {
 
 impl ToNLargestCount for DescriptionIpAddr {
     fn to_n_largest_count(self, ec: Vec<ElementCount>) -> NLargestCount {
-        let ipaddr = IpAddr::V4(
-            Ipv4Addr::from_str(&self.mode).unwrap_or_else(|_| Ipv4Addr::new(0, 0, 0, 0)),
-        );
         NLargestCount::new(
             usize::try_from(self.unique_count).unwrap_or_default(),
             ec,
-            Some(Element::IpAddr(ipaddr)),
+            Some(Element::IpAddr(parse_ipaddr(&self.mode))),
         )
     }
 }
@@ -82,16 +76,19 @@ impl DescriptionIndex for TopNIpAddr {
 
 impl ToElementCount for TopNIpAddr {
     fn to_element_count(self) -> ElementCount {
-        let ipaddr = IpAddr::V4(
-            Ipv4Addr::from_str(&self.value).unwrap_or_else(|_| Ipv4Addr::new(0, 0, 0, 0)),
-        );
         ElementCount {
-            value: Element::IpAddr(ipaddr),
+            value: Element::IpAddr(parse_ipaddr(&self.value)),
             count: usize::try_from(self.count).unwrap_or_default(),
         }
     }
 }
 
+/// Parses an IPv4 or IPv6 address stored as text, falling back to the
+/// unspecified IPv4 address if it is invalid.
+fn parse_ipaddr(value: &str) -> IpAddr {
+    value.parse().unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+}
+
 pub(super) async fn get_ipaddr_statistics(
     mut conn: AsyncPgConnection,
     description_ids: &[i32],
@@ -120,3 +117,45 @@ pub(super) async fn get_ipaddr_statistics(
 
     Ok(super::build_column_statistics(column_descriptions, top_n))
 }
+
+pub(super) async fn get_ipaddr_top_n(
+    mut conn: AsyncPgConnection,
+    description_id: i32,
+    offset: i64,
+    limit: i64,
+) -> Result<Vec<ElementCount>, Error> {
+    let top_n = top_n::top_n_ipaddr
+        .select((top_n::description_id, top_n::value, top_n::count))
+        .filter(top_n::description_id.eq(description_id))
+        .order_by((top_n::count.desc(), top_n::id.asc()))
+        .offset(offset)
+        .limit(limit)
+        .load::<TopNIpAddr>(&mut conn)
+        .await?;
+
+    Ok(top_n
+        .into_iter()
+        .map(ToElementCount::to_element_count)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    fn parse_ipaddr() {
+        assert_eq!(
+            super::parse_ipaddr("192.168.0.1"),
+            IpAddr::V4(Ipv4Addr::new(192, 168, 0, 1))
+        );
+        assert_eq!(
+            super::parse_ipaddr("2001:db8::1"),
+            IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1))
+        );
+        assert_eq!(
+            super::parse_ipaddr("not an address"),
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+        );
+    }
+}
@@ -0,0 +1,116 @@
+use crate::{
+    schema::{
+        cluster::dsl as c_d, column_description::dsl as cd_d, description_binary,
+        description_datetime, description_enum, description_float, description_int,
+        description_ipaddr, description_text, histogram, top_n_binary, top_n_datetime, top_n_enum,
+        top_n_float, top_n_int, top_n_ipaddr, top_n_text,
+    },
+    Database, Error,
+};
+use chrono::NaiveDateTime;
+use diesel::{ExpressionMethods, QueryDsl};
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
+
+/// The numbers of rows deleted by `Database::prune_column_statistics`.
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct PrunedStatistics {
+    /// The number of rows deleted from `column_description`.
+    pub column_descriptions: usize,
+    /// The number of rows deleted from the `description_*` tables.
+    pub descriptions: usize,
+    /// The number of rows deleted from the `top_n_*` tables.
+    pub top_n: usize,
+    /// The number of rows deleted from `histogram`.
+    pub histograms: usize,
+}
+
+macro_rules! delete_by_description {
+    ($conn:expr, $ids:expr, $($table:ident),+) => {{
+        let mut deleted = 0;
+        $(
+            deleted += diesel::delete(
+                $table::table.filter($table::description_id.eq_any($ids)),
+            )
+            .execute($conn)
+            .await?;
+        )+
+        deleted
+    }};
+}
+
+impl Database {
+    /// Deletes the column statistics of the model with `model_id` for the
+    /// batches before `cutoff`, and returns the numbers of rows deleted.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a database operation fails.
+    pub async fn prune_column_statistics(
+        &self,
+        model_id: i32,
+        cutoff: NaiveDateTime,
+    ) -> Result<PrunedStatistics, Error> {
+        let mut conn = self.pool.get_diesel_conn().await?;
+        conn.transaction::<_, Error, _>(|conn| {
+            async move { prune(conn, model_id, cutoff).await }.scope_boxed()
+        })
+        .await
+    }
+}
+
+async fn prune(
+    conn: &mut AsyncPgConnection,
+    model_id: i32,
+    cutoff: NaiveDateTime,
+) -> Result<PrunedStatistics, Error> {
+    let cluster_ids: Vec<i32> = c_d::cluster
+        .select(c_d::id)
+        .filter(c_d::model_id.eq(model_id))
+        .load(conn)
+        .await?;
+    let ids: Vec<i32> = cd_d::column_description
+        .select(cd_d::id)
+        .filter(cd_d::cluster_id.eq_any(&cluster_ids))
+        .filter(cd_d::batch_ts.lt(cutoff))
+        .load(conn)
+        .await?;
+    if ids.is_empty() {
+        return Ok(PrunedStatistics::default());
+    }
+
+    let descriptions = delete_by_description!(
+        conn,
+        &ids,
+        description_binary,
+        description_datetime,
+        description_enum,
+        description_float,
+        description_int,
+        description_ipaddr,
+        description_text
+    );
+    let top_n = delete_by_description!(
+        conn,
+        &ids,
+        top_n_binary,
+        top_n_datetime,
+        top_n_enum,
+        top_n_float,
+        top_n_int,
+        top_n_ipaddr,
+        top_n_text
+    );
+    let histograms = delete_by_description!(conn, &ids, histogram);
+    let column_descriptions =
+        diesel::delete(cd_d::column_description.filter(cd_d::id.eq_any(&ids)))
+            .execute(conn)
+            .await?;
+    Ok(PrunedStatistics {
+        column_descriptions,
+        descriptions,
+        top_n,
+        histograms,
+    })
+}
@@ -0,0 +1,214 @@
+use super::load::ColumnDescriptionLoad;
+use crate::{
+    schema::{cluster::dsl as c_d, column_description::dsl as cd_d},
+    Database, Error,
+};
+use chrono::NaiveDateTime;
+use diesel::{ExpressionMethods, QueryDsl};
+use diesel_async::RunQueryDsl;
+use std::cmp::Ordering;
+use structured::{ColumnStatistics, Description, Element, ElementCount, NLargestCount};
+
+impl Database {
+    /// Returns the statistics of the column at `column_index`, merged over
+    /// all the clusters of the model with `model_id` and all the batches in
+    /// `[start, end)`, or `None` if there are no such statistics.
+    ///
+    /// The counts and the top-N counts are summed, and the mean and the
+    /// standard deviation are recomputed for the combined population. The
+    /// number of distinct elements is the largest one among the batches, a
+    /// lower bound of the actual number.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a database operation fails.
+    pub async fn rollup_column_statistics(
+        &self,
+        model_id: i32,
+        column_index: i32,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> Result<Option<ColumnStatistics>, Error> {
+        let column_info = {
+            let mut conn = self.pool.get_diesel_conn().await?;
+            let cluster_ids: Vec<i32> = c_d::cluster
+                .select(c_d::id)
+                .filter(c_d::model_id.eq(model_id))
+                .load(&mut conn)
+                .await?;
+            cd_d::column_description
+                .select((cd_d::id, cd_d::type_id))
+                .filter(cd_d::cluster_id.eq_any(&cluster_ids))
+                .filter(cd_d::column_index.eq(column_index))
+                .filter(cd_d::batch_ts.ge(start))
+                .filter(cd_d::batch_ts.lt(end))
+                .load::<ColumnDescriptionLoad>(&mut conn)
+                .await?
+        };
+        let statistics: Vec<_> = self
+            .load_statistics(&column_info)
+            .await
+            .into_iter()
+            .map(|s| s.statistics)
+            .collect();
+        Ok(rollup(&statistics))
+    }
+}
+
+/// Merges the statistics of a column in several batches.
+///
+/// The top-N values are truncated to the longest top-N list among
+/// `statistics`. The mean and the standard deviation are `None` if any of the
+/// batches lacks them.
+fn rollup(statistics: &[ColumnStatistics]) -> Option<ColumnStatistics> {
+    let first = statistics.first()?;
+
+    let count: usize = statistics.iter().map(|s| s.description.count()).sum();
+    let (mean, s_deviation) = merge_moments(statistics, count);
+    let mut min = first.description.min().cloned();
+    let mut max = first.description.max().cloned();
+    for s in &statistics[1..] {
+        if let Some(m) = s.description.min() {
+            if min
+                .as_ref()
+                .map_or(true, |cur| compare(m, cur) == Ordering::Less)
+            {
+                min = Some(m.clone());
+            }
+        }
+        if let Some(m) = s.description.max() {
+            if max
+                .as_ref()
+                .map_or(true, |cur| compare(m, cur) == Ordering::Greater)
+            {
+                max = Some(m.clone());
+            }
+        }
+    }
+
+    let mut top_n: Vec<ElementCount> = Vec::new();
+    for ec in statistics.iter().flat_map(|s| s.n_largest_count.top_n()) {
+        if let Some(merged) = top_n.iter_mut().find(|m| m.value == ec.value) {
+            merged.count += ec.count;
+        } else {
+            top_n.push(ec.clone());
+        }
+    }
+    top_n.sort_by(|a, b| b.count.cmp(&a.count));
+    let n = statistics
+        .iter()
+        .map(|s| s.n_largest_count.top_n().len())
+        .max()
+        .unwrap_or_default();
+    top_n.truncate(n);
+    let mode = top_n.first().map(|ec| ec.value.clone());
+    let number_of_elements = statistics
+        .iter()
+        .map(|s| s.n_largest_count.number_of_elements())
+        .max()
+        .unwrap_or_default();
+
+    Some(ColumnStatistics {
+        description: Description::new(count, mean, s_deviation, min, max),
+        n_largest_count: NLargestCount::new(number_of_elements, top_n, mode),
+    })
+}
+
+/// Returns the mean and the standard deviation of the union of the batches,
+/// which have `count` elements in total.
+#[allow(clippy::cast_precision_loss)] // approximation is ok
+fn merge_moments(statistics: &[ColumnStatistics], count: usize) -> (Option<f64>, Option<f64>) {
+    if count == 0 {
+        return (None, None);
+    }
+    let mut sum = 0.0;
+    let mut sum_of_squares = Some(0.0);
+    for s in statistics {
+        let n = s.description.count() as f64;
+        let Some(mean) = s.description.mean() else {
+            return (None, None);
+        };
+        sum += n * mean;
+        sum_of_squares = sum_of_squares
+            .zip(s.description.std_deviation())
+            .map(|(acc, sd)| acc + n * (sd * sd + mean * mean));
+    }
+    let count = count as f64;
+    let mean = sum / count;
+    let s_deviation = sum_of_squares.map(|s| (s / count - mean * mean).max(0.0).sqrt());
+    (Some(mean), s_deviation)
+}
+
+/// Compares two elements of the same type. Elements of different types, or
+/// of a type without a natural order, are considered equal.
+fn compare(a: &Element, b: &Element) -> Ordering {
+    match (a, b) {
+        (Element::Int(a), Element::Int(b)) => a.cmp(b),
+        (Element::Float(a), Element::Float(b)) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+        (Element::DateTime(a), Element::DateTime(b)) => a.cmp(b),
+        _ => Ordering::Equal,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn statistics(
+        count: usize,
+        mean: f64,
+        s_deviation: f64,
+        min: i64,
+        max: i64,
+        top_n: &[(i64, usize)],
+    ) -> ColumnStatistics {
+        let top_n: Vec<_> = top_n
+            .iter()
+            .map(|&(value, count)| ElementCount {
+                value: Element::Int(value),
+                count,
+            })
+            .collect();
+        let mode = top_n.first().map(|ec| ec.value.clone());
+        ColumnStatistics {
+            description: Description::new(
+                count,
+                Some(mean),
+                Some(s_deviation),
+                Some(Element::Int(min)),
+                Some(Element::Int(max)),
+            ),
+            n_largest_count: NLargestCount::new(top_n.len(), top_n, mode),
+        }
+    }
+
+    #[test]
+    fn rollup_batches() {
+        assert!(rollup(&[]).is_none());
+
+        // The descriptions are of {1, 1, 3, 3} and {5, 5, 7, 7}. The top-N
+        // lists don't follow them; they put 3 in both batches so that it
+        // becomes the most frequent value once merged.
+        let a = statistics(4, 2.0, 1.0, 1, 3, &[(1, 2), (3, 2)]);
+        let b = statistics(4, 6.0, 1.0, 5, 7, &[(5, 2), (3, 1), (7, 1)]);
+        let merged = rollup(&[a, b]).unwrap();
+
+        let description = &merged.description;
+        assert_eq!(description.count(), 8);
+        assert_eq!(description.mean(), Some(4.0));
+        assert!((description.std_deviation().unwrap() - 5.0_f64.sqrt()).abs() < 1e-9);
+        assert_eq!(description.min(), Some(&Element::Int(1)));
+        assert_eq!(description.max(), Some(&Element::Int(7)));
+
+        let top_n = merged.n_largest_count.top_n();
+        assert_eq!(top_n.len(), 3);
+        assert_eq!(
+            top_n[0],
+            ElementCount {
+                value: Element::Int(3),
+                count: 3
+            }
+        );
+        assert_eq!(merged.n_largest_count.number_of_elements(), 3);
+    }
+}
@@ -1,9 +1,9 @@
 use crate::{
     schema::{cluster::dsl as c_d, column_description::dsl as cd_d},
-    Database, Error,
+    Cursor, Database, Direction, Error, Page, PageRequest,
 };
 use chrono::NaiveDateTime;
-use diesel::{BoolExpressionMethods, ExpressionMethods, QueryDsl};
+use diesel::{ExpressionMethods, QueryDsl};
 use diesel_async::RunQueryDsl;
 use serde::Deserialize;
 
@@ -37,48 +37,68 @@ impl Database {
             .await?)
     }
 
-    /// Returns the rounds in the given cluster.
+    /// Returns the ID of the model of the given cluster, and a page of the
+    /// rounds in the cluster in chronological order.
     ///
     /// # Errors
     ///
-    /// Returns an error if the database query fails.
+    /// Returns an error if the cursor of `request` is invalid or the database
+    /// query fails.
     pub async fn load_rounds_by_cluster(
         &self,
         cluster_id: i32,
-        after: &Option<NaiveDateTime>,
-        before: &Option<NaiveDateTime>,
-        is_first: bool,
-        limit: usize,
-    ) -> Result<(i32, Vec<NaiveDateTime>), Error> {
+        request: &PageRequest,
+    ) -> Result<(i32, Page<NaiveDateTime>), Error> {
+        let cursor = request
+            .cursor
+            .as_ref()
+            .map(Cursor::key::<NaiveDateTime>)
+            .transpose()?;
+        let limit =
+            i64::try_from(request.limit).map_err(|_| Error::InvalidInput("limit".into()))? + 1;
         let mut conn = self.pool.get_diesel_conn().await?;
-        let limit = i64::try_from(limit).map_err(|_| Error::InvalidInput("limit".into()))? + 1;
         let model_id = c_d::cluster
             .select(c_d::model_id)
             .filter(c_d::id.eq(cluster_id))
             .first(&mut conn)
             .await?;
 
-        let mut query = cd_d::column_description
-            .select(cd_d::batch_ts)
-            .distinct()
-            .filter(cd_d::cluster_id.eq(cluster_id))
-            .into_boxed();
-
-        if let Some(after) = after {
-            query = query.filter(cd_d::batch_ts.eq(after).or(cd_d::batch_ts.gt(after)));
-        }
-        if let Some(before) = before {
-            query = query.filter(cd_d::batch_ts.eq(before).or(cd_d::batch_ts.lt(before)));
+        let query = || {
+            cd_d::column_description
+                .select(cd_d::batch_ts)
+                .distinct()
+                .filter(cd_d::cluster_id.eq(cluster_id))
+                .into_boxed()
+        };
+        let mut page = query();
+        let mut beyond = query();
+        if let Some(batch_ts) = cursor {
+            match request.direction {
+                Direction::Forward => {
+                    page = page.filter(cd_d::batch_ts.gt(batch_ts));
+                    beyond = beyond.filter(cd_d::batch_ts.le(batch_ts));
+                }
+                Direction::Reverse => {
+                    page = page.filter(cd_d::batch_ts.lt(batch_ts));
+                    beyond = beyond.filter(cd_d::batch_ts.ge(batch_ts));
+                }
+            }
         }
+        page = match request.direction {
+            Direction::Forward => page.order_by(cd_d::batch_ts.asc()),
+            Direction::Reverse => page.order_by(cd_d::batch_ts.desc()),
+        };
 
-        if is_first {
-            query = query.order_by(cd_d::batch_ts.asc()).limit(limit);
-        } else {
-            query = query.order_by(cd_d::batch_ts.desc()).limit(limit);
-        }
-
-        let rounds: Vec<NaiveDateTime> = query.get_results(&mut conn).await?;
-
-        Ok((model_id, rounds))
+        let rounds: Vec<NaiveDateTime> = page.limit(limit).get_results(&mut conn).await?;
+        let has_beyond_cursor = cursor.is_some()
+            && !beyond
+                .limit(1)
+                .get_results::<NaiveDateTime>(&mut conn)
+                .await?
+                .is_empty();
+        let page = Page::from_rows(request, rounds, has_beyond_cursor, |batch_ts| {
+            (Cursor::from_key(&batch_ts), batch_ts)
+        });
+        Ok((model_id, page))
     }
 }
@@ -6,3 +6,7 @@ mod int;
 mod ipaddr;
 pub(super) mod statistics;
 mod text;
+
+/// The maximum number of rows inserted by a statement, keeping the number of
+/// bind parameters below PostgreSQL's limit of 65,535.
+pub(super) const ROWS_PER_STATEMENT: usize = 8_000;
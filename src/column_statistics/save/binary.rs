@@ -9,9 +9,9 @@ use structured::{ColumnStatistics, Element};
 
 #[derive(Deserialize, Debug, Insertable, PartialEq)]
 #[diesel(table_name = crate::schema::description_binary)]
-struct DescriptionBinary<'a> {
+struct DescriptionBinary {
     description_id: i32,
-    mode: &'a [u8],
+    mode: Vec<u8>,
 }
 
 #[derive(Deserialize, Debug, Insertable, PartialEq)]
@@ -22,41 +22,58 @@ struct TopNBinary<'a> {
     count: i64,
 }
 
-pub(super) async fn insert_top_n(
-    conn: &mut AsyncPgConnection,
-    description_id: i32,
-    column_stats: &ColumnStatistics,
-    mode: &[u8],
-) -> Result<usize, Error> {
-    let db = DescriptionBinary {
-        description_id,
-        mode,
-    };
-    let _res = diesel::insert_into(desc_d::description_binary)
-        .values(&db)
-        .execute(conn)
-        .await?;
+/// The `description_binary` and `top_n_binary` rows of the columns in a batch.
+#[derive(Default)]
+pub(super) struct Rows<'a> {
+    descriptions: Vec<DescriptionBinary>,
+    top_n: Vec<TopNBinary<'a>>,
+}
+
+impl<'a> Rows<'a> {
+    /// Adds the rows of the column with `description_id`, and returns the
+    /// number of top-N rows added.
+    pub(super) fn push(
+        &mut self,
+        description_id: i32,
+        column_stats: &'a ColumnStatistics,
+        mode: &[u8],
+    ) -> usize {
+        self.descriptions.push(DescriptionBinary {
+            description_id,
+            mode: mode.to_vec(),
+        });
+        let len = self.top_n.len();
+        self.top_n
+            .extend(column_stats.n_largest_count.top_n().iter().map(|e| {
+                let value = if let Element::Binary(binary) = &e.value {
+                    Some(binary.as_slice())
+                } else {
+                    None
+                };
+                let count = i64::try_from(e.count).expect("Must be less than i64::MAX");
+                TopNBinary {
+                    description_id,
+                    value,
+                    count,
+                }
+            }));
+        self.top_n.len() - len
+    }
 
-    let top_n: Vec<_> = column_stats
-        .n_largest_count
-        .top_n()
-        .iter()
-        .map(|e| {
-            let value = if let Element::Binary(binary) = &e.value {
-                Some(binary.as_slice())
-            } else {
-                None
-            };
-            let count = i64::try_from(e.count).expect("Must be less than i64::MAX");
-            TopNBinary {
-                description_id,
-                value,
-                count,
-            }
-        })
-        .collect();
-    Ok(diesel::insert_into(topn_d::top_n_binary)
-        .values(&top_n)
-        .execute(conn)
-        .await?)
+    /// Inserts the rows with as few statements as possible.
+    pub(super) async fn insert(&self, conn: &mut AsyncPgConnection) -> Result<(), Error> {
+        for chunk in self.descriptions.chunks(super::ROWS_PER_STATEMENT) {
+            diesel::insert_into(desc_d::description_binary)
+                .values(chunk)
+                .execute(conn)
+                .await?;
+        }
+        for chunk in self.top_n.chunks(super::ROWS_PER_STATEMENT) {
+            diesel::insert_into(topn_d::top_n_binary)
+                .values(chunk)
+                .execute(conn)
+                .await?;
+        }
+        Ok(())
+    }
 }
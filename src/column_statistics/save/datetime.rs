@@ -23,41 +23,58 @@ struct TopNDatetime {
     count: i64,
 }
 
-pub(super) async fn insert_top_n(
-    conn: &mut AsyncPgConnection,
-    description_id: i32,
-    column_stats: &ColumnStatistics,
-    mode: &NaiveDateTime,
-) -> Result<usize, Error> {
-    let db = DescriptionDatetime {
-        description_id,
-        mode: *mode,
-    };
-    let _res = diesel::insert_into(desc_d::description_datetime)
-        .values(&db)
-        .execute(conn)
-        .await?;
+/// The `description_datetime` and `top_n_datetime` rows of the columns in a batch.
+#[derive(Default)]
+pub(super) struct Rows {
+    descriptions: Vec<DescriptionDatetime>,
+    top_n: Vec<TopNDatetime>,
+}
+
+impl Rows {
+    /// Adds the rows of the column with `description_id`, and returns the
+    /// number of top-N rows added.
+    pub(super) fn push(
+        &mut self,
+        description_id: i32,
+        column_stats: &ColumnStatistics,
+        mode: &NaiveDateTime,
+    ) -> usize {
+        self.descriptions.push(DescriptionDatetime {
+            description_id,
+            mode: *mode,
+        });
+        let len = self.top_n.len();
+        self.top_n
+            .extend(column_stats.n_largest_count.top_n().iter().filter_map(|e| {
+                let value = if let Element::DateTime(datetime) = &e.value {
+                    *datetime
+                } else {
+                    return None;
+                };
+                let count = i64::try_from(e.count).expect("Must be less than i64::MAX");
+                Some(TopNDatetime {
+                    description_id,
+                    value,
+                    count,
+                })
+            }));
+        self.top_n.len() - len
+    }
 
-    let top_n: Vec<_> = column_stats
-        .n_largest_count
-        .top_n()
-        .iter()
-        .filter_map(|e| {
-            let value = if let Element::DateTime(datetime) = &e.value {
-                *datetime
-            } else {
-                return None;
-            };
-            let count = i64::try_from(e.count).expect("Must be less than i64::MAX");
-            Some(TopNDatetime {
-                description_id,
-                value,
-                count,
-            })
-        })
-        .collect();
-    Ok(diesel::insert_into(topn_d::top_n_datetime)
-        .values(&top_n)
-        .execute(conn)
-        .await?)
+    /// Inserts the rows with as few statements as possible.
+    pub(super) async fn insert(&self, conn: &mut AsyncPgConnection) -> Result<(), Error> {
+        for chunk in self.descriptions.chunks(super::ROWS_PER_STATEMENT) {
+            diesel::insert_into(desc_d::description_datetime)
+                .values(chunk)
+                .execute(conn)
+                .await?;
+        }
+        for chunk in self.top_n.chunks(super::ROWS_PER_STATEMENT) {
+            diesel::insert_into(topn_d::top_n_datetime)
+                .values(chunk)
+                .execute(conn)
+                .await?;
+        }
+        Ok(())
+    }
 }
@@ -9,9 +9,9 @@ use structured::{ColumnStatistics, Element};
 
 #[derive(Deserialize, Debug, Insertable, PartialEq)]
 #[diesel(table_name = crate::schema::description_enum)]
-struct DescriptionEnum<'a> {
+struct DescriptionEnum {
     description_id: i32,
-    mode: &'a str,
+    mode: String,
 }
 
 #[derive(Deserialize, Debug, Insertable, PartialEq)]
@@ -22,41 +22,58 @@ struct TopNEnum<'a> {
     count: i64,
 }
 
-pub(super) async fn insert_top_n(
-    conn: &mut AsyncPgConnection,
-    description_id: i32,
-    column_stats: &ColumnStatistics,
-    mode: &str,
-) -> Result<usize, Error> {
-    let db = DescriptionEnum {
-        description_id,
-        mode,
-    };
-    let _res = diesel::insert_into(desc_d::description_enum)
-        .values(&db)
-        .execute(conn)
-        .await?;
+/// The `description_enum` and `top_n_enum` rows of the columns in a batch.
+#[derive(Default)]
+pub(super) struct Rows<'a> {
+    descriptions: Vec<DescriptionEnum>,
+    top_n: Vec<TopNEnum<'a>>,
+}
+
+impl<'a> Rows<'a> {
+    /// Adds the rows of the column with `description_id`, and returns the
+    /// number of top-N rows added.
+    pub(super) fn push(
+        &mut self,
+        description_id: i32,
+        column_stats: &'a ColumnStatistics,
+        mode: &str,
+    ) -> usize {
+        self.descriptions.push(DescriptionEnum {
+            description_id,
+            mode: mode.to_string(),
+        });
+        let len = self.top_n.len();
+        self.top_n
+            .extend(column_stats.n_largest_count.top_n().iter().filter_map(|e| {
+                let value = if let Element::Enum(val) = &e.value {
+                    val.as_str()
+                } else {
+                    return None;
+                };
+                let count = i64::try_from(e.count).expect("Must be less than i64::MAX");
+                Some(TopNEnum {
+                    description_id,
+                    value,
+                    count,
+                })
+            }));
+        self.top_n.len() - len
+    }
 
-    let top_n: Vec<_> = column_stats
-        .n_largest_count
-        .top_n()
-        .iter()
-        .filter_map(|e| {
-            let value = if let Element::Enum(val) = &e.value {
-                val.as_str()
-            } else {
-                return None;
-            };
-            let count = i64::try_from(e.count).expect("Must be less than i64::MAX");
-            Some(TopNEnum {
-                description_id,
-                value,
-                count,
-            })
-        })
-        .collect();
-    Ok(diesel::insert_into(topn_d::top_n_enum)
-        .values(&top_n)
-        .execute(conn)
-        .await?)
+    /// Inserts the rows with as few statements as possible.
+    pub(super) async fn insert(&self, conn: &mut AsyncPgConnection) -> Result<(), Error> {
+        for chunk in self.descriptions.chunks(super::ROWS_PER_STATEMENT) {
+            diesel::insert_into(desc_d::description_enum)
+                .values(chunk)
+                .execute(conn)
+                .await?;
+        }
+        for chunk in self.top_n.chunks(super::ROWS_PER_STATEMENT) {
+            diesel::insert_into(topn_d::top_n_enum)
+                .values(chunk)
+                .execute(conn)
+                .await?;
+        }
+        Ok(())
+    }
 }
@@ -28,58 +28,74 @@ struct TopNFloat {
     count: i64,
 }
 
-pub(super) async fn insert_top_n(
-    conn: &mut AsyncPgConnection,
-    description_id: i32,
-    column_stats: &ColumnStatistics,
-    mode: &FloatRange,
-) -> Result<usize, Error> {
-    let min = if let Some(Element::Float(min)) = &column_stats.description.min() {
-        Some(*min)
-    } else {
-        None
-    };
-    let max = if let Some(Element::Float(max)) = &column_stats.description.max() {
-        Some(*max)
-    } else {
-        None
-    };
+/// The `description_float` and `top_n_float` rows of the columns in a batch.
+#[derive(Default)]
+pub(super) struct Rows {
+    descriptions: Vec<DescriptionFloat>,
+    top_n: Vec<TopNFloat>,
+}
 
-    let db = DescriptionFloat {
-        description_id,
-        min,
-        max,
-        mean: column_stats.description.mean(),
-        s_deviation: column_stats.description.std_deviation(),
-        mode_smallest: mode.smallest,
-        mode_largest: mode.largest,
-    };
-    let _res = diesel::insert_into(desc_d::description_float)
-        .values(&db)
-        .execute(conn)
-        .await?;
+impl Rows {
+    /// Adds the rows of the column with `description_id`, and returns the
+    /// number of top-N rows added.
+    pub(super) fn push(
+        &mut self,
+        description_id: i32,
+        column_stats: &ColumnStatistics,
+        mode: &FloatRange,
+    ) -> usize {
+        let min = if let Some(Element::Float(min)) = &column_stats.description.min() {
+            Some(*min)
+        } else {
+            None
+        };
+        let max = if let Some(Element::Float(max)) = &column_stats.description.max() {
+            Some(*max)
+        } else {
+            None
+        };
+        self.descriptions.push(DescriptionFloat {
+            description_id,
+            min,
+            max,
+            mean: column_stats.description.mean(),
+            s_deviation: column_stats.description.std_deviation(),
+            mode_smallest: mode.smallest,
+            mode_largest: mode.largest,
+        });
+        let len = self.top_n.len();
+        self.top_n
+            .extend(column_stats.n_largest_count.top_n().iter().filter_map(|e| {
+                let (value_smallest, value_largest) = if let Element::FloatRange(fr) = &e.value {
+                    (fr.smallest, fr.largest)
+                } else {
+                    return None;
+                };
+                let count = i64::try_from(e.count).expect("Must be less than i64::MAX");
+                Some(TopNFloat {
+                    description_id,
+                    value_smallest,
+                    value_largest,
+                    count,
+                })
+            }));
+        self.top_n.len() - len
+    }
 
-    let top_n: Vec<_> = column_stats
-        .n_largest_count
-        .top_n()
-        .iter()
-        .filter_map(|e| {
-            let (value_smallest, value_largest) = if let Element::FloatRange(fr) = &e.value {
-                (fr.smallest, fr.largest)
-            } else {
-                return None;
-            };
-            let count = i64::try_from(e.count).expect("Must be less than i64::MAX");
-            Some(TopNFloat {
-                description_id,
-                value_smallest,
-                value_largest,
-                count,
-            })
-        })
-        .collect();
-    Ok(diesel::insert_into(topn_d::top_n_float)
-        .values(&top_n)
-        .execute(conn)
-        .await?)
+    /// Inserts the rows with as few statements as possible.
+    pub(super) async fn insert(&self, conn: &mut AsyncPgConnection) -> Result<(), Error> {
+        for chunk in self.descriptions.chunks(super::ROWS_PER_STATEMENT) {
+            diesel::insert_into(desc_d::description_float)
+                .values(chunk)
+                .execute(conn)
+                .await?;
+        }
+        for chunk in self.top_n.chunks(super::ROWS_PER_STATEMENT) {
+            diesel::insert_into(topn_d::top_n_float)
+                .values(chunk)
+                .execute(conn)
+                .await?;
+        }
+        Ok(())
+    }
 }
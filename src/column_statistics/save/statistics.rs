@@ -1,12 +1,18 @@
 use super::{binary, datetime, float_range, int, ipaddr, r#enum, text};
+use crate::column_statistics::histogram::{
+    insert_histograms, Histogram, HistogramInput, HISTOGRAM_TYPE_IDS,
+};
 use crate::schema::{cluster::dsl as cluster_d, column_description::dsl as cd_d};
-use crate::Database;
+use crate::{Database, Error};
 use anyhow::Result;
 use chrono::NaiveDateTime;
 use diesel::{BoolExpressionMethods, ExpressionMethods, QueryDsl};
-use diesel_async::RunQueryDsl;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
 use serde::Deserialize;
-use std::convert::TryFrom;
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryFrom,
+};
 use structured::{ColumnStatistics, Element};
 
 #[allow(clippy::module_name_repetitions)]
@@ -14,6 +20,11 @@ use structured::{ColumnStatistics, Element};
 pub struct ColumnStatisticsUpdate {
     cluster_id: String, // NOT cluster_id but id of cluster table
     column_statistics: Vec<ColumnStatistics>,
+    /// The histograms of the columns, in the same order as
+    /// `column_statistics`. Only those of integer and floating-point columns
+    /// are stored.
+    #[serde(default)]
+    histograms: Vec<Option<Histogram>>,
 }
 
 #[derive(Deserialize, Debug, Insertable, PartialEq)]
@@ -63,108 +74,173 @@ fn check_column_types(stats: &[ColumnStatisticsUpdate]) -> Vec<Option<i32>> {
 impl Database {
     /// Inserts column statistics into the database.
     ///
+    /// The statistics of all the columns are inserted in a single
+    /// transaction, with a few multi-row statements per table.
+    ///
     /// # Errors
     ///
-    /// Returns an error if a database operation fails.
-    #[allow(clippy::too_many_lines)]
+    /// Returns an error if a cluster doesn't exist or appears more than once
+    /// in `statistics`, or a database operation fails.
     pub async fn insert_column_statistics(
         &self,
         statistics: Vec<ColumnStatisticsUpdate>,
         model_id: i32,
         batch_ts: NaiveDateTime,
     ) -> Result<()> {
-        let mut conn = self.pool.get_diesel_conn().await?;
+        use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection};
 
         let column_types = check_column_types(&statistics);
         if column_types.is_empty() {
             anyhow::bail!("Unsupported column type");
         }
-        for stat in statistics {
-            let query = cluster_d::cluster.select(cluster_d::id).filter(
-                cluster_d::model_id
-                    .eq(model_id)
-                    .and(cluster_d::cluster_id.eq(stat.cluster_id)),
-            );
-            let cluster_id = query.load::<i32>(&mut conn).await?[0];
+        let mut conn = self.pool.get_diesel_conn().await?;
+        conn.transaction::<_, Error, _>(|conn| {
+            async move {
+                insert_statistics(conn, &statistics, &column_types, model_id, batch_ts).await
+            }
+            .scope_boxed()
+        })
+        .await?;
+        Ok(())
+    }
+}
+
+/// Inserts the column descriptions and the top-N rows of `statistics`.
+///
+/// The inserted descriptions are matched to their statistics by cluster and
+/// column index, so each cluster may appear in `statistics` only once.
+async fn insert_statistics(
+    conn: &mut AsyncPgConnection,
+    statistics: &[ColumnStatisticsUpdate],
+    column_types: &[Option<i32>],
+    model_id: i32,
+    batch_ts: NaiveDateTime,
+) -> Result<(), Error> {
+    let names: Vec<_> = statistics.iter().map(|s| s.cluster_id.as_str()).collect();
+    let cluster_ids: HashMap<String, i32> = cluster_d::cluster
+        .select((cluster_d::cluster_id, cluster_d::id))
+        .filter(
+            cluster_d::model_id
+                .eq(model_id)
+                .and(cluster_d::cluster_id.eq_any(&names)),
+        )
+        .load::<(String, i32)>(conn)
+        .await?
+        .into_iter()
+        .collect();
 
-            let column_descriptions: Vec<_> = (0..)
-                .zip(&column_types)
+    let mut inputs = Vec::new();
+    let mut seen = HashSet::with_capacity(statistics.len());
+    for stat in statistics {
+        let Some(&cluster_id) = cluster_ids.get(&stat.cluster_id) else {
+            return Err(Error::InvalidInput(format!(
+                "no such cluster: {}",
+                stat.cluster_id
+            )));
+        };
+        if !seen.insert(cluster_id) {
+            return Err(Error::InvalidInput(format!(
+                "duplicate cluster: {}",
+                stat.cluster_id
+            )));
+        }
+        inputs.extend(
+            (0..)
+                .zip(column_types)
                 .zip(&stat.column_statistics)
                 .filter_map(|((column_index, &type_id), column_stats)| {
-                    if let Some(type_id) = type_id {
-                        let count =
-                            i64::try_from(column_stats.description.count()).unwrap_or_default();
-                        let unique_count =
-                            i64::try_from(column_stats.n_largest_count.number_of_elements())
-                                .unwrap_or_default();
-                        Some(ColumnDescriptionInput {
-                            column_index,
-                            type_id,
-                            count,
-                            unique_count,
-                            cluster_id,
-                            batch_ts,
-                        })
-                    } else {
-                        None
-                    }
-                })
-                .collect();
-
-            let query = diesel::insert_into(cd_d::column_description).values(&column_descriptions);
-            let column_descriptions: Vec<ColumnDescription> = query.get_results(&mut conn).await?;
-            for (id, column_stats) in
-                column_descriptions
-                    .into_iter()
-                    .filter_map(|c: ColumnDescription| {
-                        if let Ok(cid) = usize::try_from(c.column_index) {
-                            Some((c.id, &stat.column_statistics[cid]))
-                        } else {
-                            None
-                        }
+                    let type_id = type_id?;
+                    let count = i64::try_from(column_stats.description.count()).unwrap_or_default();
+                    let unique_count =
+                        i64::try_from(column_stats.n_largest_count.number_of_elements())
+                            .unwrap_or_default();
+                    Some(ColumnDescriptionInput {
+                        column_index,
+                        type_id,
+                        count,
+                        unique_count,
+                        cluster_id,
+                        batch_ts,
                     })
-            {
-                let (type_name, inserted) = match &column_stats.n_largest_count.mode() {
-                    Some(Element::Int(mode)) => (
-                        "int",
-                        int::insert_top_n(&mut conn, id, column_stats, *mode).await?,
-                    ),
-                    Some(Element::Enum(mode)) => (
-                        "enum",
-                        r#enum::insert_top_n(&mut conn, id, column_stats, mode).await?,
-                    ),
-                    Some(Element::FloatRange(mode)) => (
-                        "float_range",
-                        float_range::insert_top_n(&mut conn, id, column_stats, mode).await?,
-                    ),
-                    Some(Element::Text(mode)) => (
-                        "text",
-                        text::insert_top_n(&mut conn, id, column_stats, mode).await?,
-                    ),
-                    Some(Element::IpAddr(mode)) => (
-                        "ipaddr",
-                        ipaddr::insert_top_n(&mut conn, id, column_stats, mode).await?,
-                    ),
-                    Some(Element::DateTime(mode)) => (
-                        "datetime",
-                        datetime::insert_top_n(&mut conn, id, column_stats, mode).await?,
-                    ),
-                    Some(Element::Binary(mode)) => (
-                        "binary",
-                        binary::insert_top_n(&mut conn, id, column_stats, mode).await?,
-                    ),
-                    _ => ("", column_stats.n_largest_count.top_n().len()),
-                };
-                if inserted != column_stats.n_largest_count.top_n().len() {
-                    tracing::error!(
-                        "Failed to insert all of top_n {}, entries failed: {} / {}",
-                        type_name,
-                        column_stats.n_largest_count.top_n().len() - inserted,
-                        column_stats.n_largest_count.top_n().len()
-                    );
+                }),
+        );
+    }
+
+    let mut description_ids = HashMap::with_capacity(inputs.len());
+    for chunk in inputs.chunks(super::ROWS_PER_STATEMENT) {
+        let descriptions: Vec<ColumnDescription> = diesel::insert_into(cd_d::column_description)
+            .values(chunk)
+            .get_results(conn)
+            .await?;
+        description_ids.extend(
+            descriptions
+                .into_iter()
+                .map(|c| ((c.cluster_id, c.column_index), c.id)),
+        );
+    }
+
+    let mut ints = int::Rows::default();
+    let mut enums = r#enum::Rows::default();
+    let mut float_ranges = float_range::Rows::default();
+    let mut texts = text::Rows::default();
+    let mut ipaddrs = ipaddr::Rows::default();
+    let mut datetimes = datetime::Rows::default();
+    let mut binaries = binary::Rows::default();
+    for stat in statistics {
+        let cluster_id = cluster_ids[&stat.cluster_id];
+        for (column_index, column_stats) in (0..).zip(&stat.column_statistics) {
+            let Some(&id) = description_ids.get(&(cluster_id, column_index)) else {
+                continue;
+            };
+            let (type_name, added) = match &column_stats.n_largest_count.mode() {
+                Some(Element::Int(mode)) => ("int", ints.push(id, column_stats, *mode)),
+                Some(Element::Enum(mode)) => ("enum", enums.push(id, column_stats, mode)),
+                Some(Element::FloatRange(mode)) => {
+                    ("float_range", float_ranges.push(id, column_stats, mode))
                 }
+                Some(Element::Text(mode)) => ("text", texts.push(id, column_stats, mode)),
+                Some(Element::IpAddr(mode)) => ("ipaddr", ipaddrs.push(id, column_stats, mode)),
+                Some(Element::DateTime(mode)) => {
+                    ("datetime", datetimes.push(id, column_stats, mode))
+                }
+                Some(Element::Binary(mode)) => ("binary", binaries.push(id, column_stats, mode)),
+                _ => ("", column_stats.n_largest_count.top_n().len()),
+            };
+            if added != column_stats.n_largest_count.top_n().len() {
+                tracing::error!(
+                    "Failed to insert all of top_n {}, entries failed: {} / {}",
+                    type_name,
+                    column_stats.n_largest_count.top_n().len() - added,
+                    column_stats.n_largest_count.top_n().len()
+                );
             }
         }
-        Ok(())
     }
+
+    let description_ids = &description_ids;
+    let histograms: Vec<_> = statistics
+        .iter()
+        .flat_map(|stat| {
+            let cluster_id = cluster_ids[&stat.cluster_id];
+            (0..).zip(column_types).zip(&stat.histograms).filter_map(
+                move |((column_index, type_id), histogram)| {
+                    if !HISTOGRAM_TYPE_IDS.contains(type_id.as_ref()?) {
+                        return None;
+                    }
+                    let id = description_ids.get(&(cluster_id, column_index))?;
+                    Some(HistogramInput::new(*id, histogram.as_ref()?))
+                },
+            )
+        })
+        .collect();
+
+    ints.insert(conn).await?;
+    enums.insert(conn).await?;
+    float_ranges.insert(conn).await?;
+    texts.insert(conn).await?;
+    ipaddrs.insert(conn).await?;
+    datetimes.insert(conn).await?;
+    binaries.insert(conn).await?;
+    insert_histograms(conn, &histograms).await?;
+    Ok(())
 }
@@ -1,10 +1,12 @@
 #![allow(clippy::too_many_lines)]
 mod common;
 mod conn;
+mod custom;
 mod dcerpc;
 mod dns;
 mod ftp;
 mod http;
+mod icmp;
 mod kerberos;
 mod ldap;
 mod log;
@@ -13,6 +15,7 @@ mod network;
 mod nfs;
 mod ntlm;
 mod rdp;
+mod siem;
 mod smb;
 mod smtp;
 mod ssh;
@@ -20,14 +23,20 @@ mod sysmon;
 mod tls;
 mod tor;
 
-use self::{common::Match, http::RepeatedHttpSessionsFields};
+use self::{
+    common::Match,
+    http::{LargeFields, RepeatedHttpSessionsFields},
+};
 pub use self::{
-    common::TriageScore,
+    common::{MatchedRule, TriageResult, TriageScore},
     conn::{
         BlockListConn, BlockListConnFields, ExternalDdos, ExternalDdosFields, MultiHostPortScan,
         MultiHostPortScanFields, PortScan, PortScanFields,
     },
-    dcerpc::{BlockListDceRpc, BlockListDceRpcFields},
+    custom::{CustomDetection, CustomDetectionFields, CustomFieldValue},
+    dcerpc::{
+        BlockListDceRpc, BlockListDceRpcFields, DceRpcLateralMovement, DceRpcLateralMovementFields,
+    },
     dns::{
         BlockListDns, BlockListDnsFields, CryptocurrencyMiningPool, CryptocurrencyMiningPoolFields,
         DnsCovertChannel, DnsEventFields,
@@ -38,8 +47,9 @@ pub use self::{
     },
     http::{
         BlockListHttp, BlockListHttpFields, DgaFields, DomainGenerationAlgorithm, HttpThreat,
-        HttpThreatFields, NonBrowser, NonBrowserFields, RepeatedHttpSessions,
+        HttpThreatFields, NonBrowser, NonBrowserFields, RepeatedHttpSessions, TRUNCATION_MARKER,
     },
+    icmp::{IcmpTunnel, IcmpTunnelFields},
     kerberos::{BlockListKerberos, BlockListKerberosFields},
     ldap::{
         BlockListLdap, BlockListLdapFields, LdapBruteForce, LdapBruteForceFields, LdapPlainText,
@@ -51,16 +61,20 @@ pub use self::{
     nfs::{BlockListNfs, BlockListNfsFields},
     ntlm::{BlockListNtlm, BlockListNtlmFields},
     rdp::{BlockListRdp, BlockListRdpFields, RdpBruteForce, RdpBruteForceFields},
-    smb::{BlockListSmb, BlockListSmbFields},
+    smb::{BlockListSmb, BlockListSmbFields, SmbLateralMovement, SmbLateralMovementFields},
     smtp::{BlockListSmtp, BlockListSmtpFields},
     ssh::{BlockListSsh, BlockListSshFields},
     sysmon::WindowsThreat,
-    tls::{BlockListTls, BlockListTlsFields},
+    tls::{BlockListTls, BlockListTlsFields, TlsCertificateAnomaly, TlsCertificateAnomalyFields},
     tor::{TorConnection, TorConnectionFields},
 };
 use super::{
+    metrics::{record, Operation},
+    notification::{notify, ChangeKind, Channel},
+    retention::EventRetentionPolicy,
+    tables::{SyslogMapping, DEFAULT_SYSLOG_FACILITY},
     types::{Endpoint, EventCategory, HostNetworkGroup},
-    Customer, Network, TriagePolicy,
+    Customer, Network, Table, Tidb, TriagePolicy,
 };
 use aho_corasick::AhoCorasickBuilder;
 use anyhow::{bail, Context, Result};
@@ -72,11 +86,13 @@ pub use rocksdb::Direction;
 use rocksdb::IteratorMode;
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     convert::TryInto,
     fmt,
+    io::Write,
     net::IpAddr,
     num::NonZeroU8,
+    ops::{Range, RangeInclusive},
     sync::{Arc, Mutex, MutexGuard},
 };
 
@@ -107,6 +123,11 @@ const BLOCK_LIST: &str = "Block List";
 const WINDOWS_THREAT_EVENT: &str = "Windows Threat Events";
 const NETWORK_THREAT_EVENT: &str = "Network Threat Events";
 const MISC_LOG_THREAT: &str = "Log Threat";
+const TLS_CERTIFICATE_ANOMALY: &str = "TLS Certificate Anomaly";
+const SMB_LATERAL_MOVEMENT: &str = "SMB Lateral Movement";
+const DCE_RPC_LATERAL_MOVEMENT: &str = "DCE-RPC Lateral Movement";
+const ICMP_TUNNEL: &str = "ICMP Tunnel";
+const CUSTOM_DETECTION: &str = "Custom Detection";
 
 pub enum Event {
     /// DNS requests and responses that convey unusual host names.
@@ -163,6 +184,26 @@ pub enum Event {
     NetworkThreat(NetworkThreat),
 
     ExtraThreat(ExtraThreat),
+
+    /// A TLS session with an anomalous server certificate, such as a
+    /// self-signed or expired one.
+    TlsCertificateAnomaly(TlsCertificateAnomaly),
+
+    /// Suspicious SMB activity that indicates lateral movement, such as
+    /// access to an administrative share.
+    SmbLateralMovement(SmbLateralMovement),
+
+    /// Suspicious DCE-RPC activity that indicates lateral movement, such as
+    /// the use of a named pipe of a remote execution tool.
+    DceRpcLateralMovement(DceRpcLateralMovement),
+
+    /// ICMP packets whose payloads are anomalous, such as oversized or
+    /// high-entropy payloads, indicating data tunneled through ICMP.
+    IcmpTunnel(IcmpTunnel),
+
+    /// An event from a detector outside this crate, with a detector-defined
+    /// name, category, and fields.
+    CustomDetection(CustomDetection),
 }
 
 pub enum RecordType {
@@ -232,6 +273,115 @@ impl Event {
             Event::WindowsThreat(event) => event.matches(locator, filter),
             Event::NetworkThreat(event) => event.matches(locator, filter),
             Event::ExtraThreat(event) => event.matches(locator, filter),
+            Event::TlsCertificateAnomaly(event) => event.matches(locator, filter),
+            Event::SmbLateralMovement(event) => event.matches(locator, filter),
+            Event::DceRpcLateralMovement(event) => event.matches(locator, filter),
+            Event::IcmpTunnel(event) => event.matches(locator, filter),
+            Event::CustomDetection(event) => event.matches(locator, filter),
+        }
+    }
+
+    /// Returns the category of the event.
+    #[must_use]
+    pub fn category(&self) -> EventCategory {
+        self.as_match().category()
+    }
+
+    /// Evaluates the event against the triage policy at `now`, looking up the
+    /// threat-intelligence databases referred to by the policy in `tidbs`.
+    /// The threat-intelligence rules expired at `now` are skipped.
+    #[must_use]
+    pub fn triage(
+        &self,
+        policy: &TriagePolicy,
+        tidbs: &[Tidb],
+        now: DateTime<Utc>,
+    ) -> TriageResult {
+        self.as_match().triage(policy, tidbs, now)
+    }
+
+    /// Formats the event that occurred at `time` in CEF (Common Event
+    /// Format) for forwarding to a SIEM.
+    ///
+    /// The extension has the keys common to all kinds of events, `rt`, `src`,
+    /// `spt`, `dst`, `dpt`, `proto`, `cat`, and `dvchost` (the sensor),
+    /// followed by the attributes specific to the kind of the event, e.g.
+    /// `query` of a DNS event, and `cfp1` (the confidence, if any).
+    #[must_use]
+    pub fn to_cef(&self, time: DateTime<Utc>) -> String {
+        siem::to_cef(self.as_match(), time)
+    }
+
+    /// Formats the event that occurred at `time` in LEEF (Log Event Extended
+    /// Format) 1.0 for forwarding to a SIEM.
+    ///
+    /// The attributes are the same as those of [`Event::to_cef`], with
+    /// LEEF's predefined keys where available.
+    #[must_use]
+    pub fn to_leef(&self, time: DateTime<Utc>) -> String {
+        siem::to_leef(self.as_match(), time)
+    }
+
+    /// Returns the syslog priority of the event given by `mappings`.
+    ///
+    /// If no mapping applies to the event, the facility is
+    /// [`DEFAULT_SYSLOG_FACILITY`](crate::DEFAULT_SYSLOG_FACILITY) and the
+    /// severity is derived from the level of the event, from 6
+    /// (informational) for the lowest level to 2 (critical) for the highest.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the mappings cannot be read.
+    pub fn syslog_priority(&self, mappings: &Table<SyslogMapping>) -> Result<u8> {
+        let event = self.as_match();
+        Ok(match mappings.resolve(event.kind(), event.category())? {
+            Some(mapping) => mapping.priority(),
+            None => default_syslog_priority(event.level()),
+        })
+    }
+
+    fn as_match(&self) -> &dyn Match {
+        match self {
+            Event::DnsCovertChannel(event) => event,
+            Event::HttpThreat(event) => event,
+            Event::RdpBruteForce(event) => event,
+            Event::RepeatedHttpSessions(event) => event,
+            Event::TorConnection(event) => event,
+            Event::DomainGenerationAlgorithm(event) => event,
+            Event::FtpBruteForce(event) => event,
+            Event::FtpPlainText(event) => event,
+            Event::PortScan(event) => event,
+            Event::MultiHostPortScan(event) => event,
+            Event::ExternalDdos(event) => event,
+            Event::NonBrowser(event) => event,
+            Event::LdapBruteForce(event) => event,
+            Event::LdapPlainText(event) => event,
+            Event::CryptocurrencyMiningPool(event) => event,
+            Event::BlockList(record_type) => match record_type {
+                RecordType::Conn(event) => event,
+                RecordType::Dns(event) => event,
+                RecordType::DceRpc(event) => event,
+                RecordType::Ftp(event) => event,
+                RecordType::Http(event) => event,
+                RecordType::Kerberos(event) => event,
+                RecordType::Ldap(event) => event,
+                RecordType::Mqtt(event) => event,
+                RecordType::Nfs(event) => event,
+                RecordType::Ntlm(event) => event,
+                RecordType::Rdp(event) => event,
+                RecordType::Smb(event) => event,
+                RecordType::Smtp(event) => event,
+                RecordType::Ssh(event) => event,
+                RecordType::Tls(event) => event,
+            },
+            Event::WindowsThreat(event) => event,
+            Event::NetworkThreat(event) => event,
+            Event::ExtraThreat(event) => event,
+            Event::TlsCertificateAnomaly(event) => event,
+            Event::SmbLateralMovement(event) => event,
+            Event::DceRpcLateralMovement(event) => event,
+            Event::IcmpTunnel(event) => event,
+            Event::CustomDetection(event) => event,
         }
     }
 
@@ -401,6 +551,31 @@ impl Event {
                 }
             }
             Event::ExtraThreat(_event) => {}
+            Event::TlsCertificateAnomaly(event) => {
+                if event.matches(locator, filter)?.0 {
+                    addr_pair = (Some(event.src_addr), Some(event.dst_addr));
+                }
+            }
+            Event::SmbLateralMovement(event) => {
+                if event.matches(locator, filter)?.0 {
+                    addr_pair = (Some(event.src_addr), Some(event.dst_addr));
+                }
+            }
+            Event::DceRpcLateralMovement(event) => {
+                if event.matches(locator, filter)?.0 {
+                    addr_pair = (Some(event.src_addr), Some(event.dst_addr));
+                }
+            }
+            Event::IcmpTunnel(event) => {
+                if event.matches(locator, filter)?.0 {
+                    addr_pair = (Some(event.src_addr), Some(event.dst_addr));
+                }
+            }
+            Event::CustomDetection(event) => {
+                if event.matches(locator, filter)?.0 {
+                    addr_pair = (Some(event.src_addr), Some(event.dst_addr));
+                }
+            }
         }
         Ok(addr_pair)
     }
@@ -579,6 +754,31 @@ impl Event {
                     kind = Some(MISC_LOG_THREAT);
                 }
             }
+            Event::TlsCertificateAnomaly(event) => {
+                if event.matches(locator, filter)?.0 {
+                    kind = Some(TLS_CERTIFICATE_ANOMALY);
+                }
+            }
+            Event::SmbLateralMovement(event) => {
+                if event.matches(locator, filter)?.0 {
+                    kind = Some(SMB_LATERAL_MOVEMENT);
+                }
+            }
+            Event::DceRpcLateralMovement(event) => {
+                if event.matches(locator, filter)?.0 {
+                    kind = Some(DCE_RPC_LATERAL_MOVEMENT);
+                }
+            }
+            Event::IcmpTunnel(event) => {
+                if event.matches(locator, filter)?.0 {
+                    kind = Some(ICMP_TUNNEL);
+                }
+            }
+            Event::CustomDetection(event) => {
+                if event.matches(locator, filter)?.0 {
+                    kind = Some(CUSTOM_DETECTION);
+                }
+            }
         }
         Ok(kind)
     }
@@ -808,6 +1008,31 @@ impl Event {
                     category = Some(EventCategory::Reconnaissance);
                 }
             }
+            Event::SmbLateralMovement(event) => {
+                if event.matches(locator, filter)?.0 {
+                    category = Some(EventCategory::LateralMovement);
+                }
+            }
+            Event::DceRpcLateralMovement(event) => {
+                if event.matches(locator, filter)?.0 {
+                    category = Some(EventCategory::LateralMovement);
+                }
+            }
+            Event::TlsCertificateAnomaly(event) => {
+                if event.matches(locator, filter)?.0 {
+                    category = Some(EventCategory::CommandAndControl);
+                }
+            }
+            Event::IcmpTunnel(event) => {
+                if event.matches(locator, filter)?.0 {
+                    category = Some(EventCategory::CommandAndControl);
+                }
+            }
+            Event::CustomDetection(event) => {
+                if event.matches(locator, filter)?.0 {
+                    category = Some(event.category);
+                }
+            }
         };
 
         if let Some(category) = category {
@@ -1141,6 +1366,31 @@ impl Event {
                     level = Some(MEDIUM);
                 }
             }
+            Event::SmbLateralMovement(event) => {
+                if event.matches(locator, filter)?.0 {
+                    level = Some(MEDIUM);
+                }
+            }
+            Event::DceRpcLateralMovement(event) => {
+                if event.matches(locator, filter)?.0 {
+                    level = Some(MEDIUM);
+                }
+            }
+            Event::TlsCertificateAnomaly(event) => {
+                if event.matches(locator, filter)?.0 {
+                    level = Some(MEDIUM);
+                }
+            }
+            Event::IcmpTunnel(event) => {
+                if event.matches(locator, filter)?.0 {
+                    level = Some(MEDIUM);
+                }
+            }
+            Event::CustomDetection(event) => {
+                if event.matches(locator, filter)?.0 {
+                    level = Some(event.level);
+                }
+            }
         }
 
         if let Some(level) = level {
@@ -1282,6 +1532,21 @@ impl Event {
             Event::ExtraThreat(event) => {
                 event.triage_scores = Some(triage_scores);
             }
+            Event::TlsCertificateAnomaly(event) => {
+                event.triage_scores = Some(triage_scores);
+            }
+            Event::SmbLateralMovement(event) => {
+                event.triage_scores = Some(triage_scores);
+            }
+            Event::DceRpcLateralMovement(event) => {
+                event.triage_scores = Some(triage_scores);
+            }
+            Event::IcmpTunnel(event) => {
+                event.triage_scores = Some(triage_scores);
+            }
+            Event::CustomDetection(event) => {
+                event.triage_scores = Some(triage_scores);
+            }
         }
     }
 }
@@ -1331,6 +1596,206 @@ pub enum EventKind {
     BlockListTls,
     WindowsThreat,
     NetworkThreat,
+    TlsCertificateAnomaly,
+    SmbLateralMovement,
+    DceRpcLateralMovement,
+    IcmpTunnel,
+    CustomDetection,
+}
+
+impl EventKind {
+    /// Returns the category of the events of this kind, or `None` if the
+    /// category is defined by each event, as in `CustomDetection`.
+    #[must_use]
+    pub fn category(self) -> Option<EventCategory> {
+        let category = match self {
+            Self::PortScan | Self::MultiHostPortScan | Self::ExtraThreat | Self::NetworkThreat => {
+                EventCategory::Reconnaissance
+            }
+            Self::BlockListConn
+            | Self::BlockListDns
+            | Self::BlockListDceRpc
+            | Self::BlockListFtp
+            | Self::BlockListHttp
+            | Self::BlockListKerberos
+            | Self::BlockListLdap
+            | Self::BlockListMqtt
+            | Self::BlockListNfs
+            | Self::BlockListNtlm
+            | Self::BlockListRdp
+            | Self::BlockListSmb
+            | Self::BlockListSmtp
+            | Self::BlockListSsh
+            | Self::BlockListTls => EventCategory::InitialAccess,
+            Self::FtpBruteForce | Self::LdapBruteForce => EventCategory::CredentialAccess,
+            Self::FtpPlainText
+            | Self::LdapPlainText
+            | Self::SmbLateralMovement
+            | Self::DceRpcLateralMovement => EventCategory::LateralMovement,
+            Self::DnsCovertChannel
+            | Self::CryptocurrencyMiningPool
+            | Self::DomainGenerationAlgorithm
+            | Self::NonBrowser
+            | Self::TlsCertificateAnomaly
+            | Self::IcmpTunnel
+            | Self::TorConnection => EventCategory::CommandAndControl,
+            Self::RepeatedHttpSessions | Self::RdpBruteForce => EventCategory::Exfiltration,
+            Self::ExternalDdos | Self::WindowsThreat => EventCategory::Impact,
+            Self::HttpThreat => EventCategory::HttpThreat,
+            Self::CustomDetection => return None,
+        };
+        Some(category)
+    }
+
+    /// Returns the name of the events of this kind, e.g., "dns covert
+    /// channel", or `None` if the name is defined by each event, as in
+    /// `CustomDetection`.
+    #[must_use]
+    pub fn name(self) -> Option<&'static str> {
+        let name = match self {
+            Self::DnsCovertChannel => "dns covert channel",
+            Self::HttpThreat => "http threat",
+            Self::RdpBruteForce => "rdp brute force",
+            Self::RepeatedHttpSessions => "repeated http sessions",
+            Self::ExtraThreat => "extra threat",
+            Self::TorConnection => "tor exit nodes",
+            Self::DomainGenerationAlgorithm => "dga",
+            Self::FtpBruteForce => "ftp brute force",
+            Self::FtpPlainText => "ftp plain text",
+            Self::PortScan => "port scan",
+            Self::MultiHostPortScan => "multi host port scan",
+            Self::NonBrowser => "non browser",
+            Self::LdapBruteForce => "ldap brute force",
+            Self::LdapPlainText => "ldap plain text",
+            Self::ExternalDdos => "external ddos",
+            Self::CryptocurrencyMiningPool => "cryptocurrency mining pool",
+            Self::BlockListConn => "block list conn",
+            Self::BlockListDns => "block list dns",
+            Self::BlockListDceRpc => "block list dcerpc",
+            Self::BlockListFtp => "block list ftp",
+            Self::BlockListHttp => "block list http",
+            Self::BlockListKerberos => "block list kerberos",
+            Self::BlockListLdap => "block list ldap",
+            Self::BlockListMqtt => "block list mqtt",
+            Self::BlockListNfs => "block list nfs",
+            Self::BlockListNtlm => "block list ntlm",
+            Self::BlockListRdp => "block list rdp",
+            Self::BlockListSmb => "block list smb",
+            Self::BlockListSmtp => "block list stmp",
+            Self::BlockListSsh => "block list ssh",
+            Self::BlockListTls => "block list tls",
+            Self::WindowsThreat => "windows threat",
+            Self::NetworkThreat => "network threat",
+            Self::TlsCertificateAnomaly => "tls certificate anomaly",
+            Self::SmbLateralMovement => "smb lateral movement",
+            Self::DceRpcLateralMovement => "dcerpc lateral movement",
+            Self::IcmpTunnel => "icmp tunnel",
+            Self::CustomDetection => return None,
+        };
+        Some(name)
+    }
+
+    /// Returns the kind whose events are named `name`, as returned by
+    /// [`EventKind::name`].
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        (0..)
+            .map_while(Self::from_u32)
+            .find(|kind| kind.name() == Some(name))
+    }
+}
+
+/// The length of the time intervals in which events are counted by
+/// [`EventDb::count_by_interval`].
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum EventInterval {
+    Hour,
+    Day,
+}
+
+impl EventInterval {
+    fn nanos(self) -> i128 {
+        match self {
+            Self::Hour => 3_600_000_000_000,
+            Self::Day => 86_400_000_000_000,
+        }
+    }
+}
+
+/// The number of events in a category that occurred in a time interval.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct EventCount {
+    /// The start of the interval.
+    pub start: DateTime<Utc>,
+    pub category: EventCategory,
+    pub count: usize,
+}
+
+/// The statistics of the events stored from a sensor.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SensorStats {
+    pub sensor: String,
+    /// The number of events stored from the sensor.
+    pub count: u64,
+    /// The time of the latest event stored from the sensor.
+    pub last_seen: DateTime<Utc>,
+}
+
+/// The value of an entry in the sensor statistics column family, and the
+/// merge operand that changes it.
+///
+/// As a merge operand, `count` is the number of added events minus the
+/// number of removed ones, and `last_seen` is `i64::MIN` if no event is
+/// added.
+#[derive(Deserialize, Serialize)]
+struct StoredSensorStats {
+    count: i64,
+    last_seen: i64,
+}
+
+/// The changes to the statistics of a sensor made by storing or deleting
+/// events.
+#[derive(Default)]
+struct SensorStatsDelta {
+    count: i64,
+    last_seen: Option<i64>,
+}
+
+/// Merges the statistics of a sensor with the changes to them, for the merge
+/// operator of the sensor statistics column family. Returns `None`, which
+/// RocksDB reports as corruption, if any value is invalid.
+pub(crate) fn merge_sensor_stats(
+    _key: &[u8],
+    existing: Option<&[u8]>,
+    operands: &rocksdb::MergeOperands,
+) -> Option<Vec<u8>> {
+    let mut merged = StoredSensorStats {
+        count: 0,
+        last_seen: i64::MIN,
+    };
+    for value in existing.into_iter().chain(operands) {
+        let value: StoredSensorStats = bincode::deserialize(value).ok()?;
+        merged.count = merged.count.saturating_add(value.count);
+        merged.last_seen = merged.last_seen.max(value.last_seen);
+    }
+    bincode::serialize(&merged).ok()
+}
+
+/// An analyst's verdict on an event.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Disposition {
+    TruePositive,
+    FalsePositive,
+    Benign,
+}
+
+/// A disposition attached to an event, with an optional comment.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct EventDisposition {
+    pub disposition: Disposition,
+    pub comment: Option<String>,
 }
 
 /// Machine Learning Method.
@@ -1355,6 +1820,9 @@ pub struct EventFilter {
     sensors: Option<Vec<String>>,
     confidence: Option<f32>,
     triage_policies: Option<Vec<TriagePolicy>>,
+    src_ports: Option<Vec<RangeInclusive<u16>>>,
+    dst_ports: Option<Vec<RangeInclusive<u16>>>,
+    protocols: Option<Vec<u8>>,
 }
 
 impl EventFilter {
@@ -1389,9 +1857,35 @@ impl EventFilter {
             sensors,
             confidence,
             triage_policies,
+            src_ports: None,
+            dst_ports: None,
+            protocols: None,
         }
     }
 
+    /// Limits the events to those whose source port is in any of `ports`.
+    #[must_use]
+    pub fn with_src_ports(mut self, ports: Vec<RangeInclusive<u16>>) -> Self {
+        self.src_ports = Some(ports);
+        self
+    }
+
+    /// Limits the events to those whose destination port is in any of
+    /// `ports`.
+    #[must_use]
+    pub fn with_dst_ports(mut self, ports: Vec<RangeInclusive<u16>>) -> Self {
+        self.dst_ports = Some(ports);
+        self
+    }
+
+    /// Limits the events to those whose L4 protocol number is any of
+    /// `protocols`.
+    #[must_use]
+    pub fn with_protocols(mut self, protocols: Vec<u8>) -> Self {
+        self.protocols = Some(protocols);
+        self
+    }
+
     #[must_use]
     pub fn has_country(&self) -> bool {
         self.countries.is_some()
@@ -1443,6 +1937,72 @@ impl EventFilter {
             moderate_kinds_by(kinds, &["windows", "threat"], "windows threat");
             moderate_kinds_by(kinds, &["network", "threat"], "network threat");
             moderate_kinds_by(kinds, &["extra", "threat"], "extra threat");
+            moderate_kinds_by(
+                kinds,
+                &["tls", "certificate", "anomaly"],
+                "tls certificate anomaly",
+            );
+            moderate_kinds_by(
+                kinds,
+                &["smb", "lateral", "movement"],
+                "smb lateral movement",
+            );
+            moderate_kinds_by(
+                kinds,
+                &["dcerpc", "lateral", "movement"],
+                "dcerpc lateral movement",
+            );
+            moderate_kinds_by(kinds, &["icmp", "tunnel"], "icmp tunnel");
+        }
+    }
+}
+
+/// A boolean composition of event filters, e.g., "category C2 AND NOT
+/// internal network".
+pub enum EventFilterExpr {
+    /// Matches the events that match the filter.
+    Filter(EventFilter),
+    /// Matches the events that don't match the expression.
+    Not(Box<EventFilterExpr>),
+    /// Matches the events that match all the expressions, or all the events
+    /// if there is none.
+    And(Vec<EventFilterExpr>),
+    /// Matches the events that match any of the expressions, or no event if
+    /// there is none.
+    Or(Vec<EventFilterExpr>),
+}
+
+impl EventFilterExpr {
+    /// Returns whether `event` matches the expression.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a filter in the expression contains a country
+    /// filter but the ip2location database is not available.
+    pub fn matches(
+        &self,
+        event: &Event,
+        locator: Option<&Arc<Mutex<ip2location::DB>>>,
+    ) -> Result<bool> {
+        match self {
+            Self::Filter(filter) => Ok(event.matches(locator.cloned(), filter)?.0),
+            Self::Not(expr) => Ok(!expr.matches(event, locator)?),
+            Self::And(exprs) => {
+                for expr in exprs {
+                    if !expr.matches(event, locator)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            Self::Or(exprs) => {
+                for expr in exprs {
+                    if expr.matches(event, locator)? {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
         }
     }
 }
@@ -1472,6 +2032,58 @@ pub struct EventMessage {
     pub fields: Vec<u8>,
 }
 
+impl EventMessage {
+    /// Formats the event as a syslog message, prefixing its syslog format
+    /// with the priority given by `mappings`.
+    ///
+    /// If no mapping applies to the event, the facility is
+    /// [`DEFAULT_SYSLOG_FACILITY`](crate::DEFAULT_SYSLOG_FACILITY) and the
+    /// severity is derived from the level of the event, from 6
+    /// (informational) for the lowest level to 2 (critical) for the highest.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the event cannot be deserialized or the mappings
+    /// cannot be read.
+    ///
+    /// The fields are deserialized only if they are needed to resolve the
+    /// priority, i.e., for a custom detection or an event without a mapping.
+    /// [`Event::syslog_priority`] returns the priority of an event already
+    /// deserialized.
+    pub fn to_syslog(&self, mappings: &Table<SyslogMapping>) -> Result<String> {
+        let priority = match (self.kind.name(), self.kind.category()) {
+            (Some(name), Some(category)) => match mappings.resolve(name, category)? {
+                Some(mapping) => mapping.priority(),
+                None => default_syslog_priority(self.parse()?.as_match().level()),
+            },
+            _ => self.parse()?.syslog_priority(mappings)?,
+        };
+        Ok(format!("<{priority}>{self}"))
+    }
+
+    fn parse(&self) -> Result<Event> {
+        use anyhow::anyhow;
+        let key = i128::from(self.time.timestamp_nanos_opt().unwrap_or(i64::MAX)) << 64
+            | self
+                .kind
+                .to_i128()
+                .ok_or(anyhow!("`EventKind` exceeds i128::MAX"))?
+                << 32;
+        let (_, event) = parse_event(
+            Box::from(key.to_be_bytes()),
+            Box::from(self.fields.as_slice()),
+        )
+        .map_err(|_| anyhow!("invalid event"))?;
+        Ok(event)
+    }
+}
+
+/// Returns the syslog priority of an event at `level` without a syslog
+/// mapping.
+fn default_syslog_priority(level: NonZeroU8) -> u8 {
+    DEFAULT_SYSLOG_FACILITY * 8 + 7 - level.get().min(5)
+}
+
 impl fmt::Display for EventMessage {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{},", self.time.to_rfc3339())?;
@@ -1710,6 +2322,45 @@ impl fmt::Display for EventMessage {
                     write!(f, "invalid event")
                 }
             }
+            EventKind::TlsCertificateAnomaly => {
+                if let Ok(fields) =
+                    bincode::deserialize::<TlsCertificateAnomalyFields>(&self.fields)
+                {
+                    write!(f, "TlsCertificateAnomaly,{fields}")
+                } else {
+                    write!(f, "invalid event")
+                }
+            }
+            EventKind::SmbLateralMovement => {
+                if let Ok(fields) = bincode::deserialize::<SmbLateralMovementFields>(&self.fields) {
+                    write!(f, "SmbLateralMovement,{fields}")
+                } else {
+                    write!(f, "invalid event")
+                }
+            }
+            EventKind::DceRpcLateralMovement => {
+                if let Ok(fields) =
+                    bincode::deserialize::<DceRpcLateralMovementFields>(&self.fields)
+                {
+                    write!(f, "DceRpcLateralMovement,{fields}")
+                } else {
+                    write!(f, "invalid event")
+                }
+            }
+            EventKind::IcmpTunnel => {
+                if let Ok(fields) = bincode::deserialize::<IcmpTunnelFields>(&self.fields) {
+                    write!(f, "IcmpTunnel,{fields}")
+                } else {
+                    write!(f, "invalid event")
+                }
+            }
+            EventKind::CustomDetection => {
+                if let Ok(fields) = bincode::deserialize::<CustomDetectionFields>(&self.fields) {
+                    write!(f, "CustomDetection,{fields}")
+                } else {
+                    write!(f, "invalid event")
+                }
+            }
         }
     }
 }
@@ -1717,17 +2368,43 @@ impl fmt::Display for EventMessage {
 #[allow(clippy::module_name_repetitions)]
 pub struct EventDb<'a> {
     inner: &'a rocksdb::OptimisticTransactionDB,
+    notifier: Option<&'a Channel>,
+    max_field_len: Option<usize>,
 }
 
 impl<'a> EventDb<'a> {
     #[must_use]
     pub fn new(inner: &'a rocksdb::OptimisticTransactionDB) -> EventDb {
-        Self { inner }
+        Self {
+            inner,
+            notifier: None,
+            max_field_len: None,
+        }
+    }
+
+    /// Truncates the large string fields of HTTP events, the URI, referrer,
+    /// user agent, cookie, and the request content matched by an HTTP threat
+    /// (or the file names of a block-list HTTP event), to at most `max_len`
+    /// bytes each when they are stored, appending [`TRUNCATION_MARKER`] to
+    /// each truncated field.
+    #[must_use]
+    pub fn with_max_field_len(mut self, max_len: usize) -> Self {
+        self.max_field_len = Some(max_len);
+        self
+    }
+
+    /// Reports changes and operations made through this database to
+    /// `notifier`.
+    #[must_use]
+    pub(crate) fn with_notifier(mut self, notifier: Option<&'a Channel>) -> Self {
+        self.notifier = notifier;
+        self
     }
 
     /// Creates an iterator over key-value pairs, starting from `key`.
     #[must_use]
     pub fn iter_from(&self, key: i128, direction: Direction) -> EventIterator {
+        record(self.notifier, Operation::Scan);
         let iter = self
             .inner
             .iterator(IteratorMode::From(&key.to_be_bytes(), direction));
@@ -1737,548 +2414,2762 @@ impl<'a> EventDb<'a> {
     /// Creates an iterator over key-value pairs for the entire events.
     #[must_use]
     pub fn iter_forward(&self) -> EventIterator {
+        record(self.notifier, Operation::Scan);
         let iter = self.inner.iterator(IteratorMode::Start);
         EventIterator { inner: iter }
     }
 
-    /// Stores a new event into the database.
-    ///
-    /// # Errors
+    /// Creates an iterator over the events that occurred in `[start, end)`.
+    /// If `categories` is given, only the events in those categories are
+    /// returned.
     ///
-    /// Returns an error if a database operation fails.
-    pub fn put(&self, event: &EventMessage) -> Result<i128> {
-        use anyhow::anyhow;
-        let mut key = i128::from(event.time.timestamp_nanos_opt().unwrap_or(i64::MAX)) << 64
-            | event
-                .kind
-                .to_i128()
-                .ok_or(anyhow!("`EventKind` exceeds i128::MAX"))?
-                << 32;
-        loop {
-            let txn = self.inner.transaction();
-            if txn
+    /// The kind of an event is encoded in its key, so events in other
+    /// categories are skipped without being deserialized, except for the
+    /// kinds whose category is defined by each event.
+    #[must_use]
+    pub fn iter_range(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        categories: Option<&[EventCategory]>,
+    ) -> EventRangeIterator {
+        record(self.notifier, Operation::Scan);
+        let start = time_key_bound(start);
+        let end = time_key_bound(end);
+        let mut opts = rocksdb::ReadOptions::default();
+        opts.set_iterate_upper_bound(end.to_be_bytes());
+        let inner = self.inner.iterator_opt(
+            IteratorMode::From(&start.to_be_bytes(), Direction::Forward),
+            opts,
+        );
+        let kinds = categories.map(|categories| {
+            (0..)
+                .map_while(EventKind::from_i128)
+                .filter(|kind| match kind.category() {
+                    Some(category) => categories.contains(&category),
+                    None => true,
+                })
+                .filter_map(|kind| kind.to_i128())
+                .collect()
+        });
+        EventRangeIterator {
+            inner,
+            kinds,
+            categories: categories.map(<[EventCategory]>::to_vec),
+        }
+    }
+
+    /// Creates an iterator over the events that occurred in `[start, end)`
+    /// and match `expr`. The expression is evaluated as the events are read.
+    #[must_use]
+    pub fn iter_matching<'f>(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        expr: &'f EventFilterExpr,
+        locator: Option<Arc<Mutex<ip2location::DB>>>,
+    ) -> MatchingEventIterator<'_, 'f> {
+        MatchingEventIterator {
+            inner: self.iter_range(start, end, None),
+            expr,
+            locator,
+        }
+    }
+
+    /// Counts the events that occurred in `[start, end)` by category and by
+    /// `interval`, and returns the non-zero counts ordered by interval and
+    /// category. Intervals are aligned to UTC.
+    ///
+    /// The kind of an event is encoded in its key, so only the events whose
+    /// category is defined by each event are deserialized.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a database operation fails.
+    pub fn count_by_interval(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        interval: EventInterval,
+    ) -> Result<Vec<EventCount>> {
+        record(self.notifier, Operation::Scan);
+        let start = time_key_bound(start);
+        let end = time_key_bound(end);
+        let mut opts = rocksdb::ReadOptions::default();
+        opts.set_iterate_upper_bound(end.to_be_bytes());
+        let iter = self.inner.iterator_opt(
+            IteratorMode::From(&start.to_be_bytes(), Direction::Forward),
+            opts,
+        );
+
+        let mut counts = BTreeMap::new();
+        for item in iter {
+            let (raw_key, value) = item.context("cannot read from event database")?;
+            let Ok(key) = <[u8; 16]>::try_from(raw_key.as_ref()) else {
+                continue;
+            };
+            let key = i128::from_be_bytes(key);
+            let Some(kind) = EventKind::from_i128((key & 0xffff_ffff_0000_0000) >> 32) else {
+                continue;
+            };
+            let category = match kind.category() {
+                Some(category) => category,
+                None => match parse_event(raw_key, value) {
+                    Ok((_, event)) => event.category(),
+                    Err(_) => continue,
+                },
+            };
+            let bucket = (key >> 64).div_euclid(interval.nanos()) * interval.nanos();
+            *counts.entry((bucket, category)).or_insert(0) += 1;
+        }
+        Ok(counts
+            .into_iter()
+            .filter_map(|((bucket, category), count)| {
+                let start = Utc.timestamp_nanos(i64::try_from(bucket).ok()?);
+                Some(EventCount {
+                    start,
+                    category,
+                    count,
+                })
+            })
+            .collect())
+    }
+
+    /// Writes the events that occurred in `range` to `writer` in the JSON
+    /// Lines format, one JSON object per line, and returns the number of
+    /// written events. Events that cannot be deserialized are skipped.
+    ///
+    /// Each object has the following fields:
+    ///
+    /// * `key`: the key of the event in the database, as a decimal string
+    /// * `time`: the time the event is stored at, in RFC 3339 format
+    /// * `event_kind`: the name of the [`EventKind`]
+    /// * `category`: the name of the [`EventCategory`]
+    /// * `kind`: the kind of the detection, e.g., "DGA"
+    /// * `level`: the severity level
+    /// * `sensor`: the sensor that detected the event
+    /// * `src_addr`, `src_port`, `dst_addr`, `dst_port`, `proto`: the
+    ///   addresses, ports, and protocol number; unspecified addresses and
+    ///   ports are written as "0.0.0.0" and 0
+    /// * `confidence`: the confidence of the detection, or `null`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a database operation fails or writing to `writer`
+    /// fails.
+    pub fn export_jsonl<W: Write>(
+        &self,
+        range: Range<DateTime<Utc>>,
+        mut writer: W,
+    ) -> Result<usize> {
+        let mut exported = 0;
+        for (key, event) in self.iter_range(range.start, range.end, None).flatten() {
+            let Some(event_kind) = EventKind::from_i128((key & 0xffff_ffff_0000_0000) >> 32) else {
+                continue;
+            };
+            let fields = event.as_match();
+            let line = JsonlEvent {
+                key: key.to_string(),
+                time: Utc.timestamp_nanos((key >> 64).try_into().expect("valid i64")),
+                event_kind,
+                category: fields.category(),
+                kind: fields.kind(),
+                level: fields.level().get(),
+                sensor: fields.source(),
+                src_addr: fields.src_addr(),
+                src_port: fields.src_port(),
+                dst_addr: fields.dst_addr(),
+                dst_port: fields.dst_port(),
+                proto: fields.proto(),
+                confidence: fields.confidence(),
+            };
+            serde_json::to_writer(&mut writer, &line).context("cannot write event")?;
+            writer.write_all(b"\n").context("cannot write event")?;
+            exported += 1;
+        }
+        writer.flush().context("cannot write event")?;
+        Ok(exported)
+    }
+
+    /// Returns the events of any kind that occurred in `range` and whose
+    /// source or destination address is `addr`, in the order of their keys.
+    ///
+    /// An IPv4 address and its IPv4-mapped IPv6 address are treated as the
+    /// same address.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a database operation fails or an indexed event
+    /// cannot be deserialized.
+    pub fn find_by_addr(
+        &self,
+        addr: IpAddr,
+        range: Range<DateTime<Utc>>,
+    ) -> Result<Vec<(i128, Event)>> {
+        record(self.notifier, Operation::Scan);
+        let index = self.address_index()?;
+        let start = time_key_bound(range.start);
+        let end = time_key_bound(range.end);
+        let mut opts = rocksdb::ReadOptions::default();
+        opts.set_iterate_upper_bound(address_index_key(addr, end));
+        let iter = self.inner.iterator_cf_opt(
+            index,
+            opts,
+            IteratorMode::From(&address_index_key(addr, start), Direction::Forward),
+        );
+
+        let mut events = Vec::new();
+        for item in iter {
+            let (index_key, _) = item.context("cannot read from event address index")?;
+            let Some(key) = index_key.get(16..) else {
+                continue;
+            };
+            // An entry may outlive its event if the event was removed
+            // without going through this database.
+            let Some(value) = self
+                .inner
+                .get(key)
+                .context("cannot read from event database")?
+            else {
+                continue;
+            };
+            let event = parse_event(Box::from(key), value.into_boxed_slice())
+                .map_err(|_| anyhow::anyhow!("invalid event"))?;
+            events.push(event);
+        }
+        Ok(events)
+    }
+
+    /// Returns up to `n` events spread evenly across `range`, in the order of
+    /// their keys.
+    ///
+    /// The range is divided into `n` intervals of the same length, and the
+    /// first event in each interval is returned, so an interval without any
+    /// event contributes nothing. Only one seek is made per interval, rather
+    /// than iterating over all the events in the range. Events that cannot be
+    /// deserialized are skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a database operation fails.
+    pub fn sample(&self, range: Range<DateTime<Utc>>, n: usize) -> Result<Vec<(i128, Event)>> {
+        record(self.notifier, Operation::Scan);
+        let start = time_key_bound(range.start) >> 64;
+        let end = time_key_bound(range.end) >> 64;
+        if n == 0 || start >= end {
+            return Ok(Vec::new());
+        }
+        let span = end - start;
+        let n = i128::try_from(n).unwrap_or(i128::MAX).min(span);
+
+        let mut events = Vec::new();
+        let mut iter = self.inner.raw_iterator();
+        for i in 0..n {
+            let slot_start = (start + span * i / n) << 64;
+            let slot_end = (start + span * (i + 1) / n) << 64;
+            iter.seek(slot_start.to_be_bytes());
+            let (Some(k), Some(v)) = (iter.key(), iter.value()) else {
+                break;
+            };
+            let Ok(key) = <[u8; 16]>::try_from(k) else {
+                continue;
+            };
+            if i128::from_be_bytes(key) >= slot_end {
+                continue;
+            }
+            if let Ok(event) = parse_event(Box::from(k), Box::from(v)) {
+                events.push(event);
+            }
+        }
+        iter.status().context("cannot read from event database")?;
+        Ok(events)
+    }
+
+    /// Adds the address index entries of all the events stored in the
+    /// database, and returns the number of indexed events.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a database operation fails.
+    pub(crate) fn rebuild_address_index(&self) -> Result<usize> {
+        const BATCH_SIZE: usize = 10_000;
+
+        let index = self.address_index()?;
+        let mut batch = rocksdb::WriteBatchWithTransaction::<true>::default();
+        let mut indexed = 0;
+        for (key, event) in self.iter_forward().flatten() {
+            for addr in event_addresses(&event) {
+                batch.put_cf(index, address_index_key(addr, key), b"");
+            }
+            indexed += 1;
+            if batch.len() >= BATCH_SIZE {
+                self.inner
+                    .write(std::mem::take(&mut batch))
+                    .context("failed to write event addresses")?;
+            }
+        }
+        self.inner
+            .write(batch)
+            .context("failed to write event addresses")?;
+        Ok(indexed)
+    }
+
+    fn address_index(&self) -> Result<&'a rocksdb::ColumnFamily> {
+        self.inner
+            .cf_handle(super::tables::EVENT_ADDRESSES)
+            .context("event address index must be present")
+    }
+
+    /// Attaches the event tag `tag_id` to the event with `key`. Returns
+    /// `false` if the tag was already attached.
+    ///
+    /// The event itself isn't modified. `tag_id` should be the ID of a tag in
+    /// [`Store::event_tag_set`](crate::Store::event_tag_set).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the event doesn't exist or a database operation
+    /// fails.
+    pub fn attach_tag(&self, key: i128, tag_id: u32) -> Result<bool> {
+        let assignments = self.tag_assignments()?;
+        let (by_event, by_tag) = tag_assignment_keys(key, tag_id);
+        loop {
+            let txn = self.inner.transaction();
+            if txn
                 .get_for_update(key.to_be_bytes(), super::EXCLUSIVE)
                 .context("cannot read from event database")?
+                .is_none()
+            {
+                bail!("no such event");
+            }
+            if txn
+                .get_for_update_cf(assignments, by_event, super::EXCLUSIVE)
+                .context("cannot read event tags")?
                 .is_some()
             {
-                let start = i128::from(thread_rng().next_u32());
-                key |= start;
-                #[allow(clippy::cast_possible_wrap)] // bit pattern
-                while txn
-                    .get_for_update(key.to_be_bytes(), super::EXCLUSIVE)
-                    .context("cannot read from event database")?
-                    .is_some()
-                {
-                    let next = (key + 1) & 0xffff_ffff;
-                    if next == start {
-                        bail!("too many events with the same timestamp");
-                    }
-                    key = key & 0xffff_ffff_ffff_ffff_ffff_ffff_0000_0000_u128 as i128 | next;
-                }
+                return Ok(false);
             }
-            txn.put(key.to_be_bytes(), event.fields.as_slice())
-                .context("cannot write event")?;
+            txn.put_cf(assignments, by_event, b"")
+                .context("cannot write event tag")?;
+            txn.put_cf(assignments, by_tag, b"")
+                .context("cannot write event tag")?;
             match txn.commit() {
                 Ok(()) => break,
                 Err(e) => {
                     if !e.as_ref().starts_with("Resource busy:") {
-                        return Err(e).context("failed to store event");
+                        return Err(e).context("failed to attach event tag");
                     }
                 }
             }
         }
-        Ok(key)
+        Ok(true)
     }
 
-    /// Updates an old key-value pair to a new one.
+    /// Detaches the event tag `tag_id` from the event with `key`. Returns
+    /// `false` if the tag wasn't attached.
     ///
     /// # Errors
     ///
-    /// Returns an error if the old value does not match the value in the database, the old key does
-    /// not exist, or the database operation fails.
-    pub fn update(&self, old: (&[u8], &[u8]), new: (&[u8], &[u8])) -> Result<()> {
+    /// Returns an error if a database operation fails.
+    pub fn detach_tag(&self, key: i128, tag_id: u32) -> Result<bool> {
+        let assignments = self.tag_assignments()?;
+        let (by_event, by_tag) = tag_assignment_keys(key, tag_id);
         loop {
             let txn = self.inner.transaction();
-            if let Some(old_value) = txn
-                .get_for_update(old.0, super::EXCLUSIVE)
-                .context("cannot read old entry")?
+            if txn
+                .get_for_update_cf(assignments, by_event, super::EXCLUSIVE)
+                .context("cannot read event tags")?
+                .is_none()
             {
-                if old.1 != old_value.as_slice() {
-                    bail!("old value mismatch");
-                }
-            } else {
-                bail!("no such entry");
-            };
-
-            txn.put(new.0, new.1).context("failed to write new entry")?;
-            if old.0 != new.0 {
-                txn.delete(old.0).context("failed to delete old entry")?;
+                return Ok(false);
             }
-
+            txn.delete_cf(assignments, by_event)
+                .context("cannot delete event tag")?;
+            txn.delete_cf(assignments, by_tag)
+                .context("cannot delete event tag")?;
             match txn.commit() {
                 Ok(()) => break,
                 Err(e) => {
                     if !e.as_ref().starts_with("Resource busy:") {
-                        return Err(e).context("failed to update entry");
+                        return Err(e).context("failed to detach event tag");
                     }
                 }
             }
         }
-        Ok(())
+        Ok(true)
     }
-}
 
-#[allow(clippy::module_name_repetitions)]
-pub struct EventIterator<'i> {
-    inner: rocksdb::DBIteratorWithThreadMode<
-        'i,
-        rocksdb::OptimisticTransactionDB<rocksdb::SingleThreaded>,
-    >,
-}
+    /// Returns the IDs of the event tags attached to the event with `key`,
+    /// in ascending order.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a database operation fails.
+    pub fn tag_ids(&self, key: i128) -> Result<Vec<u32>> {
+        let mut prefix = vec![TAGS_OF_EVENT];
+        prefix.extend(key.to_be_bytes());
+        Ok(self
+            .tag_assignment_suffixes(&prefix)?
+            .into_iter()
+            .filter_map(|id| Some(u32::from_be_bytes(id.as_ref().try_into().ok()?)))
+            .collect())
+    }
 
-impl<'i> Iterator for EventIterator<'i> {
-    type Item = Result<(i128, Event), InvalidEvent>;
+    /// Returns the events to which the event tag `tag_id` is attached, in the
+    /// order of their keys.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a database operation fails or a tagged event
+    /// cannot be deserialized.
+    pub fn find_by_tag(&self, tag_id: u32) -> Result<Vec<(i128, Event)>> {
+        record(self.notifier, Operation::Scan);
+        let mut prefix = vec![EVENTS_WITH_TAG];
+        prefix.extend(tag_id.to_be_bytes());
+        let mut events = Vec::new();
+        for key in self.tag_assignment_suffixes(&prefix)? {
+            let Some(value) = self
+                .inner
+                .get(&key)
+                .context("cannot read from event database")?
+            else {
+                continue;
+            };
+            let event = parse_event(key, value.into_boxed_slice())
+                .map_err(|_| anyhow::anyhow!("invalid event"))?;
+            events.push(event);
+        }
+        Ok(events)
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let (k, v) = self.inner.next().transpose().ok().flatten()?;
+    /// Detaches the event tag `tag_id` from all the events.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a database operation fails.
+    pub(crate) fn remove_tag(&self, tag_id: u32) -> Result<()> {
+        let assignments = self.tag_assignments()?;
+        let mut prefix = vec![EVENTS_WITH_TAG];
+        prefix.extend(tag_id.to_be_bytes());
+        let mut batch = rocksdb::WriteBatchWithTransaction::<true>::default();
+        for key in self.tag_assignment_suffixes(&prefix)? {
+            let Ok(key) = <[u8; 16]>::try_from(key.as_ref()) else {
+                continue;
+            };
+            let (by_event, by_tag) = tag_assignment_keys(i128::from_be_bytes(key), tag_id);
+            batch.delete_cf(assignments, by_event);
+            batch.delete_cf(assignments, by_tag);
+        }
+        self.inner
+            .write(batch)
+            .context("failed to remove event tag")
+    }
 
-        let key: [u8; 16] = if let Ok(key) = k.as_ref().try_into() {
-            key
-        } else {
-            return Some(Err(InvalidEvent::Key(k)));
-        };
-        let key = i128::from_be_bytes(key);
-        let time = Utc.timestamp_nanos((key >> 64).try_into().expect("valid i64"));
-        let kind_num = (key & 0xffff_ffff_0000_0000) >> 32;
-        let Some(kind) = EventKind::from_i128(kind_num) else {
-            return Some(Err(InvalidEvent::Key(k)));
-        };
-        match kind {
-            EventKind::DnsCovertChannel => {
-                let Ok(fields) = bincode::deserialize::<DnsEventFields>(v.as_ref()) else {
-                    return Some(Err(InvalidEvent::Value(v)));
-                };
-                Some(Ok((
-                    key,
-                    Event::DnsCovertChannel(DnsCovertChannel::new(time, fields)),
-                )))
-            }
-            EventKind::HttpThreat => {
-                let Ok(fields) = bincode::deserialize::<HttpThreatFields>(v.as_ref()) else {
-                    return Some(Err(InvalidEvent::Value(v)));
-                };
-                Some(Ok((
-                    key,
-                    Event::HttpThreat(HttpThreat::new(fields.time, fields)),
-                )))
-            }
-            EventKind::RdpBruteForce => {
-                let Ok(fields) = bincode::deserialize::<RdpBruteForceFields>(v.as_ref()) else {
-                    return Some(Err(InvalidEvent::Value(v)));
-                };
-                Some(Ok((
-                    key,
-                    Event::RdpBruteForce(RdpBruteForce::new(time, &fields)),
-                )))
-            }
-            EventKind::RepeatedHttpSessions => {
-                let Ok(fields) = bincode::deserialize::<RepeatedHttpSessionsFields>(v.as_ref())
-                else {
-                    return Some(Err(InvalidEvent::Value(v)));
-                };
-                Some(Ok((
-                    key,
-                    Event::RepeatedHttpSessions(RepeatedHttpSessions::new(time, &fields)),
-                )))
-            }
-            EventKind::TorConnection => {
-                let Ok(fields) = bincode::deserialize::<TorConnectionFields>(v.as_ref()) else {
-                    return Some(Err(InvalidEvent::Value(v)));
-                };
-                Some(Ok((
-                    key,
-                    Event::TorConnection(TorConnection::new(time, &fields)),
-                )))
-            }
-            EventKind::DomainGenerationAlgorithm => {
-                let Ok(fields) = bincode::deserialize::<DgaFields>(v.as_ref()) else {
-                    return Some(Err(InvalidEvent::Value(v)));
-                };
-                Some(Ok((
-                    key,
-                    Event::DomainGenerationAlgorithm(DomainGenerationAlgorithm::new(time, fields)),
-                )))
-            }
-            EventKind::FtpBruteForce => {
-                let Ok(fields) = bincode::deserialize::<FtpBruteForceFields>(v.as_ref()) else {
-                    return Some(Err(InvalidEvent::Value(v)));
-                };
-                Some(Ok((
-                    key,
-                    Event::FtpBruteForce(FtpBruteForce::new(time, &fields)),
-                )))
-            }
-            EventKind::FtpPlainText => {
-                let Ok(fields) = bincode::deserialize::<FtpPlainTextFields>(v.as_ref()) else {
-                    return Some(Err(InvalidEvent::Value(v)));
-                };
-                Some(Ok((
-                    key,
-                    Event::FtpPlainText(FtpPlainText::new(time, fields)),
-                )))
-            }
-            EventKind::PortScan => {
-                let Ok(fields) = bincode::deserialize::<PortScanFields>(v.as_ref()) else {
-                    return Some(Err(InvalidEvent::Value(v)));
-                };
-                Some(Ok((key, Event::PortScan(PortScan::new(time, &fields)))))
-            }
-            EventKind::MultiHostPortScan => {
-                let Ok(fields) = bincode::deserialize::<MultiHostPortScanFields>(v.as_ref()) else {
-                    return Some(Err(InvalidEvent::Value(v)));
-                };
-                Some(Ok((
-                    key,
-                    Event::MultiHostPortScan(MultiHostPortScan::new(time, &fields)),
-                )))
-            }
-            EventKind::NonBrowser => {
-                let Ok(fields) = bincode::deserialize::<NonBrowserFields>(v.as_ref()) else {
-                    return Some(Err(InvalidEvent::Value(v)));
-                };
-                Some(Ok((key, Event::NonBrowser(NonBrowser::new(time, &fields)))))
-            }
-            EventKind::LdapBruteForce => {
-                let Ok(fields) = bincode::deserialize::<LdapBruteForceFields>(v.as_ref()) else {
-                    return Some(Err(InvalidEvent::Value(v)));
-                };
-                Some(Ok((
-                    key,
-                    Event::LdapBruteForce(LdapBruteForce::new(time, &fields)),
-                )))
-            }
-            EventKind::LdapPlainText => {
-                let Ok(fields) = bincode::deserialize::<LdapPlainTextFields>(v.as_ref()) else {
-                    return Some(Err(InvalidEvent::Value(v)));
-                };
-                Some(Ok((
-                    key,
-                    Event::LdapPlainText(LdapPlainText::new(time, fields)),
-                )))
-            }
-            EventKind::ExternalDdos => {
-                let Ok(fields) = bincode::deserialize::<ExternalDdosFields>(v.as_ref()) else {
-                    return Some(Err(InvalidEvent::Value(v)));
-                };
-                Some(Ok((
-                    key,
-                    Event::ExternalDdos(ExternalDdos::new(time, &fields)),
-                )))
-            }
-            EventKind::CryptocurrencyMiningPool => {
-                let Ok(fields) = bincode::deserialize::<CryptocurrencyMiningPoolFields>(v.as_ref())
-                else {
-                    return Some(Err(InvalidEvent::Value(v)));
-                };
-                Some(Ok((
-                    key,
-                    Event::CryptocurrencyMiningPool(CryptocurrencyMiningPool::new(time, fields)),
-                )))
-            }
-            EventKind::BlockListConn => {
-                let Ok(fields) = bincode::deserialize::<BlockListConnFields>(v.as_ref()) else {
-                    return Some(Err(InvalidEvent::Value(v)));
-                };
-                Some(Ok((
-                    key,
-                    Event::BlockList(RecordType::Conn(BlockListConn::new(time, fields))),
-                )))
-            }
-            EventKind::BlockListDns => {
-                let Ok(fields) = bincode::deserialize::<BlockListDnsFields>(v.as_ref()) else {
-                    return Some(Err(InvalidEvent::Value(v)));
-                };
-                Some(Ok((
-                    key,
-                    Event::BlockList(RecordType::Dns(BlockListDns::new(time, fields))),
-                )))
-            }
-            EventKind::BlockListDceRpc => {
-                let Ok(fields) = bincode::deserialize::<BlockListDceRpcFields>(v.as_ref()) else {
-                    return Some(Err(InvalidEvent::Value(v)));
-                };
-                Some(Ok((
-                    key,
-                    Event::BlockList(RecordType::DceRpc(BlockListDceRpc::new(time, fields))),
-                )))
-            }
-            EventKind::BlockListFtp => {
-                let Ok(fields) = bincode::deserialize::<BlockListFtpFields>(v.as_ref()) else {
-                    return Some(Err(InvalidEvent::Value(v)));
-                };
-                Some(Ok((
-                    key,
-                    Event::BlockList(RecordType::Ftp(BlockListFtp::new(time, fields))),
-                )))
-            }
-            EventKind::BlockListHttp => {
-                let Ok(fields) = bincode::deserialize::<BlockListHttpFields>(v.as_ref()) else {
-                    return Some(Err(InvalidEvent::Value(v)));
-                };
-                Some(Ok((
-                    key,
-                    Event::BlockList(RecordType::Http(BlockListHttp::new(time, fields))),
-                )))
+    /// Adds the deletion of the tags attached to the event with `key` to
+    /// `batch`.
+    fn delete_tags(
+        &self,
+        batch: &mut rocksdb::WriteBatchWithTransaction<true>,
+        key: i128,
+    ) -> Result<()> {
+        let assignments = self.tag_assignments()?;
+        for tag_id in self.tag_ids(key)? {
+            let (by_event, by_tag) = tag_assignment_keys(key, tag_id);
+            batch.delete_cf(assignments, by_event);
+            batch.delete_cf(assignments, by_tag);
+        }
+        Ok(())
+    }
+
+    /// Returns the remainders of the tag assignment keys starting with
+    /// `prefix`.
+    fn tag_assignment_suffixes(&self, prefix: &[u8]) -> Result<Vec<Box<[u8]>>> {
+        let assignments = self.tag_assignments()?;
+        let iter = self
+            .inner
+            .iterator_cf(assignments, IteratorMode::From(prefix, Direction::Forward));
+        let mut suffixes = Vec::new();
+        for item in iter {
+            let (key, _) = item.context("cannot read event tags")?;
+            let Some(suffix) = key.strip_prefix(prefix) else {
+                break;
+            };
+            suffixes.push(Box::from(suffix));
+        }
+        Ok(suffixes)
+    }
+
+    fn tag_assignments(&self) -> Result<&'a rocksdb::ColumnFamily> {
+        self.inner
+            .cf_handle(super::tables::EVENT_TAG_ASSIGNMENTS)
+            .context("event tag assignments must be present")
+    }
+
+    /// Attaches `disposition` to the event with `key`, replacing the
+    /// disposition previously attached to it, if any.
+    ///
+    /// The event itself isn't modified.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the event doesn't exist or a database operation
+    /// fails.
+    pub fn set_disposition(&self, key: i128, disposition: &EventDisposition) -> Result<()> {
+        let dispositions = self.dispositions()?;
+        let value = bincode::serialize(disposition)?;
+        loop {
+            let txn = self.inner.transaction();
+            if txn
+                .get_for_update(key.to_be_bytes(), super::EXCLUSIVE)
+                .context("cannot read from event database")?
+                .is_none()
+            {
+                bail!("no such event");
             }
-            EventKind::BlockListKerberos => {
-                let Ok(fields) = bincode::deserialize::<BlockListKerberosFields>(v.as_ref()) else {
-                    return Some(Err(InvalidEvent::Value(v)));
-                };
-                Some(Ok((
-                    key,
-                    Event::BlockList(RecordType::Kerberos(BlockListKerberos::new(time, fields))),
-                )))
+            txn.put_cf(dispositions, key.to_be_bytes(), &value)
+                .context("cannot write event disposition")?;
+            match txn.commit() {
+                Ok(()) => break,
+                Err(e) => {
+                    if !e.as_ref().starts_with("Resource busy:") {
+                        return Err(e).context("failed to set event disposition");
+                    }
+                }
             }
-            EventKind::BlockListLdap => {
-                let Ok(fields) = bincode::deserialize::<BlockListLdapFields>(v.as_ref()) else {
-                    return Some(Err(InvalidEvent::Value(v)));
-                };
-                Some(Ok((
-                    key,
-                    Event::BlockList(RecordType::Ldap(BlockListLdap::new(time, fields))),
-                )))
+        }
+        Ok(())
+    }
+
+    /// Returns the disposition attached to the event with `key`, if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a database operation fails or the disposition
+    /// cannot be deserialized.
+    pub fn disposition(&self, key: i128) -> Result<Option<EventDisposition>> {
+        let dispositions = self.dispositions()?;
+        let Some(value) = self
+            .inner
+            .get_cf(dispositions, key.to_be_bytes())
+            .context("cannot read event disposition")?
+        else {
+            return Ok(None);
+        };
+        Ok(Some(bincode::deserialize(&value)?))
+    }
+
+    /// Removes the disposition attached to the event with `key`. Returns
+    /// `false` if there was none.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a database operation fails.
+    pub fn clear_disposition(&self, key: i128) -> Result<bool> {
+        let dispositions = self.dispositions()?;
+        loop {
+            let txn = self.inner.transaction();
+            if txn
+                .get_for_update_cf(dispositions, key.to_be_bytes(), super::EXCLUSIVE)
+                .context("cannot read event disposition")?
+                .is_none()
+            {
+                return Ok(false);
             }
-            EventKind::BlockListMqtt => {
-                let Ok(fields) = bincode::deserialize::<BlockListMqttFields>(v.as_ref()) else {
-                    return Some(Err(InvalidEvent::Value(v)));
-                };
-                Some(Ok((
-                    key,
-                    Event::BlockList(RecordType::Mqtt(BlockListMqtt::new(time, fields))),
-                )))
+            txn.delete_cf(dispositions, key.to_be_bytes())
+                .context("cannot delete event disposition")?;
+            match txn.commit() {
+                Ok(()) => break,
+                Err(e) => {
+                    if !e.as_ref().starts_with("Resource busy:") {
+                        return Err(e).context("failed to clear event disposition");
+                    }
+                }
             }
-            EventKind::BlockListNfs => {
-                let Ok(fields) = bincode::deserialize::<BlockListNfsFields>(v.as_ref()) else {
-                    return Some(Err(InvalidEvent::Value(v)));
-                };
-                Some(Ok((
-                    key,
-                    Event::BlockList(RecordType::Nfs(BlockListNfs::new(time, fields))),
-                )))
+        }
+        Ok(true)
+    }
+
+    /// Returns the keys of the events that occurred in `range` with
+    /// `disposition` attached, along with their comments, in the order of
+    /// their keys.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a database operation fails or a disposition
+    /// cannot be deserialized.
+    pub fn find_by_disposition(
+        &self,
+        disposition: Disposition,
+        range: Range<DateTime<Utc>>,
+    ) -> Result<Vec<(i128, Option<String>)>> {
+        record(self.notifier, Operation::Scan);
+        let dispositions = self.dispositions()?;
+        let start = time_key_bound(range.start);
+        let end = time_key_bound(range.end);
+        let mut opts = rocksdb::ReadOptions::default();
+        opts.set_iterate_upper_bound(end.to_be_bytes());
+        let iter = self.inner.iterator_cf_opt(
+            dispositions,
+            opts,
+            IteratorMode::From(&start.to_be_bytes(), Direction::Forward),
+        );
+        let mut found = Vec::new();
+        for item in iter {
+            let (key, value) = item.context("cannot read event disposition")?;
+            let Ok(key) = <[u8; 16]>::try_from(key.as_ref()) else {
+                continue;
+            };
+            let value: EventDisposition = bincode::deserialize(&value)?;
+            if value.disposition == disposition {
+                found.push((i128::from_be_bytes(key), value.comment));
             }
-            EventKind::BlockListNtlm => {
-                let Ok(fields) = bincode::deserialize::<BlockListNtlmFields>(v.as_ref()) else {
-                    return Some(Err(InvalidEvent::Value(v)));
-                };
-                Some(Ok((
-                    key,
-                    Event::BlockList(RecordType::Ntlm(BlockListNtlm::new(time, fields))),
-                )))
+        }
+        Ok(found)
+    }
+
+    fn dispositions(&self) -> Result<&'a rocksdb::ColumnFamily> {
+        self.inner
+            .cf_handle(super::tables::EVENT_DISPOSITIONS)
+            .context("event dispositions must be present")
+    }
+
+    /// Returns the number of stored events and the time of the latest one
+    /// for each sensor, in the order of the sensor names.
+    ///
+    /// A sensor whose events have all been deleted is still listed, with a
+    /// count of zero, so that a sensor that stopped reporting can be
+    /// detected.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a database operation fails or the statistics
+    /// cannot be deserialized.
+    pub fn sensor_stats(&self) -> Result<Vec<SensorStats>> {
+        let cf = self.sensor_stats_cf()?;
+        let mut stats = Vec::new();
+        for item in self.inner.iterator_cf(cf, IteratorMode::Start) {
+            let (sensor, value) = item.context("cannot read sensor statistics")?;
+            let value: StoredSensorStats = bincode::deserialize(&value)?;
+            if value.last_seen == i64::MIN {
+                // Only the removal of events not counted has been recorded.
+                continue;
+            }
+            let sensor = String::from_utf8(sensor.into_vec()).context("invalid sensor name")?;
+            stats.push(SensorStats {
+                sensor,
+                count: u64::try_from(value.count).unwrap_or_default(),
+                last_seen: Utc.timestamp_nanos(value.last_seen),
+            });
+        }
+        Ok(stats)
+    }
+
+    /// Recomputes the statistics of all the sensors from the events stored
+    /// in the database, and returns the number of sensors.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a database operation fails.
+    pub(crate) fn rebuild_sensor_stats(&self) -> Result<usize> {
+        let cf = self.sensor_stats_cf()?;
+        let mut deltas = HashMap::new();
+        for (key, event) in self.iter_forward().flatten() {
+            count_sensor(&mut deltas, &event, key, true);
+        }
+        let mut batch = rocksdb::WriteBatchWithTransaction::<true>::default();
+        for item in self.inner.iterator_cf(cf, IteratorMode::Start) {
+            let (sensor, _) = item.context("cannot read sensor statistics")?;
+            batch.delete_cf(cf, sensor);
+        }
+        for (sensor, delta) in &deltas {
+            let value = StoredSensorStats {
+                count: delta.count,
+                last_seen: delta.last_seen.unwrap_or_default(),
+            };
+            batch.put_cf(cf, sensor, bincode::serialize(&value)?);
+        }
+        self.inner
+            .write(batch)
+            .context("failed to write sensor statistics")?;
+        Ok(deltas.len())
+    }
+
+    /// Adds the merges that apply `deltas` to the sensor statistics to `txn`.
+    /// Merges don't read the statistics, so concurrent transactions don't
+    /// conflict on them.
+    fn stage_sensor_stats(
+        &self,
+        txn: &rocksdb::Transaction<'_, rocksdb::OptimisticTransactionDB<rocksdb::SingleThreaded>>,
+        deltas: &HashMap<String, SensorStatsDelta>,
+    ) -> Result<()> {
+        let cf = self.sensor_stats_cf()?;
+        for (sensor, operand) in sensor_stats_operands(deltas)? {
+            txn.merge_cf(cf, sensor, operand)
+                .context("cannot write sensor statistics")?;
+        }
+        Ok(())
+    }
+
+    /// Adds the merges that apply `deltas` to the sensor statistics to
+    /// `batch`, so that they are written along with the events.
+    fn batch_sensor_stats(
+        &self,
+        batch: &mut rocksdb::WriteBatchWithTransaction<true>,
+        deltas: &HashMap<String, SensorStatsDelta>,
+    ) -> Result<()> {
+        let cf = self.sensor_stats_cf()?;
+        for (sensor, operand) in sensor_stats_operands(deltas)? {
+            batch.merge_cf(cf, sensor, operand);
+        }
+        Ok(())
+    }
+
+    fn sensor_stats_cf(&self) -> Result<&'a rocksdb::ColumnFamily> {
+        self.inner
+            .cf_handle(super::tables::EVENT_SENSOR_STATS)
+            .context("event sensor statistics must be present")
+    }
+
+    /// Stores a new event into the database.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a database operation fails.
+    pub fn put(&self, event: &EventMessage) -> Result<i128> {
+        use anyhow::anyhow;
+        let base_key = i128::from(event.time.timestamp_nanos_opt().unwrap_or(i64::MAX)) << 64
+            | event
+                .kind
+                .to_i128()
+                .ok_or(anyhow!("`EventKind` exceeds i128::MAX"))?
+                << 32;
+        let truncated = self
+            .max_field_len
+            .and_then(|max_len| truncate_fields(event, max_len));
+        let fields = truncated.as_deref().unwrap_or(&event.fields);
+        let parsed = parse_event(Box::from(base_key.to_be_bytes()), Box::from(fields)).ok();
+        let addresses = parsed
+            .as_ref()
+            .map(|(_, event)| event_addresses(event))
+            .unwrap_or_default();
+        let mut deltas = HashMap::new();
+        if let Some((k, event)) = &parsed {
+            count_sensor(&mut deltas, event, *k, true);
+        }
+        let index = self.address_index()?;
+        let key = loop {
+            let txn = self.inner.transaction();
+            let key = reserve_key(&txn, base_key)?;
+            txn.put(key.to_be_bytes(), fields)
+                .context("cannot write event")?;
+            for addr in &addresses {
+                txn.put_cf(index, address_index_key(*addr, key), b"")
+                    .context("cannot write event address")?;
             }
-            EventKind::BlockListRdp => {
-                let Ok(fields) = bincode::deserialize::<BlockListRdpFields>(v.as_ref()) else {
-                    return Some(Err(InvalidEvent::Value(v)));
-                };
-                Some(Ok((
-                    key,
-                    Event::BlockList(RecordType::Rdp(BlockListRdp::new(time, fields))),
-                )))
+            self.stage_sensor_stats(&txn, &deltas)?;
+            match txn.commit() {
+                Ok(()) => break key,
+                Err(e) => {
+                    if !e.as_ref().starts_with("Resource busy:") {
+                        return Err(e).context("failed to store event");
+                    }
+                }
             }
-            EventKind::BlockListSmb => {
-                let Ok(fields) = bincode::deserialize::<BlockListSmbFields>(v.as_ref()) else {
-                    return Some(Err(InvalidEvent::Value(v)));
-                };
-                Some(Ok((
-                    key,
-                    Event::BlockList(RecordType::Smb(BlockListSmb::new(time, fields))),
-                )))
+        };
+        notify(self.notifier, ChangeKind::Insert, &key.to_be_bytes());
+        Ok(key)
+    }
+
+    /// Stores new events into the database in a single transaction, and
+    /// returns their keys in the same order as `events`.
+    ///
+    /// If `sync` is `true`, the write is flushed to storage before returning.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a database operation fails.
+    pub fn put_many(&self, events: &[EventMessage], sync: bool) -> Result<Vec<i128>> {
+        use anyhow::anyhow;
+        let index = self.address_index()?;
+        let mut staged = Vec::with_capacity(events.len());
+        for event in events {
+            let key = i128::from(event.time.timestamp_nanos_opt().unwrap_or(i64::MAX)) << 64
+                | event
+                    .kind
+                    .to_i128()
+                    .ok_or(anyhow!("`EventKind` exceeds i128::MAX"))?
+                    << 32;
+            let truncated = self
+                .max_field_len
+                .and_then(|max_len| truncate_fields(event, max_len));
+            staged.push((key, truncated));
+        }
+
+        let mut opts = rocksdb::WriteOptions::default();
+        opts.set_sync(sync);
+        let keys = loop {
+            let txn = self
+                .inner
+                .transaction_opt(&opts, &rocksdb::OptimisticTransactionOptions::default());
+            let mut keys = Vec::with_capacity(events.len());
+            let mut deltas = HashMap::new();
+            for (event, (key, truncated)) in events.iter().zip(&staged) {
+                // The keys written earlier in the transaction are visible to
+                // it, so events with the same time and kind get distinct keys.
+                let key = reserve_key(&txn, *key)?;
+                let fields = truncated.as_deref().unwrap_or(&event.fields);
+                txn.put(key.to_be_bytes(), fields)
+                    .context("cannot write event")?;
+                if let Ok((_, event)) = parse_event(Box::from(key.to_be_bytes()), Box::from(fields))
+                {
+                    for addr in event_addresses(&event) {
+                        txn.put_cf(index, address_index_key(addr, key), b"")
+                            .context("cannot write event address")?;
+                    }
+                    count_sensor(&mut deltas, &event, key, true);
+                }
+                keys.push(key);
             }
-            EventKind::BlockListSmtp => {
-                let Ok(fields) = bincode::deserialize::<BlockListSmtpFields>(v.as_ref()) else {
-                    return Some(Err(InvalidEvent::Value(v)));
-                };
-                Some(Ok((
-                    key,
-                    Event::BlockList(RecordType::Smtp(BlockListSmtp::new(time, fields))),
-                )))
+            self.stage_sensor_stats(&txn, &deltas)?;
+            match txn.commit() {
+                Ok(()) => break keys,
+                Err(e) => {
+                    if !e.as_ref().starts_with("Resource busy:") {
+                        return Err(e).context("failed to store events");
+                    }
+                }
             }
-            EventKind::BlockListSsh => {
-                let Ok(fields) = bincode::deserialize::<BlockListSshFields>(v.as_ref()) else {
-                    return Some(Err(InvalidEvent::Value(v)));
-                };
-                Some(Ok((
-                    key,
-                    Event::BlockList(RecordType::Ssh(BlockListSsh::new(time, fields))),
-                )))
+        };
+        for key in &keys {
+            notify(self.notifier, ChangeKind::Insert, &key.to_be_bytes());
+        }
+        Ok(keys)
+    }
+
+    /// Updates an old key-value pair to a new one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the old value does not match the value in the database, the old key does
+    /// not exist, or the database operation fails.
+    pub fn update(&self, old: (&[u8], &[u8]), new: (&[u8], &[u8])) -> Result<()> {
+        let index = self.address_index()?;
+        let old_index_keys = address_index_keys(old.0, old.1);
+        let new_index_keys = address_index_keys(new.0, new.1);
+        let mut deltas = HashMap::new();
+        if let Ok((key, event)) = parse_event(Box::from(old.0), Box::from(old.1)) {
+            count_sensor(&mut deltas, &event, key, false);
+        }
+        if let Ok((key, event)) = parse_event(Box::from(new.0), Box::from(new.1)) {
+            count_sensor(&mut deltas, &event, key, true);
+        }
+        let assignments = self.tag_assignments()?;
+        let moved_tags = match (<[u8; 16]>::try_from(old.0), <[u8; 16]>::try_from(new.0)) {
+            (Ok(old_key), Ok(new_key)) if old_key != new_key => {
+                let (old_key, new_key) =
+                    (i128::from_be_bytes(old_key), i128::from_be_bytes(new_key));
+                self.tag_ids(old_key)?
+                    .into_iter()
+                    .map(|tag_id| {
+                        (
+                            tag_assignment_keys(old_key, tag_id),
+                            tag_assignment_keys(new_key, tag_id),
+                        )
+                    })
+                    .collect()
+            }
+            _ => Vec::new(),
+        };
+        let dispositions = self.dispositions()?;
+        loop {
+            let txn = self.inner.transaction();
+            if let Some(old_value) = txn
+                .get_for_update(old.0, super::EXCLUSIVE)
+                .context("cannot read old entry")?
+            {
+                if old.1 != old_value.as_slice() {
+                    bail!("old value mismatch");
+                }
+            } else {
+                bail!("no such entry");
+            };
+
+            txn.put(new.0, new.1).context("failed to write new entry")?;
+            if old.0 != new.0 {
+                txn.delete(old.0).context("failed to delete old entry")?;
+                if let Some(disposition) = txn
+                    .get_for_update_cf(dispositions, old.0, super::EXCLUSIVE)
+                    .context("cannot read old event disposition")?
+                {
+                    txn.delete_cf(dispositions, old.0)
+                        .context("failed to delete old event disposition")?;
+                    txn.put_cf(dispositions, new.0, disposition)
+                        .context("failed to write new event disposition")?;
+                }
             }
-            EventKind::BlockListTls => {
-                let Ok(fields) = bincode::deserialize::<BlockListTlsFields>(v.as_ref()) else {
-                    return Some(Err(InvalidEvent::Value(v)));
-                };
-                Some(Ok((
-                    key,
-                    Event::BlockList(RecordType::Tls(BlockListTls::new(time, fields))),
-                )))
+            for key in old_index_keys
+                .iter()
+                .filter(|k| !new_index_keys.contains(k))
+            {
+                txn.delete_cf(index, key)
+                    .context("failed to delete old event address")?;
+            }
+            for key in &new_index_keys {
+                txn.put_cf(index, key, b"")
+                    .context("failed to write new event address")?;
+            }
+            for ((old_by_event, old_by_tag), (new_by_event, new_by_tag)) in &moved_tags {
+                txn.delete_cf(assignments, old_by_event)
+                    .context("failed to delete old event tag")?;
+                txn.delete_cf(assignments, old_by_tag)
+                    .context("failed to delete old event tag")?;
+                txn.put_cf(assignments, new_by_event, b"")
+                    .context("failed to write new event tag")?;
+                txn.put_cf(assignments, new_by_tag, b"")
+                    .context("failed to write new event tag")?;
+            }
+            self.stage_sensor_stats(&txn, &deltas)?;
+
+            match txn.commit() {
+                Ok(()) => break,
+                Err(e) => {
+                    if !e.as_ref().starts_with("Resource busy:") {
+                        return Err(e).context("failed to update entry");
+                    }
+                }
             }
-            EventKind::WindowsThreat => {
-                let Ok(fields) = bincode::deserialize::<WindowsThreat>(v.as_ref()) else {
-                    return Some(Err(InvalidEvent::Value(v)));
-                };
-                Some(Ok((key, Event::WindowsThreat(fields))))
+        }
+        if old.0 != new.0 {
+            notify(self.notifier, ChangeKind::Remove, old.0);
+        }
+        notify(self.notifier, ChangeKind::Update, new.0);
+        Ok(())
+    }
+
+    /// Deletes the events that occurred in `[start, end)` and returns the
+    /// number of deleted events. If `kinds` is not empty, only the events of
+    /// the given kinds are deleted.
+    ///
+    /// The deletions are written in batches rather than in a transaction per
+    /// event. RocksDB's range deletion isn't available for an optimistic
+    /// transaction database, so each key in the range is deleted separately.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a database operation fails.
+    pub fn delete_range(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        kinds: &[EventKind],
+    ) -> Result<usize> {
+        const BATCH_SIZE: usize = 10_000;
+
+        let index = self.address_index()?;
+        let dispositions = self.dispositions()?;
+        let start = time_key_bound(start);
+        let end = time_key_bound(end);
+        if start >= end {
+            return Ok(0);
+        }
+        let kinds = kinds
+            .iter()
+            .filter_map(ToPrimitive::to_i128)
+            .collect::<Vec<_>>();
+
+        let mut deleted = Vec::new();
+        let mut deltas = HashMap::new();
+        let mut batch = rocksdb::WriteBatchWithTransaction::<true>::default();
+        let iter = self
+            .inner
+            .iterator(IteratorMode::From(&start.to_be_bytes(), Direction::Forward));
+        for item in iter {
+            let (raw_key, value) = item.context("cannot read from event database")?;
+            let Ok(key) = <[u8; 16]>::try_from(raw_key.as_ref()) else {
+                continue;
+            };
+            let key = i128::from_be_bytes(key);
+            if key >= end {
+                break;
             }
-            EventKind::NetworkThreat => {
-                let Ok(fields) = bincode::deserialize::<NetworkThreat>(v.as_ref()) else {
-                    return Some(Err(InvalidEvent::Value(v)));
-                };
-                Some(Ok((key, Event::NetworkThreat(fields))))
+            if key < start {
+                continue;
             }
-            EventKind::ExtraThreat => {
-                let Ok(fields) = bincode::deserialize::<ExtraThreat>(v.as_ref()) else {
-                    return Some(Err(InvalidEvent::Value(v)));
-                };
-                Some(Ok((key, Event::ExtraThreat(fields))))
+            if !kinds.is_empty() && !kinds.contains(&((key & 0xffff_ffff_0000_0000) >> 32)) {
+                continue;
+            }
+            if let Ok((_, event)) = parse_event(raw_key, value) {
+                for addr in event_addresses(&event) {
+                    batch.delete_cf(index, address_index_key(addr, key));
+                }
+                count_sensor(&mut deltas, &event, key, false);
+            }
+            self.delete_tags(&mut batch, key)?;
+            batch.delete_cf(dispositions, key.to_be_bytes());
+            batch.delete(key.to_be_bytes());
+            deleted.push(key);
+            if batch.len() >= BATCH_SIZE {
+                self.batch_sensor_stats(&mut batch, &std::mem::take(&mut deltas))?;
+                self.inner
+                    .write(std::mem::take(&mut batch))
+                    .context("failed to delete events")?;
+            }
+        }
+        self.batch_sensor_stats(&mut batch, &deltas)?;
+        self.inner.write(batch).context("failed to delete events")?;
+
+        for key in &deleted {
+            notify(self.notifier, ChangeKind::Remove, &key.to_be_bytes());
+        }
+        Ok(deleted.len())
+    }
+
+    /// Deletes the events that have expired at `now` according to `policy`,
+    /// and returns the number of deleted events. After deleting many events,
+    /// the range of the deleted events is compacted to reclaim the space.
+    ///
+    /// Events that cannot be deserialized are left untouched.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a database operation fails.
+    pub fn delete_expired(
+        &self,
+        policy: &EventRetentionPolicy,
+        now: DateTime<Utc>,
+    ) -> Result<usize> {
+        const BATCH_SIZE: usize = 10_000;
+        const COMPACTION_THRESHOLD: usize = BATCH_SIZE;
+
+        let Some(min_ttl) = policy.min_ttl() else {
+            return Ok(0);
+        };
+        let index = self.address_index()?;
+        let dispositions = self.dispositions()?;
+        let now = i128::from(now.timestamp_nanos_opt().unwrap_or(i64::MAX));
+        let Some(end) = i128::try_from(min_ttl.as_nanos())
+            .ok()
+            .and_then(|ttl| now.checked_sub(ttl))
+            .filter(|end| *end > 0)
+        else {
+            return Ok(0);
+        };
+
+        let mut opts = rocksdb::ReadOptions::default();
+        opts.set_iterate_upper_bound((end << 64).to_be_bytes());
+
+        let mut deleted = Vec::new();
+        let mut deltas = HashMap::new();
+        let mut batch = rocksdb::WriteBatchWithTransaction::<true>::default();
+        for item in self.inner.iterator_opt(IteratorMode::Start, opts) {
+            let (k, v) = item.context("cannot read events")?;
+            let Ok((key, event)) = parse_event(k, v) else {
+                continue;
+            };
+            let Some(ttl) = policy
+                .ttl(event.category())
+                .and_then(|ttl| i128::try_from(ttl.as_nanos()).ok())
+            else {
+                continue;
+            };
+            if (key >> 64).saturating_add(ttl) > now {
+                continue;
+            }
+            for addr in event_addresses(&event) {
+                batch.delete_cf(index, address_index_key(addr, key));
+            }
+            count_sensor(&mut deltas, &event, key, false);
+            self.delete_tags(&mut batch, key)?;
+            batch.delete_cf(dispositions, key.to_be_bytes());
+            batch.delete(key.to_be_bytes());
+            deleted.push(key);
+            if batch.len() >= BATCH_SIZE {
+                self.batch_sensor_stats(&mut batch, &std::mem::take(&mut deltas))?;
+                self.inner
+                    .write(std::mem::take(&mut batch))
+                    .context("failed to delete events")?;
+            }
+        }
+        self.batch_sensor_stats(&mut batch, &deltas)?;
+        self.inner.write(batch).context("failed to delete events")?;
+
+        if deleted.len() >= COMPACTION_THRESHOLD {
+            if let (Some(first), Some(last)) = (deleted.first(), deleted.last()) {
+                let (from, to) = (first.to_be_bytes(), last.to_be_bytes());
+                self.inner.compact_range(Some(from), Some(to));
+                self.inner
+                    .compact_range_cf(dispositions, Some(from), Some(to));
+            }
+        }
+
+        for key in &deleted {
+            notify(self.notifier, ChangeKind::Remove, &key.to_be_bytes());
+        }
+        Ok(deleted.len())
+    }
+}
+
+#[allow(clippy::module_name_repetitions)]
+pub struct EventIterator<'i> {
+    inner: rocksdb::DBIteratorWithThreadMode<
+        'i,
+        rocksdb::OptimisticTransactionDB<rocksdb::SingleThreaded>,
+    >,
+}
+
+impl<'i> Iterator for EventIterator<'i> {
+    type Item = Result<(i128, Event), InvalidEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (k, v) = self.inner.next().transpose().ok().flatten()?;
+        Some(parse_event(k, v))
+    }
+}
+
+/// An iterator over the events in a time range, returned by
+/// [`EventDb::iter_range`].
+#[allow(clippy::module_name_repetitions)]
+pub struct EventRangeIterator<'i> {
+    inner: rocksdb::DBIteratorWithThreadMode<
+        'i,
+        rocksdb::OptimisticTransactionDB<rocksdb::SingleThreaded>,
+    >,
+    kinds: Option<Vec<i128>>,
+    categories: Option<Vec<EventCategory>>,
+}
+
+impl<'i> Iterator for EventRangeIterator<'i> {
+    type Item = Result<(i128, Event), InvalidEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (k, v) = self.inner.next().transpose().ok().flatten()?;
+            if let (Some(kinds), Ok(key)) = (&self.kinds, <[u8; 16]>::try_from(k.as_ref())) {
+                let kind = (i128::from_be_bytes(key) & 0xffff_ffff_0000_0000) >> 32;
+                if !kinds.contains(&kind) {
+                    continue;
+                }
+            }
+            let event = parse_event(k, v);
+            if let (Some(categories), Ok((_, event))) = (&self.categories, &event) {
+                if !categories.contains(&event.category()) {
+                    continue;
+                }
+            }
+            return Some(event);
+        }
+    }
+}
+
+/// Returns an unused key for an event with `key` in `txn`, reading it for
+/// update so that the transaction fails if another writes the same key.
+///
+/// If `key` is taken, its lower 32 bits are replaced with a random number and
+/// incremented until an unused key is found.
+fn reserve_key(
+    txn: &rocksdb::Transaction<'_, rocksdb::OptimisticTransactionDB<rocksdb::SingleThreaded>>,
+    mut key: i128,
+) -> Result<i128> {
+    let is_taken = |key: i128| -> Result<bool> {
+        Ok(txn
+            .get_for_update(key.to_be_bytes(), super::EXCLUSIVE)
+            .context("cannot read from event database")?
+            .is_some())
+    };
+    if !is_taken(key)? {
+        return Ok(key);
+    }
+    let start = i128::from(thread_rng().next_u32());
+    key |= start;
+    #[allow(clippy::cast_possible_wrap)] // bit pattern
+    while is_taken(key)? {
+        let next = (key + 1) & 0xffff_ffff;
+        if next == start {
+            bail!("too many events with the same timestamp");
+        }
+        key = key & 0xffff_ffff_ffff_ffff_ffff_ffff_0000_0000_u128 as i128 | next;
+    }
+    Ok(key)
+}
+
+/// Returns the nanoseconds since the epoch of `time`, clamped to the range of
+/// `i64` on the side of the epoch `time` is on.
+fn clamped_nanos(time: DateTime<Utc>) -> i64 {
+    time.timestamp_nanos_opt()
+        .unwrap_or(if time.timestamp() < 0 {
+            i64::MIN
+        } else {
+            i64::MAX
+        })
+}
+
+/// Returns the lower bound of the keys of the events that occurred at or
+/// after `time`.
+///
+/// A key before the epoch is negative and sorts after the non-negative ones,
+/// so a time before the epoch is bounded by the first key.
+fn time_key_bound(time: DateTime<Utc>) -> i128 {
+    i128::from(clamped_nanos(time).max(0)) << 64
+}
+
+// Each tag attached to an event is stored twice, under the key of the event
+// followed by the tag ID, and under the tag ID followed by the key of the
+// event, so that both can be looked up by prefix.
+const TAGS_OF_EVENT: u8 = 0;
+const EVENTS_WITH_TAG: u8 = 1;
+
+/// Returns the keys of the two tag assignment entries for `tag_id` attached
+/// to the event with `key`.
+fn tag_assignment_keys(key: i128, tag_id: u32) -> ([u8; 21], [u8; 21]) {
+    let mut by_event = [TAGS_OF_EVENT; 21];
+    by_event[1..17].copy_from_slice(&key.to_be_bytes());
+    by_event[17..].copy_from_slice(&tag_id.to_be_bytes());
+    let mut by_tag = [EVENTS_WITH_TAG; 21];
+    by_tag[1..5].copy_from_slice(&tag_id.to_be_bytes());
+    by_tag[5..].copy_from_slice(&key.to_be_bytes());
+    (by_event, by_tag)
+}
+
+/// Returns the distinct, specified source and destination addresses of
+/// `event`.
+fn event_addresses(event: &Event) -> Vec<IpAddr> {
+    let event = event.as_match();
+    let mut addresses = vec![event.src_addr()];
+    if event.dst_addr() != event.src_addr() {
+        addresses.push(event.dst_addr());
+    }
+    addresses.retain(|addr| !addr.is_unspecified());
+    addresses
+}
+
+/// Returns the key of an address index entry, the address as an IPv6
+/// address followed by the key of the event.
+fn address_index_key(addr: IpAddr, key: i128) -> [u8; 32] {
+    let addr = match addr {
+        IpAddr::V4(addr) => addr.to_ipv6_mapped(),
+        IpAddr::V6(addr) => addr,
+    };
+    let mut index_key = [0; 32];
+    index_key[..16].copy_from_slice(&addr.octets());
+    index_key[16..].copy_from_slice(&key.to_be_bytes());
+    index_key
+}
+
+/// Returns the keys of the address index entries for the event stored with
+/// key `k` and value `v`, or none if the event cannot be deserialized.
+fn address_index_keys(k: &[u8], v: &[u8]) -> Vec<[u8; 32]> {
+    let Ok((key, event)) = parse_event(Box::from(k), Box::from(v)) else {
+        return Vec::new();
+    };
+    event_addresses(&event)
+        .into_iter()
+        .map(|addr| address_index_key(addr, key))
+        .collect()
+}
+
+/// Returns the serialized fields of `event` with the large string fields
+/// truncated to `max_len` bytes, or `None` if `event` isn't an HTTP event or
+/// none of its fields is longer than `max_len` bytes.
+fn truncate_fields(event: &EventMessage, max_len: usize) -> Option<Vec<u8>> {
+    fn reserialize<T: serde::de::DeserializeOwned + Serialize + LargeFields>(
+        fields: &[u8],
+        max_len: usize,
+    ) -> Option<Vec<u8>> {
+        let mut fields = bincode::deserialize::<T>(fields).ok()?;
+        if !fields.truncate(max_len) {
+            return None;
+        }
+        bincode::serialize(&fields).ok()
+    }
+
+    match event.kind {
+        EventKind::HttpThreat => reserialize::<HttpThreatFields>(&event.fields, max_len),
+        EventKind::DomainGenerationAlgorithm => reserialize::<DgaFields>(&event.fields, max_len),
+        EventKind::NonBrowser => reserialize::<NonBrowserFields>(&event.fields, max_len),
+        EventKind::BlockListHttp => reserialize::<BlockListHttpFields>(&event.fields, max_len),
+        _ => None,
+    }
+}
+
+/// Counts `event` with `key` as added to or removed from its sensor in
+/// `deltas`.
+fn count_sensor(
+    deltas: &mut HashMap<String, SensorStatsDelta>,
+    event: &Event,
+    key: i128,
+    added: bool,
+) {
+    let delta = deltas
+        .entry(event.as_match().source().to_string())
+        .or_default();
+    if added {
+        delta.count += 1;
+        #[allow(clippy::cast_possible_truncation)] // upper 64 bits of the key
+        let time = (key >> 64) as i64;
+        delta.last_seen = Some(delta.last_seen.map_or(time, |t| t.max(time)));
+    } else {
+        delta.count -= 1;
+    }
+}
+
+/// Returns the merge operands that apply `deltas` to the sensor statistics.
+fn sensor_stats_operands(
+    deltas: &HashMap<String, SensorStatsDelta>,
+) -> Result<Vec<(&String, Vec<u8>)>> {
+    deltas
+        .iter()
+        .map(|(sensor, delta)| {
+            let operand = StoredSensorStats {
+                count: delta.count,
+                last_seen: delta.last_seen.unwrap_or(i64::MIN),
+            };
+            Ok((sensor, bincode::serialize(&operand)?))
+        })
+        .collect()
+}
+
+/// An event written by [`EventDb::export_jsonl`].
+#[derive(Serialize)]
+struct JsonlEvent<'e> {
+    key: String,
+    time: DateTime<Utc>,
+    event_kind: EventKind,
+    category: EventCategory,
+    kind: &'e str,
+    level: u8,
+    sensor: &'e str,
+    src_addr: IpAddr,
+    src_port: u16,
+    dst_addr: IpAddr,
+    dst_port: u16,
+    proto: u8,
+    confidence: Option<f32>,
+}
+
+/// An iterator over the events in a time range that match an
+/// [`EventFilterExpr`], returned by [`EventDb::iter_matching`].
+pub struct MatchingEventIterator<'i, 'f> {
+    inner: EventRangeIterator<'i>,
+    expr: &'f EventFilterExpr,
+    locator: Option<Arc<Mutex<ip2location::DB>>>,
+}
+
+impl<'i, 'f> Iterator for MatchingEventIterator<'i, 'f> {
+    type Item = Result<(i128, Event)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (key, event) = match self.inner.next()? {
+                Ok(event) => event,
+                Err(_) => return Some(Err(anyhow::anyhow!("invalid event"))),
+            };
+            match self.expr.matches(&event, self.locator.as_ref()) {
+                Ok(true) => return Some(Ok((key, event))),
+                Ok(false) => continue,
+                Err(e) => return Some(Err(e)),
             }
         }
     }
-}
+}
+
+/// Decodes an event stored in the database with key `k` and value `v`.
+pub(crate) fn parse_event(k: Box<[u8]>, v: Box<[u8]>) -> Result<(i128, Event), InvalidEvent> {
+    let key: [u8; 16] = if let Ok(key) = k.as_ref().try_into() {
+        key
+    } else {
+        return Err(InvalidEvent::Key(k));
+    };
+    let key = i128::from_be_bytes(key);
+    let time = Utc.timestamp_nanos((key >> 64).try_into().expect("valid i64"));
+    let kind_num = (key & 0xffff_ffff_0000_0000) >> 32;
+    let Some(kind) = EventKind::from_i128(kind_num) else {
+        return Err(InvalidEvent::Key(k));
+    };
+    match kind {
+        EventKind::DnsCovertChannel => {
+            let Ok(fields) = bincode::deserialize::<DnsEventFields>(v.as_ref()) else {
+                return Err(InvalidEvent::Value(v));
+            };
+            Ok((
+                key,
+                Event::DnsCovertChannel(DnsCovertChannel::new(time, fields)),
+            ))
+        }
+        EventKind::HttpThreat => {
+            let Ok(fields) = bincode::deserialize::<HttpThreatFields>(v.as_ref()) else {
+                return Err(InvalidEvent::Value(v));
+            };
+            Ok((key, Event::HttpThreat(HttpThreat::new(fields.time, fields))))
+        }
+        EventKind::RdpBruteForce => {
+            let Ok(fields) = bincode::deserialize::<RdpBruteForceFields>(v.as_ref()) else {
+                return Err(InvalidEvent::Value(v));
+            };
+            Ok((key, Event::RdpBruteForce(RdpBruteForce::new(time, &fields))))
+        }
+        EventKind::RepeatedHttpSessions => {
+            let Ok(fields) = bincode::deserialize::<RepeatedHttpSessionsFields>(v.as_ref()) else {
+                return Err(InvalidEvent::Value(v));
+            };
+            Ok((
+                key,
+                Event::RepeatedHttpSessions(RepeatedHttpSessions::new(time, &fields)),
+            ))
+        }
+        EventKind::TorConnection => {
+            let Ok(fields) = bincode::deserialize::<TorConnectionFields>(v.as_ref()) else {
+                return Err(InvalidEvent::Value(v));
+            };
+            Ok((key, Event::TorConnection(TorConnection::new(time, &fields))))
+        }
+        EventKind::DomainGenerationAlgorithm => {
+            let Ok(fields) = bincode::deserialize::<DgaFields>(v.as_ref()) else {
+                return Err(InvalidEvent::Value(v));
+            };
+            Ok((
+                key,
+                Event::DomainGenerationAlgorithm(DomainGenerationAlgorithm::new(time, fields)),
+            ))
+        }
+        EventKind::FtpBruteForce => {
+            let Ok(fields) = bincode::deserialize::<FtpBruteForceFields>(v.as_ref()) else {
+                return Err(InvalidEvent::Value(v));
+            };
+            Ok((key, Event::FtpBruteForce(FtpBruteForce::new(time, &fields))))
+        }
+        EventKind::FtpPlainText => {
+            let Ok(fields) = bincode::deserialize::<FtpPlainTextFields>(v.as_ref()) else {
+                return Err(InvalidEvent::Value(v));
+            };
+            Ok((key, Event::FtpPlainText(FtpPlainText::new(time, fields))))
+        }
+        EventKind::PortScan => {
+            let Ok(fields) = bincode::deserialize::<PortScanFields>(v.as_ref()) else {
+                return Err(InvalidEvent::Value(v));
+            };
+            Ok((key, Event::PortScan(PortScan::new(time, &fields))))
+        }
+        EventKind::MultiHostPortScan => {
+            let Ok(fields) = bincode::deserialize::<MultiHostPortScanFields>(v.as_ref()) else {
+                return Err(InvalidEvent::Value(v));
+            };
+            Ok((
+                key,
+                Event::MultiHostPortScan(MultiHostPortScan::new(time, &fields)),
+            ))
+        }
+        EventKind::NonBrowser => {
+            let Ok(fields) = bincode::deserialize::<NonBrowserFields>(v.as_ref()) else {
+                return Err(InvalidEvent::Value(v));
+            };
+            Ok((key, Event::NonBrowser(NonBrowser::new(time, &fields))))
+        }
+        EventKind::LdapBruteForce => {
+            let Ok(fields) = bincode::deserialize::<LdapBruteForceFields>(v.as_ref()) else {
+                return Err(InvalidEvent::Value(v));
+            };
+            Ok((
+                key,
+                Event::LdapBruteForce(LdapBruteForce::new(time, &fields)),
+            ))
+        }
+        EventKind::LdapPlainText => {
+            let Ok(fields) = bincode::deserialize::<LdapPlainTextFields>(v.as_ref()) else {
+                return Err(InvalidEvent::Value(v));
+            };
+            Ok((key, Event::LdapPlainText(LdapPlainText::new(time, fields))))
+        }
+        EventKind::ExternalDdos => {
+            let Ok(fields) = bincode::deserialize::<ExternalDdosFields>(v.as_ref()) else {
+                return Err(InvalidEvent::Value(v));
+            };
+            Ok((key, Event::ExternalDdos(ExternalDdos::new(time, &fields))))
+        }
+        EventKind::CryptocurrencyMiningPool => {
+            let Ok(fields) = bincode::deserialize::<CryptocurrencyMiningPoolFields>(v.as_ref())
+            else {
+                return Err(InvalidEvent::Value(v));
+            };
+            Ok((
+                key,
+                Event::CryptocurrencyMiningPool(CryptocurrencyMiningPool::new(time, fields)),
+            ))
+        }
+        EventKind::BlockListConn => {
+            let Ok(fields) = bincode::deserialize::<BlockListConnFields>(v.as_ref()) else {
+                return Err(InvalidEvent::Value(v));
+            };
+            Ok((
+                key,
+                Event::BlockList(RecordType::Conn(BlockListConn::new(time, fields))),
+            ))
+        }
+        EventKind::BlockListDns => {
+            let Ok(fields) = bincode::deserialize::<BlockListDnsFields>(v.as_ref()) else {
+                return Err(InvalidEvent::Value(v));
+            };
+            Ok((
+                key,
+                Event::BlockList(RecordType::Dns(BlockListDns::new(time, fields))),
+            ))
+        }
+        EventKind::BlockListDceRpc => {
+            let Ok(fields) = bincode::deserialize::<BlockListDceRpcFields>(v.as_ref()) else {
+                return Err(InvalidEvent::Value(v));
+            };
+            Ok((
+                key,
+                Event::BlockList(RecordType::DceRpc(BlockListDceRpc::new(time, fields))),
+            ))
+        }
+        EventKind::BlockListFtp => {
+            let Ok(fields) = bincode::deserialize::<BlockListFtpFields>(v.as_ref()) else {
+                return Err(InvalidEvent::Value(v));
+            };
+            Ok((
+                key,
+                Event::BlockList(RecordType::Ftp(BlockListFtp::new(time, fields))),
+            ))
+        }
+        EventKind::BlockListHttp => {
+            let Ok(fields) = bincode::deserialize::<BlockListHttpFields>(v.as_ref()) else {
+                return Err(InvalidEvent::Value(v));
+            };
+            Ok((
+                key,
+                Event::BlockList(RecordType::Http(BlockListHttp::new(time, fields))),
+            ))
+        }
+        EventKind::BlockListKerberos => {
+            let Ok(fields) = bincode::deserialize::<BlockListKerberosFields>(v.as_ref()) else {
+                return Err(InvalidEvent::Value(v));
+            };
+            Ok((
+                key,
+                Event::BlockList(RecordType::Kerberos(BlockListKerberos::new(time, fields))),
+            ))
+        }
+        EventKind::BlockListLdap => {
+            let Ok(fields) = bincode::deserialize::<BlockListLdapFields>(v.as_ref()) else {
+                return Err(InvalidEvent::Value(v));
+            };
+            Ok((
+                key,
+                Event::BlockList(RecordType::Ldap(BlockListLdap::new(time, fields))),
+            ))
+        }
+        EventKind::BlockListMqtt => {
+            let Ok(fields) = bincode::deserialize::<BlockListMqttFields>(v.as_ref()) else {
+                return Err(InvalidEvent::Value(v));
+            };
+            Ok((
+                key,
+                Event::BlockList(RecordType::Mqtt(BlockListMqtt::new(time, fields))),
+            ))
+        }
+        EventKind::BlockListNfs => {
+            let Ok(fields) = bincode::deserialize::<BlockListNfsFields>(v.as_ref()) else {
+                return Err(InvalidEvent::Value(v));
+            };
+            Ok((
+                key,
+                Event::BlockList(RecordType::Nfs(BlockListNfs::new(time, fields))),
+            ))
+        }
+        EventKind::BlockListNtlm => {
+            let Ok(fields) = bincode::deserialize::<BlockListNtlmFields>(v.as_ref()) else {
+                return Err(InvalidEvent::Value(v));
+            };
+            Ok((
+                key,
+                Event::BlockList(RecordType::Ntlm(BlockListNtlm::new(time, fields))),
+            ))
+        }
+        EventKind::BlockListRdp => {
+            let Ok(fields) = bincode::deserialize::<BlockListRdpFields>(v.as_ref()) else {
+                return Err(InvalidEvent::Value(v));
+            };
+            Ok((
+                key,
+                Event::BlockList(RecordType::Rdp(BlockListRdp::new(time, fields))),
+            ))
+        }
+        EventKind::BlockListSmb => {
+            let Ok(fields) = bincode::deserialize::<BlockListSmbFields>(v.as_ref()) else {
+                return Err(InvalidEvent::Value(v));
+            };
+            Ok((
+                key,
+                Event::BlockList(RecordType::Smb(BlockListSmb::new(time, fields))),
+            ))
+        }
+        EventKind::BlockListSmtp => {
+            let Ok(fields) = bincode::deserialize::<BlockListSmtpFields>(v.as_ref()) else {
+                return Err(InvalidEvent::Value(v));
+            };
+            Ok((
+                key,
+                Event::BlockList(RecordType::Smtp(BlockListSmtp::new(time, fields))),
+            ))
+        }
+        EventKind::BlockListSsh => {
+            let Ok(fields) = bincode::deserialize::<BlockListSshFields>(v.as_ref()) else {
+                return Err(InvalidEvent::Value(v));
+            };
+            Ok((
+                key,
+                Event::BlockList(RecordType::Ssh(BlockListSsh::new(time, fields))),
+            ))
+        }
+        EventKind::BlockListTls => {
+            let Ok(fields) = bincode::deserialize::<BlockListTlsFields>(v.as_ref()) else {
+                return Err(InvalidEvent::Value(v));
+            };
+            Ok((
+                key,
+                Event::BlockList(RecordType::Tls(BlockListTls::new(time, fields))),
+            ))
+        }
+        EventKind::WindowsThreat => {
+            let Ok(fields) = bincode::deserialize::<WindowsThreat>(v.as_ref()) else {
+                return Err(InvalidEvent::Value(v));
+            };
+            Ok((key, Event::WindowsThreat(fields)))
+        }
+        EventKind::NetworkThreat => {
+            let Ok(fields) = bincode::deserialize::<NetworkThreat>(v.as_ref()) else {
+                return Err(InvalidEvent::Value(v));
+            };
+            Ok((key, Event::NetworkThreat(fields)))
+        }
+        EventKind::ExtraThreat => {
+            let Ok(fields) = bincode::deserialize::<ExtraThreat>(v.as_ref()) else {
+                return Err(InvalidEvent::Value(v));
+            };
+            Ok((key, Event::ExtraThreat(fields)))
+        }
+        EventKind::TlsCertificateAnomaly => {
+            let Ok(fields) = bincode::deserialize::<TlsCertificateAnomalyFields>(v.as_ref()) else {
+                return Err(InvalidEvent::Value(v));
+            };
+            Ok((
+                key,
+                Event::TlsCertificateAnomaly(TlsCertificateAnomaly::new(time, fields)),
+            ))
+        }
+        EventKind::SmbLateralMovement => {
+            let Ok(fields) = bincode::deserialize::<SmbLateralMovementFields>(v.as_ref()) else {
+                return Err(InvalidEvent::Value(v));
+            };
+            Ok((
+                key,
+                Event::SmbLateralMovement(SmbLateralMovement::new(time, fields)),
+            ))
+        }
+        EventKind::DceRpcLateralMovement => {
+            let Ok(fields) = bincode::deserialize::<DceRpcLateralMovementFields>(v.as_ref()) else {
+                return Err(InvalidEvent::Value(v));
+            };
+            Ok((
+                key,
+                Event::DceRpcLateralMovement(DceRpcLateralMovement::new(time, fields)),
+            ))
+        }
+        EventKind::IcmpTunnel => {
+            let Ok(fields) = bincode::deserialize::<IcmpTunnelFields>(v.as_ref()) else {
+                return Err(InvalidEvent::Value(v));
+            };
+            Ok((key, Event::IcmpTunnel(IcmpTunnel::new(time, fields))))
+        }
+        EventKind::CustomDetection => {
+            let Ok(fields) = bincode::deserialize::<CustomDetectionFields>(v.as_ref()) else {
+                return Err(InvalidEvent::Value(v));
+            };
+            Ok((
+                key,
+                Event::CustomDetection(CustomDetection::new(time, fields)),
+            ))
+        }
+    }
+}
+
+#[derive(Debug)]
+#[allow(clippy::module_name_repetitions)]
+pub enum InvalidEvent {
+    Key(Box<[u8]>),
+    Value(Box<[u8]>),
+}
+
+pub type Id = u32;
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct FilterEndpoint {
+    pub direction: Option<TrafficDirection>,
+    pub predefined: Option<Id>,
+    pub custom: Option<HostNetworkGroup>,
+}
+
+/// Traffic flow direction.
+#[derive(Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+pub enum FlowKind {
+    Inbound,
+    Outbound,
+    Internal,
+}
+
+/// Possible network types of `CustomerNetwork`.
+#[derive(Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+pub enum NetworkType {
+    Intranet,
+    Extranet,
+    Gateway,
+}
+
+#[derive(Clone, Copy, Deserialize, Eq, PartialEq, Serialize)]
+pub enum TrafficDirection {
+    From,
+    To,
+}
+
+pub fn find_ip_country(locator: &mut ip2location::DB, addr: IpAddr) -> String {
+    locator
+        .ip_lookup(addr)
+        .map(|r| get_record_country_short_name(&r))
+        .ok()
+        .flatten()
+        .unwrap_or_else(|| "XX".to_string())
+}
+
+fn eq_ip_country(
+    locator: &mut MutexGuard<ip2location::DB>,
+    addr: IpAddr,
+    country: [u8; 2],
+) -> bool {
+    locator
+        .ip_lookup(addr)
+        .ok()
+        .and_then(|r| get_record_country_short_name(&r))
+        .map_or(false, |c| c.as_bytes() == country)
+}
+
+fn get_record_country_short_name(record: &ip2location::Record) -> Option<String> {
+    use ip2location::Record;
+    match record {
+        Record::ProxyDb(r) => r
+            .country
+            .as_ref()
+            .map(|c| c.short_name.clone().into_owned()),
+        Record::LocationDb(r) => r
+            .country
+            .as_ref()
+            .map(|c| c.short_name.clone().into_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        event::DgaFields, event::DnsEventFields, DomainGenerationAlgorithm, Event, EventInterval,
+        EventKind, EventMessage, Store,
+    };
+    use bincode::Options;
+    use chrono::{DateTime, TimeZone, Utc};
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        sync::Arc,
+    };
+
+    fn example_message() -> EventMessage {
+        let codec = bincode::DefaultOptions::new();
+        let fields = DnsEventFields {
+            source: "collector1".to_string(),
+            session_end_time: Utc::now(),
+            src_addr: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+            src_port: 10000,
+            dst_addr: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)),
+            dst_port: 53,
+            proto: 17,
+            query: "foo.com".to_string(),
+            answer: vec!["1.1.1.1".to_string()],
+            trans_id: 1,
+            rtt: 1,
+            qclass: 0,
+            qtype: 0,
+            rcode: 0,
+            aa_flag: false,
+            tc_flag: false,
+            rd_flag: false,
+            ra_flag: false,
+            ttl: vec![1; 5],
+            confidence: 0.8,
+        };
+        EventMessage {
+            time: Utc::now(),
+            kind: EventKind::DnsCovertChannel,
+            fields: codec.serialize(&fields).expect("serializable"),
+        }
+    }
+
+    #[tokio::test]
+    async fn event_db_put() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+
+        let store = Arc::new(Store::new(db_dir.path(), backup_dir.path()).unwrap());
+        let db = store.events();
+        assert!(db.iter_forward().next().is_none());
+
+        let msg = example_message();
+        db.put(&msg).unwrap();
+        let mut iter = db.iter_forward();
+        assert!(iter.next().is_some());
+        assert!(iter.next().is_none());
+
+        db.put(&msg).unwrap();
+        let mut iter = db.iter_forward();
+        assert!(iter.next().is_some());
+        assert!(iter.next().is_some());
+        assert!(iter.next().is_none());
+    }
+
+    #[tokio::test]
+    async fn event_stream() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+        let db = store.events();
+        for _ in 0..3 {
+            db.put(&example_message()).unwrap();
+        }
+
+        let store = Arc::new(tokio::sync::RwLock::new(store));
+        let mut rx = crate::event_stream(&store, 0, crate::Direction::Forward);
+        let mut count = 0;
+        while let Some(item) = rx.recv().await {
+            assert!(item.is_ok());
+            count += 1;
+        }
+        assert_eq!(count, 3);
+    }
+
+    #[test]
+    fn event_db_iter_range() {
+        use crate::EventCategory;
+        use chrono::Duration;
+
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+        let db = store.events();
+
+        let msg = example_message();
+        let time = msg.time;
+        db.put(&msg).unwrap();
+        let mut msg = example_message();
+        msg.time = time + Duration::hours(1);
+        db.put(&msg).unwrap();
+
+        let end = time + Duration::minutes(1);
+        assert_eq!(db.iter_range(time, end, None).count(), 1);
+        let c2 = [EventCategory::CommandAndControl];
+        assert_eq!(db.iter_range(time, end, Some(&c2)).count(), 1);
+        let impact = [EventCategory::Impact];
+        assert_eq!(db.iter_range(time, end, Some(&impact)).count(), 0);
+        let end = time + Duration::hours(2);
+        assert_eq!(db.iter_range(time, end, Some(&c2)).count(), 2);
+        assert_eq!(db.iter_range(end, end, None).count(), 0);
+    }
+
+    #[test]
+    fn event_db_count_by_interval() {
+        use crate::EventCategory;
+        use chrono::Duration;
+
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+        let db = store.events();
+
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        for minutes in [10, 20, 70, 25 * 60] {
+            let mut msg = example_message();
+            msg.time = start + Duration::minutes(minutes);
+            db.put(&msg).unwrap();
+        }
+
+        let end = start + Duration::days(2);
+        let counts = db
+            .count_by_interval(start, end, EventInterval::Hour)
+            .unwrap();
+        let counts = counts
+            .iter()
+            .map(|c| (c.start, c.category, c.count))
+            .collect::<Vec<_>>();
+        let c2 = EventCategory::CommandAndControl;
+        assert_eq!(
+            counts,
+            [
+                (start, c2, 2),
+                (start + Duration::hours(1), c2, 1),
+                (start + Duration::hours(25), c2, 1),
+            ]
+        );
+
+        let counts = db
+            .count_by_interval(start, end, EventInterval::Day)
+            .unwrap();
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts[0].count, 3);
+        assert_eq!(counts[1].start, start + Duration::days(1));
+        assert!(db
+            .count_by_interval(end, end, EventInterval::Day)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn event_db_find_by_addr() {
+        use chrono::Duration;
+
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+        let db = store.events();
+
+        let src = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+        let dst = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2));
+        let msg = example_message();
+        let time = msg.time;
+        let first = db.put(&msg).unwrap();
+        let mut msg = example_message();
+        msg.time = time + Duration::hours(1);
+        let second = db.put(&msg).unwrap();
+
+        let range = time..time + Duration::hours(2);
+        let keys =
+            |events: Vec<(i128, Event)>| events.into_iter().map(|(k, _)| k).collect::<Vec<_>>();
+        assert_eq!(
+            keys(db.find_by_addr(src, range.clone()).unwrap()),
+            [first, second]
+        );
+        assert_eq!(
+            keys(db.find_by_addr(dst, range.clone()).unwrap()),
+            [first, second]
+        );
+        assert_eq!(
+            keys(
+                db.find_by_addr(src, time..time + Duration::minutes(1))
+                    .unwrap()
+            ),
+            [first]
+        );
+        let other = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 3));
+        assert!(db.find_by_addr(other, range.clone()).unwrap().is_empty());
+
+        db.delete_range(time, time + Duration::minutes(1), &[])
+            .unwrap();
+        assert_eq!(keys(db.find_by_addr(src, range.clone()).unwrap()), [second]);
+
+        let index = db.address_index().unwrap();
+        for item in db.inner.iterator_cf(index, rocksdb::IteratorMode::Start) {
+            let (key, _) = item.unwrap();
+            db.inner.delete_cf(index, key).unwrap();
+        }
+        assert!(db.find_by_addr(src, range.clone()).unwrap().is_empty());
+        assert_eq!(db.rebuild_address_index().unwrap(), 1);
+        assert_eq!(keys(db.find_by_addr(dst, range).unwrap()), [second]);
+    }
+
+    #[test]
+    fn event_db_export_jsonl() {
+        use chrono::Duration;
+
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+        let db = store.events();
+
+        let msg = example_message();
+        let time = msg.time;
+        let key = db.put(&msg).unwrap();
+        let mut msg = example_message();
+        msg.time = time + Duration::hours(1);
+        db.put(&msg).unwrap();
+
+        let mut output = Vec::new();
+        let range = time..time + Duration::minutes(1);
+        assert_eq!(db.export_jsonl(range, &mut output).unwrap(), 1);
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output.lines().count(), 1);
+        let line: serde_json::Value = serde_json::from_str(output.trim_end()).unwrap();
+        assert_eq!(line["key"], key.to_string());
+        assert_eq!(line["event_kind"], "DnsCovertChannel");
+        assert_eq!(line["category"], "CommandAndControl");
+        assert_eq!(line["src_addr"], "127.0.0.1");
+        assert_eq!(line["dst_addr"], "127.0.0.2");
+        assert_eq!(
+            line["time"]
+                .as_str()
+                .unwrap()
+                .parse::<DateTime<Utc>>()
+                .unwrap(),
+            time
+        );
+    }
+
+    #[test]
+    fn event_filter_ports_and_protocols() {
+        use crate::EventFilter;
+
+        let msg = example_message();
+        let key = i128::from(msg.time.timestamp_nanos_opt().unwrap()) << 64
+            | i128::from(EventKind::DnsCovertChannel as u32) << 32;
+        let (_, event) =
+            crate::event::parse_event(Box::from(key.to_be_bytes()), msg.fields.into_boxed_slice())
+                .unwrap();
+
+        let filter = || {
+            EventFilter::new(
+                None, None, None, None, None, None, None, None, None, None, None, None, None,
+            )
+        };
+        assert!(event.matches(None, &filter()).unwrap().0);
+        let udp_dns = filter()
+            .with_dst_ports(vec![53..=53, 5353..=5353])
+            .with_protocols(vec![17]);
+        assert!(event.matches(None, &udp_dns).unwrap().0);
+        let tcp = filter().with_protocols(vec![6]);
+        assert!(!event.matches(None, &tcp).unwrap().0);
+        let ephemeral = filter().with_src_ports(vec![1024..=9999]);
+        assert!(!event.matches(None, &ephemeral).unwrap().0);
+        let ephemeral = filter().with_src_ports(vec![1024..=65535]);
+        assert!(event.matches(None, &ephemeral).unwrap().0);
+    }
+
+    #[test]
+    fn event_db_iter_matching() {
+        use crate::{EventFilter, EventFilterExpr};
+        use chrono::Duration;
+
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+        let db = store.events();
+
+        let msg = example_message();
+        let start = msg.time;
+        let end = start + Duration::hours(1);
+        let key = db.put(&msg).unwrap();
+
+        let filter = || {
+            EventFilter::new(
+                None, None, None, None, None, None, None, None, None, None, None, None, None,
+            )
+        };
+        let udp = || EventFilterExpr::Filter(filter().with_protocols(vec![17]));
+        let dns = || EventFilterExpr::Filter(filter().with_dst_ports(vec![53..=53]));
+        let keys = |expr: &EventFilterExpr| {
+            db.iter_matching(start, end, expr, None)
+                .map(|r| r.unwrap().0)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(keys(&EventFilterExpr::And(vec![])), [key]);
+        assert!(keys(&EventFilterExpr::Or(vec![])).is_empty());
+        assert_eq!(keys(&EventFilterExpr::And(vec![udp(), dns()])), [key]);
+        let udp_not_dns = EventFilterExpr::And(vec![udp(), EventFilterExpr::Not(Box::new(dns()))]);
+        assert!(keys(&udp_not_dns).is_empty());
+        let either = EventFilterExpr::Or(vec![
+            EventFilterExpr::Not(Box::new(udp())),
+            EventFilterExpr::Filter(filter().with_protocols(vec![17])),
+        ]);
+        assert_eq!(keys(&either), [key]);
+    }
+
+    #[test]
+    fn event_siem_formats() {
+        let msg = example_message();
+        let time = Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap();
+        let key = i128::from(time.timestamp_nanos_opt().unwrap()) << 64
+            | i128::from(EventKind::DnsCovertChannel as u32) << 32;
+        let (_, event) =
+            crate::event::parse_event(Box::from(key.to_be_bytes()), msg.fields.into_boxed_slice())
+                .unwrap();
+
+        assert_eq!(
+            event.to_cef(time),
+            format!(
+                "CEF:0|Petabi|REview|{}|dns covert channel|CommandAndControl|6|rt=1704164645000 \
+                 src=127.0.0.1 spt=10000 dst=127.0.0.2 dpt=53 proto=UDP cat=CommandAndControl \
+                 dvchost=collector1 query=foo.com qclass=0 qtype=0 rcode=0 rtt=1 cfp1=0.8 \
+                 cfp1Label=confidence",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+        let leef = event.to_leef(time);
+        assert!(leef.starts_with(&format!(
+            "LEEF:1.0|Petabi|REview|{}|dns covert channel|devTime=2024-01-02T03:04:05.000+00:00\t",
+            env!("CARGO_PKG_VERSION")
+        )));
+        assert!(leef.contains("\tsrc=127.0.0.1\tsrcPort=10000\t"));
+        assert!(leef.ends_with(
+            "\tidentHostName=collector1\tquery=foo.com\tqclass=0\tqtype=0\trcode=0\trtt=1\t\
+             confidence=0.8"
+        ));
+    }
+
+    #[test]
+    fn event_db_tags() {
+        use chrono::Duration;
+
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+        let db = store.events();
+        let mut tags = store.event_tag_set().unwrap();
+        let investigated = tags.insert("investigated").unwrap();
+        let false_positive = tags.insert("false positive").unwrap();
+
+        let msg = example_message();
+        let time = msg.time;
+        let first = db.put(&msg).unwrap();
+        let mut msg = example_message();
+        msg.time = time + Duration::hours(1);
+        let second = db.put(&msg).unwrap();
+
+        assert!(db.attach_tag(first, investigated).unwrap());
+        assert!(!db.attach_tag(first, investigated).unwrap());
+        assert!(db.attach_tag(first, false_positive).unwrap());
+        assert!(db.attach_tag(second, investigated).unwrap());
+        assert!(db.attach_tag(second + 1, investigated).is_err());
+        assert_eq!(db.tag_ids(first).unwrap(), [investigated, false_positive]);
+
+        let keys =
+            |events: Vec<(i128, Event)>| events.into_iter().map(|(k, _)| k).collect::<Vec<_>>();
+        assert_eq!(keys(db.find_by_tag(investigated).unwrap()), [first, second]);
+        assert_eq!(keys(db.find_by_tag(false_positive).unwrap()), [first]);
+
+        assert!(db.detach_tag(first, investigated).unwrap());
+        assert!(!db.detach_tag(first, investigated).unwrap());
+        assert_eq!(keys(db.find_by_tag(investigated).unwrap()), [second]);
+
+        db.delete_range(time, time + Duration::minutes(1), &[])
+            .unwrap();
+        assert!(db.tag_ids(first).unwrap().is_empty());
+        assert!(db.find_by_tag(false_positive).unwrap().is_empty());
+
+        tags.remove_event_tag(investigated, &store.triage_response_map(), &db)
+            .unwrap();
+        assert!(db.tag_ids(second).unwrap().is_empty());
+    }
+
+    #[test]
+    fn event_db_put_many() {
+        use chrono::Duration;
+
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+        let db = store.events();
+
+        let first = example_message();
+        let time = first.time;
+        let existing = db.put(&first).unwrap();
+        let mut second = example_message();
+        second.time = time;
+        let mut third = example_message();
+        third.time = time + Duration::hours(1);
+
+        let keys = db.put_many(&[first, second, third], false).unwrap();
+        assert_eq!(keys.len(), 3);
+        assert!(!keys.contains(&existing));
+        assert_ne!(keys[0], keys[1]);
+        assert_eq!(keys[0] >> 64, keys[1] >> 64);
+        assert_eq!(db.iter_forward().count(), 4);
+        assert_eq!(
+            db.find_by_addr(
+                IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+                time..time + Duration::hours(2)
+            )
+            .unwrap()
+            .len(),
+            4
+        );
+        assert!(db.put_many(&[], true).unwrap().is_empty());
+    }
+
+    #[test]
+    fn event_db_sample() {
+        use chrono::Duration;
+
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+        let db = store.events();
+
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let mut keys = Vec::new();
+        for minutes in 0..60 {
+            let mut msg = example_message();
+            msg.time = start + Duration::minutes(minutes);
+            keys.push(db.put(&msg).unwrap());
+        }
+        let range = start..start + Duration::hours(1);
+
+        let sample = db.sample(range.clone(), 4).unwrap();
+        let sampled = sample.iter().map(|(k, _)| *k).collect::<Vec<_>>();
+        assert_eq!(sampled, [keys[0], keys[15], keys[30], keys[45]]);
+        assert_eq!(db.sample(range.clone(), 100).unwrap().len(), 60);
+        assert!(db.sample(range, 0).unwrap().is_empty());
+        assert!(db
+            .sample(start - Duration::hours(1)..start, 10)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn event_db_max_field_len() {
+        use crate::{
+            event::{Direction, HttpThreatFields},
+            TRUNCATION_MARKER,
+        };
+
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+        let db = store.events().with_max_field_len(8);
+
+        let fields = DgaFields {
+            source: "collector1".to_string(),
+            src_addr: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+            src_port: 10000,
+            dst_addr: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)),
+            dst_port: 80,
+            proto: 6,
+            duration: 0,
+            method: "GET".to_string(),
+            host: "example.com".to_string(),
+            uri: "/a/very/long/path".to_string(),
+            referer: "-".to_string(),
+            version: "1.1".to_string(),
+            user_agent: "Mozilla\u{e9}/5.0".to_string(),
+            request_len: 100,
+            response_len: 100,
+            status_code: 200,
+            status_msg: "-".to_string(),
+            username: "-".to_string(),
+            password: "-".to_string(),
+            cookie: "cookie".to_string(),
+            content_encoding: "-".to_string(),
+            content_type: "-".to_string(),
+            cache_control: "-".to_string(),
+            confidence: 0.8,
+        };
+        let msg = EventMessage {
+            time: Utc::now(),
+            kind: EventKind::DomainGenerationAlgorithm,
+            fields: bincode::serialize(&fields).expect("serializable"),
+        };
+        db.put(&msg).unwrap();
+        db.put_many(&[msg], false).unwrap();
+
+        for event in db.iter_forward() {
+            let Event::DomainGenerationAlgorithm(event) = event.unwrap().1 else {
+                panic!("unexpected event kind");
+            };
+            assert_eq!(event.uri, format!("/a/very/{TRUNCATION_MARKER}"));
+            assert_eq!(event.user_agent, format!("Mozilla{TRUNCATION_MARKER}"));
+            assert_eq!(event.referer, "-");
+            assert_eq!(event.cookie, "cookie");
+        }
+
+        let fields = HttpThreatFields {
+            time: Utc::now(),
+            source: "collector1".to_string(),
+            src_addr: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+            src_port: 10000,
+            dst_addr: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)),
+            dst_port: 80,
+            proto: 6,
+            duration: 0,
+            method: "POST".to_string(),
+            host: "example.com".to_string(),
+            uri: "/".to_string(),
+            referer: "-".to_string(),
+            version: "1.1".to_string(),
+            user_agent: "-".to_string(),
+            request_len: 0,
+            response_len: 0,
+            status_code: 200,
+            status_msg: "OK".to_string(),
+            username: "-".to_string(),
+            password: "-".to_string(),
+            cookie: "-".to_string(),
+            content_encoding: "-".to_string(),
+            content_type: "-".to_string(),
+            cache_control: "-".to_string(),
+            db_name: "db".to_string(),
+            rule_id: 1,
+            matched_to: "id=1 UNION SELECT".to_string(),
+            cluster_id: 1,
+            attack_kind: "sqli".to_string(),
+            confidence: 0.8,
+        };
+        let msg = EventMessage {
+            time: Utc::now(),
+            kind: EventKind::HttpThreat,
+            fields: bincode::serialize(&fields).expect("serializable"),
+        };
+        let key = db.put(&msg).unwrap();
+        let Some(Ok((_, Event::HttpThreat(event)))) = db.iter_from(key, Direction::Forward).next()
+        else {
+            panic!("unexpected event kind");
+        };
+        assert_eq!(event.matched_to, format!("id=1 UNI{TRUNCATION_MARKER}"));
+        assert_eq!(event.uri, "/");
+    }
+
+    #[test]
+    fn event_db_sensor_stats() {
+        use crate::SensorStats;
+        use chrono::Duration;
+
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+        let db = store.events();
+        assert!(db.sensor_stats().unwrap().is_empty());
+
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let mut msg = example_message();
+        msg.time = start;
+        db.put(&msg).unwrap();
+        let mut later = example_message();
+        later.time = start + Duration::hours(2);
+        let mut earlier = example_message();
+        earlier.time = start + Duration::hours(1);
+        db.put_many(&[later, earlier], false).unwrap();
+        let stats = |count| {
+            vec![SensorStats {
+                sensor: "collector1".to_string(),
+                count,
+                last_seen: start + Duration::hours(2),
+            }]
+        };
+        assert_eq!(db.sensor_stats().unwrap(), stats(3));
+
+        db.delete_range(start, start + Duration::minutes(1), &[])
+            .unwrap();
+        assert_eq!(db.sensor_stats().unwrap(), stats(2));
+        db.delete_range(start, start + Duration::hours(3), &[])
+            .unwrap();
+        assert_eq!(db.sensor_stats().unwrap(), stats(0));
+
+        db.put(&msg).unwrap();
+        assert_eq!(db.rebuild_sensor_stats().unwrap(), 1);
+        assert_eq!(
+            db.sensor_stats().unwrap(),
+            [SensorStats {
+                sensor: "collector1".to_string(),
+                count: 1,
+                last_seen: start,
+            }]
+        );
+    }
+
+    #[test]
+    fn event_db_dispositions() {
+        use crate::{Disposition, EventDisposition};
+        use chrono::Duration;
+
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+        let db = store.events();
+
+        let msg = example_message();
+        let time = msg.time;
+        let first = db.put(&msg).unwrap();
+        let mut msg = example_message();
+        msg.time = time + Duration::hours(1);
+        let second = db.put(&msg).unwrap();
+
+        let false_positive = EventDisposition {
+            disposition: Disposition::FalsePositive,
+            comment: Some("internal scanner".to_string()),
+        };
+        let true_positive = EventDisposition {
+            disposition: Disposition::TruePositive,
+            comment: None,
+        };
+        assert_eq!(db.disposition(first).unwrap(), None);
+        db.set_disposition(first, &false_positive).unwrap();
+        db.set_disposition(second, &false_positive).unwrap();
+        db.set_disposition(second, &true_positive).unwrap();
+        assert!(db.set_disposition(second + 1, &true_positive).is_err());
+        assert_eq!(db.disposition(first).unwrap(), Some(false_positive));
+
+        let range = time..time + Duration::hours(2);
+        assert_eq!(
+            db.find_by_disposition(Disposition::FalsePositive, range.clone())
+                .unwrap(),
+            [(first, Some("internal scanner".to_string()))]
+        );
+        assert_eq!(
+            db.find_by_disposition(Disposition::TruePositive, range.clone())
+                .unwrap(),
+            [(second, None)]
+        );
+        assert!(db
+            .find_by_disposition(Disposition::TruePositive, time..time + Duration::minutes(1))
+            .unwrap()
+            .is_empty());
+
+        assert!(db.clear_disposition(second).unwrap());
+        assert!(!db.clear_disposition(second).unwrap());
+        db.delete_range(time, time + Duration::minutes(1), &[])
+            .unwrap();
+        assert_eq!(db.disposition(first).unwrap(), None);
+        assert!(db
+            .find_by_disposition(Disposition::FalsePositive, range)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn event_db_delete_range() {
+        use chrono::Duration;
+
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+
+        let store = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+        let db = store.events();
+
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        for hours in 0..3 {
+            let mut msg = example_message();
+            msg.time = start + Duration::hours(hours);
+            db.put(&msg).unwrap();
+        }
+        let end = start + Duration::hours(2);
+
+        assert_eq!(
+            db.delete_range(start, end, &[EventKind::HttpThreat])
+                .unwrap(),
+            0
+        );
+        assert_eq!(db.iter_forward().count(), 3);
+
+        assert_eq!(db.delete_range(start, end, &[]).unwrap(), 2);
+        let mut iter = db.iter_forward();
+        let (key, _) = iter.next().unwrap().unwrap();
+        assert_eq!(key >> 64, i128::from(end.timestamp_nanos_opt().unwrap()));
+        assert!(iter.next().is_none());
+
+        // A start beyond the representable range selects from the beginning.
+        assert_eq!(
+            db.delete_range(DateTime::<Utc>::MIN_UTC, start + Duration::hours(3), &[])
+                .unwrap(),
+            1
+        );
+        assert!(db.iter_forward().next().is_none());
+    }
 
-#[derive(Debug)]
-#[allow(clippy::module_name_repetitions)]
-pub enum InvalidEvent {
-    Key(Box<[u8]>),
-    Value(Box<[u8]>),
-}
+    #[test]
+    fn event_db_delete_range_out_of_nanos() {
+        use chrono::Duration;
 
-pub type Id = u32;
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
 
-#[derive(Clone, Deserialize, Serialize)]
-pub struct FilterEndpoint {
-    pub direction: Option<TrafficDirection>,
-    pub predefined: Option<Id>,
-    pub custom: Option<HostNetworkGroup>,
-}
+        let store = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+        let db = store.events();
 
-/// Traffic flow direction.
-#[derive(Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
-pub enum FlowKind {
-    Inbound,
-    Outbound,
-    Internal,
-}
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        for hours in 0..3 {
+            let mut msg = example_message();
+            msg.time = start + Duration::hours(hours);
+            db.put(&msg).unwrap();
+        }
 
-/// Possible network types of `CustomerNetwork`.
-#[derive(Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
-pub enum NetworkType {
-    Intranet,
-    Extranet,
-    Gateway,
-}
+        let future = Utc.with_ymd_and_hms(2300, 1, 1, 0, 0, 0).unwrap();
+        let far_future = Utc.with_ymd_and_hms(2400, 1, 1, 0, 0, 0).unwrap();
+        assert_eq!(db.delete_range(future, far_future, &[]).unwrap(), 0);
+        let past = Utc.with_ymd_and_hms(1600, 1, 1, 0, 0, 0).unwrap();
+        let less_past = Utc.with_ymd_and_hms(1650, 1, 1, 0, 0, 0).unwrap();
+        assert_eq!(db.delete_range(past, less_past, &[]).unwrap(), 0);
+        assert_eq!(db.iter_forward().count(), 3);
+    }
 
-#[derive(Clone, Copy, Deserialize, Eq, PartialEq, Serialize)]
-pub enum TrafficDirection {
-    From,
-    To,
-}
+    #[test]
+    fn event_db_range_out_of_nanos() {
+        use chrono::Duration;
 
-pub fn find_ip_country(locator: &mut ip2location::DB, addr: IpAddr) -> String {
-    locator
-        .ip_lookup(addr)
-        .map(|r| get_record_country_short_name(&r))
-        .ok()
-        .flatten()
-        .unwrap_or_else(|| "XX".to_string())
-}
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
 
-fn eq_ip_country(
-    locator: &mut MutexGuard<ip2location::DB>,
-    addr: IpAddr,
-    country: [u8; 2],
-) -> bool {
-    locator
-        .ip_lookup(addr)
-        .ok()
-        .and_then(|r| get_record_country_short_name(&r))
-        .map_or(false, |c| c.as_bytes() == country)
-}
+        let store = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+        let db = store.events();
 
-fn get_record_country_short_name(record: &ip2location::Record) -> Option<String> {
-    use ip2location::Record;
-    match record {
-        Record::ProxyDb(r) => r
-            .country
-            .as_ref()
-            .map(|c| c.short_name.clone().into_owned()),
-        Record::LocationDb(r) => r
-            .country
-            .as_ref()
-            .map(|c| c.short_name.clone().into_owned()),
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        for hours in 0..3 {
+            let mut msg = example_message();
+            msg.time = start + Duration::hours(hours);
+            db.put(&msg).unwrap();
+        }
+
+        // A bound before the representable range selects from the beginning,
+        // and one after it selects to the end.
+        let past = Utc.with_ymd_and_hms(1600, 1, 1, 0, 0, 0).unwrap();
+        let future = Utc.with_ymd_and_hms(2300, 1, 1, 0, 0, 0).unwrap();
+        let addr = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+        assert_eq!(db.iter_range(past, future, None).count(), 3);
+        let counts = db
+            .count_by_interval(past, future, EventInterval::Day)
+            .unwrap();
+        assert_eq!(counts.iter().map(|c| c.count).sum::<usize>(), 3);
+        assert_eq!(db.find_by_addr(addr, past..future).unwrap().len(), 3);
+        assert_eq!(db.sample(past..future, 1).unwrap().len(), 1);
+
+        // A range entirely outside the representable range selects nothing.
+        let less_past = Utc.with_ymd_and_hms(1650, 1, 1, 0, 0, 0).unwrap();
+        let far_future = Utc.with_ymd_and_hms(2400, 1, 1, 0, 0, 0).unwrap();
+        assert_eq!(db.iter_range(past, less_past, None).count(), 0);
+        assert_eq!(db.iter_range(future, far_future, None).count(), 0);
+        assert!(db.find_by_addr(addr, past..less_past).unwrap().is_empty());
+        assert!(db.sample(future..far_future, 10).unwrap().is_empty());
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::{
-        event::DgaFields, event::DnsEventFields, DomainGenerationAlgorithm, EventKind,
-        EventMessage, Store,
-    };
-    use bincode::Options;
-    use chrono::{TimeZone, Utc};
-    use std::{
-        net::{IpAddr, Ipv4Addr},
-        sync::Arc,
-    };
+    #[test]
+    fn tls_certificate_anomaly() {
+        use crate::{event::TlsCertificateAnomalyFields, EventCategory};
 
-    fn example_message() -> EventMessage {
-        let codec = bincode::DefaultOptions::new();
-        let fields = DnsEventFields {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+        let db = store.events();
+
+        let fields = TlsCertificateAnomalyFields {
             source: "collector1".to_string(),
-            session_end_time: Utc::now(),
             src_addr: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
             src_port: 10000,
             dst_addr: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)),
-            dst_port: 53,
-            proto: 17,
-            query: "foo.com".to_string(),
-            answer: vec!["1.1.1.1".to_string()],
-            trans_id: 1,
+            dst_port: 443,
+            proto: 6,
+            last_time: 1,
+            server_name: "example.com".to_string(),
+            ja3: "ja3".to_string(),
+            ja3s: "ja3s".to_string(),
+            ja4: "ja4".to_string(),
+            serial: "01".to_string(),
+            subject_common_name: "example.com".to_string(),
+            issuer_common_name: "example.com".to_string(),
+            validity_not_before: 0,
+            validity_not_after: 1,
+            self_signed: true,
+            expired: true,
+            confidence: 0.9,
+        };
+        let msg = EventMessage {
+            time: Utc.with_ymd_and_hms(1970, 1, 1, 0, 1, 1).unwrap(),
+            kind: EventKind::TlsCertificateAnomaly,
+            fields: bincode::serialize(&fields).expect("serializable"),
+        };
+        assert_eq!(
+            format!("{msg}"),
+            "1970-01-01T00:01:01+00:00,TlsCertificateAnomaly,127.0.0.1,10000,127.0.0.2,443,6,\
+             TLS Certificate Anomaly,3,example.com,self-signed|expired"
+        );
+        assert_eq!(
+            EventKind::TlsCertificateAnomaly.category(),
+            Some(EventCategory::CommandAndControl)
+        );
+
+        db.put(&msg).unwrap();
+        let (_, event) = db.iter_forward().next().unwrap().unwrap();
+        let Event::TlsCertificateAnomaly(anomaly) = &event else {
+            panic!("unexpected event");
+        };
+        assert!(anomaly.self_signed && anomaly.expired);
+        assert_eq!(event.category(), EventCategory::CommandAndControl);
+    }
+
+    #[test]
+    fn lateral_movement() {
+        use crate::{
+            event::{DceRpcLateralMovementFields, SmbLateralMovementFields},
+            EventCategory,
+        };
+
+        let time = Utc.with_ymd_and_hms(1970, 1, 1, 0, 1, 1).unwrap();
+        let smb = SmbLateralMovementFields {
+            source: "collector1".to_string(),
+            src_addr: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+            src_port: 10000,
+            dst_addr: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)),
+            dst_port: 445,
+            proto: 6,
+            last_time: 1,
+            command: 0x75,
+            path: "\\\\host\\ADMIN$".to_string(),
+            service: "A:".to_string(),
+            file_name: "PSEXESVC.exe".to_string(),
+            confidence: 0.7,
+        };
+        let msg = EventMessage {
+            time,
+            kind: EventKind::SmbLateralMovement,
+            fields: bincode::serialize(&smb).expect("serializable"),
+        };
+        assert_eq!(
+            format!("{msg}"),
+            "1970-01-01T00:01:01+00:00,SmbLateralMovement,127.0.0.1,10000,127.0.0.2,445,6,\
+             SMB Lateral Movement,3,\\\\host\\ADMIN$,PSEXESVC.exe"
+        );
+
+        let dcerpc = DceRpcLateralMovementFields {
+            source: "collector1".to_string(),
+            src_addr: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+            src_port: 10001,
+            dst_addr: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)),
+            dst_port: 445,
+            proto: 6,
+            last_time: 1,
             rtt: 1,
-            qclass: 0,
-            qtype: 0,
-            rcode: 0,
-            aa_flag: false,
-            tc_flag: false,
-            rd_flag: false,
-            ra_flag: false,
-            ttl: vec![1; 5],
-            confidence: 0.8,
+            named_pipe: "svcctl".to_string(),
+            endpoint: "svcctl".to_string(),
+            operation: "CreateServiceW".to_string(),
+            confidence: 0.7,
         };
-        EventMessage {
-            time: Utc::now(),
-            kind: EventKind::DnsCovertChannel,
-            fields: codec.serialize(&fields).expect("serializable"),
-        }
+        let msg = EventMessage {
+            time,
+            kind: EventKind::DceRpcLateralMovement,
+            fields: bincode::serialize(&dcerpc).expect("serializable"),
+        };
+        assert_eq!(
+            format!("{msg}"),
+            "1970-01-01T00:01:01+00:00,DceRpcLateralMovement,127.0.0.1,10001,127.0.0.2,445,6,\
+             DCE-RPC Lateral Movement,3,svcctl,svcctl,CreateServiceW"
+        );
+
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+        let db = store.events();
+        db.put(&msg).unwrap();
+        let (_, event) = db.iter_forward().next().unwrap().unwrap();
+        assert!(matches!(event, Event::DceRpcLateralMovement(_)));
+        assert_eq!(event.category(), EventCategory::LateralMovement);
+        assert_eq!(
+            EventKind::SmbLateralMovement.category(),
+            Some(EventCategory::LateralMovement)
+        );
     }
 
-    #[tokio::test]
-    async fn event_db_put() {
+    #[test]
+    fn icmp_tunnel() {
+        use crate::{event::IcmpTunnelFields, EventCategory};
+
+        let fields = IcmpTunnelFields {
+            source: "collector1".to_string(),
+            src_addr: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+            dst_addr: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)),
+            proto: 1,
+            last_time: 1,
+            icmp_type: 8,
+            icmp_code: 0,
+            packet_count: 100,
+            max_payload_size: 1400,
+            total_payload_size: 120_000,
+            payload_entropy: 7.5,
+            confidence: 0.9,
+        };
+        let msg = EventMessage {
+            time: Utc.with_ymd_and_hms(1970, 1, 1, 0, 1, 1).unwrap(),
+            kind: EventKind::IcmpTunnel,
+            fields: bincode::serialize(&fields).expect("serializable"),
+        };
+        assert_eq!(
+            format!("{msg}"),
+            "1970-01-01T00:01:01+00:00,IcmpTunnel,127.0.0.1,0,127.0.0.2,0,1,ICMP Tunnel,3,8,0,1400,7.5"
+        );
+
         let db_dir = tempfile::tempdir().unwrap();
         let backup_dir = tempfile::tempdir().unwrap();
+        let store = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+        let db = store.events();
+        db.put(&msg).unwrap();
+        let (_, event) = db.iter_forward().next().unwrap().unwrap();
+        let Event::IcmpTunnel(tunnel) = &event else {
+            panic!("unexpected event");
+        };
+        assert_eq!(tunnel.max_payload_size, 1400);
+        assert_eq!(event.category(), EventCategory::CommandAndControl);
+    }
 
-        let store = Arc::new(Store::new(db_dir.path(), backup_dir.path()).unwrap());
+    #[test]
+    fn custom_detection() {
+        use crate::{
+            event::{CustomDetectionFields, CustomFieldValue},
+            EventCategory,
+        };
+        use std::num::NonZeroU8;
+
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Store::new(db_dir.path(), backup_dir.path()).unwrap();
         let db = store.events();
-        assert!(db.iter_forward().next().is_none());
 
-        let msg = example_message();
+        let fields = CustomDetectionFields {
+            source: "detector1".to_string(),
+            src_addr: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+            src_port: 10000,
+            dst_addr: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)),
+            dst_port: 8080,
+            proto: 6,
+            name: "beaconing".to_string(),
+            category: EventCategory::Exfiltration,
+            level: NonZeroU8::new(4).unwrap(),
+            confidence: 0.6,
+            fields: [
+                ("interval".to_string(), CustomFieldValue::Integer(60)),
+                ("jitter".to_string(), CustomFieldValue::Float(0.5)),
+            ]
+            .into_iter()
+            .collect(),
+        };
+        let time = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let msg = EventMessage {
+            time,
+            kind: EventKind::CustomDetection,
+            fields: bincode::serialize(&fields).expect("serializable"),
+        };
+        assert_eq!(
+            format!("{msg}"),
+            "2024-01-01T00:00:00+00:00,CustomDetection,127.0.0.1,10000,127.0.0.2,8080,6,\
+             beaconing,4,interval=60;jitter=0.5"
+        );
         db.put(&msg).unwrap();
-        let mut iter = db.iter_forward();
-        assert!(iter.next().is_some());
-        assert!(iter.next().is_none());
 
-        db.put(&msg).unwrap();
-        let mut iter = db.iter_forward();
-        assert!(iter.next().is_some());
-        assert!(iter.next().is_some());
-        assert!(iter.next().is_none());
+        let (_, event) = db.iter_forward().next().unwrap().unwrap();
+        let Event::CustomDetection(detection) = &event else {
+            panic!("unexpected event");
+        };
+        assert_eq!(detection.name, "beaconing");
+        assert_eq!(event.category(), EventCategory::Exfiltration);
+        assert_eq!(EventKind::CustomDetection.category(), None);
+
+        let end = time + chrono::Duration::hours(1);
+        let exfiltration = [EventCategory::Exfiltration];
+        assert_eq!(db.iter_range(time, end, Some(&exfiltration)).count(), 1);
+        let impact = [EventCategory::Impact];
+        assert_eq!(db.iter_range(time, end, Some(&impact)).count(), 0);
+        let counts = db
+            .count_by_interval(time, end, EventInterval::Hour)
+            .unwrap();
+        assert_eq!(counts.len(), 1);
+        assert_eq!(counts[0].category, EventCategory::Exfiltration);
     }
 
     #[tokio::test]
@@ -2327,6 +5218,53 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn event_message_to_syslog() {
+        use crate::{SyslogMapping, SyslogTarget};
+
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+        let mappings = store.syslog_mapping_map();
+
+        let msg = example_message();
+        let (_, event) = crate::event::parse_event(
+            Box::from((i128::from(EventKind::DnsCovertChannel as u32) << 32).to_be_bytes()),
+            Box::from(msg.fields.as_slice()),
+        )
+        .unwrap();
+        assert_eq!(
+            EventKind::DnsCovertChannel.name(),
+            Some(event.as_match().kind())
+        );
+        assert_eq!(
+            EventKind::from_name("block list stmp"),
+            Some(EventKind::BlockListSmtp)
+        );
+        let severity = 7 - event.as_match().level().get();
+        assert_eq!(
+            msg.to_syslog(&mappings).unwrap(),
+            format!("<{}>{msg}", 16 * 8 + severity)
+        );
+
+        mappings
+            .put(SyslogMapping {
+                target: SyslogTarget::Category(event.category()),
+                facility: 4,
+                severity: 1,
+            })
+            .unwrap();
+        assert_eq!(msg.to_syslog(&mappings).unwrap(), format!("<33>{msg}"));
+        mappings
+            .put(SyslogMapping {
+                target: SyslogTarget::Kind("dns covert channel".to_string()),
+                facility: 1,
+                severity: 3,
+            })
+            .unwrap();
+        assert_eq!(msg.to_syslog(&mappings).unwrap(), format!("<11>{msg}"));
+    }
+
     #[tokio::test]
     async fn event_db_backup() {
         use rocksdb::backup::{BackupEngine, BackupEngineOptions, RestoreOptions};
@@ -2403,4 +5341,139 @@ mod tests {
         assert_eq!(info.len(), 1);
         assert_eq!(info[0].backup_id, 1);
     }
+
+    #[test]
+    fn triage_block_list_dns() {
+        use crate::{
+            event::RecordType, BlockListDns, Ti, TiCmpKind, Tidb, TidbKind, TidbRule, TriagePolicy,
+        };
+
+        let event = Event::BlockList(RecordType::Dns(BlockListDns {
+            time: Utc::now(),
+            source: "collector1".to_string(),
+            src_addr: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+            src_port: 10000,
+            dst_addr: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)),
+            dst_port: 53,
+            proto: 17,
+            last_time: 0,
+            query: "pool.evil.example".to_string(),
+            answer: vec![],
+            trans_id: 1,
+            rtt: 1,
+            qclass: 0,
+            qtype: 0,
+            rcode: 0,
+            aa_flag: false,
+            tc_flag: false,
+            rd_flag: false,
+            ra_flag: false,
+            ttl: vec![],
+            triage_scores: None,
+        }));
+        let tidb = Tidb {
+            id: 1,
+            name: "domains".to_string(),
+            description: None,
+            kind: TidbKind::Token,
+            version: "1".to_string(),
+            patterns: vec![TidbRule {
+                rule_id: 1,
+                name: "rule".to_string(),
+                description: None,
+                references: None,
+                samples: None,
+                signatures: Some(vec!["evil.example".to_string()]),
+                confidence: None,
+                valid_until: None,
+            }],
+        };
+        let policy = TriagePolicy {
+            id: 1,
+            name: "policy".to_string(),
+            ti_db: vec![Ti {
+                ti_name: "domains".to_string(),
+                kind: TiCmpKind::Domain,
+                weight: Some(2.0),
+            }],
+            packet_attr: vec![],
+            response: vec![],
+            confidence: vec![],
+            customer_ids: vec![],
+            network_ids: vec![],
+            enabled: true,
+            active_from: None,
+            active_until: None,
+            creation_time: Utc::now(),
+        };
+        assert!((event.triage(&policy, &[tidb], Utc::now()).score - 2.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn triage_windows_threat_file_hash() {
+        use crate::{Ti, TiCmpKind, Tidb, TidbKind, TidbRule, TriagePolicy, WindowsThreat};
+
+        let hash = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        let event = |content: String| {
+            Event::WindowsThreat(WindowsThreat {
+                time: Utc::now(),
+                source: "collector1".to_string(),
+                service: "sysmon".to_string(),
+                agent_name: "agent".to_string(),
+                agent_id: "1".to_string(),
+                process_guid: String::new(),
+                process_id: 1,
+                image: "C:\\evil.exe".to_string(),
+                user: String::new(),
+                content,
+                db_name: String::new(),
+                rule_id: 0,
+                matched_to: String::new(),
+                cluster_id: 0,
+                attack_kind: String::new(),
+                confidence: 1.0,
+                triage_scores: None,
+            })
+        };
+        let tidb = Tidb {
+            id: 1,
+            name: "hashes".to_string(),
+            description: None,
+            kind: TidbKind::Token,
+            version: "1".to_string(),
+            patterns: vec![TidbRule {
+                rule_id: 1,
+                name: "rule".to_string(),
+                description: None,
+                references: None,
+                samples: None,
+                signatures: Some(vec![hash.to_string()]),
+                confidence: None,
+                valid_until: None,
+            }],
+        };
+        let policy = TriagePolicy {
+            id: 1,
+            name: "policy".to_string(),
+            ti_db: vec![Ti {
+                ti_name: "hashes".to_string(),
+                kind: TiCmpKind::FileHash,
+                weight: Some(2.0),
+            }],
+            packet_attr: vec![],
+            response: vec![],
+            confidence: vec![],
+            customer_ids: vec![],
+            network_ids: vec![],
+            enabled: true,
+            active_from: None,
+            active_until: None,
+            creation_time: Utc::now(),
+        };
+        let tidbs = [tidb];
+        let matching = event(format!("Hashes: MD5=0123456789abcdef,SHA256={hash}"));
+        assert!((matching.triage(&policy, &tidbs, Utc::now()).score - 2.0).abs() < f64::EPSILON);
+        let other = event(format!("CommandLine: {hash}"));
+        assert!(other.triage(&policy, &tidbs, Utc::now()).score.abs() < f64::EPSILON);
+    }
 }
@@ -2,10 +2,16 @@ use super::{
     eq_ip_country, EventCategory, EventFilter, FlowKind, LearningMethod, TrafficDirection,
     TriagePolicy,
 };
+use crate::{
+    tables::compiled_regex, AttrCmpKind, Confidence, PacketAttr, ResponseKind, Ti, TiCmpKind, Tidb,
+    ValueKind,
+};
 use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
 use num_traits::ToPrimitive;
 use serde::{Deserialize, Serialize};
 use std::{
+    cmp::Ordering,
     net::IpAddr,
     num::NonZeroU8,
     sync::{Arc, Mutex},
@@ -24,7 +30,89 @@ pub(super) trait Match {
     fn kind(&self) -> &str;
     fn source(&self) -> &str;
     fn confidence(&self) -> Option<f32>;
-    fn score_by_packet_attr(&self, triage: &TriagePolicy) -> f64;
+
+    /// Returns the value of the packet attribute `name`, or `None` if the
+    /// event doesn't have the attribute.
+    ///
+    /// An event type with attributes other than the common ones overrides
+    /// this, falling back to [`common_packet_attr`].
+    fn packet_attr(&self, name: &str) -> Option<AttrValue> {
+        common_packet_attr(self, name)
+    }
+
+    /// Returns the packet attributes of the event other than the common ones,
+    /// as pairs of a name and a value.
+    ///
+    /// An event type whose attributes aren't in [`PACKET_ATTR_NAMES`], such as
+    /// a custom detection, overrides this.
+    fn extra_packet_attrs(&self) -> Vec<(String, AttrValue)> {
+        PACKET_ATTR_NAMES
+            .iter()
+            .filter_map(|name| Some(((*name).to_string(), self.packet_attr(name)?)))
+            .collect()
+    }
+
+    /// Returns the values of the event to look up in threat-intelligence
+    /// databases of `kind`.
+    ///
+    /// An event type with values not exposed as packet attributes, such as
+    /// file hashes, overrides this, falling back to [`common_ti_values`].
+    fn ti_values(&self, kind: TiCmpKind) -> Vec<String> {
+        common_ti_values(self, kind)
+    }
+
+    /// Evaluates the event against `policy` at `now`, looking up the
+    /// threat-intelligence databases referred to by the policy in `tidbs`. The
+    /// threat-intelligence rules expired at `now` are skipped.
+    fn triage(&self, policy: &TriagePolicy, tidbs: &[Tidb], now: DateTime<Utc>) -> TriageResult {
+        let mut score = 0.0;
+        let mut matched = Vec::new();
+        for ti in &policy.ti_db {
+            let Some(tidb) = tidbs.iter().find(|tidb| tidb.name == ti.ti_name) else {
+                continue;
+            };
+            if self
+                .ti_values(ti.kind)
+                .iter()
+                .any(|value| tidb.matches_as_at(ti.kind, value, now))
+            {
+                score += ti.weight.unwrap_or(1.0);
+                matched.push(MatchedRule::Ti(ti.clone()));
+            }
+        }
+        for attr in &policy.packet_attr {
+            if self
+                .packet_attr(&attr.attr_name)
+                .is_some_and(|value| attr_matches(attr, &value))
+            {
+                score += attr.weight.unwrap_or(1.0);
+                matched.push(MatchedRule::PacketAttr(attr.clone()));
+            }
+        }
+        for conf in &policy.confidence {
+            if conf.threat_category == self.category()
+                && conf.threat_kind.to_lowercase() == self.kind().to_lowercase()
+                && self.confidence().map_or(true, |c| {
+                    c.to_f64().expect("safe: f32 -> f64") >= conf.confidence
+                })
+            {
+                score += conf.weight.unwrap_or(1.0);
+                matched.push(MatchedRule::Confidence(conf.clone()));
+            }
+        }
+        let response = policy
+            .response
+            .iter()
+            .filter(|response| score >= response.minimum_score)
+            .max_by(|a, b| a.minimum_score.total_cmp(&b.minimum_score))
+            .map(|response| response.kind);
+        TriageResult {
+            policy_id: policy.id,
+            score,
+            matched,
+            response,
+        }
+    }
 
     /// Returns whether the event matches the filter and the triage scores. The triage scores are
     /// only returned if the event matches the filter.
@@ -100,6 +188,24 @@ pub(super) trait Match {
             }
         }
 
+        if let Some(ports) = &filter.src_ports {
+            if ports.iter().all(|range| !range.contains(&self.src_port())) {
+                return Ok((false, None));
+            }
+        }
+
+        if let Some(ports) = &filter.dst_ports {
+            if ports.iter().all(|range| !range.contains(&self.dst_port())) {
+                return Ok((false, None));
+            }
+        }
+
+        if let Some(protocols) = &filter.protocols {
+            if !protocols.contains(&self.proto()) {
+                return Ok((false, None));
+            }
+        }
+
         if let Some((kinds, internal)) = &filter.directions {
             let internal_src = internal.iter().any(|net| net.contains(self.src_addr()));
             let internal_dst = internal.iter().any(|net| net.contains(self.dst_addr()));
@@ -184,17 +290,11 @@ pub(super) trait Match {
                 let triage_scores = triage_policies
                     .iter()
                     .filter_map(|triage| {
-                        let score = self.score_by_ti_db(triage)
-                            + self.score_by_packet_attr(triage)
-                            + self.score_by_confidence(triage);
-                        if triage.response.iter().any(|r| score >= r.minimum_score) {
-                            Some(TriageScore {
-                                policy_id: triage.id,
-                                score,
-                            })
-                        } else {
-                            None
-                        }
+                        let result = self.triage(triage, &[], Utc::now());
+                        result.response.map(|_| TriageScore {
+                            policy_id: triage.id,
+                            score: result.score,
+                        })
                     })
                     .collect::<Vec<_>>();
                 if triage_scores.is_empty() {
@@ -206,28 +306,227 @@ pub(super) trait Match {
 
         Ok((true, None))
     }
+}
 
-    fn score_by_ti_db(&self, _triage: &TriagePolicy) -> f64 {
-        // TODO: implement
-        0.0
+/// The names of the packet attributes, other than the common ones, of the
+/// event types with a fixed set of attributes. A new attribute returned by
+/// [`Match::packet_attr`] should be added here too.
+pub(super) const PACKET_ATTR_NAMES: &[&str] = &[
+    // DCE/RPC, SMB
+    "named_pipe",
+    "endpoint",
+    "operation",
+    "path",
+    "service",
+    "file_name",
+    "command",
+    // DNS
+    "query",
+    "qclass",
+    "qtype",
+    "rcode",
+    "rtt",
+    // HTTP
+    "method",
+    "host",
+    "uri",
+    "referer",
+    "version",
+    "user_agent",
+    "status_msg",
+    "username",
+    "cookie",
+    "content_encoding",
+    "content_type",
+    "cache_control",
+    "request_len",
+    "response_len",
+    "status_code",
+    "duration",
+    // ICMP
+    "icmp_type",
+    "icmp_code",
+    "packet_count",
+    "max_payload_size",
+    "total_payload_size",
+    "payload_entropy",
+    // SMTP
+    "mailfrom",
+    "from",
+    "to",
+    "subject",
+    "agent",
+    // TLS
+    "server_name",
+    "alpn_protocol",
+    "ja3",
+    "ja3s",
+    "ja4",
+    "serial",
+    "subject_common_name",
+    "subject_alt_name",
+    "issuer_common_name",
+    "cipher",
+    "validity_not_before",
+    "validity_not_after",
+    "self_signed",
+    "expired",
+];
+
+/// The value of a packet attribute of an event.
+#[derive(Clone, Debug, PartialEq)]
+pub(super) enum AttrValue {
+    String(String),
+    Integer(i64),
+    Float(f64),
+}
+
+/// Returns the value of the packet attribute `name` common to all events.
+pub(super) fn common_packet_attr<M: Match + ?Sized>(event: &M, name: &str) -> Option<AttrValue> {
+    match name {
+        "src_addr" => Some(AttrValue::String(event.src_addr().to_string())),
+        "src_port" => Some(AttrValue::Integer(event.src_port().into())),
+        "dst_addr" => Some(AttrValue::String(event.dst_addr().to_string())),
+        "dst_port" => Some(AttrValue::Integer(event.dst_port().into())),
+        "proto" => Some(AttrValue::Integer(event.proto().into())),
+        "source" => Some(AttrValue::String(event.source().to_string())),
+        _ => None,
     }
+}
 
-    fn score_by_confidence(&self, triage: &TriagePolicy) -> f64 {
-        triage.confidence.iter().fold(0.0, |score, conf| {
-            if conf.threat_category == self.category()
-                && conf.threat_kind.to_lowercase() == self.kind().to_lowercase()
-                && self.confidence().map_or(true, |c| {
-                    c.to_f64().expect("safe: f32 -> f64") >= conf.confidence
-                })
-            {
-                score + conf.weight.unwrap_or(1.0)
-            } else {
-                score
-            }
+/// Returns the values of the packet attributes of `event` to look up in
+/// threat-intelligence databases of `kind`.
+pub(super) fn common_ti_values<M: Match + ?Sized>(event: &M, kind: TiCmpKind) -> Vec<String> {
+    let names: &[&str] = match kind {
+        TiCmpKind::IpAddress => &["src_addr", "dst_addr"],
+        TiCmpKind::Domain => &["host", "query"],
+        TiCmpKind::Hostname => &["host"],
+        TiCmpKind::Uri => &["uri"],
+        TiCmpKind::Ja3 => &["ja3", "ja3s"],
+        TiCmpKind::Ja4 => &["ja4"],
+        // No packet attribute is a file hash.
+        TiCmpKind::FileHash => &[],
+        TiCmpKind::Email => &["mailfrom", "from", "to"],
+    };
+    names
+        .iter()
+        .filter_map(|name| match event.packet_attr(name) {
+            Some(AttrValue::String(value)) if !value.is_empty() => Some(value),
+            _ => None,
         })
+        .collect()
+}
+
+/// Decodes a value of a packet-attribute rule. A string is in UTF-8, and a
+/// number is in the little-endian byte order.
+fn decode_attr_value(kind: ValueKind, bytes: &[u8]) -> Option<AttrValue> {
+    match kind {
+        ValueKind::String => String::from_utf8(bytes.to_vec())
+            .ok()
+            .map(AttrValue::String),
+        ValueKind::Integer => bytes
+            .try_into()
+            .ok()
+            .map(|bytes| AttrValue::Integer(i64::from_le_bytes(bytes))),
+        ValueKind::Float => bytes
+            .try_into()
+            .ok()
+            .map(|bytes| AttrValue::Float(f64::from_le_bytes(bytes))),
+    }
+}
+
+/// Compares the value of an event to the value of a rule.
+#[allow(clippy::cast_precision_loss)]
+fn compare_attr_values(value: &AttrValue, other: &AttrValue) -> Option<Ordering> {
+    match (value, other) {
+        (AttrValue::String(a), AttrValue::String(b)) => Some(a.cmp(b)),
+        (AttrValue::Integer(a), AttrValue::Integer(b)) => Some(a.cmp(b)),
+        (AttrValue::Float(a), AttrValue::Float(b)) => a.partial_cmp(b),
+        (AttrValue::Integer(a), AttrValue::Float(b)) => (*a as f64).partial_cmp(b),
+        (AttrValue::Float(a), AttrValue::Integer(b)) => a.partial_cmp(&(*b as f64)),
+        _ => None,
+    }
+}
+
+/// Returns `true` if `value` satisfies the packet-attribute rule `attr`.
+fn attr_matches(attr: &PacketAttr, value: &AttrValue) -> bool {
+    let Some(first) = decode_attr_value(attr.value_kind, &attr.first_value) else {
+        return false;
+    };
+    let to_first = compare_attr_values(value, &first);
+    let in_range = |left_closed: bool, right_closed: bool| -> Option<bool> {
+        let second = decode_attr_value(attr.value_kind, attr.second_value.as_deref()?)?;
+        let to_second = compare_attr_values(value, &second)?;
+        let above = match to_first? {
+            Ordering::Greater => true,
+            Ordering::Equal => left_closed,
+            Ordering::Less => false,
+        };
+        let below = match to_second {
+            Ordering::Less => true,
+            Ordering::Equal => right_closed,
+            Ordering::Greater => false,
+        };
+        Some(above && below)
+    };
+    let contains = || match (value, &first) {
+        (AttrValue::String(value), AttrValue::String(first)) => Some(value.contains(first)),
+        _ => None,
+    };
+    let regex_matches = || match (value, &first) {
+        (AttrValue::String(value), AttrValue::String(pattern)) => {
+            compiled_regex(pattern).map(|regex| regex.is_match(value))
+        }
+        _ => None,
+    };
+    match attr.cmp_kind {
+        AttrCmpKind::Less => to_first == Some(Ordering::Less),
+        AttrCmpKind::Equal => to_first == Some(Ordering::Equal),
+        AttrCmpKind::Greater => to_first == Some(Ordering::Greater),
+        AttrCmpKind::LessOrEqual => matches!(to_first, Some(Ordering::Less | Ordering::Equal)),
+        AttrCmpKind::GreaterOrEqual => {
+            matches!(to_first, Some(Ordering::Greater | Ordering::Equal))
+        }
+        AttrCmpKind::NotEqual => to_first.is_some_and(|o| o != Ordering::Equal),
+        AttrCmpKind::Contain => contains() == Some(true),
+        AttrCmpKind::NotContain => contains() == Some(false),
+        AttrCmpKind::OpenRange => in_range(false, false) == Some(true),
+        AttrCmpKind::CloseRange => in_range(true, true) == Some(true),
+        AttrCmpKind::LeftOpenRange => in_range(false, true) == Some(true),
+        AttrCmpKind::RightOpenRange => in_range(true, false) == Some(true),
+        AttrCmpKind::NotOpenRange => in_range(false, false) == Some(false),
+        AttrCmpKind::NotCloseRange => in_range(true, true) == Some(false),
+        AttrCmpKind::NotLeftOpenRange => in_range(false, true) == Some(false),
+        AttrCmpKind::NotRightOpenRange => in_range(true, false) == Some(false),
+        AttrCmpKind::Regex => regex_matches() == Some(true),
+        AttrCmpKind::NotRegex => regex_matches() == Some(false),
     }
 }
 
+/// A rule of a triage policy matched by an event.
+#[derive(Clone, PartialEq, Deserialize, Serialize)]
+pub enum MatchedRule {
+    Ti(Ti),
+    PacketAttr(PacketAttr),
+    Confidence(Confidence),
+}
+
+/// The result of evaluating an event against a triage policy, returned by
+/// [`TriagePolicy::score`].
+#[derive(Clone, PartialEq)]
+pub struct TriageResult {
+    pub policy_id: u32,
+
+    /// The sum of the weights of the matched rules.
+    pub score: f64,
+
+    /// The rules matched by the event.
+    pub matched: Vec<MatchedRule>,
+
+    /// The response with the highest minimum score not above `score`, if any.
+    pub response: Option<ResponseKind>,
+}
+
 #[derive(Clone, Deserialize, Serialize)]
 pub struct TriageScore {
     pub policy_id: u32,
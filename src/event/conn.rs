@@ -1,4 +1,4 @@
-use super::{common::Match, EventCategory, TriagePolicy, TriageScore, MEDIUM};
+use super::{common::Match, EventCategory, TriageScore, MEDIUM};
 use chrono::{DateTime, Local, Utc};
 use serde::{Deserialize, Serialize};
 use std::{
@@ -109,11 +109,6 @@ impl Match for PortScan {
     fn confidence(&self) -> Option<f32> {
         None
     }
-
-    fn score_by_packet_attr(&self, _triage: &TriagePolicy) -> f64 {
-        // TODO: implement
-        0.0
-    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -218,11 +213,6 @@ impl Match for MultiHostPortScan {
     fn confidence(&self) -> Option<f32> {
         None
     }
-
-    fn score_by_packet_attr(&self, _triage: &TriagePolicy) -> f64 {
-        // TODO: implement
-        0.0
-    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -323,11 +313,6 @@ impl Match for ExternalDdos {
     fn confidence(&self) -> Option<f32> {
         None
     }
-
-    fn score_by_packet_attr(&self, _triage: &TriagePolicy) -> f64 {
-        // TODO: implement
-        0.0
-    }
 }
 
 #[derive(Deserialize, Serialize)]
@@ -451,9 +436,4 @@ impl Match for BlockListConn {
     fn confidence(&self) -> Option<f32> {
         None
     }
-
-    fn score_by_packet_attr(&self, _triage: &TriagePolicy) -> f64 {
-        // TODO: implement
-        0.0
-    }
 }
@@ -0,0 +1,190 @@
+#![allow(clippy::module_name_repetitions)]
+use super::{
+    common::{common_packet_attr, AttrValue, Match},
+    EventCategory, TriageScore,
+};
+use chrono::{DateTime, Local, Utc};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt, net::IpAddr, num::NonZeroU8};
+
+/// The value of a field of a [`CustomDetection`].
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum CustomFieldValue {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Bool(bool),
+    IpAddr(IpAddr),
+}
+
+impl fmt::Display for CustomFieldValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::String(value) => write!(f, "{value}"),
+            Self::Integer(value) => write!(f, "{value}"),
+            Self::Float(value) => write!(f, "{value}"),
+            Self::Bool(value) => write!(f, "{value}"),
+            Self::IpAddr(value) => write!(f, "{value}"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CustomDetectionFields {
+    pub source: String,
+    pub src_addr: IpAddr,
+    pub src_port: u16,
+    pub dst_addr: IpAddr,
+    pub dst_port: u16,
+    pub proto: u8,
+    /// The name of the detection, defined by the detector.
+    pub name: String,
+    pub category: EventCategory,
+    pub level: NonZeroU8,
+    pub confidence: f32,
+    pub fields: BTreeMap<String, CustomFieldValue>,
+}
+
+impl fmt::Display for CustomDetectionFields {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{},{},{},{},{},{},{},{}",
+            self.src_addr,
+            self.src_port,
+            self.dst_addr,
+            self.dst_port,
+            self.proto,
+            self.name,
+            self.level,
+            format_fields(&self.fields),
+        )
+    }
+}
+
+/// An event from a detector outside this crate, with a detector-defined
+/// name, category, and fields.
+pub struct CustomDetection {
+    pub time: DateTime<Utc>,
+    pub source: String,
+    pub src_addr: IpAddr,
+    pub src_port: u16,
+    pub dst_addr: IpAddr,
+    pub dst_port: u16,
+    pub proto: u8,
+    pub name: String,
+    pub category: EventCategory,
+    pub level: NonZeroU8,
+    pub confidence: f32,
+    pub fields: BTreeMap<String, CustomFieldValue>,
+    pub triage_scores: Option<Vec<TriageScore>>,
+}
+
+impl fmt::Display for CustomDetection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{},{},{},{},{},{},{},{}",
+            DateTime::<Local>::from(self.time).format("%Y-%m-%d %H:%M:%S"),
+            self.src_addr,
+            self.src_port,
+            self.dst_addr,
+            self.dst_port,
+            self.proto,
+            self.name,
+            format_fields(&self.fields),
+        )
+    }
+}
+
+impl CustomDetection {
+    pub(super) fn new(time: DateTime<Utc>, fields: CustomDetectionFields) -> Self {
+        Self {
+            time,
+            source: fields.source,
+            src_addr: fields.src_addr,
+            src_port: fields.src_port,
+            dst_addr: fields.dst_addr,
+            dst_port: fields.dst_port,
+            proto: fields.proto,
+            name: fields.name,
+            category: fields.category,
+            level: fields.level,
+            confidence: fields.confidence,
+            fields: fields.fields,
+            triage_scores: None,
+        }
+    }
+}
+
+impl Match for CustomDetection {
+    fn src_addr(&self) -> IpAddr {
+        self.src_addr
+    }
+
+    fn src_port(&self) -> u16 {
+        self.src_port
+    }
+
+    fn dst_addr(&self) -> IpAddr {
+        self.dst_addr
+    }
+
+    fn dst_port(&self) -> u16 {
+        self.dst_port
+    }
+
+    fn proto(&self) -> u8 {
+        self.proto
+    }
+
+    fn category(&self) -> EventCategory {
+        self.category
+    }
+
+    fn level(&self) -> NonZeroU8 {
+        self.level
+    }
+
+    fn kind(&self) -> &str {
+        &self.name
+    }
+
+    fn source(&self) -> &str {
+        self.source.as_str()
+    }
+
+    fn confidence(&self) -> Option<f32> {
+        Some(self.confidence)
+    }
+
+    fn packet_attr(&self, name: &str) -> Option<AttrValue> {
+        if let Some(value) = common_packet_attr(self, name) {
+            return Some(value);
+        }
+        Some(match self.fields.get(name)? {
+            CustomFieldValue::String(value) => AttrValue::String(value.clone()),
+            CustomFieldValue::Integer(value) => AttrValue::Integer(*value),
+            CustomFieldValue::Float(value) => AttrValue::Float(*value),
+            CustomFieldValue::Bool(value) => AttrValue::Integer((*value).into()),
+            CustomFieldValue::IpAddr(value) => AttrValue::String(value.to_string()),
+        })
+    }
+
+    fn extra_packet_attrs(&self) -> Vec<(String, AttrValue)> {
+        self.fields
+            .keys()
+            .filter(|name| common_packet_attr(self, name).is_none())
+            .filter_map(|name| Some((name.clone(), self.packet_attr(name)?)))
+            .collect()
+    }
+}
+
+/// Formats `fields` as "name=value" pairs separated by ";".
+fn format_fields(fields: &BTreeMap<String, CustomFieldValue>) -> String {
+    fields
+        .iter()
+        .map(|(name, value)| format!("{name}={value}"))
+        .collect::<Vec<_>>()
+        .join(";")
+}
@@ -1,4 +1,7 @@
-use super::{common::Match, EventCategory, TriagePolicy, TriageScore, MEDIUM};
+use super::{
+    common::{common_packet_attr, AttrValue, Match},
+    EventCategory, TriageScore, MEDIUM,
+};
 use chrono::{DateTime, Local, Utc};
 use serde::{Deserialize, Serialize};
 use std::{fmt, net::IpAddr, num::NonZeroU8};
@@ -119,9 +122,148 @@ impl Match for BlockListDceRpc {
     fn confidence(&self) -> Option<f32> {
         None
     }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DceRpcLateralMovementFields {
+    pub source: String,
+    pub src_addr: IpAddr,
+    pub src_port: u16,
+    pub dst_addr: IpAddr,
+    pub dst_port: u16,
+    pub proto: u8,
+    pub last_time: i64,
+    pub rtt: i64,
+    pub named_pipe: String,
+    pub endpoint: String,
+    pub operation: String,
+    pub confidence: f32,
+}
+
+impl fmt::Display for DceRpcLateralMovementFields {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{},{},{},{},{},DCE-RPC Lateral Movement,3,{},{},{}",
+            self.src_addr,
+            self.src_port,
+            self.dst_addr,
+            self.dst_port,
+            self.proto,
+            self.named_pipe,
+            self.endpoint,
+            self.operation,
+        )
+    }
+}
+
+/// Suspicious DCE-RPC activity that indicates lateral movement, such as the
+/// use of a named pipe of a remote execution tool like `PsExec`.
+pub struct DceRpcLateralMovement {
+    pub time: DateTime<Utc>,
+    pub source: String,
+    pub src_addr: IpAddr,
+    pub src_port: u16,
+    pub dst_addr: IpAddr,
+    pub dst_port: u16,
+    pub proto: u8,
+    pub last_time: i64,
+    pub rtt: i64,
+    pub named_pipe: String,
+    pub endpoint: String,
+    pub operation: String,
+    pub confidence: f32,
+    pub triage_scores: Option<Vec<TriageScore>>,
+}
+
+impl fmt::Display for DceRpcLateralMovement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{},{},{},{},{},{},DCE-RPC Lateral Movement,{},{},{}",
+            DateTime::<Local>::from(self.time).format("%Y-%m-%d %H:%M:%S"),
+            self.src_addr,
+            self.src_port,
+            self.dst_addr,
+            self.dst_port,
+            self.proto,
+            self.named_pipe,
+            self.endpoint,
+            self.operation,
+        )
+    }
+}
+
+impl DceRpcLateralMovement {
+    pub(super) fn new(time: DateTime<Utc>, fields: DceRpcLateralMovementFields) -> Self {
+        Self {
+            time,
+            source: fields.source,
+            src_addr: fields.src_addr,
+            src_port: fields.src_port,
+            dst_addr: fields.dst_addr,
+            dst_port: fields.dst_port,
+            proto: fields.proto,
+            last_time: fields.last_time,
+            rtt: fields.rtt,
+            named_pipe: fields.named_pipe,
+            endpoint: fields.endpoint,
+            operation: fields.operation,
+            confidence: fields.confidence,
+            triage_scores: None,
+        }
+    }
+}
+
+impl Match for DceRpcLateralMovement {
+    fn src_addr(&self) -> IpAddr {
+        self.src_addr
+    }
+
+    fn src_port(&self) -> u16 {
+        self.src_port
+    }
+
+    fn dst_addr(&self) -> IpAddr {
+        self.dst_addr
+    }
+
+    fn dst_port(&self) -> u16 {
+        self.dst_port
+    }
+
+    fn proto(&self) -> u8 {
+        self.proto
+    }
+
+    fn category(&self) -> EventCategory {
+        EventCategory::LateralMovement
+    }
+
+    fn level(&self) -> NonZeroU8 {
+        MEDIUM
+    }
+
+    fn kind(&self) -> &str {
+        "dcerpc lateral movement"
+    }
+
+    fn source(&self) -> &str {
+        self.source.as_str()
+    }
+
+    fn confidence(&self) -> Option<f32> {
+        Some(self.confidence)
+    }
 
-    fn score_by_packet_attr(&self, _triage: &TriagePolicy) -> f64 {
-        // TODO: implement
-        0.0
+    fn packet_attr(&self, name: &str) -> Option<AttrValue> {
+        let value = match name {
+            "named_pipe" => &self.named_pipe,
+            "endpoint" => &self.endpoint,
+            "operation" => &self.operation,
+            "rtt" => return Some(AttrValue::Integer(self.rtt)),
+            _ => return common_packet_attr(self, name),
+        };
+        Some(AttrValue::String(value.clone()))
     }
 }
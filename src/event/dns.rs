@@ -1,5 +1,8 @@
 #![allow(clippy::module_name_repetitions, clippy::struct_excessive_bools)]
-use super::{common::Match, EventCategory, TriagePolicy, TriageScore, MEDIUM};
+use super::{
+    common::{common_packet_attr, AttrValue, Match},
+    EventCategory, TriageScore, MEDIUM,
+};
 use chrono::{serde::ts_nanoseconds, DateTime, Local, Utc};
 use serde::{Deserialize, Serialize};
 use std::{fmt, net::IpAddr, num::NonZeroU8};
@@ -151,9 +154,15 @@ impl Match for DnsCovertChannel {
         Some(self.confidence)
     }
 
-    fn score_by_packet_attr(&self, _triage: &TriagePolicy) -> f64 {
-        // TODO: implement
-        0.0
+    fn packet_attr(&self, name: &str) -> Option<AttrValue> {
+        match name {
+            "query" => Some(AttrValue::String(self.query.clone())),
+            "qclass" => Some(AttrValue::Integer(self.qclass.into())),
+            "qtype" => Some(AttrValue::Integer(self.qtype.into())),
+            "rcode" => Some(AttrValue::Integer(self.rcode.into())),
+            "rtt" => Some(AttrValue::Integer(self.rtt)),
+            _ => common_packet_attr(self, name),
+        }
     }
 }
 
@@ -303,9 +312,15 @@ impl Match for CryptocurrencyMiningPool {
         None
     }
 
-    fn score_by_packet_attr(&self, _triage: &TriagePolicy) -> f64 {
-        // TODO: implement
-        0.0
+    fn packet_attr(&self, name: &str) -> Option<AttrValue> {
+        match name {
+            "query" => Some(AttrValue::String(self.query.clone())),
+            "qclass" => Some(AttrValue::Integer(self.qclass.into())),
+            "qtype" => Some(AttrValue::Integer(self.qtype.into())),
+            "rcode" => Some(AttrValue::Integer(self.rcode.into())),
+            "rtt" => Some(AttrValue::Integer(self.rtt)),
+            _ => common_packet_attr(self, name),
+        }
     }
 }
 
@@ -451,8 +466,14 @@ impl Match for BlockListDns {
         None
     }
 
-    fn score_by_packet_attr(&self, _triage: &TriagePolicy) -> f64 {
-        // TODO: implement
-        0.0
+    fn packet_attr(&self, name: &str) -> Option<AttrValue> {
+        match name {
+            "query" => Some(AttrValue::String(self.query.clone())),
+            "qclass" => Some(AttrValue::Integer(self.qclass.into())),
+            "qtype" => Some(AttrValue::Integer(self.qtype.into())),
+            "rcode" => Some(AttrValue::Integer(self.rcode.into())),
+            "rtt" => Some(AttrValue::Integer(self.rtt)),
+            _ => common_packet_attr(self, name),
+        }
     }
 }
@@ -1,5 +1,5 @@
 #![allow(clippy::module_name_repetitions)]
-use super::{common::Match, EventCategory, TriagePolicy, TriageScore, MEDIUM};
+use super::{common::Match, EventCategory, TriageScore, MEDIUM};
 use chrono::{DateTime, Local, Utc};
 use serde::{Deserialize, Serialize};
 use std::{fmt, net::IpAddr, num::NonZeroU8};
@@ -119,11 +119,6 @@ impl Match for FtpBruteForce {
     fn confidence(&self) -> Option<f32> {
         None
     }
-
-    fn score_by_packet_attr(&self, _triage: &TriagePolicy) -> f64 {
-        // TODO: implement
-        0.0
-    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -275,11 +270,6 @@ impl Match for FtpPlainText {
     fn confidence(&self) -> Option<f32> {
         None
     }
-
-    fn score_by_packet_attr(&self, _triage: &TriagePolicy) -> f64 {
-        // TODO: implement
-        0.0
-    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -431,9 +421,4 @@ impl Match for BlockListFtp {
     fn confidence(&self) -> Option<f32> {
         None
     }
-
-    fn score_by_packet_attr(&self, _triage: &TriagePolicy) -> f64 {
-        // TODO: implement
-        0.0
-    }
 }
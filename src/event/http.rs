@@ -1,9 +1,97 @@
-use super::{common::Match, EventCategory, EventFilter, TriagePolicy, TriageScore, LOW, MEDIUM};
+use super::{
+    common::{common_packet_attr, AttrValue, Match},
+    EventCategory, EventFilter, TriageScore, LOW, MEDIUM,
+};
 use aho_corasick::AhoCorasickBuilder;
 use chrono::{serde::ts_nanoseconds, DateTime, Local, Utc};
 use serde::{Deserialize, Serialize};
 use std::{fmt, net::IpAddr, num::NonZeroU8};
 
+/// The marker appended to a string field truncated when stored.
+pub const TRUNCATION_MARKER: &str = "...[truncated]";
+
+/// Truncates `value` to at most `max_len` bytes, at a character boundary, and
+/// appends [`TRUNCATION_MARKER`] if it is longer than `max_len` bytes. Returns
+/// `true` if `value` was truncated.
+fn truncate_field(value: &mut String, max_len: usize) -> bool {
+    if value.len() <= max_len {
+        return false;
+    }
+    let mut end = max_len;
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    value.truncate(end);
+    value.push_str(TRUNCATION_MARKER);
+    true
+}
+
+/// The fields of an HTTP event whose large string fields are truncated when
+/// stored.
+pub(super) trait LargeFields {
+    /// Returns the string fields that can be arbitrarily large, such as the
+    /// URI and the matched content of the request.
+    fn large_fields(&mut self) -> Vec<&mut String>;
+
+    /// Truncates the large string fields to at most `max_len` bytes each.
+    /// Returns `true` if any of them was truncated.
+    fn truncate(&mut self, max_len: usize) -> bool {
+        self.large_fields()
+            .into_iter()
+            .fold(false, |truncated, value| {
+                truncate_field(value, max_len) | truncated
+            })
+    }
+}
+
+impl LargeFields for HttpThreatFields {
+    fn large_fields(&mut self) -> Vec<&mut String> {
+        vec![
+            &mut self.uri,
+            &mut self.referer,
+            &mut self.user_agent,
+            &mut self.cookie,
+            &mut self.matched_to,
+        ]
+    }
+}
+
+impl LargeFields for DgaFields {
+    fn large_fields(&mut self) -> Vec<&mut String> {
+        vec![
+            &mut self.uri,
+            &mut self.referer,
+            &mut self.user_agent,
+            &mut self.cookie,
+        ]
+    }
+}
+
+impl LargeFields for NonBrowserFields {
+    fn large_fields(&mut self) -> Vec<&mut String> {
+        vec![
+            &mut self.uri,
+            &mut self.referrer,
+            &mut self.user_agent,
+            &mut self.cookie,
+        ]
+    }
+}
+
+impl LargeFields for BlockListHttpFields {
+    fn large_fields(&mut self) -> Vec<&mut String> {
+        let mut fields = vec![
+            &mut self.uri,
+            &mut self.referrer,
+            &mut self.user_agent,
+            &mut self.cookie,
+        ];
+        fields.extend(self.orig_filenames.iter_mut());
+        fields.extend(self.resp_filenames.iter_mut());
+        fields
+    }
+}
+
 #[derive(Deserialize)]
 pub(super) struct RepeatedHttpSessionsFields {
     source: String,
@@ -105,11 +193,6 @@ impl Match for RepeatedHttpSessions {
     fn confidence(&self) -> Option<f32> {
         None
     }
-
-    fn score_by_packet_attr(&self, _triage: &TriagePolicy) -> f64 {
-        // TODO: implement
-        0.0
-    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -149,6 +232,7 @@ pub struct HttpThreatFields {
 }
 
 // Syslog format: 5-tuple,attack-name,severity,content
+
 impl fmt::Display for HttpThreatFields {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let user_agent = self.user_agent.replace(',', " ");
@@ -315,9 +399,31 @@ impl Match for HttpThreat {
         Some(self.confidence)
     }
 
-    fn score_by_packet_attr(&self, _triage: &TriagePolicy) -> f64 {
-        // TODO: implement
-        0.0
+    fn packet_attr(&self, name: &str) -> Option<AttrValue> {
+        let value = match name {
+            "method" => &self.method,
+            "host" => &self.host,
+            "uri" => &self.uri,
+            "referer" => &self.referer,
+            "version" => &self.version,
+            "user_agent" => &self.user_agent,
+            "status_msg" => &self.status_msg,
+            "username" => &self.username,
+            "cookie" => &self.cookie,
+            "content_encoding" => &self.content_encoding,
+            "content_type" => &self.content_type,
+            "cache_control" => &self.cache_control,
+            "request_len" => return i64::try_from(self.request_len).ok().map(AttrValue::Integer),
+            "response_len" => {
+                return i64::try_from(self.response_len)
+                    .ok()
+                    .map(AttrValue::Integer)
+            }
+            "status_code" => return Some(AttrValue::Integer(self.status_code.into())),
+            "duration" => return Some(AttrValue::Integer(self.duration)),
+            _ => return common_packet_attr(self, name),
+        };
+        Some(AttrValue::String(value.clone()))
     }
 
     fn kind_matches(&self, filter: &EventFilter) -> bool {
@@ -520,9 +626,31 @@ impl Match for DomainGenerationAlgorithm {
         Some(self.confidence)
     }
 
-    fn score_by_packet_attr(&self, _triage: &TriagePolicy) -> f64 {
-        // TODO: implement
-        0.0
+    fn packet_attr(&self, name: &str) -> Option<AttrValue> {
+        let value = match name {
+            "method" => &self.method,
+            "host" => &self.host,
+            "uri" => &self.uri,
+            "referer" => &self.referer,
+            "version" => &self.version,
+            "user_agent" => &self.user_agent,
+            "status_msg" => &self.status_msg,
+            "username" => &self.username,
+            "cookie" => &self.cookie,
+            "content_encoding" => &self.content_encoding,
+            "content_type" => &self.content_type,
+            "cache_control" => &self.cache_control,
+            "request_len" => return i64::try_from(self.request_len).ok().map(AttrValue::Integer),
+            "response_len" => {
+                return i64::try_from(self.response_len)
+                    .ok()
+                    .map(AttrValue::Integer)
+            }
+            "status_code" => return Some(AttrValue::Integer(self.status_code.into())),
+            "duration" => return Some(AttrValue::Integer(self.duration)),
+            _ => return common_packet_attr(self, name),
+        };
+        Some(AttrValue::String(value.clone()))
     }
 }
 
@@ -685,9 +813,30 @@ impl Match for NonBrowser {
         None
     }
 
-    fn score_by_packet_attr(&self, _triage: &TriagePolicy) -> f64 {
-        // TODO: implement
-        0.0
+    fn packet_attr(&self, name: &str) -> Option<AttrValue> {
+        let value = match name {
+            "method" => &self.method,
+            "host" => &self.host,
+            "uri" => &self.uri,
+            "referer" => &self.referrer,
+            "version" => &self.version,
+            "user_agent" => &self.user_agent,
+            "status_msg" => &self.status_msg,
+            "username" => &self.username,
+            "cookie" => &self.cookie,
+            "content_encoding" => &self.content_encoding,
+            "content_type" => &self.content_type,
+            "cache_control" => &self.cache_control,
+            "request_len" => return i64::try_from(self.request_len).ok().map(AttrValue::Integer),
+            "response_len" => {
+                return i64::try_from(self.response_len)
+                    .ok()
+                    .map(AttrValue::Integer)
+            }
+            "status_code" => return Some(AttrValue::Integer(self.status_code.into())),
+            _ => return common_packet_attr(self, name),
+        };
+        Some(AttrValue::String(value.clone()))
     }
 }
 
@@ -861,8 +1010,29 @@ impl Match for BlockListHttp {
         None
     }
 
-    fn score_by_packet_attr(&self, _triage: &TriagePolicy) -> f64 {
-        // TODO: implement
-        0.0
+    fn packet_attr(&self, name: &str) -> Option<AttrValue> {
+        let value = match name {
+            "method" => &self.method,
+            "host" => &self.host,
+            "uri" => &self.uri,
+            "referer" => &self.referrer,
+            "version" => &self.version,
+            "user_agent" => &self.user_agent,
+            "status_msg" => &self.status_msg,
+            "username" => &self.username,
+            "cookie" => &self.cookie,
+            "content_encoding" => &self.content_encoding,
+            "content_type" => &self.content_type,
+            "cache_control" => &self.cache_control,
+            "request_len" => return i64::try_from(self.request_len).ok().map(AttrValue::Integer),
+            "response_len" => {
+                return i64::try_from(self.response_len)
+                    .ok()
+                    .map(AttrValue::Integer)
+            }
+            "status_code" => return Some(AttrValue::Integer(self.status_code.into())),
+            _ => return common_packet_attr(self, name),
+        };
+        Some(AttrValue::String(value.clone()))
     }
 }
@@ -0,0 +1,153 @@
+use super::{
+    common::{common_packet_attr, AttrValue, Match},
+    EventCategory, TriageScore, MEDIUM,
+};
+use chrono::{DateTime, Local, Utc};
+use serde::{Deserialize, Serialize};
+use std::{fmt, net::IpAddr, num::NonZeroU8};
+
+#[allow(clippy::module_name_repetitions)]
+#[derive(Serialize, Deserialize)]
+pub struct IcmpTunnelFields {
+    pub source: String,
+    pub src_addr: IpAddr,
+    pub dst_addr: IpAddr,
+    pub proto: u8,
+    pub last_time: i64,
+    pub icmp_type: u8,
+    pub icmp_code: u8,
+    pub packet_count: u64,
+    pub max_payload_size: u64,
+    pub total_payload_size: u64,
+    pub payload_entropy: f64,
+    pub confidence: f32,
+}
+
+impl fmt::Display for IcmpTunnelFields {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{},0,{},0,{},ICMP Tunnel,3,{},{},{},{}",
+            self.src_addr,
+            self.dst_addr,
+            self.proto,
+            self.icmp_type,
+            self.icmp_code,
+            self.max_payload_size,
+            self.payload_entropy,
+        )
+    }
+}
+
+/// ICMP packets whose payloads are anomalous, such as oversized or
+/// high-entropy payloads, indicating data tunneled through ICMP.
+#[allow(clippy::module_name_repetitions)]
+pub struct IcmpTunnel {
+    pub time: DateTime<Utc>,
+    pub source: String,
+    pub src_addr: IpAddr,
+    pub dst_addr: IpAddr,
+    pub proto: u8,
+    pub last_time: i64,
+    pub icmp_type: u8,
+    pub icmp_code: u8,
+    pub packet_count: u64,
+    pub max_payload_size: u64,
+    pub total_payload_size: u64,
+    pub payload_entropy: f64,
+    pub confidence: f32,
+    pub triage_scores: Option<Vec<TriageScore>>,
+}
+
+impl fmt::Display for IcmpTunnel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{},{},0,{},0,{},ICMP Tunnel,{},{},{},{}",
+            DateTime::<Local>::from(self.time).format("%Y-%m-%d %H:%M:%S"),
+            self.src_addr,
+            self.dst_addr,
+            self.proto,
+            self.icmp_type,
+            self.icmp_code,
+            self.max_payload_size,
+            self.payload_entropy,
+        )
+    }
+}
+
+impl IcmpTunnel {
+    pub(super) fn new(time: DateTime<Utc>, fields: IcmpTunnelFields) -> Self {
+        Self {
+            time,
+            source: fields.source,
+            src_addr: fields.src_addr,
+            dst_addr: fields.dst_addr,
+            proto: fields.proto,
+            last_time: fields.last_time,
+            icmp_type: fields.icmp_type,
+            icmp_code: fields.icmp_code,
+            packet_count: fields.packet_count,
+            max_payload_size: fields.max_payload_size,
+            total_payload_size: fields.total_payload_size,
+            payload_entropy: fields.payload_entropy,
+            confidence: fields.confidence,
+            triage_scores: None,
+        }
+    }
+}
+
+impl Match for IcmpTunnel {
+    fn src_addr(&self) -> IpAddr {
+        self.src_addr
+    }
+
+    fn src_port(&self) -> u16 {
+        0
+    }
+
+    fn dst_addr(&self) -> IpAddr {
+        self.dst_addr
+    }
+
+    fn dst_port(&self) -> u16 {
+        0
+    }
+
+    fn proto(&self) -> u8 {
+        self.proto
+    }
+
+    fn category(&self) -> EventCategory {
+        EventCategory::CommandAndControl
+    }
+
+    fn level(&self) -> NonZeroU8 {
+        MEDIUM
+    }
+
+    fn kind(&self) -> &str {
+        "icmp tunnel"
+    }
+
+    fn source(&self) -> &str {
+        self.source.as_str()
+    }
+
+    fn confidence(&self) -> Option<f32> {
+        Some(self.confidence)
+    }
+
+    fn packet_attr(&self, name: &str) -> Option<AttrValue> {
+        let value = match name {
+            "icmp_type" => i64::from(self.icmp_type),
+            "icmp_code" => i64::from(self.icmp_code),
+            "packet_count" => i64::try_from(self.packet_count).ok()?,
+            "max_payload_size" => i64::try_from(self.max_payload_size).ok()?,
+            "total_payload_size" => i64::try_from(self.total_payload_size).ok()?,
+            "payload_entropy" => return Some(AttrValue::Float(self.payload_entropy)),
+            _ => return common_packet_attr(self, name),
+        };
+        Some(AttrValue::Integer(value))
+    }
+}
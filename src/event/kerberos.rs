@@ -1,4 +1,4 @@
-use super::{common::Match, EventCategory, TriagePolicy, TriageScore, MEDIUM};
+use super::{common::Match, EventCategory, TriageScore, MEDIUM};
 use chrono::{DateTime, Local, Utc};
 use serde::{Deserialize, Serialize};
 use std::{fmt, net::IpAddr, num::NonZeroU8};
@@ -135,9 +135,4 @@ impl Match for BlockListKerberos {
     fn confidence(&self) -> Option<f32> {
         None
     }
-
-    fn score_by_packet_attr(&self, _triage: &TriagePolicy) -> f64 {
-        // TODO: implement
-        0.0
-    }
 }
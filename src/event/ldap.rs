@@ -1,5 +1,5 @@
 #![allow(clippy::module_name_repetitions)]
-use super::{common::Match, EventCategory, TriagePolicy, TriageScore, MEDIUM};
+use super::{common::Match, EventCategory, TriageScore, MEDIUM};
 use chrono::{DateTime, Local, Utc};
 use serde::{Deserialize, Serialize};
 use std::{fmt, net::IpAddr, num::NonZeroU8};
@@ -114,11 +114,6 @@ impl Match for LdapBruteForce {
     fn confidence(&self) -> Option<f32> {
         None
     }
-
-    fn score_by_packet_attr(&self, _triage: &TriagePolicy) -> f64 {
-        // TODO: implement
-        0.0
-    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -247,11 +242,6 @@ impl Match for LdapPlainText {
     fn confidence(&self) -> Option<f32> {
         None
     }
-
-    fn score_by_packet_attr(&self, _triage: &TriagePolicy) -> f64 {
-        // TODO: implement
-        0.0
-    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -380,9 +370,4 @@ impl Match for BlockListLdap {
     fn confidence(&self) -> Option<f32> {
         None
     }
-
-    fn score_by_packet_attr(&self, _triage: &TriagePolicy) -> f64 {
-        // TODO: implement
-        0.0
-    }
 }
@@ -1,5 +1,5 @@
 #![allow(clippy::module_name_repetitions)]
-use super::{common::Match, EventCategory, TriagePolicy, TriageScore, MEDIUM};
+use super::{common::Match, EventCategory, TriageScore, MEDIUM};
 use chrono::{serde::ts_nanoseconds, DateTime, Local, Utc};
 use serde::{Deserialize, Serialize};
 use std::{
@@ -82,9 +82,4 @@ impl Match for ExtraThreat {
     fn confidence(&self) -> Option<f32> {
         Some(self.confidence)
     }
-
-    fn score_by_packet_attr(&self, _triage: &TriagePolicy) -> f64 {
-        // TODO: implement
-        0.0
-    }
 }
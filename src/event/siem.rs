@@ -0,0 +1,191 @@
+//! Formatting of events in CEF and LEEF, the formats SIEMs such as Splunk
+//! and QRadar accept.
+
+use super::common::{AttrValue, Match};
+use chrono::{DateTime, Utc};
+use std::{borrow::Cow, fmt::Write};
+
+const VENDOR: &str = "Petabi";
+const PRODUCT: &str = "REview";
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Formats `event` that occurred at `time` in CEF.
+///
+/// The signature ID is the kind of the detection, and the name is the
+/// category. The severity is the level of the event scaled to 0–10.
+pub(super) fn to_cef(event: &dyn Match, time: DateTime<Utc>) -> String {
+    let mut cef = format!(
+        "CEF:0|{VENDOR}|{PRODUCT}|{VERSION}|{}|{}|{}|rt={}",
+        escape_cef_header(event.kind()),
+        event.category(),
+        severity(event),
+        time.timestamp_millis(),
+    );
+    for (key, value) in extension(event) {
+        write!(cef, " {key}={}", escape_cef_value(&value)).expect("writing to string");
+    }
+    if let Some(confidence) = event.confidence() {
+        write!(cef, " cfp1={confidence} cfp1Label=confidence").expect("writing to string");
+    }
+    cef
+}
+
+/// Formats `event` that occurred at `time` in LEEF 1.0.
+///
+/// The event ID is the kind of the detection. Attributes are separated by
+/// tabs, and keys are the same as those in CEF except where LEEF has its own
+/// predefined keys.
+pub(super) fn to_leef(event: &dyn Match, time: DateTime<Utc>) -> String {
+    let mut leef = format!(
+        "LEEF:1.0|{VENDOR}|{PRODUCT}|{VERSION}|{}|devTime={}\tdevTimeFormat=yyyy-MM-dd'T'HH:mm:ss.SSSXXX\tsev={}",
+        escape_leef_header(event.kind()),
+        time.format("%Y-%m-%dT%H:%M:%S%.3f%:z"),
+        severity(event),
+    );
+    for (key, value) in extension(event) {
+        let key = match key.as_ref() {
+            "spt" => "srcPort",
+            "dpt" => "dstPort",
+            "dvchost" => "identHostName",
+            "suser" => "usrName",
+            key => key,
+        };
+        write!(leef, "\t{key}={}", escape_leef(&value)).expect("writing to string");
+    }
+    if let Some(confidence) = event.confidence() {
+        write!(leef, "\tconfidence={confidence}").expect("writing to string");
+    }
+    leef
+}
+
+/// Returns the fields of `event` as CEF extension keys and values: the common
+/// fields followed by the packet attributes specific to the kind of the event.
+/// Unspecified addresses and zero ports are left out.
+fn extension(event: &dyn Match) -> Vec<(Cow<'static, str>, String)> {
+    let mut fields = Vec::new();
+    if !event.src_addr().is_unspecified() {
+        fields.push(("src".into(), event.src_addr().to_string()));
+    }
+    if event.src_port() != 0 {
+        fields.push(("spt".into(), event.src_port().to_string()));
+    }
+    if !event.dst_addr().is_unspecified() {
+        fields.push(("dst".into(), event.dst_addr().to_string()));
+    }
+    if event.dst_port() != 0 {
+        fields.push(("dpt".into(), event.dst_port().to_string()));
+    }
+    let proto = match event.proto() {
+        0 => None,
+        1 => Some("ICMP".to_string()),
+        6 => Some("TCP".to_string()),
+        17 => Some("UDP".to_string()),
+        proto => Some(proto.to_string()),
+    };
+    if let Some(proto) = proto {
+        fields.push(("proto".into(), proto));
+    }
+    fields.push(("cat".into(), event.category().to_string()));
+    fields.push(("dvchost".into(), event.source().to_string()));
+    for (name, value) in event.extra_packet_attrs() {
+        let key = extension_key(&name);
+        if key.is_empty() || fields.iter().any(|(k, _)| *k == key) {
+            continue;
+        }
+        let value = match value {
+            AttrValue::String(value) => value,
+            AttrValue::Integer(value) => value.to_string(),
+            AttrValue::Float(value) => value.to_string(),
+        };
+        fields.push((key, value));
+    }
+    fields
+}
+
+/// Returns the CEF extension key for the packet attribute `name`.
+///
+/// Attributes with an equivalent in the CEF dictionary use its key. Others
+/// are converted to camel case without non-alphanumeric characters, e.g.
+/// `server_name` to `serverName`, since CEF keys can't contain them.
+fn extension_key(name: &str) -> Cow<'static, str> {
+    let key = match name {
+        "host" => "dhost",
+        "method" => "requestMethod",
+        "uri" => "request",
+        "user_agent" => "requestClientApplication",
+        "cookie" => "requestCookies",
+        "username" => "suser",
+        "file_name" => "fname",
+        "path" => "filePath",
+        "mailfrom" => "suser",
+        "to" => "duser",
+        _ => {
+            let mut key = String::with_capacity(name.len());
+            let mut upper = false;
+            for c in name.chars() {
+                if !c.is_ascii_alphanumeric() {
+                    upper = !key.is_empty();
+                } else if upper {
+                    key.push(c.to_ascii_uppercase());
+                    upper = false;
+                } else {
+                    key.push(c);
+                }
+            }
+            return key.into();
+        }
+    };
+    key.into()
+}
+
+/// Scales the level of `event`, from 1 to 5, to a severity from 0 to 10.
+fn severity(event: &dyn Match) -> u8 {
+    event.level().get().saturating_mul(2).min(10)
+}
+
+fn escape_cef_header(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('|', "\\|")
+}
+
+fn escape_cef_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('=', "\\=")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+/// Escapes backslashes, pipes, which separate header fields, and carets, which
+/// LEEF 2.0 uses as the delimiter, in a LEEF header field.
+fn escape_leef_header(value: &str) -> String {
+    escape_leef(
+        &value
+            .replace('\\', "\\\\")
+            .replace('|', "\\|")
+            .replace('^', "\\^"),
+    )
+}
+
+/// Replaces the characters LEEF 1.0 cannot escape, tabs and line breaks, with
+/// spaces.
+fn escape_leef(value: &str) -> String {
+    value.replace(['\t', '\n', '\r'], " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{escape_leef_header, extension_key};
+
+    #[test]
+    fn extension_keys() {
+        assert_eq!(extension_key("uri"), "request");
+        assert_eq!(extension_key("server_name"), "serverName");
+        assert_eq!(extension_key("ja3s"), "ja3s");
+        assert_eq!(extension_key("_a b-c"), "aBC");
+    }
+
+    #[test]
+    fn leef_header_escape() {
+        assert_eq!(escape_leef_header("a|b^c\\d\te"), "a\\|b\\^c\\\\d e");
+    }
+}
@@ -1,4 +1,7 @@
-use super::{common::Match, EventCategory, TriagePolicy, TriageScore, MEDIUM};
+use super::{
+    common::{common_packet_attr, AttrValue, Match},
+    EventCategory, TriageScore, MEDIUM,
+};
 use chrono::{DateTime, Local, Utc};
 use serde::{Deserialize, Serialize};
 use std::{fmt, net::IpAddr, num::NonZeroU8};
@@ -141,9 +144,148 @@ impl Match for BlockListSmb {
     fn confidence(&self) -> Option<f32> {
         None
     }
+}
+
+#[allow(clippy::module_name_repetitions)]
+#[derive(Serialize, Deserialize)]
+pub struct SmbLateralMovementFields {
+    pub source: String,
+    pub src_addr: IpAddr,
+    pub src_port: u16,
+    pub dst_addr: IpAddr,
+    pub dst_port: u16,
+    pub proto: u8,
+    pub last_time: i64,
+    pub command: u8,
+    pub path: String,
+    pub service: String,
+    pub file_name: String,
+    pub confidence: f32,
+}
+
+impl fmt::Display for SmbLateralMovementFields {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{},{},{},{},{},SMB Lateral Movement,3,{},{}",
+            self.src_addr,
+            self.src_port,
+            self.dst_addr,
+            self.dst_port,
+            self.proto,
+            self.path,
+            self.file_name,
+        )
+    }
+}
+
+/// Suspicious SMB activity that indicates lateral movement, such as access
+/// to an administrative share.
+#[allow(clippy::module_name_repetitions)]
+pub struct SmbLateralMovement {
+    pub time: DateTime<Utc>,
+    pub source: String,
+    pub src_addr: IpAddr,
+    pub src_port: u16,
+    pub dst_addr: IpAddr,
+    pub dst_port: u16,
+    pub proto: u8,
+    pub last_time: i64,
+    pub command: u8,
+    pub path: String,
+    pub service: String,
+    pub file_name: String,
+    pub confidence: f32,
+    pub triage_scores: Option<Vec<TriageScore>>,
+}
+
+impl fmt::Display for SmbLateralMovement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{},{},{},{},{},{},SMB Lateral Movement,{},{}",
+            DateTime::<Local>::from(self.time).format("%Y-%m-%d %H:%M:%S"),
+            self.src_addr,
+            self.src_port,
+            self.dst_addr,
+            self.dst_port,
+            self.proto,
+            self.path,
+            self.file_name,
+        )
+    }
+}
+
+impl SmbLateralMovement {
+    pub(super) fn new(time: DateTime<Utc>, fields: SmbLateralMovementFields) -> Self {
+        Self {
+            time,
+            source: fields.source,
+            src_addr: fields.src_addr,
+            src_port: fields.src_port,
+            dst_addr: fields.dst_addr,
+            dst_port: fields.dst_port,
+            proto: fields.proto,
+            last_time: fields.last_time,
+            command: fields.command,
+            path: fields.path,
+            service: fields.service,
+            file_name: fields.file_name,
+            confidence: fields.confidence,
+            triage_scores: None,
+        }
+    }
+}
+
+impl Match for SmbLateralMovement {
+    fn src_addr(&self) -> IpAddr {
+        self.src_addr
+    }
+
+    fn src_port(&self) -> u16 {
+        self.src_port
+    }
+
+    fn dst_addr(&self) -> IpAddr {
+        self.dst_addr
+    }
+
+    fn dst_port(&self) -> u16 {
+        self.dst_port
+    }
+
+    fn proto(&self) -> u8 {
+        self.proto
+    }
+
+    fn category(&self) -> EventCategory {
+        EventCategory::LateralMovement
+    }
+
+    fn level(&self) -> NonZeroU8 {
+        MEDIUM
+    }
+
+    fn kind(&self) -> &str {
+        "smb lateral movement"
+    }
+
+    fn source(&self) -> &str {
+        self.source.as_str()
+    }
+
+    fn confidence(&self) -> Option<f32> {
+        Some(self.confidence)
+    }
 
-    fn score_by_packet_attr(&self, _triage: &TriagePolicy) -> f64 {
-        // TODO: implement
-        0.0
+    fn packet_attr(&self, name: &str) -> Option<AttrValue> {
+        let value = match name {
+            "path" => &self.path,
+            "service" => &self.service,
+            "file_name" => &self.file_name,
+            "command" => return Some(AttrValue::Integer(self.command.into())),
+            _ => return common_packet_attr(self, name),
+        };
+        Some(AttrValue::String(value.clone()))
     }
 }
@@ -1,4 +1,7 @@
-use super::{common::Match, EventCategory, TriagePolicy, TriageScore, MEDIUM};
+use super::{
+    common::{common_packet_attr, AttrValue, Match},
+    EventCategory, TriageScore, MEDIUM,
+};
 use chrono::{DateTime, Local, Utc};
 use serde::{Deserialize, Serialize};
 use std::{fmt, net::IpAddr, num::NonZeroU8};
@@ -127,8 +130,15 @@ impl Match for BlockListSmtp {
         None
     }
 
-    fn score_by_packet_attr(&self, _triage: &TriagePolicy) -> f64 {
-        // TODO: implement
-        0.0
+    fn packet_attr(&self, name: &str) -> Option<AttrValue> {
+        let value = match name {
+            "mailfrom" => &self.mailfrom,
+            "from" => &self.from,
+            "to" => &self.to,
+            "subject" => &self.subject,
+            "agent" => &self.agent,
+            _ => return common_packet_attr(self, name),
+        };
+        Some(AttrValue::String(value.clone()))
     }
 }
@@ -1,5 +1,9 @@
 #![allow(clippy::module_name_repetitions)]
-use super::{common::Match, EventCategory, TriagePolicy, TriageScore, MEDIUM};
+use super::{
+    common::{common_ti_values, Match},
+    EventCategory, TriageScore, MEDIUM,
+};
+use crate::TiCmpKind;
 use chrono::{serde::ts_nanoseconds, DateTime, Local, Utc};
 use serde::{Deserialize, Serialize};
 use std::{
@@ -98,8 +102,34 @@ impl Match for WindowsThreat {
         None
     }
 
-    fn score_by_packet_attr(&self, _triage: &TriagePolicy) -> f64 {
-        // TODO: implement
-        0.0
+    fn ti_values(&self, kind: TiCmpKind) -> Vec<String> {
+        match kind {
+            TiCmpKind::FileHash => file_hashes(&self.content),
+            _ => common_ti_values(self, kind),
+        }
     }
 }
+
+/// The algorithms of the file hashes Sysmon reports.
+const HASH_ALGORITHMS: [&str; 6] = ["MD5", "SHA1", "SHA256", "SHA384", "SHA512", "IMPHASH"];
+
+/// Returns the file hashes in `content`, listed as Sysmon does, e.g.,
+/// `Hashes: SHA1=...,MD5=...,SHA256=...`.
+fn file_hashes(content: &str) -> Vec<String> {
+    content
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter_map(|field| {
+            let (algorithm, hash) = field.rsplit_once('=')?;
+            let algorithm = algorithm.rsplit(|c| c == '=' || c == ':').next()?;
+            if !HASH_ALGORITHMS
+                .iter()
+                .any(|known| known.eq_ignore_ascii_case(algorithm))
+                || hash.is_empty()
+                || !hash.chars().all(|c| c.is_ascii_hexdigit())
+            {
+                return None;
+            }
+            Some(hash.to_string())
+        })
+        .collect()
+}
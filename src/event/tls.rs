@@ -1,4 +1,7 @@
-use super::{common::Match, EventCategory, TriagePolicy, TriageScore, MEDIUM};
+use super::{
+    common::{common_packet_attr, AttrValue, Match},
+    EventCategory, TriageScore, MEDIUM,
+};
 use chrono::{DateTime, Local, Utc};
 use serde::{Deserialize, Serialize};
 use std::{fmt, net::IpAddr, num::NonZeroU8};
@@ -163,8 +166,202 @@ impl Match for BlockListTls {
         None
     }
 
-    fn score_by_packet_attr(&self, _triage: &TriagePolicy) -> f64 {
-        // TODO: implement
-        0.0
+    fn packet_attr(&self, name: &str) -> Option<AttrValue> {
+        let value = match name {
+            "server_name" => &self.server_name,
+            "alpn_protocol" => &self.alpn_protocol,
+            "ja3" => &self.ja3,
+            "ja3s" => &self.ja3s,
+            "version" => &self.version,
+            "serial" => &self.serial,
+            "subject_common_name" => &self.subject_common_name,
+            "subject_alt_name" => &self.subject_alt_name,
+            "issuer_common_name" => &self.issuer_common_name,
+            "cipher" => return Some(AttrValue::Integer(self.cipher.into())),
+            _ => return common_packet_attr(self, name),
+        };
+        Some(AttrValue::String(value.clone()))
     }
 }
+
+#[derive(Serialize, Deserialize)]
+pub struct TlsCertificateAnomalyFields {
+    pub source: String,
+    pub src_addr: IpAddr,
+    pub src_port: u16,
+    pub dst_addr: IpAddr,
+    pub dst_port: u16,
+    pub proto: u8,
+    pub last_time: i64,
+    pub server_name: String,
+    pub ja3: String,
+    pub ja3s: String,
+    pub ja4: String,
+    pub serial: String,
+    pub subject_common_name: String,
+    pub issuer_common_name: String,
+    pub validity_not_before: i64,
+    pub validity_not_after: i64,
+    pub self_signed: bool,
+    pub expired: bool,
+    pub confidence: f32,
+}
+
+impl fmt::Display for TlsCertificateAnomalyFields {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{},{},{},{},{},TLS Certificate Anomaly,3,{},{}",
+            self.src_addr,
+            self.src_port,
+            self.dst_addr,
+            self.dst_port,
+            self.proto,
+            self.server_name,
+            anomalies(self.self_signed, self.expired),
+        )
+    }
+}
+
+/// A TLS session with an anomalous server certificate, such as a
+/// self-signed or expired one.
+pub struct TlsCertificateAnomaly {
+    pub time: DateTime<Utc>,
+    pub source: String,
+    pub src_addr: IpAddr,
+    pub src_port: u16,
+    pub dst_addr: IpAddr,
+    pub dst_port: u16,
+    pub proto: u8,
+    pub last_time: i64,
+    pub server_name: String,
+    pub ja3: String,
+    pub ja3s: String,
+    pub ja4: String,
+    pub serial: String,
+    pub subject_common_name: String,
+    pub issuer_common_name: String,
+    pub validity_not_before: i64,
+    pub validity_not_after: i64,
+    pub self_signed: bool,
+    pub expired: bool,
+    pub confidence: f32,
+    pub triage_scores: Option<Vec<TriageScore>>,
+}
+
+impl fmt::Display for TlsCertificateAnomaly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{},{},{},{},{},{},TLS Certificate Anomaly,{},{}",
+            DateTime::<Local>::from(self.time).format("%Y-%m-%d %H:%M:%S"),
+            self.src_addr,
+            self.src_port,
+            self.dst_addr,
+            self.dst_port,
+            self.proto,
+            self.server_name,
+            anomalies(self.self_signed, self.expired),
+        )
+    }
+}
+
+impl TlsCertificateAnomaly {
+    pub(super) fn new(time: DateTime<Utc>, fields: TlsCertificateAnomalyFields) -> Self {
+        Self {
+            time,
+            source: fields.source,
+            src_addr: fields.src_addr,
+            src_port: fields.src_port,
+            dst_addr: fields.dst_addr,
+            dst_port: fields.dst_port,
+            proto: fields.proto,
+            last_time: fields.last_time,
+            server_name: fields.server_name,
+            ja3: fields.ja3,
+            ja3s: fields.ja3s,
+            ja4: fields.ja4,
+            serial: fields.serial,
+            subject_common_name: fields.subject_common_name,
+            issuer_common_name: fields.issuer_common_name,
+            validity_not_before: fields.validity_not_before,
+            validity_not_after: fields.validity_not_after,
+            self_signed: fields.self_signed,
+            expired: fields.expired,
+            confidence: fields.confidence,
+            triage_scores: None,
+        }
+    }
+}
+
+impl Match for TlsCertificateAnomaly {
+    fn src_addr(&self) -> IpAddr {
+        self.src_addr
+    }
+
+    fn src_port(&self) -> u16 {
+        self.src_port
+    }
+
+    fn dst_addr(&self) -> IpAddr {
+        self.dst_addr
+    }
+
+    fn dst_port(&self) -> u16 {
+        self.dst_port
+    }
+
+    fn proto(&self) -> u8 {
+        self.proto
+    }
+
+    fn category(&self) -> EventCategory {
+        EventCategory::CommandAndControl
+    }
+
+    fn level(&self) -> NonZeroU8 {
+        MEDIUM
+    }
+
+    fn kind(&self) -> &str {
+        "tls certificate anomaly"
+    }
+
+    fn source(&self) -> &str {
+        self.source.as_str()
+    }
+
+    fn confidence(&self) -> Option<f32> {
+        Some(self.confidence)
+    }
+
+    fn packet_attr(&self, name: &str) -> Option<AttrValue> {
+        let value = match name {
+            "server_name" => &self.server_name,
+            "ja3" => &self.ja3,
+            "ja3s" => &self.ja3s,
+            "ja4" => &self.ja4,
+            "serial" => &self.serial,
+            "subject_common_name" => &self.subject_common_name,
+            "issuer_common_name" => &self.issuer_common_name,
+            "validity_not_before" => return Some(AttrValue::Integer(self.validity_not_before)),
+            "validity_not_after" => return Some(AttrValue::Integer(self.validity_not_after)),
+            "self_signed" => return Some(AttrValue::Integer(self.self_signed.into())),
+            "expired" => return Some(AttrValue::Integer(self.expired.into())),
+            _ => return common_packet_attr(self, name),
+        };
+        Some(AttrValue::String(value.clone()))
+    }
+}
+
+/// Returns the anomalies of a certificate, separated by "|".
+fn anomalies(self_signed: bool, expired: bool) -> String {
+    let mut anomalies = Vec::new();
+    if self_signed {
+        anomalies.push("self-signed");
+    }
+    if expired {
+        anomalies.push("expired");
+    }
+    anomalies.join("|")
+}
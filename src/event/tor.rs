@@ -1,4 +1,7 @@
-use super::{common::Match, EventCategory, TriagePolicy, TriageScore, MEDIUM};
+use super::{
+    common::{common_packet_attr, AttrValue, Match},
+    EventCategory, TriageScore, MEDIUM,
+};
 use chrono::{serde::ts_nanoseconds, DateTime, Local, Utc};
 use serde::{Deserialize, Serialize};
 use std::{fmt, net::IpAddr, num::NonZeroU8};
@@ -159,8 +162,29 @@ impl Match for TorConnection {
         None
     }
 
-    fn score_by_packet_attr(&self, _triage: &TriagePolicy) -> f64 {
-        // TODO: implement
-        0.0
+    fn packet_attr(&self, name: &str) -> Option<AttrValue> {
+        let value = match name {
+            "method" => &self.method,
+            "host" => &self.host,
+            "uri" => &self.uri,
+            "referer" => &self.referrer,
+            "version" => &self.version,
+            "user_agent" => &self.user_agent,
+            "status_msg" => &self.status_msg,
+            "username" => &self.username,
+            "cookie" => &self.cookie,
+            "content_encoding" => &self.content_encoding,
+            "content_type" => &self.content_type,
+            "cache_control" => &self.cache_control,
+            "request_len" => return i64::try_from(self.request_len).ok().map(AttrValue::Integer),
+            "response_len" => {
+                return i64::try_from(self.response_len)
+                    .ok()
+                    .map(AttrValue::Integer)
+            }
+            "status_code" => return Some(AttrValue::Integer(self.status_code.into())),
+            _ => return common_packet_attr(self, name),
+        };
+        Some(AttrValue::String(value.clone()))
     }
 }
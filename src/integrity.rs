@@ -0,0 +1,404 @@
+//! Integrity checks of the stored data.
+
+use crate::{
+    collections::KeyIndex,
+    event::{self, InvalidEvent},
+    types::{FromKeyValue, ModelScores},
+    AccessToken, AccountAuditEntry, AgentStatus, AllowNetwork, ApiKey, BatchInfo, BatchScores,
+    BlockNetwork, Category, CsvColumnExtraConfig, Customer, DataSource, Filter, Indexable,
+    IterableMap, ModelIndicator, ModelIndicatorVersion, Network, NetworkHits, Node, NodeGroup,
+    OutlierInfo, Qualifier, SamplingPolicy, Status, Store, SyslogMapping, TableKind, Template,
+    Tidb, TorExitNode, TorExitNodeSnapshot, TrafficFilter, TriagePolicy, TriageResponse,
+    TrustedDomain, TrustedUserAgent,
+};
+use anyhow::{anyhow, Result};
+use bincode::Options;
+use std::collections::HashSet;
+
+/// The result of [`Store::verify`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IntegrityReport {
+    /// The entries that cannot be deserialized with the current schema.
+    pub corrupt: Vec<InvalidEntry>,
+
+    /// The entries referring to other entries that do not exist.
+    pub orphaned: Vec<InvalidEntry>,
+}
+
+impl IntegrityReport {
+    /// Returns `true` if no problem was found.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.corrupt.is_empty() && self.orphaned.is_empty()
+    }
+}
+
+/// An entry found to be invalid by [`Store::verify`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InvalidEntry {
+    pub table: TableKind,
+
+    /// The key of the entry in the database.
+    pub key: Vec<u8>,
+
+    /// Why the entry is invalid.
+    pub reason: String,
+}
+
+/// The IDs that entries in other tables may refer to.
+struct References {
+    categories: HashSet<u32>,
+    customers: HashSet<u32>,
+    networks: HashSet<u32>,
+    nodes: HashSet<u32>,
+    triage_policies: HashSet<u32>,
+    event_tags: HashSet<u32>,
+    network_tags: HashSet<u32>,
+}
+
+/// Checks every entry in `store`.
+pub(crate) fn verify(store: &Store) -> Result<IntegrityReport> {
+    let references = References {
+        categories: ids::<Category>(store, TableKind::Categories)?,
+        customers: ids::<Customer>(store, TableKind::Customers)?,
+        networks: ids::<Network>(store, TableKind::Networks)?,
+        nodes: ids::<Node>(store, TableKind::Nodes)?,
+        triage_policies: ids::<TriagePolicy>(store, TableKind::TriagePolicies)?,
+        event_tags: store.event_tag_set()?.tags().map(|tag| tag.id).collect(),
+        network_tags: store.network_tag_set()?.tags().map(|tag| tag.id).collect(),
+    };
+
+    let mut report = IntegrityReport::default();
+    for kind in TableKind::ALL {
+        match kind {
+            TableKind::Events => verify_events(store, &mut report)?,
+            // These tables are indices derived from other tables, or store
+            // raw bytes without a schema.
+            TableKind::AccountPolicy
+            | TableKind::ApiKeyIndex
+            | TableKind::EventAddresses
+            | TableKind::EventDispositions
+            | TableKind::EventSensorStats
+            | TableKind::EventTagAssignments
+            | TableKind::TriageResponsePolicies
+            | TableKind::TrustedDnsServers => {}
+            _ => verify_table(store, kind, &references, &mut report)?,
+        }
+    }
+    Ok(report)
+}
+
+fn verify_events(store: &Store, report: &mut IntegrityReport) -> Result<()> {
+    let map = store
+        .states
+        .map(TableKind::Events.name())
+        .ok_or_else(|| anyhow!("events table must be present"))?;
+    for (key, value) in map.iter_forward()? {
+        let reason = match event::parse_event(key.clone(), value) {
+            Ok(_) => continue,
+            Err(InvalidEvent::Key(_)) => "invalid key",
+            Err(InvalidEvent::Value(_)) => "invalid value",
+        };
+        report.corrupt.push(InvalidEntry {
+            table: TableKind::Events,
+            key: key.to_vec(),
+            reason: reason.to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn verify_table(
+    store: &Store,
+    kind: TableKind,
+    references: &References,
+    report: &mut IntegrityReport,
+) -> Result<()> {
+    let map = store
+        .states
+        .map(kind.name())
+        .ok_or_else(|| anyhow!("{} table must be present", kind.name()))?;
+    for (key, value) in map.iter_forward()? {
+        let result = if key.is_empty() && is_indexed(kind) {
+            KeyIndex::from_bytes(&value).map(|_| Vec::new())
+        } else {
+            check(kind, &key, &value, references)
+        };
+        match result {
+            Ok(missing) => {
+                report
+                    .orphaned
+                    .extend(missing.into_iter().map(|reason| InvalidEntry {
+                        table: kind,
+                        key: key.to_vec(),
+                        reason,
+                    }));
+            }
+            Err(e) => report.corrupt.push(InvalidEntry {
+                table: kind,
+                key: key.to_vec(),
+                reason: format!("{e:#}"),
+            }),
+        }
+    }
+    Ok(())
+}
+
+fn is_indexed(kind: TableKind) -> bool {
+    matches!(
+        kind,
+        TableKind::AllowNetworks
+            | TableKind::BlockNetworks
+            | TableKind::Categories
+            | TableKind::CsvColumnExtras
+            | TableKind::Customers
+            | TableKind::DataSources
+            | TableKind::Networks
+            | TableKind::Nodes
+            | TableKind::NodeGroups
+            | TableKind::Qualifiers
+            | TableKind::SamplingPolicies
+            | TableKind::Statuses
+            | TableKind::TriagePolicies
+            | TableKind::TriageResponses
+    )
+}
+
+/// Deserializes an entry, and returns the descriptions of the references to
+/// missing entries.
+fn check(
+    kind: TableKind,
+    key: &[u8],
+    value: &[u8],
+    references: &References,
+) -> Result<Vec<String>> {
+    let mut missing = Vec::new();
+    match kind {
+        TableKind::AccessTokens => decode::<AccessToken>(key, value)?,
+        TableKind::Accounts => decode::<crate::types::Account>(key, value)?,
+        TableKind::AccountAudit => decode::<AccountAuditEntry>(key, value)?,
+        TableKind::AgentStatus => decode::<AgentStatus>(key, value)?,
+        TableKind::AllowNetworks => decode::<AllowNetwork>(key, value)?,
+        TableKind::ApiKeys => decode::<ApiKey>(key, value)?,
+        TableKind::BatchInfo => decode::<BatchInfo>(key, value)?,
+        TableKind::BatchScores => decode::<BatchScores>(key, value)?,
+        TableKind::BlockNetworks => decode::<BlockNetwork>(key, value)?,
+        TableKind::Categories => {
+            let category = Category::from_key_value(key, value)?;
+            if let Some(id) = category.parent_id {
+                if !references.categories.contains(&id) {
+                    missing.push(format!("category {id} does not exist"));
+                }
+            }
+        }
+        TableKind::CsvColumnExtras => decode::<CsvColumnExtraConfig>(key, value)?,
+        TableKind::Customers => decode::<Customer>(key, value)?,
+        TableKind::DataSources => decode::<DataSource>(key, value)?,
+        TableKind::Filters => decode::<Filter>(key, value)?,
+        TableKind::ModelIndicators => decode::<ModelIndicator>(key, value)?,
+        TableKind::ModelIndicatorVersions => decode::<ModelIndicatorVersion>(key, value)?,
+        TableKind::Networks => {
+            let network = Network::from_key_value(key, value)?;
+            for id in network.tag_ids() {
+                if !references.network_tags.contains(id) {
+                    missing.push(format!("network tag {id} does not exist"));
+                }
+            }
+            for id in &network.customer_ids {
+                if !references.customers.contains(id) {
+                    missing.push(format!("customer {id} does not exist"));
+                }
+            }
+        }
+        TableKind::Nodes => {
+            let node = Node::from_key_value(key, value)?;
+            for setting in [&node.setting, &node.setting_draft].into_iter().flatten() {
+                if !references.customers.contains(&setting.customer_id) {
+                    missing.push(format!("customer {} does not exist", setting.customer_id));
+                }
+            }
+        }
+        TableKind::NetworkHits => decode::<NetworkHits>(key, value)?,
+        TableKind::Outliers => {
+            bincode::DefaultOptions::new().deserialize::<OutlierInfo>(value)?;
+        }
+        TableKind::NodeGroups => {
+            let group = NodeGroup::from_key_value(key, value)?;
+            for id in &group.node_ids {
+                if !references.nodes.contains(id) {
+                    missing.push(format!("node {id} does not exist"));
+                }
+            }
+        }
+        TableKind::Qualifiers => decode::<Qualifier>(key, value)?,
+        TableKind::SamplingPolicies => decode::<SamplingPolicy>(key, value)?,
+        TableKind::Scores => {
+            bincode::DefaultOptions::new().deserialize::<i32>(key)?;
+            bincode::DefaultOptions::new().deserialize::<ModelScores>(value)?;
+        }
+        TableKind::Statuses => decode::<Status>(key, value)?,
+        TableKind::SyslogMappings => decode::<SyslogMapping>(key, value)?,
+        TableKind::Templates => decode::<Template>(key, value)?,
+        TableKind::Tidbs => decode::<Tidb>(key, value)?,
+        TableKind::TrafficFilterRules => {
+            let filter = bincode::DefaultOptions::new().deserialize::<TrafficFilter>(value)?;
+            if filter.agent.as_bytes() != key {
+                return Err(anyhow!("traffic filter of a different agent"));
+            }
+        }
+        TableKind::TorExitNodes => decode::<TorExitNode>(key, value)?,
+        TableKind::TorExitNodeSnapshots => decode::<TorExitNodeSnapshot>(key, value)?,
+        TableKind::TriagePolicies => {
+            let policy = TriagePolicy::from_key_value(key, value)?;
+            for id in &policy.customer_ids {
+                if !references.customers.contains(id) {
+                    missing.push(format!("customer {id} does not exist"));
+                }
+            }
+            for id in &policy.network_ids {
+                if !references.networks.contains(id) {
+                    missing.push(format!("network {id} does not exist"));
+                }
+            }
+        }
+        TableKind::TrustedDomains => decode::<TrustedDomain>(key, value)?,
+        TableKind::TrustedUserAgents => decode::<TrustedUserAgent>(key, value)?,
+        TableKind::TriageResponses => {
+            let response = TriageResponse::from_key_value(key, value)?;
+            for id in response.tag_ids() {
+                if !references.event_tags.contains(id) {
+                    missing.push(format!("event tag {id} does not exist"));
+                }
+            }
+            if let Some(policy) = response.policy() {
+                if !references.triage_policies.contains(&policy.policy_id) {
+                    missing.push(format!("triage policy {} does not exist", policy.policy_id));
+                }
+            }
+        }
+        TableKind::AccountPolicy
+        | TableKind::ApiKeyIndex
+        | TableKind::EventAddresses
+        | TableKind::EventDispositions
+        | TableKind::EventSensorStats
+        | TableKind::EventTagAssignments
+        | TableKind::Events
+        | TableKind::TriageResponsePolicies
+        | TableKind::TrustedDnsServers => {}
+    }
+    Ok(missing)
+}
+
+fn decode<T: FromKeyValue>(key: &[u8], value: &[u8]) -> Result<()> {
+    T::from_key_value(key, value).map(|_| ())
+}
+
+/// Returns the IDs of the valid entries in an indexed table.
+fn ids<T: FromKeyValue + Indexable>(store: &Store, kind: TableKind) -> Result<HashSet<u32>> {
+    let map = store
+        .states
+        .map(kind.name())
+        .ok_or_else(|| anyhow!("{} table must be present", kind.name()))?;
+    Ok(map
+        .iter_forward()?
+        .filter(|(key, _)| !key.is_empty())
+        .filter_map(|(key, value)| T::from_key_value(&key, &value).ok())
+        .map(|entry| entry.index())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Store, TableKind, TrafficFilter, TriageResponse, TriageResult, TriggeredPolicy};
+    use chrono::Utc;
+
+    #[test]
+    fn verify() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+        store.category_map().insert("category 3").unwrap();
+        assert!(store.verify().unwrap().is_empty());
+
+        store
+            .states
+            .map(TableKind::Categories.name())
+            .unwrap()
+            .put(b"garbage", &[0xff; 3])
+            .unwrap();
+        store
+            .states
+            .map(TableKind::Events.name())
+            .unwrap()
+            .put(b"short key", b"")
+            .unwrap();
+        let report = store.verify().unwrap();
+        assert_eq!(report.corrupt.len(), 2);
+        assert!(report
+            .corrupt
+            .iter()
+            .any(|entry| entry.table == TableKind::Categories && entry.key == b"garbage"));
+        assert!(report
+            .corrupt
+            .iter()
+            .any(|entry| entry.table == TableKind::Events && entry.key == b"short key"));
+        assert!(report.orphaned.is_empty());
+
+        let tag = store.event_tag_set().unwrap().insert("tag").unwrap();
+        let response = TriageResponse::new(
+            "source".to_string(),
+            Utc::now(),
+            vec![tag, tag + 1],
+            String::new(),
+        );
+        store.triage_response_map().put(response).unwrap();
+        let report = store.verify().unwrap();
+        assert_eq!(report.corrupt.len(), 2);
+        assert_eq!(report.orphaned.len(), 1);
+        assert_eq!(report.orphaned[0].table, TableKind::TriageResponses);
+
+        let response = TriageResponse::new("source".to_string(), Utc::now(), vec![], String::new())
+            .with_policy(TriggeredPolicy::new(
+                TriageResult {
+                    policy_id: 7,
+                    score: 0.0,
+                    matched: vec![],
+                    response: None,
+                },
+                0,
+            ));
+        store.triage_response_map().put(response).unwrap();
+        let report = store.verify().unwrap();
+        assert_eq!(report.orphaned.len(), 2);
+        assert!(report
+            .orphaned
+            .iter()
+            .any(|entry| entry.reason == "triage policy 7 does not exist"));
+    }
+
+    #[test]
+    fn verify_traffic_filters() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+        TrafficFilter::insert(
+            &store,
+            "agent",
+            "10.0.0.0/8".parse().unwrap(),
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        assert!(store.verify().unwrap().is_empty());
+
+        store
+            .traffic_filter_map()
+            .put(b"garbage", &[0xff; 3])
+            .unwrap();
+        let report = store.verify().unwrap();
+        assert_eq!(report.corrupt.len(), 1);
+        assert_eq!(report.corrupt[0].table, TableKind::TrafficFilterRules);
+        assert_eq!(report.corrupt[0].key, b"garbage");
+    }
+}
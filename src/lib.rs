@@ -2,35 +2,50 @@
 extern crate diesel;
 
 mod account;
+mod archive;
 mod backends;
 pub mod backup;
 mod batch_info;
+pub mod bundle;
 mod category;
+pub mod cleanup;
 mod cluster;
 mod collections;
 mod column_statistics;
 mod csv_indicator;
 pub mod event;
+mod integrity;
+mod metrics;
 mod migration;
+mod misp;
 mod model;
+mod notification;
 mod outlier;
+mod pagination;
+mod pretrained;
+pub mod retention;
 mod schema;
 mod scores;
 mod tables;
 mod tags;
+mod taxonomy;
 #[cfg(test)]
 mod test;
 mod time_series;
 mod top_n;
 mod traffic_filter;
+mod trusted_user_agent;
 pub mod types;
+mod verdict;
 
-pub use self::account::Role;
+pub use self::account::{
+    ExportedAccount, LockoutPolicy, PasswordPolicy, PasswordValidation, Role, TotpKey,
+};
 use self::backends::ConnectionPool;
 pub use self::batch_info::BatchInfo;
 pub use self::category::Category;
 pub use self::cluster::*;
-pub use self::collections::{Indexable, Indexed, IterableMap, Map, MapIterator};
+pub use self::collections::{IdUsage, Indexable, Indexed, IterableMap, Map, MapIterator};
 pub(crate) use self::collections::{IndexedMap, IndexedMapUpdate};
 pub use self::column_statistics::*;
 pub use self::event::EventKind;
@@ -41,27 +56,50 @@ pub use self::event::{
     BlockListLdapFields, BlockListMqtt, BlockListMqttFields, BlockListNfs, BlockListNfsFields,
     BlockListNtlm, BlockListNtlmFields, BlockListRdp, BlockListRdpFields, BlockListSmb,
     BlockListSmbFields, BlockListSmtp, BlockListSmtpFields, BlockListSsh, BlockListSshFields,
-    BlockListTls, BlockListTlsFields, CryptocurrencyMiningPool, Direction, DnsCovertChannel,
-    DomainGenerationAlgorithm, Event, EventDb, EventFilter, EventIterator, EventMessage,
-    ExternalDdos, ExtraThreat, FilterEndpoint, FlowKind, FtpBruteForce, FtpPlainText, HttpThreat,
-    LdapBruteForce, LdapPlainText, LearningMethod, MultiHostPortScan, NetworkThreat, NetworkType,
-    NonBrowser, PortScan, RdpBruteForce, RecordType, RepeatedHttpSessions, TorConnection,
-    TrafficDirection, TriageScore, WindowsThreat,
+    BlockListTls, BlockListTlsFields, CryptocurrencyMiningPool, CustomDetection,
+    CustomDetectionFields, CustomFieldValue, DceRpcLateralMovement, DceRpcLateralMovementFields,
+    Direction, Disposition, DnsCovertChannel, DomainGenerationAlgorithm, Event, EventCount,
+    EventDb, EventDisposition, EventFilter, EventFilterExpr, EventInterval, EventIterator,
+    EventMessage, EventRangeIterator, ExternalDdos, ExtraThreat, FilterEndpoint, FlowKind,
+    FtpBruteForce, FtpPlainText, HttpThreat, IcmpTunnel, IcmpTunnelFields, LdapBruteForce,
+    LdapPlainText, LearningMethod, MatchedRule, MatchingEventIterator, MultiHostPortScan,
+    NetworkThreat, NetworkType, NonBrowser, PortScan, RdpBruteForce, RecordType,
+    RepeatedHttpSessions, SensorStats, SmbLateralMovement, SmbLateralMovementFields,
+    TlsCertificateAnomaly, TlsCertificateAnomalyFields, TorConnection, TrafficDirection,
+    TriageResult, TriageScore, WindowsThreat, TRUNCATION_MARKER,
 };
+pub use self::integrity::{IntegrityReport, InvalidEntry};
+pub use self::metrics::{Metrics, TableMetrics};
 pub use self::migration::{migrate_backend, migrate_data_dir};
-pub use self::model::{Digest as ModelDigest, Model};
+pub use self::misp::MispImport;
+pub use self::model::{Digest as ModelDigest, Model, TrainingMetadata as ModelTrainingMetadata};
+pub use self::notification::{Change, ChangeKind, TableKind};
 pub use self::outlier::*;
+pub use self::pagination::{Cursor, Page, PageRequest};
+pub use self::pretrained::{
+    PretrainedModelReader, PretrainedModelWriter,
+    DEFAULT_CHUNK_SIZE as DEFAULT_PRETRAINED_CHUNK_SIZE,
+};
+pub use self::scores::{BatchScores, ScoreBucket, ScoreDrift, Scores};
 use self::tables::StateDb;
 pub use self::tables::{
-    AccessToken, AllowNetwork, AllowNetworkUpdate, AttrCmpKind, BlockNetwork, BlockNetworkUpdate,
-    Confidence, CsvColumnExtra as CsvColumnExtraConfig, Customer, CustomerNetwork, CustomerUpdate,
-    DataSource, DataSourceUpdate, DataType, Filter, IndexedTable, Iterable, ModelIndicator,
-    Network, NetworkUpdate, Node, NodeSetting, NodeUpdate, PacketAttr, Response, ResponseKind,
-    SamplingInterval, SamplingKind, SamplingPeriod, SamplingPolicy, SamplingPolicyUpdate,
-    Structured, StructuredClusteringAlgorithm, Table, Template, Ti, TiCmpKind, Tidb, TidbKind,
-    TidbRule, TorExitNode, TriagePolicy, TriagePolicyUpdate, TriageResponse, TriageResponseUpdate,
-    UniqueKey, Unstructured, UnstructuredClusteringAlgorithm, ValueKind,
+    AccessToken, AccountAction, AccountAuditEntry, AccountSnapshot, AgentStatus, AllowNetwork,
+    AllowNetworkUpdate, ApiKey, AttrCmpKind, BlockNetwork, BlockNetworkUpdate, Confidence,
+    CsvColumnExtra as CsvColumnExtraConfig, Customer, CustomerMatch, CustomerNetwork,
+    CustomerUpdate, DataSource, DataSourceUpdate, DataType, Filter, IndexedTable, Iterable,
+    ModelIndicator, ModelIndicatorDiff, ModelIndicatorVersion, Named, Network, NetworkHits,
+    NetworkListKind, NetworkUpdate, Node, NodeGroup, NodeGroupKind, NodeGroupUpdate, NodeSetting,
+    NodeSettingDiff, NodeTemplateResult, NodeUpdate, PacketAttr, ResourceUsage, Response,
+    ResponseKind, SamplingInterval, SamplingKind, SamplingPeriod, SamplingPolicy,
+    SamplingPolicyUpdate, SamplingSchedule, SamplingWindow, SessionDevice, SessionPolicy,
+    StixImport, Structured, StructuredClusteringAlgorithm, SyslogMapping, SyslogTarget, Table,
+    Template, Ti, TiCmpKind, Tidb, TidbKind, TidbLookup, TidbMatch, TidbMatcher, TidbRule,
+    TorExitNode, TorExitNodeSnapshot, TriagePolicy, TriagePolicyNameConflict, TriagePolicyUpdate,
+    TriagePolicyVersion, TriageResponse, TriageResponseUpdate, TriggeredPolicy, TrustedDomain,
+    UniqueKey, Unstructured, UnstructuredClusteringAlgorithm, ValueKind, DEFAULT_SYSLOG_FACILITY,
+    TRIAGE_POLICY_JSON_FORMAT_VERSION,
 };
+pub use self::taxonomy::{ImportConflict, ImportReport};
 pub use self::time_series::*;
 pub use self::time_series::{ColumnTimeSeries, TimeCount, TimeSeriesResult};
 pub use self::top_n::*;
@@ -69,9 +107,13 @@ pub use self::top_n::{
     ClusterScore, ClusterScoreSet, ClusterTrend, ElementCount, LineSegment, Regression,
     StructuredColumnType, TopColumnsOfCluster, TopMultimaps, TopTrendsByColumn,
 };
-pub use self::traffic_filter::{ProtocolPorts, TrafficFilter};
-pub use self::types::{EventCategory, HostNetworkGroup, Qualifier, Status};
-use anyhow::{anyhow, Result};
+pub use self::traffic_filter::{PortRange, ProtocolPorts, TrafficFilter};
+pub use self::trusted_user_agent::{TrustedUserAgent, UserAgentKind};
+pub use self::types::{
+    EventCategory, HostNetworkGroup, ParseHostNetworkGroupError, Qualifier, Status,
+};
+pub use self::verdict::{Decision, Verdict, VerdictInput, VerdictRule};
+use anyhow::{anyhow, Context, Result};
 use backends::Value;
 use bb8_postgres::{
     bb8,
@@ -80,9 +122,11 @@ use bb8_postgres::{
 pub use rocksdb::backup::BackupEngineInfo;
 use std::io;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
 pub use tags::TagSet;
 use tags::{EventTagId, NetworkTagId, WorkflowTagId};
 use thiserror::Error;
+use tokio::sync::RwLock;
 
 #[derive(Clone)]
 pub struct Database {
@@ -109,6 +153,8 @@ const EXCLUSIVE: bool = true;
 pub struct Store {
     states: StateDb,
     pretrained: PathBuf,
+    pretrained_chunk_size: u64,
+    totp_key: OnceLock<TotpKey>,
 }
 
 impl Store {
@@ -128,10 +174,33 @@ impl Store {
                 return Err(anyhow::anyhow!("{e}"));
             }
         }
-        let store = Self { states, pretrained };
+        let store = Self {
+            states,
+            pretrained,
+            pretrained_chunk_size: pretrained::DEFAULT_CHUNK_SIZE,
+            totp_key: OnceLock::new(),
+        };
         Ok(store)
     }
 
+    /// Returns a receiver of the changes made to the table of `kind`.
+    ///
+    /// A subscriber that falls far behind misses the oldest changes, and is
+    /// notified of it with `RecvError::Lagged`.
+    #[must_use]
+    pub fn subscribe(&self, kind: TableKind) -> tokio::sync::broadcast::Receiver<Change> {
+        self.states.subscribe(kind)
+    }
+
+    /// Returns the operation counters and storage statistics of each table.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the statistics cannot be read from the database.
+    pub fn metrics(&self) -> Result<Metrics> {
+        self.states.metrics()
+    }
+
     #[must_use]
     #[allow(clippy::missing_panics_doc)]
     pub fn events(&self) -> EventDb {
@@ -150,6 +219,12 @@ impl Store {
         self.states.accounts()
     }
 
+    #[must_use]
+    #[allow(clippy::missing_panics_doc)]
+    pub fn account_audit_map(&self) -> Table<AccountAuditEntry> {
+        self.states.account_audit()
+    }
+
     #[must_use]
     #[allow(clippy::missing_panics_doc)]
     pub fn account_policy_map(&self) -> Map {
@@ -158,6 +233,12 @@ impl Store {
             .expect("always available")
     }
 
+    #[must_use]
+    #[allow(clippy::missing_panics_doc)]
+    pub fn agent_status_map(&self) -> Table<AgentStatus> {
+        self.states.agent_status()
+    }
+
     #[must_use]
     #[allow(clippy::missing_panics_doc)]
     pub fn allow_network_map(&self) -> IndexedTable<AllowNetwork> {
@@ -170,6 +251,18 @@ impl Store {
         self.states.batch_info()
     }
 
+    #[must_use]
+    #[allow(clippy::missing_panics_doc)]
+    pub fn api_key_map(&self) -> Table<ApiKey> {
+        self.states.api_keys()
+    }
+
+    #[must_use]
+    #[allow(clippy::missing_panics_doc)]
+    pub fn batch_scores_map(&self) -> Table<BatchScores> {
+        self.states.batch_scores()
+    }
+
     #[must_use]
     #[allow(clippy::missing_panics_doc)]
     pub fn block_network_map(&self) -> IndexedTable<BlockNetwork> {
@@ -226,12 +319,24 @@ impl Store {
         self.states.model_indicators()
     }
 
+    #[must_use]
+    #[allow(clippy::missing_panics_doc)]
+    pub fn model_indicator_version_map(&self) -> Table<ModelIndicatorVersion> {
+        self.states.model_indicator_versions()
+    }
+
     #[must_use]
     #[allow(clippy::missing_panics_doc)]
     pub fn network_map(&self) -> IndexedTable<Network> {
         self.states.networks()
     }
 
+    #[must_use]
+    #[allow(clippy::missing_panics_doc)]
+    pub fn network_hits_map(&self) -> Table<NetworkHits> {
+        self.states.network_hits()
+    }
+
     /// Returns the tag set for network.
     ///
     /// # Errors
@@ -252,6 +357,12 @@ impl Store {
         self.states.nodes()
     }
 
+    #[must_use]
+    #[allow(clippy::missing_panics_doc)]
+    pub fn node_group_map(&self) -> IndexedTable<NodeGroup> {
+        self.states.node_groups()
+    }
+
     #[must_use]
     #[allow(clippy::missing_panics_doc)]
     pub fn outlier_map(&self) -> Map {
@@ -294,12 +405,24 @@ impl Store {
         self.states.tidbs()
     }
 
+    #[must_use]
+    #[allow(clippy::missing_panics_doc)]
+    pub fn syslog_mapping_map(&self) -> Table<SyslogMapping> {
+        self.states.syslog_mappings()
+    }
+
     #[must_use]
     #[allow(clippy::missing_panics_doc)]
     pub fn tor_exit_node_map(&self) -> Table<TorExitNode> {
         self.states.tor_exit_nodes()
     }
 
+    #[must_use]
+    #[allow(clippy::missing_panics_doc)]
+    pub fn tor_exit_node_snapshot_map(&self) -> Table<TorExitNodeSnapshot> {
+        self.states.tor_exit_node_snapshots()
+    }
+
     #[must_use]
     #[allow(clippy::missing_panics_doc)]
     pub fn triage_policy_map(&self) -> IndexedTable<TriagePolicy> {
@@ -320,6 +443,12 @@ impl Store {
             .expect("always available")
     }
 
+    #[must_use]
+    #[allow(clippy::missing_panics_doc)]
+    pub fn trusted_domain_map(&self) -> Table<TrustedDomain> {
+        self.states.trusted_domains()
+    }
+
     #[must_use]
     #[allow(clippy::missing_panics_doc)]
     pub fn trusted_user_agent_map(&self) -> Map {
@@ -358,14 +487,346 @@ impl Store {
     pub fn pretrained_model(&self, name: &str) -> Result<types::PretrainedModel> {
         use std::io::Read;
 
-        let (_ts, most_recent) = get_most_recent(name, &self.pretrained)?;
-        let mut file = std::fs::File::open(most_recent)?;
-        let mut buf = vec![];
-        file.read_to_end(&mut buf)?;
+        let mut reader = self.pretrained_model_reader(name)?;
+        let mut buf = Vec::with_capacity(usize::try_from(reader.size()).unwrap_or_default());
+        reader.read_to_end(&mut buf)?;
 
         Ok(types::PretrainedModel(buf))
     }
 
+    /// Opens the most recent pretrained model with `name` for reading, without
+    /// loading it into memory.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error when model cannot be located or opened.
+    pub fn pretrained_model_reader(&self, name: &str) -> Result<PretrainedModelReader> {
+        let (_ts, most_recent) = get_most_recent(name, &self.pretrained)?;
+        PretrainedModelReader::open(&most_recent)
+    }
+
+    /// Stores the batch info, scores, and batch scores of a model in a single
+    /// transaction.
+    pub(crate) fn put_model_results(
+        &self,
+        batch_info: &[batch_info::BatchInfo],
+        scores: &scores::Scores,
+        batch_scores: &[BatchScores],
+    ) -> Result<()> {
+        self.states
+            .put_model_results(batch_info, scores, batch_scores)
+    }
+
+    /// Opens the most recent pretrained model with `name` for reading, and
+    /// returns it with its timestamp, or `None` if there is no such model.
+    pub(crate) fn latest_pretrained_model(
+        &self,
+        name: &str,
+    ) -> Result<Option<(i64, PretrainedModelReader)>> {
+        find_most_recent(name, &self.pretrained)?
+            .map(|(ts, path)| Ok((ts, PretrainedModelReader::open(&path)?)))
+            .transpose()
+    }
+
+    /// Creates a writer of the pretrained model with `name` at `timestamp`.
+    /// A model larger than the chunk size is split into chunks.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the model already exists or the file system
+    /// operation fails.
+    pub fn pretrained_model_writer(
+        &self,
+        name: &str,
+        timestamp: i64,
+    ) -> Result<PretrainedModelWriter> {
+        PretrainedModelWriter::new(
+            &self.pretrained,
+            name,
+            timestamp,
+            self.pretrained_chunk_size,
+        )
+    }
+
+    /// Sets the maximum size of a chunk of a pretrained model written after
+    /// this call. The default is [`DEFAULT_PRETRAINED_CHUNK_SIZE`].
+    pub fn set_pretrained_chunk_size(&mut self, size: u64) {
+        self.pretrained_chunk_size = size;
+    }
+
+    /// Writes the contents of the store into a portable archive at `path`.
+    ///
+    /// The archive can be loaded with [`Store::import`], even by a build
+    /// linked against a different version of RocksDB.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the archive cannot be written or the database
+    /// operation fails.
+    pub fn export(&self, path: &Path) -> Result<()> {
+        self.states.export(path)
+    }
+
+    /// Replaces the contents of the store with those in the archive at `path`,
+    /// which was created by [`Store::export`].
+    ///
+    /// The contents are replaced at once, only after the whole archive has
+    /// been read; a truncated or invalid archive leaves the store unchanged.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the archive cannot be read, was created by a version
+    /// with an incompatible database format, or the database operation fails.
+    pub fn import(&self, path: &Path) -> Result<()> {
+        self.states.import(path)
+    }
+
+    /// Checks that every entry in the store can be deserialized with the
+    /// current schema and that the entries it refers to exist.
+    ///
+    /// Tables storing raw bytes, such as the trusted DNS servers, are not
+    /// checked.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails.
+    pub fn verify(&self) -> Result<IntegrityReport> {
+        integrity::verify(self)
+    }
+
+    /// Returns the currently active triage policies that apply to `addr`,
+    /// i.e., those without a scope and those whose customers or networks
+    /// include `addr`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails.
+    pub fn applicable_triage_policies(&self, addr: std::net::IpAddr) -> Result<Vec<TriagePolicy>> {
+        let customers = self
+            .customer_map()
+            .iter(Direction::Forward, None)
+            .collect::<Result<Vec<_>>>()?;
+        let networks = self
+            .network_map()
+            .iter(Direction::Forward, None)
+            .collect::<Result<Vec<_>>>()?;
+        Ok(self
+            .triage_policy_map()
+            .active_at(chrono::Utc::now())?
+            .into_iter()
+            .filter(|policy| policy.applies_to(addr, &customers, &networks))
+            .collect())
+    }
+
+    /// Returns the IDs of the entries in the table of `kind` that haven't
+    /// matched traffic since `since`, including those that never have.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails.
+    pub fn unmatched_networks(
+        &self,
+        kind: NetworkListKind,
+        since: chrono::DateTime<chrono::Utc>,
+    ) -> Result<Vec<u32>> {
+        let ids = match kind {
+            NetworkListKind::Allow => self
+                .allow_network_map()
+                .iter(Direction::Forward, None)
+                .map(|entry| entry.map(|entry| entry.id))
+                .collect::<Result<Vec<_>>>()?,
+            NetworkListKind::Block => self
+                .block_network_map()
+                .iter(Direction::Forward, None)
+                .map(|entry| entry.map(|entry| entry.id))
+                .collect::<Result<Vec<_>>>()?,
+        };
+        let last_hits: std::collections::HashMap<_, _> = self
+            .network_hits_map()
+            .by_kind(kind)?
+            .into_iter()
+            .map(|hits| (hits.id, hits.last_hit_time))
+            .collect();
+        Ok(ids
+            .into_iter()
+            .filter(|id| last_hits.get(id).map_or(true, |time| *time < since))
+            .collect())
+    }
+
+    /// Replaces the Tor exit nodes with those in the snapshot taken at
+    /// `taken_at`, and keeps the snapshot in the history along with the
+    /// `history` previous ones. The history and the current exit nodes are
+    /// updated in a single transaction.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the snapshot isn't newer than the latest one kept,
+    /// or the database operation fails.
+    pub fn refresh_tor_exit_nodes(
+        &self,
+        taken_at: chrono::DateTime<chrono::Utc>,
+        ip_addresses: impl IntoIterator<Item = std::net::IpAddr>,
+        history: usize,
+    ) -> Result<()> {
+        let snapshot = TorExitNodeSnapshot::new(taken_at, ip_addresses);
+        self.tor_exit_node_snapshot_map()
+            .refresh(&snapshot, history)
+    }
+
+    /// Returns the TI databases with rules `value` matches as `kind`, along
+    /// with the matching rules and the weights triage policies give to the
+    /// databases for `kind`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails or any TI database or
+    /// triage policy is invalid.
+    pub fn tidb_lookup(
+        &self,
+        kind: TiCmpKind,
+        value: &str,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> Result<Vec<TidbLookup>> {
+        let mut lookups = self.tidb_map().lookup(kind, value, now)?;
+        if lookups.is_empty() {
+            return Ok(lookups);
+        }
+        for policy in self.triage_policy_map().iter(Direction::Forward, None) {
+            let policy = policy?;
+            for ti in policy.ti_db.iter().filter(|ti| ti.kind == kind) {
+                if let Some(lookup) = lookups.iter_mut().find(|l| l.name == ti.ti_name) {
+                    lookup.weights.push((policy.id, ti.weight.unwrap_or(1.0)));
+                }
+            }
+        }
+        Ok(lookups)
+    }
+
+    /// Decides whether to allow, block or inspect the traffic described by
+    /// `input`, based on the allow and block network entries active at `now`,
+    /// the trusted domains and the trusted user agents.
+    ///
+    /// A block network entry containing the source or destination address
+    /// takes precedence over everything else. Otherwise, the traffic is
+    /// allowed if an allow network entry contains either address, its domain
+    /// is trusted, or its user agent is trusted, in that order of precedence.
+    /// The traffic no rule matches is to be inspected. A domain that is not a
+    /// valid domain name to look up, such as a wildcard, matches no trusted
+    /// domain.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails or any entry is
+    /// invalid.
+    pub fn verdict(
+        &self,
+        input: &VerdictInput,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> Result<Decision> {
+        let addrs: Vec<_> = [input.src_addr, input.dst_addr]
+            .into_iter()
+            .flatten()
+            .collect();
+        let mut rules = Vec::new();
+        for entry in self.block_network_map().active(now)? {
+            if let Some(addr) = addrs.iter().find(|addr| entry.networks.contains(**addr)) {
+                rules.push(VerdictRule::BlockNetwork {
+                    id: entry.id,
+                    name: entry.name,
+                    addr: *addr,
+                });
+            }
+        }
+        for entry in self.allow_network_map().active(now)? {
+            if let Some(addr) = addrs.iter().find(|addr| entry.networks.contains(**addr)) {
+                rules.push(VerdictRule::AllowNetwork {
+                    id: entry.id,
+                    name: entry.name,
+                    addr: *addr,
+                });
+            }
+        }
+        if let Some(domain) = input
+            .domain
+            .and_then(|domain| TrustedDomain::normalize_lookup(domain).ok())
+        {
+            if let Some(entry) = self.trusted_domain_map().matching_normalized(&domain)? {
+                rules.push(VerdictRule::TrustedDomain { name: entry.name });
+            }
+        }
+        if let Some(user_agent) = input.user_agent {
+            rules.extend(
+                TrustedUserAgent::matching(self, user_agent)?
+                    .into_iter()
+                    .map(|entry| VerdictRule::TrustedUserAgent {
+                        user_agent: entry.user_agent,
+                    }),
+            );
+        }
+        Ok(Decision::new(rules))
+    }
+
+    /// Replaces the model indicator with the same name as `indicator`, keeping
+    /// the replaced one as a prior version in the same transaction, and
+    /// returns the version number of `indicator`. The first version of an
+    /// indicator is 1.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails.
+    pub fn update_model_indicator(&self, indicator: ModelIndicator) -> Result<u32> {
+        self.model_indicator_map().put_versioned(indicator)
+    }
+
+    /// Returns the version `version` of the model indicator `name`, whether it
+    /// is the current one or a prior one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails.
+    pub fn model_indicator_at_version(
+        &self,
+        name: &str,
+        version: u32,
+    ) -> Result<Option<ModelIndicator>> {
+        let latest = self
+            .model_indicator_version_map()
+            .latest_version(name)?
+            .unwrap_or(0);
+        if version == latest + 1 {
+            self.model_indicator_map().get(name)
+        } else if version > latest {
+            Ok(None)
+        } else {
+            Ok(self
+                .model_indicator_version_map()
+                .get(name, version)?
+                .map(|version| version.indicator))
+        }
+    }
+
+    /// Returns the token sequences added and removed from the version `from`
+    /// to the version `to` of the model indicator `name`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if either version doesn't exist or the database
+    /// operation fails.
+    pub fn model_indicator_diff(
+        &self,
+        name: &str,
+        from: u32,
+        to: u32,
+    ) -> Result<ModelIndicatorDiff> {
+        let Some(old) = self.model_indicator_at_version(name, from)? else {
+            return Err(anyhow!("model indicator {name} has no version {from}"));
+        };
+        let Some(new) = self.model_indicator_at_version(name, to)? else {
+            return Err(anyhow!("model indicator {name} has no version {to}"));
+        };
+        Ok(old.diff(&new))
+    }
+
     /// Backup current database and keep most recent `num_backups_to_keep` backups
     ///
     /// # Errors
@@ -376,6 +837,366 @@ impl Store {
             .create_new_backup_flush(flush, num_of_backups_to_keep)
     }
 
+    /// Backup current database and keep the backups selected by `policy`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error when backup engine fails.
+    pub(crate) fn backup_with_policy(
+        &mut self,
+        flush: bool,
+        policy: &backup::RetentionPolicy,
+    ) -> Result<()> {
+        self.states.create_new_backup_with_policy(flush, policy)
+    }
+
+    /// Returns the backup retention policy stored in the database, if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails or the stored policy
+    /// is invalid.
+    pub fn backup_retention_policy(&self) -> Result<Option<backup::RetentionPolicy>> {
+        self.states.backup_retention()
+    }
+
+    /// Stores the backup retention policy, which is applied whenever a new
+    /// backup is created by `backup::create`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails.
+    pub fn set_backup_retention_policy(&self, policy: &backup::RetentionPolicy) -> Result<()> {
+        self.states.set_backup_retention(policy)
+    }
+
+    /// Returns the event retention policy stored in the database, if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails or the stored policy
+    /// is invalid.
+    pub fn event_retention_policy(&self) -> Result<Option<retention::EventRetentionPolicy>> {
+        self.states.event_retention()
+    }
+
+    /// Returns the score retention policy stored in the database, if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the policy cannot be read or deserialized.
+    pub fn score_retention_policy(&self) -> Result<Option<retention::ScoreRetentionPolicy>> {
+        self.states.score_retention()
+    }
+
+    /// Stores the score retention policy, which is applied by
+    /// `retention::purge_old_batch_scores`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the policy cannot be serialized or stored.
+    pub fn set_score_retention_policy(
+        &self,
+        policy: &retention::ScoreRetentionPolicy,
+    ) -> Result<()> {
+        self.states.set_score_retention(policy)
+    }
+
+    /// Returns the lockout policy stored in the database, if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the policy cannot be read or deserialized.
+    pub fn lockout_policy(&self) -> Result<Option<LockoutPolicy>> {
+        self.states.lockout_policy()
+    }
+
+    /// Stores the lockout policy, which is applied by
+    /// [`Store::validate_password`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the policy cannot be serialized or stored.
+    pub fn set_lockout_policy(&self, policy: &LockoutPolicy) -> Result<()> {
+        self.states.set_lockout_policy(policy)
+    }
+
+    /// Returns the password policy stored in the database, if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the policy cannot be read or deserialized.
+    pub fn password_policy(&self) -> Result<Option<PasswordPolicy>> {
+        self.states.password_policy()
+    }
+
+    /// Stores the password policy, which the accounts table enforces when a
+    /// password is changed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the policy cannot be serialized or stored.
+    pub fn set_password_policy(&self, policy: &PasswordPolicy) -> Result<()> {
+        self.states.set_password_policy(policy)
+    }
+
+    /// Returns `true` if the password of the account with `username` is older
+    /// than the maximum age in the password policy.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the account does not exist, the policy cannot be
+    /// read, or the database operation fails.
+    pub fn password_expired(&self, username: &str) -> Result<bool> {
+        self.account_map().password_expired(username)
+    }
+
+    /// Returns the session policy stored in the database, if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the policy cannot be read or deserialized.
+    pub fn session_policy(&self) -> Result<Option<SessionPolicy>> {
+        self.states.session_policy()
+    }
+
+    /// Stores the session policy, which is applied by
+    /// [`Store::validate_session`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the policy cannot be serialized or stored.
+    pub fn set_session_policy(&self, policy: &SessionPolicy) -> Result<()> {
+        self.states.set_session_policy(policy)
+    }
+
+    /// Returns the session of `username` with `token` and updates its
+    /// last-used time, or returns `None` if there is no such session or it
+    /// has expired according to the stored session policy.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the policy cannot be read or the database
+    /// operation fails.
+    pub fn validate_session(&self, username: &str, token: &str) -> Result<Option<AccessToken>> {
+        let policy = self.session_policy()?;
+        self.access_token_map()
+            .validate(username, token, policy.as_ref())
+    }
+
+    /// Validates the password of the account with `username`, along with
+    /// `totp_code` if TOTP is enabled for the account, and locks the account
+    /// after too many consecutive failures according to the stored lockout
+    /// policy. If TOTP is enabled and `totp_code` is `None`, a valid password
+    /// results in [`PasswordValidation::TotpRequired`]. A sign-in from
+    /// `address` that the account doesn't allow results in
+    /// [`PasswordValidation::AddressNotAllowed`], as described in
+    /// `Table<Account>::validate_password`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `totp_code` is given but the TOTP key is not set,
+    /// the account does not exist, the policy cannot be read, or the
+    /// database operation fails.
+    pub fn validate_password(
+        &self,
+        username: &str,
+        password: &str,
+        totp_code: Option<&str>,
+        address: Option<std::net::IpAddr>,
+    ) -> Result<PasswordValidation> {
+        let policy = self.lockout_policy()?;
+        let totp = totp_code
+            .map(|code| Ok((code, self.totp_key()?)))
+            .transpose()?;
+        self.account_map()
+            .validate_password(username, password, totp, policy.as_ref(), address)
+    }
+
+    /// Returns `true` if sign-ins to the account with `username` from `addr`
+    /// are allowed by its allowed hosts and networks.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the account does not exist or the database
+    /// operation fails.
+    pub fn is_signin_allowed(&self, username: &str, addr: std::net::IpAddr) -> Result<bool> {
+        self.account_map().is_signin_allowed(username, addr)
+    }
+
+    /// Records a sign-in attempt to the account with `username` from
+    /// `address`, successful or not, as the last one of its kind.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the account does not exist or the database
+    /// operation fails.
+    pub fn record_signin(
+        &self,
+        username: &str,
+        success: bool,
+        address: Option<std::net::IpAddr>,
+    ) -> Result<()> {
+        self.account_map().record_signin(username, success, address)
+    }
+
+    /// Sets the key encrypting TOTP secrets. The key is not stored in the
+    /// database, and must be set every time the store is opened before TOTP
+    /// is used. The key can be set only once.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `key` is not `TotpKey::LEN` bytes long or the key
+    /// is already set.
+    pub fn set_totp_key(&self, key: &[u8]) -> Result<()> {
+        self.totp_key
+            .set(TotpKey::new(key)?)
+            .map_err(|_| anyhow!("TOTP key is already set"))
+    }
+
+    fn totp_key(&self) -> Result<&TotpKey> {
+        self.totp_key
+            .get()
+            .ok_or_else(|| anyhow!("TOTP key is not set"))
+    }
+
+    /// Generates a TOTP secret for the account with `username` on behalf of
+    /// `actor`, and returns it encoded in base32 for authenticator apps. The
+    /// secret replaces the existing one, if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the TOTP key is not set, the account does not
+    /// exist, random number generation fails, or the database operation
+    /// fails.
+    pub fn enable_totp(&self, username: &str, actor: &str) -> Result<String> {
+        use ring::rand::{SecureRandom, SystemRandom};
+
+        let key = self.totp_key()?;
+        let mut secret = [0_u8; 20];
+        SystemRandom::new()
+            .fill(&mut secret)
+            .map_err(|_| anyhow!("failed to generate a TOTP secret"))?;
+        self.account_map()
+            .set_totp_secret(username, &secret, key, actor)?;
+        Ok(data_encoding::BASE32_NOPAD.encode(&secret))
+    }
+
+    /// Disables TOTP for the account with `username` on behalf of `actor`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the account does not exist or the database
+    /// operation fails.
+    pub fn disable_totp(&self, username: &str, actor: &str) -> Result<()> {
+        self.account_map().clear_totp(username, actor)
+    }
+
+    /// Verifies the TOTP `code` of the account with `username`, and locks
+    /// the account after too many consecutive failures according to the
+    /// stored lockout policy. An accepted code cannot be used again.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the TOTP key is not set, the account does not
+    /// exist, TOTP is not enabled for it, the policy cannot be read, or the
+    /// database operation fails.
+    pub fn verify_totp(&self, username: &str, code: &str) -> Result<PasswordValidation> {
+        let policy = self.lockout_policy()?;
+        self.account_map()
+            .verify_totp(username, code, self.totp_key()?, policy.as_ref())
+    }
+
+    /// Inserts `account`, and records the creation by `actor` in the account
+    /// audit table.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the account exists or the database operation
+    /// fails.
+    pub fn insert_account(&self, account: &types::Account, actor: &str) -> Result<()> {
+        self.account_map().insert(account, actor)
+    }
+
+    /// Inserts `accounts` atomically, and records their creation by `actor`
+    /// in the account audit table in the same transaction.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any of the accounts exists, appears more than once,
+    /// or has a password that violates the password policy, or the database
+    /// operation fails.
+    pub fn import_accounts(&self, accounts: &[types::Account], actor: &str) -> Result<()> {
+        self.account_map().import(accounts, actor)
+    }
+
+    /// Returns all the accounts without their secrets.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails or any account is
+    /// invalid.
+    pub fn export_accounts(&self) -> Result<Vec<ExportedAccount>> {
+        self.account_map().export()
+    }
+
+    /// Updates the account with `username` as `Table<Account>::update` does, and
+    /// records the change by `actor` in the account audit table.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the account does not exist, the update fails, or
+    /// the database operation fails.
+    #[allow(clippy::too_many_arguments, clippy::type_complexity)]
+    pub fn update_account(
+        &self,
+        actor: &str,
+        username: &str,
+        new_password: &Option<String>,
+        role: Option<(Role, Role)>,
+        name: &Option<(String, String)>,
+        department: &Option<(String, String)>,
+        allow_access_from: &Option<(Option<Vec<std::net::IpAddr>>, Option<Vec<std::net::IpAddr>>)>,
+        allowed_networks: &Option<(Option<HostNetworkGroup>, Option<HostNetworkGroup>)>,
+        max_parallel_sessions: &Option<(Option<u32>, Option<u32>)>,
+    ) -> Result<()> {
+        self.account_map().update(
+            actor,
+            username.as_bytes(),
+            new_password,
+            role,
+            name,
+            department,
+            allow_access_from,
+            allowed_networks,
+            max_parallel_sessions,
+        )
+    }
+
+    /// Deletes the account with `username`, and records the deletion by
+    /// `actor` in the account audit table.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the account does not exist or the database
+    /// operation fails.
+    pub fn delete_account(&self, username: &str, actor: &str) -> Result<()> {
+        self.account_map().delete(username, actor)
+    }
+
+    /// Stores the event retention policy, which is applied by
+    /// `retention::purge_expired_events`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails.
+    pub fn set_event_retention_policy(
+        &self,
+        policy: &retention::EventRetentionPolicy,
+    ) -> Result<()> {
+        self.states.set_event_retention(policy)
+    }
+
     /// Get the backup information for backups on file.
     ///
     /// # Errors
@@ -414,6 +1235,54 @@ impl Store {
     }
 }
 
+/// Runs `f`, which performs blocking database operations on `store`, on a
+/// thread for blocking work, so that it doesn't stall the other tasks of the
+/// tokio runtime. `store` is locked for reading until `f` returns. It can be
+/// called from any kind of runtime.
+///
+/// # Errors
+///
+/// Returns an error if `f` fails or panics.
+pub async fn run_blocking<T, F>(store: &Arc<RwLock<Store>>, f: F) -> Result<T>
+where
+    F: FnOnce(&Store) -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    let store = Arc::clone(store).read_owned().await;
+    tokio::task::spawn_blocking(move || f(&store))
+        .await
+        .context("blocking database operation panicked")?
+}
+
+/// Returns a channel that receives the events in `store` from `key` in
+/// `direction`, read on a thread for blocking work. `store` is locked for
+/// reading until reading stops, when the receiver is dropped or all the
+/// events are read.
+///
+/// # Panics
+///
+/// Panics if called outside a tokio runtime.
+#[must_use]
+pub fn event_stream(
+    store: &Arc<RwLock<Store>>,
+    key: i128,
+    direction: Direction,
+) -> tokio::sync::mpsc::Receiver<Result<(i128, Event), event::InvalidEvent>> {
+    const BUFFER: usize = 64;
+
+    let (tx, rx) = tokio::sync::mpsc::channel(BUFFER);
+    let store = Arc::clone(store);
+    tokio::task::spawn_blocking(move || {
+        let store = store.blocking_read();
+        for item in store.events().iter_from(key, direction) {
+            if tx.blocking_send(item).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
 fn parse_pretrained_file_name(name: &str) -> Result<(&str, crate::types::Timestamp)> {
     use crate::types::Timestamp;
 
@@ -428,6 +1297,13 @@ fn parse_pretrained_file_name(name: &str) -> Result<(&str, crate::types::Timesta
 const DEFAULT_PRETRAINED_EXTENSION: &str = "tmm";
 
 fn get_most_recent<P: AsRef<Path>>(name: &str, dir: P) -> Result<(i64, PathBuf)> {
+    find_most_recent(name, &dir)?.ok_or(anyhow!(
+        "Fail to locate {name:?} under {}",
+        dir.as_ref().display()
+    ))
+}
+
+fn find_most_recent<P: AsRef<Path>>(name: &str, dir: P) -> Result<Option<(i64, PathBuf)>> {
     use std::fs::read_dir;
 
     let mut most_recent = None;
@@ -438,7 +1314,7 @@ fn get_most_recent<P: AsRef<Path>>(name: &str, dir: P) -> Result<(i64, PathBuf)>
         }
         match entry.extension().and_then(std::ffi::OsStr::to_str) {
             Some(ext) => {
-                if ext != DEFAULT_PRETRAINED_EXTENSION {
+                if ext != DEFAULT_PRETRAINED_EXTENSION && ext != pretrained::MANIFEST_EXTENSION {
                     continue;
                 }
             }
@@ -460,10 +1336,7 @@ fn get_most_recent<P: AsRef<Path>>(name: &str, dir: P) -> Result<(i64, PathBuf)>
             }
         }
     }
-    most_recent.ok_or(anyhow!(
-        "Fail to locate {name:?} under {}",
-        dir.as_ref().display()
-    ))
+    Ok(most_recent)
 }
 
 #[derive(Debug, Error)]
@@ -0,0 +1,197 @@
+//! Operation counters and storage statistics of the tables.
+
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use anyhow::{anyhow, Context, Result};
+use rocksdb::{properties, OptimisticTransactionDB};
+
+use crate::{
+    notification::{Channel, Notifier},
+    TableKind,
+};
+
+/// An operation counted in [`TableMetrics`].
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Operation {
+    Read,
+    Write,
+    Remove,
+    Scan,
+}
+
+/// The number of operations made to a table since the store was opened.
+#[derive(Default)]
+pub(crate) struct Counters {
+    reads: AtomicU64,
+    writes: AtomicU64,
+    removals: AtomicU64,
+    scans: AtomicU64,
+    /// The number of times the whole table may have been replaced, e.g., by
+    /// an import or a restore.
+    invalidations: AtomicU64,
+}
+
+impl Counters {
+    pub(crate) fn record(&self, operation: Operation) {
+        let counter = match operation {
+            Operation::Read => &self.reads,
+            Operation::Write => &self.writes,
+            Operation::Remove => &self.removals,
+            Operation::Scan => &self.scans,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records that the table may have been replaced without going through
+    /// its write or remove paths.
+    pub(crate) fn invalidate(&self) {
+        self.invalidations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the number of changes made to the table, which changes
+    /// whenever the table does.
+    pub(crate) fn generation(&self) -> u64 {
+        self.writes.load(Ordering::Relaxed)
+            + self.removals.load(Ordering::Relaxed)
+            + self.invalidations.load(Ordering::Relaxed)
+    }
+}
+
+/// Counts an operation made to the table of `channel`, if any.
+pub(crate) fn record(channel: Option<&Channel>, operation: Operation) {
+    if let Some(channel) = channel {
+        channel.counters.record(operation);
+    }
+}
+
+/// The metrics of a table.
+///
+/// The operation counters start from zero when the store is opened. The
+/// storage statistics are estimates reported by RocksDB.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TableMetrics {
+    /// The number of entries looked up by key or ID.
+    pub reads: u64,
+
+    /// The number of entries inserted or updated.
+    pub writes: u64,
+
+    /// The number of entries removed.
+    pub removals: u64,
+
+    /// The number of iterators created over the table.
+    pub scans: u64,
+
+    /// The estimated number of keys.
+    pub estimated_keys: u64,
+
+    /// The total size of the SST files, in bytes.
+    pub sst_files_size: u64,
+
+    /// The approximate size of the memtables, in bytes.
+    pub memtable_size: u64,
+
+    /// The estimated memory used for reading SST files, excluding the block
+    /// cache, in bytes.
+    pub table_readers_memory: u64,
+}
+
+impl std::ops::AddAssign for TableMetrics {
+    fn add_assign(&mut self, other: Self) {
+        self.reads += other.reads;
+        self.writes += other.writes;
+        self.removals += other.removals;
+        self.scans += other.scans;
+        self.estimated_keys += other.estimated_keys;
+        self.sst_files_size += other.sst_files_size;
+        self.memtable_size += other.memtable_size;
+        self.table_readers_memory += other.table_readers_memory;
+    }
+}
+
+/// The metrics of a store, returned by [`crate::Store::metrics`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Metrics {
+    pub tables: HashMap<TableKind, TableMetrics>,
+}
+
+impl Metrics {
+    /// Returns the sum of the metrics of all tables.
+    #[must_use]
+    pub fn total(&self) -> TableMetrics {
+        let mut total = TableMetrics::default();
+        for metrics in self.tables.values() {
+            total += *metrics;
+        }
+        total
+    }
+}
+
+/// Collects the metrics of every table.
+pub(crate) fn collect(db: &OptimisticTransactionDB, notifier: &Notifier) -> Result<Metrics> {
+    let mut tables = HashMap::new();
+    for kind in TableKind::ALL {
+        let name = kind.name();
+        let cf = db
+            .cf_handle(name)
+            .ok_or_else(|| anyhow!("database must have column family \"{name}\""))?;
+        let property = |property: &properties::PropName| -> Result<u64> {
+            Ok(db
+                .property_int_value_cf(cf, property)
+                .context("cannot read database property")?
+                .unwrap_or_default())
+        };
+        let mut metrics = TableMetrics {
+            estimated_keys: property(properties::ESTIMATE_NUM_KEYS)?,
+            sst_files_size: property(properties::TOTAL_SST_FILES_SIZE)?,
+            memtable_size: property(properties::CUR_SIZE_ALL_MEM_TABLES)?,
+            table_readers_memory: property(properties::ESTIMATE_TABLE_READERS_MEM)?,
+            ..TableMetrics::default()
+        };
+        if let Some(channel) = notifier.channel(name) {
+            let counters = &channel.counters;
+            metrics.reads = counters.reads.load(Ordering::Relaxed);
+            metrics.writes = counters.writes.load(Ordering::Relaxed);
+            metrics.removals = counters.removals.load(Ordering::Relaxed);
+            metrics.scans = counters.scans.load(Ordering::Relaxed);
+        }
+        tables.insert(kind, metrics);
+    }
+    Ok(Metrics { tables })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Iterable, Store, TableKind};
+
+    #[test]
+    fn metrics() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+        let before = store.metrics().unwrap();
+
+        let table = store.category_map();
+        let id = table.insert("category 3").unwrap();
+        assert!(table.get_by_id(id).unwrap().is_some());
+        assert_eq!(table.iter(crate::Direction::Forward, None).count(), 3);
+        table.remove(id).unwrap();
+
+        let map = store.trusted_dns_server_map();
+        map.put(b"key", b"value").unwrap();
+
+        let after = store.metrics().unwrap();
+        let categories = after.tables[&TableKind::Categories];
+        let prev = before.tables[&TableKind::Categories];
+        assert_eq!(categories.writes - prev.writes, 1);
+        assert!(categories.reads > prev.reads);
+        assert!(categories.scans > prev.scans);
+        assert_eq!(categories.removals - prev.removals, 1);
+        assert_eq!(after.tables[&TableKind::TrustedDnsServers].writes, 1);
+        assert_eq!(after.total().writes - before.total().writes, 2);
+        assert!(after.tables[&TableKind::TrustedDnsServers].estimated_keys >= 1);
+    }
+}
@@ -32,7 +32,15 @@ use tracing::{info, warn};
 /// // the database format won't be changed in the future alpha or beta versions.
 /// const COMPATIBLE_VERSION: &str = ">=0.5.0-alpha.2,<=0.5.0-alpha.4";
 /// ```
-const COMPATIBLE_VERSION_REQ: &str = ">=0.26.0,<=0.27.0-alpha.6";
+const COMPATIBLE_VERSION_REQ: &str = ">=0.27.0-alpha.41,<=0.27.0-alpha.41";
+
+/// Returns `true` if data created by `version` uses the current database format.
+pub(crate) fn is_compatible(version: &Version) -> bool {
+    let Ok(compatible) = VersionReq::parse(COMPATIBLE_VERSION_REQ) else {
+        unreachable!("COMPATIBLE_VERSION_REQ must be valid")
+    };
+    compatible.matches(version)
+}
 
 /// Migrates data exists in `PostgresQL` to Rocksdb if necessary.
 ///
@@ -109,11 +117,18 @@ pub fn migrate_data_dir<P: AsRef<Path>>(data_dir: P, backup_dir: P) -> Result<()
     //   to "to version". The function name should be in the form of "migrate_A_to_B" where A is
     //   the first version (major.minor) in the "version requirement" and B is the "to version"
     //   (major.minor). (NOTE: Once we release 1.0.0, A and B will contain the major version only.)
-    let migration: Vec<Migration> = vec![(
-        VersionReq::parse(">=0.25.0,<0.26.0")?,
-        Version::parse("0.26.0")?,
-        migrate_0_25_to_0_26,
-    )];
+    let migration: Vec<Migration> = vec![
+        (
+            VersionReq::parse(">=0.25.0,<0.26.0")?,
+            Version::parse("0.26.0")?,
+            migrate_0_25_to_0_26,
+        ),
+        (
+            VersionReq::parse(">=0.26.0,<0.27.0-alpha.41")?,
+            Version::parse("0.27.0-alpha.41")?,
+            migrate_0_26_to_0_27,
+        ),
+    ];
 
     let mut store = super::Store::new(data_dir, backup_dir)?;
     store.backup(false, 1)?;
@@ -331,6 +346,8 @@ fn migrate_0_25_to_0_26(store: &super::Store) -> Result<()> {
                 setting: input.as_is.map(std::convert::Into::into),
                 setting_draft: input.to_be.map(std::convert::Into::into),
                 creation_time: input.creation_time,
+                applied_time: None,
+                applied_by: None,
             })
         }
     }
@@ -354,6 +371,448 @@ fn migrate_0_25_to_0_26(store: &super::Store) -> Result<()> {
     Ok(())
 }
 
+fn migrate_0_26_to_0_27(store: &super::Store) -> Result<()> {
+    use crate::collections::Indexed;
+    use crate::{types::FromKeyValue, Category, IterableMap};
+    use bincode::Options;
+
+    #[derive(Deserialize, Serialize)]
+    struct OldCategory {
+        id: u32,
+        name: String,
+    }
+
+    impl From<OldCategory> for Category {
+        fn from(input: OldCategory) -> Self {
+            Self {
+                id: input.id,
+                name: input.name,
+                parent_id: None,
+            }
+        }
+    }
+
+    let map = store.category_map();
+    let raw = map.raw();
+    for (key, old_value) in raw.iter_forward()? {
+        if Category::from_key_value(&key, &old_value).is_ok() {
+            continue;
+        }
+        let old_category = bincode::DefaultOptions::new()
+            .deserialize::<OldCategory>(&old_value)
+            .context("Failed to migrate category database: invalid category value")?;
+        raw.overwrite(&Category::from(old_category))?;
+    }
+    migrate_0_26_to_0_27_triage_policy(store)?;
+    migrate_0_26_to_0_27_triage_response(store)?;
+    migrate_0_26_to_0_27_account(store)?;
+    migrate_0_26_to_0_27_access_token(store)?;
+    migrate_0_26_to_0_27_node(store)?;
+    migrate_0_26_to_0_27_allow_network(store)?;
+    migrate_0_26_to_0_27_block_network(store)?;
+    migrate_0_26_to_0_27_traffic_filter(store)?;
+    migrate_0_26_to_0_27_sampling_policy(store)?;
+    migrate_0_26_to_0_27_tidb(store)?;
+    migrate_0_26_to_0_27_trusted_user_agent(store)?;
+    store.events().rebuild_address_index()?;
+    store.events().rebuild_sensor_stats()?;
+    Ok(())
+}
+
+/// Adds the scope, customers and networks, and the activation settings to
+/// triage policies and their history.
+///
+/// A policy is stored in one of two older formats: one without both, used
+/// until 0.27.0-alpha.7, and one with the scope only, used in 0.27.0-alpha.8.
+fn migrate_0_26_to_0_27_triage_policy(store: &super::Store) -> Result<()> {
+    use crate::collections::Indexed;
+    use crate::{
+        types::FromKeyValue, Confidence, IterableMap, PacketAttr, Response, Ti, TriagePolicy,
+        TriagePolicyVersion,
+    };
+    use bincode::Options;
+    use chrono::{DateTime, Utc};
+
+    #[derive(Deserialize, Serialize)]
+    struct OldTriagePolicy {
+        id: u32,
+        name: String,
+        ti_db: Vec<Ti>,
+        packet_attr: Vec<PacketAttr>,
+        confidence: Vec<Confidence>,
+        response: Vec<Response>,
+        creation_time: DateTime<Utc>,
+    }
+
+    #[derive(Deserialize, Serialize)]
+    struct ScopedTriagePolicy {
+        id: u32,
+        name: String,
+        ti_db: Vec<Ti>,
+        packet_attr: Vec<PacketAttr>,
+        confidence: Vec<Confidence>,
+        response: Vec<Response>,
+        customer_ids: Vec<u32>,
+        network_ids: Vec<u32>,
+        creation_time: DateTime<Utc>,
+    }
+
+    impl From<OldTriagePolicy> for ScopedTriagePolicy {
+        fn from(input: OldTriagePolicy) -> Self {
+            Self {
+                id: input.id,
+                name: input.name,
+                ti_db: input.ti_db,
+                packet_attr: input.packet_attr,
+                confidence: input.confidence,
+                response: input.response,
+                customer_ids: Vec::new(),
+                network_ids: Vec::new(),
+                creation_time: input.creation_time,
+            }
+        }
+    }
+
+    impl From<ScopedTriagePolicy> for TriagePolicy {
+        fn from(input: ScopedTriagePolicy) -> Self {
+            Self {
+                id: input.id,
+                name: input.name,
+                ti_db: input.ti_db,
+                packet_attr: input.packet_attr,
+                confidence: input.confidence,
+                response: input.response,
+                customer_ids: input.customer_ids,
+                network_ids: input.network_ids,
+                enabled: true,
+                active_from: None,
+                active_until: None,
+                creation_time: input.creation_time,
+            }
+        }
+    }
+
+    #[derive(Deserialize, Serialize)]
+    struct OldVersion<P> {
+        version: u32,
+        author: Option<String>,
+        time: DateTime<Utc>,
+        policy: P,
+    }
+
+    impl<P: Into<ScopedTriagePolicy>> From<OldVersion<P>> for TriagePolicyVersion {
+        fn from(input: OldVersion<P>) -> Self {
+            Self {
+                version: input.version,
+                author: input.author,
+                time: input.time,
+                policy: input.policy.into().into(),
+            }
+        }
+    }
+
+    let codec = bincode::DefaultOptions::new();
+    let map = store.triage_policy_map();
+    let raw = map.raw();
+    for (key, old_value) in raw.iter_forward()? {
+        if TriagePolicy::from_key_value(&key, &old_value).is_ok() {
+            continue;
+        }
+        let policy = if let Ok(scoped) = codec.deserialize::<ScopedTriagePolicy>(&old_value) {
+            scoped
+        } else {
+            codec
+                .deserialize::<OldTriagePolicy>(&old_value)
+                .context("Failed to migrate triage policy database: invalid triage policy value")?
+                .into()
+        };
+        raw.overwrite(&TriagePolicy::from(policy))?;
+    }
+
+    let meta = store
+        .states
+        .map(super::tables::META)
+        .ok_or_else(|| anyhow!("meta table must be present"))?;
+    for (key, old_value) in meta.iter_forward()? {
+        if !key.starts_with(b"triage policy history ")
+            || codec
+                .deserialize::<Vec<TriagePolicyVersion>>(&old_value)
+                .is_ok()
+        {
+            continue;
+        }
+        let history: Vec<TriagePolicyVersion> = if let Ok(scoped) =
+            codec.deserialize::<Vec<OldVersion<ScopedTriagePolicy>>>(&old_value)
+        {
+            scoped.into_iter().map(Into::into).collect()
+        } else {
+            codec
+                .deserialize::<Vec<OldVersion<OldTriagePolicy>>>(&old_value)
+                .context("Failed to migrate triage policy history: invalid history value")?
+                .into_iter()
+                .map(Into::into)
+                .collect()
+        };
+        meta.put(&key, &codec.serialize(&history)?)?;
+    }
+    Ok(())
+}
+
+/// Adds the lockout state to accounts.
+fn migrate_0_26_to_0_27_account(store: &super::Store) -> Result<()> {
+    use bincode::Options;
+
+    use crate::{types::Account, types::FromKeyValue, IterableMap};
+
+    let raw = store
+        .states
+        .map(super::tables::ACCOUNTS)
+        .ok_or_else(|| anyhow!("accounts table must be present"))?;
+    for (key, old_value) in raw.iter_forward()? {
+        if Account::from_key_value(&key, &old_value).is_ok() {
+            continue;
+        }
+        let account = Account::from_v0_26(&old_value)
+            .context("Failed to migrate account database: invalid account value")?;
+        raw.put(&key, &bincode::DefaultOptions::new().serialize(&account)?)?;
+    }
+    Ok(())
+}
+
+/// Adds the time and the account of the last application of the draft, which
+/// are unknown for existing nodes, to nodes.
+fn migrate_0_26_to_0_27_node(store: &super::Store) -> Result<()> {
+    use crate::collections::Indexed;
+    use crate::{types::FromKeyValue, IterableMap, Node};
+
+    let map = store.node_map();
+    let raw = map.raw();
+    for (key, old_value) in raw.iter_forward()? {
+        if Node::from_key_value(&key, &old_value).is_ok() {
+            continue;
+        }
+        let node = Node::from_v0_26(&old_value)
+            .context("Failed to migrate node database: invalid node value")?;
+        raw.overwrite(&node)?;
+    }
+    Ok(())
+}
+
+/// Adds the expiration time to allow networks. Existing entries never expire.
+fn migrate_0_26_to_0_27_allow_network(store: &super::Store) -> Result<()> {
+    use crate::collections::Indexed;
+    use crate::{types::FromKeyValue, AllowNetwork, IterableMap};
+
+    let map = store.allow_network_map();
+    let raw = map.raw();
+    for (key, old_value) in raw.iter_forward()? {
+        if AllowNetwork::from_key_value(&key, &old_value).is_ok() {
+            continue;
+        }
+        let entry = AllowNetwork::from_v0_26(&old_value)
+            .context("Failed to migrate allow network database: invalid value")?;
+        raw.overwrite(&entry)?;
+    }
+    Ok(())
+}
+
+/// Adds the expiration time to block networks. Existing entries never expire.
+fn migrate_0_26_to_0_27_block_network(store: &super::Store) -> Result<()> {
+    use crate::collections::Indexed;
+    use crate::{types::FromKeyValue, BlockNetwork, IterableMap};
+
+    let map = store.block_network_map();
+    let raw = map.raw();
+    for (key, old_value) in raw.iter_forward()? {
+        if BlockNetwork::from_key_value(&key, &old_value).is_ok() {
+            continue;
+        }
+        let entry = BlockNetwork::from_v0_26(&old_value)
+            .context("Failed to migrate block network database: invalid value")?;
+        raw.overwrite(&entry)?;
+    }
+    Ok(())
+}
+
+/// Adds the port ranges, the other protocols and the direction to the rules of
+/// traffic filters. Existing rules apply to both directions.
+fn migrate_0_26_to_0_27_traffic_filter(store: &super::Store) -> Result<()> {
+    use crate::{IterableMap, TrafficFilter};
+    use bincode::Options;
+
+    let map = store.traffic_filter_map();
+    for (key, old_value) in map.iter_forward()? {
+        if bincode::DefaultOptions::new()
+            .deserialize::<TrafficFilter>(&old_value)
+            .is_ok()
+        {
+            continue;
+        }
+        let filter = TrafficFilter::from_v0_26(&old_value)
+            .context("Failed to migrate traffic filter database: invalid value")?;
+        map.put(&key, &bincode::DefaultOptions::new().serialize(&filter)?)?;
+    }
+    Ok(())
+}
+
+/// Moves sampling policies, which were stored in the column family of
+/// networks until 0.26, to their own column family, keeping their IDs, and
+/// adds the schedule to them. Existing policies are always active.
+///
+/// An entry is a sampling policy if it can be deserialized as one and its key
+/// is the name of the policy; the key of a network has its ID appended to the
+/// name.
+fn migrate_0_26_to_0_27_sampling_policy(store: &super::Store) -> Result<()> {
+    use crate::collections::Indexed;
+    use crate::{types::FromKeyValue, IterableMap, SamplingPolicy};
+
+    let networks = store.network_map();
+    let networks = networks.raw();
+    let mut moved = Vec::new();
+    for (key, value) in networks.iter_forward()? {
+        let policy = SamplingPolicy::from_key_value(&key, &value)
+            .or_else(|_| SamplingPolicy::from_v0_26(&value));
+        if let Ok(policy) = policy {
+            if policy.name.as_bytes() == key.as_ref() {
+                moved.push(policy);
+            }
+        }
+    }
+
+    let map = store.sampling_policy_map();
+    let policies = map.raw();
+    for policy in moved {
+        // A previous run may have stopped after moving the policy but before
+        // removing it from the networks.
+        let already_moved = policies
+            .get_by_id::<SamplingPolicy>(policy.id)?
+            .is_some_and(|existing| existing.name == policy.name);
+        if !already_moved {
+            policies
+                .insert_at(&policy)
+                .context("Failed to migrate sampling policy database")?;
+        }
+        networks.remove::<SamplingPolicy>(policy.id)?;
+    }
+    Ok(())
+}
+
+/// Adds the confidence and the valid-until time to the rules of TI databases.
+/// Existing rules have neither.
+fn migrate_0_26_to_0_27_tidb(store: &super::Store) -> Result<()> {
+    use crate::{types::FromKeyValue, IterableMap, Tidb};
+
+    let raw = store
+        .states
+        .map(super::tables::TIDB)
+        .ok_or_else(|| anyhow!("TI database table must be present"))?;
+    let map = store.tidb_map();
+    for (key, old_value) in raw.iter_forward()? {
+        if Tidb::from_key_value(&key, &old_value).is_ok() {
+            continue;
+        }
+        let tidb =
+            Tidb::from_v0_26(&old_value).context("Failed to migrate TI database: invalid value")?;
+        map.insert(tidb)?;
+    }
+    Ok(())
+}
+
+/// Adds the creation and last-used times and the device to access tokens,
+/// which are stored without a value in 0.26.
+fn migrate_0_26_to_0_27_access_token(store: &super::Store) -> Result<()> {
+    use crate::{types::FromKeyValue, AccessToken, IterableMap};
+
+    let raw = store
+        .states
+        .map(super::tables::ACCESS_TOKENS)
+        .ok_or_else(|| anyhow!("access tokens table must be present"))?;
+    for (key, old_value) in raw.iter_forward()? {
+        if AccessToken::from_key_value(&key, &old_value).is_ok() {
+            continue;
+        }
+        raw.put(&key, &AccessToken::value_from_v0_26()?)?;
+    }
+    Ok(())
+}
+
+/// Adds the kind to trusted user agents, which are stored with the time of the
+/// last update in UTF-8 in 0.26, as exact matches.
+fn migrate_0_26_to_0_27_trusted_user_agent(store: &super::Store) -> Result<()> {
+    use crate::{types::FromKeyValue, IterableMap, TrustedUserAgent};
+
+    let map = store.trusted_user_agent_map();
+    for (key, old_value) in map.iter_forward()? {
+        if TrustedUserAgent::from_key_value(&key, &old_value).is_ok() {
+            continue;
+        }
+        map.put(&key, &TrustedUserAgent::value_from_v0_26(&old_value)?)?;
+    }
+    Ok(())
+}
+
+/// Adds the triggering policy, which is unknown for existing responses, to
+/// triage responses.
+fn migrate_0_26_to_0_27_triage_response(store: &super::Store) -> Result<()> {
+    use crate::{types::FromKeyValue, IterableMap, TriageResponse, TriggeredPolicy};
+    use bincode::Options;
+    use chrono::{DateTime, Utc};
+
+    #[derive(Deserialize, Serialize)]
+    struct OldTriageResponse {
+        id: u32,
+        key: Vec<u8>,
+        source: String,
+        time: DateTime<Utc>,
+        tag_ids: Vec<u32>,
+        remarks: String,
+        creation_time: DateTime<Utc>,
+        last_modified_time: DateTime<Utc>,
+    }
+
+    // Has the same layout as `TriageResponse`, whose fields are private.
+    #[derive(Deserialize, Serialize)]
+    struct NewTriageResponse {
+        id: u32,
+        key: Vec<u8>,
+        source: String,
+        time: DateTime<Utc>,
+        tag_ids: Vec<u32>,
+        remarks: String,
+        creation_time: DateTime<Utc>,
+        last_modified_time: DateTime<Utc>,
+        policy: Option<TriggeredPolicy>,
+    }
+
+    let codec = bincode::DefaultOptions::new();
+    let map = store
+        .states
+        .map(crate::TableKind::TriageResponses.name())
+        .ok_or_else(|| anyhow!("triage response table must be present"))?;
+    for (key, old_value) in map.iter_forward()? {
+        // The empty key is for the index of IDs.
+        if key.is_empty() || TriageResponse::from_key_value(&key, &old_value).is_ok() {
+            continue;
+        }
+        let old = codec
+            .deserialize::<OldTriageResponse>(&old_value)
+            .context("Failed to migrate triage response database: invalid response value")?;
+        let new = NewTriageResponse {
+            id: old.id,
+            key: old.key,
+            source: old.source,
+            time: old.time,
+            tag_ids: old.tag_ids,
+            remarks: old.remarks,
+            creation_time: old.creation_time,
+            last_modified_time: old.last_modified_time,
+            policy: None,
+        };
+        map.put(&key, &codec.serialize(&new)?)?;
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use std::borrow::Cow;
@@ -602,4 +1061,313 @@ mod tests {
         assert_eq!(new_node.id, 0);
         assert_eq!(new_node.name, "name");
     }
+
+    #[test]
+    fn migrate_0_26_to_0_27_category() {
+        use crate::collections::Indexed;
+        use crate::{Category, Indexable};
+        use bincode::Options;
+        use serde::{Deserialize, Serialize};
+
+        #[derive(Deserialize, Serialize)]
+        struct OldCategory {
+            id: u32,
+            name: String,
+        }
+
+        impl Indexable for OldCategory {
+            fn key(&self) -> Cow<[u8]> {
+                Cow::Borrowed(self.name.as_bytes())
+            }
+
+            fn index(&self) -> u32 {
+                self.id
+            }
+
+            fn make_indexed_key(key: Cow<[u8]>, _index: u32) -> Cow<[u8]> {
+                key
+            }
+
+            fn value(&self) -> Vec<u8> {
+                bincode::DefaultOptions::new()
+                    .serialize(self)
+                    .expect("serializable")
+            }
+
+            fn set_index(&mut self, index: u32) {
+                self.id = index;
+            }
+        }
+
+        let settings = TestSchema::new();
+        let map = settings.store.category_map();
+        let raw = map.raw();
+        let id = raw
+            .insert(OldCategory {
+                id: u32::MAX,
+                name: "old category".to_string(),
+            })
+            .unwrap();
+        let (db_dir, backup_dir) = settings.close();
+        let settings = TestSchema::new_with_dir(db_dir, backup_dir);
+        assert!(super::migrate_0_26_to_0_27(&settings.store).is_ok());
+
+        let map = settings.store.category_map();
+        let category = map.get_by_id(id).unwrap().unwrap();
+        assert_eq!(
+            category,
+            Category {
+                id,
+                name: "old category".to_string(),
+                parent_id: None,
+            }
+        );
+        assert!(map.get_by_id(1).unwrap().is_some());
+    }
+
+    #[test]
+    fn migrate_0_26_to_0_27_triage_policy() {
+        use crate::collections::Indexed;
+        use crate::{Indexable, Response, ResponseKind};
+        use bincode::Options;
+        use chrono::{DateTime, Utc};
+        use serde::{Deserialize, Serialize};
+
+        #[derive(Deserialize, Serialize)]
+        struct OldTriagePolicy {
+            id: u32,
+            name: String,
+            ti_db: Vec<crate::Ti>,
+            packet_attr: Vec<crate::PacketAttr>,
+            confidence: Vec<crate::Confidence>,
+            response: Vec<Response>,
+            creation_time: DateTime<Utc>,
+        }
+
+        impl Indexable for OldTriagePolicy {
+            fn key(&self) -> Cow<[u8]> {
+                Cow::Borrowed(self.name.as_bytes())
+            }
+
+            fn index(&self) -> u32 {
+                self.id
+            }
+
+            fn make_indexed_key(key: Cow<[u8]>, _index: u32) -> Cow<[u8]> {
+                key
+            }
+
+            fn value(&self) -> Vec<u8> {
+                bincode::DefaultOptions::new()
+                    .serialize(self)
+                    .expect("serializable")
+            }
+
+            fn set_index(&mut self, index: u32) {
+                self.id = index;
+            }
+        }
+
+        let settings = TestSchema::new();
+        let map = settings.store.triage_policy_map();
+        let raw = map.raw();
+        let id = raw
+            .insert(OldTriagePolicy {
+                id: u32::MAX,
+                name: "old policy".to_string(),
+                ti_db: vec![],
+                packet_attr: vec![],
+                confidence: vec![],
+                response: vec![Response {
+                    minimum_score: 1.0,
+                    kind: ResponseKind::Manual,
+                }],
+                creation_time: Utc::now(),
+            })
+            .unwrap();
+        let (db_dir, backup_dir) = settings.close();
+        let settings = TestSchema::new_with_dir(db_dir, backup_dir);
+        assert!(super::migrate_0_26_to_0_27(&settings.store).is_ok());
+
+        let map = settings.store.triage_policy_map();
+        let policy = map.get_by_id(id).unwrap().unwrap();
+        assert_eq!(policy.name, "old policy");
+        assert_eq!(policy.response.len(), 1);
+        assert!(policy.customer_ids.is_empty());
+        assert!(policy.network_ids.is_empty());
+        assert!(policy.enabled);
+        assert!(policy.active_from.is_none() && policy.active_until.is_none());
+    }
+
+    #[test]
+    fn migrate_0_26_to_0_27_triage_response() {
+        use crate::{TableKind, TriageResponse};
+        use bincode::Options;
+        use chrono::{DateTime, Utc};
+        use serde::{Deserialize, Serialize};
+
+        #[derive(Deserialize, Serialize)]
+        struct OldTriageResponse {
+            id: u32,
+            key: Vec<u8>,
+            source: String,
+            time: DateTime<Utc>,
+            tag_ids: Vec<u32>,
+            remarks: String,
+            creation_time: DateTime<Utc>,
+            last_modified_time: DateTime<Utc>,
+        }
+
+        let settings = TestSchema::new();
+        let time = Utc::now();
+        let key = b"source key".to_vec();
+        let old = OldTriageResponse {
+            id: 0,
+            key: key.clone(),
+            source: "source".to_string(),
+            time,
+            tag_ids: vec![1],
+            remarks: "remarks".to_string(),
+            creation_time: time,
+            last_modified_time: time,
+        };
+        settings
+            .store
+            .states
+            .map(TableKind::TriageResponses.name())
+            .unwrap()
+            .put(
+                &key,
+                &bincode::DefaultOptions::new().serialize(&old).unwrap(),
+            )
+            .unwrap();
+        let (db_dir, backup_dir) = settings.close();
+        let settings = TestSchema::new_with_dir(db_dir, backup_dir);
+        assert!(super::migrate_0_26_to_0_27(&settings.store).is_ok());
+
+        let value = settings
+            .store
+            .states
+            .map(TableKind::TriageResponses.name())
+            .unwrap()
+            .get(&key)
+            .unwrap()
+            .unwrap();
+        let response: TriageResponse = bincode::DefaultOptions::new()
+            .deserialize(value.as_ref())
+            .unwrap();
+        assert_eq!(response.remarks, "remarks");
+        assert_eq!(response.tag_ids(), [1]);
+        assert!(response.policy().is_none());
+    }
+
+    #[test]
+    fn migrate_0_26_to_0_27_trusted_user_agent() {
+        use crate::{TrustedUserAgent, UserAgentKind};
+
+        let settings = TestSchema::new();
+        let map = settings.store.trusted_user_agent_map();
+        map.put(b"curl/8.0.1", b"2024-01-01 00:00:00 UTC").unwrap();
+        map.put(b"Wget/1.21", b"invalid time").unwrap();
+        let (db_dir, backup_dir) = settings.close();
+        let settings = TestSchema::new_with_dir(db_dir, backup_dir);
+        assert!(super::migrate_0_26_to_0_27(&settings.store).is_ok());
+
+        let entries = TrustedUserAgent::get_list(&settings.store).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].user_agent, "Wget/1.21");
+        assert_eq!(entries[0].kind, UserAgentKind::Exact);
+        assert!(entries[0].updated_at.is_none());
+        assert_eq!(entries[1].user_agent, "curl/8.0.1");
+        assert_eq!(entries[1].kind, UserAgentKind::Exact);
+        assert_eq!(
+            entries[1].updated_at,
+            Some("2024-01-01T00:00:00Z".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn migrate_0_26_to_0_27_sampling_policy() {
+        use crate::collections::Indexed;
+        use crate::{
+            HostNetworkGroup, Iterable, Network, SamplingInterval, SamplingKind, SamplingPeriod,
+            SamplingPolicy,
+        };
+
+        let settings = TestSchema::new();
+        let networks = settings.store.network_map();
+        networks
+            .insert(Network::new(
+                "network".to_string(),
+                String::new(),
+                HostNetworkGroup::default(),
+                vec![],
+                vec![],
+            ))
+            .unwrap();
+        let policy = SamplingPolicy {
+            id: 0,
+            name: "policy".to_string(),
+            kind: SamplingKind::Conn,
+            interval: SamplingInterval::FifteenMinutes,
+            period: SamplingPeriod::TwelveHours,
+            offset: 0,
+            src_ip: None,
+            dst_ip: None,
+            node: None,
+            column: None,
+            immutable: false,
+            creation_time: chrono::Utc::now(),
+            schedule: None,
+        };
+        let id = networks.raw().insert(policy).unwrap();
+        assert_eq!(id, 1);
+
+        assert!(super::migrate_0_26_to_0_27_sampling_policy(&settings.store).is_ok());
+        let networks: Vec<_> = networks
+            .iter(rocksdb::Direction::Forward, None)
+            .collect::<anyhow::Result<_>>()
+            .unwrap();
+        assert_eq!(networks.len(), 1);
+        assert_eq!(networks[0].name, "network");
+        let policies = settings.store.sampling_policy_map();
+        let policy = policies.get_by_id(1).unwrap().unwrap();
+        assert_eq!(policy.name, "policy");
+        assert_eq!(policies.effective_at(chrono::Utc::now()).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn migrate_0_26_to_0_27_sampling_policy_interrupted() {
+        use crate::collections::Indexed;
+        use crate::{SamplingInterval, SamplingKind, SamplingPeriod, SamplingPolicy};
+
+        let settings = TestSchema::new();
+        let policy = SamplingPolicy {
+            id: 0,
+            name: "policy".to_string(),
+            kind: SamplingKind::Conn,
+            interval: SamplingInterval::FifteenMinutes,
+            period: SamplingPeriod::TwelveHours,
+            offset: 0,
+            src_ip: None,
+            dst_ip: None,
+            node: None,
+            column: None,
+            immutable: false,
+            creation_time: chrono::Utc::now(),
+            schedule: None,
+        };
+        let networks = settings.store.network_map();
+        let id = networks.raw().insert(policy.clone()).unwrap();
+        let mut moved = policy;
+        moved.id = id;
+        let policies = settings.store.sampling_policy_map();
+        policies.raw().insert_at(&moved).unwrap();
+
+        assert!(super::migrate_0_26_to_0_27_sampling_policy(&settings.store).is_ok());
+        assert_eq!(networks.raw().count().unwrap(), 0);
+        assert_eq!(policies.raw().count().unwrap(), 1);
+        let policy = policies.get_by_id(id).unwrap().unwrap();
+        assert_eq!(policy.name, "policy");
+    }
 }
@@ -0,0 +1,483 @@
+//! Import of MISP events exported as JSON into the TI databases and the model
+//! indicators.
+
+use std::collections::{BTreeSet, HashSet};
+
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+use serde::Deserialize;
+
+use crate::{ModelIndicator, Store, Ti, TiCmpKind, Tidb, TidbKind, TidbRule};
+
+/// The result of [`MispImport::import`].
+pub struct MispImport {
+    /// The TI databases added or replaced, each with the kind of the values it
+    /// should be compared with in a triage policy.
+    pub ti: Vec<Ti>,
+
+    /// The TI databases from an earlier import with the same name that this
+    /// import has no values for, which are removed.
+    pub removed: Vec<String>,
+
+    /// The name of the model indicator added or replaced, if any.
+    pub model_indicator: Option<String>,
+
+    /// The IDs of the categories the tags of the events map to.
+    pub categories: Vec<u32>,
+
+    /// The tags that don't map to any category.
+    pub unmapped_tags: Vec<String>,
+
+    /// The UUIDs of the attributes not imported, either because they are not
+    /// for detection or because their types are not supported.
+    pub skipped: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Export {
+    Event {
+        #[serde(rename = "Event")]
+        event: Event,
+    },
+    Response {
+        response: Vec<Export>,
+    },
+    Events(Vec<Export>),
+}
+
+#[derive(Deserialize)]
+struct Event {
+    #[serde(default)]
+    info: String,
+    #[serde(default, rename = "Tag")]
+    tags: Vec<Tag>,
+    #[serde(default, rename = "Attribute")]
+    attributes: Vec<Attribute>,
+    #[serde(default, rename = "Object")]
+    objects: Vec<Object>,
+}
+
+#[derive(Deserialize)]
+struct Object {
+    #[serde(default, rename = "Attribute")]
+    attributes: Vec<Attribute>,
+}
+
+#[derive(Deserialize)]
+struct Attribute {
+    #[serde(default)]
+    uuid: String,
+    #[serde(rename = "type")]
+    kind: String,
+    value: String,
+    #[serde(default)]
+    comment: String,
+    #[serde(default)]
+    to_ids: bool,
+    #[serde(default)]
+    deleted: bool,
+    #[serde(default, rename = "Tag")]
+    tags: Vec<Tag>,
+}
+
+#[derive(Deserialize)]
+struct Tag {
+    name: String,
+}
+
+impl Export {
+    fn into_events(self, events: &mut Vec<Event>) {
+        match self {
+            Self::Event { event } => events.push(event),
+            Self::Response { response: exports } | Self::Events(exports) => {
+                for export in exports {
+                    export.into_events(events);
+                }
+            }
+        }
+    }
+}
+
+/// Attribute types whose values are token sequences for a model indicator.
+const PATTERN_TYPES: [&str; 3] = ["pattern-in-file", "pattern-in-memory", "pattern-in-traffic"];
+
+/// Returns the values of an attribute to look for in events, with the kinds
+/// of the values.
+fn indicators<'a>(kind: &str, value: &'a str) -> Vec<(TiCmpKind, &'a str)> {
+    let (first, second) = match value.split_once('|') {
+        Some((first, second)) => (first, Some(second)),
+        None => (value, None),
+    };
+    let kinds: &[Option<TiCmpKind>] = match kind {
+        "ip-src" | "ip-dst" | "ip-src|port" | "ip-dst|port" => &[Some(TiCmpKind::IpAddress)],
+        "domain" => &[Some(TiCmpKind::Domain)],
+        "domain|ip" => &[Some(TiCmpKind::Domain), Some(TiCmpKind::IpAddress)],
+        "hostname" | "hostname|port" => &[Some(TiCmpKind::Hostname)],
+        "url" | "uri" => &[Some(TiCmpKind::Uri)],
+        "ja3-fingerprint-md5" => &[Some(TiCmpKind::Ja3)],
+        "md5" | "sha1" | "sha224" | "sha256" | "sha384" | "sha512" => &[Some(TiCmpKind::FileHash)],
+        "filename|md5" | "filename|sha1" | "filename|sha224" | "filename|sha256"
+        | "filename|sha384" | "filename|sha512" => &[None, Some(TiCmpKind::FileHash)],
+        "email" | "email-src" | "email-dst" => &[Some(TiCmpKind::Email)],
+        _ => &[],
+    };
+    // A URL may contain `|`, so only composite types are split.
+    let values = if kind.contains('|') {
+        [Some(first), second]
+    } else {
+        [Some(value), None]
+    };
+    kinds
+        .iter()
+        .zip(values)
+        .filter_map(|(kind, value)| Some(((*kind)?, value?.trim())))
+        .filter(|(_, value)| !value.is_empty())
+        .collect()
+}
+
+/// The kinds of values imported into TI databases.
+const TI_CMP_KINDS: [TiCmpKind; 8] = [
+    TiCmpKind::IpAddress,
+    TiCmpKind::Domain,
+    TiCmpKind::Hostname,
+    TiCmpKind::Uri,
+    TiCmpKind::Ja3,
+    TiCmpKind::Ja4,
+    TiCmpKind::FileHash,
+    TiCmpKind::Email,
+];
+
+/// Returns the kind of TI database for values of `kind`, and the suffix of its
+/// name.
+fn tidb_kind(kind: TiCmpKind) -> (TidbKind, &'static str) {
+    match kind {
+        TiCmpKind::IpAddress => (TidbKind::Ip, "ip"),
+        TiCmpKind::Domain => (TidbKind::Url, "domain"),
+        TiCmpKind::Hostname => (TidbKind::Url, "hostname"),
+        TiCmpKind::Uri => (TidbKind::Url, "uri"),
+        TiCmpKind::Ja3 => (TidbKind::Token, "ja3"),
+        TiCmpKind::Ja4 => (TidbKind::Token, "ja4"),
+        TiCmpKind::FileHash => (TidbKind::Token, "file-hash"),
+        TiCmpKind::Email => (TidbKind::Token, "email"),
+    }
+}
+
+/// The TI databases being imported, each with the kind of its values.
+struct Tidbs<'a> {
+    name: &'a str,
+    id: u32,
+    version: &'a str,
+    tidbs: Vec<(TiCmpKind, Tidb)>,
+}
+
+impl Tidbs<'_> {
+    /// Adds a rule for `value` of `attribute` to the database for `kind`.
+    fn add(&mut self, kind: TiCmpKind, value: &str, attribute: &Attribute) -> Result<()> {
+        let pos = if let Some(pos) = self.tidbs.iter().position(|(k, _)| *k == kind) {
+            pos
+        } else {
+            let (tidb_kind, suffix) = tidb_kind(kind);
+            self.tidbs.push((
+                kind,
+                Tidb {
+                    id: self.id,
+                    name: format!("{}-{suffix}", self.name),
+                    description: None,
+                    kind: tidb_kind,
+                    version: self.version.to_string(),
+                    patterns: Vec::new(),
+                },
+            ));
+            self.tidbs.len() - 1
+        };
+        let tidb = &mut self.tidbs[pos].1;
+        tidb.patterns.push(TidbRule {
+            rule_id: u32::try_from(tidb.patterns.len() + 1).context("too many attributes")?,
+            name: attribute.uuid.clone(),
+            description: (!attribute.comment.is_empty()).then(|| attribute.comment.clone()),
+            references: None,
+            samples: None,
+            signatures: Some(vec![value.to_string()]),
+            confidence: None,
+            valid_until: None,
+        });
+        Ok(())
+    }
+}
+
+/// Returns the name of the category a tag maps to. The value of a machine tag,
+/// such as `Ransomware` of `ecsirt:malicious-code="Ransomware"`, is used
+/// instead of the whole tag.
+fn category_name(tag: &str) -> &str {
+    match tag.split_once('=') {
+        Some((_, value)) => value.trim().trim_matches('"'),
+        None => tag.trim(),
+    }
+}
+
+/// Returns the IDs of the categories `tags` map to, and the tags that don't
+/// map to any.
+fn map_tags(store: &Store, tags: BTreeSet<String>) -> Result<(Vec<u32>, Vec<String>)> {
+    let mut categories = Vec::new();
+    let mut unmapped_tags = Vec::new();
+    let category_map = store.category_map();
+    for tag in tags {
+        match category_map.find_by_name(category_name(&tag))? {
+            Some(category) => {
+                if !categories.contains(&category.id) {
+                    categories.push(category.id);
+                }
+            }
+            None => unmapped_tags.push(tag),
+        }
+    }
+    Ok((categories, unmapped_tags))
+}
+
+impl MispImport {
+    /// Imports the events in a MISP JSON export into the database.
+    ///
+    /// The attributes for detection, i.e., with `to_ids` set, are grouped by
+    /// the kind of their values into TI databases, named `name` followed by
+    /// the kind, such as `misp-ip`, with the given `id` and `version`. The
+    /// existing databases with the same names are replaced, and those of an
+    /// earlier import for the kinds this import has no values for are
+    /// removed. If `model_id` is given, the `pattern-in-*` attributes become
+    /// the token sequences of the model indicator `name` for the model,
+    /// keeping the replaced one as a prior version.
+    ///
+    /// Everything is written in a single transaction, so that a failed import
+    /// leaves the earlier one as it is.
+    ///
+    /// The tags of the events and the attributes map to the categories with
+    /// the same names, or with the values of machine tags. Categories are not
+    /// created for the tags; those without one are reported instead.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `data` is not a MISP JSON export, `id`, `name` or
+    /// `version` is invalid, or the database operation fails.
+    pub fn import(
+        store: &Store,
+        data: &str,
+        name: &str,
+        id: u32,
+        version: &str,
+        model_id: Option<i32>,
+    ) -> Result<Self> {
+        let export: Export = serde_json::from_str(data).context("invalid MISP export")?;
+        let mut events = Vec::new();
+        export.into_events(&mut events);
+        if events.is_empty() {
+            bail!("no MISP events to import");
+        }
+
+        let mut tidbs = Tidbs {
+            name,
+            id,
+            version,
+            tidbs: Vec::new(),
+        };
+        let mut tokens = HashSet::new();
+        let mut tags = BTreeSet::new();
+        let mut skipped = Vec::new();
+        let mut descriptions = Vec::new();
+        for event in events {
+            if !event.info.is_empty() {
+                descriptions.push(event.info);
+            }
+            tags.extend(event.tags.into_iter().map(|tag| tag.name));
+            let attributes = event
+                .attributes
+                .into_iter()
+                .chain(event.objects.into_iter().flat_map(|o| o.attributes));
+            for attribute in attributes {
+                if attribute.deleted {
+                    continue;
+                }
+                if !attribute.to_ids {
+                    skipped.push(attribute.uuid);
+                    continue;
+                }
+                if PATTERN_TYPES.contains(&attribute.kind.as_str()) && model_id.is_some() {
+                    tokens.insert(
+                        attribute
+                            .value
+                            .split_whitespace()
+                            .map(ToString::to_string)
+                            .collect(),
+                    );
+                    tags.extend(attribute.tags.into_iter().map(|tag| tag.name));
+                    continue;
+                }
+                let indicators = indicators(&attribute.kind, &attribute.value);
+                if indicators.is_empty() {
+                    skipped.push(attribute.uuid);
+                    continue;
+                }
+                for (kind, value) in indicators {
+                    tidbs.add(kind, value, &attribute)?;
+                }
+                tags.extend(attribute.tags.into_iter().map(|tag| tag.name));
+            }
+        }
+
+        let description = descriptions.join("; ");
+        let mut tidbs = tidbs.tidbs;
+        for (_, tidb) in &mut tidbs {
+            if !description.is_empty() {
+                tidb.description = Some(description.clone());
+            }
+            tidb.validate()?;
+        }
+
+        let (categories, unmapped_tags) = map_tags(store, tags)?;
+        let ti: Vec<_> = tidbs
+            .iter()
+            .map(|(kind, tidb)| Ti {
+                ti_name: tidb.name.clone(),
+                kind: *kind,
+                weight: None,
+            })
+            .collect();
+        let stale: Vec<_> = TI_CMP_KINDS
+            .into_iter()
+            .filter(|kind| tidbs.iter().all(|(k, _)| k != kind))
+            .map(|kind| format!("{name}-{}", tidb_kind(kind).1))
+            .collect();
+        let indicator = match model_id {
+            Some(model_id) if !tokens.is_empty() => Some(ModelIndicator {
+                name: name.to_string(),
+                description,
+                model_id,
+                tokens,
+                last_modification_time: Utc::now(),
+            }),
+            _ => None,
+        };
+        let removed = store.tidb_map().import_feed(
+            &store.model_indicator_map(),
+            tidbs.into_iter().map(|(_, tidb)| tidb).collect(),
+            &stale,
+            indicator.as_ref(),
+        )?;
+
+        Ok(Self {
+            ti,
+            removed,
+            model_indicator: indicator.map(|indicator| indicator.name),
+            categories,
+            unmapped_tags,
+            skipped,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{MispImport, Store, TiCmpKind};
+
+    const EXPORT: &str = r#"{
+        "response": [
+            {
+                "Event": {
+                    "info": "Phishing campaign",
+                    "Tag": [
+                        {"name": "tlp:amber"},
+                        {"name": "ecsirt:fraud=\"phishing\""}
+                    ],
+                    "Attribute": [
+                        {"uuid": "a1", "type": "ip-dst|port", "value": "198.51.100.7|443", "to_ids": true},
+                        {"uuid": "a2", "type": "domain|ip", "value": "evil.example|203.0.113.5", "to_ids": true, "comment": "C2"},
+                        {"uuid": "a3", "type": "url", "value": "http://evil.example/a|b", "to_ids": true},
+                        {"uuid": "a4", "type": "ip-src", "value": "192.0.2.1", "to_ids": false},
+                        {"uuid": "a5", "type": "whois-registrant-name", "value": "Mallory", "to_ids": true},
+                        {"uuid": "a6", "type": "pattern-in-traffic", "value": "GET /gate.php", "to_ids": true},
+                        {"uuid": "a7", "type": "ip-src", "value": "192.0.2.2", "to_ids": true, "deleted": true}
+                    ],
+                    "Object": [
+                        {
+                            "name": "file",
+                            "Attribute": [
+                                {
+                                    "uuid": "a8", "type": "filename|sha256",
+                                    "value": "invoice.exe|aec070645fe53ee3b3763059376134f058cc337247c978add178b6ccdfb0019f",
+                                    "to_ids": true,
+                                    "Tag": [{"name": "Malware"}]
+                                }
+                            ]
+                        }
+                    ]
+                }
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn import() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+        let categories = store.category_map();
+        let phishing = categories.insert("phishing").unwrap();
+        let malware = categories.insert("Malware").unwrap();
+
+        let import = MispImport::import(&store, EXPORT, "misp", 1, "1", Some(3)).unwrap();
+        let names: Vec<_> = import.ti.iter().map(|ti| ti.ti_name.as_str()).collect();
+        assert_eq!(
+            names,
+            ["misp-ip", "misp-domain", "misp-uri", "misp-file-hash"]
+        );
+        assert!(import.ti[1].kind == TiCmpKind::Domain);
+        assert_eq!(import.skipped, ["a4", "a5"]);
+        assert_eq!(import.categories, [malware, phishing]);
+        assert_eq!(import.unmapped_tags, ["tlp:amber"]);
+
+        let tidbs = store.tidb_map();
+        let ip = tidbs.get("misp-ip").unwrap().unwrap();
+        assert!(ip.matches("198.51.100.7"));
+        assert!(ip.matches("203.0.113.5"));
+        assert!(!ip.matches("192.0.2.1"));
+        assert_eq!(ip.description.as_deref(), Some("Phishing campaign"));
+        let domain = tidbs.get("misp-domain").unwrap().unwrap();
+        assert_eq!(domain.patterns[0].description.as_deref(), Some("C2"));
+        let uri = tidbs.get("misp-uri").unwrap().unwrap();
+        assert!(uri.matches("http://evil.example/a|b"));
+        let hash = tidbs.get("misp-file-hash").unwrap().unwrap();
+        assert!(hash.matches_as(
+            TiCmpKind::FileHash,
+            "AEC070645FE53EE3B3763059376134F058CC337247C978ADD178B6CCDFB0019F"
+        ));
+        assert!(!hash.matches_as(TiCmpKind::FileHash, "invoice.exe"));
+
+        assert_eq!(import.model_indicator.as_deref(), Some("misp"));
+        let indicator = store.model_indicator_map().get("misp").unwrap().unwrap();
+        assert_eq!(indicator.model_id, 3);
+        assert!(indicator
+            .tokens
+            .contains(&vec!["GET".to_string(), "/gate.php".to_string()]));
+
+        assert!(import.removed.is_empty());
+
+        // The databases of the earlier import this one has no values for are
+        // removed.
+        let export = r#"{"Event": {"Attribute": [
+            {"uuid": "b1", "type": "ip-dst", "value": "198.51.100.8", "to_ids": true}
+        ]}}"#;
+        let import = MispImport::import(&store, export, "misp", 1, "2", None).unwrap();
+        assert_eq!(import.ti.len(), 1);
+        assert_eq!(
+            import.removed,
+            ["misp-domain", "misp-uri", "misp-file-hash"]
+        );
+        assert!(import.model_indicator.is_none());
+        let ip = tidbs.get("misp-ip").unwrap().unwrap();
+        assert_eq!(ip.version, "2");
+        assert!(!ip.matches("198.51.100.7"));
+        assert!(tidbs.get("misp-domain").unwrap().is_none());
+        assert!(store.model_indicator_map().get("misp").unwrap().is_some());
+
+        assert!(MispImport::import(&store, "{}", "misp", 1, "1", None).is_err());
+    }
+}
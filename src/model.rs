@@ -1,7 +1,9 @@
-use super::{Database, Error, Type};
+use super::{cleanup::RemovedOrphans, Cursor, Database, Direction, Error, Page, PageRequest, Type};
 use anyhow::Result;
 use bincode::Options;
+use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use strum_macros::{Display, EnumString};
 
 #[derive(Deserialize, Queryable)]
@@ -235,6 +237,83 @@ pub struct SqlModel {
     classification_id: Option<i64>,
 }
 
+/// How a model was trained.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct TrainingMetadata {
+    /// When the training was done.
+    pub trained_at: NaiveDateTime,
+    /// The start of the time window of the training data, inclusive.
+    pub data_start: NaiveDateTime,
+    /// The end of the time window of the training data, exclusive.
+    pub data_end: NaiveDateTime,
+    /// The sensors the training data was collected from.
+    pub data_sources: Vec<String>,
+    pub hyperparameters: serde_json::Value,
+    /// The features the model takes, such as the columns and their types.
+    pub feature_schema: serde_json::Value,
+}
+
+impl TrainingMetadata {
+    /// Returns `true` if the model is stale for `sensor` at `now`, that is,
+    /// the model wasn't trained with data from `sensor`, or its training data
+    /// ends more than `max_age` before `now`.
+    #[must_use]
+    pub fn is_stale_for(
+        &self,
+        sensor: &str,
+        now: NaiveDateTime,
+        max_age: chrono::Duration,
+    ) -> bool {
+        !self.data_sources.iter().any(|s| s == sensor)
+            || self
+                .data_end
+                .checked_add_signed(max_age)
+                .map_or(false, |end| end < now)
+    }
+}
+
+#[derive(AsChangeset, Insertable, Queryable)]
+#[diesel(table_name = super::schema::model_training, primary_key(model_id))]
+struct TrainingMetadataRow {
+    model_id: i32,
+    trained_at: NaiveDateTime,
+    data_start: NaiveDateTime,
+    data_end: NaiveDateTime,
+    data_sources: Vec<Option<String>>,
+    hyperparameters: serde_json::Value,
+    feature_schema: serde_json::Value,
+}
+
+impl TrainingMetadataRow {
+    fn new(model_id: i32, metadata: &TrainingMetadata) -> Self {
+        Self {
+            model_id,
+            trained_at: metadata.trained_at,
+            data_start: metadata.data_start,
+            data_end: metadata.data_end,
+            data_sources: metadata.data_sources.iter().cloned().map(Some).collect(),
+            hyperparameters: metadata.hyperparameters.clone(),
+            feature_schema: metadata.feature_schema.clone(),
+        }
+    }
+}
+
+impl From<TrainingMetadataRow> for (i32, TrainingMetadata) {
+    fn from(row: TrainingMetadataRow) -> Self {
+        (
+            row.model_id,
+            TrainingMetadata {
+                trained_at: row.trained_at,
+                data_start: row.data_start,
+                data_end: row.data_end,
+                data_sources: row.data_sources.into_iter().flatten().collect(),
+                hyperparameters: row.hyperparameters,
+                feature_schema: row.feature_schema,
+            },
+        )
+    }
+}
+
 impl Database {
     const CSV_COLUMN_TYPES: &'static [&'static str] = &[
         "binary", "datetime", "enum", "float", "int", "ipaddr", "text",
@@ -304,6 +383,8 @@ impl Database {
             .await?;
         conn.delete_from("csv_column_extra", &[("model_id", Type::INT4)], &[&id])
             .await?;
+        conn.delete_from("model_training", &[("model_id", Type::INT4)], &[&id])
+            .await?;
 
         self.delete_csv_entries_under_model_name(name).await?;
 
@@ -349,6 +430,13 @@ impl Database {
         conn.delete_from("cluster", &[("model_id", Type::INT4)], &[&id])
             .await?;
 
+        conn.delete_in(
+            "cluster_history",
+            &[],
+            &[("cluster_id", Type::INT4_ARRAY)],
+            &[&cluster_ids],
+        )
+        .await?;
         conn.delete_in(
             "time_series",
             &[],
@@ -388,10 +476,77 @@ impl Database {
                 .await?;
             }
         }
+        conn.delete_in(
+            "histogram",
+            &[],
+            &[("description_id", Type::INT4_ARRAY)],
+            &[&column_description_ids],
+        )
+        .await?;
 
         Ok(())
     }
 
+    /// Deletes the rows in PostgreSQL referencing models that no longer
+    /// exist, and returns the IDs of the existing models and the numbers of
+    /// rows deleted.
+    pub(crate) async fn delete_orphans(&self) -> Result<(HashSet<i32>, RemovedOrphans), Error> {
+        use diesel::sql_query;
+        use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection, RunQueryDsl};
+
+        const ORPHAN_CLUSTER: &str = "cluster_id NOT IN (SELECT id FROM cluster)";
+        const ORPHAN_DESCRIPTION: &str =
+            "description_id NOT IN (SELECT id FROM column_description)";
+        const ORPHAN_MODEL: &str = "model_id NOT IN (SELECT id FROM model)";
+
+        let mut conn = self.pool.get_diesel_conn().await?;
+        conn.transaction::<_, Error, _>(|conn| {
+            async move {
+                use super::schema::model::dsl;
+                use diesel::QueryDsl;
+
+                let delete = |table: &str, condition: &str| {
+                    sql_query(format!("DELETE FROM {table} WHERE {condition}"))
+                };
+                let models: HashSet<i32> = dsl::model
+                    .select(dsl::id)
+                    .load::<i32>(conn)
+                    .await?
+                    .into_iter()
+                    .collect();
+
+                let mut removed = RemovedOrphans {
+                    outliers: delete("outlier", ORPHAN_MODEL).execute(conn).await?,
+                    training_metadata: delete("model_training", ORPHAN_MODEL).execute(conn).await?,
+                    clusters: delete("cluster", ORPHAN_MODEL).execute(conn).await?,
+                    ..RemovedOrphans::default()
+                };
+                removed.cluster_history = delete("cluster_history", ORPHAN_CLUSTER)
+                    .execute(conn)
+                    .await?;
+                removed.time_series = delete("time_series", ORPHAN_CLUSTER).execute(conn).await?;
+                removed.statistics.column_descriptions =
+                    delete("column_description", ORPHAN_CLUSTER)
+                        .execute(conn)
+                        .await?;
+                for table in Self::type_tables("description") {
+                    removed.statistics.descriptions +=
+                        delete(&table, ORPHAN_DESCRIPTION).execute(conn).await?;
+                }
+                for table in Self::type_tables("top_n") {
+                    removed.statistics.top_n +=
+                        delete(&table, ORPHAN_DESCRIPTION).execute(conn).await?;
+                }
+                removed.statistics.histograms = delete("histogram", ORPHAN_DESCRIPTION)
+                    .execute(conn)
+                    .await?;
+                Ok((models, removed))
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
     /// Returns the number of models.
     ///
     /// # Errors
@@ -467,65 +622,88 @@ impl Database {
         Ok(query.get_result::<SqlModel>(&mut conn).await?)
     }
 
-    /// Returns the models between `after` and `before`.
+    /// Returns a page of models in the order of their names and then of
+    /// their IDs.
     ///
     /// # Errors
     ///
-    /// Returns an error if a database operation fails.
-    pub async fn load_models(
-        &self,
-        after: &Option<(i32, String)>,
-        before: &Option<(i32, String)>,
-        is_first: bool,
-        limit: usize,
-    ) -> Result<Vec<Digest>, Error> {
+    /// Returns an error if the cursor of `request` is invalid or a database
+    /// operation fails.
+    pub async fn load_models(&self, request: &PageRequest) -> Result<Page<Digest>, Error> {
         use super::schema::model::dsl;
         use diesel::{BoolExpressionMethods, ExpressionMethods, QueryDsl};
         use diesel_async::RunQueryDsl;
 
-        let limit = i64::try_from(limit).map_err(|_| Error::InvalidInput("limit".into()))? + 1;
-        let mut query = dsl::model
-            .select((
-                dsl::id,
-                dsl::name,
-                dsl::version,
-                dsl::data_source_id,
-                dsl::classification_id,
-            ))
-            .limit(limit)
-            .into_boxed();
-
-        if let Some(after) = after {
-            query = query.filter(
-                dsl::name
-                    .eq(&after.1)
-                    .and(dsl::id.gt(after.0))
-                    .or(dsl::name.gt(&after.1)),
-            );
-        }
-        if let Some(before) = before {
-            query = query.filter(
-                dsl::name
-                    .eq(&before.1)
-                    .and(dsl::id.lt(before.0))
-                    .or(dsl::name.lt(&before.1)),
-            );
+        let cursor = request
+            .cursor
+            .as_ref()
+            .map(Cursor::key::<(String, i32)>)
+            .transpose()?;
+        let limit =
+            i64::try_from(request.limit).map_err(|_| Error::InvalidInput("limit".into()))? + 1;
+        let query = || {
+            dsl::model
+                .select((
+                    dsl::id,
+                    dsl::name,
+                    dsl::version,
+                    dsl::data_source_id,
+                    dsl::classification_id,
+                ))
+                .into_boxed()
+        };
+
+        let mut page = query();
+        let mut beyond = query();
+        if let Some((name, id)) = &cursor {
+            match request.direction {
+                Direction::Forward => {
+                    page = page.filter(
+                        dsl::name
+                            .gt(name)
+                            .or(dsl::name.eq(name).and(dsl::id.gt(id))),
+                    );
+                    beyond = beyond.filter(
+                        dsl::name
+                            .lt(name)
+                            .or(dsl::name.eq(name).and(dsl::id.le(id))),
+                    );
+                }
+                Direction::Reverse => {
+                    page = page.filter(
+                        dsl::name
+                            .lt(name)
+                            .or(dsl::name.eq(name).and(dsl::id.lt(id))),
+                    );
+                    beyond = beyond.filter(
+                        dsl::name
+                            .gt(name)
+                            .or(dsl::name.eq(name).and(dsl::id.ge(id))),
+                    );
+                }
+            }
         }
-        if is_first {
-            query = query.order_by(dsl::name.asc()).then_order_by(dsl::id.asc());
-        } else {
-            query = query
+        page = match request.direction {
+            Direction::Forward => page.order_by(dsl::name.asc()).then_order_by(dsl::id.asc()),
+            Direction::Reverse => page
                 .order_by(dsl::name.desc())
-                .then_order_by(dsl::id.desc());
-        }
+                .then_order_by(dsl::id.desc()),
+        };
 
         let mut conn = self.pool.get_diesel_conn().await?;
-        let rows = query.get_results::<Digest>(&mut conn).await?;
-        if is_first {
-            Ok(rows)
-        } else {
-            Ok(rows.into_iter().rev().collect())
-        }
+        let rows = page.limit(limit).get_results::<Digest>(&mut conn).await?;
+        let has_beyond_cursor = cursor.is_some()
+            && !beyond
+                .limit(1)
+                .get_results::<Digest>(&mut conn)
+                .await?
+                .is_empty();
+        Ok(Page::from_rows(
+            request,
+            rows,
+            has_beyond_cursor,
+            |digest| (Cursor::from_key(&(&digest.name, digest.id)), digest),
+        ))
     }
 
     /// Updates the model with the given name.
@@ -564,6 +742,78 @@ impl Database {
         })?;
         Ok(model.id)
     }
+
+    /// Stores how the model with `model_id` was trained, replacing the
+    /// existing metadata, if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a database operation fails.
+    pub async fn set_model_training_metadata(
+        &self,
+        model_id: i32,
+        metadata: &TrainingMetadata,
+    ) -> Result<(), Error> {
+        use super::schema::model_training::dsl;
+        use diesel_async::RunQueryDsl;
+
+        let row = TrainingMetadataRow::new(model_id, metadata);
+        let mut conn = self.pool.get_diesel_conn().await?;
+        diesel::insert_into(dsl::model_training)
+            .values(&row)
+            .on_conflict(dsl::model_id)
+            .do_update()
+            .set(&row)
+            .execute(&mut conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Returns how the model with `model_id` was trained, or `None` if it is
+    /// not recorded.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a database operation fails.
+    pub async fn model_training_metadata(
+        &self,
+        model_id: i32,
+    ) -> Result<Option<TrainingMetadata>, Error> {
+        use super::schema::model_training::dsl;
+        use diesel::{ExpressionMethods, OptionalExtension, QueryDsl};
+        use diesel_async::RunQueryDsl;
+
+        let mut conn = self.pool.get_diesel_conn().await?;
+        let row = dsl::model_training
+            .filter(dsl::model_id.eq(model_id))
+            .get_result::<TrainingMetadataRow>(&mut conn)
+            .await
+            .optional()?;
+        Ok(row.map(|row| <(i32, TrainingMetadata)>::from(row).1))
+    }
+
+    /// Returns the IDs and the training metadata of the models trained with
+    /// data from `sensor`, in the order of the IDs.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a database operation fails.
+    pub async fn models_trained_on(
+        &self,
+        sensor: &str,
+    ) -> Result<Vec<(i32, TrainingMetadata)>, Error> {
+        use super::schema::model_training::dsl;
+        use diesel::{PgArrayExpressionMethods, QueryDsl};
+        use diesel_async::RunQueryDsl;
+
+        let mut conn = self.pool.get_diesel_conn().await?;
+        let rows = dsl::model_training
+            .filter(dsl::data_sources.contains(vec![Some(sensor)]))
+            .order_by(dsl::model_id)
+            .load::<TrainingMetadataRow>(&mut conn)
+            .await?;
+        Ok(rows.into_iter().map(Into::into).collect())
+    }
 }
 
 #[cfg(test)]
@@ -620,6 +870,29 @@ mod tests {
         assert_eq!(deserialized, model.header().unwrap());
     }
 
+    #[test]
+    fn stale_training_metadata() {
+        use chrono::{Duration, NaiveDate};
+
+        let day = |d| {
+            NaiveDate::from_ymd_opt(2024, 3, d)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+        };
+        let metadata = super::TrainingMetadata {
+            trained_at: day(11),
+            data_start: day(1),
+            data_end: day(10),
+            data_sources: vec!["sensor1".to_string()],
+            hyperparameters: serde_json::json!({"eps": 0.5}),
+            feature_schema: serde_json::json!([]),
+        };
+        assert!(!metadata.is_stale_for("sensor1", day(12), Duration::days(7)));
+        assert!(metadata.is_stale_for("sensor1", day(20), Duration::days(7)));
+        assert!(metadata.is_stale_for("sensor2", day(12), Duration::days(7)));
+    }
+
     #[test]
     fn serialized_model() {
         use bincode::Options;
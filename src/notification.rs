@@ -0,0 +1,348 @@
+//! Notifications of changes made to tables.
+
+use std::{
+    any::Any,
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use tokio::sync::broadcast;
+
+use crate::{
+    metrics::{Counters, Operation},
+    tables,
+};
+
+/// The number of changes a subscriber can fall behind before it starts to
+/// miss them.
+const CAPACITY: usize = 1024;
+
+/// A table whose changes can be subscribed to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TableKind {
+    AccessTokens,
+    Accounts,
+    AccountAudit,
+    AccountPolicy,
+    AgentStatus,
+    AllowNetworks,
+    ApiKeys,
+    ApiKeyIndex,
+    BatchInfo,
+    BatchScores,
+    BlockNetworks,
+    Categories,
+    CsvColumnExtras,
+    Customers,
+    DataSources,
+    EventAddresses,
+    EventDispositions,
+    EventSensorStats,
+    EventTagAssignments,
+    Events,
+    Filters,
+    ModelIndicators,
+    ModelIndicatorVersions,
+    Networks,
+    NetworkHits,
+    Nodes,
+    NodeGroups,
+    Outliers,
+    Qualifiers,
+    SamplingPolicies,
+    Scores,
+    Statuses,
+    SyslogMappings,
+    Templates,
+    Tidbs,
+    TorExitNodes,
+    TorExitNodeSnapshots,
+    TrafficFilterRules,
+    TriagePolicies,
+    TriageResponses,
+    TriageResponsePolicies,
+    TrustedDnsServers,
+    TrustedDomains,
+    TrustedUserAgents,
+}
+
+impl TableKind {
+    pub(crate) const ALL: [Self; 44] = [
+        Self::AccessTokens,
+        Self::Accounts,
+        Self::AccountAudit,
+        Self::AccountPolicy,
+        Self::AgentStatus,
+        Self::AllowNetworks,
+        Self::ApiKeys,
+        Self::ApiKeyIndex,
+        Self::BatchInfo,
+        Self::BatchScores,
+        Self::BlockNetworks,
+        Self::Categories,
+        Self::CsvColumnExtras,
+        Self::Customers,
+        Self::DataSources,
+        Self::EventAddresses,
+        Self::EventDispositions,
+        Self::EventSensorStats,
+        Self::EventTagAssignments,
+        Self::Events,
+        Self::Filters,
+        Self::ModelIndicators,
+        Self::ModelIndicatorVersions,
+        Self::Networks,
+        Self::NetworkHits,
+        Self::Nodes,
+        Self::NodeGroups,
+        Self::Outliers,
+        Self::Qualifiers,
+        Self::SamplingPolicies,
+        Self::Scores,
+        Self::Statuses,
+        Self::SyslogMappings,
+        Self::Templates,
+        Self::Tidbs,
+        Self::TorExitNodes,
+        Self::TorExitNodeSnapshots,
+        Self::TrafficFilterRules,
+        Self::TriagePolicies,
+        Self::TriageResponses,
+        Self::TriageResponsePolicies,
+        Self::TrustedDnsServers,
+        Self::TrustedDomains,
+        Self::TrustedUserAgents,
+    ];
+
+    /// Returns the name of the column family that stores the table.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::AccessTokens => tables::ACCESS_TOKENS,
+            Self::Accounts => tables::ACCOUNTS,
+            Self::AccountAudit => tables::ACCOUNT_AUDIT,
+            Self::AccountPolicy => tables::ACCOUNT_POLICY,
+            Self::AgentStatus => tables::AGENT_STATUS,
+            Self::AllowNetworks => tables::ALLOW_NETWORKS,
+            Self::ApiKeys => tables::API_KEYS,
+            Self::ApiKeyIndex => tables::API_KEY_INDEX,
+            Self::BatchInfo => tables::BATCH_INFO,
+            Self::BatchScores => tables::BATCH_SCORES,
+            Self::BlockNetworks => tables::BLOCK_NETWORKS,
+            Self::Categories => tables::CATEGORY,
+            Self::CsvColumnExtras => tables::CSV_COLUMN_EXTRAS,
+            Self::Customers => tables::CUSTOMERS,
+            Self::DataSources => tables::DATA_SOURCES,
+            Self::EventAddresses => tables::EVENT_ADDRESSES,
+            Self::EventDispositions => tables::EVENT_DISPOSITIONS,
+            Self::EventSensorStats => tables::EVENT_SENSOR_STATS,
+            Self::EventTagAssignments => tables::EVENT_TAG_ASSIGNMENTS,
+            Self::Events => rocksdb::DEFAULT_COLUMN_FAMILY_NAME,
+            Self::Filters => tables::FILTERS,
+            Self::ModelIndicators => tables::MODEL_INDICATORS,
+            Self::ModelIndicatorVersions => tables::MODEL_INDICATOR_VERSIONS,
+            Self::Networks => tables::NETWORKS,
+            Self::NetworkHits => tables::NETWORK_HITS,
+            Self::Nodes => tables::NODES,
+            Self::NodeGroups => tables::NODE_GROUPS,
+            Self::Outliers => tables::OUTLIERS,
+            Self::Qualifiers => tables::QUALIFIERS,
+            Self::SamplingPolicies => tables::SAMPLING_POLICY,
+            Self::Scores => tables::SCORES,
+            Self::Statuses => tables::STATUSES,
+            Self::SyslogMappings => tables::SYSLOG_MAPPINGS,
+            Self::Templates => tables::TEMPLATES,
+            Self::Tidbs => tables::TIDB,
+            Self::TorExitNodes => tables::TOR_EXIT_NODES,
+            Self::TorExitNodeSnapshots => tables::TOR_EXIT_NODE_SNAPSHOTS,
+            Self::TrafficFilterRules => tables::TRAFFIC_FILTER_RULES,
+            Self::TriagePolicies => tables::TRIAGE_POLICY,
+            Self::TriageResponses => tables::TRIAGE_RESPONSE,
+            Self::TriageResponsePolicies => tables::TRIAGE_RESPONSE_POLICIES,
+            Self::TrustedDnsServers => tables::TRUSTED_DNS_SERVERS,
+            Self::TrustedDomains => tables::TRUSTED_DOMAINS,
+            Self::TrustedUserAgents => tables::TRUSTED_USER_AGENTS,
+        }
+    }
+}
+
+/// The kind of a change made to an entry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChangeKind {
+    /// A new entry was added.
+    Insert,
+    /// An entry was modified, or stored regardless of whether it existed.
+    Update,
+    /// An entry was removed.
+    Remove,
+}
+
+/// A change made to an entry in a table.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Change {
+    pub kind: ChangeKind,
+
+    /// The key of the affected entry in the database.
+    pub key: Vec<u8>,
+}
+
+/// Broadcasts changes to the subscribers of each table.
+pub(crate) struct Notifier {
+    channels: HashMap<&'static str, Channel>,
+}
+
+impl Notifier {
+    pub(crate) fn new() -> Self {
+        let channels = TableKind::ALL
+            .iter()
+            .map(|kind| {
+                let channel = Channel {
+                    sender: broadcast::channel(CAPACITY).0,
+                    counters: Counters::default(),
+                    cache: Mutex::new(None),
+                };
+                (kind.name(), channel)
+            })
+            .collect();
+        Self { channels }
+    }
+
+    /// Returns the channel for the table stored in the column family `name`.
+    pub(crate) fn channel(&self, name: &str) -> Option<&Channel> {
+        self.channels.get(name)
+    }
+
+    /// Invalidates the values cached for every table, after the tables have
+    /// been replaced at once.
+    pub(crate) fn invalidate_all(&self) {
+        for channel in self.channels.values() {
+            channel.counters.invalidate();
+        }
+    }
+
+    pub(crate) fn subscribe(&self, kind: TableKind) -> broadcast::Receiver<Change> {
+        self.channel(kind.name())
+            .expect("every table has a channel")
+            .sender
+            .subscribe()
+    }
+}
+
+/// The channel through which the changes and operations made to a table are
+/// reported.
+pub(crate) struct Channel {
+    sender: broadcast::Sender<Change>,
+    pub(crate) counters: Counters,
+    /// A value derived from the table, with the generation of the table it
+    /// was derived from.
+    cache: Mutex<Option<(u64, Arc<dyn Any + Send + Sync>)>>,
+}
+
+impl Channel {
+    /// Returns the value cached for the table, or the one `build` returns if
+    /// the table has changed since the value was cached.
+    pub(crate) fn cached<T: Any + Send + Sync>(
+        &self,
+        build: impl FnOnce() -> Result<T>,
+    ) -> Result<Arc<T>> {
+        let generation = self.counters.generation();
+        let mut cache = self.cache.lock().expect("not poisoned");
+        if let Some((cached_generation, value)) = cache.as_ref() {
+            if *cached_generation == generation {
+                if let Ok(value) = Arc::clone(value).downcast::<T>() {
+                    return Ok(value);
+                }
+            }
+        }
+        let value = Arc::new(build()?);
+        *cache = Some((generation, value.clone()));
+        Ok(value)
+    }
+}
+
+/// Sends a change to the subscribers, if any.
+pub(crate) fn notify(channel: Option<&Channel>, kind: ChangeKind, key: &[u8]) {
+    let Some(channel) = channel else {
+        return;
+    };
+    channel.counters.record(match kind {
+        ChangeKind::Insert | ChangeKind::Update => Operation::Write,
+        ChangeKind::Remove => Operation::Remove,
+    });
+    if channel.sender.receiver_count() == 0 {
+        return;
+    }
+    // Sending fails only if all subscribers have been dropped in the meantime.
+    let _ = channel.sender.send(Change {
+        kind,
+        key: key.to_vec(),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Change, ChangeKind, Store, TableKind};
+    use tokio::sync::broadcast::error::TryRecvError;
+
+    #[test]
+    fn subscribe() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+
+        let mut categories = store.subscribe(TableKind::Categories);
+        let mut qualifiers = store.subscribe(TableKind::Qualifiers);
+
+        let mut table = store.category_map();
+        let id = table.insert("new category").unwrap();
+        assert_eq!(
+            categories.try_recv().unwrap(),
+            Change {
+                kind: ChangeKind::Insert,
+                key: b"new category".to_vec(),
+            }
+        );
+
+        table.update(id, "new category", "renamed").unwrap();
+        assert_eq!(
+            categories.try_recv().unwrap(),
+            Change {
+                kind: ChangeKind::Remove,
+                key: b"new category".to_vec(),
+            }
+        );
+        assert_eq!(
+            categories.try_recv().unwrap(),
+            Change {
+                kind: ChangeKind::Update,
+                key: b"renamed".to_vec(),
+            }
+        );
+
+        table.remove(id).unwrap();
+        assert_eq!(
+            categories.try_recv().unwrap(),
+            Change {
+                kind: ChangeKind::Remove,
+                key: b"renamed".to_vec(),
+            }
+        );
+        assert_eq!(categories.try_recv(), Err(TryRecvError::Empty));
+        assert_eq!(qualifiers.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[test]
+    fn subscribe_map() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+
+        let mut rx = store.subscribe(TableKind::TrustedDnsServers);
+        let map = store.trusted_dns_server_map();
+        map.insert(b"key", b"value").unwrap();
+        map.delete(b"key").unwrap();
+        assert_eq!(rx.try_recv().unwrap().kind, ChangeKind::Insert);
+        assert_eq!(rx.try_recv().unwrap().kind, ChangeKind::Remove);
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+    }
+}
@@ -1,8 +1,13 @@
-use crate::types::{Outlier, Source, Timestamp};
+use crate::{
+    types::{Outlier, Source, Timestamp},
+    Cursor, Direction, Page, PageRequest,
+};
 
 use super::{tokio_postgres::types::ToSql, Database, Error, Type};
+use chrono::{NaiveDateTime, Utc};
 use futures::future::join_all;
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use tracing::error;
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -13,6 +18,16 @@ pub struct UpdateOutlierRequest {
     pub size: i64,
 }
 
+/// The key by which outliers are ordered.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum OutlierOrder {
+    /// The number of events.
+    Size,
+    /// The timestamp of the latest event.
+    LatestEvent,
+}
+
 #[allow(clippy::module_name_repetitions)]
 #[derive(Debug, Deserialize, Serialize)]
 pub struct OutlierInfo {
@@ -22,6 +37,22 @@ pub struct OutlierInfo {
     pub source: String,
 }
 
+/// A cluster promoted from an outlier by `Database::promote_outlier`. The
+/// qualifier and the status take their default values.
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::cluster)]
+struct PromotedClusterInput {
+    category_id: i32,
+    cluster_id: String,
+    detector_id: i32,
+    event_ids: Vec<Option<i64>>,
+    event_sources: Vec<Option<Source>>,
+    last_modification_time: Option<NaiveDateTime>,
+    model_id: i32,
+    signature: String,
+    size: i64,
+}
+
 #[derive(Queryable)]
 struct OutlierDbSchema {
     id: i32,
@@ -97,68 +128,224 @@ impl Database {
         Ok(())
     }
 
-    /// Returns a list of outliers between `after` and `before`.
+    /// Promotes the outlier with `outlier_id` to a new cluster `cluster_id`
+    /// of `detector_id` with `category_id` and `signature`. Only `events`,
+    /// given as pairs of timestamps and sources, are promoted if specified;
+    /// otherwise all the events of the outlier are. The promoted events are
+    /// removed from the outlier, and the outlier is deleted if no events
+    /// remain.
+    ///
+    /// Returns the ID of the new cluster.
     ///
     /// # Errors
     ///
-    /// Returns an error if an underlying database operation fails.
+    /// Returns an error if the outlier does not exist, there are no events
+    /// to promote, not all of `events` are in the outlier, a cluster with
+    /// `cluster_id` already exists in the model, or a database operation
+    /// fails.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn promote_outlier(
+        &self,
+        outlier_id: i32,
+        events: Option<&[crate::types::Id]>,
+        cluster_id: &str,
+        detector_id: i32,
+        category_id: i32,
+        signature: &str,
+    ) -> Result<i32, Error> {
+        use crate::schema::{cluster::dsl as c_d, outlier::dsl as o_d};
+        use diesel::{ExpressionMethods, OptionalExtension, QueryDsl};
+        use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection, RunQueryDsl};
+
+        let mut conn = self.pool.get_diesel_conn().await?;
+        conn.transaction::<_, Error, _>(|conn| {
+            async move {
+                let outlier = o_d::outlier
+                    .select((
+                        o_d::id,
+                        o_d::raw_event,
+                        o_d::event_ids,
+                        o_d::event_sources,
+                        o_d::size,
+                        o_d::model_id,
+                    ))
+                    .filter(o_d::id.eq(outlier_id))
+                    .for_update()
+                    .first::<OutlierDbSchema>(conn)
+                    .await
+                    .optional()?
+                    .ok_or_else(|| Error::InvalidInput(format!("no such outlier: {outlier_id}")))?;
+
+                let all = outlier.event_ids.into_iter().zip(outlier.event_sources);
+                let (promoted, kept): (Vec<_>, Vec<_>) = if let Some(events) = events {
+                    let promoting: HashSet<_> = events
+                        .iter()
+                        .map(|(ts, src)| (Some(*ts), Some(src.clone())))
+                        .collect();
+                    let (promoted, kept): (Vec<_>, Vec<_>) =
+                        all.partition(|event| promoting.contains(event));
+                    if promoted.iter().collect::<HashSet<_>>().len() != promoting.len() {
+                        return Err(Error::InvalidInput(format!(
+                            "not all of the events are in outlier {outlier_id}"
+                        )));
+                    }
+                    (promoted, kept)
+                } else {
+                    (all.collect(), Vec::new())
+                };
+                if promoted.is_empty() {
+                    return Err(Error::InvalidInput("no events to promote".into()));
+                }
+                let promoted_size = i64::try_from(promoted.len()).unwrap_or(i64::MAX);
+
+                if kept.is_empty() {
+                    diesel::delete(o_d::outlier.filter(o_d::id.eq(outlier.id)))
+                        .execute(conn)
+                        .await?;
+                } else {
+                    let (kept_ids, kept_sources): (Vec<_>, Vec<_>) = kept.into_iter().unzip();
+                    diesel::update(o_d::outlier.filter(o_d::id.eq(outlier.id)))
+                        .set((
+                            o_d::event_ids.eq(kept_ids),
+                            o_d::event_sources.eq(kept_sources),
+                            o_d::size.eq((outlier.size - promoted_size).max(0)),
+                        ))
+                        .execute(conn)
+                        .await?;
+                }
+
+                let (event_ids, event_sources) = promoted.into_iter().unzip();
+                let cluster = PromotedClusterInput {
+                    category_id,
+                    cluster_id: cluster_id.to_string(),
+                    detector_id,
+                    event_ids,
+                    event_sources,
+                    last_modification_time: Some(Utc::now().naive_utc()),
+                    model_id: outlier.model_id,
+                    signature: signature.to_string(),
+                    size: promoted_size,
+                };
+                diesel::insert_into(c_d::cluster)
+                    .values(&cluster)
+                    .returning(c_d::id)
+                    .get_result(conn)
+                    .await
+                    .map_err(|e| crate::cluster::insert_error(cluster_id, e))
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    /// Returns a page of outliers in descending order of size.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cursor of `request` is invalid or an
+    /// underlying database operation fails.
     pub async fn load_outliers(
         &self,
         model: i32,
-        after: &Option<(i32, i64)>,
-        before: &Option<(i32, i64)>,
-        is_first: bool,
-        limit: usize,
-    ) -> Result<Vec<Outlier>, Error> {
-        use super::schema::outlier::dsl;
-        use diesel::{BoolExpressionMethods, ExpressionMethods, QueryDsl};
+        request: &PageRequest,
+    ) -> Result<Page<Outlier>, Error> {
+        self.load_outliers_ordered(model, OutlierOrder::Size, request)
+            .await
+    }
+
+    /// Returns a page of outliers in descending order of `order`, i.e., of
+    /// their sizes or the timestamps of their latest events, and then of
+    /// their IDs.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cursor of `request` is invalid or an
+    /// underlying database operation fails.
+    pub async fn load_outliers_ordered(
+        &self,
+        model: i32,
+        order: OutlierOrder,
+        request: &PageRequest,
+    ) -> Result<Page<Outlier>, Error> {
+        use super::schema::outlier::{self, dsl};
+        use diesel::{
+            pg::Pg, sql_types::BigInt, BoolExpressionMethods, BoxableExpression, ExpressionMethods,
+            QueryDsl,
+        };
         use diesel_async::RunQueryDsl;
 
-        let limit = i64::try_from(limit).map_err(|_| Error::InvalidInput("limit".into()))? + 1;
-        let mut query = dsl::outlier
-            .select((
-                dsl::id,
-                dsl::raw_event,
-                dsl::event_ids,
-                dsl::event_sources,
-                dsl::size,
-                dsl::model_id,
-            ))
-            .filter(dsl::model_id.eq(&model))
-            .limit(limit)
-            .into_boxed();
+        let key = || -> Box<dyn BoxableExpression<outlier::table, Pg, SqlType = BigInt>> {
+            match order {
+                OutlierOrder::Size => Box::new(dsl::size),
+                OutlierOrder::LatestEvent => Box::new(dsl::latest_event_id),
+            }
+        };
+        let cursor = request
+            .cursor
+            .as_ref()
+            .map(Cursor::key::<(i64, i32)>)
+            .transpose()?;
+        let limit =
+            i64::try_from(request.limit).map_err(|_| Error::InvalidInput("limit".into()))? + 1;
+        let query = || {
+            dsl::outlier
+                .select((
+                    (
+                        dsl::id,
+                        dsl::raw_event,
+                        dsl::event_ids,
+                        dsl::event_sources,
+                        dsl::size,
+                        dsl::model_id,
+                    ),
+                    dsl::latest_event_id,
+                ))
+                .filter(dsl::model_id.eq(model))
+                .into_boxed()
+        };
 
-        if let Some(after) = after {
-            query = query.filter(
-                dsl::size
-                    .eq(after.1)
-                    .and(dsl::id.lt(after.0))
-                    .or(dsl::size.lt(after.1)),
-            );
-        }
-        if let Some(before) = before {
-            query = query.filter(
-                dsl::size
-                    .eq(before.1)
-                    .and(dsl::id.gt(before.0))
-                    .or(dsl::size.gt(before.1)),
-            );
-        }
-        if is_first {
-            query = query
-                .order_by(dsl::size.desc())
-                .then_order_by(dsl::id.desc());
-        } else {
-            query = query.order_by(dsl::size.asc()).then_order_by(dsl::id.asc());
+        let mut page = query();
+        let mut beyond = query();
+        if let Some((value, id)) = cursor {
+            match request.direction {
+                Direction::Forward => {
+                    page = page.filter(key().lt(value).or(key().eq(value).and(dsl::id.lt(id))));
+                    beyond = beyond.filter(key().gt(value).or(key().eq(value).and(dsl::id.ge(id))));
+                }
+                Direction::Reverse => {
+                    page = page.filter(key().gt(value).or(key().eq(value).and(dsl::id.gt(id))));
+                    beyond = beyond.filter(key().lt(value).or(key().eq(value).and(dsl::id.le(id))));
+                }
+            }
         }
+        page = match request.direction {
+            Direction::Forward => page.order_by(key().desc()).then_order_by(dsl::id.desc()),
+            Direction::Reverse => page.order_by(key().asc()).then_order_by(dsl::id.asc()),
+        };
 
         let mut conn = self.pool.get_diesel_conn().await?;
-        let rows = query.get_results::<OutlierDbSchema>(&mut conn).await?;
-        if is_first {
-            Ok(rows.into_iter().map(Into::into).collect())
-        } else {
-            Ok(rows.into_iter().rev().map(Into::into).collect())
-        }
+        let rows = page
+            .limit(limit)
+            .get_results::<(OutlierDbSchema, i64)>(&mut conn)
+            .await?;
+        let has_beyond_cursor = cursor.is_some()
+            && !beyond
+                .limit(1)
+                .get_results::<(OutlierDbSchema, i64)>(&mut conn)
+                .await?
+                .is_empty();
+        Ok(Page::from_rows(
+            request,
+            rows,
+            has_beyond_cursor,
+            |(outlier, latest_event_id)| {
+                let value = match order {
+                    OutlierOrder::Size => outlier.size,
+                    OutlierOrder::LatestEvent => latest_event_id,
+                };
+                (Cursor::from_key(&(value, outlier.id)), outlier.into())
+            },
+        ))
     }
 
     /// Returns all outliers for the given model.
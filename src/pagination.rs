@@ -0,0 +1,231 @@
+//! Keyset pagination over indexed tables and the PostgreSQL-backed listings,
+//! such as `Database::load_clusters`.
+
+use std::{fmt, str::FromStr};
+
+use anyhow::{Context, Result};
+use bincode::Options;
+use data_encoding::BASE64URL_NOPAD;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{types::FromKeyValue, Direction, Indexable, IndexedTable, Iterable};
+
+/// A position in a table.
+///
+/// A cursor is opaque to clients. It can be passed around as a string, which
+/// is its [`Display`](fmt::Display) form, and parsed back with [`FromStr`].
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct Cursor(#[serde(with = "serde_bytes")] Vec<u8>);
+
+impl Cursor {
+    fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Returns a cursor pointing to the row with `key` in a listing ordered
+    /// by it.
+    pub(crate) fn from_key<K: Serialize>(key: &K) -> Self {
+        Self(
+            bincode::DefaultOptions::new()
+                .serialize(key)
+                .expect("serializable"),
+        )
+    }
+
+    /// Returns the key of the row the cursor points to in a listing ordered
+    /// by it.
+    pub(crate) fn key<K: DeserializeOwned>(&self) -> Result<K, crate::Error> {
+        bincode::DefaultOptions::new()
+            .deserialize(&self.0)
+            .map_err(|_| crate::Error::InvalidInput("invalid cursor".into()))
+    }
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&BASE64URL_NOPAD.encode(&self.0))
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        BASE64URL_NOPAD
+            .decode(s.as_bytes())
+            .map(Self)
+            .context("invalid cursor")
+    }
+}
+
+/// A request for a page of records.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PageRequest {
+    /// `Forward` to get the records after `cursor`, or `Reverse` to get the
+    /// records before `cursor`.
+    pub direction: Direction,
+
+    /// The position to start from, exclusive. If `None`, the page starts from
+    /// the first record in `direction`.
+    pub cursor: Option<Cursor>,
+
+    /// The maximum number of records in the page.
+    pub limit: usize,
+}
+
+/// A page of records, in the order of the table regardless of the direction
+/// of the request.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Page<R> {
+    /// The records in the page, each with the cursor pointing to it.
+    pub records: Vec<(Cursor, R)>,
+
+    /// `true` if there are records before the page.
+    pub has_previous: bool,
+
+    /// `true` if there are records after the page.
+    pub has_next: bool,
+}
+
+impl<R> Page<R> {
+    /// Returns the page of the records in `rows`, which were read in the
+    /// direction of `request` starting after its cursor, and may include one
+    /// more row than the limit to tell whether there are more.
+    /// `has_beyond_cursor` tells whether there is any row at or beyond the
+    /// cursor in the opposite direction, and `record` turns a row into a
+    /// record with the cursor pointing to it.
+    pub(crate) fn from_rows<T>(
+        request: &PageRequest,
+        rows: Vec<T>,
+        has_beyond_cursor: bool,
+        record: impl Fn(T) -> (Cursor, R),
+    ) -> Self {
+        let has_more = rows.len() > request.limit;
+        let mut records: Vec<_> = rows.into_iter().take(request.limit).map(record).collect();
+        let (has_previous, has_next) = match request.direction {
+            Direction::Forward => (has_beyond_cursor, has_more),
+            Direction::Reverse => {
+                records.reverse();
+                (has_more, has_beyond_cursor)
+            }
+        };
+        Self {
+            records,
+            has_previous,
+            has_next,
+        }
+    }
+}
+
+impl<'d, R: Indexable + FromKeyValue> IndexedTable<'d, R> {
+    /// Returns a page of records as requested.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any record cannot be read.
+    pub fn page(&self, request: &PageRequest) -> Result<Page<R>> {
+        let from = request.cursor.as_ref().map(Cursor::as_bytes);
+        let mut records = Vec::new();
+        let mut has_more = false;
+        for record in self.iter(request.direction, from) {
+            let record = record?;
+            let key = record.indexed_key().into_owned();
+            if Some(key.as_slice()) == from {
+                continue;
+            }
+            if records.len() == request.limit {
+                has_more = true;
+                break;
+            }
+            records.push((Cursor(key), record));
+        }
+
+        // Any record at or beyond the cursor in the opposite direction lies
+        // outside the page.
+        let has_beyond_cursor =
+            |direction| from.is_some() && self.iter(direction, from).next().is_some();
+        let (has_previous, has_next) = match request.direction {
+            Direction::Forward => (has_beyond_cursor(Direction::Reverse), has_more),
+            Direction::Reverse => {
+                records.reverse();
+                (has_more, has_beyond_cursor(Direction::Forward))
+            }
+        };
+        Ok(Page {
+            records,
+            has_previous,
+            has_next,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cursor, PageRequest};
+    use crate::{Direction, Store};
+
+    #[test]
+    fn page() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+        let table = store.category_map();
+        for name in ["a", "b", "c", "d"] {
+            table.insert(name).unwrap();
+        }
+        // The table also has "Irrelevant Alert" and "Non-Specified Alert".
+        let names = |page: &super::Page<crate::Category>| {
+            page.records
+                .iter()
+                .map(|(_, category)| category.name.clone())
+                .collect::<Vec<_>>()
+        };
+
+        let first = table
+            .page(&PageRequest {
+                direction: Direction::Forward,
+                cursor: None,
+                limit: 2,
+            })
+            .unwrap();
+        assert_eq!(names(&first), ["Irrelevant Alert", "Non-Specified Alert"]);
+        assert!(!first.has_previous);
+        assert!(first.has_next);
+
+        let cursor = first.records[1].0.to_string().parse::<Cursor>().unwrap();
+        let second = table
+            .page(&PageRequest {
+                direction: Direction::Forward,
+                cursor: Some(cursor),
+                limit: 4,
+            })
+            .unwrap();
+        assert_eq!(names(&second), ["a", "b", "c", "d"]);
+        assert!(second.has_previous);
+        assert!(!second.has_next);
+
+        let previous = table
+            .page(&PageRequest {
+                direction: Direction::Reverse,
+                cursor: Some(second.records[1].0.clone()),
+                limit: 2,
+            })
+            .unwrap();
+        assert_eq!(names(&previous), ["Non-Specified Alert", "a"]);
+        assert!(previous.has_previous);
+        assert!(previous.has_next);
+
+        let last = table
+            .page(&PageRequest {
+                direction: Direction::Reverse,
+                cursor: None,
+                limit: 10,
+            })
+            .unwrap();
+        assert_eq!(names(&last).len(), 6);
+        assert!(!last.has_previous);
+        assert!(!last.has_next);
+
+        assert!("not a cursor!".parse::<Cursor>().is_err());
+    }
+}
@@ -0,0 +1,304 @@
+//! Storage of pretrained models.
+//!
+//! A model no larger than the chunk size is stored in a single file,
+//! `{name}-{timestamp}.tmm`. A larger one is split into chunks of the chunk
+//! size under the directory `{name}-{timestamp}.chunks`, and the manifest,
+//! `{name}-{timestamp}.manifest`, lists the lengths of the chunks. The
+//! manifest is written last, so a model being written is not visible to
+//! readers.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Read, Take, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{types::Timestamp, DEFAULT_PRETRAINED_EXTENSION};
+
+/// The default maximum size of a chunk, 256 MiB.
+pub const DEFAULT_CHUNK_SIZE: u64 = 256 * 1024 * 1024;
+
+pub(crate) const MANIFEST_EXTENSION: &str = "manifest";
+const CHUNKS_EXTENSION: &str = "chunks";
+const PARTIAL_EXTENSION: &str = "part";
+
+#[derive(Deserialize, Serialize)]
+struct Manifest {
+    /// The total size of the model in bytes.
+    size: u64,
+    /// The length of each chunk, in order.
+    chunks: Vec<u64>,
+}
+
+fn chunk_path(chunks_dir: &Path, index: usize) -> PathBuf {
+    chunks_dir.join(format!("{index:08}"))
+}
+
+/// A reader of a pretrained model, returned by
+/// [`Store::pretrained_model_reader`](crate::Store::pretrained_model_reader).
+pub struct PretrainedModelReader {
+    size: u64,
+    inner: Inner,
+}
+
+enum Inner {
+    Single(File),
+    Chunked {
+        dir: PathBuf,
+        chunks: Vec<u64>,
+        next: usize,
+        current: Option<Take<File>>,
+    },
+}
+
+impl PretrainedModelReader {
+    /// Opens the model stored at `path`, either a model file or a manifest.
+    pub(crate) fn open(path: &Path) -> Result<Self> {
+        if path.extension().and_then(std::ffi::OsStr::to_str) != Some(MANIFEST_EXTENSION) {
+            let file = File::open(path)?;
+            let size = file.metadata()?.len();
+            return Ok(Self {
+                size,
+                inner: Inner::Single(file),
+            });
+        }
+
+        let manifest: Manifest = serde_json::from_reader(File::open(path)?)?;
+        if manifest.chunks.iter().sum::<u64>() != manifest.size {
+            return Err(anyhow!("invalid manifest: {}", path.display()));
+        }
+        Ok(Self {
+            size: manifest.size,
+            inner: Inner::Chunked {
+                dir: path.with_extension(CHUNKS_EXTENSION),
+                chunks: manifest.chunks,
+                next: 0,
+                current: None,
+            },
+        })
+    }
+
+    /// Returns the size of the model in bytes.
+    #[must_use]
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+impl Read for PretrainedModelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let (dir, chunks, next, current) = match &mut self.inner {
+            Inner::Single(file) => return file.read(buf),
+            Inner::Chunked {
+                dir,
+                chunks,
+                next,
+                current,
+            } => (dir, chunks, next, current),
+        };
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            if let Some(chunk) = current {
+                let len = chunk.read(buf)?;
+                if len > 0 {
+                    return Ok(len);
+                }
+                if chunk.limit() > 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        format!("chunk {} is truncated", *next - 1),
+                    ));
+                }
+            }
+            let Some(&len) = chunks.get(*next) else {
+                *current = None;
+                return Ok(0);
+            };
+            *current = Some(File::open(chunk_path(dir, *next))?.take(len));
+            *next += 1;
+        }
+    }
+}
+
+/// A writer of a pretrained model, returned by
+/// [`Store::pretrained_model_writer`](crate::Store::pretrained_model_writer).
+///
+/// The model becomes visible only after [`PretrainedModelWriter::finish`]
+/// succeeds. If the writer is dropped before that, what has been written is
+/// removed.
+pub struct PretrainedModelWriter {
+    dir: PathBuf,
+    stem: String,
+    chunk_size: u64,
+    chunks: Vec<u64>,
+    current: Option<(BufWriter<File>, u64)>,
+    finished: bool,
+}
+
+impl PretrainedModelWriter {
+    /// Creates a writer of the model `name` at `timestamp` under `dir`.
+    pub(crate) fn new(
+        dir: &Path,
+        name: &str,
+        timestamp: Timestamp,
+        chunk_size: u64,
+    ) -> Result<Self> {
+        let stem = format!("{name}-{timestamp}");
+        for ext in [DEFAULT_PRETRAINED_EXTENSION, MANIFEST_EXTENSION] {
+            if dir.join(format!("{stem}.{ext}")).exists() {
+                return Err(anyhow!("model {name:?} at {timestamp} already exists"));
+            }
+        }
+        // Leftovers of an interrupted write.
+        let chunks_dir = dir.join(format!("{stem}.{CHUNKS_EXTENSION}"));
+        if chunks_dir.exists() {
+            fs::remove_dir_all(&chunks_dir)?;
+        }
+        fs::create_dir(&chunks_dir)?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            stem,
+            chunk_size: chunk_size.max(1),
+            chunks: Vec::new(),
+            current: None,
+            finished: false,
+        })
+    }
+
+    fn path(&self, ext: &str) -> PathBuf {
+        self.dir.join(format!("{}.{ext}", self.stem))
+    }
+
+    fn close_chunk(&mut self) -> io::Result<()> {
+        if let Some((mut file, len)) = self.current.take() {
+            file.flush()?;
+            self.chunks.push(len);
+        }
+        Ok(())
+    }
+
+    /// Completes writing the model, and returns its size in bytes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the model cannot be written to the file system.
+    pub fn finish(mut self) -> Result<u64> {
+        self.close_chunk()?;
+        let size = self.chunks.iter().sum();
+        let chunks_dir = self.path(CHUNKS_EXTENSION);
+        if self.chunks.len() <= 1 {
+            let model = self.path(DEFAULT_PRETRAINED_EXTENSION);
+            if self.chunks.is_empty() {
+                File::create(&model)?;
+            } else {
+                fs::rename(chunk_path(&chunks_dir, 0), &model)?;
+            }
+            fs::remove_dir(&chunks_dir)?;
+        } else {
+            let partial = self.path(PARTIAL_EXTENSION);
+            let manifest = Manifest {
+                size,
+                chunks: std::mem::take(&mut self.chunks),
+            };
+            let mut file = BufWriter::new(File::create(&partial)?);
+            serde_json::to_writer(&mut file, &manifest)?;
+            file.flush()?;
+            fs::rename(&partial, self.path(MANIFEST_EXTENSION))?;
+        }
+        self.finished = true;
+        Ok(size)
+    }
+}
+
+impl Write for PretrainedModelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.current.is_none() {
+            let path = chunk_path(&self.path(CHUNKS_EXTENSION), self.chunks.len());
+            self.current = Some((BufWriter::new(File::create(path)?), 0));
+        }
+        let (file, len) = self.current.as_mut().expect("opened above");
+        let room = usize::try_from(self.chunk_size - *len).unwrap_or(usize::MAX);
+        let written = file.write(&buf[..buf.len().min(room)])?;
+        *len += written as u64;
+        if *len == self.chunk_size {
+            self.close_chunk()?;
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some((file, _)) = &mut self.current {
+            file.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for PretrainedModelWriter {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.current = None;
+            let _ = fs::remove_dir_all(self.path(CHUNKS_EXTENSION));
+            let _ = fs::remove_file(self.path(PARTIAL_EXTENSION));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use crate::Store;
+
+    fn write_and_read(store: &Store, name: &str, data: &[u8]) -> Vec<u8> {
+        let mut writer = store.pretrained_model_writer(name, 1).unwrap();
+        writer.write_all(data).unwrap();
+        assert_eq!(writer.finish().unwrap(), data.len() as u64);
+
+        let mut reader = store.pretrained_model_reader(name).unwrap();
+        assert_eq!(reader.size(), data.len() as u64);
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn chunked() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let mut store = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+        store.set_pretrained_chunk_size(4);
+        let pretrained = db_dir.path().join("pretrained");
+
+        assert_eq!(write_and_read(&store, "small", b"abcd"), b"abcd");
+        assert!(pretrained.join("small-1.tmm").is_file());
+        assert!(!pretrained.join("small-1.chunks").exists());
+
+        let data: Vec<u8> = (0..=9).collect();
+        assert_eq!(write_and_read(&store, "large", &data), data);
+        assert!(pretrained.join("large-1.manifest").is_file());
+        assert_eq!(
+            std::fs::read_dir(pretrained.join("large-1.chunks"))
+                .unwrap()
+                .count(),
+            3
+        );
+        assert_eq!(store.pretrained_model("large").unwrap().0, data);
+
+        assert!(store.pretrained_model_writer("large", 1).is_err());
+        {
+            let mut writer = store.pretrained_model_writer("aborted", 1).unwrap();
+            writer.write_all(&data).unwrap();
+        }
+        assert!(store.pretrained_model_reader("aborted").is_err());
+        assert!(!pretrained.join("aborted-1.chunks").exists());
+    }
+}
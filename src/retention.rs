@@ -0,0 +1,295 @@
+//! Retention of events and batch scores.
+
+use crate::{EventCategory, Store};
+use anyhow::Result;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+/// The time-to-live of events per category.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct EventRetentionPolicy {
+    /// The time-to-live of events in each category.
+    pub ttl: HashMap<EventCategory, Duration>,
+
+    /// The time-to-live of events in the categories not in `ttl`. If `None`,
+    /// those events are kept forever.
+    pub default_ttl: Option<Duration>,
+}
+
+impl EventRetentionPolicy {
+    /// Returns the time-to-live of events in `category`, or `None` if they
+    /// never expire.
+    #[must_use]
+    pub fn ttl(&self, category: EventCategory) -> Option<Duration> {
+        self.ttl.get(&category).copied().or(self.default_ttl)
+    }
+
+    /// Returns the shortest time-to-live in the policy, or `None` if no events
+    /// expire.
+    #[must_use]
+    pub fn min_ttl(&self) -> Option<Duration> {
+        self.ttl.values().copied().chain(self.default_ttl).min()
+    }
+}
+
+/// Deletes the events that have expired according to the retention policy
+/// stored in the database, and returns the number of deleted events.
+///
+/// This is meant to be called periodically. It does nothing if no retention
+/// policy is stored.
+///
+/// # Errors
+///
+/// Returns an error if the policy cannot be read or a database operation
+/// fails.
+pub async fn purge_expired_events(store: &Arc<RwLock<Store>>) -> Result<usize> {
+    crate::run_blocking(store, |store| {
+        let Some(policy) = store.event_retention_policy()? else {
+            return Ok(0);
+        };
+        match store.events().delete_expired(&policy, Utc::now()) {
+            Ok(deleted) => {
+                info!("deleted {deleted} expired events");
+                Ok(deleted)
+            }
+            Err(e) => {
+                warn!("failed to delete expired events: {:?}", e);
+                Err(e)
+            }
+        }
+    })
+    .await
+}
+
+/// The number of the latest batches whose scores are kept per model.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct ScoreRetentionPolicy {
+    /// The number of batches to keep for each model.
+    pub rounds: HashMap<i32, usize>,
+
+    /// The number of batches to keep for the models not in `rounds`. If
+    /// `None`, the scores of those models are kept forever.
+    pub default_rounds: Option<usize>,
+}
+
+impl ScoreRetentionPolicy {
+    /// Returns the number of batches to keep for `model`, or `None` if all
+    /// of them are kept.
+    #[must_use]
+    pub fn rounds(&self, model: i32) -> Option<usize> {
+        self.rounds.get(&model).copied().or(self.default_rounds)
+    }
+}
+
+/// Deletes the scores of old batches according to the score retention
+/// policy stored in the database, and returns the number of batches deleted.
+///
+/// This is meant to be called periodically, e.g., after a new batch. It does
+/// nothing if no retention policy is stored.
+///
+/// # Errors
+///
+/// Returns an error if the policy cannot be read or a database operation
+/// fails.
+pub async fn purge_old_batch_scores(store: &Arc<RwLock<Store>>) -> Result<usize> {
+    crate::run_blocking(store, |store| {
+        let Some(policy) = store.score_retention_policy()? else {
+            return Ok(0);
+        };
+        let table = store.batch_scores_map();
+        let mut deleted = 0;
+        for model in table.models()? {
+            let Some(rounds) = policy.rounds(model) else {
+                continue;
+            };
+            match table.prune(model, rounds) {
+                Ok(n) => deleted += n,
+                Err(e) => {
+                    warn!("failed to delete old scores of model {model}: {:?}", e);
+                    return Err(e);
+                }
+            }
+        }
+        info!("deleted scores of {deleted} old batches");
+        Ok(deleted)
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EventRetentionPolicy;
+    use crate::{event::DnsEventFields, EventCategory, EventKind, EventMessage, Store};
+    use chrono::{DateTime, Duration, TimeZone, Utc};
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        sync::Arc,
+    };
+    use tokio::sync::RwLock;
+
+    fn example_message(time: DateTime<Utc>) -> EventMessage {
+        let fields = DnsEventFields {
+            source: "collector1".to_string(),
+            session_end_time: time,
+            src_addr: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+            src_port: 10000,
+            dst_addr: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)),
+            dst_port: 53,
+            proto: 17,
+            query: "foo.com".to_string(),
+            answer: vec!["1.1.1.1".to_string()],
+            trans_id: 1,
+            rtt: 1,
+            qclass: 0,
+            qtype: 0,
+            rcode: 0,
+            aa_flag: false,
+            tc_flag: false,
+            rd_flag: false,
+            ra_flag: false,
+            ttl: vec![1; 5],
+            confidence: 0.8,
+        };
+        EventMessage {
+            time,
+            kind: EventKind::DnsCovertChannel,
+            fields: bincode::serialize(&fields).expect("serializable"),
+        }
+    }
+
+    #[test]
+    fn ttl() {
+        let policy = EventRetentionPolicy {
+            ttl: [(EventCategory::Impact, std::time::Duration::from_secs(60))]
+                .into_iter()
+                .collect(),
+            default_ttl: Some(std::time::Duration::from_secs(3600)),
+        };
+        assert_eq!(
+            policy.ttl(EventCategory::Impact),
+            Some(std::time::Duration::from_secs(60))
+        );
+        assert_eq!(
+            policy.ttl(EventCategory::Discovery),
+            Some(std::time::Duration::from_secs(3600))
+        );
+        assert_eq!(policy.min_ttl(), Some(std::time::Duration::from_secs(60)));
+        assert_eq!(EventRetentionPolicy::default().min_ttl(), None);
+    }
+
+    #[test]
+    fn delete_expired() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+        let db = store.events();
+
+        let now = Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap();
+        db.put(&example_message(now - Duration::days(10))).unwrap();
+        db.put(&example_message(now - Duration::days(3))).unwrap();
+        db.put(&example_message(now - Duration::hours(1))).unwrap();
+
+        // DNS covert channels are in `CommandAndControl`.
+        let policy = EventRetentionPolicy {
+            ttl: [(
+                EventCategory::Impact,
+                std::time::Duration::from_secs(60 * 60 * 24),
+            )]
+            .into_iter()
+            .collect(),
+            default_ttl: None,
+        };
+        assert_eq!(db.delete_expired(&policy, now).unwrap(), 0);
+
+        let policy = EventRetentionPolicy {
+            ttl: [(
+                EventCategory::CommandAndControl,
+                std::time::Duration::from_secs(60 * 60 * 24 * 7),
+            )]
+            .into_iter()
+            .collect(),
+            default_ttl: None,
+        };
+        assert_eq!(db.delete_expired(&policy, now).unwrap(), 1);
+        assert_eq!(db.iter_forward().count(), 2);
+
+        let policy = EventRetentionPolicy {
+            ttl: [(EventCategory::Impact, std::time::Duration::from_secs(60))]
+                .into_iter()
+                .collect(),
+            default_ttl: Some(std::time::Duration::from_secs(60 * 60 * 24)),
+        };
+        assert_eq!(db.delete_expired(&policy, now).unwrap(), 1);
+        assert_eq!(db.iter_forward().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn purge_expired_events() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(RwLock::new(
+            Store::new(db_dir.path(), backup_dir.path()).unwrap(),
+        ));
+
+        {
+            let store = store.read().await;
+            store
+                .events()
+                .put(&example_message(Utc::now() - Duration::days(2)))
+                .unwrap();
+        }
+        assert_eq!(super::purge_expired_events(&store).await.unwrap(), 0);
+
+        {
+            let store = store.read().await;
+            assert!(store.event_retention_policy().unwrap().is_none());
+            let policy = EventRetentionPolicy {
+                ttl: std::collections::HashMap::new(),
+                default_ttl: Some(std::time::Duration::from_secs(60 * 60 * 24)),
+            };
+            store.set_event_retention_policy(&policy).unwrap();
+            assert_eq!(store.event_retention_policy().unwrap(), Some(policy));
+        }
+        assert_eq!(super::purge_expired_events(&store).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn purge_old_batch_scores() {
+        use crate::BatchScores;
+
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(RwLock::new(
+            Store::new(db_dir.path(), backup_dir.path()).unwrap(),
+        ));
+
+        {
+            let store = store.read().await;
+            let table = store.batch_scores_map();
+            for (model, batch) in [(1, 10), (1, 20), (1, 30), (2, 10), (2, 20)] {
+                let scores = [(1, 0.5)].into_iter().collect();
+                table.put(&BatchScores::new(model, batch, scores)).unwrap();
+            }
+        }
+        assert_eq!(super::purge_old_batch_scores(&store).await.unwrap(), 0);
+
+        {
+            let store = store.read().await;
+            let policy = super::ScoreRetentionPolicy {
+                rounds: [(2, 1)].into_iter().collect(),
+                default_rounds: Some(2),
+            };
+            store.set_score_retention_policy(&policy).unwrap();
+            assert_eq!(store.score_retention_policy().unwrap(), Some(policy));
+        }
+        assert_eq!(super::purge_old_batch_scores(&store).await.unwrap(), 2);
+
+        let store = store.read().await;
+        let table = store.batch_scores_map();
+        assert_eq!(table.get_all_for(1).unwrap().len(), 2);
+        assert_eq!(table.get_all_for(2).unwrap()[0].batch, 20);
+    }
+}
@@ -1,6 +1,15 @@
 // @generated automatically by Diesel CLI.
 
+pub mod sql_types {
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "tsvector", schema = "pg_catalog"))]
+    pub struct Tsvector;
+}
+
 diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::Tsvector;
+
     cluster (id) {
         id -> Int4,
         category_id -> Int4,
@@ -16,6 +25,18 @@ diesel::table! {
         size -> Int8,
         status_id -> Int4,
         event_sources -> Array<Nullable<Text>>,
+        merged_into -> Nullable<Int4>,
+        signature_tsv -> Nullable<Tsvector>,
+    }
+}
+
+diesel::table! {
+    cluster_history (id) {
+        id -> Int4,
+        cluster_id -> Int4,
+        batch_ts -> Timestamp,
+        size -> Int8,
+        score -> Nullable<Float8>,
     }
 }
 
@@ -137,6 +158,16 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    histogram (id) {
+        id -> Int4,
+        description_id -> Int4,
+        lower -> Float8,
+        upper -> Float8,
+        counts -> Array<Nullable<Int8>>,
+    }
+}
+
 diesel::table! {
     model (id) {
         id -> Int4,
@@ -150,6 +181,18 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    model_training (model_id) {
+        model_id -> Int4,
+        trained_at -> Timestamp,
+        data_start -> Timestamp,
+        data_end -> Timestamp,
+        data_sources -> Array<Nullable<Text>>,
+        hyperparameters -> Jsonb,
+        feature_schema -> Jsonb,
+    }
+}
+
 diesel::table! {
     outlier (id) {
         id -> Int4,
@@ -158,6 +201,7 @@ diesel::table! {
         event_ids -> Array<Nullable<Int8>>,
         size -> Int8,
         event_sources -> Array<Nullable<Text>>,
+        latest_event_id -> Int8,
     }
 }
 
@@ -238,6 +282,7 @@ diesel::table! {
 
 diesel::allow_tables_to_appear_in_same_query!(
     cluster,
+    cluster_history,
     column_description,
     csv_column_extra,
     csv_column_list,
@@ -250,7 +295,9 @@ diesel::allow_tables_to_appear_in_same_query!(
     description_int,
     description_ipaddr,
     description_text,
+    histogram,
     model,
+    model_training,
     outlier,
     time_series,
     top_n_binary,
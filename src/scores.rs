@@ -18,6 +18,169 @@ impl Scores {
     pub fn into_inner(self) -> crate::types::ModelScores {
         self.inner
     }
+
+    /// Returns the `p`-th percentile of the scores, interpolating linearly
+    /// between the closest ranks, or `None` if there are no scores or `p` is
+    /// not in `[0, 100]`.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_sign_loss)] // `rank` is in `[0, len - 1]`
+    pub fn percentile(&self, p: f64) -> Option<f64> {
+        if !(0.0..=100.0).contains(&p) {
+            return None;
+        }
+        let sorted = self.sorted();
+        let last = sorted.len().checked_sub(1)?;
+        let rank = p / 100.0 * last as f64;
+        let (lower, upper) = (rank.floor() as usize, rank.ceil() as usize);
+        Some(sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - rank.floor()))
+    }
+
+    /// Returns the percentage of the scores less than or equal to `score`,
+    /// or `None` if there are no scores.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)] // approximation is ok
+    pub fn percentile_rank(&self, score: f64) -> Option<f64> {
+        let sorted = self.sorted();
+        if sorted.is_empty() {
+            return None;
+        }
+        let below = sorted.partition_point(|s| *s <= score);
+        Some(below as f64 * 100.0 / sorted.len() as f64)
+    }
+
+    /// Divides the range of the scores into `n` buckets of the same width,
+    /// and returns the number of scores in each bucket. Returns an empty
+    /// list if there are no scores or `n` is zero.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_sign_loss)] // the index is non-negative
+    pub fn histogram(&self, n: usize) -> Vec<ScoreBucket> {
+        let sorted = self.sorted();
+        let (Some(&min), Some(&max)) = (sorted.first(), sorted.last()) else {
+            return Vec::new();
+        };
+        if n == 0 {
+            return Vec::new();
+        }
+        let width = (max - min) / n as f64;
+        let mut buckets: Vec<_> = (0..n)
+            .map(|i| ScoreBucket {
+                lower: min + width * i as f64,
+                upper: if i + 1 == n {
+                    max
+                } else {
+                    min + width * (i + 1) as f64
+                },
+                count: 0,
+            })
+            .collect();
+        for score in sorted {
+            let i = if width > 0.0 {
+                (((score - min) / width) as usize).min(n - 1)
+            } else {
+                0
+            };
+            buckets[i].count += 1;
+        }
+        buckets
+    }
+
+    /// Returns how the scores have changed since `previous`, or `None` if
+    /// either has no scores.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)] // approximation is ok
+    pub fn drift(&self, previous: &Scores) -> Option<ScoreDrift> {
+        let (current, previous) = (self.sorted(), previous.sorted());
+        if current.is_empty() || previous.is_empty() {
+            return None;
+        }
+        let mean = |s: &[f64]| s.iter().sum::<f64>() / s.len() as f64;
+
+        // The Kolmogorov-Smirnov statistic: the largest difference between
+        // the empirical distribution functions.
+        let (mut i, mut j, mut ks_statistic) = (0, 0, 0.0_f64);
+        while i < current.len() && j < previous.len() {
+            let x = current[i].min(previous[j]);
+            i += current[i..].partition_point(|s| *s <= x);
+            j += previous[j..].partition_point(|s| *s <= x);
+            let diff = i as f64 / current.len() as f64 - j as f64 / previous.len() as f64;
+            ks_statistic = ks_statistic.max(diff.abs());
+        }
+
+        Some(ScoreDrift {
+            mean_change: mean(&current) - mean(&previous),
+            ks_statistic,
+        })
+    }
+
+    /// Returns the scores in ascending order, without NaNs.
+    fn sorted(&self) -> Vec<f64> {
+        let mut scores: Vec<_> = self
+            .inner
+            .values()
+            .copied()
+            .filter(|s| !s.is_nan())
+            .collect();
+        scores.sort_by(f64::total_cmp);
+        scores
+    }
+}
+
+/// The scores of a model in a batch.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct BatchScores {
+    pub model: i32,
+    /// The ID of the batch, i.e., its timestamp.
+    pub batch: i64,
+    pub scores: crate::types::ModelScores,
+}
+
+impl BatchScores {
+    #[must_use]
+    pub fn new(model: i32, batch: i64, scores: crate::types::ModelScores) -> Self {
+        Self {
+            model,
+            batch,
+            scores,
+        }
+    }
+}
+
+impl UniqueKey for BatchScores {
+    fn unique_key(&self) -> Cow<[u8]> {
+        let mut key = self.model.to_be_bytes().to_vec();
+        key.extend(self.batch.to_be_bytes());
+        Cow::Owned(key)
+    }
+}
+
+impl Value for BatchScores {
+    fn value(&self) -> Cow<[u8]> {
+        use bincode::Options;
+        let Ok(value) = bincode::DefaultOptions::new().serialize(&self.scores) else {
+            unreachable!("serialization into memory should never fail")
+        };
+        Cow::Owned(value)
+    }
+}
+
+/// The number of scores in `[lower, upper)`, or `[lower, upper]` for the last
+/// bucket of a histogram.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScoreBucket {
+    pub lower: f64,
+    pub upper: f64,
+    pub count: usize,
+}
+
+/// The change of the scores of a model between two batches.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScoreDrift {
+    /// The change of the mean score.
+    pub mean_change: f64,
+    /// The Kolmogorov-Smirnov statistic, from 0 (the same distribution) to 1.
+    pub ks_statistic: f64,
 }
 
 impl UniqueKey for Scores {
@@ -39,3 +202,63 @@ impl Value for Scores {
         Cow::Owned(value)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{ScoreBucket, Scores};
+
+    fn scores(values: &[f64]) -> Scores {
+        Scores::new(1, values.iter().copied().enumerate().collect())
+    }
+
+    #[test]
+    fn percentile() {
+        let s = scores(&[4.0, 1.0, 3.0, 2.0, 5.0]);
+        assert_eq!(s.percentile(0.0), Some(1.0));
+        assert_eq!(s.percentile(50.0), Some(3.0));
+        assert_eq!(s.percentile(100.0), Some(5.0));
+        assert_eq!(s.percentile(12.5), Some(1.5));
+        assert_eq!(s.percentile(101.0), None);
+        assert_eq!(scores(&[]).percentile(50.0), None);
+
+        assert_eq!(s.percentile_rank(3.0), Some(60.0));
+        assert_eq!(s.percentile_rank(0.0), Some(0.0));
+        assert_eq!(s.percentile_rank(9.0), Some(100.0));
+    }
+
+    #[test]
+    fn histogram() {
+        let s = scores(&[0.0, 0.1, 0.5, 0.9, 1.0]);
+        assert_eq!(
+            s.histogram(2),
+            vec![
+                ScoreBucket {
+                    lower: 0.0,
+                    upper: 0.5,
+                    count: 2
+                },
+                ScoreBucket {
+                    lower: 0.5,
+                    upper: 1.0,
+                    count: 3
+                },
+            ]
+        );
+        assert_eq!(scores(&[1.0, 1.0]).histogram(3)[0].count, 2);
+        assert!(s.histogram(0).is_empty());
+    }
+
+    #[test]
+    fn drift() {
+        let previous = scores(&[1.0, 2.0, 3.0, 4.0]);
+        let same = previous.drift(&previous).unwrap();
+        assert_eq!(same.mean_change, 0.0);
+        assert_eq!(same.ks_statistic, 0.0);
+
+        let shifted = scores(&[11.0, 12.0, 13.0, 14.0]).drift(&previous).unwrap();
+        assert_eq!(shifted.mean_change, 10.0);
+        assert_eq!(shifted.ks_statistic, 1.0);
+
+        assert!(scores(&[]).drift(&previous).is_none());
+    }
+}
@@ -1,7 +1,11 @@
 mod access_token;
+mod account_audit;
 mod accounts;
+mod agent_status;
 mod allow_network;
+mod api_key;
 mod batch_info;
+mod batch_scores;
 mod block_network;
 mod category;
 mod csv_column_extra;
@@ -10,122 +14,200 @@ mod data_source;
 mod filter;
 mod model_indicator;
 mod network;
+mod network_hits;
 mod node;
+mod node_group;
 mod qualifier;
 mod sampling_policy;
 mod scores;
 mod status;
+mod syslog_mapping;
 mod template;
 mod tidb;
 mod tor_exit_node;
 mod triage_policy;
 mod triage_response;
+mod trusted_domain;
 
 use crate::{
+    account::{LockoutPolicy, PasswordPolicy},
+    backup::RetentionPolicy,
     batch_info::BatchInfo,
     category::Category,
-    collections::IndexedSet,
-    scores::Scores,
+    collections::{IdUsage, IndexedSet},
+    metrics::{record, Metrics, Operation},
+    notification::{notify, Change, ChangeKind, Channel, Notifier, TableKind},
+    retention::{EventRetentionPolicy, ScoreRetentionPolicy},
+    scores::{BatchScores, Scores},
     types::{Account, FromKeyValue, Qualifier, Status},
-    Direction, Indexable,
+    Direction, Indexable, IndexedMapUpdate, EXCLUSIVE,
 };
 
 use super::{event, Indexed, IndexedMap, Map};
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use serde::{Deserialize, Serialize};
 use std::{
     borrow::Cow,
+    collections::{HashMap, HashSet},
     path::{Path, PathBuf},
 };
+use tokio::sync::broadcast;
 
-pub use self::access_token::AccessToken;
+pub use self::access_token::{AccessToken, SessionDevice, SessionPolicy};
+pub use self::account_audit::{AccountAction, AccountAuditEntry, AccountSnapshot};
+pub use self::agent_status::{AgentStatus, ResourceUsage};
 pub use self::allow_network::{AllowNetwork, Update as AllowNetworkUpdate};
+pub use self::api_key::ApiKey;
 pub use self::block_network::{BlockNetwork, Update as BlockNetworkUpdate};
 pub use self::csv_column_extra::CsvColumnExtra;
-pub use self::customer::{Customer, Network as CustomerNetwork, Update as CustomerUpdate};
+pub use self::customer::{
+    Customer, CustomerMatch, Network as CustomerNetwork, Update as CustomerUpdate,
+};
 pub use self::data_source::{DataSource, DataType, Update as DataSourceUpdate};
 pub use self::filter::Filter;
-pub use self::model_indicator::ModelIndicator;
+pub use self::model_indicator::{ModelIndicator, ModelIndicatorDiff, ModelIndicatorVersion};
 pub use self::network::{Network, Update as NetworkUpdate};
-pub use self::node::{Node, Setting as NodeSetting, Update as NodeUpdate};
+pub use self::network_hits::{NetworkHits, NetworkListKind};
+pub use self::node::{
+    Node, Setting as NodeSetting, SettingDiff as NodeSettingDiff,
+    TemplateResult as NodeTemplateResult, Update as NodeUpdate,
+};
+pub use self::node_group::{Kind as NodeGroupKind, NodeGroup, Update as NodeGroupUpdate};
 pub use self::sampling_policy::{
     Interval as SamplingInterval, Kind as SamplingKind, Period as SamplingPeriod, SamplingPolicy,
-    Update as SamplingPolicyUpdate,
+    Schedule as SamplingSchedule, Update as SamplingPolicyUpdate, Window as SamplingWindow,
 };
+pub use self::syslog_mapping::{SyslogMapping, SyslogTarget, DEFAULT_SYSLOG_FACILITY};
 pub use self::template::{
     Structured, StructuredClusteringAlgorithm, Template, Unstructured,
     UnstructuredClusteringAlgorithm,
 };
-pub use self::tidb::{Kind as TidbKind, Rule as TidbRule, Tidb};
-pub use self::tor_exit_node::TorExitNode;
+pub use self::tidb::{
+    Kind as TidbKind, Lookup as TidbLookup, Match as TidbMatch, Matcher as TidbMatcher,
+    Rule as TidbRule, StixImport, Tidb,
+};
+pub use self::tor_exit_node::{TorExitNode, TorExitNodeSnapshot};
+pub(crate) use self::triage_policy::compiled_regex;
 pub use self::triage_policy::{
-    AttrCmpKind, Confidence, PacketAttr, Response, ResponseKind, Ti, TiCmpKind, TriagePolicy,
-    Update as TriagePolicyUpdate, ValueKind,
+    AttrCmpKind, Confidence, NameConflict as TriagePolicyNameConflict, PacketAttr, Response,
+    ResponseKind, Ti, TiCmpKind, TriagePolicy, Update as TriagePolicyUpdate, ValueKind,
+    Version as TriagePolicyVersion, JSON_FORMAT_VERSION as TRIAGE_POLICY_JSON_FORMAT_VERSION,
 };
-pub use self::triage_response::{TriageResponse, Update as TriageResponseUpdate};
+pub use self::triage_response::{TriageResponse, TriggeredPolicy, Update as TriageResponseUpdate};
+pub use self::trusted_domain::TrustedDomain;
 
 // Key-value map names in `Database`.
 pub(super) const ACCESS_TOKENS: &str = "access_tokens";
 pub(super) const ACCOUNTS: &str = "accounts";
+pub(super) const ACCOUNT_AUDIT: &str = "account audit";
 pub(super) const ACCOUNT_POLICY: &str = "account policy";
+pub(super) const AGENT_STATUS: &str = "agent status";
 pub(super) const ALLOW_NETWORKS: &str = "allow networks";
+pub(super) const API_KEYS: &str = "api keys";
+pub(super) const API_KEY_INDEX: &str = "api key index";
 pub(super) const BATCH_INFO: &str = "batch_info";
+pub(super) const BATCH_SCORES: &str = "batch scores";
 pub(super) const BLOCK_NETWORKS: &str = "block networks";
 pub(super) const CATEGORY: &str = "category";
 pub(super) const CSV_COLUMN_EXTRAS: &str = "csv column extras";
 pub(super) const CUSTOMERS: &str = "customers";
 pub(super) const DATA_SOURCES: &str = "data sources";
+pub(super) const EVENT_ADDRESSES: &str = "event addresses";
+pub(super) const EVENT_DISPOSITIONS: &str = "event dispositions";
+pub(super) const EVENT_SENSOR_STATS: &str = "event sensor stats";
+pub(super) const EVENT_TAG_ASSIGNMENTS: &str = "event tag assignments";
 pub(super) const FILTERS: &str = "filters";
 pub(super) const MODEL_INDICATORS: &str = "model indicators";
-const META: &str = "meta";
+pub(super) const MODEL_INDICATOR_VERSIONS: &str = "model indicator versions";
+pub(super) const META: &str = "meta";
 pub(super) const NETWORKS: &str = "networks";
+pub(super) const NETWORK_HITS: &str = "network hits";
 pub(super) const NODES: &str = "nodes";
+pub(super) const NODE_GROUPS: &str = "node groups";
 pub(super) const OUTLIERS: &str = "outliers";
 pub(super) const QUALIFIERS: &str = "qualifiers";
 pub(super) const SAMPLING_POLICY: &str = "sampling policy";
 pub(super) const SCORES: &str = "scores";
 pub(super) const STATUSES: &str = "statuses";
+pub(super) const SYSLOG_MAPPINGS: &str = "syslog mappings";
 pub(super) const TEMPLATES: &str = "templates";
 pub(super) const TIDB: &str = "TI database";
 pub(super) const TOR_EXIT_NODES: &str = "Tor exit nodes";
+pub(super) const TOR_EXIT_NODE_SNAPSHOTS: &str = "Tor exit node snapshots";
 pub(super) const TRAFFIC_FILTER_RULES: &str = "traffic filter rules";
 pub(super) const TRIAGE_POLICY: &str = "triage policy";
 pub(super) const TRIAGE_RESPONSE: &str = "triage response";
+pub(super) const TRIAGE_RESPONSE_POLICIES: &str = "triage response policies";
 pub(super) const TRUSTED_DNS_SERVERS: &str = "trusted DNS servers";
+pub(super) const TRUSTED_DOMAINS: &str = "trusted domains";
 pub(super) const TRUSTED_USER_AGENTS: &str = "trusted user agents";
 
-const MAP_NAMES: [&str; 28] = [
+const MAP_NAMES: [&str; 44] = [
     ACCESS_TOKENS,
     ACCOUNTS,
+    ACCOUNT_AUDIT,
     ACCOUNT_POLICY,
+    AGENT_STATUS,
     ALLOW_NETWORKS,
+    API_KEYS,
+    API_KEY_INDEX,
     BATCH_INFO,
+    BATCH_SCORES,
     BLOCK_NETWORKS,
     CATEGORY,
     CSV_COLUMN_EXTRAS,
     CUSTOMERS,
     DATA_SOURCES,
+    EVENT_ADDRESSES,
+    EVENT_DISPOSITIONS,
+    EVENT_SENSOR_STATS,
+    EVENT_TAG_ASSIGNMENTS,
     FILTERS,
     MODEL_INDICATORS,
+    MODEL_INDICATOR_VERSIONS,
     META,
     NETWORKS,
+    NETWORK_HITS,
     NODES,
+    NODE_GROUPS,
     OUTLIERS,
     QUALIFIERS,
     SAMPLING_POLICY,
     SCORES,
     STATUSES,
+    SYSLOG_MAPPINGS,
     TEMPLATES,
     TIDB,
     TOR_EXIT_NODES,
+    TOR_EXIT_NODE_SNAPSHOTS,
     TRAFFIC_FILTER_RULES,
     TRIAGE_POLICY,
     TRIAGE_RESPONSE,
+    TRIAGE_RESPONSE_POLICIES,
     TRUSTED_DNS_SERVERS,
+    TRUSTED_DOMAINS,
     TRUSTED_USER_AGENTS,
 ];
 
+// Key-value maps included in an archive, including the default one where
+// events are stored.
+const ARCHIVED_MAP_NAMES: [&str; MAP_NAMES.len() + 1] = {
+    let mut names = [rocksdb::DEFAULT_COLUMN_FAMILY_NAME; MAP_NAMES.len() + 1];
+    let mut i = 0;
+    while i < MAP_NAMES.len() {
+        names[i + 1] = MAP_NAMES[i];
+        i += 1;
+    }
+    names
+};
+
 // Keys for the meta map.
+const BACKUP_RETENTION: &[u8] = b"backup retention";
+const EVENT_RETENTION: &[u8] = b"event retention";
+const SCORE_RETENTION: &[u8] = b"score retention";
+const LOCKOUT_POLICY: &[u8] = b"lockout policy";
+const PASSWORD_POLICY: &[u8] = b"password policy";
+const SESSION_POLICY: &[u8] = b"session policy";
 pub(super) const EVENT_TAGS: &[u8] = b"event tags";
 pub(super) const NETWORK_TAGS: &[u8] = b"network tags";
 pub(super) const WORKFLOW_TAGS: &[u8] = b"workflow tags";
@@ -135,6 +217,7 @@ pub(crate) struct StateDb {
     inner: Option<rocksdb::OptimisticTransactionDB>,
     backup: PathBuf,
     db: PathBuf,
+    notifier: Notifier,
 }
 
 impl StateDb {
@@ -143,73 +226,210 @@ impl StateDb {
             inner: Some(db),
             backup,
             db: path.to_owned(),
+            notifier: Notifier::new(),
         })
     }
 
+    pub(super) fn subscribe(&self, kind: TableKind) -> broadcast::Receiver<Change> {
+        self.notifier.subscribe(kind)
+    }
+
+    pub(super) fn metrics(&self) -> Result<Metrics> {
+        let inner = self.inner.as_ref().expect("database must be open");
+        crate::metrics::collect(inner, &self.notifier)
+    }
+
     #[must_use]
     pub(crate) fn access_tokens(&self) -> Table<AccessToken> {
         let inner = self.inner.as_ref().expect("database must be open");
-        Table::<AccessToken>::open(inner).expect("{ACCESS_TOKENS} table must be present")
+        Table::<AccessToken>::open(inner)
+            .expect("{ACCESS_TOKENS} table must be present")
+            .with_notifier(self.notifier.channel(ACCESS_TOKENS))
     }
 
     #[must_use]
     pub(crate) fn accounts(&self) -> Table<Account> {
         let inner = self.inner.as_ref().expect("database must be open");
-        Table::<Account>::open(inner).expect("{ACCOUNTS} table must be present")
+        Table::<Account>::open(inner)
+            .expect("{ACCOUNTS} table must be present")
+            .with_notifier(self.notifier.channel(ACCOUNTS))
+    }
+
+    #[must_use]
+    pub(crate) fn account_audit(&self) -> Table<AccountAuditEntry> {
+        let inner = self.inner.as_ref().expect("database must be open");
+        Table::<AccountAuditEntry>::open(inner)
+            .expect("{ACCOUNT_AUDIT} table must be present")
+            .with_notifier(self.notifier.channel(ACCOUNT_AUDIT))
+    }
+
+    #[must_use]
+    pub(crate) fn agent_status(&self) -> Table<AgentStatus> {
+        let inner = self.inner.as_ref().expect("database must be open");
+        Table::<AgentStatus>::open(inner)
+            .expect("{AGENT_STATUS} table must be present")
+            .with_notifier(self.notifier.channel(AGENT_STATUS))
     }
 
     #[must_use]
     pub(crate) fn batch_info(&self) -> Table<BatchInfo> {
         let inner = self.inner.as_ref().expect("database must be open");
-        Table::<BatchInfo>::open(inner).expect("{BATCH_INFO} table must be present")
+        Table::<BatchInfo>::open(inner)
+            .expect("{BATCH_INFO} table must be present")
+            .with_notifier(self.notifier.channel(BATCH_INFO))
+    }
+
+    #[must_use]
+    pub(crate) fn api_keys(&self) -> Table<ApiKey> {
+        let inner = self.inner.as_ref().expect("database must be open");
+        Table::<ApiKey>::open(inner)
+            .expect("{API_KEYS} table must be present")
+            .with_notifier(self.notifier.channel(API_KEYS))
+    }
+
+    #[must_use]
+    pub(crate) fn batch_scores(&self) -> Table<BatchScores> {
+        let inner = self.inner.as_ref().expect("database must be open");
+        Table::<BatchScores>::open(inner)
+            .expect("{BATCH_SCORES} table must be present")
+            .with_notifier(self.notifier.channel(BATCH_SCORES))
+    }
+
+    /// Stores the batch info, scores, and batch scores of a model in a single
+    /// transaction.
+    pub(crate) fn put_model_results(
+        &self,
+        batch_info: &[BatchInfo],
+        scores: &Scores,
+        batch_scores: &[BatchScores],
+    ) -> Result<()> {
+        let inner = self.inner.as_ref().expect("database must be open");
+        let (info_table, scores_table, batch_scores_table) =
+            (self.batch_info(), self.scores(), self.batch_scores());
+        let (info_keys, scores_key, batch_scores_keys) = loop {
+            let txn = inner.transaction();
+            let info_keys = batch_info
+                .iter()
+                .map(|record| info_table.put_in_transaction(&txn, record))
+                .collect::<Result<Vec<_>>>()?;
+            let scores_key = scores_table.put_in_transaction(&txn, scores)?;
+            let batch_scores_keys = batch_scores
+                .iter()
+                .map(|record| batch_scores_table.put_in_transaction(&txn, record))
+                .collect::<Result<Vec<_>>>()?;
+            match txn.commit() {
+                Ok(()) => break (info_keys, scores_key, batch_scores_keys),
+                Err(e) => {
+                    if !e.as_ref().starts_with("Resource busy:") {
+                        return Err(e).context("failed to store model results");
+                    }
+                }
+            }
+        };
+        for key in &info_keys {
+            notify(info_table.map.notifier, ChangeKind::Update, key);
+        }
+        notify(scores_table.map.notifier, ChangeKind::Update, &scores_key);
+        for key in &batch_scores_keys {
+            notify(batch_scores_table.map.notifier, ChangeKind::Update, key);
+        }
+        Ok(())
     }
 
     #[must_use]
     pub(crate) fn filters(&self) -> Table<Filter> {
         let inner = self.inner.as_ref().expect("database must be open");
-        Table::<Filter>::open(inner).expect("{FILTERS} table must be present")
+        Table::<Filter>::open(inner)
+            .expect("{FILTERS} table must be present")
+            .with_notifier(self.notifier.channel(FILTERS))
     }
 
     #[must_use]
     pub(crate) fn model_indicators(&self) -> Table<ModelIndicator> {
         let inner = self.inner.as_ref().expect("database must be open");
-        Table::<ModelIndicator>::open(inner).expect("{MODEL_INDICATORS} table must be present")
+        Table::<ModelIndicator>::open(inner)
+            .expect("{MODEL_INDICATORS} table must be present")
+            .with_notifier(self.notifier.channel(MODEL_INDICATORS))
+    }
+
+    #[must_use]
+    pub(crate) fn model_indicator_versions(&self) -> Table<ModelIndicatorVersion> {
+        let inner = self.inner.as_ref().expect("database must be open");
+        Table::<ModelIndicatorVersion>::open(inner)
+            .expect("{MODEL_INDICATOR_VERSIONS} table must be present")
+            .with_notifier(self.notifier.channel(MODEL_INDICATOR_VERSIONS))
     }
 
     #[must_use]
     pub(crate) fn scores(&self) -> Table<Scores> {
         let inner = self.inner.as_ref().expect("database must be open");
-        Table::<Scores>::open(inner).expect("{SCORES} table must be present")
+        Table::<Scores>::open(inner)
+            .expect("{SCORES} table must be present")
+            .with_notifier(self.notifier.channel(SCORES))
+    }
+
+    #[must_use]
+    pub(crate) fn syslog_mappings(&self) -> Table<SyslogMapping> {
+        let inner = self.inner.as_ref().expect("database must be open");
+        Table::<SyslogMapping>::open(inner)
+            .expect("{SYSLOG_MAPPINGS} table must be present")
+            .with_notifier(self.notifier.channel(SYSLOG_MAPPINGS))
     }
 
     #[must_use]
     pub(crate) fn templates(&self) -> Table<Template> {
         let inner = self.inner.as_ref().expect("database must be open");
-        Table::<Template>::open(inner).expect("{TEMPLATES} table must be present")
+        Table::<Template>::open(inner)
+            .expect("{TEMPLATES} table must be present")
+            .with_notifier(self.notifier.channel(TEMPLATES))
     }
 
     #[must_use]
     pub(crate) fn tor_exit_nodes(&self) -> Table<TorExitNode> {
         let inner = self.inner.as_ref().expect("database must be open");
-        Table::<TorExitNode>::open(inner).expect("{TOR_EXIT_NODES} table must be present")
+        Table::<TorExitNode>::open(inner)
+            .expect("{TOR_EXIT_NODES} table must be present")
+            .with_notifier(self.notifier.channel(TOR_EXIT_NODES))
+    }
+
+    #[must_use]
+    pub(crate) fn tor_exit_node_snapshots(&self) -> Table<TorExitNodeSnapshot> {
+        let inner = self.inner.as_ref().expect("database must be open");
+        Table::<TorExitNodeSnapshot>::open(inner)
+            .expect("{TOR_EXIT_NODE_SNAPSHOTS} table must be present")
+            .with_notifier(self.notifier.channel(TOR_EXIT_NODE_SNAPSHOTS))
+    }
+
+    #[must_use]
+    pub(crate) fn trusted_domains(&self) -> Table<TrustedDomain> {
+        let inner = self.inner.as_ref().expect("database must be open");
+        Table::<TrustedDomain>::open(inner)
+            .expect("{TRUSTED_DOMAINS} table must be present")
+            .with_notifier(self.notifier.channel(TRUSTED_DOMAINS))
     }
 
     #[must_use]
     pub(crate) fn categories(&self) -> IndexedTable<Category> {
         let inner = self.inner.as_ref().expect("database must be open");
-        IndexedTable::<Category>::open(inner).expect("{CATEGORY} table must be present")
+        IndexedTable::<Category>::open(inner)
+            .expect("{CATEGORY} table must be present")
+            .with_notifier(self.notifier.channel(CATEGORY))
     }
 
     #[must_use]
     pub(crate) fn qualifiers(&self) -> IndexedTable<Qualifier> {
         let inner = self.inner.as_ref().expect("database must be open");
-        IndexedTable::<Qualifier>::open(inner).expect("{QUALIFIERS} table must be present")
+        IndexedTable::<Qualifier>::open(inner)
+            .expect("{QUALIFIERS} table must be present")
+            .with_notifier(self.notifier.channel(QUALIFIERS))
     }
 
     #[must_use]
     pub(crate) fn statuses(&self) -> IndexedTable<Status> {
         let inner = self.inner.as_ref().expect("database must be open");
-        IndexedTable::<Status>::open(inner).expect("{STATUSES} table must be present")
+        IndexedTable::<Status>::open(inner)
+            .expect("{STATUSES} table must be present")
+            .with_notifier(self.notifier.channel(STATUSES))
     }
 
     #[must_use]
@@ -217,6 +437,7 @@ impl StateDb {
         let inner = self.inner.as_ref().expect("database must be open");
         IndexedTable::<CsvColumnExtra>::open(inner)
             .expect("{CSV_COLUMN_EXTRAS} table must be present")
+            .with_notifier(self.notifier.channel(CSV_COLUMN_EXTRAS))
     }
 
     #[must_use]
@@ -224,24 +445,39 @@ impl StateDb {
         let inner = self.inner.as_ref().expect("database must be open");
         IndexedTable::<TriageResponse>::open(inner)
             .expect("{TRIAGE_RESPONSE} table must be present")
+            .with_notifier(self.notifier.channel(TRIAGE_RESPONSE))
     }
 
     #[must_use]
     pub(crate) fn networks(&self) -> IndexedTable<Network> {
         let inner = self.inner.as_ref().expect("database must be open");
-        IndexedTable::<Network>::open(inner).expect("{NETWORKS} table must be present")
+        IndexedTable::<Network>::open(inner)
+            .expect("{NETWORKS} table must be present")
+            .with_notifier(self.notifier.channel(NETWORKS))
+    }
+
+    #[must_use]
+    pub(crate) fn network_hits(&self) -> Table<NetworkHits> {
+        let inner = self.inner.as_ref().expect("database must be open");
+        Table::<NetworkHits>::open(inner)
+            .expect("{NETWORK_HITS} table must be present")
+            .with_notifier(self.notifier.channel(NETWORK_HITS))
     }
 
     #[must_use]
     pub(crate) fn allow_networks(&self) -> IndexedTable<AllowNetwork> {
         let inner = self.inner.as_ref().expect("database must be open");
-        IndexedTable::<AllowNetwork>::open(inner).expect("{ALLOW_NETWORKS} table must be present")
+        IndexedTable::<AllowNetwork>::open(inner)
+            .expect("{ALLOW_NETWORKS} table must be present")
+            .with_notifier(self.notifier.channel(ALLOW_NETWORKS))
     }
 
     #[must_use]
     pub(crate) fn block_networks(&self) -> IndexedTable<BlockNetwork> {
         let inner = self.inner.as_ref().expect("database must be open");
-        IndexedTable::<BlockNetwork>::open(inner).expect("{BLOCK_NETWORKS} table must be present")
+        IndexedTable::<BlockNetwork>::open(inner)
+            .expect("{BLOCK_NETWORKS} table must be present")
+            .with_notifier(self.notifier.channel(BLOCK_NETWORKS))
     }
 
     #[must_use]
@@ -249,47 +485,66 @@ impl StateDb {
         let inner = self.inner.as_ref().expect("database must be open");
         IndexedTable::<SamplingPolicy>::open(inner)
             .expect("{SAMPLING_POLICY} table must be present")
+            .with_notifier(self.notifier.channel(SAMPLING_POLICY))
     }
 
     #[must_use]
     pub(crate) fn customers(&self) -> IndexedTable<Customer> {
         let inner = self.inner.as_ref().expect("database must be open");
-        IndexedTable::<Customer>::open(inner).expect("{CUSTOMERS} table must be present")
+        IndexedTable::<Customer>::open(inner)
+            .expect("{CUSTOMERS} table must be present")
+            .with_notifier(self.notifier.channel(CUSTOMERS))
     }
 
     #[must_use]
     pub(crate) fn data_sources(&self) -> IndexedTable<DataSource> {
         let inner = self.inner.as_ref().expect("database must be open");
-        IndexedTable::<DataSource>::open(inner).expect("{DATA_SOURCES} table must be present")
+        IndexedTable::<DataSource>::open(inner)
+            .expect("{DATA_SOURCES} table must be present")
+            .with_notifier(self.notifier.channel(DATA_SOURCES))
     }
 
     pub(crate) fn nodes(&self) -> IndexedTable<Node> {
         let inner = self.inner.as_ref().expect("database must be open");
-        IndexedTable::<Node>::open(inner).expect("{NETWORKS} table must be present")
+        IndexedTable::<Node>::open(inner)
+            .expect("{NETWORKS} table must be present")
+            .with_notifier(self.notifier.channel(NODES))
+    }
+
+    pub(crate) fn node_groups(&self) -> IndexedTable<NodeGroup> {
+        let inner = self.inner.as_ref().expect("database must be open");
+        IndexedTable::<NodeGroup>::open(inner)
+            .expect("{NODE_GROUPS} table must be present")
+            .with_notifier(self.notifier.channel(NODE_GROUPS))
     }
 
     #[must_use]
     pub(crate) fn triage_policies(&self) -> IndexedTable<TriagePolicy> {
         let inner = self.inner.as_ref().expect("database must be open");
-        IndexedTable::<TriagePolicy>::open(inner).expect("{TRIAGE_POLICY} table must be present")
+        IndexedTable::<TriagePolicy>::open(inner)
+            .expect("{TRIAGE_POLICY} table must be present")
+            .with_notifier(self.notifier.channel(TRIAGE_POLICY))
     }
 
     #[must_use]
     pub(crate) fn tidbs(&self) -> Table<Tidb> {
         let inner = self.inner.as_ref().expect("database must be open");
-        Table::<Tidb>::open(inner).expect("{TIDB} table must be present")
+        Table::<Tidb>::open(inner)
+            .expect("{TIDB} table must be present")
+            .with_notifier(self.notifier.channel(TIDB))
     }
 
     #[must_use]
     pub fn events(&self) -> event::EventDb {
         let inner = self.inner.as_ref().expect("database must be open");
         event::EventDb::new(inner)
+            .with_notifier(self.notifier.channel(rocksdb::DEFAULT_COLUMN_FAMILY_NAME))
     }
 
     #[must_use]
     pub(super) fn map(&self, name: &str) -> Option<Map> {
         let inner = self.inner.as_ref().expect("database must be open");
-        Map::open(inner, name)
+        Map::open(inner, name).map(|map| map.with_notifier(self.notifier.channel(name)))
     }
 
     #[must_use]
@@ -298,6 +553,18 @@ impl StateDb {
         IndexedSet::new(inner, META, name).ok()
     }
 
+    pub(super) fn export(&self, path: &Path) -> Result<()> {
+        let inner = self.inner.as_ref().expect("database must be open");
+        crate::archive::export(inner, &ARCHIVED_MAP_NAMES, path)
+    }
+
+    pub(super) fn import(&self, path: &Path) -> Result<()> {
+        let inner = self.inner.as_ref().expect("database must be open");
+        crate::archive::import(inner, &ARCHIVED_MAP_NAMES, path)?;
+        self.notifier.invalidate_all();
+        Ok(())
+    }
+
     pub(super) fn create_new_backup_flush(
         &mut self,
         flush: bool,
@@ -347,6 +614,87 @@ impl StateDb {
         Ok(engine.get_backup_info())
     }
 
+    pub(super) fn backup_retention(&self) -> Result<Option<RetentionPolicy>> {
+        self.get_meta(BACKUP_RETENTION)
+    }
+
+    pub(super) fn set_backup_retention(&self, policy: &RetentionPolicy) -> Result<()> {
+        self.put_meta(BACKUP_RETENTION, policy)
+    }
+
+    pub(super) fn event_retention(&self) -> Result<Option<EventRetentionPolicy>> {
+        self.get_meta(EVENT_RETENTION)
+    }
+
+    pub(super) fn set_event_retention(&self, policy: &EventRetentionPolicy) -> Result<()> {
+        self.put_meta(EVENT_RETENTION, policy)
+    }
+
+    pub(super) fn score_retention(&self) -> Result<Option<ScoreRetentionPolicy>> {
+        self.get_meta(SCORE_RETENTION)
+    }
+
+    pub(super) fn set_score_retention(&self, policy: &ScoreRetentionPolicy) -> Result<()> {
+        self.put_meta(SCORE_RETENTION, policy)
+    }
+
+    pub(super) fn lockout_policy(&self) -> Result<Option<LockoutPolicy>> {
+        self.get_meta(LOCKOUT_POLICY)
+    }
+
+    pub(super) fn set_lockout_policy(&self, policy: &LockoutPolicy) -> Result<()> {
+        self.put_meta(LOCKOUT_POLICY, policy)
+    }
+
+    pub(super) fn session_policy(&self) -> Result<Option<SessionPolicy>> {
+        self.get_meta(SESSION_POLICY)
+    }
+
+    pub(super) fn set_session_policy(&self, policy: &SessionPolicy) -> Result<()> {
+        self.put_meta(SESSION_POLICY, policy)
+    }
+
+    pub(super) fn password_policy(&self) -> Result<Option<PasswordPolicy>> {
+        self.get_meta(PASSWORD_POLICY)
+    }
+
+    pub(super) fn set_password_policy(&self, policy: &PasswordPolicy) -> Result<()> {
+        self.put_meta(PASSWORD_POLICY, policy)
+    }
+
+    fn get_meta<T: for<'de> Deserialize<'de>>(&self, key: &[u8]) -> Result<Option<T>> {
+        let inner = self.inner.as_ref().expect("database must be open");
+        let map = Map::open(inner, META).expect("{META} table must be present");
+        map.get(key)?
+            .map(|value| deserialize(value.as_ref()))
+            .transpose()
+    }
+
+    fn put_meta<T: Serialize>(&self, key: &[u8], value: &T) -> Result<()> {
+        let inner = self.inner.as_ref().expect("database must be open");
+        let map = Map::open(inner, META).expect("{META} table must be present");
+        map.put(key, &serialize(value)?)
+    }
+
+    pub(super) fn create_new_backup_with_policy(
+        &mut self,
+        flush: bool,
+        policy: &RetentionPolicy,
+    ) -> Result<()> {
+        let mut engine = open_rocksdb_backup_engine(self.backup.as_path())?;
+
+        let inner = self
+            .inner
+            .as_ref()
+            .ok_or(anyhow!("unable to backup, database has closed"))?;
+        engine.create_new_backup_flush(inner, flush)?;
+
+        let num_of_backups_to_keep = policy.backups_to_keep(&engine.get_backup_info());
+        engine
+            .purge_old_backups(num_of_backups_to_keep as usize)
+            .or_else(|_| self.reboot())
+    }
+
     pub fn purge_old_backups(&mut self, num_of_backups_to_keep: u32) -> Result<()> {
         let mut engine = open_rocksdb_backup_engine(self.backup.as_path())?;
 
@@ -367,12 +715,10 @@ impl StateDb {
     }
 
     fn reboot(&mut self) -> Result<()> {
-        let mut opts = rocksdb::Options::default();
-        opts.create_if_missing(true);
-        opts.create_missing_column_families(true);
-        let db = rocksdb::OptimisticTransactionDB::open_cf(&opts, &self.db, MAP_NAMES)?;
+        let db = Self::open_db(&self.db)?;
 
         self.inner = Some(db);
+        self.notifier.invalidate_all();
         Ok(())
     }
 
@@ -381,8 +727,20 @@ impl StateDb {
         opts.create_if_missing(true);
         opts.create_missing_column_families(true);
 
-        Ok(rocksdb::OptimisticTransactionDB::open_cf(
-            &opts, path, MAP_NAMES,
+        let cfs = MAP_NAMES.iter().map(|name| {
+            let mut cf_opts = rocksdb::Options::default();
+            if *name == EVENT_SENSOR_STATS {
+                // The statistics are updated with merges, so that storing
+                // events doesn't conflict on them.
+                cf_opts.set_merge_operator_associative(
+                    "sensor stats",
+                    crate::event::merge_sensor_stats,
+                );
+            }
+            rocksdb::ColumnFamilyDescriptor::new(*name, cf_opts)
+        });
+        Ok(rocksdb::OptimisticTransactionDB::open_cf_descriptors(
+            &opts, path, cfs,
         )?)
     }
 }
@@ -399,6 +757,8 @@ pub struct TableIter<'i, R> {
         'i,
         rocksdb::OptimisticTransactionDB<rocksdb::SingleThreaded>,
     >,
+    excluded: HashSet<Vec<u8>>,
+    error: Option<anyhow::Error>,
     _phantom: std::marker::PhantomData<R>,
 }
 
@@ -411,9 +771,21 @@ impl<'i, R> TableIter<'i, R> {
     ) -> Self {
         Self {
             inner,
+            excluded: HashSet::new(),
+            error: None,
             _phantom: std::marker::PhantomData,
         }
     }
+
+    /// Skips the records with the given keys. If the keys cannot be read, the
+    /// iterator yields the error first.
+    fn excluding(mut self, keys: Result<HashSet<Vec<u8>>>) -> Self {
+        match keys {
+            Ok(keys) => self.excluded = keys,
+            Err(e) => self.error = Some(e),
+        }
+        self
+    }
 }
 
 impl<'i, R> Iterator for TableIter<'i, R>
@@ -423,22 +795,19 @@ where
     type Item = Result<R, anyhow::Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let serialized_item = self.inner.next()?;
-        match serialized_item {
-            Ok((mut key, mut value)) => {
-                if key.is_empty() {
-                    match self.inner.next()? {
-                        Ok((k, v)) => {
-                            key = k;
-                            value = v;
-                        }
-                        Err(e) => return Some(Err(e.into())),
-                    }
-                }
-                let item = R::from_key_value(&key, &value);
-                Some(item.map_err(Into::into))
+        if let Some(e) = self.error.take() {
+            return Some(Err(e));
+        }
+        loop {
+            let (key, value) = match self.inner.next()? {
+                Ok(item) => item,
+                Err(e) => return Some(Err(e.into())),
+            };
+            if key.is_empty() || self.excluded.contains(key.as_ref()) {
+                continue;
             }
-            Err(e) => Some(Err(e.into())),
+            let item = R::from_key_value(&key, &value);
+            return Some(item.map_err(Into::into));
         }
     }
 }
@@ -456,6 +825,10 @@ impl<'d, R> Table<'d, R> {
             _phantom: std::marker::PhantomData,
         }
     }
+
+    fn with_notifier(self, notifier: Option<&'d Channel>) -> Self {
+        Self::new(self.map.with_notifier(notifier))
+    }
 }
 
 impl<'d, R: UniqueKey + Value> Table<'d, R> {
@@ -477,33 +850,66 @@ impl<'d, R: UniqueKey + Value> Table<'d, R> {
     pub fn insert(&self, record: &R) -> Result<()> {
         self.map.insert(&record.unique_key(), &record.value())
     }
+
+    /// Stores a record into the database in `txn`, and returns its key.
+    fn put_in_transaction(
+        &self,
+        txn: &rocksdb::Transaction<rocksdb::OptimisticTransactionDB>,
+        record: &R,
+    ) -> Result<Vec<u8>> {
+        let key = record.unique_key().into_owned();
+        txn.put_cf(self.map.cf, &key, record.value())
+            .context("failed to write record")?;
+        Ok(key)
+    }
+}
+
+impl<'d, R: FromKeyValue + UniqueKey> Table<'d, R> {
+    /// Returns up to `limit` records whose keys start with `prefix`, in the
+    /// order of the keys.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any record cannot be read.
+    pub fn get_range_by_prefix(&self, prefix: &str, limit: usize) -> Result<Vec<R>> {
+        self.iter(Direction::Forward, Some(prefix.as_bytes()))
+            .take_while(|record| {
+                record
+                    .as_ref()
+                    .map_or(true, |r| r.unique_key().starts_with(prefix.as_bytes()))
+            })
+            .take(limit)
+            .collect()
+    }
 }
 
 impl<R: FromKeyValue> Iterable<R> for Table<'_, R> {
     fn iter(&self, direction: Direction, from: Option<&[u8]>) -> TableIter<'_, R> {
-        use rocksdb::IteratorMode;
-
-        match direction {
-            Direction::Forward => match from {
-                Some(from) => TableIter::new(
-                    self.map
-                        .db
-                        .iterator_cf(self.map.cf, IteratorMode::From(from, Direction::Forward)),
-                ),
-                None => TableIter::new(self.map.db.iterator_cf(self.map.cf, IteratorMode::Start)),
-            },
-            Direction::Reverse => match from {
-                Some(from) => TableIter::new(
-                    self.map
-                        .db
-                        .iterator_cf(self.map.cf, IteratorMode::From(from, Direction::Reverse)),
-                ),
-                None => TableIter::new(self.map.db.iterator_cf(self.map.cf, IteratorMode::End)),
-            },
-        }
+        record(self.map.notifier, Operation::Scan);
+        TableIter::new(iterator_from(self.map.db, self.map.cf, direction, from))
     }
 }
 
+/// Creates an iterator over the column family `cf` in `direction`, starting
+/// from `from`.
+fn iterator_from<'i>(
+    db: &'i rocksdb::OptimisticTransactionDB,
+    cf: &rocksdb::ColumnFamily,
+    direction: Direction,
+    from: Option<&[u8]>,
+) -> rocksdb::DBIteratorWithThreadMode<'i, rocksdb::OptimisticTransactionDB<rocksdb::SingleThreaded>>
+{
+    use rocksdb::IteratorMode;
+
+    let mode = match (direction, from) {
+        (Direction::Forward, Some(from)) => IteratorMode::From(from, Direction::Forward),
+        (Direction::Forward, None) => IteratorMode::Start,
+        (Direction::Reverse, Some(from)) => IteratorMode::From(from, Direction::Reverse),
+        (Direction::Reverse, None) => IteratorMode::End,
+    };
+    db.iterator_cf(cf, mode)
+}
+
 pub struct IndexedTable<'d, R> {
     indexed_map: IndexedMap<'d>,
     _phantom: std::marker::PhantomData<R>,
@@ -517,6 +923,10 @@ impl<'d, R> IndexedTable<'d, R> {
         }
     }
 
+    fn with_notifier(self, notifier: Option<&'d Channel>) -> Self {
+        Self::new(self.indexed_map.with_notifier(notifier))
+    }
+
     /// Returns the number of entries.
     ///
     /// # Errors
@@ -530,14 +940,64 @@ impl<'d, R> IndexedTable<'d, R> {
     ///
     /// # Errors
     ///
-    /// Returns an error if the database operation fails.
+    /// Returns an error if the record is invalid as described in
+    /// [`Indexable::validate`], or the database operation fails.
     pub fn put(&self, entry: R) -> Result<u32>
     where
         R: Indexable,
     {
+        entry.validate()?;
         self.indexed_map.insert(entry)
     }
 
+    /// Applies updates, each given as `(id, old, new)`, in a single
+    /// transaction. Either all the updates are applied, or none of them is.
+    ///
+    /// Unlike the `update` function of each table, this doesn't validate the
+    /// new values beyond what the update type itself checks.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any `id` is invalid, any record is not `old`, or the
+    /// database operation fails.
+    pub fn update_many<U>(&self, updates: &[(u32, U, U)]) -> Result<()>
+    where
+        U: IndexedMapUpdate<Entry = R>,
+        R: Indexable + FromKeyValue,
+    {
+        let updates: Vec<_> = updates
+            .iter()
+            .map(|(id, old, new)| (*id, old, new))
+            .collect();
+        self.indexed_map.update_many(&updates)
+    }
+
+    /// Returns how the IDs of the table are used.
+    ///
+    /// The IDs of removed records are reused, so the table doesn't need to be
+    /// compacted to avoid running out of IDs.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the map index is not found or the database operation fails.
+    pub fn id_usage(&self) -> Result<IdUsage> {
+        self.indexed_map.id_usage()
+    }
+
+    /// Stores a record with its own ID, instead of a newly assigned one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the record is invalid, the ID or the key of the
+    /// record is already in use, or the database operation fails.
+    pub(crate) fn put_with_id(&self, entry: &R) -> Result<()>
+    where
+        R: Indexable,
+    {
+        entry.validate()?;
+        self.indexed_map.insert_at(entry)
+    }
+
     /// Removes a record with the given ID.
     ///
     /// # Errors
@@ -562,6 +1022,21 @@ impl<'d, R> IndexedTable<'d, R> {
         self.indexed_map.get_by_id(id)
     }
 
+    /// Gets the records with the given IDs in a single database operation.
+    ///
+    /// The records are returned in the same order as `ids`. A record is `None`
+    /// if it does not exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any ID is invalid or the database operation fails.
+    pub fn get_many(&self, ids: &[u32]) -> Result<Vec<Option<R>>>
+    where
+        R: Indexable + FromKeyValue,
+    {
+        self.indexed_map.get_many_by_id(ids)
+    }
+
     /// Deactivates a key-value pair with the given ID.
     ///
     /// # Errors
@@ -570,36 +1045,353 @@ impl<'d, R> IndexedTable<'d, R> {
     pub fn deactivate(&self, id: u32) -> Result<Vec<u8>> {
         self.indexed_map.deactivate(id)
     }
+
+    /// Deactivates a record with the given ID without deleting it, and returns
+    /// its key.
+    ///
+    /// A soft-deactivated record keeps its ID and stays in the table, but is
+    /// excluded from iteration and counting until it is reactivated or
+    /// removed. It can still be read with [`IndexedTable::get_by_id`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `id` is invalid, the record is already deactivated,
+    /// or the database operation fails.
+    pub fn soft_deactivate(&self, id: u32) -> Result<Vec<u8>>
+    where
+        R: Indexable,
+    {
+        self.indexed_map.soft_deactivate::<R>(id)
+    }
+
+    /// Reactivates a record deactivated by [`IndexedTable::soft_deactivate`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the record is not deactivated or the database
+    /// operation fails.
+    pub fn reactivate(&self, id: u32) -> Result<()>
+    where
+        R: Indexable,
+    {
+        self.indexed_map.reactivate::<R>(id)
+    }
+
+    /// Returns the soft-deactivated records, ordered by ID.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails.
+    pub fn deactivated(&self) -> Result<Vec<R>>
+    where
+        R: Indexable + FromKeyValue,
+    {
+        let ids = self.indexed_map.deactivated_ids()?;
+        Ok(self.get_many(&ids)?.into_iter().flatten().collect())
+    }
+
+    /// Returns an iterator over all the records in the table, including the
+    /// soft-deactivated ones, in `direction`, starting from `from`.
+    pub fn iter_with_deactivated(
+        &self,
+        direction: Direction,
+        from: Option<&[u8]>,
+    ) -> TableIter<'_, R> {
+        record(self.indexed_map.notifier(), Operation::Scan);
+        TableIter::new(iterator_from(
+            self.indexed_map.db(),
+            self.indexed_map.cf(),
+            direction,
+            from,
+        ))
+    }
+
+    /// Returns an iterator over the records satisfying `predicate`, in
+    /// `direction`, starting from `from`.
+    ///
+    /// Records are deserialized and tested one at a time as the iterator
+    /// advances, so a caller can stop early without reading the rest of the
+    /// table. Records that cannot be deserialized are yielded as errors.
+    pub fn iter_filtered<'a, P>(
+        &'a self,
+        direction: Direction,
+        from: Option<&[u8]>,
+        mut predicate: P,
+    ) -> impl Iterator<Item = Result<R>> + 'a
+    where
+        R: Indexable + FromKeyValue + 'a,
+        P: FnMut(&R) -> bool + 'a,
+    {
+        self.iter(direction, from)
+            .filter(move |record| record.as_ref().map_or(true, &mut predicate))
+    }
+
+    /// Returns up to `limit` records whose keys start with `prefix`, in the
+    /// order of the keys. For the tables keyed by names, such as categories
+    /// and customers, this finds the records by the prefixes of their names.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any record cannot be read.
+    pub fn get_range_by_prefix(&self, prefix: &str, limit: usize) -> Result<Vec<R>>
+    where
+        R: Indexable + FromKeyValue,
+    {
+        self.iter(Direction::Forward, Some(prefix.as_bytes()))
+            .take_while(|record| {
+                record
+                    .as_ref()
+                    .map_or(true, |r| r.indexed_key().starts_with(prefix.as_bytes()))
+            })
+            .take(limit)
+            .collect()
+    }
+
+    /// Returns the number of records satisfying `predicate`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any record cannot be read.
+    pub fn count_filtered<P>(&self, mut predicate: P) -> Result<usize>
+    where
+        R: Indexable + FromKeyValue,
+        P: FnMut(&R) -> bool,
+    {
+        let mut count = 0;
+        for record in self.iter(Direction::Forward, None) {
+            if predicate(&record?) {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
 }
 
-impl<R: FromKeyValue> Iterable<R> for IndexedTable<'_, R> {
-    fn iter(&self, direction: Direction, from: Option<&[u8]>) -> TableIter<'_, R> {
-        use rocksdb::IteratorMode;
-
-        match direction {
-            Direction::Forward => match from {
-                Some(from) => TableIter::new(self.indexed_map.db().iterator_cf(
-                    self.indexed_map.cf(),
-                    IteratorMode::From(from, Direction::Forward),
-                )),
-                None => TableIter::new(
-                    self.indexed_map
-                        .db()
-                        .iterator_cf(self.indexed_map.cf(), IteratorMode::Start),
-                ),
-            },
-            Direction::Reverse => match from {
-                Some(from) => TableIter::new(self.indexed_map.db().iterator_cf(
-                    self.indexed_map.cf(),
-                    IteratorMode::From(from, Direction::Reverse),
-                )),
-                None => TableIter::new(
-                    self.indexed_map
-                        .db()
-                        .iterator_cf(self.indexed_map.cf(), IteratorMode::End),
-                ),
+/// A record identified by a name given by users.
+pub trait Named {
+    fn name(&self) -> &str;
+}
+
+/// Functions for the indexed tables keyed by names, where names can optionally
+/// be compared case-insensitively.
+impl<'d, R: Named + Indexable + FromKeyValue> IndexedTable<'d, R> {
+    /// Returns `true` if the names in the table are compared
+    /// case-insensitively.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails.
+    pub fn is_case_insensitive(&self) -> Result<bool> {
+        is_case_insensitive(self.indexed_map.db(), self.indexed_map.name())
+    }
+
+    /// Sets whether the names in the table are compared case-insensitively.
+    ///
+    /// When enabled, inserting or renaming a record fails if another record
+    /// has the same name ignoring case, and [`IndexedTable::find_by_name`]
+    /// ignores case.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `enabled` is `true` but the table already has
+    /// records whose names differ only in case, or the database operation
+    /// fails.
+    pub fn set_case_insensitive(&self, enabled: bool) -> Result<()> {
+        set_case_insensitive(
+            self.indexed_map.db(),
+            self.indexed_map.name(),
+            enabled,
+            |txn| {
+                let index = self.indexed_map.index_in_transaction(txn)?;
+                Ok(index
+                    .iter()
+                    .chain(index.deactivated())
+                    .map(|(_, name)| name.to_vec())
+                    .collect())
             },
+        )
+    }
+
+    /// Returns the record with `name`, ignoring case if the table is
+    /// case-insensitive.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the record cannot be read.
+    pub fn find_by_name(&self, name: &str) -> Result<Option<R>> {
+        let name = match stored_name(self.indexed_map.db(), self.indexed_map.name(), name)? {
+            Some(stored) => Cow::Owned(stored),
+            None => Cow::Borrowed(name),
+        };
+        if name.is_empty() {
+            return Ok(None);
+        }
+        // The records are keyed by their names.
+        self.indexed_map
+            .get_by_key(name.as_bytes())?
+            .map(|value| R::from_key_value(name.as_bytes(), value.as_ref()))
+            .transpose()
+    }
+}
+
+fn case_insensitive_key(table: &str) -> Vec<u8> {
+    format!("case-insensitive {table}").into_bytes()
+}
+
+fn lowercase_name_prefix(table: &str) -> Vec<u8> {
+    format!("lowercase {table}\0").into_bytes()
+}
+
+fn lowercase_name_key(table: &str, name: &[u8]) -> Vec<u8> {
+    let mut key = lowercase_name_prefix(table);
+    key.extend(String::from_utf8_lossy(name).to_lowercase().into_bytes());
+    key
+}
+
+fn meta(db: &rocksdb::OptimisticTransactionDB) -> Result<&rocksdb::ColumnFamily> {
+    db.cf_handle(META)
+        .ok_or_else(|| anyhow!("{META} table must be present"))
+}
+
+fn is_case_insensitive(db: &rocksdb::OptimisticTransactionDB, table: &str) -> Result<bool> {
+    Ok(db
+        .get_cf(meta(db)?, case_insensitive_key(table))
+        .context("cannot read case sensitivity")?
+        .is_some())
+}
+
+/// Returns the name stored in `table` that matches `name` ignoring case, if
+/// the table is case-insensitive.
+fn stored_name(
+    db: &rocksdb::OptimisticTransactionDB,
+    table: &str,
+    name: &str,
+) -> Result<Option<String>> {
+    if !is_case_insensitive(db, table)? {
+        return Ok(None);
+    }
+    let Some(stored) = db
+        .get_cf(meta(db)?, lowercase_name_key(table, name.as_bytes()))
+        .context("cannot read lowercase names")?
+    else {
+        return Ok(None);
+    };
+    String::from_utf8(stored)
+        .context("invalid name in database")
+        .map(Some)
+}
+
+/// Turns case-insensitive comparison of the names in `table` on or off. When
+/// turned on, the index of lowercase names is built from the names returned
+/// by `names`, which reads them in the transaction.
+fn set_case_insensitive<F>(
+    db: &rocksdb::OptimisticTransactionDB,
+    table: &str,
+    enabled: bool,
+    names: F,
+) -> Result<()>
+where
+    F: Fn(&rocksdb::Transaction<rocksdb::OptimisticTransactionDB>) -> Result<Vec<Vec<u8>>>,
+{
+    let meta = meta(db)?;
+    let flag = case_insensitive_key(table);
+    loop {
+        let txn = db.transaction();
+        let current = txn
+            .get_for_update_cf(meta, &flag, EXCLUSIVE)
+            .context("cannot read case sensitivity")?
+            .is_some();
+        if current == enabled {
+            return Ok(());
+        }
+        if enabled {
+            let mut lowercase = HashMap::new();
+            for name in names(&txn)? {
+                let key = lowercase_name_key(table, &name);
+                if let Some(other) = lowercase.insert(key, name.clone()) {
+                    bail!(
+                        "\"{}\" and \"{}\" differ only in case",
+                        String::from_utf8_lossy(&other),
+                        String::from_utf8_lossy(&name)
+                    );
+                }
+            }
+            for (key, name) in lowercase {
+                txn.put_cf(meta, key, name)
+                    .context("failed to write lowercase name")?;
+            }
+            txn.put_cf(meta, &flag, [])
+                .context("failed to write case sensitivity")?;
+        } else {
+            let prefix = lowercase_name_prefix(table);
+            let mut readopts = rocksdb::ReadOptions::default();
+            readopts.set_iterate_range(rocksdb::PrefixRange(prefix.as_slice()));
+            for item in txn.iterator_cf_opt(meta, readopts, rocksdb::IteratorMode::Start) {
+                let (key, _) = item.context("cannot read lowercase names")?;
+                txn.delete_cf(meta, key)
+                    .context("failed to delete lowercase name")?;
+            }
+            txn.delete_cf(meta, &flag)
+                .context("failed to write case sensitivity")?;
+        }
+        match txn.commit() {
+            Ok(()) => return Ok(()),
+            Err(e) => {
+                if !e.as_ref().starts_with("Resource busy:") {
+                    return Err(e).context("failed to set case sensitivity");
+                }
+            }
+        }
+    }
+}
+
+/// Replaces the name `old` of a record in `table` with `new` in the index of
+/// lowercase names in `txn`, if the names in `table` are compared
+/// case-insensitively. `None` means the record is added or removed.
+///
+/// # Errors
+///
+/// Returns an error if another record has `new` ignoring case, or the
+/// database operation fails.
+pub(crate) fn replace_lowercase_name(
+    db: &rocksdb::OptimisticTransactionDB,
+    txn: &rocksdb::Transaction<rocksdb::OptimisticTransactionDB>,
+    table: &str,
+    old: Option<&[u8]>,
+    new: Option<&[u8]>,
+) -> Result<()> {
+    let meta = meta(db)?;
+    if txn
+        .get_for_update_cf(meta, case_insensitive_key(table), EXCLUSIVE)
+        .context("cannot read case sensitivity")?
+        .is_none()
+    {
+        return Ok(());
+    }
+    if let Some(old) = old {
+        txn.delete_cf(meta, lowercase_name_key(table, old))
+            .context("failed to delete lowercase name")?;
+    }
+    if let Some(new) = new {
+        let key = lowercase_name_key(table, new);
+        if let Some(other) = txn
+            .get_for_update_cf(meta, &key, EXCLUSIVE)
+            .context("cannot read lowercase names")?
+        {
+            bail!("\"{}\" already exists", String::from_utf8_lossy(&other));
         }
+        txn.put_cf(meta, key, new)
+            .context("failed to write lowercase name")?;
+    }
+    Ok(())
+}
+
+impl<R: FromKeyValue + Indexable> Iterable<R> for IndexedTable<'_, R> {
+    /// Returns an iterator over the records in the table, excluding the
+    /// soft-deactivated ones.
+    fn iter(&self, direction: Direction, from: Option<&[u8]>) -> TableIter<'_, R> {
+        self.iter_with_deactivated(direction, from)
+            .excluding(self.indexed_map.deactivated_keys::<R>())
     }
 }
 
@@ -681,6 +1473,33 @@ fn deserialize<'de, O: Deserialize<'de>>(input: &'de [u8]) -> anyhow::Result<O>
     Ok(bincode::DefaultOptions::new().deserialize(input)?)
 }
 
+/// Deletes the entries in `map` of the models not in `models`, and returns the
+/// number of entries deleted. `model` returns the model ID in a key, or `None`
+/// if the key is invalid; entries with invalid keys are left untouched.
+fn delete_orphans(
+    map: &Map,
+    models: &HashSet<i32>,
+    model: impl Fn(&[u8]) -> Option<i32>,
+) -> Result<usize> {
+    use crate::IterableMap;
+
+    let orphans: Vec<_> = map
+        .iter_forward()?
+        .filter_map(|(k, _v)| match model(&k) {
+            Some(m) if !models.contains(&m) => Some(k),
+            _ => None,
+        })
+        .collect();
+    map.delete_many(&orphans)?;
+    Ok(orphans.len())
+}
+
+/// Returns the model ID at the beginning of a key.
+fn model_prefix(key: &[u8]) -> Option<i32> {
+    key.get(..4)
+        .map(|m| i32::from_be_bytes(m.try_into().expect("4 bytes")))
+}
+
 /// Opens a RocksDB backup engine using the default options and environment.
 fn open_rocksdb_backup_engine(
     path: &Path,
@@ -689,3 +1508,242 @@ fn open_rocksdb_backup_engine(
     let db_env = rocksdb::Env::new()?;
     rocksdb::backup::BackupEngine::open(&opts, &db_env)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::sync::RwLock;
+
+    use crate::{Direction, Store};
+
+    #[tokio::test]
+    async fn run_blocking() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(RwLock::new(
+            Store::new(db_dir.path(), backup_dir.path()).unwrap(),
+        ));
+
+        let id = crate::run_blocking(&store, |store| store.category_map().insert("category 3"))
+            .await
+            .unwrap();
+        let categories = crate::run_blocking(&store, |store| {
+            crate::Iterable::iter(&store.category_map(), Direction::Forward, None)
+                .collect::<anyhow::Result<Vec<_>>>()
+        })
+        .await
+        .unwrap();
+        assert_eq!(categories.len(), 3);
+        assert!(categories.iter().any(|c| c.id == id));
+        assert!(crate::run_blocking(&store, |_| -> anyhow::Result<()> {
+            anyhow::bail!("failed")
+        })
+        .await
+        .is_err());
+    }
+
+    #[test]
+    fn get_many() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+
+        let table = store.category_map();
+        let id3 = table.insert("category 3").unwrap();
+        let id4 = table.insert("category 4").unwrap();
+        table.remove(id3).unwrap();
+
+        let categories = table.get_many(&[id4, 1, id3]).unwrap();
+        assert_eq!(categories.len(), 3);
+        assert_eq!(categories[0].as_ref().unwrap().name, "category 4");
+        assert_eq!(categories[1].as_ref().unwrap().id, 1);
+        assert!(categories[2].is_none());
+        assert!(table.get_many(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn iter_filtered() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+
+        let table = store.category_map();
+        for name in ["a1", "b1", "a2", "b2", "a3"] {
+            table.insert(name).unwrap();
+        }
+        let names: Vec<_> = table
+            .iter_filtered(Direction::Forward, None, |c| c.name.starts_with('a'))
+            .map(|c| c.unwrap().name)
+            .collect();
+        assert_eq!(names, ["a1", "a2", "a3"]);
+
+        let mut tested = 0;
+        let first = table
+            .iter_filtered(Direction::Reverse, None, |c| {
+                tested += 1;
+                c.name.starts_with('a')
+            })
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(first.name, "a3");
+        assert_eq!(tested, 3);
+    }
+
+    #[test]
+    fn count_filtered() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+
+        let table = store.category_map();
+        for name in ["a1", "b1", "a2"] {
+            table.insert(name).unwrap();
+        }
+        assert_eq!(
+            table.count_filtered(|c| c.name.starts_with('a')).unwrap(),
+            2
+        );
+        assert_eq!(table.count_filtered(|_| true).unwrap(), 5);
+        assert_eq!(table.count_filtered(|_| false).unwrap(), 0);
+    }
+
+    #[test]
+    fn soft_deactivate() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+
+        let table = store.category_map();
+        let a = table.insert("a").unwrap();
+        let b = table.insert("b").unwrap();
+        assert_eq!(table.count().unwrap(), 4);
+
+        assert_eq!(table.soft_deactivate(a).unwrap(), b"a");
+        assert!(table.soft_deactivate(a).is_err());
+        assert_eq!(table.count().unwrap(), 3);
+        let names: Vec<_> = table
+            .iter(Direction::Forward, None)
+            .map(|c| c.unwrap().name)
+            .collect();
+        assert_eq!(names, ["Irrelevant Alert", "Non-Specified Alert", "b"]);
+        assert_eq!(
+            table
+                .iter_with_deactivated(Direction::Forward, None)
+                .count(),
+            4
+        );
+        let deactivated = table.deactivated().unwrap();
+        assert_eq!(deactivated.len(), 1);
+        assert_eq!(deactivated[0].name, "a");
+        assert_eq!(table.get_by_id(a).unwrap().unwrap().name, "a");
+
+        table.reactivate(a).unwrap();
+        assert!(table.reactivate(a).is_err());
+        assert_eq!(table.count().unwrap(), 4);
+        assert!(table.deactivated().unwrap().is_empty());
+
+        table.soft_deactivate(b).unwrap();
+        table.remove(b).unwrap();
+        assert!(table.deactivated().unwrap().is_empty());
+        let c = table.insert("c").unwrap();
+        assert_eq!(c, b);
+        assert_eq!(table.count().unwrap(), 4);
+        assert_eq!(table.iter(Direction::Forward, None).count(), 4);
+
+        assert_eq!(table.deactivate(c).unwrap(), b"c");
+        assert!(table.get_by_id(c).unwrap().is_none());
+        assert!(table.deactivated().unwrap().is_empty());
+        assert_eq!(table.count().unwrap(), 3);
+    }
+
+    #[test]
+    fn case_insensitive() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+        let mut table = store.category_map();
+        assert!(!table.is_case_insensitive().unwrap());
+
+        let upper = table.insert("Phishing").unwrap();
+        let lower = table.insert("phishing").unwrap();
+        assert!(table.find_by_name("PHISHING").unwrap().is_none());
+        assert!(table.set_case_insensitive(true).is_err());
+        table.remove(lower).unwrap();
+
+        table.set_case_insensitive(true).unwrap();
+        assert!(table.is_case_insensitive().unwrap());
+        assert!(table.insert("phishing").is_err());
+        assert_eq!(table.find_by_name("PHISHING").unwrap().unwrap().id, upper);
+        // Renaming a record to a different case of its own name is allowed.
+        table.update(upper, "Phishing", "PHISHING").unwrap();
+        let other = table.insert("spam").unwrap();
+        assert!(table.update(other, "spam", "phishing").is_err());
+        // A removed name is available again.
+        table.remove(other).unwrap();
+        let other = table.insert("SPAM").unwrap();
+        assert_eq!(table.find_by_name("spam").unwrap().unwrap().id, other);
+
+        table.set_case_insensitive(false).unwrap();
+        assert!(table.insert("phishing").is_ok());
+    }
+
+    #[test]
+    fn update_many() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+        let table = store.category_map();
+        let a = table.insert("a").unwrap();
+        let b = table.insert("b").unwrap();
+        let category = |id, name: &str| crate::Category {
+            id,
+            name: name.to_string(),
+            parent_id: None,
+        };
+
+        // The second update fails, so the first one is not applied either.
+        assert!(table
+            .update_many(&[
+                (a, category(a, "a"), category(a, "x")),
+                (b, category(b, "wrong"), category(b, "y")),
+            ])
+            .is_err());
+        assert_eq!(table.get_by_id(a).unwrap().unwrap().name, "a");
+
+        // Later updates see the earlier ones in the same batch.
+        table
+            .update_many(&[
+                (a, category(a, "a"), category(a, "c")),
+                (b, category(b, "b"), category(b, "a")),
+            ])
+            .unwrap();
+        assert_eq!(table.get_by_id(a).unwrap().unwrap().name, "c");
+        assert_eq!(table.get_by_id(b).unwrap().unwrap().name, "a");
+    }
+
+    #[test]
+    fn get_range_by_prefix() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+        let table = store.category_map();
+        for name in ["Phishing", "Phish kit", "Physical", "Pharming"] {
+            table.insert(name).unwrap();
+        }
+
+        let names = |categories: Vec<crate::Category>| -> Vec<String> {
+            categories.into_iter().map(|c| c.name).collect()
+        };
+        assert_eq!(
+            names(table.get_range_by_prefix("Phi", 10).unwrap()),
+            ["Phish kit", "Phishing"]
+        );
+        assert_eq!(
+            names(table.get_range_by_prefix("Ph", 2).unwrap()),
+            ["Pharming", "Phish kit"]
+        );
+        assert!(table.get_range_by_prefix("phi", 10).unwrap().is_empty());
+    }
+}
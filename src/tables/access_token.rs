@@ -1,27 +1,104 @@
 //! The `access_token` map.
 
-use anyhow::Result;
-use rocksdb::OptimisticTransactionDB;
+use std::{net::IpAddr, time::Duration};
 
-use crate::{types::FromKeyValue, Map, Table};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use rocksdb::{IteratorMode, OptimisticTransactionDB, Transaction};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, PartialEq)]
+use crate::{
+    notification::{notify, ChangeKind},
+    types::FromKeyValue,
+    IterableMap, Map, Table, EXCLUSIVE,
+};
+
+/// A session, identified by the username and the access token.
+#[derive(Clone, Debug, PartialEq)]
 pub struct AccessToken {
     pub username: String,
     pub token: String,
+    pub creation_time: DateTime<Utc>,
+    pub last_used_time: DateTime<Utc>,
+    pub device: SessionDevice,
+}
+
+/// The device a session was created from.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct SessionDevice {
+    pub name: Option<String>,
+    pub user_agent: Option<String>,
+    pub address: Option<IpAddr>,
+}
+
+/// How long a session stays valid.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SessionPolicy {
+    /// The maximum time between two uses of a session.
+    pub idle_timeout: Option<Duration>,
+    /// The maximum time since the creation of a session.
+    pub absolute_timeout: Option<Duration>,
+}
+
+impl SessionPolicy {
+    /// Returns `true` if `session` has expired at `now`.
+    #[must_use]
+    pub fn is_expired(&self, session: &AccessToken, now: DateTime<Utc>) -> bool {
+        let elapsed = |since: DateTime<Utc>, timeout: Option<Duration>| {
+            timeout.map_or(false, |timeout| {
+                (now - since)
+                    .to_std()
+                    .map_or(false, |elapsed| elapsed >= timeout)
+            })
+        };
+        elapsed(session.last_used_time, self.idle_timeout)
+            || elapsed(session.creation_time, self.absolute_timeout)
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct Session {
+    creation_time: DateTime<Utc>,
+    last_used_time: DateTime<Utc>,
+    device: SessionDevice,
 }
 
 impl AccessToken {
-    fn create_key_value(username: &str, token: &str) -> (Vec<u8>, Vec<u8>) {
+    fn create_key(username: &str, token: &str) -> Vec<u8> {
         let mut key = username.as_bytes().to_owned();
         key.push(0);
         key.extend(token.as_bytes());
-        (key, vec![])
+        key
+    }
+
+    fn key_prefix(username: &str) -> Vec<u8> {
+        let mut prefix = username.as_bytes().to_owned();
+        prefix.push(0);
+        prefix
+    }
+
+    /// Returns the value of a session stored in the 0.26 format, which has no
+    /// value, as if it were created now from an unknown device.
+    pub(crate) fn value_from_v0_26() -> Result<Vec<u8>> {
+        let now = Utc::now();
+        super::serialize(&Session {
+            creation_time: now,
+            last_used_time: now,
+            device: SessionDevice::default(),
+        })
+    }
+
+    fn value(&self) -> Result<Vec<u8>> {
+        super::serialize(&Session {
+            creation_time: self.creation_time,
+            last_used_time: self.last_used_time,
+            device: self.device.clone(),
+        })
     }
 }
 
 impl FromKeyValue for AccessToken {
-    fn from_key_value(key: &[u8], _value: &[u8]) -> Result<Self> {
+    fn from_key_value(key: &[u8], value: &[u8]) -> Result<Self> {
         use anyhow::anyhow;
 
         let sep = key
@@ -30,10 +107,43 @@ impl FromKeyValue for AccessToken {
             .ok_or(anyhow!("corruptted access token"))?;
         let username = String::from_utf8_lossy(&key[..sep]).into_owned();
         let token = String::from_utf8_lossy(&key[sep + 1..]).into_owned();
-        Ok(AccessToken { username, token })
+        let session: Session = super::deserialize(value)?;
+        Ok(AccessToken {
+            username,
+            token,
+            creation_time: session.creation_time,
+            last_used_time: session.last_used_time,
+            device: session.device,
+        })
     }
 }
 
+/// Deletes all the sessions of `username` in `txn`, and returns their keys.
+///
+/// # Errors
+///
+/// Returns an error if the database operation fails.
+pub(super) fn delete_all_for(
+    db: &OptimisticTransactionDB,
+    txn: &Transaction<OptimisticTransactionDB>,
+    username: &str,
+) -> Result<Vec<Box<[u8]>>> {
+    let cf = db
+        .cf_handle(super::ACCESS_TOKENS)
+        .context("no access tokens table")?;
+    let prefix = AccessToken::key_prefix(username);
+    let mut readopts = rocksdb::ReadOptions::default();
+    readopts.set_iterate_range(rocksdb::PrefixRange(prefix.as_slice()));
+    let mut keys = Vec::new();
+    for entry in txn.iterator_cf_opt(cf, readopts, IteratorMode::Start) {
+        let (key, _) = entry.context("cannot read session")?;
+        txn.delete_cf(cf, &key)
+            .context("failed to delete session")?;
+        keys.push(key);
+    }
+    Ok(keys)
+}
+
 /// Functions for the `access_token` map.
 impl<'d> Table<'d, AccessToken> {
     /// Opens the  `access_token` map in the database.
@@ -49,8 +159,27 @@ impl<'d> Table<'d, AccessToken> {
     ///
     /// Returns an error if the database operation fails.
     pub fn insert(&self, username: &str, token: &str) -> Result<()> {
-        let (key, value) = AccessToken::create_key_value(username, token);
-        self.map.insert(&key, &value)
+        self.insert_session(username, token, SessionDevice::default())
+    }
+
+    /// Inserts a session of `username` with `token`, created from `device`
+    /// now.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the session exists or the database operation
+    /// fails.
+    pub fn insert_session(&self, username: &str, token: &str, device: SessionDevice) -> Result<()> {
+        let now = Utc::now();
+        let session = AccessToken {
+            username: username.to_string(),
+            token: token.to_string(),
+            creation_time: now,
+            last_used_time: now,
+            device,
+        };
+        self.map
+            .insert(&AccessToken::create_key(username, token), &session.value()?)
     }
 
     /// Remove `(username, token)` from map in the database.
@@ -59,18 +188,253 @@ impl<'d> Table<'d, AccessToken> {
     ///
     /// Returns an error if the combo does not exist or the database operation fails.
     pub fn revoke(&self, username: &str, token: &str) -> Result<()> {
-        let (key, _value) = AccessToken::create_key_value(username, token);
+        let key = AccessToken::create_key(username, token);
 
         self.map.delete(&key)
     }
 
+    /// Revokes all the sessions of `username`, and returns the number of
+    /// sessions revoked.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails.
+    pub fn revoke_all(&self, username: &str) -> Result<usize> {
+        let keys = loop {
+            let txn = self.map.db.transaction();
+            let keys = delete_all_for(self.map.db, &txn, username)?;
+            match txn.commit() {
+                Ok(()) => break keys,
+                Err(e) => {
+                    if !e.as_ref().starts_with("Resource busy:") {
+                        return Err(e).context("failed to revoke sessions");
+                    }
+                }
+            }
+        };
+        for key in &keys {
+            notify(self.map.notifier, ChangeKind::Remove, key);
+        }
+        Ok(keys.len())
+    }
+
     /// Find whether `username` `token` exists in the database.
     ///
     /// # Errors
     ///
     /// Returns an error if the database operation fails.
     pub fn contains(&self, username: &str, token: &str) -> Result<bool> {
-        let (key, _value) = AccessToken::create_key_value(username, token);
+        let key = AccessToken::create_key(username, token);
         self.map.get(&key).map(|v| v.is_some())
     }
+
+    /// Returns the session of `username` with `token`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails or the session is
+    /// invalid.
+    pub fn get(&self, username: &str, token: &str) -> Result<Option<AccessToken>> {
+        let key = AccessToken::create_key(username, token);
+        self.map
+            .get(&key)?
+            .map(|value| AccessToken::from_key_value(&key, value.as_ref()))
+            .transpose()
+    }
+
+    /// Returns the sessions of `username`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any session is invalid.
+    pub fn sessions(&self, username: &str) -> Result<Vec<AccessToken>> {
+        let prefix = AccessToken::key_prefix(username);
+        self.map
+            .inner_prefix_iterator(IteratorMode::Start, &prefix)
+            .map(|(k, v)| AccessToken::from_key_value(&k, &v))
+            .collect()
+    }
+
+    /// Returns the session of `username` with `token` and updates its
+    /// last-used time, or returns `None` if there is no such session or it
+    /// has expired according to `policy`. An expired session is revoked.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails or the session is
+    /// invalid.
+    pub fn validate(
+        &self,
+        username: &str,
+        token: &str,
+        policy: Option<&SessionPolicy>,
+    ) -> Result<Option<AccessToken>> {
+        let key = AccessToken::create_key(username, token);
+        loop {
+            let txn = self.map.db.transaction();
+            let Some(value) = txn
+                .get_for_update_cf(self.map.cf, &key, EXCLUSIVE)
+                .context("cannot read session")?
+            else {
+                return Ok(None);
+            };
+            let mut session = AccessToken::from_key_value(&key, &value)?;
+            let now = Utc::now();
+            let expired = policy.map_or(false, |policy| policy.is_expired(&session, now));
+            if expired {
+                txn.delete_cf(self.map.cf, &key)
+                    .context("failed to delete session")?;
+            } else {
+                session.last_used_time = now;
+                txn.put_cf(self.map.cf, &key, session.value()?)
+                    .context("failed to update session")?;
+            }
+
+            match txn.commit() {
+                Ok(()) => {
+                    if expired {
+                        notify(self.map.notifier, ChangeKind::Remove, &key);
+                        return Ok(None);
+                    }
+                    notify(self.map.notifier, ChangeKind::Update, &key);
+                    return Ok(Some(session));
+                }
+                Err(e) => {
+                    if !e.as_ref().starts_with("Resource busy:") {
+                        return Err(e).context("failed to validate session");
+                    }
+                }
+            }
+        }
+    }
+
+    /// Revokes all the sessions expired according to `policy`, and returns
+    /// the number of sessions revoked.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails or any session is
+    /// invalid.
+    pub fn purge_expired(&self, policy: &SessionPolicy) -> Result<usize> {
+        let now = Utc::now();
+        let mut candidates = Vec::new();
+        for (k, v) in self.map.iter_forward()? {
+            let session = AccessToken::from_key_value(&k, &v)?;
+            if policy.is_expired(&session, now) {
+                candidates.push(k);
+            }
+        }
+
+        // A session used after it was read above is no longer expired, so
+        // each one is checked again before it is deleted.
+        let keys = loop {
+            let txn = self.map.db.transaction();
+            let mut keys = Vec::new();
+            for key in &candidates {
+                let Some(value) = txn
+                    .get_for_update_cf(self.map.cf, key, EXCLUSIVE)
+                    .context("cannot read session")?
+                else {
+                    continue;
+                };
+                if policy.is_expired(&AccessToken::from_key_value(key, &value)?, now) {
+                    txn.delete_cf(self.map.cf, key)
+                        .context("failed to delete session")?;
+                    keys.push(key);
+                }
+            }
+            match txn.commit() {
+                Ok(()) => break keys,
+                Err(e) => {
+                    if !e.as_ref().starts_with("Resource busy:") {
+                        return Err(e).context("failed to purge sessions");
+                    }
+                }
+            }
+        };
+        for key in &keys {
+            notify(self.map.notifier, ChangeKind::Remove, key);
+        }
+        Ok(keys.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use super::{SessionDevice, SessionPolicy};
+    use crate::{types::Account, Role, Store};
+
+    #[test]
+    fn sessions() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(Store::new(db_dir.path(), backup_dir.path()).unwrap());
+        let table = store.access_token_map();
+
+        let device = SessionDevice {
+            name: Some("laptop".to_string()),
+            user_agent: None,
+            address: Some("127.0.0.1".parse().unwrap()),
+        };
+        table
+            .insert_session("user1", "token1", device.clone())
+            .unwrap();
+        table.insert("user1", "token2").unwrap();
+        table.insert("user10", "token3").unwrap();
+        assert!(table.insert("user1", "token1").is_err());
+
+        let session = table.get("user1", "token1").unwrap().unwrap();
+        assert_eq!(session.device, device);
+        assert_eq!(session.creation_time, session.last_used_time);
+        assert_eq!(table.sessions("user1").unwrap().len(), 2);
+
+        let validated = table.validate("user1", "token1", None).unwrap().unwrap();
+        assert!(validated.last_used_time >= session.last_used_time);
+        assert!(table.validate("user1", "token9", None).unwrap().is_none());
+
+        let policy = SessionPolicy {
+            idle_timeout: Some(Duration::ZERO),
+            absolute_timeout: None,
+        };
+        assert!(table
+            .validate("user1", "token1", Some(&policy))
+            .unwrap()
+            .is_none());
+        assert!(!table.contains("user1", "token1").unwrap());
+
+        assert_eq!(table.revoke_all("user1").unwrap(), 1);
+        assert!(table.sessions("user1").unwrap().is_empty());
+        assert_eq!(table.sessions("user10").unwrap().len(), 1);
+        assert_eq!(table.purge_expired(&policy).unwrap(), 1);
+    }
+
+    #[test]
+    fn account_deletion() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(Store::new(db_dir.path(), backup_dir.path()).unwrap());
+        let table = store.access_token_map();
+
+        let account = Account::new(
+            "user1",
+            "password",
+            Role::SecurityMonitor,
+            String::new(),
+            String::new(),
+            None,
+            None,
+        )
+        .unwrap();
+        store.account_map().insert(&account, "admin").unwrap();
+        table.insert("user1", "token1").unwrap();
+        table.insert("user1", "token2").unwrap();
+        table.insert("user10", "token3").unwrap();
+
+        store.account_map().delete("user1", "admin").unwrap();
+        assert!(table.sessions("user1").unwrap().is_empty());
+        assert!(table.validate("user1", "token1", None).unwrap().is_none());
+        assert_eq!(table.sessions("user10").unwrap().len(), 1);
+    }
 }
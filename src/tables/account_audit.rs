@@ -0,0 +1,366 @@
+//! The `account audit` table.
+
+use std::{net::IpAddr, ops::Range};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use rocksdb::{OptimisticTransactionDB, Transaction};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    types::{Account, FromKeyValue, HostNetworkGroup},
+    Direction, Iterable, Map, Role, Table, EXCLUSIVE,
+};
+
+/// The kind of a change made to an account.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum AccountAction {
+    Create,
+    Update,
+    Delete,
+}
+
+/// The audited attributes of an account. Passwords are not recorded; a
+/// password change shows as a change of `password_changed_time`.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct AccountSnapshot {
+    pub role: Role,
+    pub name: String,
+    pub department: String,
+    pub allow_access_from: Option<Vec<IpAddr>>,
+    pub allowed_networks: Option<HostNetworkGroup>,
+    pub max_parallel_sessions: Option<u32>,
+    pub password_changed_time: DateTime<Utc>,
+    /// The time until which the account is locked, if it is locked.
+    pub locked_until: Option<DateTime<Utc>>,
+    pub totp_enabled: bool,
+}
+
+impl From<&Account> for AccountSnapshot {
+    fn from(account: &Account) -> Self {
+        Self {
+            role: account.role,
+            name: account.name.clone(),
+            department: account.department.clone(),
+            allow_access_from: account.allow_access_from.clone(),
+            allowed_networks: account.allowed_networks.clone(),
+            max_parallel_sessions: account.max_parallel_sessions,
+            password_changed_time: account.password_changed_time(),
+            locked_until: account.locked_until(Utc::now()),
+            totp_enabled: account.totp_enabled(),
+        }
+    }
+}
+
+/// A change made to an account, and who made it.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct AccountAuditEntry {
+    pub time: DateTime<Utc>,
+    pub username: String,
+    /// The username of the account that made the change.
+    pub actor: String,
+    pub action: AccountAction,
+    /// The account before the change. `None` if the account is created.
+    pub before: Option<AccountSnapshot>,
+    /// The account after the change. `None` if the account is deleted.
+    pub after: Option<AccountSnapshot>,
+}
+
+impl AccountAuditEntry {
+    /// Returns `true` if the change modified the role.
+    #[must_use]
+    pub fn role_changed(&self) -> bool {
+        match (&self.before, &self.after) {
+            (Some(before), Some(after)) => before.role != after.role,
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if the change modified the password.
+    #[must_use]
+    pub fn password_changed(&self) -> bool {
+        match (&self.before, &self.after) {
+            (Some(before), Some(after)) => {
+                before.password_changed_time != after.password_changed_time
+            }
+            _ => false,
+        }
+    }
+
+    fn key(time: DateTime<Utc>, username: &str) -> Vec<u8> {
+        let mut key = time_key(time).to_vec();
+        key.extend(username.as_bytes());
+        key
+    }
+}
+
+/// Returns the prefix of the keys of the entries recorded at `time`, which
+/// sort in chronological order. A time out of the range of nanosecond
+/// timestamps is clamped to it.
+fn time_key(time: DateTime<Utc>) -> [u8; 8] {
+    let nanos = time
+        .timestamp_nanos_opt()
+        .unwrap_or(if time.timestamp() < 0 {
+            i64::MIN
+        } else {
+            i64::MAX
+        });
+    // Flipping the sign bit makes negative timestamps sort first.
+    (u64::from_be_bytes(nanos.to_be_bytes()) ^ (1 << 63)).to_be_bytes()
+}
+
+/// Records in `txn` a change made by `actor` to the account with `username`,
+/// given the account before and after the change. A change that modifies none
+/// of the audited attributes is not recorded.
+///
+/// # Errors
+///
+/// Returns an error if both `before` and `after` are `None`, or the database
+/// operation fails.
+pub(crate) fn record(
+    db: &OptimisticTransactionDB,
+    txn: &Transaction<OptimisticTransactionDB>,
+    actor: &str,
+    username: &str,
+    before: Option<AccountSnapshot>,
+    after: Option<AccountSnapshot>,
+) -> Result<()> {
+    let action = match (&before, &after) {
+        (None, Some(_)) => AccountAction::Create,
+        (Some(_), None) => AccountAction::Delete,
+        (Some(before), Some(after)) if before == after => return Ok(()),
+        (Some(_), Some(_)) => AccountAction::Update,
+        (None, None) => bail!("no account to record"),
+    };
+    let cf = db
+        .cf_handle(super::ACCOUNT_AUDIT)
+        .context("no account audit table")?;
+    let mut entry = AccountAuditEntry {
+        time: Utc::now(),
+        username: username.to_string(),
+        actor: actor.to_string(),
+        action,
+        before,
+        after,
+    };
+    // Two changes to the same account can be made at the same time.
+    while txn
+        .get_for_update_cf(cf, AccountAuditEntry::key(entry.time, username), EXCLUSIVE)
+        .context("cannot read account audit entry")?
+        .is_some()
+    {
+        entry.time += chrono::Duration::nanoseconds(1);
+    }
+    txn.put_cf(
+        cf,
+        AccountAuditEntry::key(entry.time, username),
+        super::serialize(&entry)?,
+    )
+    .context("failed to write account audit entry")
+}
+
+impl FromKeyValue for AccountAuditEntry {
+    fn from_key_value(_key: &[u8], value: &[u8]) -> Result<Self> {
+        super::deserialize(value)
+    }
+}
+
+/// Functions for the `account audit` table.
+///
+/// The table is append-only. Entries are recorded by the functions of the
+/// accounts table in the same transactions as the changes they record, and
+/// can only be queried.
+impl<'d> Table<'d, AccountAuditEntry> {
+    /// Opens the `account audit` table in the database.
+    ///
+    /// Returns `None` if the table does not exist.
+    pub(super) fn open(db: &'d OptimisticTransactionDB) -> Option<Self> {
+        Map::open(db, super::ACCOUNT_AUDIT).map(Table::new)
+    }
+
+    /// Returns the changes made to the account with `username` in `range`,
+    /// in chronological order.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails or any entry is
+    /// invalid.
+    pub fn by_account(
+        &self,
+        username: &str,
+        range: Range<DateTime<Utc>>,
+    ) -> Result<Vec<AccountAuditEntry>> {
+        let mut entries = self.by_time(range)?;
+        entries.retain(|entry| entry.username == username);
+        Ok(entries)
+    }
+
+    /// Returns the changes made to any account in `range`, in chronological
+    /// order.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails or any entry is
+    /// invalid.
+    pub fn by_time(&self, range: Range<DateTime<Utc>>) -> Result<Vec<AccountAuditEntry>> {
+        let start = time_key(range.start);
+        let mut entries = Vec::new();
+        for entry in self.iter(Direction::Forward, Some(&start)) {
+            let entry = entry?;
+            if entry.time >= range.end {
+                break;
+            }
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::{Duration, Utc};
+
+    use super::AccountAction;
+    use crate::{types::Account, HostNetworkGroup, LockoutPolicy, PasswordValidation, Role, Store};
+
+    #[test]
+    fn record_and_query() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(Store::new(db_dir.path(), backup_dir.path()).unwrap());
+        let start = Utc::now();
+
+        for username in ["user1", "user10"] {
+            let account = Account::new(
+                username,
+                "password",
+                Role::SecurityMonitor,
+                "User".to_string(),
+                "Department".to_string(),
+                None,
+                None,
+            )
+            .unwrap();
+            store.insert_account(&account, "admin").unwrap();
+        }
+        store
+            .update_account(
+                "admin",
+                "user1",
+                &Some("new password".to_string()),
+                Some((Role::SecurityMonitor, Role::SecurityManager)),
+                &None,
+                &None,
+                &None,
+                &None,
+                &None,
+            )
+            .unwrap();
+        store.delete_account("user1", "root").unwrap();
+
+        let table = store.account_audit_map();
+        let end = Utc::now() + Duration::seconds(1);
+        let entries = table.by_account("user1", start..end).unwrap();
+        let actions: Vec<_> = entries.iter().map(|e| e.action).collect();
+        assert_eq!(
+            actions,
+            [
+                AccountAction::Create,
+                AccountAction::Update,
+                AccountAction::Delete
+            ]
+        );
+        assert!(entries[1].role_changed());
+        assert!(entries[1].password_changed());
+        assert_eq!(entries[2].actor, "root");
+        assert!(entries[2].after.is_none());
+
+        assert_eq!(table.by_time(start..end).unwrap().len(), 4);
+        assert!(table.by_account("user1", end..end).unwrap().is_empty());
+    }
+
+    #[test]
+    fn direct_changes() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(Store::new(db_dir.path(), backup_dir.path()).unwrap());
+        let start = Utc::now();
+        let accounts = store.account_map();
+
+        let account = Account::new(
+            "user1",
+            "password",
+            Role::SecurityMonitor,
+            "User".to_string(),
+            "Department".to_string(),
+            None,
+            None,
+        )
+        .unwrap();
+        accounts.put(&account, "admin").unwrap();
+        let networks = HostNetworkGroup::new(vec![], vec!["10.0.0.0/8".parse().unwrap()], vec![]);
+        accounts
+            .set_allowed_networks("user1", Some(networks), "admin")
+            .unwrap();
+
+        // A sign-in from outside the allowed networks is rejected without
+        // checking the password.
+        let inside = "10.0.0.1".parse().unwrap();
+        for address in [Some("192.168.0.1".parse().unwrap()), None] {
+            assert_eq!(
+                accounts
+                    .validate_password("user1", "password", None, None, address)
+                    .unwrap(),
+                PasswordValidation::AddressNotAllowed
+            );
+        }
+        assert_eq!(
+            accounts
+                .validate_password("user1", "password", None, None, Some(inside))
+                .unwrap(),
+            PasswordValidation::Valid
+        );
+
+        // A failure that doesn't lock the account changes nothing audited.
+        let policy = LockoutPolicy {
+            max_failures: 2,
+            duration: std::time::Duration::from_secs(600),
+        };
+        accounts
+            .validate_password("user1", "wrong", None, Some(&policy), Some(inside))
+            .unwrap();
+        accounts
+            .validate_password("user1", "wrong", None, Some(&policy), Some(inside))
+            .unwrap();
+        accounts.clear_lockout("user1", "admin").unwrap();
+
+        let end = Utc::now() + Duration::seconds(1);
+        let entries = store
+            .account_audit_map()
+            .by_account("user1", start..end)
+            .unwrap();
+        let changes: Vec<_> = entries
+            .iter()
+            .map(|e| (e.action, e.actor.as_str()))
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                (AccountAction::Create, "admin"),
+                (AccountAction::Update, "admin"),
+                (AccountAction::Update, "user1"),
+                (AccountAction::Update, "admin"),
+            ]
+        );
+        assert!(entries[1]
+            .after
+            .as_ref()
+            .unwrap()
+            .allowed_networks
+            .is_some());
+        assert!(entries[2].after.as_ref().unwrap().locked_until.is_some());
+        assert!(entries[3].after.as_ref().unwrap().locked_until.is_none());
+    }
+}
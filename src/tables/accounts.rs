@@ -1,13 +1,17 @@
 //! The accounts table.
 
-use std::net::IpAddr;
+use std::{collections::HashSet, net::IpAddr};
 
 use anyhow::{bail, Context};
 use bincode::Options;
+use chrono::{DateTime, Utc};
 use rocksdb::OptimisticTransactionDB;
 
+use super::account_audit::{self, AccountSnapshot};
 use crate::{
-    types::{Account, FromKeyValue},
+    account::{ExportedAccount, LockoutPolicy, PasswordPolicy, PasswordValidation, TotpKey},
+    notification::{notify, ChangeKind},
+    types::{Account, FromKeyValue, HostNetworkGroup},
     Map, Role, Table, EXCLUSIVE,
 };
 
@@ -18,6 +22,10 @@ impl FromKeyValue for Account {
 }
 
 /// Functions for the accounts table.
+///
+/// Every change to an account is recorded in the account audit table in the
+/// same transaction, with the account that made the change. Only the changes
+/// to the accounts table are notified to the subscribers.
 impl<'d> Table<'d, Account> {
     /// Opens the accounts table in the database.
     ///
@@ -35,13 +43,184 @@ impl<'d> Table<'d, Account> {
         self.map.get(username.as_bytes()).map(|v| v.is_some())
     }
 
-    /// Deletes an account with the given username.
+    /// Deletes an account with the given username on behalf of `actor`, and
+    /// revokes its sessions and API keys in the same transaction.
     ///
     /// # Errors
     ///
     /// Returns an error if the account does not exist or the database operation fails.
-    pub fn delete(&self, username: &str) -> Result<(), anyhow::Error> {
-        self.map.delete(username.as_bytes())
+    pub fn delete(&self, username: &str, actor: &str) -> Result<(), anyhow::Error> {
+        let key = username.as_bytes();
+        loop {
+            let txn = self.map.db.transaction();
+            let Some(old_value) = txn
+                .get_for_update_cf(self.map.cf, key, EXCLUSIVE)
+                .context("cannot read old entry")?
+            else {
+                bail!("no such entry");
+            };
+            let before = bincode::DefaultOptions::new().deserialize::<Account>(&old_value)?;
+            txn.delete_cf(self.map.cf, key)
+                .context("failed to delete entry")?;
+            account_audit::record(
+                self.map.db,
+                &txn,
+                actor,
+                username,
+                Some(AccountSnapshot::from(&before)),
+                None,
+            )?;
+            super::access_token::delete_all_for(self.map.db, &txn, username)?;
+            super::api_key::delete_all_for(self.map.db, &txn, username)?;
+
+            match txn.commit() {
+                Ok(()) => break,
+                Err(e) => {
+                    if !e.as_ref().starts_with("Resource busy:") {
+                        return Err(e).context("failed to delete entry");
+                    }
+                }
+            }
+        }
+        notify(self.map.notifier, ChangeKind::Remove, key);
+        Ok(())
+    }
+
+    /// Inserts `account` on behalf of `actor`. A password set to the account
+    /// is checked against the password policy, if one is stored.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the account exists, its password violates the
+    /// password policy, or the database operation fails.
+    pub fn insert(&self, account: &Account, actor: &str) -> Result<(), anyhow::Error> {
+        self.import(std::slice::from_ref(account), actor)
+    }
+
+    /// Stores `account` on behalf of `actor`, replacing the account with the
+    /// same username, if any. A password set to the account is checked against
+    /// the password policy, if one is stored.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the password of the account violates the password
+    /// policy, or the database operation fails.
+    pub fn put(&self, account: &Account, actor: &str) -> Result<(), anyhow::Error> {
+        let key = account.username.as_bytes();
+        let value = bincode::DefaultOptions::new().serialize(account)?;
+        loop {
+            let txn = self.map.db.transaction();
+            if let Some(policy) = self.password_policy_in(&txn)? {
+                account.check_new_password(&policy)?;
+            }
+            let before = txn
+                .get_for_update_cf(self.map.cf, key, EXCLUSIVE)
+                .context("cannot read old entry")?
+                .map(|old_value| bincode::DefaultOptions::new().deserialize::<Account>(&old_value))
+                .transpose()?;
+            txn.put_cf(self.map.cf, key, &value)
+                .context("failed to write new entry")?;
+            account_audit::record(
+                self.map.db,
+                &txn,
+                actor,
+                &account.username,
+                before.as_ref().map(AccountSnapshot::from),
+                Some(AccountSnapshot::from(account)),
+            )?;
+
+            match txn.commit() {
+                Ok(()) => break,
+                Err(e) => {
+                    if !e.as_ref().starts_with("Resource busy:") {
+                        return Err(e).context("failed to write entry");
+                    }
+                }
+            }
+        }
+        notify(self.map.notifier, ChangeKind::Update, key);
+        Ok(())
+    }
+
+    /// Inserts `accounts` atomically on behalf of `actor`; either all of them
+    /// are inserted or none is. Their passwords are checked against the
+    /// password policy, if one is stored, and their creation is recorded in
+    /// the account audit table in the same transaction.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any of the accounts exists, appears more than once
+    /// in `accounts`, or has a password that violates the password policy, or
+    /// the database operation fails.
+    pub fn import(&self, accounts: &[Account], actor: &str) -> Result<(), anyhow::Error> {
+        let mut usernames = HashSet::new();
+        for account in accounts {
+            if !usernames.insert(account.username.as_str()) {
+                bail!("duplicate account: {}", account.username);
+            }
+        }
+        loop {
+            let txn = self.map.db.transaction();
+            if let Some(policy) = self.password_policy_in(&txn)? {
+                for account in accounts {
+                    account
+                        .check_new_password(&policy)
+                        .with_context(|| format!("invalid account: {}", account.username))?;
+                }
+            }
+            for account in accounts {
+                let key = account.username.as_bytes();
+                if txn
+                    .get_for_update_cf(self.map.cf, key, EXCLUSIVE)
+                    .context("cannot read old entry")?
+                    .is_some()
+                {
+                    bail!("account already exists: {}", account.username);
+                }
+                let value = bincode::DefaultOptions::new().serialize(account)?;
+                txn.put_cf(self.map.cf, key, value)
+                    .context("failed to write new entry")?;
+                account_audit::record(
+                    self.map.db,
+                    &txn,
+                    actor,
+                    &account.username,
+                    None,
+                    Some(AccountSnapshot::from(account)),
+                )?;
+            }
+
+            match txn.commit() {
+                Ok(()) => break,
+                Err(e) => {
+                    if !e.as_ref().starts_with("Resource busy:") {
+                        return Err(e).context("failed to import accounts");
+                    }
+                }
+            }
+        }
+        for account in accounts {
+            notify(
+                self.map.notifier,
+                ChangeKind::Insert,
+                account.username.as_bytes(),
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns all the accounts without their secrets.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails or any account is
+    /// invalid.
+    pub fn export(&self) -> Result<Vec<ExportedAccount>, anyhow::Error> {
+        use crate::{Direction, Iterable};
+
+        self.iter(Direction::Forward, None)
+            .map(|account| account.map(|account| ExportedAccount::from(&account)))
+            .collect()
     }
 
     /// Returns an account with the given username.
@@ -58,25 +237,29 @@ impl<'d> Table<'d, Account> {
         ))
     }
 
-    /// Updates an entry in account map.
+    /// Updates an entry in account map on behalf of `actor`. A new password is
+    /// checked against the password policy, if one is stored.
     ///
     /// # Errors
     ///
     /// Returns an error in the following cases:
     ///
     /// * The account stored in the database in invalid.
+    /// * The new password violates the password policy.
     /// * Random number generation for a password salt fails.
     /// * The old values do not match the values in the database.
     /// * The underlying database operation fails.
     #[allow(clippy::too_many_arguments, clippy::type_complexity)]
     pub fn update(
         &self,
+        actor: &str,
         username: &[u8],
         new_password: &Option<String>,
         role: Option<(Role, Role)>,
         name: &Option<(String, String)>,
         department: &Option<(String, String)>,
         allow_access_from: &Option<(Option<Vec<IpAddr>>, Option<Vec<IpAddr>>)>,
+        allowed_networks: &Option<(Option<HostNetworkGroup>, Option<HostNetworkGroup>)>,
         max_parallel_sessions: &Option<(Option<u32>, Option<u32>)>,
     ) -> Result<(), anyhow::Error> {
         loop {
@@ -87,9 +270,14 @@ impl<'d> Table<'d, Account> {
             {
                 let mut account =
                     bincode::DefaultOptions::new().deserialize::<Account>(old_value.as_ref())?;
+                let before = AccountSnapshot::from(&account);
 
                 if let Some(password) = &new_password {
-                    account.update_password(password)?;
+                    if let Some(policy) = self.password_policy_in(&txn)? {
+                        account.change_password(password, &policy)?;
+                    } else {
+                        account.update_password(password)?;
+                    }
                 }
 
                 if let Some((old, new)) = &role {
@@ -116,6 +304,12 @@ impl<'d> Table<'d, Account> {
                     }
                     account.allow_access_from = new.clone();
                 }
+                if let Some((old, new)) = &allowed_networks {
+                    if account.allowed_networks != *old {
+                        bail!("old value mismatch");
+                    }
+                    account.allowed_networks = new.clone();
+                }
                 if let Some((old, new)) = max_parallel_sessions {
                     if account.max_parallel_sessions != *old {
                         bail!("old value mismatch");
@@ -126,6 +320,14 @@ impl<'d> Table<'d, Account> {
                 let value = bincode::DefaultOptions::new().serialize(&account)?;
                 txn.put_cf(self.map.cf, username, value)
                     .context("failed to write new entry")?;
+                account_audit::record(
+                    self.map.db,
+                    &txn,
+                    actor,
+                    &account.username,
+                    Some(before),
+                    Some(AccountSnapshot::from(&account)),
+                )?;
             } else {
                 bail!("no such entry");
             };
@@ -139,15 +341,269 @@ impl<'d> Table<'d, Account> {
                 }
             }
         }
+        notify(self.map.notifier, ChangeKind::Update, username);
         Ok(())
     }
+
+    /// Validates the password of the account with the given username, along
+    /// with the TOTP code and its key in `totp` if TOTP is enabled for the
+    /// account, counting consecutive failures, and locks the account
+    /// according to `policy`. A locked account fails validation regardless of
+    /// the password. A lockout is recorded as a change made by the account
+    /// itself.
+    ///
+    /// A sign-in from `address` not allowed by the allowed hosts and networks
+    /// of the account results in [`PasswordValidation::AddressNotAllowed`]
+    /// without checking the password or counting a failure. If `address` is
+    /// `None`, only accounts without allowed hosts or networks can be signed
+    /// in to.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the account does not exist, the TOTP secret cannot
+    /// be decrypted with the key, or the database operation fails.
+    pub fn validate_password(
+        &self,
+        username: &str,
+        password: &str,
+        totp: Option<(&str, &TotpKey)>,
+        policy: Option<&LockoutPolicy>,
+        address: Option<IpAddr>,
+    ) -> Result<PasswordValidation, anyhow::Error> {
+        self.modify(username, username, |account| {
+            let allowed = match address {
+                Some(addr) => account.is_signin_allowed(addr),
+                None => account.allow_access_from.is_none() && account.allowed_networks.is_none(),
+            };
+            if !allowed {
+                return Ok(PasswordValidation::AddressNotAllowed);
+            }
+            account.validate_password(password, totp, policy, Utc::now())
+        })?
+    }
+
+    /// Sets the networks sign-ins to the account with the given username are
+    /// allowed from, on behalf of `actor`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the account does not exist or the database
+    /// operation fails.
+    pub fn set_allowed_networks(
+        &self,
+        username: &str,
+        networks: Option<HostNetworkGroup>,
+        actor: &str,
+    ) -> Result<(), anyhow::Error> {
+        self.modify(username, actor, |account| {
+            account.allowed_networks.clone_from(&networks);
+        })
+    }
+
+    /// Returns `true` if sign-ins to the account with the given username from
+    /// `addr` are allowed by its allowed hosts and networks.
+    /// [`Table::validate_password`] checks this before the password.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the account does not exist or the database
+    /// operation fails.
+    pub fn is_signin_allowed(&self, username: &str, addr: IpAddr) -> Result<bool, anyhow::Error> {
+        let Some(account) = self.get(username)? else {
+            bail!("no such entry");
+        };
+        Ok(account.is_signin_allowed(addr))
+    }
+
+    /// Records a sign-in attempt to the account with the given username from
+    /// `address`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the account does not exist or the database
+    /// operation fails.
+    pub fn record_signin(
+        &self,
+        username: &str,
+        success: bool,
+        address: Option<IpAddr>,
+    ) -> Result<(), anyhow::Error> {
+        self.modify(username, username, |account| {
+            account.record_signin(success, address, Utc::now());
+        })
+    }
+
+    /// Returns the time until which the account with the given username is
+    /// locked, or `None` if it is not locked.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the account does not exist or the database
+    /// operation fails.
+    pub fn locked_until(&self, username: &str) -> Result<Option<DateTime<Utc>>, anyhow::Error> {
+        let Some(account) = self.get(username)? else {
+            bail!("no such entry");
+        };
+        Ok(account.locked_until(Utc::now()))
+    }
+
+    /// Unlocks the account with the given username and resets the count of
+    /// its failed password validations, on behalf of `actor`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the account does not exist or the database
+    /// operation fails.
+    pub fn clear_lockout(&self, username: &str, actor: &str) -> Result<(), anyhow::Error> {
+        self.modify(username, actor, Account::clear_lockout)
+    }
+
+    /// Stores the TOTP secret of the account with the given username,
+    /// encrypted with `key`, on behalf of `actor`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the account does not exist, the secret cannot be
+    /// encrypted, or the database operation fails.
+    pub fn set_totp_secret(
+        &self,
+        username: &str,
+        secret: &[u8],
+        key: &TotpKey,
+        actor: &str,
+    ) -> Result<(), anyhow::Error> {
+        self.modify(username, actor, |account| {
+            account.set_totp_secret(secret, key)
+        })?
+    }
+
+    /// Removes the TOTP secret of the account with the given username, on
+    /// behalf of `actor`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the account does not exist or the database
+    /// operation fails.
+    pub fn clear_totp(&self, username: &str, actor: &str) -> Result<(), anyhow::Error> {
+        self.modify(username, actor, Account::clear_totp)
+    }
+
+    /// Verifies the TOTP `code` of the account with the given username,
+    /// counting consecutive failures, and locks the account according to
+    /// `policy`. An accepted code cannot be used again.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the account does not exist, TOTP is not enabled
+    /// for it, the secret cannot be decrypted with `key`, or the database
+    /// operation fails.
+    pub fn verify_totp(
+        &self,
+        username: &str,
+        code: &str,
+        key: &TotpKey,
+        policy: Option<&LockoutPolicy>,
+    ) -> Result<PasswordValidation, anyhow::Error> {
+        self.modify(username, username, |account| {
+            account.verify_totp(code, key, policy, Utc::now())
+        })?
+    }
+
+    /// Returns `true` if the password of the account with the given username
+    /// is older than the maximum age in the password policy.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the account does not exist or the database
+    /// operation fails.
+    pub fn password_expired(&self, username: &str) -> Result<bool, anyhow::Error> {
+        let Some(account) = self.get(username)? else {
+            bail!("no such entry");
+        };
+        Ok(self.password_policy()?.map_or(false, |policy| {
+            account.password_expired(&policy, Utc::now())
+        }))
+    }
+
+    /// Returns the password policy stored in the database, if any.
+    fn password_policy(&self) -> Result<Option<PasswordPolicy>, anyhow::Error> {
+        let meta = Map::open(self.map.db, super::META).context("no meta table")?;
+        meta.get(super::PASSWORD_POLICY)?
+            .map(|value| super::deserialize(value.as_ref()))
+            .transpose()
+    }
+
+    /// Returns the password policy stored in the database, if any, reading it
+    /// in `txn` so that the transaction fails if the policy changes before it
+    /// is committed.
+    fn password_policy_in(
+        &self,
+        txn: &rocksdb::Transaction<OptimisticTransactionDB>,
+    ) -> Result<Option<PasswordPolicy>, anyhow::Error> {
+        let meta = self
+            .map
+            .db
+            .cf_handle(super::META)
+            .context("no meta table")?;
+        txn.get_for_update_cf(meta, super::PASSWORD_POLICY, false)
+            .context("cannot read password policy")?
+            .map(|value| super::deserialize(value.as_ref()))
+            .transpose()
+    }
+
+    /// Applies `f` to the account with the given username in a transaction on
+    /// behalf of `actor`, and returns what `f` returns.
+    fn modify<T>(
+        &self,
+        username: &str,
+        actor: &str,
+        mut f: impl FnMut(&mut Account) -> T,
+    ) -> Result<T, anyhow::Error> {
+        let key = username.as_bytes();
+        loop {
+            let txn = self.map.db.transaction();
+            let Some(old_value) = txn
+                .get_for_update_cf(self.map.cf, key, EXCLUSIVE)
+                .context("cannot read old entry")?
+            else {
+                bail!("no such entry");
+            };
+            let mut account =
+                bincode::DefaultOptions::new().deserialize::<Account>(old_value.as_ref())?;
+            let before = AccountSnapshot::from(&account);
+            let output = f(&mut account);
+            let value = bincode::DefaultOptions::new().serialize(&account)?;
+            txn.put_cf(self.map.cf, key, value)
+                .context("failed to write new entry")?;
+            account_audit::record(
+                self.map.db,
+                &txn,
+                actor,
+                username,
+                Some(before),
+                Some(AccountSnapshot::from(&account)),
+            )?;
+
+            match txn.commit() {
+                Ok(()) => {
+                    notify(self.map.notifier, ChangeKind::Update, key);
+                    return Ok(output);
+                }
+                Err(e) => {
+                    if !e.as_ref().starts_with("Resource busy:") {
+                        return Err(e).context("failed to update entry");
+                    }
+                }
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::sync::Arc;
+    use std::{sync::Arc, time::Duration};
 
-    use crate::{types::Account, Direction, Role, Store};
+    use crate::{types::Account, Direction, ExportedAccount, PasswordPolicy, Role, Store};
 
     #[test]
     fn put_delete() {
@@ -167,7 +623,7 @@ mod tests {
             None,
         )
         .unwrap();
-        table.put(&acc1).unwrap();
+        table.put(&acc1, "admin").unwrap();
         assert!(table.contains("user1").unwrap());
 
         let acc2 = Account::new(
@@ -180,10 +636,10 @@ mod tests {
             None,
         )
         .unwrap();
-        table.put(&acc2).unwrap();
+        table.put(&acc2, "admin").unwrap();
         assert!(table.contains("user2").unwrap());
 
-        table.delete("user1").unwrap();
+        table.delete("user1", "admin").unwrap();
         assert!(!table.contains("user1").unwrap());
     }
 
@@ -209,7 +665,7 @@ mod tests {
             None,
         )
         .unwrap();
-        table.put(&acc1).unwrap();
+        table.put(&acc1, "admin").unwrap();
 
         let mut iter = table.iter(Direction::Forward, None);
         let acc = iter.next().unwrap().unwrap();
@@ -226,7 +682,7 @@ mod tests {
             None,
         )
         .unwrap();
-        table.put(&acc2).unwrap();
+        table.put(&acc2, "admin").unwrap();
 
         let mut iter = table.iter(Direction::Forward, Some(b"user2"));
         let acc = iter.next().unwrap().unwrap();
@@ -240,4 +696,181 @@ mod tests {
         let acc = iter.next().unwrap().unwrap();
         assert_eq!(acc.username, "user1");
     }
+
+    #[test]
+    fn password_policy() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(Store::new(db_dir.path(), backup_dir.path()).unwrap());
+        let table = store.account_map();
+
+        let account = Account::new(
+            "user1",
+            "password",
+            Role::SystemAdministrator,
+            "User 1".to_string(),
+            "Department 1".to_string(),
+            None,
+            None,
+        )
+        .unwrap();
+        table.put(&account, "admin").unwrap();
+
+        let update = |password: &str| {
+            table.update(
+                "admin",
+                b"user1",
+                &Some(password.to_string()),
+                None,
+                &None,
+                &None,
+                &None,
+                &None,
+                &None,
+            )
+        };
+        assert!(update("short").is_ok());
+
+        store
+            .set_password_policy(&PasswordPolicy {
+                min_length: 8,
+                history: 2,
+                max_age: Some(Duration::from_secs(3600)),
+                ..PasswordPolicy::default()
+            })
+            .unwrap();
+        assert!(update("short").is_err());
+        assert!(update("password").is_err());
+        assert!(update("new password").is_ok());
+        assert!(table
+            .get("user1")
+            .unwrap()
+            .unwrap()
+            .verify_password("new password"));
+        assert!(!store.password_expired("user1").unwrap());
+        assert!(store.password_expired("user2").is_err());
+
+        let new_account = |password: &str| {
+            Account::new(
+                "user2",
+                password,
+                Role::SecurityMonitor,
+                String::new(),
+                String::new(),
+                None,
+                None,
+            )
+            .unwrap()
+        };
+        assert!(table.insert(&new_account("short"), "admin").is_err());
+        assert!(table.put(&new_account("short"), "admin").is_err());
+        assert!(!table.contains("user2").unwrap());
+        table
+            .insert(&new_account("long password"), "admin")
+            .unwrap();
+
+        // An account read from the database is stored without a check, unless
+        // its password is changed.
+        let mut account = table.get("user2").unwrap().unwrap();
+        table.put(&account, "admin").unwrap();
+        account.update_password("short").unwrap();
+        assert!(table.put(&account, "admin").is_err());
+        assert!(table
+            .get("user2")
+            .unwrap()
+            .unwrap()
+            .verify_password("long password"));
+
+        // A password kept in the history cannot be reused through `put`.
+        let mut account = table.get("user2").unwrap().unwrap();
+        account.update_password("other password").unwrap();
+        table.put(&account, "admin").unwrap();
+        let mut account = table.get("user2").unwrap().unwrap();
+        account.update_password("long password").unwrap();
+        assert!(table.put(&account, "admin").is_err());
+        let mut account = table.get("user2").unwrap().unwrap();
+        account.update_password("other password").unwrap();
+        assert!(table.put(&account, "admin").is_err());
+        assert!(table
+            .get("user2")
+            .unwrap()
+            .unwrap()
+            .verify_password("other password"));
+    }
+
+    #[test]
+    fn import_export() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(Store::new(db_dir.path(), backup_dir.path()).unwrap());
+        let table = store.account_map();
+
+        let account = |username: &str| {
+            Account::new(
+                username,
+                "password",
+                Role::SecurityMonitor,
+                String::new(),
+                String::new(),
+                Some(vec!["10.0.0.1".parse().unwrap()]),
+                None,
+            )
+            .unwrap()
+        };
+        table.put(&account("user2"), "admin").unwrap();
+
+        assert!(table
+            .import(&[account("user1"), account("user1")], "admin")
+            .is_err());
+        assert!(table
+            .import(&[account("user1"), account("user2")], "admin")
+            .is_err());
+        assert!(!table.contains("user1").unwrap());
+
+        table
+            .import(&[account("user1"), account("user3")], "admin")
+            .unwrap();
+        let exported = table.export().unwrap();
+        let usernames: Vec<_> = exported.iter().map(|a| a.username.as_str()).collect();
+        assert_eq!(usernames, ["user1", "user2", "user3"]);
+        assert_eq!(
+            exported[0].allow_access_from,
+            Some(vec!["10.0.0.1".parse().unwrap()])
+        );
+
+        let imported = exported[0].clone().into_account("new password").unwrap();
+        assert!(imported.verify_password("new password"));
+        assert_eq!(imported.creation_time(), exported[0].creation_time);
+
+        // An account violating the password policy fails the whole import,
+        // and nothing is recorded in the audit table.
+        store
+            .set_password_policy(&PasswordPolicy {
+                min_length: 10,
+                ..PasswordPolicy::default()
+            })
+            .unwrap();
+        let strong = ExportedAccount {
+            username: "user4".to_string(),
+            ..exported[1].clone()
+        };
+        assert!(table
+            .import(
+                &[
+                    strong.clone().into_account("long password").unwrap(),
+                    account("user5")
+                ],
+                "admin"
+            )
+            .is_err());
+        assert!(!table.contains("user4").unwrap());
+        let start = imported.creation_time() - chrono::Duration::days(1);
+        let end = chrono::Utc::now() + chrono::Duration::seconds(1);
+        let audit = store.account_audit_map();
+        assert!(audit.by_account("user4", start..end).unwrap().is_empty());
+        table
+            .import(&[strong.into_account("long password").unwrap()], "admin")
+            .unwrap();
+        assert_eq!(audit.by_account("user4", start..end).unwrap().len(), 1);
+    }
 }
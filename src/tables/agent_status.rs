@@ -0,0 +1,200 @@
+//! The `agent status` table.
+
+use std::borrow::Cow;
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use rocksdb::OptimisticTransactionDB;
+use serde::{Deserialize, Serialize};
+
+use crate::{types::FromKeyValue, Direction, Iterable, Map, Table, UniqueKey};
+
+/// The resources used by an agent, as reported in its heartbeat.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct ResourceUsage {
+    /// The CPU usage in percent.
+    pub cpu_usage: f32,
+    /// The total and used memory in bytes.
+    pub total_memory: u64,
+    pub used_memory: u64,
+    /// The total and used disk space in bytes.
+    pub total_disk_space: u64,
+    pub used_disk_space: u64,
+}
+
+/// The status of an agent, updated by its heartbeats.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct AgentStatus {
+    /// The identifier of the agent, unique across nodes.
+    pub agent_id: String,
+    /// The ID of the node the agent runs on, if known.
+    pub node_id: Option<u32>,
+    pub version: String,
+    pub last_heartbeat: DateTime<Utc>,
+    pub resource_usage: ResourceUsage,
+}
+
+impl AgentStatus {
+    /// Returns `true` if the agent hasn't sent a heartbeat for `duration` at
+    /// `now`.
+    #[must_use]
+    pub fn is_stale(&self, duration: Duration, now: DateTime<Utc>) -> bool {
+        self.last_heartbeat + duration <= now
+    }
+}
+
+impl FromKeyValue for AgentStatus {
+    fn from_key_value(_key: &[u8], value: &[u8]) -> Result<Self> {
+        super::deserialize(value)
+    }
+}
+
+impl UniqueKey for AgentStatus {
+    fn unique_key(&self) -> Cow<[u8]> {
+        Cow::Borrowed(self.agent_id.as_bytes())
+    }
+}
+
+impl super::Value for AgentStatus {
+    fn value(&self) -> Cow<[u8]> {
+        let Ok(value) = super::serialize(self) else {
+            unreachable!("serialization into memory should never fail")
+        };
+        Cow::Owned(value)
+    }
+}
+
+/// Functions for the `agent status` table.
+impl<'d> Table<'d, AgentStatus> {
+    /// Opens the `agent status` table in the database.
+    ///
+    /// Returns `None` if the table does not exist.
+    pub(super) fn open(db: &'d OptimisticTransactionDB) -> Option<Self> {
+        Map::open(db, super::AGENT_STATUS).map(Table::new)
+    }
+
+    /// Records a heartbeat of the agent with `agent_id` received now, and
+    /// returns the updated status.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails.
+    pub fn heartbeat(
+        &self,
+        agent_id: &str,
+        node_id: Option<u32>,
+        version: &str,
+        resource_usage: ResourceUsage,
+    ) -> Result<AgentStatus> {
+        let status = AgentStatus {
+            agent_id: agent_id.to_string(),
+            node_id,
+            version: version.to_string(),
+            last_heartbeat: Utc::now(),
+            resource_usage,
+        };
+        self.put(&status)?;
+        Ok(status)
+    }
+
+    /// Returns the status of the agent with `agent_id`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails or the status is
+    /// invalid.
+    pub fn get(&self, agent_id: &str) -> Result<Option<AgentStatus>> {
+        self.map
+            .get(agent_id.as_bytes())?
+            .map(|value| super::deserialize(value.as_ref()))
+            .transpose()
+    }
+
+    /// Returns the statuses of the agents running on the node with `node_id`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails or any status is
+    /// invalid.
+    pub fn by_node(&self, node_id: u32) -> Result<Vec<AgentStatus>> {
+        self.filter(|status| status.node_id == Some(node_id))
+    }
+
+    /// Returns the statuses of the agents that haven't sent a heartbeat for
+    /// `duration`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails or any status is
+    /// invalid.
+    pub fn not_seen_for(&self, duration: Duration) -> Result<Vec<AgentStatus>> {
+        let now = Utc::now();
+        self.filter(|status| status.is_stale(duration, now))
+    }
+
+    /// Removes the status of the agent with `agent_id`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails.
+    pub fn remove(&self, agent_id: &str) -> Result<()> {
+        self.map.delete(agent_id.as_bytes())
+    }
+
+    fn filter(&self, predicate: impl Fn(&AgentStatus) -> bool) -> Result<Vec<AgentStatus>> {
+        let mut statuses = Vec::new();
+        for status in self.iter(Direction::Forward, None) {
+            let status = status?;
+            if predicate(&status) {
+                statuses.push(status);
+            }
+        }
+        Ok(statuses)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::{Duration, Utc};
+
+    use super::ResourceUsage;
+    use crate::Store;
+
+    #[test]
+    fn heartbeat() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(Store::new(db_dir.path(), backup_dir.path()).unwrap());
+        let table = store.agent_status_map();
+
+        let usage = ResourceUsage {
+            cpu_usage: 12.5,
+            total_memory: 1024,
+            used_memory: 512,
+            ..ResourceUsage::default()
+        };
+        let status = table
+            .heartbeat("piglet@node1", Some(1), "0.1.0", usage.clone())
+            .unwrap();
+        table
+            .heartbeat("hog@node2", Some(2), "0.2.0", ResourceUsage::default())
+            .unwrap();
+        assert_eq!(table.get("piglet@node1").unwrap(), Some(status.clone()));
+        assert_eq!(table.by_node(1).unwrap(), [status.clone()]);
+
+        assert!(table.not_seen_for(Duration::minutes(5)).unwrap().is_empty());
+        assert_eq!(table.not_seen_for(Duration::zero()).unwrap().len(), 2);
+        assert!(status.is_stale(Duration::minutes(5), Utc::now() + Duration::minutes(6)));
+
+        let updated = table
+            .heartbeat("piglet@node1", Some(1), "0.1.1", usage)
+            .unwrap();
+        assert_eq!(table.get("piglet@node1").unwrap().unwrap().version, "0.1.1");
+        assert!(updated.last_heartbeat >= status.last_heartbeat);
+
+        table.remove("hog@node2").unwrap();
+        assert!(table.get("hog@node2").unwrap().is_none());
+    }
+}
@@ -0,0 +1,434 @@
+//! The `api keys` table.
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Utc};
+use data_encoding::{BASE64URL_NOPAD, HEXLOWER};
+use ring::{
+    digest,
+    rand::{SecureRandom, SystemRandom},
+};
+use rocksdb::{IteratorMode, OptimisticTransactionDB, Transaction};
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+
+use crate::{
+    notification::{notify, ChangeKind},
+    types::{Account, FromKeyValue},
+    Direction, Iterable, Map, Table, EXCLUSIVE,
+};
+
+const ID_LEN: usize = 8;
+const SECRET_LEN: usize = 32;
+
+/// An API key bound to an account.
+///
+/// Only the hash of the secret part of the key is stored; the key itself is
+/// returned once, when it is generated.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ApiKey {
+    /// The public identifier of the key, the part before the dot.
+    pub id: String,
+    pub username: String,
+    /// The operations the key is allowed to perform.
+    pub scopes: Vec<String>,
+    pub creation_time: DateTime<Utc>,
+    /// When the key expires. If `None`, it never expires.
+    pub expiration_time: Option<DateTime<Utc>>,
+    hash: Vec<u8>,
+}
+
+impl ApiKey {
+    /// Returns `true` if the key is allowed to perform `scope`.
+    #[must_use]
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == scope)
+    }
+
+    /// Returns `true` if the key has expired at `now`.
+    #[must_use]
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expiration_time.map_or(false, |t| t <= now)
+    }
+}
+
+impl FromKeyValue for ApiKey {
+    fn from_key_value(_key: &[u8], value: &[u8]) -> Result<Self> {
+        super::deserialize(value)
+    }
+}
+
+fn hash(secret: &[u8]) -> Vec<u8> {
+    digest::digest(&digest::SHA256, secret).as_ref().to_vec()
+}
+
+/// Returns the key of `id` of `username` in the `api key index` table.
+fn index_key(username: &str, id: &str) -> Vec<u8> {
+    let mut key = index_prefix(username);
+    key.extend(id.as_bytes());
+    key
+}
+
+fn index_prefix(username: &str) -> Vec<u8> {
+    let mut prefix = username.as_bytes().to_vec();
+    prefix.push(0);
+    prefix
+}
+
+/// Deletes all the API keys of `username` in `txn`, and returns their IDs.
+///
+/// # Errors
+///
+/// Returns an error if the database operation fails.
+pub(super) fn delete_all_for(
+    db: &OptimisticTransactionDB,
+    txn: &Transaction<OptimisticTransactionDB>,
+    username: &str,
+) -> Result<Vec<String>> {
+    let keys = db.cf_handle(super::API_KEYS).context("no API keys table")?;
+    let index = db
+        .cf_handle(super::API_KEY_INDEX)
+        .context("no API key index table")?;
+    let prefix = index_prefix(username);
+    let mut readopts = rocksdb::ReadOptions::default();
+    readopts.set_iterate_range(rocksdb::PrefixRange(prefix.as_slice()));
+    let mut ids = Vec::new();
+    for entry in txn.iterator_cf_opt(index, readopts, IteratorMode::Start) {
+        let (index_key, _) = entry.context("cannot read API key index")?;
+        let id = String::from_utf8_lossy(&index_key[prefix.len()..]).into_owned();
+        txn.delete_cf(keys, id.as_bytes())
+            .context("failed to delete API key")?;
+        txn.delete_cf(index, &index_key)
+            .context("failed to delete API key index")?;
+        ids.push(id);
+    }
+    Ok(ids)
+}
+
+/// Functions for the `api keys` table.
+///
+/// The keys of each account are also listed in the `api key index` table, so
+/// that they can be found without scanning all the keys. When an account is
+/// deleted, its keys are deleted with it.
+impl<'d> Table<'d, ApiKey> {
+    /// Opens the `api keys` table in the database.
+    ///
+    /// Returns `None` if the table does not exist.
+    pub(super) fn open(db: &'d OptimisticTransactionDB) -> Option<Self> {
+        Map::open(db, super::API_KEYS).map(Table::new)
+    }
+
+    /// Generates a new API key for `username` with `scopes`, and returns the
+    /// stored record and the key. The key cannot be retrieved later.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the account does not exist, or random number
+    /// generation or the database operation fails.
+    pub fn generate(
+        &self,
+        username: &str,
+        scopes: Vec<String>,
+        expiration_time: Option<DateTime<Utc>>,
+    ) -> Result<(ApiKey, String)> {
+        let rng = SystemRandom::new();
+        let mut id = [0_u8; ID_LEN];
+        rng.fill(&mut id)?;
+        let mut secret = [0_u8; SECRET_LEN];
+        rng.fill(&mut secret)?;
+        let secret = BASE64URL_NOPAD.encode(&secret);
+
+        let record = ApiKey {
+            id: HEXLOWER.encode(&id),
+            username: username.to_string(),
+            scopes,
+            creation_time: Utc::now(),
+            expiration_time,
+            hash: hash(secret.as_bytes()),
+        };
+        let value = super::serialize(&record)?;
+        let accounts = self
+            .map
+            .db
+            .cf_handle(super::ACCOUNTS)
+            .context("no accounts table")?;
+        let index = self
+            .map
+            .db
+            .cf_handle(super::API_KEY_INDEX)
+            .context("no API key index table")?;
+        loop {
+            let txn = self.map.db.transaction();
+            // Reading the account for update makes the transaction fail if
+            // the account is deleted at the same time.
+            if txn
+                .get_for_update_cf(accounts, username.as_bytes(), EXCLUSIVE)
+                .context("cannot read account")?
+                .is_none()
+            {
+                bail!("no such account: {username}");
+            }
+            if txn
+                .get_for_update_cf(self.map.cf, record.id.as_bytes(), EXCLUSIVE)
+                .context("cannot read old entry")?
+                .is_some()
+            {
+                bail!("API key already exists: {}", record.id);
+            }
+            txn.put_cf(self.map.cf, record.id.as_bytes(), &value)
+                .context("failed to write new entry")?;
+            txn.put_cf(index, index_key(username, &record.id), [])
+                .context("failed to write API key index")?;
+
+            match txn.commit() {
+                Ok(()) => break,
+                Err(e) => {
+                    if !e.as_ref().starts_with("Resource busy:") {
+                        return Err(e).context("failed to generate API key");
+                    }
+                }
+            }
+        }
+        notify(self.map.notifier, ChangeKind::Insert, record.id.as_bytes());
+        let key = format!("{}.{secret}", record.id);
+        Ok((record, key))
+    }
+
+    /// Returns the record of `key` if the key is valid, has not expired, and
+    /// is allowed to perform `scope`, if given. A key is not valid if its
+    /// account does not exist or is locked.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails or the account is
+    /// invalid.
+    pub fn validate(&self, key: &str, scope: Option<&str>) -> Result<Option<ApiKey>> {
+        let Some((id, secret)) = key.split_once('.') else {
+            return Ok(None);
+        };
+        let Some(record) = self.get(id)? else {
+            return Ok(None);
+        };
+        let now = Utc::now();
+        if !bool::from(record.hash.ct_eq(&hash(secret.as_bytes())))
+            || record.is_expired(now)
+            || scope.map_or(false, |scope| !record.has_scope(scope))
+        {
+            return Ok(None);
+        }
+        let accounts = Map::open(self.map.db, super::ACCOUNTS).context("no accounts table")?;
+        let Some(account) = accounts.get(record.username.as_bytes())? else {
+            return Ok(None);
+        };
+        let account: Account = super::deserialize(account.as_ref())?;
+        if account.locked_until(now).is_some() {
+            return Ok(None);
+        }
+        Ok(Some(record))
+    }
+
+    /// Returns the API key with `id`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails or the record is
+    /// invalid.
+    pub fn get(&self, id: &str) -> Result<Option<ApiKey>> {
+        self.map
+            .get(id.as_bytes())?
+            .map(|value| super::deserialize(value.as_ref()))
+            .transpose()
+    }
+
+    /// Returns the API keys of `username`, or all the API keys if `username`
+    /// is `None`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails or any record is
+    /// invalid.
+    pub fn list(&self, username: Option<&str>) -> Result<Vec<ApiKey>> {
+        let Some(username) = username else {
+            return self.iter(Direction::Forward, None).collect();
+        };
+        let index = Map::open(self.map.db, super::API_KEY_INDEX).context("no API key index")?;
+        let prefix = index_prefix(username);
+        let mut keys = Vec::new();
+        for (index_key, _) in index.inner_prefix_iterator(IteratorMode::Start, &prefix) {
+            let id = String::from_utf8_lossy(&index_key[prefix.len()..]);
+            let key = self
+                .get(&id)?
+                .ok_or_else(|| anyhow!("API key index refers to missing key: {id}"))?;
+            keys.push(key);
+        }
+        Ok(keys)
+    }
+
+    /// Revokes the API key with `id`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the key does not exist or the database operation
+    /// fails.
+    pub fn revoke(&self, id: &str) -> Result<()> {
+        let index = self
+            .map
+            .db
+            .cf_handle(super::API_KEY_INDEX)
+            .context("no API key index table")?;
+        loop {
+            let txn = self.map.db.transaction();
+            let Some(value) = txn
+                .get_for_update_cf(self.map.cf, id.as_bytes(), EXCLUSIVE)
+                .context("cannot read old entry")?
+            else {
+                bail!("no such API key: {id}");
+            };
+            let record: ApiKey = super::deserialize(&value)?;
+            txn.delete_cf(self.map.cf, id.as_bytes())
+                .context("failed to delete entry")?;
+            txn.delete_cf(index, index_key(&record.username, id))
+                .context("failed to delete API key index")?;
+
+            match txn.commit() {
+                Ok(()) => break,
+                Err(e) => {
+                    if !e.as_ref().starts_with("Resource busy:") {
+                        return Err(e).context("failed to revoke API key");
+                    }
+                }
+            }
+        }
+        notify(self.map.notifier, ChangeKind::Remove, id.as_bytes());
+        Ok(())
+    }
+
+    /// Revokes all the API keys of `username`, and returns the number of keys
+    /// revoked.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails.
+    pub fn revoke_all_for(&self, username: &str) -> Result<usize> {
+        let ids = loop {
+            let txn = self.map.db.transaction();
+            let ids = delete_all_for(self.map.db, &txn, username)?;
+            match txn.commit() {
+                Ok(()) => break ids,
+                Err(e) => {
+                    if !e.as_ref().starts_with("Resource busy:") {
+                        return Err(e).context("failed to revoke API keys");
+                    }
+                }
+            }
+        };
+        for id in &ids {
+            notify(self.map.notifier, ChangeKind::Remove, id.as_bytes());
+        }
+        Ok(ids.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::{Duration, Utc};
+
+    use crate::{types::Account, LockoutPolicy, Role, Store};
+
+    fn account(username: &str) -> Account {
+        Account::new(
+            username,
+            "password",
+            Role::SecurityMonitor,
+            String::new(),
+            String::new(),
+            None,
+            None,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn generate_validate_revoke() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(Store::new(db_dir.path(), backup_dir.path()).unwrap());
+        let table = store.api_key_map();
+        store
+            .account_map()
+            .insert(&account("user1"), "admin")
+            .unwrap();
+        store
+            .account_map()
+            .insert(&account("user2"), "admin")
+            .unwrap();
+        assert!(table.generate("user3", vec![], None).is_err());
+
+        let (record, key) = table
+            .generate("user1", vec!["events:read".to_string()], None)
+            .unwrap();
+        assert!(key.starts_with(&format!("{}.", record.id)));
+        assert_eq!(table.validate(&key, None).unwrap(), Some(record.clone()));
+        assert!(table.validate(&key, Some("events:read")).unwrap().is_some());
+        assert!(table
+            .validate(&key, Some("accounts:write"))
+            .unwrap()
+            .is_none());
+        assert!(table
+            .validate(&format!("{}.wrong", record.id), None)
+            .unwrap()
+            .is_none());
+        assert!(table.validate("malformed", None).unwrap().is_none());
+
+        let expired = Some(Utc::now() - Duration::minutes(1));
+        let (_, expired_key) = table.generate("user1", vec![], expired).unwrap();
+        assert!(table.validate(&expired_key, None).unwrap().is_none());
+        table.generate("user2", vec![], None).unwrap();
+
+        assert_eq!(table.list(None).unwrap().len(), 3);
+        assert_eq!(table.list(Some("user1")).unwrap().len(), 2);
+
+        table.revoke(&record.id).unwrap();
+        assert!(table.validate(&key, None).unwrap().is_none());
+        assert!(table.revoke(&record.id).is_err());
+        assert_eq!(table.revoke_all_for("user1").unwrap(), 1);
+        assert_eq!(table.list(None).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn account_state() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(Store::new(db_dir.path(), backup_dir.path()).unwrap());
+        let table = store.api_key_map();
+        store
+            .account_map()
+            .insert(&account("user1"), "admin")
+            .unwrap();
+        store
+            .account_map()
+            .insert(&account("user2"), "admin")
+            .unwrap();
+        let (_, key1) = table.generate("user1", vec![], None).unwrap();
+        let (_, key2) = table.generate("user2", vec![], None).unwrap();
+
+        store
+            .set_lockout_policy(&LockoutPolicy {
+                max_failures: 1,
+                duration: std::time::Duration::from_secs(3600),
+            })
+            .unwrap();
+        store
+            .validate_password("user1", "wrong", None, None)
+            .unwrap();
+        assert!(table.validate(&key1, None).unwrap().is_none());
+        store.account_map().clear_lockout("user1", "admin").unwrap();
+        assert!(table.validate(&key1, None).unwrap().is_some());
+
+        store.account_map().delete("user2", "admin").unwrap();
+        assert!(table.validate(&key2, None).unwrap().is_none());
+        assert!(table.list(Some("user2")).unwrap().is_empty());
+        assert_eq!(table.list(None).unwrap().len(), 1);
+    }
+}
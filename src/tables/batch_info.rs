@@ -1,9 +1,15 @@
 //! The `batch_info` table.
 
+use std::{collections::HashSet, ops::Range};
+
 use anyhow::Result;
 use rocksdb::{IteratorMode, OptimisticTransactionDB};
 
-use crate::{batch_info::BatchInfo, types::FromKeyValue, Map, Table};
+use crate::{
+    batch_info::BatchInfo,
+    types::{FromKeyValue, ModelBatchInfo},
+    Map, Table,
+};
 
 impl FromKeyValue for BatchInfo {
     fn from_key_value(_key: &[u8], value: &[u8]) -> Result<Self> {
@@ -36,6 +42,33 @@ impl<'d> Table<'d, crate::batch_info::BatchInfo> {
         Ok(batch_info)
     }
 
+    /// Returns the `batch_info`s of the model whose time ranges,
+    /// `[earliest, latest]`, overlap with `range`, in the order of `earliest`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails.
+    pub fn get_overlapping(&self, model: i32, range: Range<i64>) -> Result<Vec<BatchInfo>> {
+        let mut batch_info: Vec<_> = self
+            .get_all_for(model)?
+            .into_iter()
+            .filter(|b| b.inner.earliest < range.end && b.inner.latest >= range.start)
+            .collect();
+        batch_info.sort_by_key(|b| (b.inner.earliest, b.inner.latest));
+        Ok(batch_info)
+    }
+
+    /// Returns the time ranges in `range` not covered by any batch of the
+    /// model, in chronological order. A batch covers `[earliest, latest]`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails.
+    pub fn gaps(&self, model: i32, range: Range<i64>) -> Result<Vec<Range<i64>>> {
+        let batch_info = self.get_overlapping(model, range.clone())?;
+        Ok(gaps(batch_info.iter().map(|b| &b.inner), range))
+    }
+
     /// Returns the count of `batch_info` with the given model id.
     ///
     /// # Errors
@@ -86,6 +119,42 @@ impl<'d> Table<'d, crate::batch_info::BatchInfo> {
         }
         Ok(deleted)
     }
+
+    /// Deletes all `batch_info`s of the models not in `models`, and returns
+    /// the number of `batch_info`s deleted.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any of database operation fails.
+    pub fn delete_orphans(&self, models: &HashSet<i32>) -> Result<usize> {
+        super::delete_orphans(&self.map, models, super::model_prefix)
+    }
+}
+
+/// Returns the parts of `range` not covered by `batches`, which are sorted
+/// by `earliest`.
+fn gaps<'a>(
+    batches: impl Iterator<Item = &'a ModelBatchInfo>,
+    range: Range<i64>,
+) -> Vec<Range<i64>> {
+    let mut gaps = Vec::new();
+    if range.is_empty() {
+        return gaps;
+    }
+    let mut covered_until = range.start;
+    for batch in batches {
+        if batch.earliest > covered_until {
+            gaps.push(covered_until..batch.earliest.min(range.end));
+        }
+        covered_until = covered_until.max(batch.latest.saturating_add(1));
+        if covered_until >= range.end {
+            return gaps;
+        }
+    }
+    if covered_until < range.end {
+        gaps.push(covered_until..range.end);
+    }
+    gaps
 }
 
 #[cfg(test)]
@@ -190,4 +259,39 @@ mod tests {
         let entry = table.get(2, 321).unwrap();
         assert!(entry.is_none());
     }
+
+    #[test]
+    fn overlapping_and_gaps() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(Store::new(db_dir.path(), backup_dir.path()).unwrap());
+        let table = store.batch_info_map();
+
+        for (id, earliest, latest) in [(1, 10, 19), (2, 20, 29), (3, 40, 49), (4, 45, 59)] {
+            let inner = ModelBatchInfo {
+                id,
+                earliest,
+                latest,
+                size: 1,
+                sources: vec![],
+            };
+            table.put(&BatchInfo::new(1, inner)).unwrap();
+        }
+
+        let ids = |range| {
+            table
+                .get_overlapping(1, range)
+                .unwrap()
+                .into_iter()
+                .map(|b| b.inner.id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(25..45), vec![2, 3, 4]);
+        assert_eq!(ids(30..40), Vec::<i64>::new());
+
+        assert_eq!(table.gaps(1, 0..100).unwrap(), vec![0..10, 30..40, 60..100]);
+        assert_eq!(table.gaps(1, 12..50).unwrap(), vec![30..40]);
+        assert!(table.gaps(1, 42..58).unwrap().is_empty());
+        assert_eq!(table.gaps(2, 0..10).unwrap(), vec![0..10]);
+    }
 }
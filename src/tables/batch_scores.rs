@@ -0,0 +1,173 @@
+//! The `batch scores` table.
+
+use std::collections::HashSet;
+
+use anyhow::{anyhow, Context, Result};
+use rocksdb::{IteratorMode, OptimisticTransactionDB};
+
+use crate::{
+    metrics::{record, Operation},
+    scores::BatchScores,
+    types::FromKeyValue,
+    Map, Table,
+};
+
+impl FromKeyValue for BatchScores {
+    fn from_key_value(key: &[u8], value: &[u8]) -> Result<Self> {
+        let (Some(model), Some(batch)) = (key.get(..4), key.get(4..12)) else {
+            return Err(anyhow!("invalid key length: {}", key.len()));
+        };
+        let model = i32::from_be_bytes(model.try_into().expect("4 bytes"));
+        let batch = i64::from_be_bytes(batch.try_into().expect("8 bytes"));
+        Ok(BatchScores::new(model, batch, super::deserialize(value)?))
+    }
+}
+
+impl<'d> Table<'d, BatchScores> {
+    /// Opens the batch scores table in the database.
+    ///
+    /// Returns `None` if the table does not exist.
+    pub(super) fn open(db: &'d OptimisticTransactionDB) -> Option<Self> {
+        Map::open(db, super::BATCH_SCORES).map(Table::new)
+    }
+
+    /// Returns the scores of the model in the batch.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails or the scores cannot
+    /// be deserialized.
+    pub fn get(&self, model: i32, batch: i64) -> Result<Option<BatchScores>> {
+        let mut key = model.to_be_bytes().to_vec();
+        key.extend(batch.to_be_bytes());
+        let Some(value) = self.map.get(&key)? else {
+            return Ok(None);
+        };
+        Ok(Some(BatchScores::new(
+            model,
+            batch,
+            super::deserialize(value.as_ref())?,
+        )))
+    }
+
+    /// Returns the IDs of the models with scores, in the order of their keys.
+    ///
+    /// Only one seek is made per model, and no scores are deserialized.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails.
+    pub fn models(&self) -> Result<Vec<i32>> {
+        record(self.map.notifier, Operation::Scan);
+        let mut models = Vec::new();
+        let mut iter = self.map.db.raw_iterator_cf(self.map.cf);
+        iter.seek_to_first();
+        while let Some(key) = iter.key() {
+            let Some(prefix) = key.get(..4) else {
+                iter.next();
+                continue;
+            };
+            let prefix: [u8; 4] = prefix.try_into().expect("4 bytes");
+            models.push(i32::from_be_bytes(prefix));
+            // Skips the rest of the batches of the model.
+            let Some(next) = u32::from_be_bytes(prefix).checked_add(1) else {
+                break;
+            };
+            iter.seek(next.to_be_bytes());
+        }
+        iter.status().context("cannot read batch scores")?;
+        Ok(models)
+    }
+
+    /// Returns the scores of the model in all the batches, in the order of
+    /// the batches.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails or any scores cannot
+    /// be deserialized.
+    pub fn get_all_for(&self, model: i32) -> Result<Vec<BatchScores>> {
+        let prefix = model.to_be_bytes();
+        self.map
+            .inner_prefix_iterator(IteratorMode::Start, &prefix)
+            .map(|(k, v)| BatchScores::from_key_value(&k, &v))
+            .collect()
+    }
+
+    /// Deletes the scores of the model in all but the latest `rounds`
+    /// batches, and returns the number of batches deleted.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any of deletion operation fails.
+    pub fn prune(&self, model: i32, rounds: usize) -> Result<usize> {
+        let prefix = model.to_be_bytes();
+        let keys: Vec<_> = self
+            .map
+            .inner_prefix_iterator(IteratorMode::Start, &prefix)
+            .map(|(k, _v)| k)
+            .collect();
+        let expired = keys.len().saturating_sub(rounds);
+        self.map.delete_many(&keys[..expired])?;
+        Ok(expired)
+    }
+
+    /// Deletes the scores of the model in all the batches, and returns the
+    /// number of batches deleted.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any of deletion operation fails.
+    pub fn delete_all_for(&self, model: i32) -> Result<usize> {
+        self.prune(model, 0)
+    }
+
+    /// Deletes the scores of the models not in `models`, and returns the
+    /// number of batches deleted.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any of database operation fails.
+    pub fn delete_orphans(&self, models: &HashSet<i32>) -> Result<usize> {
+        super::delete_orphans(&self.map, models, super::model_prefix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{BatchScores, Store};
+
+    #[test]
+    fn prune() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(Store::new(db_dir.path(), backup_dir.path()).unwrap());
+        let table = store.batch_scores_map();
+
+        for batch in [30, 10, 20] {
+            let scores = [(1, 0.5)].into_iter().collect();
+            table.put(&BatchScores::new(1, batch, scores)).unwrap();
+        }
+        table
+            .put(&BatchScores::new(2, 10, [(1, 0.7)].into_iter().collect()))
+            .unwrap();
+        assert_eq!(table.get(1, 20).unwrap().map(|s| s.scores[&1]), Some(0.5));
+        assert_eq!(table.models().unwrap(), vec![1, 2]);
+
+        assert_eq!(table.prune(1, 5).unwrap(), 0);
+        assert_eq!(table.prune(1, 2).unwrap(), 1);
+        let batches: Vec<_> = table
+            .get_all_for(1)
+            .unwrap()
+            .into_iter()
+            .map(|s| s.batch)
+            .collect();
+        assert_eq!(batches, vec![20, 30]);
+
+        assert_eq!(table.delete_all_for(1).unwrap(), 2);
+        assert!(table.get_all_for(1).unwrap().is_empty());
+        assert_eq!(table.get_all_for(2).unwrap().len(), 1);
+    }
+}
@@ -3,12 +3,14 @@
 use std::borrow::Cow;
 
 use anyhow::Result;
-use rocksdb::OptimisticTransactionDB;
+use chrono::{DateTime, Utc};
+use rocksdb::{OptimisticTransactionDB, Transaction};
 use serde::{Deserialize, Serialize};
 
+use super::network_hits;
 use crate::{
-    types::FromKeyValue, HostNetworkGroup, Indexable, Indexed, IndexedMap, IndexedMapUpdate,
-    IndexedTable,
+    types::FromKeyValue, Direction, HostNetworkGroup, Indexable, Indexed, IndexedMap,
+    IndexedMapUpdate, IndexedTable, Iterable, NetworkListKind,
 };
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
@@ -17,6 +19,41 @@ pub struct BlockNetwork {
     pub name: String,
     pub networks: HostNetworkGroup,
     pub description: String,
+    /// When the entry expires. `None` if it never expires.
+    pub expiration_time: Option<DateTime<Utc>>,
+}
+
+/// An entry in the format used until 0.26.
+#[derive(Deserialize, Serialize)]
+struct BlockNetworkV0_26 {
+    id: u32,
+    name: String,
+    networks: HostNetworkGroup,
+    description: String,
+}
+
+impl BlockNetwork {
+    /// Deserializes an entry stored in the format used until 0.26.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `value` is not an entry in the old format.
+    pub(crate) fn from_v0_26(value: &[u8]) -> Result<Self> {
+        let old: BlockNetworkV0_26 = super::deserialize(value)?;
+        Ok(Self {
+            id: old.id,
+            name: old.name,
+            networks: old.networks,
+            description: old.description,
+            expiration_time: None,
+        })
+    }
+
+    /// Returns `true` if the entry has expired at `now`.
+    #[must_use]
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expiration_time.is_some_and(|time| time <= now)
+    }
 }
 
 impl FromKeyValue for BlockNetwork {
@@ -45,12 +82,23 @@ impl Indexable for BlockNetwork {
     fn set_index(&mut self, index: u32) {
         self.id = index;
     }
+
+    /// Removes the hits of the entry.
+    fn remove_references(
+        db: &OptimisticTransactionDB,
+        txn: &Transaction<OptimisticTransactionDB>,
+        id: u32,
+        _key: &[u8],
+    ) -> Result<()> {
+        network_hits::remove_in(db, txn, NetworkListKind::Block, id)
+    }
 }
 
 pub struct Update {
     pub name: Option<String>,
     pub networks: Option<HostNetworkGroup>,
     pub description: Option<String>,
+    pub expiration_time: Option<Option<DateTime<Utc>>>,
 }
 
 impl IndexedMapUpdate for Update {
@@ -72,6 +120,9 @@ impl IndexedMapUpdate for Update {
             value.description.clear();
             value.description.push_str(description);
         }
+        if let Some(expiration_time) = self.expiration_time {
+            value.expiration_time = expiration_time;
+        }
         Ok(value)
     }
 
@@ -91,6 +142,11 @@ impl IndexedMapUpdate for Update {
                 return false;
             }
         }
+        if let Some(v) = self.expiration_time {
+            if v != value.expiration_time {
+                return false;
+            }
+        }
         true
     }
 }
@@ -106,6 +162,51 @@ impl<'d> IndexedTable<'d, BlockNetwork> {
             .ok()
     }
 
+    pub(crate) fn raw(&self) -> &IndexedMap<'_> {
+        &self.indexed_map
+    }
+
+    /// Returns the entries that haven't expired at `now`, which are the ones
+    /// to be enforced.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails or any entry is
+    /// invalid.
+    pub fn active(&self, now: DateTime<Utc>) -> Result<Vec<BlockNetwork>> {
+        let mut entries = Vec::new();
+        for entry in self.iter(Direction::Forward, None) {
+            let entry = entry?;
+            if !entry.is_expired(now) {
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Removes the entries that have expired at `now`, and returns their
+    /// names.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails or any entry is
+    /// invalid.
+    pub fn remove_expired(&self, now: DateTime<Utc>) -> Result<Vec<String>> {
+        let mut expired = Vec::new();
+        for entry in self.iter(Direction::Forward, None) {
+            let entry = entry?;
+            if entry.is_expired(now) {
+                expired.push((entry.id, entry.name));
+            }
+        }
+        let mut names = Vec::with_capacity(expired.len());
+        for (id, name) in expired {
+            self.remove(id)?;
+            names.push(name);
+        }
+        Ok(names)
+    }
+
     /// Updates the `BlockNetwork` from `old` to `new`, given `id`.
     ///
     /// # Errors
@@ -120,6 +221,7 @@ impl<'d> IndexedTable<'d, BlockNetwork> {
 mod test {
     use std::sync::Arc;
 
+    use chrono::{Duration, Utc};
     use rocksdb::Direction;
 
     use crate::{BlockNetwork, HostNetworkGroup, Iterable, Store};
@@ -155,6 +257,7 @@ mod test {
             name: Some(block_network.name.clone()),
             networks: Some(block_network.networks.clone()),
             description: Some(block_network.description.clone()),
+            expiration_time: Some(None),
         };
 
         let updated_block_network =
@@ -163,6 +266,7 @@ mod test {
             name: Some(updated_block_network.name.clone()),
             networks: Some(updated_block_network.networks.clone()),
             description: Some(updated_block_network.description.clone()),
+            expiration_time: Some(None),
         };
 
         table.update(inserted_id, &old, &update).unwrap();
@@ -182,6 +286,7 @@ mod test {
             name: Some(a.name.clone()),
             networks: Some(a.networks.clone()),
             description: Some(a.description.clone()),
+            expiration_time: Some(None),
         };
         let mut b = create_block_network("b", "b");
         b.id = table.put(b.clone()).unwrap();
@@ -189,12 +294,14 @@ mod test {
             name: Some(b.name.clone()),
             networks: Some(b.networks.clone()),
             description: Some(b.description.clone()),
+            expiration_time: Some(None),
         };
 
         let c_update = super::Update {
             name: Some("c".to_string()),
             networks: Some(HostNetworkGroup::default()),
             description: Some("c".to_string()),
+            expiration_time: Some(None),
         };
 
         assert!(table.update(a.id, &a_update, &c_update).is_ok());
@@ -209,6 +316,38 @@ mod test {
         assert_eq!(table.iter(Direction::Reverse, None).count(), 2);
     }
 
+    #[test]
+    fn expiration() {
+        let store = setup_store();
+        let table = store.block_network_map();
+        let now = Utc::now();
+
+        let permanent = create_block_network("permanent", "");
+        table.put(permanent.clone()).unwrap();
+        let mut temporary = create_block_network("temporary", "");
+        temporary.expiration_time = Some(now + Duration::hours(1));
+        table.put(temporary).unwrap();
+        let mut expired = create_block_network("expired", "");
+        expired.expiration_time = Some(now - Duration::hours(1));
+        table.put(expired).unwrap();
+
+        let names = |entries: Vec<BlockNetwork>| -> Vec<String> {
+            entries.into_iter().map(|entry| entry.name).collect()
+        };
+        assert_eq!(
+            names(table.active(now).unwrap()),
+            ["permanent", "temporary"]
+        );
+        assert_eq!(
+            names(table.active(now + Duration::hours(2)).unwrap()),
+            ["permanent"]
+        );
+
+        assert_eq!(table.remove_expired(now).unwrap(), ["expired"]);
+        assert_eq!(table.iter(Direction::Forward, None).count(), 2);
+        assert!(table.remove_expired(now).unwrap().is_empty());
+    }
+
     // Helper functions
 
     fn setup_store() -> Arc<Store> {
@@ -223,6 +362,7 @@ mod test {
             name: name.to_string(),
             networks: HostNetworkGroup::default(),
             description: description.to_string(),
+            expiration_time: None,
         }
     }
 }
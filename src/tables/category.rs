@@ -1,8 +1,14 @@
 //! The `category` table.
-use anyhow::Result;
+use std::collections::HashSet;
+
+use anyhow::{bail, Context, Result};
 use rocksdb::OptimisticTransactionDB;
 
-use crate::{category::Category, types::FromKeyValue, Indexed, IndexedMap, IndexedTable};
+use crate::{
+    category::{Category, ParentUpdate},
+    types::FromKeyValue,
+    Database, Direction, Indexed, IndexedMap, IndexedTable, Iterable,
+};
 
 const DEFAULT_ENTRIES: [(u32, &str); 2] = [(1, "Non-Specified Alert"), (2, "Irrelevant Alert")];
 
@@ -34,10 +40,34 @@ impl<'d> IndexedTable<'d, Category> {
         let entry = Category {
             id: u32::MAX,
             name: name.to_string(),
+            parent_id: None,
+        };
+        self.indexed_map.insert(entry)
+    }
+
+    /// Inserts a category under the category `parent_id` into the table and
+    /// returns the ID of the newly added category.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `parent_id` is invalid, or the table already has a
+    /// category with the same name.
+    pub fn insert_child(&self, name: &str, parent_id: u32) -> Result<u32> {
+        if self.get_by_id(parent_id)?.is_none() {
+            bail!("no such parent category {parent_id}");
+        }
+        let entry = Category {
+            id: u32::MAX,
+            name: name.to_string(),
+            parent_id: Some(parent_id),
         };
         self.indexed_map.insert(entry)
     }
 
+    pub(crate) fn raw(&self) -> &IndexedMap<'_> {
+        &self.indexed_map
+    }
+
     /// Update the category name from `old` to `new`, given `id`.
     ///
     /// # Errors
@@ -47,14 +77,160 @@ impl<'d> IndexedTable<'d, Category> {
         let new = Category {
             id,
             name: new.to_string(),
+            parent_id: None,
         };
         let old = Category {
             id,
             name: old.to_string(),
+            parent_id: None,
         };
         self.indexed_map.update(id, &old, &new)
     }
 
+    /// Moves the category with `id` from the parent `old` to the parent
+    /// `new`. `None` means the category is at the top level.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `id` or `new` is invalid, `new` is `id` itself or
+    /// one of its descendants, the current parent is not `old`, or the
+    /// database operation fails.
+    pub fn set_parent(&self, id: u32, old: Option<u32>, new: Option<u32>) -> Result<()> {
+        // The new parent is checked in the transaction of the update, so that
+        // concurrent moves cannot create a cycle together.
+        self.indexed_map.update_many_with(
+            &[(
+                id,
+                &ParentUpdate { parent_id: old },
+                &ParentUpdate { parent_id: new },
+            )],
+            |txn| {
+                let Some(parent_id) = new else {
+                    return Ok(());
+                };
+                let mut visited = HashSet::new();
+                let mut ancestor_id = Some(parent_id);
+                while let Some(ancestor) = ancestor_id {
+                    if ancestor == id {
+                        bail!("category {id} cannot be a descendant of itself");
+                    }
+                    if !visited.insert(ancestor) {
+                        bail!("cycle in category hierarchy at {ancestor}");
+                    }
+                    let Some(category) = self
+                        .indexed_map
+                        .get_by_id_in_transaction::<Category>(txn, ancestor)?
+                    else {
+                        if ancestor == parent_id {
+                            bail!("no such parent category {parent_id}");
+                        }
+                        break;
+                    };
+                    ancestor_id = category.parent_id;
+                }
+                Ok(())
+            },
+        )
+    }
+
+    /// Returns the categories directly under the category with `id`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails.
+    pub fn children(&self, id: u32) -> Result<Vec<Category>> {
+        let mut children = Vec::new();
+        for category in self.iter(Direction::Forward, None) {
+            let category = category?;
+            if category.parent_id == Some(id) {
+                children.push(category);
+            }
+        }
+        Ok(children)
+    }
+
+    /// Returns the ancestors of the category with `id`, starting from its
+    /// parent up to the top-level category.
+    ///
+    /// A parent that no longer exists ends the chain.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `id` is invalid or the database operation fails.
+    pub fn ancestors(&self, id: u32) -> Result<Vec<Category>> {
+        let Some(category) = self.get_by_id(id)? else {
+            bail!("no such category {id}");
+        };
+        let mut visited = HashSet::from([id]);
+        let mut ancestors = Vec::new();
+        let mut parent_id = category.parent_id;
+        while let Some(id) = parent_id {
+            if !visited.insert(id) {
+                bail!("cycle in category hierarchy at {id}");
+            }
+            let Some(parent) = self.get_by_id(id)? else {
+                break;
+            };
+            parent_id = parent.parent_id;
+            ancestors.push(parent);
+        }
+        Ok(ancestors)
+    }
+
+    /// Removes the category with `id`, and returns the number of clusters that
+    /// were in the category.
+    ///
+    /// Clusters in `database` refer to categories by ID, so renaming a category
+    /// with [`IndexedTable::update`] doesn't affect them. Removing one, however,
+    /// would leave them orphaned. This moves them to the category
+    /// `replacement` first; if `replacement` is `None`, the category is removed
+    /// only if no cluster is in it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `id` or `replacement` is invalid, any cluster is in
+    /// the category while `replacement` is `None`, or a database operation
+    /// fails. The clusters may have been moved even if removing the category
+    /// fails.
+    pub async fn remove_with_clusters(
+        &self,
+        database: &Database,
+        id: u32,
+        replacement: Option<u32>,
+    ) -> Result<usize> {
+        let (category, replacement) = self.removal_ids(id, replacement)?;
+        let moved = if let Some(replacement) = replacement {
+            database
+                .replace_cluster_category(category, replacement)
+                .await?
+        } else {
+            let count = database.count_clusters_in_category(category).await?;
+            if count > 0 {
+                bail!("category {id} has {count} clusters");
+            }
+            0
+        };
+        self.remove(id)?;
+        Ok(moved)
+    }
+
+    /// Validates the IDs for [`IndexedTable::remove_with_clusters`] and
+    /// converts them to the IDs clusters refer to.
+    fn removal_ids(&self, id: u32, replacement: Option<u32>) -> Result<(i32, Option<i32>)> {
+        if self.get_by_id(id)?.is_none() {
+            bail!("no such category {id}");
+        }
+        let category = i32::try_from(id).context("invalid category ID")?;
+        let Some(replacement) = replacement else {
+            return Ok((category, None));
+        };
+        if replacement == id || self.get_by_id(replacement)?.is_none() {
+            bail!("invalid replacement category {replacement}");
+        }
+        let replacement = i32::try_from(replacement).context("invalid category ID")?;
+        Ok((category, Some(replacement)))
+    }
+
     /// Try adding default entries into the database.
     ///
     /// # Errors
@@ -98,18 +274,22 @@ mod tests {
             Category {
                 id: u32::MAX,
                 name: "c".to_string(),
+                parent_id: None,
             },
             Category {
                 id: u32::MAX,
                 name: "a".to_string(),
+                parent_id: None,
             },
             Category {
                 id: u32::MAX,
                 name: "b".to_string(),
+                parent_id: None,
             },
             Category {
                 id: u32::MAX,
                 name: "d".to_string(),
+                parent_id: None,
             },
         ];
 
@@ -157,4 +337,59 @@ mod tests {
             entries.len() + DEFAULT_ENTRIES.len()
         );
     }
+
+    #[test]
+    fn hierarchy() {
+        let (store, entries) = set_up_db();
+        let table = store.category_map();
+        let (c, a, b) = (entries[0].id, entries[1].id, entries[2].id);
+
+        let child = table.insert_child("a-1", a).unwrap();
+        assert!(table.insert_child("orphan", 100).is_err());
+        table.set_parent(b, None, Some(child)).unwrap();
+        assert!(table.set_parent(b, None, Some(c)).is_err());
+
+        let children = table.children(a).unwrap();
+        assert_eq!(children.len(), 1);
+        assert_eq!(children[0].name, "a-1");
+        let ancestors: Vec<_> = table
+            .ancestors(b)
+            .unwrap()
+            .into_iter()
+            .map(|c| c.name)
+            .collect();
+        assert_eq!(ancestors, ["a-1", "a"]);
+        assert!(table.ancestors(a).unwrap().is_empty());
+
+        // A category cannot be moved under itself or its own descendant.
+        assert!(table.set_parent(a, None, Some(b)).is_err());
+        assert!(table.set_parent(a, None, Some(a)).is_err());
+
+        table.set_parent(b, Some(child), None).unwrap();
+        assert!(table.children(child).unwrap().is_empty());
+    }
+
+    #[test]
+    fn removal_ids() {
+        let (store, entries) = set_up_db();
+        let table = store.category_map();
+        let (a, b) = (entries[1].id, entries[2].id);
+
+        assert_eq!(
+            table.removal_ids(a, None).unwrap(),
+            (i32::try_from(a).unwrap(), None)
+        );
+        assert_eq!(
+            table.removal_ids(a, Some(b)).unwrap(),
+            (i32::try_from(a).unwrap(), Some(i32::try_from(b).unwrap()))
+        );
+
+        // The replacement must be another existing category.
+        assert!(table.removal_ids(a, Some(a)).is_err());
+        assert!(table.removal_ids(a, Some(100)).is_err());
+
+        // The category to remove must exist.
+        assert!(table.removal_ids(100, None).is_err());
+        assert!(table.removal_ids(100, Some(b)).is_err());
+    }
 }
@@ -1,6 +1,11 @@
 //! The `customer` table.
 
-use std::{borrow::Cow, net::IpAddr};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet},
+    net::IpAddr,
+    sync::Arc,
+};
 
 use anyhow::Result;
 use chrono::{DateTime, Utc};
@@ -8,8 +13,9 @@ use rocksdb::OptimisticTransactionDB;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    types::FromKeyValue, HostNetworkGroup, Indexable, Indexed, IndexedMap, IndexedMapUpdate,
-    IndexedTable, NetworkType,
+    types::{addr_to_int, FromKeyValue},
+    Direction, HostNetworkGroup, Indexable, Indexed, IndexedMap, IndexedMapUpdate, IndexedTable,
+    Iterable, NetworkType,
 };
 
 #[derive(Clone, Deserialize, Serialize)]
@@ -68,6 +74,117 @@ impl Network {
     }
 }
 
+/// A customer with networks containing an address, returned by
+/// `IndexedTable<Customer>::find_by_addr`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CustomerMatch {
+    pub customer_id: u32,
+    pub customer_name: String,
+    /// The names of the networks of the customer containing the address.
+    pub networks: Vec<String>,
+}
+
+/// The address intervals of the networks of customers, for looking up the
+/// customers by address.
+struct AddrIndex {
+    customers: Vec<(u32, String)>,
+    network_names: Vec<String>,
+    /// The IPv4 and IPv6 intervals, respectively, sorted by their starts.
+    v4: Intervals,
+    v6: Intervals,
+}
+
+/// Address intervals sorted by their starts, each with the indices of the
+/// customer and the network in `AddrIndex`.
+#[derive(Default)]
+struct Intervals {
+    entries: Vec<(u128, u128, usize, usize)>,
+    /// The largest end of the entries up to each position.
+    max_ends: Vec<u128>,
+}
+
+impl Intervals {
+    fn build(mut entries: Vec<(u128, u128, usize, usize)>) -> Self {
+        entries.sort_unstable();
+        let max_ends = entries
+            .iter()
+            .scan(0, |max, (_, end, _, _)| {
+                *max = (*max).max(*end);
+                Some(*max)
+            })
+            .collect();
+        Self { entries, max_ends }
+    }
+
+    /// Returns the customer and network indices of the intervals containing
+    /// `addr`.
+    fn find(&self, addr: u128) -> impl Iterator<Item = (usize, usize)> + '_ {
+        let next = self.entries.partition_point(|(start, ..)| *start <= addr);
+        (0..next)
+            .rev()
+            .take_while(move |i| self.max_ends[*i] >= addr)
+            .filter(move |i| self.entries[*i].1 >= addr)
+            .map(|i| (self.entries[i].2, self.entries[i].3))
+    }
+}
+
+impl AddrIndex {
+    fn new(table: &IndexedTable<Customer>) -> Result<Self> {
+        let mut customers = Vec::new();
+        let mut network_names = Vec::new();
+        let mut v4 = Vec::new();
+        let mut v6 = Vec::new();
+        for customer in table.iter(Direction::Forward, None) {
+            let customer = customer?;
+            let customer_index = customers.len();
+            for network in customer.networks {
+                let network_index = network_names.len();
+                for (is_v6, start, end) in network.network_group.intervals() {
+                    let entry = (start, end, customer_index, network_index);
+                    if is_v6 {
+                        v6.push(entry);
+                    } else {
+                        v4.push(entry);
+                    }
+                }
+                network_names.push(network.name);
+            }
+            customers.push((customer.id, customer.name));
+        }
+        Ok(Self {
+            customers,
+            network_names,
+            v4: Intervals::build(v4),
+            v6: Intervals::build(v6),
+        })
+    }
+
+    fn find(&self, addr: IpAddr) -> Vec<CustomerMatch> {
+        let (is_v6, addr) = addr_to_int(addr);
+        let intervals = if is_v6 { &self.v6 } else { &self.v4 };
+        let mut found: BTreeMap<usize, BTreeSet<usize>> = BTreeMap::new();
+        for (customer, network) in intervals.find(addr) {
+            found.entry(customer).or_default().insert(network);
+        }
+        let mut matches: Vec<_> = found
+            .into_iter()
+            .map(|(customer, networks)| {
+                let (customer_id, customer_name) = &self.customers[customer];
+                CustomerMatch {
+                    customer_id: *customer_id,
+                    customer_name: customer_name.clone(),
+                    networks: networks
+                        .into_iter()
+                        .map(|network| self.network_names[network].clone())
+                        .collect(),
+                }
+            })
+            .collect();
+        matches.sort_by_key(|m| m.customer_id);
+        matches
+    }
+}
+
 #[derive(Clone)]
 pub struct Update {
     pub name: Option<String>,
@@ -136,6 +253,24 @@ impl<'d> IndexedTable<'d, Customer> {
             .ok()
     }
 
+    /// Returns the customers with a network containing `addr`, ordered by ID,
+    /// and the names of their networks containing it.
+    ///
+    /// The lookup index is built from the active customers when first needed,
+    /// and rebuilt after the table changes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the index cannot be built because the database
+    /// operation fails or a customer is invalid.
+    pub fn find_by_addr(&self, addr: IpAddr) -> Result<Vec<CustomerMatch>> {
+        let index = match self.indexed_map.notifier() {
+            Some(channel) => channel.cached(|| AddrIndex::new(self))?,
+            None => Arc::new(AddrIndex::new(self)?),
+        };
+        Ok(index.find(addr))
+    }
+
     /// Updates the `Cutomer` from `old` to `new`, given `id`.
     ///
     /// # Errors
@@ -178,6 +313,105 @@ mod test {
         assert_eq!(entry.map(|e| e.name), Some("b".to_string()));
     }
 
+    #[test]
+    fn find_by_addr() {
+        use crate::{CustomerNetwork, HostNetworkGroup, NetworkType};
+
+        let store = setup_store();
+        let mut table = store.customer_map();
+
+        let network = |name: &str, group: &str| CustomerNetwork {
+            name: name.to_string(),
+            description: String::new(),
+            network_type: NetworkType::Intranet,
+            network_group: group.parse::<HostNetworkGroup>().unwrap(),
+        };
+        let mut a = create_entry("a");
+        a.networks = vec![
+            network("office", "10.0.0.0/24"),
+            network("servers", "10.0.0.5 - 10.0.0.9, 192.168.0.1"),
+        ];
+        let a = table.put(a).unwrap();
+        let mut b = create_entry("b");
+        b.networks = vec![network("all", "10.0.0.0/8")];
+        let b = table.put(b).unwrap();
+
+        let found = table.find_by_addr("10.0.0.7".parse().unwrap()).unwrap();
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].customer_id, a);
+        assert_eq!(found[0].networks, ["office", "servers"]);
+        assert_eq!(found[1].customer_id, b);
+        assert_eq!(found[1].networks, ["all"]);
+
+        let found = table.find_by_addr("192.168.0.1".parse().unwrap()).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].networks, ["servers"]);
+        assert!(table
+            .find_by_addr("172.16.0.1".parse().unwrap())
+            .unwrap()
+            .is_empty());
+
+        let old = CustomerUpdate {
+            name: None,
+            description: None,
+            networks: Some(vec![network("all", "10.0.0.0/8")]),
+        };
+        let new = CustomerUpdate {
+            name: None,
+            description: None,
+            networks: Some(vec![network("other", "172.16.0.0/12")]),
+        };
+        table.update(b, &old, &new).unwrap();
+        let found = table.find_by_addr("172.16.0.1".parse().unwrap()).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].customer_name, "b");
+        let found = table.find_by_addr("10.1.0.1".parse().unwrap()).unwrap();
+        assert!(found.is_empty());
+    }
+
+    #[test]
+    fn find_by_addr_after_import() {
+        use crate::{CustomerNetwork, HostNetworkGroup, NetworkType};
+
+        let network = |group: &str| CustomerNetwork {
+            name: "network".to_string(),
+            description: String::new(),
+            network_type: NetworkType::Intranet,
+            network_group: group.parse::<HostNetworkGroup>().unwrap(),
+        };
+
+        let archive_dir = tempfile::tempdir().unwrap();
+        let archive = archive_dir.path().join("store.archive");
+        let src = setup_store();
+        let mut entry = create_entry("imported");
+        entry.networks = vec![network("172.16.0.0/12")];
+        src.customer_map().put(entry).unwrap();
+        src.export(&archive).unwrap();
+
+        let dst = setup_store();
+        let mut entry = create_entry("replaced");
+        entry.networks = vec![network("10.0.0.0/8")];
+        dst.customer_map().put(entry).unwrap();
+        let table = dst.customer_map();
+        assert_eq!(
+            table
+                .find_by_addr("10.0.0.1".parse().unwrap())
+                .unwrap()
+                .len(),
+            1
+        );
+
+        dst.import(&archive).unwrap();
+        let table = dst.customer_map();
+        assert!(table
+            .find_by_addr("10.0.0.1".parse().unwrap())
+            .unwrap()
+            .is_empty());
+        let found = table.find_by_addr("172.16.0.1".parse().unwrap()).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].customer_name, "imported");
+    }
+
     fn setup_store() -> Arc<Store> {
         let db_dir = tempfile::tempdir().unwrap();
         let backup_dir = tempfile::tempdir().unwrap();
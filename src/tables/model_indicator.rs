@@ -1,18 +1,24 @@
-//! The `model_indicator` map.
+//! The `model_indicator` map, and the `model indicator versions` map keeping
+//! the prior versions of model indicators.
 
 use std::{
+    borrow::Cow,
     collections::HashSet,
     io::{BufReader, Read},
 };
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use chrono::{serde::ts_seconds, DateTime, Utc};
 use data_encoding::BASE64;
 use flate2::read::GzDecoder;
-use rocksdb::OptimisticTransactionDB;
+use rocksdb::{IteratorMode, OptimisticTransactionDB, Transaction};
 use serde::{Deserialize, Serialize};
 
-use crate::{types::FromKeyValue, Map, Table};
+use crate::{
+    notification::{notify, ChangeKind},
+    types::FromKeyValue,
+    Direction, Iterable, Map, Table, UniqueKey, EXCLUSIVE,
+};
 
 #[derive(Default)]
 pub struct ModelIndicator {
@@ -39,18 +45,27 @@ impl ModelIndicator {
         Self::from_key_value(name.as_bytes(), &buf)
     }
 
-    fn into_key_value(self) -> Result<(Vec<u8>, Vec<u8>)> {
-        let key = self.name.as_bytes().to_owned();
-        let value = Value {
-            description: self.description,
-            model_id: self.model_id,
-            tokens: self.tokens,
-            last_modification_time: self.last_modification_time,
-        };
-        Ok((key, super::serialize(&value)?))
+    /// Returns the token sequences added and removed from `self` to `new`.
+    #[must_use]
+    pub fn diff(&self, new: &ModelIndicator) -> ModelIndicatorDiff {
+        let mut added: Vec<_> = new.tokens.difference(&self.tokens).cloned().collect();
+        let mut removed: Vec<_> = self.tokens.difference(&new.tokens).cloned().collect();
+        added.sort_unstable();
+        removed.sort_unstable();
+        ModelIndicatorDiff { added, removed }
     }
 }
 
+/// The changes in the token sequences between two versions of a model
+/// indicator, returned by [`ModelIndicator::diff`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ModelIndicatorDiff {
+    /// The token sequences only in the newer version, in sorted order.
+    pub added: Vec<Vec<String>>,
+    /// The token sequences only in the older version, in sorted order.
+    pub removed: Vec<Vec<String>>,
+}
+
 #[derive(Deserialize, Serialize)]
 struct Value {
     description: String,
@@ -60,6 +75,17 @@ struct Value {
     last_modification_time: DateTime<Utc>,
 }
 
+impl From<&ModelIndicator> for Value {
+    fn from(indicator: &ModelIndicator) -> Self {
+        Self {
+            description: indicator.description.clone(),
+            model_id: indicator.model_id,
+            tokens: indicator.tokens.clone(),
+            last_modification_time: indicator.last_modification_time,
+        }
+    }
+}
+
 impl FromKeyValue for ModelIndicator {
     fn from_key_value(key: &[u8], value: &[u8]) -> Result<Self> {
         let name = std::str::from_utf8(key)?.to_string();
@@ -95,38 +121,249 @@ impl<'d> Table<'d, ModelIndicator> {
             .transpose()
     }
 
-    /// Inserts the `ModelIndicator` into the database.
+    /// Inserts the `ModelIndicator` into the database. An indicator with the
+    /// same name is replaced and kept as a prior version.
     ///
     /// # Errors
     ///
     /// Returns an error if the serialization fails or the database operation fails.
     pub fn insert(&self, indicator: ModelIndicator) -> Result<()> {
-        let (key, value) = indicator.into_key_value()?;
-        self.map.put(&key, &value)
+        self.put_versioned(indicator).map(|_| ())
     }
 
-    /// Removes the `ModelIndicator`s with the given names. The removed names are returned.
+    /// Removes the `ModelIndicator`s with the given names, along with their
+    /// prior versions, in a transaction. The removed names are returned.
     ///
     /// # Errors
     ///
     /// Returns an error if the database operation fails.
     pub fn remove<'a>(&self, names: impl Iterator<Item = &'a str>) -> Result<Vec<String>> {
-        let mut removed = vec![];
-        for name in names {
-            self.map.delete(name.as_bytes())?;
-            removed.push(name.to_string());
+        let names: Vec<_> = names.collect();
+        let versions = self.versions_cf()?;
+        loop {
+            let txn = self.map.db.transaction();
+            for name in &names {
+                txn.delete_cf(self.map.cf, name.as_bytes())
+                    .context("failed to delete model indicator")?;
+                for key in version_keys(&txn, versions, name)? {
+                    txn.delete_cf(versions, key)
+                        .context("failed to delete model indicator version")?;
+                }
+            }
+
+            match txn.commit() {
+                Ok(()) => break,
+                Err(e) => {
+                    if !e.as_ref().starts_with("Resource busy:") {
+                        return Err(e).context("failed to remove model indicators");
+                    }
+                }
+            }
+        }
+        for name in &names {
+            notify(self.map.notifier, ChangeKind::Remove, name.as_bytes());
         }
-        Ok(removed)
+        Ok(names.into_iter().map(ToString::to_string).collect())
     }
 
-    /// Updates the `ModelIndicator` in the database.
+    /// Updates the `ModelIndicator` in the database, keeping the replaced one
+    /// as a prior version.
     ///
     /// # Errors
     ///
     /// Returns an error if the serialization fails or the database operation fails.
     pub fn update(&self, indicator: ModelIndicator) -> Result<()> {
-        self.remove(std::iter::once(indicator.name.as_str()))?;
-        self.insert(indicator)
+        self.put_versioned(indicator).map(|_| ())
+    }
+
+    /// Stores `indicator`, keeping the one it replaces, if any, as a prior
+    /// version in the same transaction, and returns the version number of
+    /// `indicator`. The first version of an indicator is 1.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the serialization fails or the database operation fails.
+    pub(crate) fn put_versioned(&self, indicator: ModelIndicator) -> Result<u32> {
+        let version = loop {
+            let txn = self.map.db.transaction();
+            let version = self.put_versioned_in(&txn, &indicator)?;
+            match txn.commit() {
+                Ok(()) => break version,
+                Err(e) => {
+                    if !e.as_ref().starts_with("Resource busy:") {
+                        return Err(e).context("failed to write model indicator");
+                    }
+                }
+            }
+        };
+        self.notify_update(&indicator.name);
+        Ok(version)
+    }
+
+    /// Stores `indicator` in `txn` in the same way as `put_versioned`, leaving
+    /// the notification of the change to the caller.
+    pub(super) fn put_versioned_in(
+        &self,
+        txn: &Transaction<OptimisticTransactionDB>,
+        indicator: &ModelIndicator,
+    ) -> Result<u32> {
+        let versions = self.versions_cf()?;
+        let name = indicator.name.as_str();
+        let value = super::serialize(&Value::from(indicator))?;
+        let version = if let Some(current) = txn
+            .get_for_update_cf(self.map.cf, name.as_bytes(), EXCLUSIVE)
+            .context("cannot read model indicator")?
+        {
+            let prior = match version_keys(txn, versions, name)?.last() {
+                Some(key) => ModelIndicatorVersion::split_key(key)?.1 + 1,
+                None => 1,
+            };
+            txn.put_cf(versions, ModelIndicatorVersion::key(name, prior), current)
+                .context("failed to write model indicator version")?;
+            prior + 1
+        } else {
+            1
+        };
+        txn.put_cf(self.map.cf, name.as_bytes(), value)
+            .context("failed to write model indicator")?;
+        Ok(version)
+    }
+
+    /// Notifies the subscribers that the model indicator `name` was stored.
+    pub(super) fn notify_update(&self, name: &str) {
+        notify(self.map.notifier, ChangeKind::Update, name.as_bytes());
+    }
+
+    fn versions_cf(&self) -> Result<&'d rocksdb::ColumnFamily> {
+        self.map
+            .db
+            .cf_handle(super::MODEL_INDICATOR_VERSIONS)
+            .context("model indicator versions table must be present")
+    }
+}
+
+/// Returns the keys of the prior versions of the model indicator `name` in
+/// `txn`, from the oldest.
+fn version_keys(
+    txn: &Transaction<OptimisticTransactionDB>,
+    versions: &rocksdb::ColumnFamily,
+    name: &str,
+) -> Result<Vec<Box<[u8]>>> {
+    let prefix = ModelIndicatorVersion::prefix(name);
+    let mut readopts = rocksdb::ReadOptions::default();
+    readopts.set_iterate_range(rocksdb::PrefixRange(prefix.as_slice()));
+    txn.iterator_cf_opt(versions, readopts, IteratorMode::Start)
+        .map(|item| item.map(|(key, _)| key))
+        .collect::<Result<_, _>>()
+        .context("cannot read model indicator versions")
+}
+
+/// A prior version of a model indicator, kept when it is replaced.
+pub struct ModelIndicatorVersion {
+    /// The version number, starting from 1 for the first version of the
+    /// indicator.
+    pub version: u32,
+    pub indicator: ModelIndicator,
+}
+
+impl ModelIndicatorVersion {
+    fn key(name: &str, version: u32) -> Vec<u8> {
+        let mut key = Self::prefix(name);
+        key.extend(version.to_be_bytes());
+        key
+    }
+
+    fn prefix(name: &str) -> Vec<u8> {
+        let mut prefix = name.as_bytes().to_vec();
+        prefix.push(0);
+        prefix
+    }
+
+    /// Splits `key` into the name and the version number.
+    fn split_key(key: &[u8]) -> Result<(&[u8], u32)> {
+        let Some((name, version)) = key.len().checked_sub(4).map(|pos| key.split_at(pos)) else {
+            bail!("invalid model indicator version key");
+        };
+        let Some(name) = name.strip_suffix(&[0]) else {
+            bail!("invalid model indicator version key");
+        };
+        Ok((name, u32::from_be_bytes(version.try_into()?)))
+    }
+}
+
+impl FromKeyValue for ModelIndicatorVersion {
+    fn from_key_value(key: &[u8], value: &[u8]) -> Result<Self> {
+        let (name, version) = Self::split_key(key)?;
+        Ok(Self {
+            version,
+            indicator: ModelIndicator::from_key_value(name, value)?,
+        })
+    }
+}
+
+impl UniqueKey for ModelIndicatorVersion {
+    fn unique_key(&self) -> Cow<[u8]> {
+        Cow::Owned(Self::key(&self.indicator.name, self.version))
+    }
+}
+
+/// Functions for the `model indicator versions` map. The versions are written
+/// only by the functions of the `model_indicator` map.
+impl<'d> Table<'d, ModelIndicatorVersion> {
+    /// Opens the `model indicator versions` map in the database.
+    ///
+    /// Returns `None` if the map does not exist.
+    pub(super) fn open(db: &'d OptimisticTransactionDB) -> Option<Self> {
+        Map::open(db, super::MODEL_INDICATOR_VERSIONS).map(Table::new)
+    }
+
+    /// Returns the prior version `version` of the model indicator `name`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database query fails.
+    pub fn get(&self, name: &str, version: u32) -> Result<Option<ModelIndicatorVersion>> {
+        let key = ModelIndicatorVersion::key(name, version);
+        self.map
+            .get(&key)?
+            .map(|v| ModelIndicatorVersion::from_key_value(&key, v.as_ref()))
+            .transpose()
+    }
+
+    /// Returns the prior versions of the model indicator `name`, from the
+    /// oldest.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database query fails.
+    pub fn list(&self, name: &str) -> Result<Vec<ModelIndicatorVersion>> {
+        let prefix = ModelIndicatorVersion::prefix(name);
+        let mut versions = Vec::new();
+        for version in self.iter(Direction::Forward, Some(&prefix)) {
+            let version = version?;
+            if version.indicator.name != name {
+                break;
+            }
+            versions.push(version);
+        }
+        Ok(versions)
+    }
+
+    /// Returns the number of the latest prior version of the model indicator
+    /// `name`, or `None` if it has none.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database query fails.
+    pub fn latest_version(&self, name: &str) -> Result<Option<u32>> {
+        let last = ModelIndicatorVersion::key(name, u32::MAX);
+        match self.iter(Direction::Reverse, Some(&last)).next() {
+            Some(version) => {
+                let version = version?;
+                Ok((version.indicator.name == name).then_some(version.version))
+            }
+            None => Ok(None),
+        }
     }
 }
 
@@ -203,4 +440,129 @@ mod tests {
         let removed = res.unwrap();
         assert_eq!(removed, list);
     }
+
+    #[test]
+    fn versions() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(Store::new(db_dir.path(), backup_dir.path()).unwrap());
+
+        let indicator = |tokens: &[&[&str]]| ModelIndicator {
+            name: "mi".to_string(),
+            tokens: tokens
+                .iter()
+                .map(|seq| seq.iter().map(ToString::to_string).collect())
+                .collect(),
+            ..ModelIndicator::default()
+        };
+        assert_eq!(
+            store
+                .update_model_indicator(indicator(&[&["a", "b"], &["c"]]))
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            store
+                .update_model_indicator(indicator(&[&["a", "b"], &["d"]]))
+                .unwrap(),
+            2
+        );
+        assert_eq!(
+            store
+                .update_model_indicator(indicator(&[&["d"], &["e", "f"]]))
+                .unwrap(),
+            3
+        );
+        let mut other = indicator(&[&["x"]]);
+        other.name = "mi2".to_string();
+        assert_eq!(store.update_model_indicator(other).unwrap(), 1);
+
+        let versions = store.model_indicator_version_map();
+        assert_eq!(versions.latest_version("mi").unwrap(), Some(2));
+        assert_eq!(versions.latest_version("mi2").unwrap(), None);
+        assert_eq!(versions.list("mi").unwrap().len(), 2);
+        assert!(versions.get("mi", 1).unwrap().is_some());
+        assert!(versions.get("mi", 3).unwrap().is_none());
+        assert_eq!(
+            store
+                .model_indicator_at_version("mi", 3)
+                .unwrap()
+                .unwrap()
+                .tokens
+                .len(),
+            2
+        );
+        assert!(store.model_indicator_at_version("mi", 4).unwrap().is_none());
+
+        let diff = store.model_indicator_diff("mi", 1, 3).unwrap();
+        assert_eq!(
+            diff.added,
+            [
+                vec!["d".to_string()],
+                vec!["e".to_string(), "f".to_string()]
+            ]
+        );
+        assert_eq!(
+            diff.removed,
+            [
+                vec!["a".to_string(), "b".to_string()],
+                vec!["c".to_string()]
+            ]
+        );
+        assert!(store.model_indicator_diff("mi", 1, 4).is_err());
+    }
+
+    #[test]
+    fn versions_of_table_operations() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(Store::new(db_dir.path(), backup_dir.path()).unwrap());
+        let table = store.model_indicator_map();
+        let versions = store.model_indicator_version_map();
+
+        let indicator = |description: &str| ModelIndicator {
+            name: "mi".to_string(),
+            description: description.to_string(),
+            ..ModelIndicator::default()
+        };
+        table.insert(indicator("first")).unwrap();
+        assert_eq!(versions.latest_version("mi").unwrap(), None);
+        table.insert(indicator("second")).unwrap();
+        table.update(indicator("third")).unwrap();
+        assert_eq!(versions.latest_version("mi").unwrap(), Some(2));
+        assert_eq!(
+            versions
+                .get("mi", 1)
+                .unwrap()
+                .unwrap()
+                .indicator
+                .description,
+            "first"
+        );
+        assert_eq!(
+            versions
+                .get("mi", 2)
+                .unwrap()
+                .unwrap()
+                .indicator
+                .description,
+            "second"
+        );
+
+        table.remove(std::iter::once("mi")).unwrap();
+        assert!(table.get("mi").unwrap().is_none());
+        assert!(versions.list("mi").unwrap().is_empty());
+
+        assert_eq!(store.update_model_indicator(indicator("new")).unwrap(), 1);
+        assert_eq!(store.update_model_indicator(indicator("newer")).unwrap(), 2);
+        assert_eq!(
+            versions
+                .get("mi", 1)
+                .unwrap()
+                .unwrap()
+                .indicator
+                .description,
+            "new"
+        );
+    }
 }
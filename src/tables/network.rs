@@ -145,6 +145,10 @@ impl<'d> IndexedTable<'d, Network> {
             .ok()
     }
 
+    pub(crate) fn raw(&self) -> &IndexedMap<'_> {
+        &self.indexed_map
+    }
+
     /// Inserts a network into the table and returns the ID of the newly added
     /// network.
     ///
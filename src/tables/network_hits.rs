@@ -0,0 +1,345 @@
+//! The `network hits` table.
+
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use rocksdb::{OptimisticTransactionDB, Transaction};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    notification::{notify, ChangeKind},
+    types::FromKeyValue,
+    Direction, Iterable, Map, Table, EXCLUSIVE,
+};
+
+/// The table an entry matching traffic is in.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub enum NetworkListKind {
+    /// `IndexedTable<AllowNetwork>`.
+    Allow,
+    /// `IndexedTable<BlockNetwork>`.
+    Block,
+}
+
+impl NetworkListKind {
+    fn prefix(self) -> u8 {
+        match self {
+            Self::Allow => 0,
+            Self::Block => 1,
+        }
+    }
+}
+
+/// The number of times an allow or block network entry matched traffic, and
+/// when it last did.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct NetworkHits {
+    pub kind: NetworkListKind,
+    /// The ID of the entry in the table of `kind`.
+    pub id: u32,
+    pub count: u64,
+    pub last_hit_time: DateTime<Utc>,
+}
+
+impl NetworkHits {
+    fn key(kind: NetworkListKind, id: u32) -> [u8; 5] {
+        let mut key = [0; 5];
+        key[0] = kind.prefix();
+        key[1..].copy_from_slice(&id.to_be_bytes());
+        key
+    }
+}
+
+/// Removes the hits of the entry with `id` in the table of `kind` in `txn`.
+///
+/// # Errors
+///
+/// Returns an error if the database operation fails.
+pub(super) fn remove_in(
+    db: &OptimisticTransactionDB,
+    txn: &Transaction<OptimisticTransactionDB>,
+    kind: NetworkListKind,
+    id: u32,
+) -> Result<()> {
+    let cf = db
+        .cf_handle(super::NETWORK_HITS)
+        .context("network hits table must be present")?;
+    txn.delete_cf(cf, NetworkHits::key(kind, id))
+        .context("failed to remove network hits")
+}
+
+impl FromKeyValue for NetworkHits {
+    fn from_key_value(_key: &[u8], value: &[u8]) -> Result<Self> {
+        super::deserialize(value)
+    }
+}
+
+/// Functions for the `network hits` table.
+///
+/// The counters are kept by ID, and aren't removed with their entries; they
+/// should be removed with [`Table::remove`] when an entry is removed, so that
+/// they aren't counted for another entry reusing the ID.
+impl<'d> Table<'d, NetworkHits> {
+    /// Opens the `network hits` table in the database.
+    ///
+    /// Returns `None` if the table does not exist.
+    pub(super) fn open(db: &'d OptimisticTransactionDB) -> Option<Self> {
+        Map::open(db, super::NETWORK_HITS).map(Table::new)
+    }
+
+    /// Adds a batch of hits, each the number of times an entry matched
+    /// traffic since the last report and when it last did, to the counters in
+    /// a single transaction.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails or any stored counter
+    /// is invalid.
+    pub fn record(&self, hits: &[NetworkHits]) -> Result<()> {
+        let mut deltas: HashMap<[u8; 5], NetworkHits> = HashMap::new();
+        for hit in hits {
+            deltas
+                .entry(NetworkHits::key(hit.kind, hit.id))
+                .and_modify(|delta| {
+                    delta.count = delta.count.saturating_add(hit.count);
+                    delta.last_hit_time = delta.last_hit_time.max(hit.last_hit_time);
+                })
+                .or_insert_with(|| hit.clone());
+        }
+        if deltas.is_empty() {
+            return Ok(());
+        }
+
+        loop {
+            let txn = self.map.db.transaction();
+            for (key, delta) in &deltas {
+                let hits = match txn
+                    .get_for_update_cf(self.map.cf, key, EXCLUSIVE)
+                    .context("cannot read network hits")?
+                {
+                    Some(value) => {
+                        let mut hits: NetworkHits = super::deserialize(&value)?;
+                        hits.count = hits.count.saturating_add(delta.count);
+                        hits.last_hit_time = hits.last_hit_time.max(delta.last_hit_time);
+                        hits
+                    }
+                    None => delta.clone(),
+                };
+                txn.put_cf(self.map.cf, key, super::serialize(&hits)?)
+                    .context("cannot write network hits")?;
+            }
+            match txn.commit() {
+                Ok(()) => break,
+                Err(e) => {
+                    if !e.as_ref().starts_with("Resource busy:") {
+                        return Err(e).context("failed to update network hits");
+                    }
+                }
+            }
+        }
+        for key in deltas.keys() {
+            notify(self.map.notifier, ChangeKind::Update, key);
+        }
+        Ok(())
+    }
+
+    /// Returns the hits of the entry with `id` in the table of `kind`, or
+    /// `None` if it has never matched traffic.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails or the counter is
+    /// invalid.
+    pub fn get(&self, kind: NetworkListKind, id: u32) -> Result<Option<NetworkHits>> {
+        self.map
+            .get(&NetworkHits::key(kind, id))?
+            .map(|value| super::deserialize(value.as_ref()))
+            .transpose()
+    }
+
+    /// Returns the hits of the entries in the table of `kind` that have
+    /// matched traffic, in the order of their IDs.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails or any counter is
+    /// invalid.
+    pub fn by_kind(&self, kind: NetworkListKind) -> Result<Vec<NetworkHits>> {
+        let prefix = [kind.prefix()];
+        let mut hits = Vec::new();
+        for entry in self.iter(Direction::Forward, Some(&prefix)) {
+            let entry = entry?;
+            if entry.kind != kind {
+                break;
+            }
+            hits.push(entry);
+        }
+        Ok(hits)
+    }
+
+    /// Removes the hits of the entry with `id` in the table of `kind`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails.
+    pub fn remove(&self, kind: NetworkListKind, id: u32) -> Result<()> {
+        self.map.delete(&NetworkHits::key(kind, id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::{Duration, Utc};
+
+    use super::{NetworkHits, NetworkListKind};
+    use crate::{AllowNetwork, BlockNetwork, HostNetworkGroup, Store};
+
+    #[test]
+    fn record() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(Store::new(db_dir.path(), backup_dir.path()).unwrap());
+        let table = store.network_hits_map();
+
+        let now = Utc::now();
+        let hits = |kind, id, count, last_hit_time| NetworkHits {
+            kind,
+            id,
+            count,
+            last_hit_time,
+        };
+        table
+            .record(&[
+                hits(NetworkListKind::Block, 1, 3, now - Duration::minutes(2)),
+                hits(NetworkListKind::Block, 1, 2, now - Duration::minutes(1)),
+                hits(NetworkListKind::Allow, 1, 1, now),
+            ])
+            .unwrap();
+        table
+            .record(&[hits(
+                NetworkListKind::Block,
+                1,
+                5,
+                now - Duration::minutes(3),
+            )])
+            .unwrap();
+
+        let block = table.get(NetworkListKind::Block, 1).unwrap().unwrap();
+        assert_eq!(block.count, 10);
+        assert_eq!(block.last_hit_time, now - Duration::minutes(1));
+        assert!(table.get(NetworkListKind::Block, 2).unwrap().is_none());
+        assert_eq!(table.by_kind(NetworkListKind::Block).unwrap(), [block]);
+        assert_eq!(table.by_kind(NetworkListKind::Allow).unwrap().len(), 1);
+
+        table.remove(NetworkListKind::Allow, 1).unwrap();
+        assert!(table.by_kind(NetworkListKind::Allow).unwrap().is_empty());
+    }
+
+    #[test]
+    fn unmatched_networks() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(Store::new(db_dir.path(), backup_dir.path()).unwrap());
+
+        let block_networks = store.block_network_map();
+        let mut ids = Vec::new();
+        for name in ["a", "b", "c"] {
+            let entry = BlockNetwork {
+                id: u32::MAX,
+                name: name.to_string(),
+                networks: HostNetworkGroup::default(),
+                description: String::new(),
+                expiration_time: None,
+            };
+            ids.push(block_networks.put(entry).unwrap());
+        }
+
+        let now = Utc::now();
+        let hits = |id, last_hit_time| NetworkHits {
+            kind: NetworkListKind::Block,
+            id,
+            count: 1,
+            last_hit_time,
+        };
+        store
+            .network_hits_map()
+            .record(&[hits(ids[0], now), hits(ids[1], now - Duration::days(30))])
+            .unwrap();
+
+        let since = now - Duration::days(7);
+        assert_eq!(
+            store
+                .unmatched_networks(NetworkListKind::Block, since)
+                .unwrap(),
+            [ids[1], ids[2]]
+        );
+        assert!(store
+            .unmatched_networks(NetworkListKind::Allow, since)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn removed_with_entries() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(Store::new(db_dir.path(), backup_dir.path()).unwrap());
+
+        let now = Utc::now();
+        let block_id = store
+            .block_network_map()
+            .put(BlockNetwork {
+                id: u32::MAX,
+                name: "block".to_string(),
+                networks: HostNetworkGroup::default(),
+                description: String::new(),
+                expiration_time: None,
+            })
+            .unwrap();
+        let allow_id = store
+            .allow_network_map()
+            .put(AllowNetwork {
+                id: u32::MAX,
+                name: "allow".to_string(),
+                networks: HostNetworkGroup::default(),
+                description: String::new(),
+                expiration_time: Some(now - Duration::hours(1)),
+            })
+            .unwrap();
+        let hits = |kind, id| NetworkHits {
+            kind,
+            id,
+            count: 1,
+            last_hit_time: now,
+        };
+        let table = store.network_hits_map();
+        table
+            .record(&[
+                hits(NetworkListKind::Block, block_id),
+                hits(NetworkListKind::Allow, allow_id),
+            ])
+            .unwrap();
+
+        store.block_network_map().remove(block_id).unwrap();
+        assert!(table
+            .get(NetworkListKind::Block, block_id)
+            .unwrap()
+            .is_none());
+        assert!(table
+            .get(NetworkListKind::Allow, allow_id)
+            .unwrap()
+            .is_some());
+
+        assert_eq!(
+            store.allow_network_map().remove_expired(now).unwrap(),
+            ["allow"]
+        );
+        assert!(table
+            .get(NetworkListKind::Allow, allow_id)
+            .unwrap()
+            .is_none());
+    }
+}
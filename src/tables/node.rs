@@ -1,13 +1,20 @@
 //! The `network` table.
 
-use std::{borrow::Cow, collections::HashMap, net::IpAddr};
+use std::{
+    borrow::Cow,
+    collections::{BTreeSet, HashMap},
+    net::IpAddr,
+};
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Context, Result};
 use chrono::{DateTime, Utc};
-use rocksdb::OptimisticTransactionDB;
+use rocksdb::{IteratorMode, OptimisticTransactionDB, Transaction};
 use serde::{Deserialize, Serialize};
 
-use crate::{types::FromKeyValue, Indexable, Indexed, IndexedMap, IndexedMapUpdate, IndexedTable};
+use super::NodeGroup;
+use crate::{
+    types::FromKeyValue, Indexable, Indexed, IndexedMap, IndexedMapUpdate, IndexedTable, EXCLUSIVE,
+};
 
 type PortNumber = u16;
 
@@ -72,6 +79,111 @@ pub struct Node {
     pub setting: Option<Setting>,
     pub setting_draft: Option<Setting>,
     pub creation_time: DateTime<Utc>,
+    /// When the draft was last applied.
+    pub applied_time: Option<DateTime<Utc>>,
+    /// The username of the account that last applied the draft.
+    pub applied_by: Option<String>,
+}
+
+/// A node in the format used until 0.26.
+#[derive(Deserialize, Serialize)]
+struct NodeV0_26 {
+    id: u32,
+    name: String,
+    name_draft: Option<String>,
+    setting: Option<Setting>,
+    setting_draft: Option<Setting>,
+    creation_time: DateTime<Utc>,
+}
+
+impl Node {
+    /// Deserializes a node stored in the format used until 0.26.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `value` is not a node in the old format.
+    pub(crate) fn from_v0_26(value: &[u8]) -> Result<Self> {
+        let old: NodeV0_26 = super::deserialize(value)?;
+        Ok(Self {
+            id: old.id,
+            name: old.name,
+            name_draft: old.name_draft,
+            setting: old.setting,
+            setting_draft: old.setting_draft,
+            creation_time: old.creation_time,
+            applied_time: None,
+            applied_by: None,
+        })
+    }
+
+    /// Returns `true` if the node has a draft not applied yet.
+    #[must_use]
+    pub fn has_draft(&self) -> bool {
+        self.name_draft.is_some() || self.setting_draft.is_some()
+    }
+
+    /// Returns the differences between the applied configuration and the
+    /// draft, one for each field the draft changes. The name is reported as
+    /// the field `name`, and the fields of `Setting` by their names.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a setting cannot be serialized.
+    pub fn draft_diff(&self) -> Result<Vec<SettingDiff>> {
+        let mut diff = Vec::new();
+        if let Some(name_draft) = &self.name_draft {
+            if *name_draft != self.name {
+                diff.push(SettingDiff {
+                    field: "name".to_string(),
+                    applied: serde_json::Value::String(self.name.clone()),
+                    draft: serde_json::Value::String(name_draft.clone()),
+                });
+            }
+        }
+        let Some(draft) = &self.setting_draft else {
+            return Ok(diff);
+        };
+        let applied = setting_fields(self.setting.as_ref())?;
+        for (field, draft) in setting_fields(Some(draft))? {
+            let applied = applied.get(&field).cloned().unwrap_or_default();
+            if applied != draft {
+                diff.push(SettingDiff {
+                    field,
+                    applied,
+                    draft,
+                });
+            }
+        }
+        Ok(diff)
+    }
+}
+
+/// Returns the fields of `setting` by their names, or an empty map if
+/// `setting` is `None`.
+fn setting_fields(setting: Option<&Setting>) -> Result<serde_json::Map<String, serde_json::Value>> {
+    match setting.map(serde_json::to_value).transpose()? {
+        Some(serde_json::Value::Object(fields)) => Ok(fields),
+        _ => Ok(serde_json::Map::new()),
+    }
+}
+
+/// A field of the configuration of a node that differs between the applied
+/// configuration and the draft, returned by [`Node::draft_diff`]. The values
+/// are `null` if there is no applied configuration.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SettingDiff {
+    pub field: String,
+    pub applied: serde_json::Value,
+    pub draft: serde_json::Value,
+}
+
+/// Whether a configuration template could be applied to a node, returned by
+/// `IndexedTable<Node>::apply_template`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TemplateResult {
+    pub node_id: u32,
+    /// Why the template couldn't be applied to the node. `None` if it could.
+    pub error: Option<String>,
 }
 
 impl FromKeyValue for Node {
@@ -100,6 +212,45 @@ impl Indexable for Node {
     fn set_index(&mut self, index: u32) {
         self.id = index;
     }
+
+    /// Removes the node from the node groups it belongs to.
+    fn remove_references(
+        db: &OptimisticTransactionDB,
+        txn: &Transaction<OptimisticTransactionDB>,
+        id: u32,
+        _key: &[u8],
+    ) -> Result<()> {
+        let groups = db
+            .cf_handle(super::NODE_GROUPS)
+            .context("node group table must be present")?;
+        let mut keys = Vec::new();
+        for item in txn.iterator_cf(groups, IteratorMode::Start) {
+            let (key, value) = item.context("cannot read node group")?;
+            // The entry with the empty key is the index of the table.
+            if key.is_empty() {
+                continue;
+            }
+            if NodeGroup::from_key_value(&key, &value)?
+                .node_ids
+                .contains(&id)
+            {
+                keys.push(key);
+            }
+        }
+        for key in keys {
+            let Some(value) = txn
+                .get_for_update_cf(groups, &key, EXCLUSIVE)
+                .context("cannot read node group")?
+            else {
+                continue;
+            };
+            let mut group = NodeGroup::from_key_value(&key, &value)?;
+            group.node_ids.retain(|node_id| *node_id != id);
+            txn.put_cf(groups, &key, group.value())
+                .context("failed to update node group")?;
+        }
+        Ok(())
+    }
 }
 
 /// Functions for the `node` indexed map.
@@ -117,6 +268,153 @@ impl<'d> IndexedTable<'d, Node> {
         &self.indexed_map
     }
 
+    /// Replaces the draft of the node with `id`, leaving the applied
+    /// configuration unchanged. `None` means the draft doesn't change the
+    /// name or the setting, respectively.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the `id` is invalid, the node changes while
+    /// staging, or the database operation fails.
+    pub fn stage(
+        &self,
+        id: u32,
+        name_draft: Option<String>,
+        setting_draft: Option<Setting>,
+    ) -> Result<()> {
+        let node = self.get_by_id(id)?.ok_or_else(|| anyhow!("no such node"))?;
+        let new = Update {
+            name: None,
+            name_draft,
+            setting: node.setting.clone(),
+            setting_draft,
+        };
+        self.indexed_map.update(id, &Update::from(node), &new)
+    }
+
+    /// Returns the differences between the applied configuration of the node
+    /// with `id` and its draft.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the `id` is invalid or the database operation fails.
+    pub fn draft_diff(&self, id: u32) -> Result<Vec<SettingDiff>> {
+        self.get_by_id(id)?
+            .ok_or_else(|| anyhow!("no such node"))?
+            .draft_diff()
+    }
+
+    /// Applies the draft of the node with `id`, recording the time and
+    /// `username` of the account applying it, and returns the updated node.
+    /// The draft must be the same as `reviewed`, the node whose draft was
+    /// reviewed, so that a draft changed after the review isn't applied.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the `id` is invalid, the node has no draft, the
+    /// draft differs from the reviewed one, or the database operation fails.
+    pub fn apply_draft(&self, id: u32, reviewed: &Node, username: &str) -> Result<Node> {
+        if !reviewed.has_draft() {
+            bail!("no draft to apply");
+        }
+        let apply = ApplyDraft {
+            name_draft: reviewed.name_draft.clone(),
+            setting_draft: reviewed.setting_draft.clone(),
+            time: Utc::now(),
+            username: username.to_string(),
+        };
+        self.indexed_map.update(id, &apply, &apply)?;
+        self.get_by_id(id)?.ok_or_else(|| anyhow!("no such node"))
+    }
+
+    /// Applies `template`, the values of the fields of `Setting` by their
+    /// names, to the applied configuration of every node in `group`,
+    /// recording the time and `username` of the account applying it. The
+    /// fields not in `template` are kept as they are in each node.
+    ///
+    /// The nodes are updated in a single transaction; if the template cannot
+    /// be applied to any of them, none is updated. The returned results tell
+    /// whether the template was applied to each node, and why not. When the
+    /// template cannot be applied to some nodes, the others are reported as
+    /// not applied as well.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `template` has a field `Setting` doesn't have, a
+    /// node changes while applying, or the database operation fails.
+    pub fn apply_template(
+        &self,
+        group: &NodeGroup,
+        template: &serde_json::Map<String, serde_json::Value>,
+        username: &str,
+    ) -> Result<Vec<TemplateResult>> {
+        let known = setting_fields(Some(&Setting::default()))?;
+        if let Some(field) = template.keys().find(|field| !known.contains_key(*field)) {
+            bail!("unknown setting field: {field}");
+        }
+
+        let time = Utc::now();
+        let node_ids: BTreeSet<u32> = group.node_ids.iter().copied().collect();
+        let mut results = Vec::with_capacity(node_ids.len());
+        let mut updates = Vec::with_capacity(node_ids.len());
+        for node_id in node_ids {
+            let error = match self.template_update(node_id, template, time, username)? {
+                Ok(update) => {
+                    updates.push((node_id, update));
+                    None
+                }
+                Err(reason) => Some(reason),
+            };
+            results.push(TemplateResult { node_id, error });
+        }
+        if updates.len() == results.len() {
+            let updates: Vec<_> = updates
+                .iter()
+                .map(|(id, update)| (*id, update, update))
+                .collect();
+            self.indexed_map.update_many(&updates)?;
+        } else {
+            for result in &mut results {
+                result.error.get_or_insert_with(|| {
+                    "not applied because the template cannot be applied to other nodes".to_string()
+                });
+            }
+        }
+        Ok(results)
+    }
+
+    /// Returns the update applying `template` to the node with `id`, or why
+    /// the template cannot be applied to it.
+    fn template_update(
+        &self,
+        id: u32,
+        template: &serde_json::Map<String, serde_json::Value>,
+        time: DateTime<Utc>,
+        username: &str,
+    ) -> Result<Result<ApplyTemplate, String>> {
+        let Some(node) = self.get_by_id(id)? else {
+            return Ok(Err("no such node".to_string()));
+        };
+        if node.has_draft() {
+            return Ok(Err("node has a draft not applied yet".to_string()));
+        }
+        let Some(applied) = node.setting else {
+            return Ok(Err("node has no applied configuration".to_string()));
+        };
+        let mut fields = setting_fields(Some(&applied))?;
+        fields.extend(template.clone());
+        let setting = match serde_json::from_value(serde_json::Value::Object(fields)) {
+            Ok(setting) => setting,
+            Err(e) => return Ok(Err(format!("invalid template: {e}"))),
+        };
+        Ok(Ok(ApplyTemplate {
+            applied: Some(applied),
+            setting,
+            time,
+            username: username.to_string(),
+        }))
+    }
+
     /// Updates the `Node` from `old` to `new`, given `id`.
     ///
     /// # Errors
@@ -145,6 +443,68 @@ impl From<Node> for Update {
     }
 }
 
+/// Moves the draft of a node into its applied configuration.
+struct ApplyDraft {
+    name_draft: Option<String>,
+    setting_draft: Option<Setting>,
+    time: DateTime<Utc>,
+    username: String,
+}
+
+impl IndexedMapUpdate for ApplyDraft {
+    type Entry = Node;
+
+    fn key(&self) -> Option<Cow<[u8]>> {
+        self.name_draft
+            .as_deref()
+            .map(|n| Cow::Borrowed(n.as_bytes()))
+    }
+
+    fn apply(&self, mut value: Self::Entry) -> Result<Self::Entry, anyhow::Error> {
+        if let Some(name) = value.name_draft.take() {
+            value.name = name;
+        }
+        if let Some(setting) = value.setting_draft.take() {
+            value.setting = Some(setting);
+        }
+        value.applied_time = Some(self.time);
+        value.applied_by = Some(self.username.clone());
+        Ok(value)
+    }
+
+    fn verify(&self, value: &Self::Entry) -> bool {
+        self.name_draft == value.name_draft && self.setting_draft == value.setting_draft
+    }
+}
+
+/// Replaces the applied configuration of a node with one made from a
+/// template.
+struct ApplyTemplate {
+    applied: Option<Setting>,
+    setting: Setting,
+    time: DateTime<Utc>,
+    username: String,
+}
+
+impl IndexedMapUpdate for ApplyTemplate {
+    type Entry = Node;
+
+    fn key(&self) -> Option<Cow<[u8]>> {
+        None
+    }
+
+    fn apply(&self, mut value: Self::Entry) -> Result<Self::Entry, anyhow::Error> {
+        value.setting = Some(self.setting.clone());
+        value.applied_time = Some(self.time);
+        value.applied_by = Some(self.username.clone());
+        Ok(value)
+    }
+
+    fn verify(&self, value: &Self::Entry) -> bool {
+        !value.has_draft() && self.applied == value.setting
+    }
+}
+
 impl IndexedMapUpdate for Update {
     type Entry = Node;
 
@@ -177,3 +537,150 @@ impl IndexedMapUpdate for Update {
         self.setting_draft == value.setting_draft
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::Utc;
+
+    use crate::{Node, NodeGroup, NodeGroupKind, NodeSetting, Store};
+
+    fn node(name: &str, hostname: &str) -> Node {
+        Node {
+            id: u32::MAX,
+            name: name.to_string(),
+            name_draft: None,
+            setting: Some(NodeSetting {
+                hostname: hostname.to_string(),
+                ..NodeSetting::default()
+            }),
+            setting_draft: None,
+            creation_time: Utc::now(),
+            applied_time: None,
+            applied_by: None,
+        }
+    }
+
+    #[test]
+    fn draft_and_apply() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(Store::new(db_dir.path(), backup_dir.path()).unwrap());
+        let table = store.node_map();
+
+        let setting = NodeSetting {
+            hostname: "host1".to_string(),
+            ..NodeSetting::default()
+        };
+        let node = Node {
+            id: u32::MAX,
+            name: "node1".to_string(),
+            name_draft: None,
+            setting: Some(setting.clone()),
+            setting_draft: None,
+            creation_time: Utc::now(),
+            applied_time: None,
+            applied_by: None,
+        };
+        let id = table.put(node).unwrap();
+
+        let reviewed = table.get_by_id(id).unwrap().unwrap();
+        assert!(table.apply_draft(id, &reviewed, "admin").is_err());
+
+        let draft = NodeSetting {
+            hostname: "host2".to_string(),
+            review: true,
+            ..setting
+        };
+        table
+            .stage(id, Some("node2".to_string()), Some(draft.clone()))
+            .unwrap();
+        let diff = table.draft_diff(id).unwrap();
+        let fields: Vec<_> = diff.iter().map(|d| d.field.as_str()).collect();
+        assert_eq!(fields, ["name", "hostname", "review"]);
+        assert_eq!(diff[1].applied, "host1");
+        assert_eq!(diff[1].draft, "host2");
+
+        let reviewed = table.get_by_id(id).unwrap().unwrap();
+        table
+            .stage(id, Some("node3".to_string()), Some(draft.clone()))
+            .unwrap();
+        assert!(table.apply_draft(id, &reviewed, "admin").is_err());
+
+        let reviewed = table.get_by_id(id).unwrap().unwrap();
+        let applied = table.apply_draft(id, &reviewed, "admin").unwrap();
+        assert_eq!(applied.name, "node3");
+        assert!(applied.setting == Some(draft));
+        assert!(!applied.has_draft());
+        assert_eq!(applied.applied_by.as_deref(), Some("admin"));
+        assert!(applied.applied_time.is_some());
+        assert!(table.draft_diff(id).unwrap().is_empty());
+    }
+
+    #[test]
+    fn apply_template() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(Store::new(db_dir.path(), backup_dir.path()).unwrap());
+        let table = store.node_map();
+
+        let id1 = table.put(node("node1", "host1")).unwrap();
+        let id2 = table.put(node("node2", "host2")).unwrap();
+        let group = NodeGroup {
+            id: u32::MAX,
+            name: "site1".to_string(),
+            kind: NodeGroupKind::Site,
+            description: String::new(),
+            node_ids: vec![id1, id2],
+            creation_time: Utc::now(),
+        };
+        let group_id = store.node_group_map().put(group).unwrap();
+        let group = store.node_group_map().get_by_id(group_id).unwrap().unwrap();
+        assert_eq!(
+            store.node_group_map().groups_of(id2).unwrap(),
+            [group.clone()]
+        );
+
+        let template = serde_json::json!({"review": true, "retention_period": 30});
+        let template = template.as_object().unwrap();
+        let results = table.apply_template(&group, template, "admin").unwrap();
+        assert!(results.iter().all(|result| result.error.is_none()));
+        for (id, hostname) in [(id1, "host1"), (id2, "host2")] {
+            let node = table.get_by_id(id).unwrap().unwrap();
+            let setting = node.setting.unwrap();
+            assert!(setting.review);
+            assert_eq!(setting.retention_period, Some(30));
+            assert_eq!(setting.hostname, hostname);
+            assert_eq!(node.applied_by.as_deref(), Some("admin"));
+        }
+
+        let unknown = serde_json::json!({"no_such_field": true});
+        assert!(table
+            .apply_template(&group, unknown.as_object().unwrap(), "admin")
+            .is_err());
+
+        table.stage(id2, Some("node3".to_string()), None).unwrap();
+        let template = serde_json::json!({"review": false});
+        let template = template.as_object().unwrap();
+        let results = table.apply_template(&group, template, "admin").unwrap();
+        assert_eq!(results[0].node_id, id1);
+        assert_eq!(
+            results[0].error.as_deref(),
+            Some("not applied because the template cannot be applied to other nodes")
+        );
+        assert_eq!(results[1].node_id, id2);
+        assert_eq!(
+            results[1].error.as_deref(),
+            Some("node has a draft not applied yet")
+        );
+        let node1 = table.get_by_id(id1).unwrap().unwrap();
+        assert!(node1.setting.unwrap().review);
+
+        // A removed node is removed from its groups.
+        table.remove(id1).unwrap();
+        let group = store.node_group_map().get_by_id(group_id).unwrap().unwrap();
+        assert_eq!(group.node_ids, [id2]);
+        assert!(store.node_group_map().groups_of(id1).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,167 @@
+//! The `node group` table.
+
+use std::borrow::Cow;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use rocksdb::OptimisticTransactionDB;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    types::FromKeyValue, Direction, Indexable, Indexed, IndexedMap, IndexedMapUpdate, IndexedTable,
+    Iterable,
+};
+
+/// What the nodes in a group have in common.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Kind {
+    /// The nodes are at the same site.
+    Site,
+    /// The nodes play the same role.
+    Role,
+}
+
+/// A named group of nodes, whose configuration can be changed at once with
+/// `IndexedTable<Node>::apply_template`.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct NodeGroup {
+    pub id: u32,
+    pub name: String,
+    pub kind: Kind,
+    pub description: String,
+    pub node_ids: Vec<u32>,
+    pub creation_time: DateTime<Utc>,
+}
+
+impl FromKeyValue for NodeGroup {
+    fn from_key_value(_key: &[u8], value: &[u8]) -> Result<Self> {
+        super::deserialize(value)
+    }
+}
+
+impl Indexable for NodeGroup {
+    fn key(&self) -> Cow<[u8]> {
+        Cow::Borrowed(self.name.as_bytes())
+    }
+
+    fn index(&self) -> u32 {
+        self.id
+    }
+
+    fn make_indexed_key(key: Cow<[u8]>, _index: u32) -> Cow<[u8]> {
+        key
+    }
+
+    fn value(&self) -> Vec<u8> {
+        super::serialize(self).expect("serializable")
+    }
+
+    fn set_index(&mut self, index: u32) {
+        self.id = index;
+    }
+}
+
+/// Functions for the `node group` indexed map.
+impl<'d> IndexedTable<'d, NodeGroup> {
+    /// Opens the `node group` table in the database.
+    ///
+    /// Returns `None` if the table does not exist.
+    pub(super) fn open(db: &'d OptimisticTransactionDB) -> Option<Self> {
+        IndexedMap::new(db, super::NODE_GROUPS)
+            .map(IndexedTable::new)
+            .ok()
+    }
+
+    /// Gets the `NodeGroup`, given `name`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails.
+    pub fn get(&self, name: &str) -> Result<Option<NodeGroup>> {
+        let res = self.indexed_map.get_by_key(name.as_bytes())?;
+        res.map(|value| NodeGroup::from_key_value(name.as_bytes(), value.as_ref()))
+            .transpose()
+    }
+
+    /// Returns the groups the node with `node_id` belongs to.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails or any group is
+    /// invalid.
+    pub fn groups_of(&self, node_id: u32) -> Result<Vec<NodeGroup>> {
+        let mut groups = Vec::new();
+        for group in self.iter(Direction::Forward, None) {
+            let group = group?;
+            if group.node_ids.contains(&node_id) {
+                groups.push(group);
+            }
+        }
+        Ok(groups)
+    }
+
+    /// Updates the `NodeGroup` from `old` to `new`, given `id`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the `id` is invalid or the database operation fails.
+    pub fn update(&mut self, id: u32, old: &Update, new: &Update) -> Result<()> {
+        self.indexed_map.update(id, old, new)
+    }
+}
+
+#[derive(Clone)]
+pub struct Update {
+    pub name: Option<String>,
+    pub kind: Option<Kind>,
+    pub description: Option<String>,
+    pub node_ids: Option<Vec<u32>>,
+}
+
+impl IndexedMapUpdate for Update {
+    type Entry = NodeGroup;
+
+    fn key(&self) -> Option<Cow<[u8]>> {
+        self.name.as_deref().map(str::as_bytes).map(Cow::Borrowed)
+    }
+
+    fn apply(&self, mut value: Self::Entry) -> Result<Self::Entry, anyhow::Error> {
+        if let Some(v) = self.name.as_deref() {
+            value.name = v.to_string();
+        }
+        if let Some(v) = self.kind {
+            value.kind = v;
+        }
+        if let Some(v) = self.description.as_deref() {
+            value.description = v.to_string();
+        }
+        if let Some(v) = self.node_ids.as_ref() {
+            value.node_ids.clone_from(v);
+        }
+        Ok(value)
+    }
+
+    fn verify(&self, value: &Self::Entry) -> bool {
+        if let Some(v) = self.name.as_deref() {
+            if v != value.name {
+                return false;
+            }
+        }
+        if let Some(v) = self.kind {
+            if v != value.kind {
+                return false;
+            }
+        }
+        if let Some(v) = self.description.as_deref() {
+            if v != value.description {
+                return false;
+            }
+        }
+        if let Some(v) = self.node_ids.as_ref() {
+            if *v != value.node_ids {
+                return false;
+            }
+        }
+        true
+    }
+}
@@ -6,7 +6,7 @@ use rocksdb::OptimisticTransactionDB;
 
 use crate::{
     types::{FromKeyValue, Qualifier},
-    Indexable, Indexed, IndexedMap, IndexedMapUpdate, IndexedTable,
+    Indexable, Indexed, IndexedMap, IndexedMapUpdate, IndexedTable, Named,
 };
 
 // The following will be used when PostgreSQL qualifier table is deleted
@@ -49,6 +49,12 @@ impl Indexable for Qualifier {
     }
 }
 
+impl Named for Qualifier {
+    fn name(&self) -> &str {
+        &self.description
+    }
+}
+
 impl IndexedMapUpdate for Qualifier {
     type Entry = Qualifier;
 
@@ -3,11 +3,14 @@
 use std::{borrow::Cow, net::IpAddr};
 
 use anyhow::Result;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Datelike, Duration, NaiveDateTime, NaiveTime, Utc, Weekday};
 use rocksdb::OptimisticTransactionDB;
 use serde::{Deserialize, Serialize};
 
-use crate::{types::FromKeyValue, Indexable, Indexed, IndexedMap, IndexedMapUpdate, IndexedTable};
+use crate::{
+    types::FromKeyValue, Indexable, Indexed, IndexedMap, IndexedMapUpdate, IndexedTable,
+    IterableMap,
+};
 
 #[derive(Clone, Deserialize, Serialize)]
 pub struct SamplingPolicy {
@@ -23,6 +26,105 @@ pub struct SamplingPolicy {
     pub column: Option<u32>,
     pub immutable: bool,
     pub creation_time: DateTime<Utc>,
+    /// When the policy is active. `None` if it is always active.
+    pub schedule: Option<Schedule>,
+}
+
+/// A sampling policy in the format used until 0.26.
+#[derive(Deserialize, Serialize)]
+struct SamplingPolicyV0_26 {
+    id: u32,
+    name: String,
+    kind: Kind,
+    interval: Interval,
+    period: Period,
+    offset: i32,
+    src_ip: Option<IpAddr>,
+    dst_ip: Option<IpAddr>,
+    node: Option<String>,
+    column: Option<u32>,
+    immutable: bool,
+    creation_time: DateTime<Utc>,
+}
+
+impl SamplingPolicy {
+    /// Deserializes a sampling policy stored in the format used until 0.26.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `value` is not a sampling policy in the old format.
+    pub(crate) fn from_v0_26(value: &[u8]) -> Result<Self> {
+        let old: SamplingPolicyV0_26 = super::deserialize(value)?;
+        Ok(Self {
+            id: old.id,
+            name: old.name,
+            kind: old.kind,
+            interval: old.interval,
+            period: old.period,
+            offset: old.offset,
+            src_ip: old.src_ip,
+            dst_ip: old.dst_ip,
+            node: old.node,
+            column: old.column,
+            immutable: old.immutable,
+            creation_time: old.creation_time,
+            schedule: None,
+        })
+    }
+
+    /// Returns `true` if the policy is active at `time`.
+    #[must_use]
+    pub fn is_active_at(&self, time: DateTime<Utc>) -> bool {
+        self.schedule
+            .as_ref()
+            .map_or(true, |schedule| schedule.contains(time))
+    }
+}
+
+/// The times a sampling policy is active, repeated every week.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Schedule {
+    /// The offset from UTC, in seconds, of the time zone the windows are
+    /// given in.
+    pub utc_offset: i32,
+    pub windows: Vec<Window>,
+}
+
+impl Schedule {
+    /// Returns `true` if `time` is in any of the windows.
+    #[must_use]
+    pub fn contains(&self, time: DateTime<Utc>) -> bool {
+        let local = time.naive_utc() + Duration::seconds(self.utc_offset.into());
+        self.windows.iter().any(|window| window.contains(local))
+    }
+}
+
+/// A window of time in the days of the week it starts on.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Window {
+    /// The days of the week the window starts on. Every day if empty.
+    pub days: Vec<Weekday>,
+    /// When the window starts, inclusive.
+    pub start: NaiveTime,
+    /// When the window ends, exclusive. A window ending at or before its
+    /// start ends on the next day.
+    pub end: NaiveTime,
+}
+
+impl Window {
+    fn starts_on(&self, day: Weekday) -> bool {
+        self.days.is_empty() || self.days.contains(&day)
+    }
+
+    fn contains(&self, local: NaiveDateTime) -> bool {
+        let (day, time) = (local.weekday(), local.time());
+        if self.start < self.end {
+            self.starts_on(day) && self.start <= time && time < self.end
+        } else {
+            (self.starts_on(day) && self.start <= time)
+                || (self.starts_on(day.pred()) && time < self.end)
+        }
+    }
 }
 
 impl FromKeyValue for SamplingPolicy {
@@ -86,11 +188,32 @@ impl<'d> IndexedTable<'d, SamplingPolicy> {
     ///
     /// Returns `None` if the table does not exist.
     pub(super) fn open(db: &'d OptimisticTransactionDB) -> Option<Self> {
-        IndexedMap::new(db, super::NETWORKS)
+        IndexedMap::new(db, super::SAMPLING_POLICY)
             .map(IndexedTable::new)
             .ok()
     }
 
+    pub(crate) fn raw(&self) -> &IndexedMap<'_> {
+        &self.indexed_map
+    }
+
+    /// Returns the policies active at `time`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails or any policy is
+    /// invalid.
+    pub fn effective_at(&self, time: DateTime<Utc>) -> Result<Vec<SamplingPolicy>> {
+        let mut policies = Vec::new();
+        for (key, value) in self.indexed_map.iter_forward()? {
+            let policy = SamplingPolicy::from_key_value(&key, &value)?;
+            if policy.is_active_at(time) {
+                policies.push(policy);
+            }
+        }
+        Ok(policies)
+    }
+
     /// Updates the `SamplingPolicy` from `old` to `new`, given `id`.
     ///
     /// # Errors
@@ -113,6 +236,7 @@ pub struct Update {
     pub node: Option<String>,
     pub column: Option<u32>,
     pub immutable: bool,
+    pub schedule: Option<Schedule>,
 }
 
 impl From<SamplingPolicy> for Update {
@@ -128,6 +252,7 @@ impl From<SamplingPolicy> for Update {
             node: input.node,
             column: input.column,
             immutable: input.immutable,
+            schedule: input.schedule,
         }
     }
 }
@@ -157,6 +282,8 @@ impl IndexedMapUpdate for Update {
 
         value.immutable = self.immutable;
 
+        value.schedule.clone_from(&self.schedule);
+
         Ok(value)
     }
 
@@ -201,6 +328,10 @@ impl IndexedMapUpdate for Update {
             return false;
         }
 
+        if self.schedule != value.schedule {
+            return false;
+        }
+
         true
     }
 }
@@ -209,8 +340,11 @@ impl IndexedMapUpdate for Update {
 mod test {
     use std::sync::Arc;
 
+    use chrono::{NaiveTime, Weekday};
+
     use crate::{
-        SamplingInterval, SamplingKind, SamplingPeriod, SamplingPolicy, SamplingPolicyUpdate, Store,
+        SamplingInterval, SamplingKind, SamplingPeriod, SamplingPolicy, SamplingPolicyUpdate,
+        SamplingSchedule, SamplingWindow, Store,
     };
 
     #[test]
@@ -231,6 +365,60 @@ mod test {
         assert_eq!(entry.map(|e| e.name), Some("b".to_string()));
     }
 
+    #[test]
+    fn effective_at() {
+        let store = setup_store();
+        let table = store.sampling_policy_map();
+
+        table.put(create_sampling_policy("always")).unwrap();
+        let business_hours = SamplingWindow {
+            days: vec![
+                Weekday::Mon,
+                Weekday::Tue,
+                Weekday::Wed,
+                Weekday::Thu,
+                Weekday::Fri,
+            ],
+            start: NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
+            end: NaiveTime::from_hms_opt(18, 0, 0).unwrap(),
+        };
+        let mut daytime = create_sampling_policy("daytime");
+        // UTC+09:00
+        daytime.schedule = Some(SamplingSchedule {
+            utc_offset: 9 * 3600,
+            windows: vec![business_hours],
+        });
+        table.put(daytime).unwrap();
+        let overnight = SamplingWindow {
+            days: vec![Weekday::Fri],
+            start: NaiveTime::from_hms_opt(22, 0, 0).unwrap(),
+            end: NaiveTime::from_hms_opt(6, 0, 0).unwrap(),
+        };
+        let mut nighttime = create_sampling_policy("nighttime");
+        nighttime.schedule = Some(SamplingSchedule {
+            utc_offset: 0,
+            windows: vec![overnight],
+        });
+        table.put(nighttime).unwrap();
+
+        let names = |time: &str| -> Vec<String> {
+            let time = time.parse().unwrap();
+            table
+                .effective_at(time)
+                .unwrap()
+                .into_iter()
+                .map(|policy| policy.name)
+                .collect()
+        };
+        // Friday 10:00 in UTC+09:00
+        assert_eq!(names("2024-03-08T01:00:00Z"), ["always", "daytime"]);
+        // Friday 19:00 in UTC+09:00
+        assert_eq!(names("2024-03-08T10:00:00Z"), ["always"]);
+        assert_eq!(names("2024-03-08T23:00:00Z"), ["always", "nighttime"]);
+        assert_eq!(names("2024-03-09T05:59:59Z"), ["always", "nighttime"]);
+        assert_eq!(names("2024-03-09T06:00:00Z"), ["always"]);
+    }
+
     fn setup_store() -> Arc<Store> {
         let db_dir = tempfile::tempdir().unwrap();
         let backup_dir = tempfile::tempdir().unwrap();
@@ -251,6 +439,7 @@ mod test {
             column: None,
             immutable: false,
             creation_time: chrono::Utc::now(),
+            schedule: None,
         }
     }
 }
@@ -1,5 +1,7 @@
 //! The `scores` table.
 
+use std::collections::HashSet;
+
 use anyhow::Result;
 use rocksdb::OptimisticTransactionDB;
 
@@ -37,6 +39,16 @@ impl<'d> Table<'d, Scores> {
         let scores = Scores::new(model, value);
         Ok(Some(scores))
     }
+
+    /// Deletes the scores of the models not in `models`, and returns the
+    /// number of models whose scores are deleted.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any of database operation fails.
+    pub fn delete_orphans(&self, models: &HashSet<i32>) -> Result<usize> {
+        super::delete_orphans(&self.map, models, |key| super::deserialize(key).ok())
+    }
 }
 
 #[cfg(test)]
@@ -81,4 +93,21 @@ mod tests {
             assert_eq!(table.get(*id).unwrap(), None);
         }
     }
+
+    #[test]
+    fn delete_orphans() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(Store::new(db_dir.path(), backup_dir.path()).unwrap());
+        let table = store.scores_map();
+
+        for id in [1, 2, 300] {
+            table.put(&Scores::new(id, ModelScores::default())).unwrap();
+        }
+        let models = [2].into_iter().collect();
+        assert_eq!(table.delete_orphans(&models).unwrap(), 2);
+        assert!(table.get(1).unwrap().is_none());
+        assert!(table.get(2).unwrap().is_some());
+        assert!(table.get(300).unwrap().is_none());
+    }
 }
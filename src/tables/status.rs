@@ -6,7 +6,7 @@ use rocksdb::OptimisticTransactionDB;
 
 use crate::{
     types::{FromKeyValue, Status},
-    Indexable, Indexed, IndexedMap, IndexedMapUpdate, IndexedTable,
+    Indexable, Indexed, IndexedMap, IndexedMapUpdate, IndexedTable, Named,
 };
 
 // The following will be used when PostgreSQL status table is deleted
@@ -44,6 +44,12 @@ impl Indexable for Status {
     }
 }
 
+impl Named for Status {
+    fn name(&self) -> &str {
+        &self.description
+    }
+}
+
 impl IndexedMapUpdate for Status {
     type Entry = Status;
 
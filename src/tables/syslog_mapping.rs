@@ -0,0 +1,190 @@
+//! The `syslog mappings` table.
+
+use anyhow::{bail, Result};
+use rocksdb::OptimisticTransactionDB;
+use serde::{Deserialize, Serialize};
+
+use crate::{types::FromKeyValue, EventCategory, EventKind, Map, Table};
+
+/// The facility used for an event without a syslog mapping, `local0`.
+pub const DEFAULT_SYSLOG_FACILITY: u8 = 16;
+
+/// The events to which a syslog mapping applies.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum SyslogTarget {
+    /// The events in the category.
+    Category(EventCategory),
+    /// The events of the kind, e.g., "dns covert channel", as returned by
+    /// [`EventKind::name`]. A mapping for a kind takes precedence over the one
+    /// for its category. Custom detections, which have no fixed kind, can be
+    /// mapped only by category.
+    Kind(String),
+}
+
+/// The syslog facility and severity of events.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SyslogMapping {
+    pub target: SyslogTarget,
+    /// The facility, from 0 (kernel) to 23 (local7).
+    pub facility: u8,
+    /// The severity, from 0 (emergency) to 7 (debug).
+    pub severity: u8,
+}
+
+impl SyslogMapping {
+    /// Returns the syslog priority, the facility multiplied by 8 plus the
+    /// severity. Out-of-range values are clamped to the largest valid ones.
+    #[must_use]
+    pub fn priority(&self) -> u8 {
+        self.facility.min(23) * 8 + self.severity.min(7)
+    }
+}
+
+/// The value of an entry in the table.
+#[derive(Deserialize, Serialize)]
+struct Priority {
+    facility: u8,
+    severity: u8,
+}
+
+impl SyslogMapping {
+    fn into_key_value(self) -> Result<(Vec<u8>, Vec<u8>)> {
+        let key = super::serialize(&self.target)?;
+        let value = super::serialize(&Priority {
+            facility: self.facility,
+            severity: self.severity,
+        })?;
+        Ok((key, value))
+    }
+}
+
+impl FromKeyValue for SyslogMapping {
+    fn from_key_value(key: &[u8], value: &[u8]) -> Result<Self> {
+        let target = super::deserialize(key)?;
+        let value: Priority = super::deserialize(value)?;
+        Ok(Self {
+            target,
+            facility: value.facility,
+            severity: value.severity,
+        })
+    }
+}
+
+/// Functions for the `syslog mappings` map.
+impl<'d> Table<'d, SyslogMapping> {
+    /// Opens the `syslog mappings` map in the database.
+    ///
+    /// Returns `None` if the map does not exist.
+    pub(super) fn open(db: &'d OptimisticTransactionDB) -> Option<Self> {
+        Map::open(db, super::SYSLOG_MAPPINGS).map(Table::new)
+    }
+
+    /// Stores `mapping`, replacing the one for the same target if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the target is a kind that isn't the name of any
+    /// [`EventKind`], or the database operation fails.
+    pub fn put(&self, mapping: SyslogMapping) -> Result<()> {
+        if let SyslogTarget::Kind(kind) = &mapping.target {
+            if EventKind::from_name(kind).is_none() {
+                bail!("unknown event kind: {kind}");
+            }
+        }
+        let (key, value) = mapping.into_key_value()?;
+        self.map.put(&key, &value)
+    }
+
+    /// Returns the mapping for `target`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails or the mapping
+    /// cannot be deserialized.
+    pub fn get(&self, target: &SyslogTarget) -> Result<Option<SyslogMapping>> {
+        let key = super::serialize(target)?;
+        let Some(value) = self.map.get(&key)? else {
+            return Ok(None);
+        };
+        SyslogMapping::from_key_value(&key, value.as_ref()).map(Some)
+    }
+
+    /// Deletes the mapping for `target`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the mapping does not exist or the database
+    /// operation fails.
+    pub fn delete(&self, target: &SyslogTarget) -> Result<()> {
+        self.map.delete(&super::serialize(target)?)
+    }
+
+    /// Returns the mapping that applies to the events of `kind` in
+    /// `category`: the one for the kind if any, or else the one for the
+    /// category.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails or a mapping cannot
+    /// be deserialized.
+    pub fn resolve(&self, kind: &str, category: EventCategory) -> Result<Option<SyslogMapping>> {
+        if let Some(mapping) = self.get(&SyslogTarget::Kind(kind.to_string()))? {
+            return Ok(Some(mapping));
+        }
+        self.get(&SyslogTarget::Category(category))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{EventCategory, Store, SyslogMapping, SyslogTarget};
+
+    #[test]
+    fn resolve() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(Store::new(db_dir.path(), backup_dir.path()).unwrap());
+        let table = store.syslog_mapping_map();
+
+        let category = SyslogMapping {
+            target: SyslogTarget::Category(EventCategory::CommandAndControl),
+            facility: 16,
+            severity: 4,
+        };
+        let kind = SyslogMapping {
+            target: SyslogTarget::Kind("dns covert channel".to_string()),
+            facility: 17,
+            severity: 2,
+        };
+        table.put(category.clone()).unwrap();
+        table.put(kind.clone()).unwrap();
+        assert!(table
+            .put(SyslogMapping {
+                target: SyslogTarget::Kind("dns covert chanel".to_string()),
+                facility: 17,
+                severity: 2,
+            })
+            .is_err());
+        assert_eq!(table.get(&category.target).unwrap(), Some(category.clone()));
+        assert_eq!(kind.priority(), 138);
+
+        let resolved = |kind| {
+            table
+                .resolve(kind, EventCategory::CommandAndControl)
+                .unwrap()
+        };
+        assert_eq!(resolved("dns covert channel"), Some(kind.clone()));
+        assert_eq!(resolved("tor exit nodes"), Some(category.clone()));
+        assert_eq!(
+            table
+                .resolve("port scan", EventCategory::Reconnaissance)
+                .unwrap(),
+            None
+        );
+
+        table.delete(&kind.target).unwrap();
+        assert_eq!(resolved("dns covert channel"), Some(category));
+    }
+}
@@ -2,11 +2,15 @@
 
 use std::borrow::Cow;
 
-use anyhow::Result;
-use rocksdb::OptimisticTransactionDB;
+use anyhow::{bail, Context, Result};
+use rocksdb::{IteratorMode, OptimisticTransactionDB};
 use serde::{Deserialize, Serialize};
 
-use crate::{types::FromKeyValue, Map, Table, UniqueKey};
+use crate::{
+    notification::{notify, ChangeKind},
+    types::FromKeyValue,
+    Map, Named, Table, UniqueKey, EXCLUSIVE,
+};
 
 #[derive(Debug, Deserialize, Serialize, PartialEq)]
 pub enum Template {
@@ -29,6 +33,12 @@ impl Template {
     }
 }
 
+impl Named for Template {
+    fn name(&self) -> &str {
+        Template::name(self)
+    }
+}
+
 impl UniqueKey for Template {
     fn unique_key(&self) -> Cow<[u8]> {
         Cow::Borrowed(self.name().as_bytes())
@@ -85,10 +95,37 @@ impl<'d> Table<'d, Template> {
     ///
     /// # Errors
     ///
-    /// Returns an error if the serialization fails or the database operation fails.
+    /// Returns an error if the table is case-insensitive and another template
+    /// has the same name ignoring case, the serialization fails, or the
+    /// database operation fails.
     pub fn insert(&self, template: Template) -> Result<()> {
         let (key, value) = template.into_key_value()?;
-        self.map.put(&key, &value)
+        loop {
+            let txn = self.map.db.transaction();
+            let exists = txn
+                .get_for_update_cf(self.map.cf, &key, EXCLUSIVE)
+                .context("cannot read template")?
+                .is_some();
+            super::replace_lowercase_name(
+                self.map.db,
+                &txn,
+                super::TEMPLATES,
+                exists.then_some(key.as_slice()),
+                Some(&key),
+            )?;
+            txn.put_cf(self.map.cf, &key, &value)
+                .context("failed to write template")?;
+            match txn.commit() {
+                Ok(()) => break,
+                Err(e) => {
+                    if !e.as_ref().starts_with("Resource busy:") {
+                        return Err(e).context("failed to insert template");
+                    }
+                }
+            }
+        }
+        notify(self.map.notifier, ChangeKind::Update, &key);
+        Ok(())
     }
 
     /// Removes the `Template` with the given name.
@@ -97,18 +134,139 @@ impl<'d> Table<'d, Template> {
     ///
     /// Returns an error if the database operation fails.
     pub fn remove(&self, name: &str) -> Result<()> {
-        self.map.delete(name.as_bytes())
+        let key = name.as_bytes();
+        loop {
+            let txn = self.map.db.transaction();
+            if txn
+                .get_for_update_cf(self.map.cf, key, EXCLUSIVE)
+                .context("cannot read template")?
+                .is_some()
+            {
+                super::replace_lowercase_name(
+                    self.map.db,
+                    &txn,
+                    super::TEMPLATES,
+                    Some(key),
+                    None,
+                )?;
+            }
+            txn.delete_cf(self.map.cf, key)
+                .context("failed to delete template")?;
+            match txn.commit() {
+                Ok(()) => break,
+                Err(e) => {
+                    if !e.as_ref().starts_with("Resource busy:") {
+                        return Err(e).context("failed to remove template");
+                    }
+                }
+            }
+        }
+        notify(self.map.notifier, ChangeKind::Remove, key);
+        Ok(())
     }
 
     /// Updates the `Template` in the database.
     ///
     /// # Errors
     ///
-    /// Returns an error if the serialization fails or the database operation fails.
+    /// Returns an error if `old` doesn't match the stored template, the table
+    /// is case-insensitive and another template has the new name ignoring
+    /// case, the serialization fails, or the database operation fails.
     pub fn update(&self, old: Template, new: Template) -> Result<()> {
         let (ok, ov) = old.into_key_value()?;
         let (nk, nv) = new.into_key_value()?;
-        self.map.update((&ok, &ov), (&nk, &nv))
+        loop {
+            let txn = self.map.db.transaction();
+            let Some(value) = txn
+                .get_for_update_cf(self.map.cf, &ok, EXCLUSIVE)
+                .context("cannot read old entry")?
+            else {
+                bail!("no such entry");
+            };
+            if value != ov {
+                bail!("old value mismatch");
+            }
+            if ok != nk {
+                txn.delete_cf(self.map.cf, &ok)
+                    .context("failed to delete old entry")?;
+                if txn
+                    .get_for_update_cf(self.map.cf, &nk, EXCLUSIVE)
+                    .context("cannot read from database")?
+                    .is_some()
+                {
+                    bail!("new key already exists");
+                }
+                super::replace_lowercase_name(
+                    self.map.db,
+                    &txn,
+                    super::TEMPLATES,
+                    Some(&ok),
+                    Some(&nk),
+                )?;
+            }
+            txn.put_cf(self.map.cf, &nk, &nv)
+                .context("failed to write new entry")?;
+            match txn.commit() {
+                Ok(()) => break,
+                Err(e) => {
+                    if !e.as_ref().starts_with("Resource busy:") {
+                        return Err(e).context("failed to update entry");
+                    }
+                }
+            }
+        }
+        if ok != nk {
+            notify(self.map.notifier, ChangeKind::Remove, &ok);
+        }
+        notify(self.map.notifier, ChangeKind::Update, &nk);
+        Ok(())
+    }
+
+    /// Returns `true` if the names of templates are compared
+    /// case-insensitively.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails.
+    pub fn is_case_insensitive(&self) -> Result<bool> {
+        super::is_case_insensitive(self.map.db, super::TEMPLATES)
+    }
+
+    /// Sets whether the names of templates are compared case-insensitively.
+    ///
+    /// When enabled, inserting or renaming a template fails if another
+    /// template has the same name ignoring case, and
+    /// [`Table::find_by_name`] ignores case.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `enabled` is `true` but the table already has
+    /// templates whose names differ only in case, or the database operation
+    /// fails.
+    pub fn set_case_insensitive(&self, enabled: bool) -> Result<()> {
+        super::set_case_insensitive(self.map.db, super::TEMPLATES, enabled, |txn| {
+            txn.iterator_cf(self.map.cf, IteratorMode::Start)
+                .map(|item| {
+                    item.map(|(key, _)| key.into_vec())
+                        .context("cannot read templates")
+                })
+                .collect()
+        })
+    }
+
+    /// Returns the template with `name`, ignoring case if the table is
+    /// case-insensitive.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the template cannot be read.
+    pub fn find_by_name(&self, name: &str) -> Result<Option<Template>> {
+        let stored = super::stored_name(self.map.db, super::TEMPLATES, name)?;
+        let key = stored.as_deref().unwrap_or(name);
+        self.map
+            .get(key.as_bytes())?
+            .map(|value| Template::from_key_value(key.as_bytes(), value.as_ref()))
+            .transpose()
     }
 }
 
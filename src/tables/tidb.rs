@@ -1,14 +1,31 @@
 //! The `tidb` table.
 
-use std::io::{BufReader, Read};
+mod matcher;
+mod stix;
+
+use std::{
+    borrow::Cow,
+    io::{BufReader, Read},
+    net::IpAddr,
+    sync::Arc,
+};
 
 use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Duration, Utc};
 use data_encoding::BASE64;
 use flate2::read::GzDecoder;
-use rocksdb::{Direction, OptimisticTransactionDB};
+use ipnet::IpNet;
+use rocksdb::{Direction, IteratorMode, OptimisticTransactionDB};
 use serde::{Deserialize, Serialize};
 
-use crate::{types::FromKeyValue, Iterable, Map, Table, UniqueKey};
+pub use self::matcher::{Match, Matcher};
+pub use self::stix::Import as StixImport;
+use super::compiled_regex;
+use crate::{
+    notification::{notify, ChangeKind},
+    types::FromKeyValue,
+    Iterable, Map, ModelIndicator, Table, TiCmpKind, UniqueKey, EXCLUSIVE,
+};
 
 #[derive(Clone, Deserialize, Serialize)]
 pub struct Tidb {
@@ -20,9 +37,33 @@ pub struct Tidb {
     pub patterns: Vec<Rule>,
 }
 
+/// A TI database in the format used until 0.26.
+#[derive(Deserialize, Serialize)]
+struct TidbV0_26 {
+    id: u32,
+    name: String,
+    description: Option<String>,
+    kind: Kind,
+    version: String,
+    patterns: Vec<RuleV0_26>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct RuleV0_26 {
+    rule_id: u32,
+    name: String,
+    description: Option<String>,
+    references: Option<Vec<String>>,
+    samples: Option<Vec<String>>,
+    signatures: Option<Vec<String>>,
+}
+
 impl Tidb {
     /// Parses and validates input TI database
     ///
+    /// A TI database in the format used until 0.26, whose rules have no
+    /// confidence or valid-until time, is also accepted.
+    ///
     /// # Errors
     ///
     /// * Returns an error if it fails to decode or uncompress input TI database
@@ -33,18 +74,51 @@ impl Tidb {
         let mut buf = Vec::new();
         let mut reader = BufReader::new(decoder);
         reader.read_to_end(&mut buf)?;
-        let tidb: Tidb = super::deserialize(&buf).context("invalid value in database")?;
+        let tidb: Tidb = match super::deserialize(&buf) {
+            Ok(tidb) => tidb,
+            Err(_) => Self::from_v0_26(&buf).context("invalid value in database")?,
+        };
         tidb.validate()?;
         Ok(tidb)
     }
 
+    /// Deserializes a TI database stored in the format used until 0.26.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `value` is not a TI database in the old format.
+    pub(crate) fn from_v0_26(value: &[u8]) -> Result<Self> {
+        let old: TidbV0_26 = super::deserialize(value)?;
+        Ok(Self {
+            id: old.id,
+            name: old.name,
+            description: old.description,
+            kind: old.kind,
+            version: old.version,
+            patterns: old
+                .patterns
+                .into_iter()
+                .map(|rule| Rule {
+                    rule_id: rule.rule_id,
+                    name: rule.name,
+                    description: rule.description,
+                    references: rule.references,
+                    samples: rule.samples,
+                    signatures: rule.signatures,
+                    confidence: None,
+                    valid_until: None,
+                })
+                .collect(),
+        })
+    }
+
     fn into_key_value(self) -> Result<(Vec<u8>, Vec<u8>)> {
         let key = self.name.as_bytes().to_owned();
 
         Ok((key, super::serialize(&self)?))
     }
 
-    fn validate(&self) -> Result<()> {
+    pub(crate) fn validate(&self) -> Result<()> {
         if self.id == 0 {
             bail!("invalid db id");
         } else if self.name.trim().is_empty() {
@@ -52,6 +126,19 @@ impl Tidb {
         } else if self.version.trim().is_empty() {
             bail!("db version is required");
         }
+        if matches!(self.kind, Kind::Url | Kind::Token) {
+            // An empty signature would be contained in every value.
+            for rule in &self.patterns {
+                if rule
+                    .signatures
+                    .iter()
+                    .flatten()
+                    .any(|s| s.trim().is_empty())
+                {
+                    bail!("rule {} has an empty signature", rule.rule_id);
+                }
+            }
+        }
         Ok(())
     }
 
@@ -59,6 +146,275 @@ impl Tidb {
     pub fn patterns(&self) -> String {
         format!("{} rules", self.patterns.len())
     }
+
+    /// Returns `true` if `value` matches a signature of any rule.
+    ///
+    /// A signature of an `Ip` database is an IP address or a network; that of
+    /// a `Url` or `Token` database matches a value containing it, ignoring
+    /// case; and that of a `Regex` database is a regular expression. An
+    /// invalid signature, including an empty one in a `Url` or `Token`
+    /// database, never matches.
+    #[must_use]
+    pub fn matches(&self, value: &str) -> bool {
+        self.is_match(value, None)
+    }
+
+    /// Returns `true` if `value` matches a signature of any rule valid at
+    /// `now`, skipping the expired ones. Otherwise, this is the same as
+    /// [`matches`](Self::matches).
+    #[must_use]
+    pub fn matches_at(&self, value: &str, now: DateTime<Utc>) -> bool {
+        self.is_match(value, Some(now))
+    }
+
+    /// Returns the rules valid at `now`, or all the rules if `now` is `None`.
+    fn rules(&self, now: Option<DateTime<Utc>>) -> impl Iterator<Item = &Rule> {
+        self.patterns
+            .iter()
+            .filter(move |rule| now.map_or(true, |now| !rule.is_expired(now)))
+    }
+
+    fn is_match(&self, value: &str, now: Option<DateTime<Utc>>) -> bool {
+        let candidate = Candidate::new(value);
+        self.rules(now)
+            .any(|rule| self.rule_matches(rule, &candidate))
+    }
+
+    fn rule_matches(&self, rule: &Rule, candidate: &Candidate) -> bool {
+        rule.signatures
+            .iter()
+            .flatten()
+            .any(|signature| match self.kind {
+                Kind::Ip => {
+                    let Some(addr) = candidate.addr else {
+                        return false;
+                    };
+                    if let Ok(network) = signature.parse::<IpNet>() {
+                        network.contains(&addr)
+                    } else {
+                        signature.parse::<IpAddr>().is_ok_and(|s| s == addr)
+                    }
+                }
+                Kind::Url | Kind::Token => {
+                    !signature.trim().is_empty()
+                        && candidate.lowercase.contains(&signature.to_lowercase())
+                }
+                Kind::Regex => {
+                    compiled_regex(signature).is_some_and(|re| re.is_match(&candidate.value))
+                }
+            })
+    }
+
+    /// Returns `true` if `value`, taken from an event as a `kind` of a
+    /// triage policy, matches a signature of any rule.
+    ///
+    /// A fingerprint or a file hash in a `Url` or `Token` database must equal a
+    /// signature, ignoring case, rather than contain it. Each address in an
+    /// email header, such as `Alice <alice@example.com>, bob@example.com`, is
+    /// looked up separately. Otherwise, this is the same as
+    /// [`matches`](Self::matches).
+    #[must_use]
+    pub fn matches_as(&self, kind: TiCmpKind, value: &str) -> bool {
+        self.is_match_as(kind, value, None)
+    }
+
+    /// Returns `true` if `value`, taken from an event as a `kind` of a
+    /// triage policy, matches a signature of any rule valid at `now`, skipping
+    /// the expired ones. Otherwise, this is the same as
+    /// [`matches_as`](Self::matches_as).
+    #[must_use]
+    pub fn matches_as_at(&self, kind: TiCmpKind, value: &str, now: DateTime<Utc>) -> bool {
+        self.is_match_as(kind, value, Some(now))
+    }
+
+    /// Returns the rules that `value`, taken from an event as a `kind` of a
+    /// triage policy, matches, in the same way as
+    /// [`matches_as`](Self::matches_as).
+    #[must_use]
+    pub fn rules_matching(&self, kind: TiCmpKind, value: &str) -> Vec<&Rule> {
+        self.rules_matching_as(kind, value, None).collect()
+    }
+
+    fn is_match_as(&self, kind: TiCmpKind, value: &str, now: Option<DateTime<Utc>>) -> bool {
+        self.rules_matching_as(kind, value, now).next().is_some()
+    }
+
+    fn rules_matching_as<'a>(
+        &'a self,
+        kind: TiCmpKind,
+        value: &'a str,
+        now: Option<DateTime<Utc>>,
+    ) -> impl Iterator<Item = &'a Rule> {
+        let exact = is_exact(kind, self.kind);
+        let candidates: Vec<_> = if exact {
+            Vec::new()
+        } else if kind == TiCmpKind::Email {
+            email_addresses(value).map(Candidate::new).collect()
+        } else {
+            vec![Candidate::new(value)]
+        };
+        self.rules(now).filter(move |rule| {
+            if exact {
+                rule.has_signature_equal_to(value)
+            } else {
+                candidates
+                    .iter()
+                    .any(|candidate| self.rule_matches(rule, candidate))
+            }
+        })
+    }
+
+    /// Removes the rules expired at `now`, and returns the number of the rules
+    /// removed.
+    pub fn remove_expired(&mut self, now: DateTime<Utc>) -> usize {
+        let len = self.patterns.len();
+        self.patterns.retain(|rule| !rule.is_expired(now));
+        len - self.patterns.len()
+    }
+
+    /// Changes the version after the rules are changed at `now`, so that the
+    /// clients comparing versions fetch the database again. A numeric version
+    /// is incremented; otherwise, `now` is attached to the version as build
+    /// metadata, replacing the one attached earlier.
+    fn bump_version(&mut self, now: DateTime<Utc>) {
+        self.version = match self.version.parse::<u64>() {
+            Ok(version) => version.saturating_add(1).to_string(),
+            Err(_) => {
+                let base = self
+                    .version
+                    .split_once('+')
+                    .map_or(self.version.as_str(), |(base, _)| base);
+                format!("{base}+{}", now.timestamp())
+            }
+        };
+    }
+}
+
+/// Returns `true` if a value taken from an event as `kind` must equal a
+/// signature of a `db` database, rather than match it.
+fn is_exact(kind: TiCmpKind, db: Kind) -> bool {
+    matches!(
+        (kind, db),
+        (
+            TiCmpKind::Ja3 | TiCmpKind::Ja4 | TiCmpKind::FileHash,
+            Kind::Url | Kind::Token
+        )
+    )
+}
+
+/// Returns `value` in lowercase, borrowing it if it is already.
+fn lowercase(value: &str) -> Cow<str> {
+    if value
+        .chars()
+        .all(|c| c.to_lowercase().eq(std::iter::once(c)))
+    {
+        Cow::Borrowed(value)
+    } else {
+        Cow::Owned(value.to_lowercase())
+    }
+}
+
+/// A value to look up, prepared for comparing with signatures.
+struct Candidate<'a> {
+    value: &'a str,
+    lowercase: Cow<'a, str>,
+    addr: Option<IpAddr>,
+}
+
+impl<'a> Candidate<'a> {
+    fn new(value: &'a str) -> Self {
+        Self {
+            value,
+            lowercase: lowercase(value),
+            addr: value.parse().ok(),
+        }
+    }
+}
+
+/// The TI databases indexed for looking up a value, cached until the table
+/// changes.
+struct LookupIndex {
+    tidbs: Vec<Tidb>,
+    matcher: Matcher,
+}
+
+impl LookupIndex {
+    fn new(table: &Table<Tidb>) -> Result<Self> {
+        let tidbs = table.get_list()?;
+        let matcher = Matcher::new(&tidbs, None);
+        Ok(Self { tidbs, matcher })
+    }
+
+    fn lookup(&self, kind: TiCmpKind, value: &str, now: DateTime<Utc>) -> Vec<Lookup> {
+        let mut matched = if kind == TiCmpKind::Email {
+            email_addresses(value)
+                .flat_map(|addr| self.matcher.matching_rules(addr))
+                .collect()
+        } else {
+            self.matcher.matching_rules(value)
+        };
+        // A value that must equal a signature is compared without the
+        // surrounding whitespace.
+        let trimmed = value.trim();
+        if trimmed.len() != value.len() {
+            matched.extend(
+                self.matcher
+                    .matching_rules(trimmed)
+                    .into_iter()
+                    .filter(|(db, _)| is_exact(kind, self.tidbs[*db].kind)),
+            );
+        }
+        matched.sort_unstable();
+        matched.dedup();
+
+        let mut lookups: Vec<Lookup> = Vec::new();
+        for (db, index) in matched {
+            let tidb = &self.tidbs[db];
+            let rule = &tidb.patterns[index];
+            if rule.is_expired(now)
+                || (is_exact(kind, tidb.kind) && !rule.has_signature_equal_to(value))
+            {
+                continue;
+            }
+            match lookups.last_mut() {
+                Some(lookup) if lookup.name == tidb.name => lookup.rules.push(rule.clone()),
+                _ => lookups.push(Lookup {
+                    name: tidb.name.clone(),
+                    kind: tidb.kind,
+                    version: tidb.version.clone(),
+                    rules: vec![rule.clone()],
+                    weights: Vec::new(),
+                }),
+            }
+        }
+        lookups
+    }
+}
+
+/// A TI database with the rules a value matches, returned by
+/// [`crate::Store::tidb_lookup`].
+#[derive(Clone)]
+pub struct Lookup {
+    pub name: String,
+    pub kind: Kind,
+    pub version: String,
+    /// The rules the value matches.
+    pub rules: Vec<Rule>,
+    /// The weights given to the database by triage policies for the kind of
+    /// the value, with the IDs of the policies.
+    pub weights: Vec<(u32, f64)>,
+}
+
+/// Returns the addresses in an email header, dropping display names.
+fn email_addresses(header: &str) -> impl Iterator<Item = &str> {
+    header.split(',').filter_map(|mailbox| {
+        let mailbox = mailbox.trim();
+        let addr = match (mailbox.rfind('<'), mailbox.rfind('>')) {
+            (Some(start), Some(end)) if start < end => &mailbox[start + 1..end],
+            _ => mailbox,
+        };
+        (!addr.is_empty()).then_some(addr)
+    })
 }
 
 #[derive(Clone, Deserialize, Serialize)]
@@ -69,6 +425,47 @@ pub struct Rule {
     pub references: Option<Vec<String>>,
     pub samples: Option<Vec<String>>,
     pub signatures: Option<Vec<String>>,
+    /// How confident the source is that the rule is correct, from 0 to 100.
+    pub confidence: Option<u8>,
+    /// When the rule should no longer be considered valid.
+    pub valid_until: Option<DateTime<Utc>>,
+}
+
+impl Rule {
+    /// Returns `true` if a signature equals `value`, ignoring case and
+    /// surrounding whitespace.
+    fn has_signature_equal_to(&self, value: &str) -> bool {
+        self.signatures.iter().flatten().any(|signature| {
+            !signature.trim().is_empty() && signature.trim().eq_ignore_ascii_case(value.trim())
+        })
+    }
+
+    /// Returns `true` if the rule has expired at `now`.
+    #[must_use]
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.valid_until.is_some_and(|time| time <= now)
+    }
+
+    /// Returns the confidence of the rule at `now`, which decreases linearly
+    /// from `confidence` to 0 over the `decay` period ending at `valid_until`.
+    /// The confidence of a rule without `valid_until` doesn't decay.
+    #[must_use]
+    pub fn decayed_confidence(&self, now: DateTime<Utc>, decay: Duration) -> Option<u8> {
+        let confidence = self.confidence?;
+        let Some(valid_until) = self.valid_until else {
+            return Some(confidence);
+        };
+        if valid_until <= now {
+            return Some(0);
+        }
+        let remaining = (valid_until - now).num_milliseconds();
+        let decay = decay.num_milliseconds();
+        if remaining >= decay {
+            return Some(confidence);
+        }
+        let decayed = i64::from(confidence) * remaining / decay;
+        Some(u8::try_from(decayed).unwrap_or(confidence))
+    }
 }
 
 #[derive(Clone, Copy, Deserialize, Eq, PartialEq, Serialize)]
@@ -122,9 +519,11 @@ impl<'d> Table<'d, Tidb> {
     ///
     /// # Errors
     ///
+    /// * Returns an error if the TI database is invalid
     /// * Returns an error if it fails to encode TI database
     /// * Returns an error if it fails to save TI database
     pub fn insert(&self, entry: Tidb) -> Result<()> {
+        entry.validate()?;
         let (key, value) = entry.into_key_value()?;
 
         self.map.put(&key, &value)?;
@@ -136,12 +535,14 @@ impl<'d> Table<'d, Tidb> {
     /// # Errors
     ///
     /// * Returns an error if the TI database name does not match
+    /// * Returns an error if the TI database is invalid
     /// * Returns an error if it fails to encode TI database
     /// * Returns an error if it fails to delete or save TI database
     pub fn update(&self, name: &str, entry: Tidb) -> Result<()> {
         if name != entry.name {
             bail!("Tidb name does not matched");
         }
+        entry.validate()?;
         let (key, value) = entry.into_key_value()?;
         self.map.delete(&key)?;
         self.map.put(name.as_bytes(), &value)
@@ -198,6 +599,152 @@ impl<'d> Table<'d, Tidb> {
         Ok(ret)
     }
 
+    /// Stores `tidbs` and the model indicator `indicator`, if any, in the way
+    /// `Table<ModelIndicator>::update` does, and removes the TI databases
+    /// named in `stale`, in a single transaction. Returns the names of the
+    /// removed databases, i.e., those in `stale` that existed.
+    ///
+    /// # Errors
+    ///
+    /// * Returns an error if it fails to encode TI database
+    /// * Returns an error if it fails to save or remove TI database or to
+    ///   save the model indicator
+    pub(crate) fn import_feed(
+        &self,
+        indicators: &Table<ModelIndicator>,
+        tidbs: Vec<Tidb>,
+        stale: &[String],
+        indicator: Option<&ModelIndicator>,
+    ) -> Result<Vec<String>> {
+        let entries = tidbs
+            .into_iter()
+            .map(Tidb::into_key_value)
+            .collect::<Result<Vec<_>>>()?;
+        let removed = loop {
+            let txn = self.map.db.transaction();
+            for (key, value) in &entries {
+                txn.put_cf(self.map.cf, key, value)
+                    .context("failed to save TI database")?;
+            }
+            let mut removed = Vec::new();
+            for name in stale {
+                if txn
+                    .get_for_update_cf(self.map.cf, name.as_bytes(), EXCLUSIVE)
+                    .context("cannot read TI database")?
+                    .is_some()
+                {
+                    txn.delete_cf(self.map.cf, name.as_bytes())
+                        .context("failed to remove TI database")?;
+                    removed.push(name.clone());
+                }
+            }
+            if let Some(indicator) = indicator {
+                indicators.put_versioned_in(&txn, indicator)?;
+            }
+
+            match txn.commit() {
+                Ok(()) => break removed,
+                Err(e) => {
+                    if !e.as_ref().starts_with("Resource busy:") {
+                        return Err(e).context("failed to import TI databases");
+                    }
+                }
+            }
+        };
+        for (key, _) in &entries {
+            notify(self.map.notifier, ChangeKind::Update, key);
+        }
+        for name in &removed {
+            notify(self.map.notifier, ChangeKind::Remove, name.as_bytes());
+        }
+        if let Some(indicator) = indicator {
+            indicators.notify_update(&indicator.name);
+        }
+        Ok(removed)
+    }
+
+    /// Returns a [`Matcher`] over the rules of all the TI databases valid at
+    /// `now`, for matching many values at once.
+    ///
+    /// # Errors
+    ///
+    /// * Returns an error if it fails to read or decode TI database
+    pub fn matcher(&self, now: DateTime<Utc>) -> Result<Matcher> {
+        Ok(Matcher::new(&self.get_list()?, Some(now)))
+    }
+
+    /// Returns the TI databases with rules valid at `now` that `value`
+    /// matches as `kind`, in the same way as [`Tidb::matches_as_at`], along
+    /// with the matching rules. The weights of the returned databases are
+    /// left empty.
+    ///
+    /// The lookup index is built from the TI databases when first needed,
+    /// and rebuilt after the table changes.
+    ///
+    /// # Errors
+    ///
+    /// * Returns an error if it fails to read or decode TI database
+    pub fn lookup(&self, kind: TiCmpKind, value: &str, now: DateTime<Utc>) -> Result<Vec<Lookup>> {
+        let index = match self.map.notifier {
+            Some(channel) => channel.cached(|| LookupIndex::new(self))?,
+            None => Arc::new(LookupIndex::new(self)?),
+        };
+        Ok(index.lookup(kind, value, now))
+    }
+
+    /// Removes the rules expired at `now` from all the TI databases in a
+    /// transaction, and returns the number of the rules removed. The version
+    /// of a database losing rules is changed, so that the clients comparing
+    /// versions fetch it again.
+    ///
+    /// # Errors
+    ///
+    /// * Returns an error if it fails to read or decode TI database
+    /// * Returns an error if it fails to save TI database
+    pub fn remove_expired(&self, now: DateTime<Utc>) -> Result<usize> {
+        let (removed, changed) = loop {
+            let txn = self.map.db.transaction();
+            let keys = txn
+                .iterator_cf(self.map.cf, IteratorMode::Start)
+                .map(|item| item.map(|(key, _)| key))
+                .collect::<Result<Vec<_>, _>>()
+                .context("cannot read TI database")?;
+            let mut removed = 0;
+            let mut changed = Vec::new();
+            for key in keys {
+                let Some(value) = txn
+                    .get_for_update_cf(self.map.cf, &key, EXCLUSIVE)
+                    .context("cannot read TI database")?
+                else {
+                    continue;
+                };
+                let mut tidb = Tidb::from_key_value(&key, &value)?;
+                let count = tidb.remove_expired(now);
+                if count == 0 {
+                    continue;
+                }
+                removed += count;
+                tidb.bump_version(now);
+                txn.put_cf(self.map.cf, &key, super::serialize(&tidb)?)
+                    .context("failed to write TI database")?;
+                changed.push(key);
+            }
+
+            match txn.commit() {
+                Ok(()) => break (removed, changed),
+                Err(e) => {
+                    if !e.as_ref().starts_with("Resource busy:") {
+                        return Err(e).context("failed to remove expired TI rules");
+                    }
+                }
+            }
+        };
+        for key in &changed {
+            notify(self.map.notifier, ChangeKind::Update, key);
+        }
+        Ok(removed)
+    }
+
     /// Removes TI database
     ///
     /// # Errors
@@ -212,7 +759,271 @@ impl<'d> Table<'d, Tidb> {
 mod tests {
     use std::sync::Arc;
 
-    use crate::{Store, Tidb};
+    use crate::{Store, TiCmpKind, Tidb, TidbKind, TidbMatcher, TidbRule};
+
+    fn tidb(kind: TidbKind, signatures: &[&str]) -> Tidb {
+        Tidb {
+            id: 1,
+            name: "tidb".to_string(),
+            description: None,
+            kind,
+            version: "1".to_string(),
+            patterns: vec![TidbRule {
+                rule_id: 1,
+                name: "rule".to_string(),
+                description: None,
+                references: None,
+                samples: None,
+                signatures: Some(signatures.iter().map(ToString::to_string).collect()),
+                confidence: None,
+                valid_until: None,
+            }],
+        }
+    }
+
+    #[test]
+    fn matches() {
+        let ip = tidb(TidbKind::Ip, &["10.0.0.0/8", "192.168.0.1"]);
+        assert!(ip.matches("10.1.2.3"));
+        assert!(ip.matches("192.168.0.1"));
+        assert!(!ip.matches("192.168.0.2"));
+        assert!(!ip.matches("example.com"));
+
+        let token = tidb(
+            TidbKind::Token,
+            &["Evil", "e7d705a3286e19ea42f587b344ee6865"],
+        );
+        assert!(token.matches("www.evil.com"));
+        assert!(token.matches_as(TiCmpKind::Ja3, "E7D705A3286E19EA42F587B344EE6865"));
+        assert!(!token.matches_as(TiCmpKind::Ja3, "e7d705a3286e19ea42f587b344ee6865ff"));
+        assert!(!token.matches_as(TiCmpKind::FileHash, "evil"));
+
+        let email = tidb(TidbKind::Token, &["mallory@example.com"]);
+        assert!(email.matches_as(
+            TiCmpKind::Email,
+            "Alice <alice@example.com>, Mallory <mallory@example.com>"
+        ));
+        assert!(!email.matches_as(TiCmpKind::Email, "alice@example.com"));
+
+        let regex = tidb(TidbKind::Regex, &[r"^/admin/.*\.php$"]);
+        assert!(regex.matches("/admin/login.php"));
+        assert!(!regex.matches("/index.php"));
+    }
+
+    #[test]
+    fn empty_signature() {
+        let token = tidb(TidbKind::Token, &["evil", " "]);
+        assert!(token.validate().is_err());
+        assert!(!token.matches("example.com"));
+        assert!(!token.matches_as(TiCmpKind::FileHash, " "));
+        assert!(TidbMatcher::new(&[token], None).matches(&["example.com"])[0].is_empty());
+
+        let regex = tidb(TidbKind::Regex, &[".*"]);
+        assert!(regex.validate().is_ok());
+    }
+
+    #[test]
+    fn expiry() {
+        use chrono::{Duration, Utc};
+
+        let now = Utc::now();
+        let mut tidb = tidb(TidbKind::Token, &["evil"]);
+        let mut rule = tidb.patterns[0].clone();
+        rule.rule_id = 2;
+        rule.signatures = Some(vec!["stale".to_string()]);
+        rule.confidence = Some(80);
+        rule.valid_until = Some(now + Duration::days(1));
+        tidb.patterns.push(rule.clone());
+
+        assert!(tidb.matches_at("stale.example", now));
+        assert!(!tidb.matches_at("stale.example", now + Duration::days(1)));
+        assert!(tidb.matches("stale.example"));
+        assert!(tidb.matches_as_at(TiCmpKind::FileHash, "EVIL", now + Duration::days(2)));
+        assert!(!tidb.matches_as_at(TiCmpKind::FileHash, "stale", now + Duration::days(2)));
+
+        let decay = Duration::days(4);
+        assert_eq!(
+            rule.decayed_confidence(now - Duration::days(3), decay),
+            Some(80)
+        );
+        assert_eq!(rule.decayed_confidence(now, decay), Some(20));
+        assert_eq!(
+            rule.decayed_confidence(now + Duration::days(1), decay),
+            Some(0)
+        );
+        assert_eq!(tidb.patterns[0].decayed_confidence(now, decay), None);
+
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(Store::new(db_dir.path(), backup_dir.path()).unwrap());
+        let table = store.tidb_map();
+        table.insert(tidb).unwrap();
+        assert_eq!(table.remove_expired(now).unwrap(), 0);
+        assert_eq!(table.remove_expired(now + Duration::days(1)).unwrap(), 1);
+        let tidb = table.get("tidb").unwrap().unwrap();
+        assert_eq!(tidb.patterns.len(), 1);
+        assert_eq!(tidb.patterns[0].rule_id, 1);
+        assert_eq!(tidb.version, "2");
+    }
+
+    #[test]
+    fn lookup() {
+        use chrono::{Duration, Utc};
+
+        use crate::{Ti, TriagePolicy};
+
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(Store::new(db_dir.path(), backup_dir.path()).unwrap());
+        let mut networks = tidb(TidbKind::Ip, &["10.0.0.0/8"]);
+        networks.name = "networks".to_string();
+        let mut rule = networks.patterns[0].clone();
+        rule.rule_id = 2;
+        rule.signatures = Some(vec!["192.168.0.1".to_string()]);
+        networks.patterns.push(rule);
+        store.tidb_map().insert(networks).unwrap();
+        store
+            .tidb_map()
+            .insert(tidb(TidbKind::Token, &["10.1."]))
+            .unwrap();
+
+        let ti = |ti_name: &str, kind, weight| Ti {
+            ti_name: ti_name.to_string(),
+            kind,
+            weight,
+        };
+        let policy_id = store
+            .triage_policy_map()
+            .put(TriagePolicy {
+                id: u32::MAX,
+                name: "policy".to_string(),
+                ti_db: vec![
+                    ti("networks", TiCmpKind::IpAddress, Some(2.0)),
+                    ti("networks", TiCmpKind::Domain, Some(3.0)),
+                    ti("tidb", TiCmpKind::IpAddress, None),
+                ],
+                packet_attr: vec![],
+                response: vec![],
+                confidence: vec![],
+                customer_ids: vec![],
+                network_ids: vec![],
+                enabled: true,
+                active_from: None,
+                active_until: None,
+                creation_time: Utc::now(),
+            })
+            .unwrap();
+
+        let now = Utc::now();
+        let lookups = store
+            .tidb_lookup(TiCmpKind::IpAddress, "10.1.2.3", now)
+            .unwrap();
+        assert_eq!(lookups.len(), 2);
+        assert_eq!(lookups[0].name, "networks");
+        assert_eq!(lookups[0].rules.len(), 1);
+        assert_eq!(lookups[0].rules[0].rule_id, 1);
+        assert_eq!(lookups[0].weights, [(policy_id, 2.0)]);
+        assert_eq!(lookups[1].name, "tidb");
+        assert_eq!(lookups[1].weights, [(policy_id, 1.0)]);
+        assert!(store
+            .tidb_lookup(TiCmpKind::IpAddress, "172.16.0.1", now)
+            .unwrap()
+            .is_empty());
+
+        // The index is rebuilt after the table changes, and the rules expired
+        // at the time of the lookup are skipped.
+        let mut hashes = tidb(TidbKind::Token, &["D41D8CD98F00B204E9800998ECF8427E"]);
+        hashes.name = "hashes".to_string();
+        let mut expired = hashes.patterns[0].clone();
+        expired.rule_id = 2;
+        expired.signatures = Some(vec!["bob@example.com".to_string()]);
+        expired.valid_until = Some(now + Duration::hours(1));
+        hashes.patterns.push(expired);
+        store.tidb_map().insert(hashes).unwrap();
+        let lookups = store
+            .tidb_lookup(
+                TiCmpKind::FileHash,
+                " d41d8cd98f00b204e9800998ecf8427e ",
+                now,
+            )
+            .unwrap();
+        assert_eq!(lookups.len(), 1);
+        assert_eq!(lookups[0].name, "hashes");
+        assert_eq!(lookups[0].rules[0].rule_id, 1);
+        assert!(store
+            .tidb_lookup(
+                TiCmpKind::FileHash,
+                "d41d8cd98f00b204e9800998ecf8427e0",
+                now
+            )
+            .unwrap()
+            .is_empty());
+        let lookups = store
+            .tidb_lookup(
+                TiCmpKind::Email,
+                "Alice <alice@example.org>, BOB@example.com",
+                now,
+            )
+            .unwrap();
+        assert_eq!(lookups.len(), 1);
+        assert_eq!(lookups[0].rules[0].rule_id, 2);
+        assert!(store
+            .tidb_lookup(
+                TiCmpKind::Email,
+                "bob@example.com",
+                now + Duration::hours(1)
+            )
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn matcher() {
+        use chrono::{Duration, Utc};
+
+        use crate::TidbMatch;
+
+        let now = Utc::now();
+        let mut ip = tidb(TidbKind::Ip, &["10.0.0.0/8", "192.168.0.1", "invalid"]);
+        ip.name = "ip".to_string();
+        let mut expired = ip.patterns[0].clone();
+        expired.rule_id = 2;
+        expired.signatures = Some(vec!["172.16.0.1".to_string()]);
+        expired.valid_until = Some(now - Duration::days(1));
+        ip.patterns.push(expired);
+        let mut token = tidb(TidbKind::Token, &["Evil", "evil.example", "bad"]);
+        token.name = "token".to_string();
+        let mut regex = tidb(TidbKind::Regex, &[r"^/admin/.*\.php$"]);
+        regex.name = "regex".to_string();
+        let tidbs = [ip, token, regex];
+
+        let matcher = TidbMatcher::new(&tidbs, Some(now));
+        let values = [
+            "10.1.2.3",
+            "www.EVIL.example",
+            "172.16.0.1",
+            "/admin/login.php",
+            "good.example",
+        ];
+        let m = |tidb: &str, rule_id| TidbMatch {
+            tidb: tidb.to_string(),
+            rule_id,
+        };
+        let matches = matcher.matches(&values);
+        assert_eq!(matches[0], [m("ip", 1)]);
+        assert_eq!(matches[1], [m("token", 1)]);
+        assert!(matches[2].is_empty());
+        assert_eq!(matches[3], [m("regex", 1)]);
+        assert!(matches[4].is_empty());
+        for (value, matches) in values.iter().zip(&matches) {
+            let expected = tidbs.iter().any(|t| t.matches_at(value, now));
+            assert_eq!(!matches.is_empty(), expected);
+        }
+        assert_eq!(
+            TidbMatcher::new(&tidbs, None).matches(&["172.16.0.1"]),
+            [[m("ip", 2)]]
+        );
+    }
 
     #[test]
     fn serde() {
@@ -238,6 +1049,53 @@ mod tests {
         assert_eq!(tidb.id, id);
     }
 
+    #[test]
+    fn new_from_v0_26() {
+        use data_encoding::BASE64;
+        use flate2::{write::GzEncoder, Compression};
+        use std::io::Write;
+
+        let old = super::TidbV0_26 {
+            id: 1,
+            name: "old".to_string(),
+            description: None,
+            kind: TidbKind::Url,
+            version: "1".to_string(),
+            patterns: vec![
+                super::RuleV0_26 {
+                    rule_id: 1,
+                    name: "first".to_string(),
+                    description: None,
+                    references: None,
+                    samples: None,
+                    signatures: Some(vec!["evil.example".to_string()]),
+                },
+                super::RuleV0_26 {
+                    rule_id: 2,
+                    name: "second".to_string(),
+                    description: Some("bad".to_string()),
+                    references: Some(vec!["https://feed.example".to_string()]),
+                    samples: None,
+                    signatures: Some(vec!["worse.example".to_string()]),
+                },
+            ],
+        };
+        let mut gz = GzEncoder::new(Vec::new(), Compression::fast());
+        gz.write_all(&crate::tables::serialize(&old).unwrap())
+            .unwrap();
+        let encoded = BASE64.encode(&gz.finish().unwrap());
+
+        let tidb = Tidb::new(&encoded).unwrap();
+        assert_eq!(tidb.name, "old");
+        assert_eq!(tidb.patterns.len(), 2);
+        assert_eq!(tidb.patterns[1].description.as_deref(), Some("bad"));
+        assert!(tidb
+            .patterns
+            .iter()
+            .all(|rule| rule.confidence.is_none() && rule.valid_until.is_none()));
+        assert!(tidb.matches("www.worse.example"));
+    }
+
     #[test]
     fn operations() {
         use crate::Iterable;
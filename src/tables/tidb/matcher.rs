@@ -0,0 +1,144 @@
+//! Matching of values in bulk against TI databases.
+
+use std::{collections::HashMap, net::IpAddr};
+
+use aho_corasick::{AhoCorasick, AhoCorasickBuilder};
+use chrono::{DateTime, Utc};
+use ipnet::IpNet;
+use regex::Regex;
+
+use super::{compiled_regex, lowercase, Kind, Tidb};
+
+/// A rule a value matches, returned by [`Matcher::matches`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Match {
+    /// The name of the TI database.
+    pub tidb: String,
+    pub rule_id: u32,
+}
+
+/// The rules of TI databases indexed for matching many values at once.
+///
+/// A value matches a rule in the same way as [`Tidb::matches`], but all the
+/// signatures are looked up together: IP addresses in a hash map, and the
+/// signatures of `Url` and `Token` databases with a single Aho-Corasick
+/// automaton.
+///
+/// A rule is referred to internally by the position of its database in the
+/// databases indexed and its position in the rules of the database.
+pub struct Matcher {
+    names: Vec<String>,
+    rule_ids: Vec<Vec<u32>>,
+    addrs: HashMap<IpAddr, Vec<(usize, usize)>>,
+    networks: Vec<(IpNet, usize, usize)>,
+    substrings: Option<AhoCorasick>,
+    substring_rules: Vec<(usize, usize)>,
+    regexes: Vec<(Regex, usize, usize)>,
+}
+
+impl Matcher {
+    /// Indexes the rules of `tidbs` valid at `now`, or all the rules if `now`
+    /// is `None`. Invalid signatures are ignored, as they never match.
+    #[must_use]
+    pub fn new(tidbs: &[Tidb], now: Option<DateTime<Utc>>) -> Self {
+        let mut names = Vec::with_capacity(tidbs.len());
+        let mut rule_ids = Vec::with_capacity(tidbs.len());
+        let mut addrs: HashMap<_, Vec<_>> = HashMap::new();
+        let mut networks = Vec::new();
+        let mut substrings = Vec::new();
+        let mut substring_rules = Vec::new();
+        let mut regexes = Vec::new();
+        for (db, tidb) in tidbs.iter().enumerate() {
+            names.push(tidb.name.clone());
+            rule_ids.push(tidb.patterns.iter().map(|rule| rule.rule_id).collect());
+            for (index, rule) in tidb.patterns.iter().enumerate() {
+                if now.is_some_and(|now| rule.is_expired(now)) {
+                    continue;
+                }
+                for signature in rule.signatures.iter().flatten() {
+                    match tidb.kind {
+                        Kind::Ip => {
+                            if let Ok(network) = signature.parse::<IpNet>() {
+                                networks.push((network, db, index));
+                            } else if let Ok(addr) = signature.parse::<IpAddr>() {
+                                addrs.entry(addr).or_default().push((db, index));
+                            }
+                        }
+                        Kind::Url | Kind::Token => {
+                            if signature.trim().is_empty() {
+                                continue;
+                            }
+                            substrings.push(signature.to_lowercase());
+                            substring_rules.push((db, index));
+                        }
+                        Kind::Regex => {
+                            if let Some(regex) = compiled_regex(signature) {
+                                regexes.push((regex, db, index));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        let substrings =
+            (!substrings.is_empty()).then(|| AhoCorasickBuilder::new().build(substrings));
+        Self {
+            names,
+            rule_ids,
+            addrs,
+            networks,
+            substrings,
+            substring_rules,
+            regexes,
+        }
+    }
+
+    /// Returns the rules each of `values` matches, in the order of `values`.
+    #[must_use]
+    pub fn matches(&self, values: &[&str]) -> Vec<Vec<Match>> {
+        values.iter().map(|value| self.matches_one(value)).collect()
+    }
+
+    fn matches_one(&self, value: &str) -> Vec<Match> {
+        self.matching_rules(value)
+            .into_iter()
+            .map(|(db, index)| Match {
+                tidb: self.names[db].clone(),
+                rule_id: self.rule_ids[db][index],
+            })
+            .collect()
+    }
+
+    /// Returns the positions of the databases and the rules `value` matches,
+    /// in order.
+    pub(super) fn matching_rules(&self, value: &str) -> Vec<(usize, usize)> {
+        let mut rules = Vec::new();
+        if let Ok(addr) = value.parse::<IpAddr>() {
+            if let Some(matched) = self.addrs.get(&addr) {
+                rules.extend_from_slice(matched);
+            }
+            rules.extend(
+                self.networks
+                    .iter()
+                    .filter(|(network, _, _)| network.contains(&addr))
+                    .map(|(_, db, index)| (*db, *index)),
+            );
+        }
+        if let Some(substrings) = &self.substrings {
+            rules.extend(
+                substrings
+                    .find_overlapping_iter(lowercase(value).as_ref())
+                    .map(|m| self.substring_rules[m.pattern()]),
+            );
+        }
+        rules.extend(
+            self.regexes
+                .iter()
+                .filter(|(regex, _, _)| regex.is_match(value))
+                .map(|(_, db, index)| (*db, *index)),
+        );
+        rules.sort_unstable();
+        rules.dedup();
+        rules
+    }
+}
@@ -0,0 +1,340 @@
+//! Conversion of STIX 2.1 bundles into TI databases.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use super::{Kind, Rule, Tidb};
+
+/// The result of converting a STIX bundle with [`Tidb::from_stix`].
+pub struct Import {
+    /// A TI database for each kind of the indicators in the bundle.
+    pub tidbs: Vec<Tidb>,
+    /// The IDs of the indicators skipped because their patterns cannot be
+    /// represented as signatures.
+    pub skipped: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Bundle {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    objects: Vec<Object>,
+}
+
+#[derive(Deserialize)]
+struct Object {
+    #[serde(rename = "type")]
+    kind: String,
+    id: String,
+    name: Option<String>,
+    description: Option<String>,
+    pattern: Option<String>,
+    pattern_type: Option<String>,
+    valid_until: Option<DateTime<Utc>>,
+    confidence: Option<u8>,
+    #[serde(default)]
+    revoked: bool,
+    #[serde(default)]
+    external_references: Vec<ExternalReference>,
+}
+
+#[derive(Deserialize)]
+struct ExternalReference {
+    url: Option<String>,
+}
+
+impl Tidb {
+    /// Converts the indicators in a STIX 2.1 bundle into TI databases, one for
+    /// each kind of the values they look for: `Ip` for IP addresses and
+    /// networks, `Url` for domain names and URLs, and `Token` for file hashes.
+    /// The databases are named `name` followed by the kind, such as
+    /// `feed-ip`, and have the given `version`. Each has its own ID: `id` for
+    /// the `Ip` database, `id + 1` for the `Url` one, and `id + 2` for the
+    /// `Token` one, whether or not the bundle has indicators of each kind.
+    ///
+    /// Each indicator becomes a rule with the values in its pattern as the
+    /// signatures, keeping its confidence and valid-until time. A pattern is
+    /// supported if it only compares the values with `=`, or an IP address
+    /// with `ISSUBSET`, and combines the comparisons with `OR`. Revoked
+    /// indicators are ignored.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `bundle` is not a STIX bundle in JSON, or `id`,
+    /// `name` or `version` is invalid. `id` is invalid if it is 0 or any of
+    /// the IDs from it overflows.
+    pub fn from_stix(bundle: &str, name: &str, id: u32, version: &str) -> Result<Import> {
+        let bundle: Bundle = serde_json::from_str(bundle).context("invalid STIX bundle")?;
+        if bundle.kind != "bundle" {
+            bail!("not a STIX bundle: {}", bundle.kind);
+        }
+
+        let mut tidbs: Vec<Tidb> = Vec::new();
+        let mut skipped = Vec::new();
+        for object in bundle.objects {
+            if object.kind != "indicator" || object.revoked {
+                continue;
+            }
+            let comparisons = match (&object.pattern, object.pattern_type.as_deref()) {
+                (Some(pattern), Some("stix")) => comparisons(pattern),
+                _ => None,
+            };
+            let Some(comparisons) = comparisons else {
+                skipped.push(object.id);
+                continue;
+            };
+
+            for kind in [Kind::Ip, Kind::Url, Kind::Token] {
+                let signatures: Vec<_> = comparisons
+                    .iter()
+                    .filter(|(k, _)| *k == kind)
+                    .map(|(_, value)| value.clone())
+                    .collect();
+                if signatures.is_empty() {
+                    continue;
+                }
+                let tidb_name = format!("{name}-{}", kind_name(kind));
+                let pos = if let Some(pos) = tidbs.iter().position(|t| t.name == tidb_name) {
+                    pos
+                } else {
+                    tidbs.push(Tidb {
+                        id: id.checked_add(kind_offset(kind)).context("invalid db id")?,
+                        name: tidb_name,
+                        description: None,
+                        kind,
+                        version: version.to_string(),
+                        patterns: Vec::new(),
+                    });
+                    tidbs.len() - 1
+                };
+                let tidb = &mut tidbs[pos];
+                let references: Vec<_> = object
+                    .external_references
+                    .iter()
+                    .filter_map(|reference| reference.url.clone())
+                    .collect();
+                tidb.patterns.push(Rule {
+                    rule_id: u32::try_from(tidb.patterns.len() + 1)
+                        .context("too many indicators")?,
+                    name: object.name.clone().unwrap_or_else(|| object.id.clone()),
+                    description: object.description.clone(),
+                    references: (!references.is_empty()).then_some(references),
+                    samples: None,
+                    signatures: Some(signatures),
+                    confidence: object.confidence.map(|confidence| confidence.min(100)),
+                    valid_until: object.valid_until,
+                });
+            }
+        }
+
+        for tidb in &tidbs {
+            tidb.validate()?;
+        }
+        Ok(Import { tidbs, skipped })
+    }
+}
+
+/// Returns the offset of the ID of the database of `kind` from the ID given to
+/// [`Tidb::from_stix`].
+fn kind_offset(kind: Kind) -> u32 {
+    match kind {
+        Kind::Ip => 0,
+        Kind::Url => 1,
+        Kind::Token => 2,
+        Kind::Regex => 3,
+    }
+}
+
+fn kind_name(kind: Kind) -> &'static str {
+    match kind {
+        Kind::Ip => "ip",
+        Kind::Url => "url",
+        Kind::Token => "token",
+        Kind::Regex => "regex",
+    }
+}
+
+/// Returns the kind of the value an object path in a STIX pattern refers to.
+fn kind_of(path: &str) -> Option<Kind> {
+    match path {
+        "ipv4-addr:value" | "ipv6-addr:value" => Some(Kind::Ip),
+        "domain-name:value" | "url:value" => Some(Kind::Url),
+        _ if path.starts_with("file:hashes.") => Some(Kind::Token),
+        _ => None,
+    }
+}
+
+/// Returns the values compared in `pattern` with their kinds, or `None` if the
+/// pattern is not supported.
+///
+/// The supported patterns are observation expressions, such as
+/// `[ipv4-addr:value = '198.51.100.1' OR domain-name:value = 'example.com']`,
+/// combined with `OR`.
+fn comparisons(pattern: &str) -> Option<Vec<(Kind, String)>> {
+    let mut comparisons = Vec::new();
+    let mut rest = pattern.trim();
+    loop {
+        rest = rest.strip_prefix('[')?.trim_start();
+        loop {
+            let end = rest.find(|c: char| c.is_whitespace() || c == '=')?;
+            let (path, after) = rest.split_at(end);
+            let kind = kind_of(path)?;
+            let after = after.trim_start();
+            let after = match after.strip_prefix('=') {
+                Some(after) => after,
+                None if kind == Kind::Ip => keyword(after, "ISSUBSET")?,
+                None => return None,
+            };
+            let (value, after) = quoted(after.trim_start())?;
+            comparisons.push((kind, value));
+            rest = after.trim_start();
+            match keyword(rest, "OR") {
+                Some(after) => rest = after.trim_start(),
+                None => break,
+            }
+        }
+        rest = rest.strip_prefix(']')?.trim_start();
+        if rest.is_empty() {
+            return Some(comparisons);
+        }
+        rest = keyword(rest, "OR")?.trim_start();
+    }
+}
+
+/// Returns the rest of `input` after `keyword`, if it starts with it.
+fn keyword<'a>(input: &'a str, keyword: &str) -> Option<&'a str> {
+    let rest = input.strip_prefix(keyword)?;
+    match rest.chars().next() {
+        Some(c) if !c.is_whitespace() && c != '[' => None,
+        _ => Some(rest),
+    }
+}
+
+/// Returns the string in single quotes at the beginning of `input`, and the
+/// rest of `input` after it.
+fn quoted(input: &str) -> Option<(String, &str)> {
+    let mut chars = input.strip_prefix('\'')?.char_indices();
+    let mut value = String::new();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => value.push(chars.next()?.1),
+            '\'' => return Some((value, &input[i + 2..])),
+            _ => value.push(c),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::comparisons;
+    use crate::{Tidb, TidbKind};
+
+    #[test]
+    fn patterns() {
+        assert_eq!(
+            comparisons(
+                "[ipv4-addr:value = '198.51.100.0/24' OR ipv6-addr:value ISSUBSET '2001:db8::/32'] \
+                 OR [url:value = 'http://example.com/it\\'s']"
+            )
+            .unwrap()
+            .into_iter()
+            .map(|(_, value)| value)
+            .collect::<Vec<_>>(),
+            ["198.51.100.0/24", "2001:db8::/32", "http://example.com/it's"]
+        );
+        assert!(comparisons("[domain-name:value = 'a.example' AND url:value = 'x']").is_none());
+        assert!(comparisons("[domain-name:value ISSUBSET 'example.com']").is_none());
+        assert!(comparisons("[process:name = 'evil.exe']").is_none());
+        assert!(comparisons("[url:value = 'x'] WITHIN 300 SECONDS").is_none());
+    }
+
+    #[test]
+    fn from_stix() {
+        let bundle = r#"{
+            "type": "bundle",
+            "id": "bundle--1",
+            "objects": [
+                {
+                    "type": "indicator",
+                    "spec_version": "2.1",
+                    "id": "indicator--1",
+                    "name": "C2 server",
+                    "pattern": "[ipv4-addr:value = '198.51.100.1'] OR [domain-name:value = 'c2.example']",
+                    "pattern_type": "stix",
+                    "valid_from": "2024-01-01T00:00:00Z",
+                    "valid_until": "2025-01-01T00:00:00Z",
+                    "confidence": 85,
+                    "external_references": [{"source_name": "feed", "url": "https://feed.example/1"}]
+                },
+                {
+                    "type": "indicator",
+                    "spec_version": "2.1",
+                    "id": "indicator--2",
+                    "pattern": "[file:hashes.'SHA-256' = 'AEC070645FE53EE3B3763059376134F058CC337247C978ADD178B6CCDFB0019F']",
+                    "pattern_type": "stix",
+                    "valid_from": "2024-01-01T00:00:00Z"
+                },
+                {
+                    "type": "indicator",
+                    "spec_version": "2.1",
+                    "id": "indicator--3",
+                    "pattern": "alert tcp any any -> any any",
+                    "pattern_type": "snort",
+                    "valid_from": "2024-01-01T00:00:00Z"
+                },
+                {
+                    "type": "indicator",
+                    "spec_version": "2.1",
+                    "id": "indicator--4",
+                    "pattern": "[url:value = 'http://revoked.example']",
+                    "pattern_type": "stix",
+                    "valid_from": "2024-01-01T00:00:00Z",
+                    "revoked": true
+                },
+                {
+                    "type": "malware",
+                    "spec_version": "2.1",
+                    "id": "malware--1",
+                    "name": "Evil",
+                    "is_family": false
+                }
+            ]
+        }"#;
+
+        let import = Tidb::from_stix(bundle, "feed", 1, "2024-01-01").unwrap();
+        assert_eq!(import.skipped, ["indicator--3"]);
+        let names: Vec<_> = import.tidbs.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, ["feed-ip", "feed-url", "feed-token"]);
+        let ids: Vec<_> = import.tidbs.iter().map(|t| t.id).collect();
+        assert_eq!(ids, [1, 2, 3]);
+
+        let ip = &import.tidbs[0];
+        assert!(ip.kind == TidbKind::Ip);
+        assert!(ip.matches("198.51.100.1"));
+        let rule = &ip.patterns[0];
+        assert_eq!(rule.name, "C2 server");
+        assert_eq!(rule.confidence, Some(85));
+        assert_eq!(
+            rule.valid_until,
+            Some("2025-01-01T00:00:00Z".parse().unwrap())
+        );
+        assert_eq!(
+            rule.references.as_deref(),
+            Some(&["https://feed.example/1".to_string()][..])
+        );
+        assert!(import.tidbs[1].matches("c2.example"));
+        assert!(!import.tidbs[1].matches("revoked.example"));
+        assert!(import.tidbs[2].matches_as(
+            crate::TiCmpKind::FileHash,
+            "aec070645fe53ee3b3763059376134f058cc337247c978add178b6ccdfb0019f"
+        ));
+        assert_eq!(import.tidbs[2].patterns[0].name, "indicator--2");
+
+        assert!(Tidb::from_stix(r#"{"type": "indicator"}"#, "feed", 1, "1").is_err());
+        assert!(Tidb::from_stix(bundle, "feed", 0, "1").is_err());
+        assert!(Tidb::from_stix(bundle, "feed", u32::MAX, "1").is_err());
+    }
+}
@@ -1,10 +1,19 @@
-//! The `tor_exit_node` table.
+//! The `tor_exit_node` table, and the `Tor exit node snapshots` table keeping
+//! its history.
 
-use anyhow::{Context, Result};
+use std::{borrow::Cow, net::IpAddr};
+
+use anyhow::{bail, Context, Result};
 use chrono::{DateTime, Utc};
-use rocksdb::OptimisticTransactionDB;
+use rocksdb::{IteratorMode, OptimisticTransactionDB};
+use serde::{Deserialize, Serialize};
 
-use crate::{types::FromKeyValue, Map, Table, UniqueKey};
+use super::Value;
+use crate::{
+    notification::{notify, ChangeKind},
+    types::FromKeyValue,
+    Direction, Iterable, Map, Table, UniqueKey, EXCLUSIVE,
+};
 
 pub struct TorExitNode {
     pub ip_address: String,
@@ -65,11 +74,214 @@ impl<'d> Table<'d, TorExitNode> {
     }
 }
 
+/// The list of Tor exit nodes as of a point in time.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct TorExitNodeSnapshot {
+    /// When the list was taken.
+    pub taken_at: DateTime<Utc>,
+    /// The IP addresses of the exit nodes, sorted and without duplicates.
+    pub ip_addresses: Vec<IpAddr>,
+}
+
+impl TorExitNodeSnapshot {
+    #[must_use]
+    pub fn new(taken_at: DateTime<Utc>, ip_addresses: impl IntoIterator<Item = IpAddr>) -> Self {
+        let mut ip_addresses: Vec<_> = ip_addresses.into_iter().collect();
+        ip_addresses.sort_unstable();
+        ip_addresses.dedup();
+        Self {
+            taken_at,
+            ip_addresses,
+        }
+    }
+
+    /// Returns `true` if `ip_address` is in the list.
+    #[must_use]
+    pub fn contains(&self, ip_address: IpAddr) -> bool {
+        self.ip_addresses.binary_search(&ip_address).is_ok()
+    }
+
+    fn key(time: DateTime<Utc>) -> [u8; 8] {
+        time.timestamp_nanos_opt().unwrap_or(i64::MAX).to_be_bytes()
+    }
+}
+
+impl FromKeyValue for TorExitNodeSnapshot {
+    fn from_key_value(_key: &[u8], value: &[u8]) -> Result<Self> {
+        super::deserialize(value)
+    }
+}
+
+impl UniqueKey for TorExitNodeSnapshot {
+    fn unique_key(&self) -> Cow<[u8]> {
+        Cow::Owned(Self::key(self.taken_at).to_vec())
+    }
+}
+
+impl Value for TorExitNodeSnapshot {
+    fn value(&self) -> Cow<[u8]> {
+        let Ok(value) = super::serialize(self) else {
+            unreachable!("serialization into memory should never fail")
+        };
+        Cow::Owned(value)
+    }
+}
+
+/// Functions for the `Tor exit node snapshots` table.
+impl<'d> Table<'d, TorExitNodeSnapshot> {
+    /// Opens the `Tor exit node snapshots` table in the database.
+    ///
+    /// Returns `None` if the table does not exist.
+    pub(super) fn open(db: &'d OptimisticTransactionDB) -> Option<Self> {
+        Map::open(db, super::TOR_EXIT_NODE_SNAPSHOTS).map(Table::new)
+    }
+
+    /// Returns the most recent snapshot.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails or the snapshot is
+    /// invalid.
+    pub fn latest(&self) -> Result<Option<TorExitNodeSnapshot>> {
+        self.iter(Direction::Reverse, None).next().transpose()
+    }
+
+    /// Returns the snapshot in effect at `time`, i.e., the most recent one
+    /// taken at or before `time`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails or the snapshot is
+    /// invalid.
+    pub fn at(&self, time: DateTime<Utc>) -> Result<Option<TorExitNodeSnapshot>> {
+        let key = TorExitNodeSnapshot::key(time);
+        self.iter(Direction::Reverse, Some(&key)).next().transpose()
+    }
+
+    /// Returns whether `ip_address` was a Tor exit node at `time`, or `None`
+    /// if `time` is before the oldest snapshot kept.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails or the snapshot is
+    /// invalid.
+    pub fn was_exit_node(&self, ip_address: IpAddr, time: DateTime<Utc>) -> Result<Option<bool>> {
+        Ok(self.at(time)?.map(|snapshot| snapshot.contains(ip_address)))
+    }
+
+    /// Stores `snapshot` as the latest one, replaces all the entries of the
+    /// `tor_exit_node` table with its IP addresses, and removes all but the
+    /// `history` snapshots before it, in a single transaction.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `snapshot` isn't newer than the latest one kept, or
+    /// the database operation fails.
+    pub(crate) fn refresh(&self, snapshot: &TorExitNodeSnapshot, history: usize) -> Result<()> {
+        let nodes = self
+            .map
+            .db
+            .cf_handle(super::TOR_EXIT_NODES)
+            .context("tor_exit_node table must be present")?;
+        let key = snapshot.unique_key();
+        let value = snapshot.value();
+        let new_nodes: Vec<_> = snapshot
+            .ip_addresses
+            .iter()
+            .map(|ip_address| {
+                TorExitNode {
+                    ip_address: ip_address.to_string(),
+                    updated_at: snapshot.taken_at,
+                }
+                .into_key_value()
+            })
+            .collect();
+        let mut old_nodes = Vec::new();
+        let mut expired = Vec::new();
+        loop {
+            let txn = self.map.db.transaction();
+            let mut old_keys = Vec::new();
+            for item in txn.iterator_cf(self.map.cf, IteratorMode::End) {
+                let (old_key, _) = item.context("cannot read Tor exit node snapshot")?;
+                old_keys.push(old_key);
+            }
+            if let Some(latest) = old_keys.first() {
+                let latest = txn
+                    .get_for_update_cf(self.map.cf, latest, EXCLUSIVE)
+                    .context("cannot read Tor exit node snapshot")?
+                    .context("Tor exit node snapshot removed")?;
+                let latest = TorExitNodeSnapshot::from_key_value(&[], &latest)?;
+                if snapshot.taken_at <= latest.taken_at {
+                    bail!(
+                        "snapshot taken at {} is not newer than the latest one taken at {}",
+                        snapshot.taken_at,
+                        latest.taken_at
+                    );
+                }
+            }
+            txn.put_cf(self.map.cf, &key, &value)
+                .context("failed to write Tor exit node snapshot")?;
+            expired.clear();
+            for old_key in old_keys.into_iter().skip(history) {
+                txn.delete_cf(self.map.cf, &old_key)
+                    .context("failed to delete Tor exit node snapshot")?;
+                expired.push(old_key);
+            }
+
+            old_nodes.clear();
+            for item in txn.iterator_cf(nodes, IteratorMode::Start) {
+                let (old_key, _) = item.context("cannot read Tor exit node")?;
+                txn.delete_cf(nodes, &old_key)
+                    .context("failed to delete Tor exit node")?;
+                old_nodes.push(old_key);
+            }
+            for (node_key, node_value) in &new_nodes {
+                txn.put_cf(nodes, node_key, node_value)
+                    .context("failed to write Tor exit node")?;
+            }
+
+            match txn.commit() {
+                Ok(()) => break,
+                Err(e) => {
+                    if !e.as_ref().starts_with("Resource busy:") {
+                        return Err(e).context("failed to refresh Tor exit nodes");
+                    }
+                }
+            }
+        }
+
+        notify(self.map.notifier, ChangeKind::Insert, &key);
+        for old_key in &expired {
+            notify(self.map.notifier, ChangeKind::Remove, old_key);
+        }
+        for old_key in &old_nodes {
+            notify(self.map.notifier, ChangeKind::Remove, old_key);
+        }
+        for (node_key, _) in &new_nodes {
+            notify(self.map.notifier, ChangeKind::Insert, node_key);
+        }
+        Ok(())
+    }
+
+    /// Removes all but the `count` most recent snapshots.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails or any snapshot is
+    /// invalid.
+    pub fn retain_latest(&self, count: usize) -> Result<()> {
+        for snapshot in self.iter(Direction::Reverse, None).skip(count) {
+            self.map.delete(&snapshot?.unique_key())?;
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::sync::Arc;
 
-    use chrono::Utc;
+    use chrono::{Duration, Utc};
     use rocksdb::Direction;
 
     use crate::{Iterable, Store, TorExitNode};
@@ -106,4 +318,51 @@ mod tests {
         assert_eq!(&entries[0].ip_address, "1.0.0.127");
         assert_eq!(entries[0].updated_at, t2);
     }
+
+    #[test]
+    fn history() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(Store::new(db_dir.path(), backup_dir.path()).unwrap());
+        let snapshots = store.tor_exit_node_snapshot_map();
+
+        let a = "192.0.2.1".parse().unwrap();
+        let b = "2001:db8::1".parse().unwrap();
+        let t0 = Utc::now() - Duration::hours(3);
+        let t1 = t0 + Duration::hours(1);
+        let t2 = t1 + Duration::hours(1);
+        store.refresh_tor_exit_nodes(t0, [a], 1).unwrap();
+        store.refresh_tor_exit_nodes(t1, [b, a, b], 1).unwrap();
+        assert!(store.refresh_tor_exit_nodes(t1, [a], 1).is_err());
+        assert_eq!(snapshots.latest().unwrap().unwrap().ip_addresses, [a, b]);
+
+        assert_eq!(snapshots.was_exit_node(b, t0).unwrap(), Some(false));
+        assert_eq!(
+            snapshots
+                .was_exit_node(b, t1 + Duration::minutes(30))
+                .unwrap(),
+            Some(true)
+        );
+        assert_eq!(
+            snapshots
+                .was_exit_node(a, t0 - Duration::minutes(1))
+                .unwrap(),
+            None
+        );
+
+        // Only the previous snapshot is kept, besides the latest one.
+        store.refresh_tor_exit_nodes(t2, [b], 1).unwrap();
+        assert_eq!(snapshots.was_exit_node(a, t0).unwrap(), None);
+        assert_eq!(snapshots.was_exit_node(a, t1).unwrap(), Some(true));
+        assert_eq!(snapshots.was_exit_node(a, t2).unwrap(), Some(false));
+
+        let current: Vec<_> = store
+            .tor_exit_node_map()
+            .iter(Direction::Forward, None)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(current.len(), 1);
+        assert_eq!(current[0].ip_address, "2001:db8::1");
+        assert_eq!(current[0].updated_at, t2);
+    }
 }
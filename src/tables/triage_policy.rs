@@ -1,13 +1,25 @@
 //! The `TriagePolicy` table.
 
-use std::{borrow::Cow, cmp::Ordering};
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    collections::HashMap,
+    io::{Read, Write},
+    net::IpAddr,
+    sync::{Mutex, OnceLock},
+};
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Context, Result};
 use chrono::{DateTime, Utc};
-use rocksdb::OptimisticTransactionDB;
+use regex::Regex;
+use rocksdb::{ColumnFamily, Direction, OptimisticTransactionDB, Transaction};
 use serde::{Deserialize, Serialize};
 
-use crate::{types::FromKeyValue, Indexable, Indexed, IndexedMap, IndexedMapUpdate, IndexedTable};
+use crate::{
+    types::FromKeyValue, Customer, Event, ImportConflict, ImportReport, Indexable, Indexed,
+    IndexedMap, IndexedMapUpdate, IndexedTable, Iterable, Map, Network, Tidb, TriageResult,
+    EXCLUSIVE,
+};
 
 #[derive(Clone, Deserialize, Serialize)]
 pub struct TriagePolicy {
@@ -17,9 +29,146 @@ pub struct TriagePolicy {
     pub packet_attr: Vec<PacketAttr>,
     pub confidence: Vec<Confidence>,
     pub response: Vec<Response>,
+
+    /// The customers whose networks the policy applies to.
+    pub customer_ids: Vec<u32>,
+
+    /// The networks the policy applies to.
+    ///
+    /// If both `customer_ids` and `network_ids` are empty, the policy applies
+    /// to all addresses.
+    pub network_ids: Vec<u32>,
+
+    /// Whether the policy is in use. A disabled policy is never active.
+    pub enabled: bool,
+
+    /// When the policy becomes active, if it is scheduled.
+    pub active_from: Option<DateTime<Utc>>,
+
+    /// When the policy stops being active, if it is scheduled.
+    pub active_until: Option<DateTime<Utc>>,
+
     pub creation_time: DateTime<Utc>,
 }
 
+impl TriagePolicy {
+    /// Returns `true` if the policy is enabled and `time` is within its
+    /// activation window, which includes `active_from` but not
+    /// `active_until`.
+    #[must_use]
+    pub fn is_active_at(&self, time: DateTime<Utc>) -> bool {
+        self.enabled
+            && self.active_from.map_or(true, |from| from <= time)
+            && self.active_until.map_or(true, |until| time < until)
+    }
+
+    /// Returns `true` if the policy applies to `addr`.
+    ///
+    /// `customers` and `networks` are where the IDs in `customer_ids` and
+    /// `network_ids` are looked up; IDs not found in them are ignored.
+    #[must_use]
+    pub fn applies_to(&self, addr: IpAddr, customers: &[Customer], networks: &[Network]) -> bool {
+        if self.customer_ids.is_empty() && self.network_ids.is_empty() {
+            return true;
+        }
+        customers
+            .iter()
+            .filter(|customer| self.customer_ids.contains(&customer.id))
+            .flat_map(|customer| &customer.networks)
+            .any(|network| network.contains(addr))
+            || networks
+                .iter()
+                .filter(|network| self.network_ids.contains(&network.id))
+                .any(|network| network.networks.contains(addr))
+    }
+
+    /// Evaluates `event` against the policy, returning the score, the matched
+    /// rules, and the response for the score.
+    ///
+    /// Each matched rule adds its weight, or 1.0 if it has none, to the score.
+    /// A threat-intelligence rule is matched if the database of the name in
+    /// `tidbs` matches a value of the event; a rule referring to a database
+    /// not in `tidbs` is never matched, and neither is a rule expired at
+    /// `now`.
+    #[must_use]
+    pub fn score(&self, event: &Event, tidbs: &[Tidb], now: DateTime<Utc>) -> TriageResult {
+        event.triage(self, tidbs, now)
+    }
+
+    /// Checks whether the policy is well-formed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if
+    ///
+    /// * the name is empty,
+    /// * two packet-attribute rules are the same except for their weights,
+    /// * a value of a packet-attribute rule can't be decoded as its kind, a
+    ///   range rule lacks its second value, or its bounds are reversed,
+    /// * a weight is negative or not finite,
+    /// * a confidence is not between 0 and 1, or
+    /// * two responses have the same minimum score, or a minimum score is
+    ///   negative or not finite, or
+    /// * the activation window ends before it starts.
+    pub fn validate(&self) -> Result<()> {
+        if self.name.trim().is_empty() {
+            bail!("triage policy name is required");
+        }
+        if let (Some(from), Some(until)) = (self.active_from, self.active_until) {
+            if until <= from {
+                bail!("activation window ends before it starts");
+            }
+        }
+        for ti in &self.ti_db {
+            validate_weight(ti.weight)?;
+        }
+        for (i, attr) in self.packet_attr.iter().enumerate() {
+            attr.validate()?;
+            if self.packet_attr[..i].iter().any(|other| {
+                other.attr_name == attr.attr_name
+                    && other.value_kind == attr.value_kind
+                    && other.cmp_kind == attr.cmp_kind
+                    && other.first_value == attr.first_value
+                    && other.second_value == attr.second_value
+            }) {
+                bail!("duplicate packet attribute rule for {}", attr.attr_name);
+            }
+        }
+        for conf in &self.confidence {
+            validate_weight(conf.weight)?;
+            if !(0.0..=1.0).contains(&conf.confidence) {
+                bail!("confidence out of range: {}", conf.confidence);
+            }
+        }
+        for (i, response) in self.response.iter().enumerate() {
+            if !response.minimum_score.is_finite() || response.minimum_score < 0.0 {
+                bail!("minimum score out of range: {}", response.minimum_score);
+            }
+            if self.response[..i].iter().any(|other| {
+                other
+                    .minimum_score
+                    .total_cmp(&response.minimum_score)
+                    .is_eq()
+            }) {
+                bail!(
+                    "more than one response for minimum score {}",
+                    response.minimum_score
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+fn validate_weight(weight: Option<f64>) -> Result<()> {
+    match weight {
+        Some(weight) if !weight.is_finite() || weight < 0.0 => {
+            bail!("weight out of range: {weight}")
+        }
+        _ => Ok(()),
+    }
+}
+
 impl FromKeyValue for TriagePolicy {
     fn from_key_value(_key: &[u8], value: &[u8]) -> Result<Self> {
         super::deserialize(value)
@@ -43,6 +192,85 @@ impl Indexable for TriagePolicy {
     fn set_index(&mut self, index: u32) {
         self.id = index;
     }
+
+    fn validate(&self) -> Result<()> {
+        TriagePolicy::validate(self)
+    }
+
+    /// Records the updated policy as a new version in its history.
+    fn update_references(
+        &self,
+        db: &OptimisticTransactionDB,
+        txn: &Transaction<OptimisticTransactionDB>,
+        old_key: &[u8],
+    ) -> Result<()> {
+        let mut history = history_in_transaction(db, txn, self)?;
+        if history.is_empty() {
+            // The policy is already updated in `txn`, so the stored one is
+            // read as the first version.
+            let previous: TriagePolicy = db
+                .get_cf(policies(db)?, old_key)
+                .context("cannot read triage policy")?
+                .map(|value| super::deserialize(&value))
+                .transpose()?
+                .context("triage policy removed while being updated")?;
+            history.push(Version {
+                version: 1,
+                author: None,
+                time: previous.creation_time,
+                policy: previous,
+            });
+        }
+        let version = history.last().map_or(1, |v| v.version + 1);
+        history.push(Version {
+            version,
+            author: None,
+            time: Utc::now(),
+            policy: self.clone(),
+        });
+        txn.put_cf(
+            super::meta(db)?,
+            history_key(self),
+            super::serialize(&history)?,
+        )
+        .context("failed to write triage policy history")
+    }
+
+    /// Removes the history of the policy.
+    fn remove_references(
+        db: &OptimisticTransactionDB,
+        txn: &Transaction<OptimisticTransactionDB>,
+        _id: u32,
+        key: &[u8],
+    ) -> Result<()> {
+        // The policy is already deleted in `txn`. Its ID and creation time
+        // never change, so the stored one is read instead.
+        let Some(value) = db
+            .get_cf(policies(db)?, key)
+            .context("cannot read triage policy")?
+        else {
+            return Ok(());
+        };
+        let policy: TriagePolicy = super::deserialize(&value)?;
+        txn.delete_cf(super::meta(db)?, history_key(&policy))
+            .context("failed to remove triage policy history")
+    }
+}
+
+fn policies(db: &OptimisticTransactionDB) -> Result<&ColumnFamily> {
+    db.cf_handle(super::TRIAGE_POLICY)
+        .context("triage policy table must be present")
+}
+
+/// Reads the history of `policy` in `txn`.
+fn history_in_transaction(
+    db: &OptimisticTransactionDB,
+    txn: &Transaction<OptimisticTransactionDB>,
+    policy: &TriagePolicy,
+) -> Result<Vec<Version>> {
+    txn.get_for_update_cf(super::meta(db)?, history_key(policy), EXCLUSIVE)
+        .context("cannot read triage policy history")?
+        .map_or(Ok(Vec::new()), |value| super::deserialize(&value))
 }
 
 #[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Deserialize, Serialize)]
@@ -51,6 +279,14 @@ pub enum TiCmpKind {
     Domain,
     Hostname,
     Uri,
+    /// The JA3 or JA3S fingerprint of a TLS session.
+    Ja3,
+    /// The JA4 fingerprint of a TLS session.
+    Ja4,
+    /// The hash of a transferred file.
+    FileHash,
+    /// An email address of a sender or recipient.
+    Email,
 }
 
 #[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Deserialize, Serialize)]
@@ -78,6 +314,10 @@ pub enum AttrCmpKind {
     NotCloseRange,
     NotLeftOpenRange,
     NotRightOpenRange,
+    /// Matches a string containing a match of the regular expression.
+    Regex,
+    /// Matches a string not containing a match of the regular expression.
+    NotRegex,
 }
 
 #[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Deserialize, Serialize)]
@@ -131,6 +371,104 @@ pub struct PacketAttr {
     pub weight: Option<f64>,
 }
 
+impl PacketAttr {
+    /// Returns `true` if the comparison takes a range of two values.
+    #[must_use]
+    pub fn is_range(&self) -> bool {
+        matches!(
+            self.cmp_kind,
+            AttrCmpKind::OpenRange
+                | AttrCmpKind::CloseRange
+                | AttrCmpKind::LeftOpenRange
+                | AttrCmpKind::RightOpenRange
+                | AttrCmpKind::NotOpenRange
+                | AttrCmpKind::NotCloseRange
+                | AttrCmpKind::NotLeftOpenRange
+                | AttrCmpKind::NotRightOpenRange
+        )
+    }
+
+    fn validate(&self) -> Result<()> {
+        validate_weight(self.weight)?;
+        let first = decode_number(self.value_kind, &self.first_value)
+            .with_context(|| format!("invalid value for {}", self.attr_name))?;
+        if matches!(self.cmp_kind, AttrCmpKind::Regex | AttrCmpKind::NotRegex) {
+            if self.value_kind != ValueKind::String {
+                bail!("regex for {} must be a string", self.attr_name);
+            }
+            let pattern = std::str::from_utf8(&self.first_value)?;
+            Regex::new(pattern).with_context(|| format!("invalid regex for {}", self.attr_name))?;
+            return Ok(());
+        }
+        if !self.is_range() {
+            return Ok(());
+        }
+        let Some(second_value) = &self.second_value else {
+            bail!("range for {} requires a second value", self.attr_name);
+        };
+        let second = decode_number(self.value_kind, second_value)
+            .with_context(|| format!("invalid second value for {}", self.attr_name))?;
+        if let (Some(first), Some(second)) = (first, second) {
+            if first > second {
+                bail!("reversed range for {}", self.attr_name);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The maximum number of compiled regular expressions kept by
+/// [`compiled_regex`].
+const REGEX_CACHE_SIZE: usize = 1024;
+
+/// Returns the compiled regular expression of `pattern`, or `None` if it is
+/// invalid.
+///
+/// Compiled expressions are cached so that a rule evaluated against many
+/// events is compiled only once. The cache is emptied when it is full.
+pub(crate) fn compiled_regex(pattern: &str) -> Option<Regex> {
+    static CACHE: OnceLock<Mutex<HashMap<String, Option<Regex>>>> = OnceLock::new();
+    let mut cache = CACHE
+        .get_or_init(Mutex::default)
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+    if let Some(regex) = cache.get(pattern) {
+        return regex.clone();
+    }
+    if cache.len() >= REGEX_CACHE_SIZE {
+        cache.clear();
+    }
+    let regex = Regex::new(pattern).ok();
+    cache.insert(pattern.to_string(), regex.clone());
+    regex
+}
+
+/// Decodes a value of a packet-attribute rule, returning the number it
+/// represents, or `None` if it is a string.
+///
+/// A string is in UTF-8, and a number is in the little-endian byte order.
+#[allow(clippy::cast_precision_loss)]
+fn decode_number(kind: ValueKind, value: &[u8]) -> Result<Option<f64>> {
+    match kind {
+        ValueKind::String => {
+            std::str::from_utf8(value)?;
+            Ok(None)
+        }
+        ValueKind::Integer => {
+            let bytes = value.try_into().context("integer must be 8 bytes")?;
+            Ok(Some(i64::from_le_bytes(bytes) as f64))
+        }
+        ValueKind::Float => {
+            let bytes = value.try_into().context("float must be 8 bytes")?;
+            let value = f64::from_le_bytes(bytes);
+            if value.is_nan() {
+                bail!("float must not be NaN");
+            }
+            Ok(Some(value))
+        }
+    }
+}
+
 impl Eq for PacketAttr {}
 
 impl PartialOrd for PacketAttr {
@@ -244,14 +582,376 @@ impl<'d> IndexedTable<'d, TriagePolicy> {
             .ok()
     }
 
+    pub(crate) fn raw(&self) -> &IndexedMap<'_> {
+        &self.indexed_map
+    }
+
+    /// Inserts a policy into the table and returns the ID of the newly added
+    /// policy.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the policy is invalid as described in
+    /// [`TriagePolicy::validate`], the table already has a policy with the
+    /// same name, or the database operation fails.
+    pub fn insert(&self, policy: TriagePolicy) -> Result<u32> {
+        self.put(policy)
+    }
+
+    /// Returns the policies active at `time`, i.e., those enabled and
+    /// scheduled to be active at `time`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails.
+    pub fn active_at(&self, time: DateTime<Utc>) -> Result<Vec<TriagePolicy>> {
+        let mut policies = Vec::new();
+        for policy in self.iter(Direction::Forward, None) {
+            let policy = policy?;
+            if policy.is_active_at(time) {
+                policies.push(policy);
+            }
+        }
+        Ok(policies)
+    }
+
+    /// Returns the policy named `name`, if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails.
+    pub fn get_by_name(&self, name: &str) -> Result<Option<TriagePolicy>> {
+        self.indexed_map
+            .get_by_key(name.as_bytes())?
+            .map(|value| TriagePolicy::from_key_value(name.as_bytes(), value.as_ref()))
+            .transpose()
+    }
+
+    /// Writes the policies in the table to `writer` as JSON.
+    ///
+    /// The document has the format version, [`JSON_FORMAT_VERSION`], and the
+    /// policies without their IDs, creation times, and scopes, which are
+    /// specific to a database.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any policy cannot be read or written.
+    pub fn export_json<W: Write>(&self, writer: W) -> Result<()> {
+        let policies = self
+            .iter(Direction::Forward, None)
+            .map(|policy| policy.map(PolicyJson::from))
+            .collect::<Result<Vec<_>>>()?;
+        let document = JsonDocument {
+            version: JSON_FORMAT_VERSION,
+            policies,
+        };
+        serde_json::to_writer_pretty(writer, &document).context("cannot write JSON")
+    }
+
+    /// Reads policies in the format of [`IndexedTable::export_json`] from
+    /// `reader`, and adds them to the table.
+    ///
+    /// `on_conflict` decides what to do with a policy whose name is already
+    /// used; a policy identical to the existing one is left unchanged. A
+    /// policy that can't be added is reported as a conflict with the ID of
+    /// the existing policy, or 0 if it is invalid.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the JSON is invalid, its format version is not
+    /// supported, or the database operation fails.
+    pub fn import_json<Rd: Read>(
+        &mut self,
+        reader: Rd,
+        on_conflict: NameConflict,
+    ) -> Result<ImportReport> {
+        let document: JsonDocument =
+            serde_json::from_reader(reader).context("invalid triage policy JSON")?;
+        if document.version != JSON_FORMAT_VERSION {
+            bail!(
+                "unsupported triage policy format version {}",
+                document.version
+            );
+        }
+        let mut report = ImportReport::default();
+        for imported in document.policies {
+            let existing = self.get_by_name(&imported.name)?;
+            if let Some(existing) = &existing {
+                if PolicyJson::from(existing.clone()) == imported {
+                    report.unchanged.push(existing.id);
+                    continue;
+                }
+            }
+            let mut policy = TriagePolicy::from(imported);
+            let result = match (&existing, on_conflict) {
+                (None, _) => self.insert(policy.clone()),
+                (Some(existing), NameConflict::Skip) => {
+                    Err(anyhow!("name is used by policy {}", existing.id))
+                }
+                (Some(_), NameConflict::Rename) => {
+                    policy.name = self.unused_name(&policy.name)?;
+                    self.insert(policy.clone())
+                }
+                (Some(existing), NameConflict::Overwrite) => {
+                    let mut new = Update::from(&policy);
+                    new.customer_ids.clone_from(&existing.customer_ids);
+                    new.network_ids.clone_from(&existing.network_ids);
+                    self.update(existing.id, &Update::from(existing), &new)
+                        .map(|()| existing.id)
+                }
+            };
+            match result {
+                Ok(id) => report.imported.push(id),
+                Err(e) => report.conflicts.push(ImportConflict {
+                    id: existing.map_or(0, |existing| existing.id),
+                    name: policy.name,
+                    reason: format!("{e:#}"),
+                }),
+            }
+        }
+        Ok(report)
+    }
+
+    /// Returns `name` followed by the smallest number from 2 that makes it
+    /// unused, such as "name (2)".
+    fn unused_name(&self, name: &str) -> Result<String> {
+        for n in 2.. {
+            let candidate = format!("{name} ({n})");
+            if self.get_by_name(&candidate)?.is_none() {
+                return Ok(candidate);
+            }
+        }
+        unreachable!("there is always an unused name")
+    }
+
+    /// Copies the policy with `id` under `new_name`, and returns the ID of the
+    /// copy. The copy has its own creation time and no history.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `id` is invalid, the table already has a policy
+    /// named `new_name`, or the database operation fails.
+    pub fn duplicate(&self, id: u32, new_name: &str) -> Result<u32> {
+        let Some(mut policy) = self.get_by_id(id)? else {
+            bail!("no such triage policy {id}");
+        };
+        policy.id = u32::MAX;
+        new_name.clone_into(&mut policy.name);
+        policy.creation_time = Utc::now();
+        self.insert(policy)
+    }
+
     /// Updates the `TriagePolicy` from `old` to `new`, given `id`.
     ///
+    /// The new version is recorded in the history of the policy without an
+    /// author.
+    ///
     /// # Errors
     ///
-    /// Returns an error if the `id` is invalid or the database operation fails.
+    /// Returns an error if the `id` is invalid, the new policy is invalid as
+    /// described in [`TriagePolicy::validate`], or the database operation
+    /// fails.
     pub fn update(&mut self, id: u32, old: &Update, new: &Update) -> Result<()> {
-        self.indexed_map.update(id, old, new)
+        self.update_with_history(id, old, new, None)
+    }
+
+    /// Updates the `TriagePolicy` from `old` to `new`, given `id`, and records
+    /// the new version in the history of the policy as made by `author`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the `id` is invalid, the new policy is invalid as
+    /// described in [`TriagePolicy::validate`], or the database operation
+    /// fails.
+    pub fn update_by(&mut self, id: u32, old: &Update, new: &Update, author: &str) -> Result<()> {
+        self.update_with_history(id, old, new, Some(author))
+    }
+
+    /// Returns the versions of the policy with `id`, from the oldest to the
+    /// current one.
+    ///
+    /// A policy that has never been updated has no history.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `id` is invalid or the database operation fails.
+    pub fn get_history(&self, id: u32) -> Result<Vec<Version>> {
+        let Some(policy) = self.get_by_id(id)? else {
+            bail!("no such triage policy {id}");
+        };
+        self.history(&policy)
     }
+
+    /// Restores the policy with `id` to the given version of its history. The
+    /// restored policy is recorded as a new version made by `author`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `id` or `version` is invalid or the database
+    /// operation fails.
+    pub fn rollback(&mut self, id: u32, version: u32, author: &str) -> Result<()> {
+        let Some(current) = self.get_by_id(id)? else {
+            bail!("no such triage policy {id}");
+        };
+        let history = self.history(&current)?;
+        let Some(target) = history.iter().find(|v| v.version == version) else {
+            bail!("no such version {version} of triage policy {id}");
+        };
+        let old = Update::from(&current);
+        let new = Update::from(&target.policy);
+        self.update_by(id, &old, &new, author)
+    }
+
+    /// Returns the current version number of the policy with `id`, which is
+    /// 1 if it has never been updated.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `id` is invalid or the database operation fails.
+    pub fn current_version(&self, id: u32) -> Result<u32> {
+        Ok(self.get_history(id)?.last().map_or(1, |v| v.version))
+    }
+
+    /// Updates the policy, recording the new version made by `author` in the
+    /// same transaction.
+    fn update_with_history(
+        &mut self,
+        id: u32,
+        old: &Update,
+        new: &Update,
+        author: Option<&str>,
+    ) -> Result<()> {
+        let Some(author) = author else {
+            return self.indexed_map.update(id, old, new);
+        };
+        let Some(previous) = self.get_by_id(id)? else {
+            bail!("no such triage policy {id}");
+        };
+        let db = self.indexed_map.db();
+        self.indexed_map.update_many_with(&[(id, old, new)], |txn| {
+            let mut history = history_in_transaction(db, txn, &previous)?;
+            let latest = history
+                .last_mut()
+                .context("triage policy history not recorded")?;
+            latest.author = Some(author.to_string());
+            txn.put_cf(
+                super::meta(db)?,
+                history_key(&previous),
+                super::serialize(&history)?,
+            )
+            .context("failed to write triage policy history")
+        })
+    }
+
+    fn history(&self, policy: &TriagePolicy) -> Result<Vec<Version>> {
+        let meta = self.meta()?;
+        meta.get(&history_key(policy))?
+            .map_or(Ok(Vec::new()), |value| super::deserialize(value.as_ref()))
+    }
+
+    fn meta(&self) -> Result<Map<'_>> {
+        Map::open(self.indexed_map.db(), super::META)
+            .ok_or_else(|| anyhow!("{} table must be present", super::META))
+    }
+}
+
+/// The key of the history of `policy` in the meta table.
+///
+/// The key includes the creation time, as well as the ID, so that a new policy
+/// reusing the ID of a removed one doesn't inherit its history.
+fn history_key(policy: &TriagePolicy) -> Vec<u8> {
+    format!(
+        "triage policy history {} {}",
+        policy.id,
+        policy.creation_time.to_rfc3339()
+    )
+    .into_bytes()
+}
+
+/// The version of the JSON format of [`IndexedTable::export_json`].
+pub const JSON_FORMAT_VERSION: u32 = 1;
+
+/// What [`IndexedTable::import_json`] does with a policy whose name is already
+/// used by another policy in the table.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum NameConflict {
+    /// Keeps the existing policy and reports a conflict.
+    Skip,
+
+    /// Adds the policy under a new name, such as "name (2)".
+    Rename,
+
+    /// Replaces the rules of the existing policy, keeping its ID and scope.
+    Overwrite,
+}
+
+#[derive(Deserialize, Serialize)]
+struct JsonDocument {
+    version: u32,
+    policies: Vec<PolicyJson>,
+}
+
+/// A policy in the JSON format, without the fields specific to a database.
+#[derive(Clone, PartialEq, Deserialize, Serialize)]
+struct PolicyJson {
+    name: String,
+    ti_db: Vec<Ti>,
+    packet_attr: Vec<PacketAttr>,
+    confidence: Vec<Confidence>,
+    response: Vec<Response>,
+    enabled: bool,
+    active_from: Option<DateTime<Utc>>,
+    active_until: Option<DateTime<Utc>>,
+}
+
+impl From<TriagePolicy> for PolicyJson {
+    fn from(policy: TriagePolicy) -> Self {
+        Self {
+            name: policy.name,
+            ti_db: policy.ti_db,
+            packet_attr: policy.packet_attr,
+            confidence: policy.confidence,
+            response: policy.response,
+            enabled: policy.enabled,
+            active_from: policy.active_from,
+            active_until: policy.active_until,
+        }
+    }
+}
+
+impl From<PolicyJson> for TriagePolicy {
+    fn from(policy: PolicyJson) -> Self {
+        Self {
+            id: u32::MAX,
+            name: policy.name,
+            ti_db: policy.ti_db,
+            packet_attr: policy.packet_attr,
+            confidence: policy.confidence,
+            response: policy.response,
+            customer_ids: Vec::new(),
+            network_ids: Vec::new(),
+            enabled: policy.enabled,
+            active_from: policy.active_from,
+            active_until: policy.active_until,
+            creation_time: Utc::now(),
+        }
+    }
+}
+
+/// A version of a triage policy, returned by
+/// [`IndexedTable::get_history`].
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Version {
+    /// The version number, starting from 1.
+    pub version: u32,
+
+    /// Who made this version, if known.
+    pub author: Option<String>,
+
+    /// When this version was made.
+    pub time: DateTime<Utc>,
+
+    pub policy: TriagePolicy,
 }
 
 #[derive(Clone)]
@@ -261,6 +961,28 @@ pub struct Update {
     pub packet_attr: Vec<PacketAttr>,
     pub confidence: Vec<Confidence>,
     pub response: Vec<Response>,
+    pub customer_ids: Vec<u32>,
+    pub network_ids: Vec<u32>,
+    pub enabled: bool,
+    pub active_from: Option<DateTime<Utc>>,
+    pub active_until: Option<DateTime<Utc>>,
+}
+
+impl From<&TriagePolicy> for Update {
+    fn from(policy: &TriagePolicy) -> Self {
+        Self {
+            name: policy.name.clone(),
+            ti_db: policy.ti_db.clone(),
+            packet_attr: policy.packet_attr.clone(),
+            confidence: policy.confidence.clone(),
+            response: policy.response.clone(),
+            customer_ids: policy.customer_ids.clone(),
+            network_ids: policy.network_ids.clone(),
+            enabled: policy.enabled,
+            active_from: policy.active_from,
+            active_until: policy.active_until,
+        }
+    }
 }
 
 impl IndexedMapUpdate for Update {
@@ -289,6 +1011,13 @@ impl IndexedMapUpdate for Update {
         response.sort_unstable();
         value.response = response;
 
+        value.customer_ids = sorted_ids(&self.customer_ids);
+        value.network_ids = sorted_ids(&self.network_ids);
+        value.enabled = self.enabled;
+        value.active_from = self.active_from;
+        value.active_until = self.active_until;
+
+        value.validate()?;
         Ok(value)
     }
 
@@ -318,17 +1047,36 @@ impl IndexedMapUpdate for Update {
         if response != value.response {
             return false;
         }
-        true
+        sorted_ids(&self.customer_ids) == value.customer_ids
+            && sorted_ids(&self.network_ids) == value.network_ids
+            && self.enabled == value.enabled
+            && self.active_from == value.active_from
+            && self.active_until == value.active_until
     }
 }
 
+fn sorted_ids(ids: &[u32]) -> Vec<u32> {
+    let mut ids = ids.to_vec();
+    ids.sort_unstable();
+    ids.dedup();
+    ids
+}
+
 #[cfg(test)]
 mod test {
-    use std::sync::Arc;
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        sync::Arc,
+    };
 
-    use chrono::Utc;
+    use chrono::{Duration, Utc};
 
-    use crate::{Store, TriagePolicy, TriagePolicyUpdate};
+    use crate::{
+        types::HostNetworkGroup, AttrCmpKind, Customer, CustomerNetwork, DnsCovertChannel, Event,
+        EventCategory, MatchedRule, Network, NetworkType, PacketAttr, Response, ResponseKind,
+        Store, Ti, TiCmpKind, Tidb, TidbKind, TidbRule, TriagePolicy, TriagePolicyNameConflict,
+        TriagePolicyUpdate, ValueKind,
+    };
 
     #[test]
     fn update() {
@@ -348,6 +1096,442 @@ mod test {
         assert_eq!(entry.map(|e| e.name), Some("b".to_string()));
     }
 
+    #[test]
+    fn history() {
+        let store = setup_store();
+        let mut table = store.triage_policy_map();
+        let id = table.put(create_entry("a")).unwrap();
+        assert!(table.get_history(id).unwrap().is_empty());
+
+        table
+            .update_by(id, &create_update("a"), &create_update("b"), "alice")
+            .unwrap();
+        table
+            .update(id, &create_update("b"), &create_update("c"))
+            .unwrap();
+        let history = table.get_history(id).unwrap();
+        let versions: Vec<_> = history
+            .iter()
+            .map(|v| (v.version, v.author.as_deref(), v.policy.name.as_str()))
+            .collect();
+        assert_eq!(
+            versions,
+            [(1, None, "a"), (2, Some("alice"), "b"), (3, None, "c")]
+        );
+
+        table.rollback(id, 1, "bob").unwrap();
+        assert_eq!(table.get_by_id(id).unwrap().unwrap().name, "a");
+        let history = table.get_history(id).unwrap();
+        assert_eq!(history.len(), 4);
+        assert_eq!(history[3].author.as_deref(), Some("bob"));
+        assert!(table.rollback(id, 5, "bob").is_err());
+
+        // Updates through `update_many` are recorded as well.
+        table
+            .update_many(&[(id, create_update("a"), create_update("e"))])
+            .unwrap();
+        let history = table.get_history(id).unwrap();
+        assert_eq!(history.len(), 5);
+        assert_eq!(history[4].version, 5);
+        assert_eq!(history[4].author, None);
+        assert_eq!(history[4].policy.name, "e");
+
+        // A failed update leaves the history intact.
+        assert!(table
+            .update_by(id, &create_update("a"), &create_update("f"), "carol")
+            .is_err());
+        assert_eq!(table.get_history(id).unwrap().len(), 5);
+
+        // The history is removed with the policy.
+        let policy = table.get_by_id(id).unwrap().unwrap();
+        table.remove(id).unwrap();
+        let meta = table.meta().unwrap();
+        assert!(meta.get(&history_key(&policy)).unwrap().is_none());
+
+        // A new policy reusing the ID doesn't inherit the history.
+        let new_id = table.put(create_entry("d")).unwrap();
+        assert_eq!(new_id, id);
+        assert!(table.get_history(new_id).unwrap().is_empty());
+    }
+
+    #[test]
+    fn scope() {
+        let store = setup_store();
+        let customer_id = store
+            .customer_map()
+            .put(Customer {
+                id: u32::MAX,
+                name: "customer".to_string(),
+                description: String::new(),
+                networks: vec![CustomerNetwork {
+                    name: "office".to_string(),
+                    description: String::new(),
+                    network_type: NetworkType::Intranet,
+                    network_group: HostNetworkGroup::new(
+                        vec![],
+                        vec!["10.0.0.0/8".parse().unwrap()],
+                        vec![],
+                    ),
+                }],
+                creation_time: Utc::now(),
+            })
+            .unwrap();
+        let network_id = store
+            .network_map()
+            .insert(Network::new(
+                "lab".to_string(),
+                String::new(),
+                HostNetworkGroup::new(vec!["192.168.0.1".parse().unwrap()], vec![], vec![]),
+                vec![],
+                vec![],
+            ))
+            .unwrap();
+
+        let table = store.triage_policy_map();
+        table.insert(create_entry("global")).unwrap();
+        let mut policy = create_entry("customer");
+        policy.customer_ids = vec![customer_id];
+        table.insert(policy).unwrap();
+        let mut policy = create_entry("network");
+        policy.network_ids = vec![network_id];
+        table.insert(policy).unwrap();
+
+        let names = |addr: &str| -> Vec<String> {
+            store
+                .applicable_triage_policies(addr.parse().unwrap())
+                .unwrap()
+                .into_iter()
+                .map(|policy| policy.name)
+                .collect()
+        };
+        assert_eq!(names("10.1.1.1"), ["customer", "global"]);
+        assert_eq!(names("192.168.0.1"), ["global", "network"]);
+        assert_eq!(names("172.16.0.1"), ["global"]);
+    }
+
+    #[test]
+    fn active() {
+        let store = setup_store();
+        let table = store.triage_policy_map();
+        let now = Utc::now();
+        let hour = Duration::hours(1);
+
+        table.insert(create_entry("always")).unwrap();
+        let mut policy = create_entry("disabled");
+        policy.enabled = false;
+        table.insert(policy).unwrap();
+        let mut policy = create_entry("scheduled");
+        policy.active_from = Some(now + hour);
+        policy.active_until = Some(now + hour * 2);
+        table.insert(policy).unwrap();
+        let mut policy = create_entry("reversed");
+        policy.active_from = Some(now + hour);
+        policy.active_until = Some(now);
+        assert!(table.insert(policy).is_err());
+
+        let names = |time| -> Vec<String> {
+            table
+                .active_at(time)
+                .unwrap()
+                .into_iter()
+                .map(|policy| policy.name)
+                .collect()
+        };
+        assert_eq!(names(now), ["always"]);
+        assert_eq!(names(now + hour), ["always", "scheduled"]);
+        assert_eq!(names(now + hour * 2), ["always"]);
+    }
+
+    #[test]
+    fn json() {
+        let store = setup_store();
+        let table = store.triage_policy_map();
+        let mut policy = create_entry("a");
+        policy.response = vec![Response {
+            minimum_score: 1.0,
+            kind: ResponseKind::Manual,
+        }];
+        table.insert(policy).unwrap();
+        table.insert(create_entry("b")).unwrap();
+        let mut json = Vec::new();
+        table.export_json(&mut json).unwrap();
+
+        let other = setup_store();
+        let mut table = other.triage_policy_map();
+        let report = table
+            .import_json(json.as_slice(), TriagePolicyNameConflict::Skip)
+            .unwrap();
+        assert_eq!(report.imported.len(), 2);
+        let report = table
+            .import_json(json.as_slice(), TriagePolicyNameConflict::Skip)
+            .unwrap();
+        assert_eq!(report.unchanged.len(), 2);
+
+        let a = table.get_by_name("a").unwrap().unwrap();
+        let mut update = TriagePolicyUpdate::from(&a);
+        update.response.clear();
+        table
+            .update(a.id, &TriagePolicyUpdate::from(&a), &update)
+            .unwrap();
+        let report = table
+            .import_json(json.as_slice(), TriagePolicyNameConflict::Skip)
+            .unwrap();
+        assert_eq!(report.conflicts.len(), 1);
+        assert_eq!(report.conflicts[0].id, a.id);
+
+        let report = table
+            .import_json(json.as_slice(), TriagePolicyNameConflict::Rename)
+            .unwrap();
+        assert_eq!(report.imported.len(), 1);
+        let renamed = table.get_by_name("a (2)").unwrap().unwrap();
+        assert_eq!(renamed.response.len(), 1);
+
+        let report = table
+            .import_json(json.as_slice(), TriagePolicyNameConflict::Overwrite)
+            .unwrap();
+        assert_eq!(report.imported, [a.id]);
+        assert_eq!(table.get_by_id(a.id).unwrap().unwrap().response.len(), 1);
+
+        let json = String::from_utf8(json)
+            .unwrap()
+            .replacen("\"version\": 1", "\"version\": 2", 1);
+        assert!(table
+            .import_json(json.as_bytes(), TriagePolicyNameConflict::Skip)
+            .is_err());
+    }
+
+    #[test]
+    fn duplicate() {
+        let store = setup_store();
+        let mut table = store.triage_policy_map();
+        let mut policy = create_entry("a");
+        policy.response = vec![Response {
+            minimum_score: 1.0,
+            kind: ResponseKind::Manual,
+        }];
+        let id = table.insert(policy).unwrap();
+        table
+            .update_by(
+                id,
+                &TriagePolicyUpdate::from(&table.get_by_id(id).unwrap().unwrap()),
+                &TriagePolicyUpdate {
+                    response: vec![Response {
+                        minimum_score: 2.0,
+                        kind: ResponseKind::Manual,
+                    }],
+                    ..create_update("a")
+                },
+                "alice",
+            )
+            .unwrap();
+
+        let copy_id = table.duplicate(id, "b").unwrap();
+        assert_ne!(copy_id, id);
+        let copy = table.get_by_id(copy_id).unwrap().unwrap();
+        assert_eq!(copy.name, "b");
+        assert!(copy.response == table.get_by_id(id).unwrap().unwrap().response);
+        assert!(table.get_history(copy_id).unwrap().is_empty());
+
+        assert!(table.duplicate(id, "b").is_err());
+        assert!(table.duplicate(copy_id + 1, "c").is_err());
+        assert_eq!(table.count().unwrap(), 2);
+    }
+
+    #[test]
+    fn validate() {
+        let store = setup_store();
+        let mut table = store.triage_policy_map();
+        let range = PacketAttr {
+            attr_name: "dst_port".to_string(),
+            value_kind: ValueKind::Integer,
+            cmp_kind: AttrCmpKind::CloseRange,
+            first_value: 50_i64.to_le_bytes().to_vec(),
+            second_value: Some(60_i64.to_le_bytes().to_vec()),
+            weight: None,
+        };
+        let response = Response {
+            minimum_score: 1.0,
+            kind: ResponseKind::Manual,
+        };
+
+        let mut policy = create_entry("a");
+        policy.packet_attr = vec![range.clone()];
+        policy.response = vec![response.clone()];
+        let id = table.insert(policy.clone()).unwrap();
+        let old = TriagePolicyUpdate::from(&policy);
+
+        let mut duplicate = range.clone();
+        duplicate.weight = Some(2.0);
+        let mut missing = range.clone();
+        missing.second_value = None;
+        let mut reversed = range.clone();
+        reversed.first_value = 70_i64.to_le_bytes().to_vec();
+        let mut malformed = range.clone();
+        malformed.first_value = vec![1, 2];
+        let mut negative = range.clone();
+        negative.weight = Some(-1.0);
+        for packet_attr in [
+            vec![range.clone(), duplicate],
+            vec![missing],
+            vec![reversed],
+            vec![malformed],
+            vec![negative],
+        ] {
+            let mut policy = create_entry("b");
+            policy.packet_attr = packet_attr.clone();
+            assert!(table.insert(policy.clone()).is_err());
+            assert!(table.put(policy).is_err());
+
+            let mut update = create_update("a");
+            update.packet_attr = packet_attr;
+            assert!(table.update(id, &old, &update).is_err());
+        }
+
+        let mut policy = create_entry("b");
+        policy.response = vec![response.clone(), response.clone()];
+        assert!(table.insert(policy).is_err());
+
+        let mut policy = create_entry("b");
+        policy.response = vec![Response {
+            minimum_score: f64::NAN,
+            kind: ResponseKind::Manual,
+        }];
+        assert!(table.insert(policy).is_err());
+        assert!(table.insert(create_entry(" ")).is_err());
+        assert_eq!(table.count().unwrap(), 1);
+    }
+
+    #[test]
+    fn regex() {
+        let regex = |cmp_kind, pattern: &str| PacketAttr {
+            attr_name: "query".to_string(),
+            value_kind: ValueKind::String,
+            cmp_kind,
+            first_value: pattern.as_bytes().to_vec(),
+            second_value: None,
+            weight: None,
+        };
+        let event = dns_event();
+        let mut policy = create_entry("a");
+        policy.packet_attr = vec![
+            regex(AttrCmpKind::Regex, r"^evil\.[a-z]+\.com$"),
+            regex(AttrCmpKind::NotRegex, "^good"),
+            regex(AttrCmpKind::Regex, "^good"),
+        ];
+        assert!(policy.validate().is_ok());
+        let result = policy.score(&event, &[], Utc::now());
+        assert_eq!(result.matched.len(), 2);
+
+        policy.packet_attr = vec![regex(AttrCmpKind::Regex, "(")];
+        assert!(policy.validate().is_err());
+    }
+
+    #[test]
+    fn score() {
+        let event = dns_event();
+        let tidb = Tidb {
+            id: 1,
+            name: "bad networks".to_string(),
+            description: None,
+            kind: TidbKind::Ip,
+            version: "1".to_string(),
+            patterns: vec![TidbRule {
+                rule_id: 1,
+                name: "private".to_string(),
+                description: None,
+                references: None,
+                samples: None,
+                signatures: Some(vec!["192.168.0.0/16".to_string()]),
+                confidence: None,
+                valid_until: None,
+            }],
+        };
+        let ti = Ti {
+            ti_name: "bad networks".to_string(),
+            kind: TiCmpKind::IpAddress,
+            weight: Some(2.0),
+        };
+        let port = PacketAttr {
+            attr_name: "dst_port".to_string(),
+            value_kind: ValueKind::Integer,
+            cmp_kind: AttrCmpKind::CloseRange,
+            first_value: 50_i64.to_le_bytes().to_vec(),
+            second_value: Some(60_i64.to_le_bytes().to_vec()),
+            weight: None,
+        };
+        let query = PacketAttr {
+            attr_name: "query".to_string(),
+            value_kind: ValueKind::String,
+            cmp_kind: AttrCmpKind::Contain,
+            first_value: b"good".to_vec(),
+            second_value: None,
+            weight: None,
+        };
+        let mut policy = create_entry("a");
+        policy.ti_db = vec![ti.clone()];
+        policy.packet_attr = vec![port.clone(), query];
+        policy.confidence = vec![crate::Confidence {
+            threat_category: EventCategory::CommandAndControl,
+            threat_kind: "DNS Covert Channel".to_string(),
+            confidence: 0.9,
+            weight: None,
+        }];
+        policy.response = vec![
+            Response {
+                minimum_score: 1.0,
+                kind: ResponseKind::Manual,
+            },
+            Response {
+                minimum_score: 3.0,
+                kind: ResponseKind::Blacklist,
+            },
+        ];
+
+        let now = Utc::now();
+        let result = policy.score(&event, &[tidb.clone()], now);
+        assert!((result.score - 3.0).abs() < f64::EPSILON);
+        assert!(result.matched == [MatchedRule::Ti(ti), MatchedRule::PacketAttr(port.clone())]);
+        assert!(result.response == Some(ResponseKind::Blacklist));
+
+        // An expired rule doesn't match.
+        let mut expired = tidb;
+        expired.patterns[0].valid_until = Some(now - Duration::hours(1));
+        let result = policy.score(&event, &[expired], now);
+        assert!((result.score - 1.0).abs() < f64::EPSILON);
+
+        let result = policy.score(&event, &[], now);
+        assert!((result.score - 1.0).abs() < f64::EPSILON);
+        assert!(result.matched == [MatchedRule::PacketAttr(port)]);
+        assert!(result.response == Some(ResponseKind::Manual));
+    }
+
+    fn dns_event() -> Event {
+        Event::DnsCovertChannel(DnsCovertChannel {
+            time: Utc::now(),
+            source: "collector1".to_string(),
+            session_end_time: Utc::now(),
+            src_addr: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            src_port: 10000,
+            dst_addr: IpAddr::V4(Ipv4Addr::new(192, 168, 0, 2)),
+            dst_port: 53,
+            proto: 17,
+            query: "evil.example.com".to_string(),
+            answer: vec![],
+            trans_id: 1,
+            rtt: 1,
+            qclass: 0,
+            qtype: 0,
+            rcode: 0,
+            aa_flag: false,
+            tc_flag: false,
+            rd_flag: false,
+            ra_flag: false,
+            ttl: vec![],
+            confidence: 0.8,
+            triage_scores: None,
+        })
+    }
+
     fn setup_store() -> Arc<Store> {
         let db_dir = tempfile::tempdir().unwrap();
         let backup_dir = tempfile::tempdir().unwrap();
@@ -362,6 +1546,11 @@ mod test {
             packet_attr: vec![],
             response: vec![],
             confidence: vec![],
+            customer_ids: vec![],
+            network_ids: vec![],
+            enabled: true,
+            active_from: None,
+            active_until: None,
             creation_time: Utc::now(),
         }
     }
@@ -373,6 +1562,11 @@ mod test {
             packet_attr: vec![],
             confidence: vec![],
             response: vec![],
+            customer_ids: vec![],
+            network_ids: vec![],
+            enabled: true,
+            active_from: None,
+            active_until: None,
         }
     }
 }
@@ -2,13 +2,14 @@
 
 use std::borrow::Cow;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
-use rocksdb::{Direction, OptimisticTransactionDB};
+use rocksdb::{ColumnFamily, Direction, IteratorMode, OptimisticTransactionDB, Transaction};
 use serde::{Deserialize, Serialize};
 
 use crate::{
     types::FromKeyValue, Indexable, Indexed, IndexedMap, IndexedMapUpdate, IndexedTable, Iterable,
+    MatchedRule, TriageResult,
 };
 
 #[derive(Deserialize, Serialize)]
@@ -21,6 +22,35 @@ pub struct TriageResponse {
     pub remarks: String,
     creation_time: DateTime<Utc>,
     last_modified_time: DateTime<Utc>,
+    policy: Option<TriggeredPolicy>,
+}
+
+/// The triage policy that produced a response, and how.
+#[derive(Clone, PartialEq, Deserialize, Serialize)]
+pub struct TriggeredPolicy {
+    pub policy_id: u32,
+
+    /// The version of the policy in its history when it was evaluated.
+    pub policy_version: u32,
+
+    pub score: f64,
+
+    /// The rules of the policy matched by the event.
+    pub matched: Vec<MatchedRule>,
+}
+
+impl TriggeredPolicy {
+    /// Creates a `TriggeredPolicy` from the result of evaluating version
+    /// `policy_version` of a policy.
+    #[must_use]
+    pub fn new(result: TriageResult, policy_version: u32) -> Self {
+        Self {
+            policy_id: result.policy_id,
+            policy_version,
+            score: result.score,
+            matched: result.matched,
+        }
+    }
 }
 
 impl TriageResponse {
@@ -40,20 +70,48 @@ impl TriageResponse {
             remarks,
             creation_time,
             last_modified_time,
+            policy: None,
         }
     }
 
+    /// Records the triage policy that produced the response.
+    #[must_use]
+    pub fn with_policy(mut self, policy: TriggeredPolicy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
     #[must_use]
     pub fn tag_ids(&self) -> &[u32] {
         &self.tag_ids
     }
 
+    /// Returns the triage policy that produced the response, if recorded.
+    #[must_use]
+    pub fn policy(&self) -> Option<&TriggeredPolicy> {
+        self.policy.as_ref()
+    }
+
+    #[must_use]
+    pub fn creation_time(&self) -> DateTime<Utc> {
+        self.creation_time
+    }
+
     fn contains_tag(&self, tag: u32) -> Result<usize> {
         self.tag_ids
             .binary_search(&tag)
             .map_err(|idx| anyhow::anyhow!("{idx}"))
     }
 
+    /// Returns the key of the response in the index of responses by the
+    /// policies that produced them, if the policy is recorded.
+    fn policy_index_key(&self) -> Option<Vec<u8>> {
+        let policy = self.policy.as_ref()?;
+        let mut key = policy_index_prefix(policy.policy_id, &self.creation_time);
+        key.extend_from_slice(&self.id.to_be_bytes());
+        Some(key)
+    }
+
     fn create_key(source: &str, time: &DateTime<Utc>) -> Vec<u8> {
         let mut key = source.as_bytes().to_vec();
         key.extend_from_slice(&time.timestamp_nanos_opt().unwrap_or_default().to_be_bytes());
@@ -93,6 +151,66 @@ impl Indexable for TriageResponse {
     fn set_index(&mut self, index: u32) {
         self.id = index;
     }
+
+    /// Indexes the response by the policy that produced it.
+    fn add_references(
+        &self,
+        db: &OptimisticTransactionDB,
+        txn: &Transaction<OptimisticTransactionDB>,
+    ) -> Result<()> {
+        let Some(key) = self.policy_index_key() else {
+            return Ok(());
+        };
+        txn.put_cf(policy_index(db)?, key, [])
+            .context("failed to index triage response")
+    }
+
+    /// Removes the response from the index by policy.
+    fn remove_references(
+        db: &OptimisticTransactionDB,
+        txn: &Transaction<OptimisticTransactionDB>,
+        _id: u32,
+        key: &[u8],
+    ) -> Result<()> {
+        let responses = db
+            .cf_handle(super::TRIAGE_RESPONSE)
+            .context("triage response table must be present")?;
+        // The response is already deleted in `txn`. Its policy and creation
+        // time never change, so the stored one is read instead.
+        let Some(value) = db
+            .get_cf(responses, key)
+            .context("cannot read triage response")?
+        else {
+            return Ok(());
+        };
+        let response: TriageResponse = super::deserialize(&value)?;
+        let Some(key) = response.policy_index_key() else {
+            return Ok(());
+        };
+        txn.delete_cf(policy_index(db)?, key)
+            .context("failed to unindex triage response")
+    }
+}
+
+fn policy_index(db: &OptimisticTransactionDB) -> Result<&ColumnFamily> {
+    db.cf_handle(super::TRIAGE_RESPONSE_POLICIES)
+        .context("triage response policy index must be present")
+}
+
+/// Returns the prefix of the keys in the index by policy for the responses
+/// produced by the policy with `policy_id` at `creation_time`.
+fn policy_index_prefix(policy_id: u32, creation_time: &DateTime<Utc>) -> Vec<u8> {
+    // Times before the epoch sort before every creation time.
+    let nanos = creation_time
+        .timestamp_nanos_opt()
+        .unwrap_or(if creation_time.timestamp() < 0 {
+            0
+        } else {
+            i64::MAX
+        });
+    let mut key = policy_id.to_be_bytes().to_vec();
+    key.extend_from_slice(&u64::try_from(nanos).unwrap_or_default().to_be_bytes());
+    key
 }
 
 /// Functions for the `triage_response` indexed map.
@@ -119,6 +237,39 @@ impl<'d> IndexedTable<'d, TriageResponse> {
             .transpose()
     }
 
+    /// Returns the responses produced by the triage policy with `policy_id`
+    /// and created in `[start, end)`, in the order of their creation times.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails.
+    pub fn produced_by(
+        &self,
+        policy_id: u32,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<Vec<TriageResponse>> {
+        let index = policy_index(self.indexed_map.db())?;
+        let mut readopts = rocksdb::ReadOptions::default();
+        readopts.set_iterate_range(
+            policy_index_prefix(policy_id, &start)..policy_index_prefix(policy_id, &end),
+        );
+        let mut ids = Vec::new();
+        for item in self
+            .indexed_map
+            .db()
+            .iterator_cf_opt(index, readopts, IteratorMode::Start)
+        {
+            let (key, _) = item.context("cannot read triage response policy index")?;
+            let id = key
+                .get(key.len().saturating_sub(4)..)
+                .and_then(|id| <[u8; 4]>::try_from(id).ok())
+                .context("invalid key in triage response policy index")?;
+            ids.push(u32::from_be_bytes(id));
+        }
+        Ok(self.get_many(&ids)?.into_iter().flatten().collect())
+    }
+
     /// Removes `tag_id` in all the related entries
     ///
     /// # Errors
@@ -216,10 +367,13 @@ impl IndexedMapUpdate for Update {
 
 #[cfg(test)]
 mod test {
-    use chrono::Utc;
+    use chrono::{Duration, Utc};
     use std::sync::Arc;
 
-    use crate::{Iterable, Store, TriageResponse, TriageResponseUpdate};
+    use crate::{
+        Iterable, ResponseKind, Store, TriageResponse, TriageResponseUpdate, TriageResult,
+        TriggeredPolicy,
+    };
 
     #[test]
     fn operations() {
@@ -273,4 +427,49 @@ mod test {
         let iter = table.iter(rocksdb::Direction::Reverse, None);
         assert_eq!(iter.count(), 0);
     }
+
+    #[test]
+    fn produced_by() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(Store::new(db_dir.path(), backup_dir.path()).unwrap());
+        let table = store.triage_response_map();
+
+        let start = Utc::now();
+        let triggered = |policy_id| {
+            TriggeredPolicy::new(
+                TriageResult {
+                    policy_id,
+                    score: 1.0,
+                    matched: vec![],
+                    response: Some(ResponseKind::Manual),
+                },
+                2,
+            )
+        };
+        let response =
+            |source: &str| TriageResponse::new(source.to_string(), start, vec![], String::new());
+        let a = table.put(response("a").with_policy(triggered(1))).unwrap();
+        table.put(response("b").with_policy(triggered(2))).unwrap();
+        table.put(response("c")).unwrap();
+        let end = Utc::now() + Duration::seconds(1);
+
+        let responses = table.produced_by(1, start, end).unwrap();
+        assert_eq!(responses.len(), 1);
+        let policy = responses[0].policy().unwrap();
+        assert_eq!(policy.policy_id, 1);
+        assert_eq!(policy.policy_version, 2);
+        assert!(table
+            .produced_by(1, end, end + Duration::days(7))
+            .unwrap()
+            .is_empty());
+        assert!(table.produced_by(3, start, end).unwrap().is_empty());
+
+        table.put(response("d").with_policy(triggered(1))).unwrap();
+        assert_eq!(table.produced_by(1, start, end).unwrap().len(), 2);
+        table.remove(a).unwrap();
+        let responses = table.produced_by(1, start, end).unwrap();
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0].source, "d");
+    }
 }
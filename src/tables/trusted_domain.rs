@@ -0,0 +1,236 @@
+//! The `trusted domains` table.
+
+use std::borrow::Cow;
+
+use anyhow::{anyhow, bail, Result};
+use rocksdb::OptimisticTransactionDB;
+use serde::{Deserialize, Serialize};
+
+use crate::{types::FromKeyValue, Map, Table, UniqueKey};
+
+const WILDCARD: &str = "*.";
+
+/// A trusted domain.
+///
+/// A name starting with `*.`, such as `*.example.com`, is a wildcard entry
+/// that matches every subdomain of the rest of the name, but not the name
+/// itself; other names match only themselves.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct TrustedDomain {
+    /// The domain name in the normalized form returned by
+    /// [`TrustedDomain::normalize`].
+    pub name: String,
+    pub remarks: String,
+}
+
+impl TrustedDomain {
+    /// Returns `name` in the form stored in the table: in lowercase, without
+    /// the trailing dot, and with internationalized labels converted to
+    /// punycode. The leading `*.` of a wildcard entry is kept.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `name` is not a valid domain name, or has a
+    /// wildcard other than the leading `*.`.
+    pub fn normalize(name: &str) -> Result<String> {
+        let name = name.trim();
+        let name = name.strip_suffix('.').unwrap_or(name);
+        let (wildcard, domain) = match name.strip_prefix(WILDCARD) {
+            Some(domain) => (true, domain),
+            None => (false, name),
+        };
+        if domain.is_empty() {
+            bail!("empty domain name");
+        }
+        if domain.contains('*') {
+            bail!("wildcard must be the leftmost label: {name}");
+        }
+        let domain =
+            idna::domain_to_ascii(domain).map_err(|e| anyhow!("invalid domain name: {e:?}"))?;
+        if wildcard {
+            Ok(format!("{WILDCARD}{domain}"))
+        } else {
+            Ok(domain)
+        }
+    }
+
+    /// Returns `domain` normalized to be looked up in the table.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `domain` is not a valid domain name or is a
+    /// wildcard.
+    pub(crate) fn normalize_lookup(domain: &str) -> Result<String> {
+        let domain = Self::normalize(domain)?;
+        if domain.starts_with(WILDCARD) {
+            bail!("domain to look up cannot be a wildcard: {domain}");
+        }
+        Ok(domain)
+    }
+
+    /// Returns `true` if this is a wildcard entry.
+    #[must_use]
+    pub fn is_wildcard(&self) -> bool {
+        self.name.starts_with(WILDCARD)
+    }
+}
+
+impl FromKeyValue for TrustedDomain {
+    fn from_key_value(_key: &[u8], value: &[u8]) -> Result<Self> {
+        super::deserialize(value)
+    }
+}
+
+impl UniqueKey for TrustedDomain {
+    fn unique_key(&self) -> Cow<[u8]> {
+        Cow::Borrowed(self.name.as_bytes())
+    }
+}
+
+impl super::Value for TrustedDomain {
+    fn value(&self) -> Cow<[u8]> {
+        let Ok(value) = super::serialize(self) else {
+            unreachable!("serialization into memory should never fail")
+        };
+        Cow::Owned(value)
+    }
+}
+
+/// Functions for the `trusted domains` table.
+impl<'d> Table<'d, TrustedDomain> {
+    /// Opens the `trusted domains` table in the database.
+    ///
+    /// Returns `None` if the table does not exist.
+    pub(super) fn open(db: &'d OptimisticTransactionDB) -> Option<Self> {
+        Map::open(db, super::TRUSTED_DOMAINS).map(Table::new)
+    }
+
+    /// Adds a trusted domain, and returns the stored entry with its name
+    /// normalized.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `name` is invalid, the domain already exists, or
+    /// the database operation fails.
+    pub fn add(&self, name: &str, remarks: &str) -> Result<TrustedDomain> {
+        let entry = TrustedDomain {
+            name: TrustedDomain::normalize(name)?,
+            remarks: remarks.to_string(),
+        };
+        self.insert(&entry)?;
+        Ok(entry)
+    }
+
+    /// Removes the trusted domain with `name`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `name` is invalid or the database operation fails.
+    pub fn remove(&self, name: &str) -> Result<()> {
+        self.map.delete(TrustedDomain::normalize(name)?.as_bytes())
+    }
+
+    /// Returns `true` if `domain` matches an exact entry, or is a subdomain of
+    /// the domain of a wildcard entry.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `domain` is not a valid domain name or the database
+    /// operation fails.
+    pub fn is_trusted(&self, domain: &str) -> Result<bool> {
+        Ok(self.matching(domain)?.is_some())
+    }
+
+    /// Returns the entry `domain` matches: the exact entry if any, or else the
+    /// wildcard entry for the closest parent domain.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `domain` is not a valid domain name or the database
+    /// operation fails.
+    pub fn matching(&self, domain: &str) -> Result<Option<TrustedDomain>> {
+        self.matching_normalized(&TrustedDomain::normalize_lookup(domain)?)
+    }
+
+    /// Returns the entry `domain`, normalized by
+    /// [`TrustedDomain::normalize_lookup`], matches.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails.
+    pub(crate) fn matching_normalized(&self, domain: &str) -> Result<Option<TrustedDomain>> {
+        if let Some(value) = self.map.get(domain.as_bytes())? {
+            return Ok(Some(TrustedDomain::from_key_value(
+                domain.as_bytes(),
+                value.as_ref(),
+            )?));
+        }
+        let mut parent = domain;
+        while let Some((_, rest)) = parent.split_once('.') {
+            let key = format!("{WILDCARD}{rest}");
+            if let Some(value) = self.map.get(key.as_bytes())? {
+                return Ok(Some(TrustedDomain::from_key_value(
+                    key.as_bytes(),
+                    value.as_ref(),
+                )?));
+            }
+            parent = rest;
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::TrustedDomain;
+    use crate::Store;
+
+    #[test]
+    fn normalize() {
+        assert_eq!(
+            TrustedDomain::normalize("WWW.Example.COM.").unwrap(),
+            "www.example.com"
+        );
+        assert_eq!(
+            TrustedDomain::normalize("*.bücher.example").unwrap(),
+            "*.xn--bcher-kva.example"
+        );
+        assert!(TrustedDomain::normalize("").is_err());
+        assert!(TrustedDomain::normalize("*.").is_err());
+        assert!(TrustedDomain::normalize("www.*.example.com").is_err());
+    }
+
+    #[test]
+    fn is_trusted() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(Store::new(db_dir.path(), backup_dir.path()).unwrap());
+        let table = store.trusted_domain_map();
+
+        let entry = table.add("*.Example.com", "wildcard").unwrap();
+        assert_eq!(entry.name, "*.example.com");
+        assert!(entry.is_wildcard());
+        assert!(table.add("*.example.com.", "duplicate").is_err());
+        table.add("intranet.test", "exact").unwrap();
+        table.add("*.bücher.example", "internationalized").unwrap();
+
+        assert!(table.is_trusted("www.example.com").unwrap());
+        assert!(table.is_trusted("a.b.EXAMPLE.com.").unwrap());
+        assert!(!table.is_trusted("example.com").unwrap());
+        assert!(!table.is_trusted("badexample.com").unwrap());
+        assert!(table.is_trusted("intranet.test").unwrap());
+        assert!(!table.is_trusted("www.intranet.test").unwrap());
+        assert!(table.is_trusted("shop.xn--bcher-kva.example").unwrap());
+        assert!(table.is_trusted("shop.Bücher.example").unwrap());
+        assert_eq!(
+            table.matching("www.example.com").unwrap().unwrap().remarks,
+            "wildcard"
+        );
+        assert!(table.matching("example.com").unwrap().is_none());
+
+        table.remove("*.EXAMPLE.com").unwrap();
+        assert!(!table.is_trusted("www.example.com").unwrap());
+    }
+}
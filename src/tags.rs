@@ -1,4 +1,4 @@
-use crate::{collections::IndexedSet, IndexedTable, Network, TriageResponse};
+use crate::{collections::IndexedSet, EventDb, IndexedTable, Network, TriageResponse};
 
 // Kinds of tag IDs. They are used to define the behavior of tag sets.
 
@@ -83,7 +83,8 @@ impl<'a, IdKind> TagSet<'a, IdKind> {
 }
 
 impl<'a> TagSet<'a, EventTagId> {
-    /// Removes a tag from the event tag set, returning its name.
+    /// Removes a tag from the event tag set, detaching it from triage
+    /// responses and events, and returns its name.
     ///
     /// # Errors
     ///
@@ -92,9 +93,11 @@ impl<'a> TagSet<'a, EventTagId> {
         &mut self,
         id: u32,
         triage_responses: &IndexedTable<TriageResponse>,
+        events: &EventDb,
     ) -> anyhow::Result<String> {
         let key = self.set.deactivate(id)?;
         triage_responses.remove_tag(id)?;
+        events.remove_tag(id)?;
         self.set.clear_inactive()?;
 
         let name = String::from_utf8(key)?;
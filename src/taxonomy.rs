@@ -0,0 +1,130 @@
+//! CSV import and export of the name-keyed tables, such as categories,
+//! qualifiers, and statuses, so that deployments can share a taxonomy.
+
+use std::io::{Read, Write};
+
+use anyhow::{Context, Result};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{types::FromKeyValue, Indexable, IndexedTable, Iterable, Named};
+
+/// The result of [`IndexedTable::import_csv`] and
+/// [`IndexedTable::import_json`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    /// The IDs of the records added to the table.
+    pub imported: Vec<u32>,
+
+    /// The IDs of the records identical to the ones already in the table.
+    pub unchanged: Vec<u32>,
+
+    /// The records not imported because they conflict with the table.
+    pub conflicts: Vec<ImportConflict>,
+}
+
+/// A record that conflicts with the table.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ImportConflict {
+    pub id: u32,
+    pub name: String,
+
+    /// Why the record cannot be imported.
+    pub reason: String,
+}
+
+impl<'d, R> IndexedTable<'d, R>
+where
+    R: Named + Indexable + FromKeyValue + PartialEq + Serialize + DeserializeOwned,
+{
+    /// Writes the records in the table to `writer` as CSV, with a header row.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any record cannot be read or written.
+    pub fn export_csv<W: Write>(&self, writer: W) -> Result<()> {
+        let mut writer = csv::Writer::from_writer(writer);
+        for record in self.iter(crate::Direction::Forward, None) {
+            writer
+                .serialize(record?)
+                .context("cannot write CSV record")?;
+        }
+        writer.flush().context("cannot write CSV")
+    }
+
+    /// Reads records in the format of [`IndexedTable::export_csv`] from
+    /// `reader`, and adds them to the table with their IDs.
+    ///
+    /// A record is not imported if its ID or name is already used by another
+    /// record in the table. Such records are reported instead of overwriting
+    /// the existing ones.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the CSV is invalid or the database operation fails.
+    pub fn import_csv<Rd: Read>(&self, reader: Rd) -> Result<ImportReport> {
+        let mut reader = csv::Reader::from_reader(reader);
+        let mut report = ImportReport::default();
+        for record in reader.deserialize::<R>() {
+            let record = record.context("invalid CSV record")?;
+            let id = record.index();
+            let conflict = if let Some(existing) = self.get_by_id(id)? {
+                if existing == record {
+                    report.unchanged.push(id);
+                    continue;
+                }
+                format!("ID {id} is used by \"{}\"", existing.name())
+            } else {
+                match self.put_with_id(&record) {
+                    Ok(()) => {
+                        report.imported.push(id);
+                        continue;
+                    }
+                    Err(e) => format!("{e:#}"),
+                }
+            };
+            report.conflicts.push(ImportConflict {
+                id,
+                name: record.name().to_string(),
+                reason: conflict,
+            });
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Store;
+
+    #[test]
+    fn export_and_import() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+        let table = store.category_map();
+        let parent = table.insert("Credential Access").unwrap();
+        table.insert_child("Brute Force", parent).unwrap();
+        let mut csv = Vec::new();
+        table.export_csv(&mut csv).unwrap();
+        assert!(String::from_utf8_lossy(&csv).starts_with("id,name,parent_id\n"));
+
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let other = Store::new(db_dir.path(), backup_dir.path()).unwrap();
+        let table = other.category_map();
+        let report = table.import_csv(csv.as_slice()).unwrap();
+        assert_eq!(report.imported, [4, 3]);
+        assert_eq!(report.unchanged, [2, 1]);
+        assert!(report.conflicts.is_empty());
+        let children = table.children(parent).unwrap();
+        assert_eq!(children[0].name, "Brute Force");
+
+        table.remove(4).unwrap();
+        table.insert("Phishing").unwrap();
+        let report = table.import_csv(csv.as_slice()).unwrap();
+        assert!(report.imported.is_empty());
+        assert_eq!(report.conflicts.len(), 1);
+        assert_eq!(report.conflicts[0].id, 4);
+        assert_eq!(table.get_by_id(4).unwrap().unwrap().name, "Phishing");
+    }
+}
@@ -1,4 +1,4 @@
-use super::{IterableMap, Store};
+use super::{IterableMap, Store, TrafficDirection};
 use anyhow::{bail, Context, Result};
 use bincode::Options;
 use chrono::{DateTime, Utc};
@@ -17,13 +17,117 @@ pub struct TrafficFilter {
     pub description: Option<String>,
 }
 
+/// A traffic filter in the format used until 0.26.
+#[derive(Deserialize, Serialize)]
+struct TrafficFilterV0_26 {
+    agent: String,
+    rules: HashMap<IpNet, ProtocolPortsV0_26>,
+    last_modification_time: DateTime<Utc>,
+    update_time: Option<DateTime<Utc>>,
+    description: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct ProtocolPortsV0_26 {
+    tcp_ports: Option<Vec<u16>>,
+    udp_ports: Option<Vec<u16>>,
+}
+
+/// An inclusive range of port numbers.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct PortRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl PortRange {
+    #[must_use]
+    pub fn contains(&self, port: u16) -> bool {
+        self.start <= port && port <= self.end
+    }
+}
+
+/// The traffic a rule applies to, in addition to its network.
 #[derive(Clone, Default, Deserialize, Serialize)]
 pub struct ProtocolPorts {
     tcp_ports: Option<Vec<u16>>,
     udp_ports: Option<Vec<u16>>,
+    tcp_port_ranges: Vec<PortRange>,
+    udp_port_ranges: Vec<PortRange>,
+    /// The IANA numbers of the layer-4 protocols other than TCP and UDP,
+    /// such as 1 for ICMP.
+    protocols: Vec<u8>,
+    /// Whether the rule applies to the traffic from or to its network. `None`
+    /// if it applies to both.
+    direction: Option<TrafficDirection>,
 }
 
 impl ProtocolPorts {
+    const TCP: u8 = 6;
+    const UDP: u8 = 17;
+
+    #[must_use]
+    pub fn new(tcp_ports: Option<Vec<u16>>, udp_ports: Option<Vec<u16>>) -> Self {
+        let mut rule = Self::default();
+        rule.update(tcp_ports, udp_ports);
+        rule
+    }
+
+    /// Sets the ranges of TCP and UDP ports the rule applies to, in addition
+    /// to the individual ports.
+    #[must_use]
+    pub fn with_port_ranges(
+        mut self,
+        mut tcp_port_ranges: Vec<PortRange>,
+        mut udp_port_ranges: Vec<PortRange>,
+    ) -> Self {
+        tcp_port_ranges.sort_unstable();
+        tcp_port_ranges.dedup();
+        udp_port_ranges.sort_unstable();
+        udp_port_ranges.dedup();
+        self.tcp_port_ranges = tcp_port_ranges;
+        self.udp_port_ranges = udp_port_ranges;
+        self
+    }
+
+    /// Sets the layer-4 protocols other than TCP and UDP the rule applies to.
+    #[must_use]
+    pub fn with_protocols(mut self, mut protocols: Vec<u8>) -> Self {
+        protocols.sort_unstable();
+        protocols.dedup();
+        self.protocols = protocols;
+        self
+    }
+
+    /// Limits the rule to the traffic from or to its network.
+    #[must_use]
+    pub fn with_direction(mut self, direction: TrafficDirection) -> Self {
+        self.direction = Some(direction);
+        self
+    }
+
+    /// Checks whether the rule is valid.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a port range ends before it starts, or TCP or UDP
+    /// is given as one of the other protocols.
+    pub fn validate(&self) -> Result<()> {
+        for range in self.tcp_port_ranges.iter().chain(&self.udp_port_ranges) {
+            if range.start > range.end {
+                bail!("invalid port range: {}-{}", range.start, range.end);
+            }
+        }
+        if let Some(protocol) = self
+            .protocols
+            .iter()
+            .find(|p| **p == Self::TCP || **p == Self::UDP)
+        {
+            bail!("protocol {protocol} should be specified with ports");
+        }
+        Ok(())
+    }
+
     fn update(&mut self, tcp_ports: Option<Vec<u16>>, udp_ports: Option<Vec<u16>>) {
         self.tcp_ports = tcp_ports;
         if let Some(tcp_ports) = &mut self.tcp_ports {
@@ -47,6 +151,93 @@ impl ProtocolPorts {
     pub fn udp_ports(&self) -> &Option<Vec<u16>> {
         &self.udp_ports
     }
+
+    #[must_use]
+    pub fn tcp_port_ranges(&self) -> &[PortRange] {
+        &self.tcp_port_ranges
+    }
+
+    #[must_use]
+    pub fn udp_port_ranges(&self) -> &[PortRange] {
+        &self.udp_port_ranges
+    }
+
+    #[must_use]
+    pub fn protocols(&self) -> &[u8] {
+        &self.protocols
+    }
+
+    #[must_use]
+    pub fn direction(&self) -> Option<TrafficDirection> {
+        self.direction
+    }
+
+    /// Returns `true` if the rule applies to all the traffic of its network
+    /// in its direction, i.e., it specifies no ports or protocols.
+    fn applies_to_all(&self) -> bool {
+        self.tcp_ports.as_ref().map_or(true, Vec::is_empty)
+            && self.udp_ports.as_ref().map_or(true, Vec::is_empty)
+            && self.tcp_port_ranges.is_empty()
+            && self.udp_port_ranges.is_empty()
+            && self.protocols.is_empty()
+    }
+
+    /// Returns `true` if this rule and `other`, for overlapping networks,
+    /// can apply to the same traffic: their directions overlap, and they
+    /// share a protocol, with a common port for TCP or UDP.
+    #[must_use]
+    pub fn overlaps(&self, other: &Self) -> bool {
+        if !directions_overlap(self.direction, other.direction) {
+            return false;
+        }
+        if self.applies_to_all() || other.applies_to_all() {
+            return true;
+        }
+        ports_overlap(
+            self.tcp_ports.as_deref().unwrap_or_default(),
+            &self.tcp_port_ranges,
+            other.tcp_ports.as_deref().unwrap_or_default(),
+            &other.tcp_port_ranges,
+        ) || ports_overlap(
+            self.udp_ports.as_deref().unwrap_or_default(),
+            &self.udp_port_ranges,
+            other.udp_ports.as_deref().unwrap_or_default(),
+            &other.udp_port_ranges,
+        ) || self.protocols.iter().any(|p| other.protocols.contains(p))
+    }
+}
+
+/// Returns `true` if rules with the given directions can apply to the same
+/// traffic.
+fn directions_overlap(a: Option<TrafficDirection>, b: Option<TrafficDirection>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a == b,
+        _ => true,
+    }
+}
+
+/// Returns `true` if the ports given as individual ports and ranges in `a`
+/// and those in `b` have any port in common.
+fn ports_overlap(
+    a_ports: &[u16],
+    a_ranges: &[PortRange],
+    b_ports: &[u16],
+    b_ranges: &[PortRange],
+) -> bool {
+    let contains = |ports: &[u16], ranges: &[PortRange], port: u16| {
+        ports.binary_search(&port).is_ok() || ranges.iter().any(|range| range.contains(port))
+    };
+    a_ports
+        .iter()
+        .any(|port| contains(b_ports, b_ranges, *port))
+        || b_ports
+            .iter()
+            .any(|port| a_ranges.iter().any(|r| r.contains(*port)))
+        || a_ranges.iter().any(|a| {
+            b_ranges
+                .iter()
+                .any(|b| a.start <= b.end && b.start <= a.end)
+        })
 }
 
 impl TrafficFilter {
@@ -77,13 +268,7 @@ impl TrafficFilter {
         description: Option<String>,
     ) -> Self {
         let mut rules = HashMap::new();
-        rules.insert(
-            net,
-            ProtocolPorts {
-                tcp_ports,
-                udp_ports,
-            },
-        );
+        rules.insert(net, ProtocolPorts::new(tcp_ports, udp_ports));
         Self {
             agent: agent.to_string(),
             rules,
@@ -161,22 +346,45 @@ impl TrafficFilter {
         udp_ports: Option<Vec<u16>>,
         description: Option<String>,
     ) -> Result<usize> {
+        Self::insert_rule(
+            store,
+            agent,
+            network,
+            ProtocolPorts::new(tcp_ports, udp_ports),
+            description,
+        )
+    }
+
+    /// Add a new rule with its protocols, ports and direction
+    ///
+    /// # Errors
+    ///
+    /// * Returns an error if the rule is invalid
+    /// * Returns an error if a conflicting rule is already exist in database
+    /// * Returns an error if it fails to encode rules
+    /// * Returns an error if it fails to insert rule in database
+    pub fn insert_rule(
+        store: &Store,
+        agent: &str,
+        network: IpNet,
+        rule: ProtocolPorts,
+        description: Option<String>,
+    ) -> Result<usize> {
+        rule.validate()?;
         let tf = match Self::get(store, agent)? {
             Some(mut v) => {
-                if let Some(net) = v.check_duplicate(network) {
+                if let Some(net) = v.check_duplicate(network, &rule) {
                     bail!("Duplicate rule found. \"{net}\"");
                 }
-                v.rules.insert(
-                    network,
-                    ProtocolPorts {
-                        tcp_ports,
-                        udp_ports,
-                    },
-                );
+                v.rules.insert(network, rule);
                 v.last_modification_time = Utc::now();
                 v
             }
-            None => Self::new(agent, network, tcp_ports, udp_ports, description),
+            None => {
+                let mut v = Self::new(agent, network, None, None, description);
+                v.rules.insert(network, rule);
+                v
+            }
         };
         let value = bincode::DefaultOptions::new().serialize(&tf)?;
         let map = store.traffic_filter_map();
@@ -191,6 +399,8 @@ impl TrafficFilter {
     /// * Returns an error if it fails to open database
     /// * Returns an error if agent is not exist in database
     /// * Returns an error if network of agent is not exist in the rules of agent
+    /// * Returns an error if the new ports make the rule conflict with another
+    ///   rule
     /// * Returns an error if it fails to write updated rules in database
     pub fn update(
         store: &Store,
@@ -202,15 +412,18 @@ impl TrafficFilter {
     ) -> Result<usize> {
         let tf = match Self::get(store, agent)? {
             Some(mut v) => {
-                if let Some(ports) = v.rules.get_mut(&network) {
-                    ports.update(tcp_ports, udp_ports);
-                    if let Some(description) = description {
-                        v.description = Some(description);
-                    }
-                    v.last_modification_time = Utc::now();
-                } else {
+                let Some(mut rule) = v.rules.remove(&network) else {
                     bail!("Rule not found");
+                };
+                rule.update(tcp_ports, udp_ports);
+                if let Some(net) = v.check_duplicate(network, &rule) {
+                    bail!("Duplicate rule found. \"{net}\"");
+                }
+                v.rules.insert(network, rule);
+                if let Some(description) = description {
+                    v.description = Some(description);
                 }
+                v.last_modification_time = Utc::now();
                 v
             }
             _ => bail!("Agent not found"),
@@ -221,6 +434,43 @@ impl TrafficFilter {
         Ok(tf.len())
     }
 
+    /// Replace the protocols, ports and direction of the rule for network
+    ///
+    /// # Errors
+    ///
+    /// * Returns an error if the rule is invalid
+    /// * Returns an error if agent is not exist in database
+    /// * Returns an error if network of agent is not exist in the rules of agent
+    /// * Returns an error if the new rule conflicts with another rule
+    /// * Returns an error if it fails to write updated rules in database
+    pub fn update_rule(
+        store: &Store,
+        agent: &str,
+        network: IpNet,
+        rule: ProtocolPorts,
+        description: Option<String>,
+    ) -> Result<usize> {
+        rule.validate()?;
+        let Some(mut tf) = Self::get(store, agent)? else {
+            bail!("Agent not found");
+        };
+        if tf.rules.remove(&network).is_none() {
+            bail!("Rule not found");
+        }
+        if let Some(net) = tf.check_duplicate(network, &rule) {
+            bail!("Duplicate rule found. \"{net}\"");
+        }
+        tf.rules.insert(network, rule);
+        if let Some(description) = description {
+            tf.description = Some(description);
+        }
+        tf.last_modification_time = Utc::now();
+        let value = bincode::DefaultOptions::new().serialize(&tf)?;
+        let map = store.traffic_filter_map();
+        map.put(agent.as_bytes(), &value)?;
+        Ok(tf.len())
+    }
+
     /// Remove some rules. Agent will be removed if rules is empty
     ///
     /// # Errors
@@ -285,27 +535,72 @@ impl TrafficFilter {
         Ok(0)
     }
 
-    fn check_duplicate(&self, network: IpNet) -> Option<IpNet> {
+    /// Returns the pairs of rules whose networks overlap and which can apply
+    /// to the same traffic, as described in [`ProtocolPorts::overlaps`]. Such
+    /// rules are rejected when inserting a rule but may exist in rules stored
+    /// by an older version.
+    #[must_use]
+    pub fn conflicts(&self) -> Vec<(IpNet, IpNet)> {
+        let mut rules: Vec<_> = self
+            .rules
+            .iter()
+            .filter(|(net, _)| !net.addr().is_unspecified())
+            .collect();
+        rules.sort_unstable_by_key(|(net, _)| **net);
+        let mut conflicts = Vec::new();
+        for (i, (a, a_rule)) in rules.iter().enumerate() {
+            for (b, b_rule) in &rules[i + 1..] {
+                if (a.contains(*b) || b.contains(*a)) && a_rule.overlaps(b_rule) {
+                    conflicts.push((**a, **b));
+                }
+            }
+        }
+        conflicts
+    }
+
+    fn check_duplicate(&self, network: IpNet, rule: &ProtocolPorts) -> Option<IpNet> {
         if network.addr().is_unspecified() && self.rules.get(&network).is_some() {
             return Some(network);
         }
         self.rules
-            .keys()
-            .find(|net| {
+            .iter()
+            .find(|(net, other)| {
                 if net.addr().is_unspecified() {
                     false
+                } else if **net == network {
+                    true
                 } else {
-                    **net == network || net.contains(&network) || network.contains(*net)
+                    (net.contains(&network) || network.contains(*net)) && other.overlaps(rule)
                 }
             })
-            .copied()
+            .map(|(net, _)| *net)
+    }
+
+    /// Deserializes a traffic filter stored in the format used until 0.26.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `value` is not a traffic filter in the old format.
+    pub(crate) fn from_v0_26(value: &[u8]) -> Result<Self> {
+        let old = bincode::DefaultOptions::new().deserialize::<TrafficFilterV0_26>(value)?;
+        Ok(Self {
+            agent: old.agent,
+            rules: old
+                .rules
+                .into_iter()
+                .map(|(net, ports)| (net, ProtocolPorts::new(ports.tcp_ports, ports.udp_ports)))
+                .collect(),
+            last_modification_time: old.last_modification_time,
+            update_time: old.update_time,
+            description: old.description,
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::IterableMap;
-    use crate::{Store, TrafficFilter};
+    use super::{IterableMap, PortRange, ProtocolPorts};
+    use crate::{Store, TrafficDirection, TrafficFilter};
     use std::sync::Arc;
 
     #[tokio::test]
@@ -417,7 +712,7 @@ mod tests {
             &store,
             agent,
             subnet_network,
-            Some(new_tcp_ports.clone()),
+            Some(vec![8000, 8888]),
             None,
             None,
         );
@@ -434,4 +729,87 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn rule_with_ranges_and_direction() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(Store::new(db_dir.path(), backup_dir.path()).unwrap());
+        let agent = "piglet@node1";
+
+        let invalid = ProtocolPorts::new(None, None).with_port_ranges(
+            vec![PortRange {
+                start: 9000,
+                end: 8000,
+            }],
+            Vec::new(),
+        );
+        let network = "10.0.0.0/8".parse().unwrap();
+        assert!(TrafficFilter::insert_rule(&store, agent, network, invalid, None).is_err());
+        let tcp = ProtocolPorts::new(None, None).with_protocols(vec![6]);
+        assert!(TrafficFilter::insert_rule(&store, agent, network, tcp, None).is_err());
+
+        let inbound = ProtocolPorts::new(Some(vec![443]), None)
+            .with_port_ranges(
+                vec![PortRange {
+                    start: 8000,
+                    end: 8999,
+                }],
+                Vec::new(),
+            )
+            .with_protocols(vec![1])
+            .with_direction(TrafficDirection::To);
+        assert_eq!(
+            TrafficFilter::insert_rule(&store, agent, network, inbound, None).unwrap(),
+            1
+        );
+
+        // A rule for a subnet doesn't conflict if it applies to the other
+        // direction.
+        let subnet = "10.1.0.0/16".parse().unwrap();
+        let outbound =
+            ProtocolPorts::new(None, Some(vec![53])).with_direction(TrafficDirection::From);
+        assert_eq!(
+            TrafficFilter::insert_rule(&store, agent, subnet, outbound, None).unwrap(),
+            2
+        );
+        // Nor does one for the protocols and ports the other doesn't cover.
+        let other_subnet = "10.2.0.0/16".parse().unwrap();
+        let both = ProtocolPorts::new(None, Some(vec![123]));
+        assert_eq!(
+            TrafficFilter::insert_rule(&store, agent, other_subnet, both, None).unwrap(),
+            3
+        );
+        let https = ProtocolPorts::new(Some(vec![8443]), None);
+        let third_subnet = "10.3.0.0/16".parse().unwrap();
+        assert!(TrafficFilter::insert_rule(&store, agent, third_subnet, https, None).is_err());
+        let all = ProtocolPorts::new(None, None).with_direction(TrafficDirection::To);
+        assert!(TrafficFilter::insert_rule(&store, agent, third_subnet, all, None).is_err());
+
+        let tf = TrafficFilter::get(&store, agent).unwrap().unwrap();
+        assert!(tf.conflicts().is_empty());
+        let rule = &tf.rules[&network];
+        assert!(rule.tcp_port_ranges()[0].contains(8080));
+        assert_eq!(rule.protocols(), [1]);
+        assert!(rule.direction() == Some(TrafficDirection::To));
+
+        // Changing the direction of the subnet rule makes it conflict only if
+        // the ports overlap.
+        let inbound = ProtocolPorts::new(None, Some(vec![53])).with_direction(TrafficDirection::To);
+        assert_eq!(
+            TrafficFilter::update_rule(&store, agent, subnet, inbound, None).unwrap(),
+            3
+        );
+        let inbound =
+            ProtocolPorts::new(Some(vec![443]), None).with_direction(TrafficDirection::To);
+        assert!(TrafficFilter::update_rule(&store, agent, subnet, inbound, None).is_err());
+        let outbound =
+            ProtocolPorts::new(None, Some(vec![53, 853])).with_direction(TrafficDirection::From);
+        assert_eq!(
+            TrafficFilter::update_rule(&store, agent, subnet, outbound, None).unwrap(),
+            3
+        );
+        let tf = TrafficFilter::get(&store, agent).unwrap().unwrap();
+        assert_eq!(tf.rules[&subnet].udp_ports(), &Some(vec![53, 853]));
+    }
 }
@@ -0,0 +1,179 @@
+use super::{tables::compiled_regex, types::FromKeyValue, IterableMap, Store};
+use anyhow::{bail, Context, Result};
+use bincode::Options;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// How a trusted user agent is matched against the user agent of traffic.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum UserAgentKind {
+    /// The user agent must be the same.
+    Exact,
+    /// The user agent must match the regular expression as a whole.
+    Regex,
+}
+
+#[derive(Deserialize, Serialize)]
+struct Value {
+    kind: UserAgentKind,
+    /// `None` for an entry migrated from 0.26 without a valid time.
+    updated_at: Option<DateTime<Utc>>,
+}
+
+/// An entry in the trusted user agent map.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TrustedUserAgent {
+    pub user_agent: String,
+    pub kind: UserAgentKind,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+impl TrustedUserAgent {
+    /// Returns `true` if `candidate` matches this entry.
+    #[must_use]
+    pub fn is_match(&self, candidate: &str) -> bool {
+        match self.kind {
+            UserAgentKind::Exact => self.user_agent == candidate,
+            UserAgentKind::Regex => compiled_regex(&anchored(&self.user_agent))
+                .is_some_and(|regex| regex.is_match(candidate)),
+        }
+    }
+
+    /// Adds a trusted user agent
+    ///
+    /// # Errors
+    ///
+    /// * Returns an error if `kind` is `Regex` and `user_agent` is not a valid
+    ///   regular expression
+    /// * Returns an error if the user agent already exists
+    /// * Returns an error if it fails to insert the entry in database
+    pub fn insert(store: &Store, user_agent: &str, kind: UserAgentKind) -> Result<()> {
+        if kind == UserAgentKind::Regex {
+            regex::Regex::new(user_agent).context("invalid regular expression")?;
+        }
+        let value = Value {
+            kind,
+            updated_at: Some(Utc::now()),
+        };
+        let value = bincode::DefaultOptions::new().serialize(&value)?;
+        store
+            .trusted_user_agent_map()
+            .insert(user_agent.as_bytes(), &value)
+    }
+
+    /// Removes a trusted user agent
+    ///
+    /// # Errors
+    ///
+    /// * Returns an error if it fails to delete the entry
+    pub fn remove(store: &Store, user_agent: &str) -> Result<()> {
+        store.trusted_user_agent_map().delete(user_agent.as_bytes())
+    }
+
+    /// Returns all the trusted user agents
+    ///
+    /// # Errors
+    ///
+    /// * Returns an error if it fails to read the entries from database
+    /// * Returns an error if an entry in the database is invalid
+    pub fn get_list(store: &Store) -> Result<Vec<TrustedUserAgent>> {
+        let map = store.trusted_user_agent_map();
+        let mut entries = Vec::new();
+        for (key, value) in map.iter_forward()? {
+            entries.push(Self::from_key_value(&key, &value)?);
+        }
+        Ok(entries)
+    }
+
+    /// Returns the trusted user agents `candidate` matches
+    ///
+    /// # Errors
+    ///
+    /// * Returns an error if it fails to read the entries from database
+    /// * Returns an error if an entry in the database is invalid
+    pub fn matching(store: &Store, candidate: &str) -> Result<Vec<TrustedUserAgent>> {
+        Ok(Self::get_list(store)?
+            .into_iter()
+            .filter(|entry| entry.is_match(candidate))
+            .collect())
+    }
+
+    /// Converts the value of an entry in 0.26, the time of the last update in
+    /// UTF-8, into the current format as an exact match.
+    pub(crate) fn value_from_v0_26(old: &[u8]) -> Result<Vec<u8>> {
+        let value = Value {
+            kind: UserAgentKind::Exact,
+            updated_at: std::str::from_utf8(old)
+                .ok()
+                .and_then(|time| time.parse().ok()),
+        };
+        Ok(bincode::DefaultOptions::new().serialize(&value)?)
+    }
+}
+
+impl FromKeyValue for TrustedUserAgent {
+    fn from_key_value(key: &[u8], value: &[u8]) -> Result<Self> {
+        let Ok(user_agent) = String::from_utf8(key.to_vec()) else {
+            bail!("invalid user agent in database");
+        };
+        let value = bincode::DefaultOptions::new()
+            .deserialize::<Value>(value)
+            .context("invalid trusted user agent in database")?;
+        Ok(Self {
+            user_agent,
+            kind: value.kind,
+            updated_at: value.updated_at,
+        })
+    }
+}
+
+/// Returns `pattern` anchored at both ends, so that it matches only a whole
+/// user agent.
+fn anchored(pattern: &str) -> String {
+    format!("^(?:{pattern})$")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Store, TrustedUserAgent, UserAgentKind};
+    use std::sync::Arc;
+
+    #[test]
+    fn exact_and_regex() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(Store::new(db_dir.path(), backup_dir.path()).unwrap());
+
+        TrustedUserAgent::insert(&store, "curl/8.0.1", UserAgentKind::Exact).unwrap();
+        TrustedUserAgent::insert(&store, r"Mozilla/5\.0 .*Firefox/\d+", UserAgentKind::Regex)
+            .unwrap();
+        TrustedUserAgent::insert(&store, "Wget/1.21", UserAgentKind::Exact).unwrap();
+        assert!(TrustedUserAgent::insert(&store, "Wget/1.21", UserAgentKind::Exact).is_err());
+        assert!(TrustedUserAgent::insert(&store, "Mozilla/(", UserAgentKind::Regex).is_err());
+
+        let entries = TrustedUserAgent::get_list(&store).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[1].user_agent, "curl/8.0.1");
+        assert_eq!(entries[1].kind, UserAgentKind::Exact);
+        assert!(entries[1].updated_at.is_some());
+
+        let matching = |candidate| -> Vec<String> {
+            TrustedUserAgent::matching(&store, candidate)
+                .unwrap()
+                .into_iter()
+                .map(|entry| entry.user_agent)
+                .collect()
+        };
+        assert_eq!(
+            matching("Mozilla/5.0 (X11; Linux x86_64; rv:125.0) Gecko/20100101 Firefox/125"),
+            [r"Mozilla/5\.0 .*Firefox/\d+"]
+        );
+        // A regular expression must match the whole user agent.
+        assert!(matching("Mozilla/5.0 (X11) Firefox/125 extra").is_empty());
+        assert_eq!(matching("curl/8.0.1"), ["curl/8.0.1"]);
+        assert!(matching("Wget/1.21.1").is_empty());
+
+        TrustedUserAgent::remove(&store, "Wget/1.21").unwrap();
+        assert_eq!(TrustedUserAgent::get_list(&store).unwrap().len(), 2);
+    }
+}
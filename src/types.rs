@@ -4,7 +4,10 @@ use anyhow::Result;
 use chrono::{naive::serde::ts_nanoseconds_option, NaiveDateTime};
 use ipnet::IpNet;
 use serde::{Deserialize, Serialize};
-use std::{cmp::Ordering, convert::TryFrom, net::IpAddr, ops::RangeInclusive};
+use std::{
+    cmp::Ordering, convert::TryFrom, fmt, net::IpAddr, ops::RangeInclusive, str::FromStr,
+    sync::OnceLock,
+};
 use strum_macros::Display;
 
 pub trait FromKeyValue: Sized {
@@ -83,6 +86,8 @@ pub struct Cluster {
     #[serde(with = "ts_nanoseconds_option")]
     pub last_modification_time: Option<NaiveDateTime>,
     pub model_id: i32,
+    /// The ID of the cluster this cluster was merged into, if any.
+    pub merged_into: Option<i32>,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -97,6 +102,115 @@ pub struct HostNetworkGroup {
     hosts: Vec<IpAddr>,
     networks: Vec<IpNet>,
     ip_ranges: Vec<RangeInclusive<IpAddr>>,
+    #[serde(skip)]
+    lookup: Lookup,
+}
+
+/// The lookup structure of the networks and IP ranges in a
+/// `HostNetworkGroup`, built when first needed.
+#[derive(Clone, Default)]
+struct Lookup(OnceLock<Intervals>);
+
+impl Lookup {
+    /// Groups with fewer networks and IP ranges than this are scanned
+    /// linearly.
+    const MIN_ENTRIES: usize = 32;
+}
+
+impl fmt::Debug for Lookup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Lookup")
+    }
+}
+
+/// The lookup structure doesn't affect equality.
+impl PartialEq for Lookup {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for Lookup {}
+
+/// Disjoint address intervals sorted by their starts, for IPv4 and IPv6
+/// addresses respectively.
+#[derive(Clone, Default)]
+struct Intervals {
+    v4: Vec<(u128, u128)>,
+    v6: Vec<(u128, u128)>,
+}
+
+impl Intervals {
+    fn new(networks: &[IpNet], ip_ranges: &[RangeInclusive<IpAddr>]) -> Self {
+        let mut v4 = Vec::new();
+        let mut v6 = Vec::new();
+        for (is_v6, start, end) in address_intervals(networks, ip_ranges) {
+            if is_v6 {
+                v6.push((start, end));
+            } else {
+                v4.push((start, end));
+            }
+        }
+        Self {
+            v4: merge_intervals(v4),
+            v6: merge_intervals(v6),
+        }
+    }
+
+    fn contains(&self, addr: IpAddr) -> bool {
+        let (is_v6, addr) = addr_to_int(addr);
+        let intervals = if is_v6 { &self.v6 } else { &self.v4 };
+        let next = intervals.partition_point(|(start, _)| *start <= addr);
+        next > 0 && intervals[next - 1].1 >= addr
+    }
+}
+
+/// Returns the address intervals of `networks` and `ip_ranges` as integers,
+/// each with `true` if it is of IPv6 addresses.
+fn address_intervals(
+    networks: &[IpNet],
+    ip_ranges: &[RangeInclusive<IpAddr>],
+) -> Vec<(bool, u128, u128)> {
+    let mut intervals = Vec::with_capacity(networks.len() + ip_ranges.len());
+    let mut push = |start: IpAddr, end: IpAddr| {
+        let (is_v6, start) = addr_to_int(start);
+        let (_, end) = addr_to_int(end);
+        intervals.push((is_v6, start, end));
+    };
+    for net in networks {
+        push(net.network(), net.broadcast());
+    }
+    for range in ip_ranges {
+        match (range.start(), range.end()) {
+            (start, end) if start > end => {}
+            // A range from an IPv4 address to an IPv6 address contains
+            // every address in between in the order of `IpAddr`.
+            (IpAddr::V4(start), IpAddr::V6(end)) => {
+                push(
+                    IpAddr::V4(*start),
+                    IpAddr::V4(std::net::Ipv4Addr::BROADCAST),
+                );
+                push(
+                    IpAddr::V6(std::net::Ipv6Addr::UNSPECIFIED),
+                    IpAddr::V6(*end),
+                );
+            }
+            (start, end) => push(*start, *end),
+        }
+    }
+    intervals
+}
+
+fn merge_intervals(mut intervals: Vec<(u128, u128)>) -> Vec<(u128, u128)> {
+    intervals.sort_unstable();
+    let mut merged: Vec<(u128, u128)> = Vec::with_capacity(intervals.len());
+    for (start, end) in intervals {
+        match merged.last_mut() {
+            Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
 }
 
 impl HostNetworkGroup {
@@ -120,6 +234,7 @@ impl HostNetworkGroup {
             hosts,
             networks,
             ip_ranges,
+            lookup: Lookup::default(),
         }
     }
 
@@ -144,6 +259,14 @@ impl HostNetworkGroup {
             return true;
         }
 
+        if self.networks.len() + self.ip_ranges.len() >= Lookup::MIN_ENTRIES {
+            return self
+                .lookup
+                .0
+                .get_or_init(|| Intervals::new(&self.networks, &self.ip_ranges))
+                .contains(addr);
+        }
+
         if self.networks.iter().any(|net| net.contains(&addr)) {
             return true;
         }
@@ -169,6 +292,188 @@ impl HostNetworkGroup {
     pub fn contains_network(&self, network: &IpNet) -> bool {
         self.networks.binary_search(network).is_ok()
     }
+
+    /// Returns the address intervals of the hosts, networks, and IP ranges as
+    /// integers, each with `true` if it is of IPv6 addresses.
+    pub(crate) fn intervals(&self) -> Vec<(bool, u128, u128)> {
+        let mut intervals: Vec<_> = self
+            .hosts
+            .iter()
+            .map(|host| {
+                let (is_v6, addr) = addr_to_int(*host);
+                (is_v6, addr, addr)
+            })
+            .collect();
+        intervals.extend(address_intervals(&self.networks, &self.ip_ranges));
+        intervals
+    }
+
+    /// Merges overlapping and adjacent IP ranges and networks, replaces the
+    /// IP ranges that are exactly CIDR blocks with networks, and removes the
+    /// hosts and IP ranges covered by networks or other IP ranges. The set of
+    /// addresses in the group doesn't change.
+    pub fn normalize(&mut self) {
+        let mut ip_ranges = std::mem::take(&mut self.ip_ranges);
+        ip_ranges.sort_unstable_by(|a, b| a.start().cmp(b.start()).then(a.end().cmp(b.end())));
+        let mut merged: Vec<RangeInclusive<IpAddr>> = Vec::with_capacity(ip_ranges.len());
+        for range in ip_ranges {
+            if let Some(last) = merged.last_mut() {
+                if let Some(end) = merged_end(last, &range) {
+                    *last = *last.start()..=end;
+                    continue;
+                }
+            }
+            merged.push(range);
+        }
+
+        let mut networks = std::mem::take(&mut self.networks);
+        merged.retain(|range| {
+            if range.start() == range.end() {
+                self.hosts.push(*range.start());
+                return false;
+            }
+            if let Some(network) = exact_network(range) {
+                networks.push(network);
+                return false;
+            }
+            true
+        });
+        let mut networks = IpNet::aggregate(&networks);
+        networks.sort_unstable();
+        merged.retain(|range| {
+            !networks
+                .iter()
+                .any(|net| net.contains(range.start()) && net.contains(range.end()))
+        });
+
+        self.hosts.retain(|host| {
+            !networks.iter().any(|net| net.contains(host))
+                && !merged.iter().any(|range| range.contains(host))
+        });
+        self.hosts.sort_unstable();
+        self.hosts.dedup();
+        self.networks = networks;
+        self.ip_ranges = merged;
+        self.lookup = Lookup::default();
+    }
+}
+
+/// Formats the group in the syntax [`HostNetworkGroup::from_str`] parses: the
+/// hosts, the networks, and the IP ranges, separated by commas.
+impl fmt::Display for HostNetworkGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hosts = self.hosts.iter().map(ToString::to_string);
+        let networks = self.networks.iter().map(ToString::to_string);
+        let ip_ranges = self
+            .ip_ranges
+            .iter()
+            .map(|range| format!("{} - {}", range.start(), range.end()));
+        for (i, entry) in hosts.chain(networks).chain(ip_ranges).enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            f.write_str(&entry)?;
+        }
+        Ok(())
+    }
+}
+
+/// An error in parsing a `HostNetworkGroup`.
+#[derive(Clone, Debug, Eq, PartialEq, thiserror::Error)]
+#[error("invalid entry {entry:?} at position {position}: {reason}")]
+pub struct ParseHostNetworkGroupError {
+    /// The byte offset of the invalid entry in the input.
+    pub position: usize,
+    pub entry: String,
+    pub reason: String,
+}
+
+/// Parses a list of hosts ("10.0.0.1"), networks ("10.0.0.0/24"), and IP
+/// ranges ("10.0.0.1 - 10.0.0.20"), separated by commas or newlines. Empty
+/// entries are ignored.
+impl FromStr for HostNetworkGroup {
+    type Err = ParseHostNetworkGroupError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut hosts = Vec::new();
+        let mut networks = Vec::new();
+        let mut ip_ranges = Vec::new();
+        let mut offset = 0;
+        for part in s.split([',', '\n']) {
+            let start = offset + (part.len() - part.trim_start().len());
+            offset += part.len() + 1;
+            let entry = part.trim();
+            if entry.is_empty() {
+                continue;
+            }
+            let error = |reason: String| ParseHostNetworkGroupError {
+                position: start,
+                entry: entry.to_string(),
+                reason,
+            };
+            if entry.contains('/') {
+                networks.push(entry.parse::<IpNet>().map_err(|e| error(e.to_string()))?);
+            } else if let Some((first, last)) = entry.split_once('-') {
+                let first = first
+                    .trim()
+                    .parse::<IpAddr>()
+                    .map_err(|e| error(e.to_string()))?;
+                let last = last
+                    .trim()
+                    .parse::<IpAddr>()
+                    .map_err(|e| error(e.to_string()))?;
+                if first.is_ipv4() != last.is_ipv4() {
+                    return Err(error("addresses of different families".to_string()));
+                }
+                if first > last {
+                    return Err(error("the start is after the end".to_string()));
+                }
+                ip_ranges.push(first..=last);
+            } else {
+                hosts.push(entry.parse::<IpAddr>().map_err(|e| error(e.to_string()))?);
+            }
+        }
+        Ok(Self::new(hosts, networks, ip_ranges))
+    }
+}
+
+/// Converts `addr` into an integer, with `true` if it is an IPv6 address.
+pub(crate) fn addr_to_int(addr: IpAddr) -> (bool, u128) {
+    match addr {
+        IpAddr::V4(addr) => (false, u128::from(u32::from(addr))),
+        IpAddr::V6(addr) => (true, u128::from(addr)),
+    }
+}
+
+/// Returns the end of the range merging `a` and `b` if they overlap or are
+/// adjacent, given that `a` doesn't start after `b`.
+fn merged_end(a: &RangeInclusive<IpAddr>, b: &RangeInclusive<IpAddr>) -> Option<IpAddr> {
+    let (a_v6, a_end) = addr_to_int(*a.end());
+    let (b_v6, b_start) = addr_to_int(*b.start());
+    if a_v6 != b_v6 || a.start().is_ipv6() != a_v6 || b.end().is_ipv6() != b_v6 {
+        return None;
+    }
+    if b_start > a_end.saturating_add(1) {
+        return None;
+    }
+    Some(*a.end().max(b.end()))
+}
+
+/// Returns the network consisting of exactly the addresses in `range`, if
+/// any.
+fn exact_network(range: &RangeInclusive<IpAddr>) -> Option<IpNet> {
+    let (start_v6, start) = addr_to_int(*range.start());
+    let (end_v6, end) = addr_to_int(*range.end());
+    if start_v6 != end_v6 || start > end {
+        return None;
+    }
+    let diff = end - start;
+    if diff & diff.wrapping_add(1) != 0 || start & diff != 0 {
+        return None;
+    }
+    let max_prefix_len: u32 = if start_v6 { 128 } else { 32 };
+    let prefix_len = u8::try_from(max_prefix_len - diff.count_ones()).ok()?;
+    IpNet::new(*range.start(), prefix_len).ok()
 }
 
 #[derive(Deserialize)]
@@ -205,3 +510,145 @@ pub struct Qualifier {
     pub id: u32,
     pub description: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use ipnet::IpNet;
+
+    use super::HostNetworkGroup;
+
+    #[test]
+    fn normalize_host_network_group() {
+        let mut group = HostNetworkGroup::new(
+            vec![
+                "10.0.0.1".parse().unwrap(),
+                "10.0.3.1".parse().unwrap(),
+                "192.168.0.1".parse().unwrap(),
+            ],
+            vec![
+                "10.0.0.0/24".parse().unwrap(),
+                "10.0.1.0/24".parse().unwrap(),
+                "10.0.0.128/25".parse().unwrap(),
+            ],
+            vec![
+                "10.0.2.0".parse().unwrap()..="10.0.2.255".parse().unwrap(),
+                "10.0.3.0".parse().unwrap()..="10.0.3.9".parse().unwrap(),
+                "10.0.3.5".parse().unwrap()..="10.0.3.20".parse().unwrap(),
+                "10.0.3.21".parse().unwrap()..="10.0.3.30".parse().unwrap(),
+                "172.16.0.7".parse().unwrap()..="172.16.0.7".parse().unwrap(),
+                "::".parse().unwrap()..="::3".parse().unwrap(),
+            ],
+        );
+        let before = group.clone();
+        group.normalize();
+
+        let hosts: Vec<IpAddr> = vec![
+            "172.16.0.7".parse().unwrap(),
+            "192.168.0.1".parse().unwrap(),
+        ];
+        assert_eq!(group.hosts(), hosts);
+        let networks: Vec<IpNet> = vec![
+            "10.0.0.0/23".parse().unwrap(),
+            "10.0.2.0/24".parse().unwrap(),
+            "::/126".parse().unwrap(),
+        ];
+        assert_eq!(group.networks(), networks);
+        let start: IpAddr = "10.0.3.0".parse().unwrap();
+        let end: IpAddr = "10.0.3.30".parse().unwrap();
+        assert_eq!(group.ip_ranges(), [start..=end]);
+        for addr in [
+            "10.0.0.200",
+            "10.0.1.1",
+            "10.0.2.1",
+            "10.0.3.25",
+            "10.0.3.31",
+            "10.0.4.0",
+            "172.16.0.7",
+            "::2",
+            "::4",
+        ] {
+            let addr = addr.parse().unwrap();
+            assert_eq!(group.contains(addr), before.contains(addr));
+        }
+    }
+
+    #[test]
+    fn contains_with_lookup() {
+        let networks: Vec<IpNet> = (0..100_u8)
+            .map(|i| format!("10.{i}.0.0/24").parse().unwrap())
+            .collect();
+        let start: IpAddr = "192.168.0.10".parse().unwrap();
+        let end: IpAddr = "192.168.0.20".parse().unwrap();
+        let group = HostNetworkGroup::new(
+            vec!["172.16.0.1".parse().unwrap()],
+            networks,
+            vec![
+                start..=end,
+                "fe80::1".parse().unwrap()..="fe80::5".parse().unwrap(),
+            ],
+        );
+        let linear = |addr: IpAddr| {
+            group.hosts().contains(&addr)
+                || group.networks().iter().any(|net| net.contains(&addr))
+                || group.ip_ranges().iter().any(|range| range.contains(&addr))
+        };
+        for addr in [
+            "10.0.0.0",
+            "10.50.0.255",
+            "10.99.0.7",
+            "10.99.1.0",
+            "10.100.0.1",
+            "172.16.0.1",
+            "192.168.0.9",
+            "192.168.0.10",
+            "192.168.0.20",
+            "192.168.0.21",
+            "fe80::3",
+            "fe80::6",
+        ] {
+            let addr = addr.parse().unwrap();
+            assert_eq!(group.contains(addr), linear(addr), "{addr}");
+        }
+
+        let serialized = bincode::serialize(&group).unwrap();
+        let deserialized: HostNetworkGroup = bincode::deserialize(&serialized).unwrap();
+        assert_eq!(deserialized, group);
+        assert!(deserialized.contains("10.42.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn parse_and_format() {
+        let group: HostNetworkGroup = "10.0.0.1, 10.0.0.0/24,\n 10.0.0.1 - 10.0.0.20,, ::1"
+            .parse()
+            .unwrap();
+        assert_eq!(group.hosts().len(), 2);
+        assert_eq!(group.networks().len(), 1);
+        assert_eq!(group.ip_ranges().len(), 1);
+        assert_eq!(
+            group.to_string(),
+            "10.0.0.1, ::1, 10.0.0.0/24, 10.0.0.1 - 10.0.0.20"
+        );
+        assert_eq!(
+            group.to_string().parse::<HostNetworkGroup>().unwrap(),
+            group
+        );
+        assert_eq!(
+            "".parse::<HostNetworkGroup>().unwrap(),
+            HostNetworkGroup::default()
+        );
+
+        let err = "10.0.0.1,  10.0.0.300"
+            .parse::<HostNetworkGroup>()
+            .unwrap_err();
+        assert_eq!(err.position, 11);
+        assert_eq!(err.entry, "10.0.0.300");
+        let err = "10.0.0.1\n10.0.0.9 - 10.0.0.2"
+            .parse::<HostNetworkGroup>()
+            .unwrap_err();
+        assert_eq!(err.position, 9);
+        assert!("10.0.0.1 - ::1".parse::<HostNetworkGroup>().is_err());
+        assert!("10.0.0.0/33".parse::<HostNetworkGroup>().is_err());
+    }
+}
@@ -0,0 +1,210 @@
+//! A single verdict on traffic from the allow and block lists.
+
+use std::net::IpAddr;
+
+/// What to do with traffic, decided by [`crate::Store::verdict`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Verdict {
+    Allow,
+    Block,
+    /// No rule applies, and the traffic is to be inspected as usual.
+    Inspect,
+}
+
+/// The attributes of traffic a verdict is based on. The attributes that are
+/// `None` are not checked.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct VerdictInput<'a> {
+    pub src_addr: Option<IpAddr>,
+    pub dst_addr: Option<IpAddr>,
+    /// The domain name the traffic is for, such as the host of an HTTP
+    /// request or the name in a DNS query.
+    pub domain: Option<&'a str>,
+    pub user_agent: Option<&'a str>,
+}
+
+/// A rule that matches traffic.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum VerdictRule {
+    /// A block network entry contains `addr`.
+    BlockNetwork { id: u32, name: String, addr: IpAddr },
+    /// An allow network entry contains `addr`.
+    AllowNetwork { id: u32, name: String, addr: IpAddr },
+    /// The domain matches the trusted domain `name`.
+    TrustedDomain { name: String },
+    /// The user agent matches the trusted user agent `user_agent`.
+    TrustedUserAgent { user_agent: String },
+}
+
+impl VerdictRule {
+    fn verdict(&self) -> Verdict {
+        match self {
+            Self::BlockNetwork { .. } => Verdict::Block,
+            Self::AllowNetwork { .. }
+            | Self::TrustedDomain { .. }
+            | Self::TrustedUserAgent { .. } => Verdict::Allow,
+        }
+    }
+}
+
+/// A verdict with the rules it is based on.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Decision {
+    pub verdict: Verdict,
+    /// All the rules the traffic matches, in the order of precedence. The
+    /// first one decides the verdict, and the others are overridden by it or
+    /// agree with it.
+    pub rules: Vec<VerdictRule>,
+}
+
+impl Decision {
+    /// Returns the decision made by `rules`, which must be in the order of
+    /// precedence.
+    pub(crate) fn new(rules: Vec<VerdictRule>) -> Self {
+        let verdict = rules.first().map_or(Verdict::Inspect, VerdictRule::verdict);
+        Self { verdict, rules }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::{Duration, Utc};
+
+    use crate::{
+        AllowNetwork, BlockNetwork, HostNetworkGroup, Store, TrustedUserAgent, UserAgentKind,
+        Verdict, VerdictInput, VerdictRule,
+    };
+
+    #[test]
+    fn precedence() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(Store::new(db_dir.path(), backup_dir.path()).unwrap());
+        let now = Utc::now();
+
+        let networks =
+            |net: &str| HostNetworkGroup::new(vec![], vec![net.parse().unwrap()], vec![]);
+        let block_id = store
+            .block_network_map()
+            .put(BlockNetwork {
+                id: u32::MAX,
+                name: "block".to_string(),
+                networks: networks("198.51.100.0/24"),
+                description: String::new(),
+                expiration_time: None,
+            })
+            .unwrap();
+        let allow_id = store
+            .allow_network_map()
+            .put(AllowNetwork {
+                id: u32::MAX,
+                name: "allow".to_string(),
+                networks: networks("198.51.0.0/16"),
+                description: String::new(),
+                expiration_time: None,
+            })
+            .unwrap();
+        store
+            .allow_network_map()
+            .put(AllowNetwork {
+                id: u32::MAX,
+                name: "expired".to_string(),
+                networks: networks("203.0.113.0/24"),
+                description: String::new(),
+                expiration_time: Some(now - Duration::hours(1)),
+            })
+            .unwrap();
+        store.trusted_domain_map().add("*.example.com", "").unwrap();
+        TrustedUserAgent::insert(&store, "updater/1.0", UserAgentKind::Exact).unwrap();
+
+        // A block network overrides all the others.
+        let input = VerdictInput {
+            src_addr: Some("192.0.2.1".parse().unwrap()),
+            dst_addr: Some("198.51.100.1".parse().unwrap()),
+            domain: Some("www.example.com"),
+            user_agent: Some("updater/1.0"),
+        };
+        let decision = store.verdict(&input, now).unwrap();
+        assert_eq!(decision.verdict, Verdict::Block);
+        assert_eq!(
+            decision.rules,
+            [
+                VerdictRule::BlockNetwork {
+                    id: block_id,
+                    name: "block".to_string(),
+                    addr: "198.51.100.1".parse().unwrap(),
+                },
+                VerdictRule::AllowNetwork {
+                    id: allow_id,
+                    name: "allow".to_string(),
+                    addr: "198.51.100.1".parse().unwrap(),
+                },
+                VerdictRule::TrustedDomain {
+                    name: "*.example.com".to_string(),
+                },
+                VerdictRule::TrustedUserAgent {
+                    user_agent: "updater/1.0".to_string(),
+                },
+            ]
+        );
+
+        let input = VerdictInput {
+            domain: Some("www.example.com"),
+            ..VerdictInput::default()
+        };
+        assert_eq!(store.verdict(&input, now).unwrap().verdict, Verdict::Allow);
+
+        // An expired entry doesn't apply.
+        let input = VerdictInput {
+            src_addr: Some("203.0.113.1".parse().unwrap()),
+            user_agent: Some("updater/1.1"),
+            ..VerdictInput::default()
+        };
+        let decision = store.verdict(&input, now).unwrap();
+        assert_eq!(decision.verdict, Verdict::Inspect);
+        assert!(decision.rules.is_empty());
+    }
+
+    #[test]
+    fn invalid_domain() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(Store::new(db_dir.path(), backup_dir.path()).unwrap());
+        let now = Utc::now();
+        store.trusted_domain_map().add("*.example.com", "").unwrap();
+        TrustedUserAgent::insert(&store, "updater/1.0", UserAgentKind::Exact).unwrap();
+
+        // A domain that cannot be looked up matches no trusted domain, but the
+        // other rules still apply.
+        for domain in [
+            "*.example.com",
+            "www.*.example.com",
+            "www.example.com:443",
+            "192.0.2.1",
+            "[2001:db8::1]",
+            "",
+        ] {
+            let input = VerdictInput {
+                domain: Some(domain),
+                ..VerdictInput::default()
+            };
+            let decision = store.verdict(&input, now).unwrap();
+            assert_eq!(decision.verdict, Verdict::Inspect, "{domain}");
+
+            let input = VerdictInput {
+                domain: Some(domain),
+                user_agent: Some("updater/1.0"),
+                ..VerdictInput::default()
+            };
+            let decision = store.verdict(&input, now).unwrap();
+            assert_eq!(
+                decision.rules,
+                [VerdictRule::TrustedUserAgent {
+                    user_agent: "updater/1.0".to_string(),
+                }]
+            );
+        }
+    }
+}